@@ -0,0 +1,61 @@
+//! Benchmarks of the serialization and deserialization of packets.
+//!
+//! Run with `cargo bench -p oxixenon-core`.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use oxixenon_core::protocol::{
+    ErrorCode, Event, EventPayload, Packet, RenewAvailability, RenewOutcome
+};
+
+// A packet of each kind, along with its name.
+fn packets() -> Vec<(&'static str, Packet)> {
+    vec![
+        ("fresh_ip_request", Packet::FreshIPRequest { renewer: None }),
+        ("ok", Packet::Ok),
+        ("event", Packet::Event (Event::IPRenewed, EventPayload::default())),
+        ("event_with_payload", Packet::Event (Event::IPRenewed, EventPayload::now (
+            Some ("router".into()),
+            RenewOutcome {
+                old_ip: Some ("203.0.113.7".parse().unwrap()),
+                new_ip: Some ("2001:db8::1".parse().unwrap())
+            }
+        ))),
+        ("set_available", Packet::SetRenewingAvailable (RenewAvailability::Available)),
+        ("set_unavailable", Packet::SetRenewingAvailable (
+            RenewAvailability::Unavailable ("somebody is playing online".into())
+        )),
+        ("error", Packet::Error {
+            code: ErrorCode::RenewerFailure,
+            detail: Some ("the router is on fire: ".repeat (64))
+        })
+    ]
+}
+
+fn write (c: &mut Criterion) {
+    let mut group = c.benchmark_group ("Packet::write");
+    for (name, packet) in packets() {
+        let mut buffer = Vec::with_capacity (2048);
+        group.bench_with_input (BenchmarkId::from_parameter (name), &packet, |b, packet| {
+            b.iter (|| {
+                buffer.clear();
+                black_box (packet).write (&mut buffer).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+fn read (c: &mut Criterion) {
+    let mut group = c.benchmark_group ("Packet::read");
+    for (name, packet) in packets() {
+        let mut bytes = Vec::new();
+        packet.write (&mut bytes).unwrap();
+        group.bench_with_input (BenchmarkId::from_parameter (name), &bytes, |b, bytes| {
+            b.iter (|| Packet::read (&mut black_box (bytes.as_slice())).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, write, read);
+criterion_main!(benches);
@@ -0,0 +1,16 @@
+//! Reads packets from arbitrary bytes: reading must never panic, and whatever is read must be
+//! written and read back unchanged.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use oxixenon_core::protocol::Packet;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(packet) = Packet::read (&mut &data[..]) {
+        let mut bytes = Vec::new();
+        packet.write (&mut bytes).expect ("packets which were read can be written");
+        let read = Packet::read (&mut bytes.as_slice()).expect ("written packets can be read");
+        assert_eq!(read, packet);
+    }
+});
@@ -0,0 +1,306 @@
+//! Which clients a server answers, and which of them may send each request: by their IP address
+//! (see `Acl`), or by the token they authenticated with (see `Token`).
+
+use crate::config::Secret;
+use crate::errors::Cause;
+use crate::protocol::Packet;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause)
+}
+
+chained_error!(Error);
+
+/// A range of IP addresses in CIDR notation (e.g. "192.168.1.0/24" or "fd00::/8"), or a single
+/// address. IPv4 addresses mapped to IPv6 (as reported by dual-stack sockets) are in the ranges
+/// of their IPv4 address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8
+}
+
+impl IpRange {
+    pub fn contains (&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) =>
+                masked (network.to_bits().into(), self.prefix, 32)
+                    == masked (ip.to_bits().into(), self.prefix, 32),
+            (IpAddr::V6(network), IpAddr::V6(ip)) =>
+                masked (network.to_bits(), self.prefix, 128)
+                    == masked (ip.to_bits(), self.prefix, 128),
+            _ => false
+        }
+    }
+}
+
+// The first `prefix` bits of `bits`, an address `len` bits long.
+fn masked (bits: u128, prefix: u8, len: u8) -> u128 {
+    match u32::from (len - prefix) {
+        128 => 0,
+        host => bits >> host << host
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = Error;
+
+    fn from_str (range: &str) -> Result<IpRange> {
+        let (network, prefix) = match range.split_once ('/') {
+            Some((network, prefix)) => (network, Some (prefix)),
+            None => (range, None)
+        };
+        let network = network.parse::<IpAddr>()
+            .chain_err (|| format!("invalid IP address '{}'", network))?
+            .to_canonical();
+        let len = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.parse::<u8>().ok().filter (|&prefix| prefix <= len)
+                .chain_err (|| format!("invalid prefix length '{}' in '{}'", prefix, range))?,
+            None => len
+        };
+        Ok(IpRange { network, prefix })
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+/// The requests which can be restricted to some clients. Asking for the capabilities of the
+/// server (which clients do before other requests) can't be.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Action {
+    Renew,
+    SetAvailability,
+    Reload,
+    SetRenewer,
+    Status,
+    Health,
+    History,
+    Subscribe
+}
+
+impl Action {
+    pub const ALL: [Action; 8] = [
+        Action::Renew, Action::SetAvailability, Action::Reload, Action::SetRenewer,
+        Action::Status, Action::Health, Action::History, Action::Subscribe
+    ];
+
+    /// The name of the action in the configuration, e.g. "set_availability".
+    pub fn name (&self) -> &'static str {
+        match *self {
+            Action::Renew => "renew",
+            Action::SetAvailability => "set_availability",
+            Action::Reload => "reload",
+            Action::SetRenewer => "set_renewer",
+            Action::Status => "status",
+            Action::Health => "health",
+            Action::History => "history",
+            Action::Subscribe => "subscribe"
+        }
+    }
+
+    /// The action requested by `packet`, if it's a request which can be restricted.
+    pub fn of (packet: &Packet) -> Option<Action> {
+        match *packet {
+            Packet::FreshIPRequest { .. } => Some (Action::Renew),
+            Packet::SetRenewingAvailable(_) => Some (Action::SetAvailability),
+            Packet::ReloadConfig { .. } => Some (Action::Reload),
+            Packet::SetRenewer { .. } => Some (Action::SetRenewer),
+            Packet::StatusRequest => Some (Action::Status),
+            Packet::HealthRequest => Some (Action::Health),
+            Packet::HistoryRequest { .. } => Some (Action::History),
+            Packet::Subscribe => Some (Action::Subscribe),
+            _ => None
+        }
+    }
+}
+
+impl FromStr for Action {
+    type Err = Error;
+
+    fn from_str (name: &str) -> Result<Action> {
+        Action::ALL.iter().copied().find (|action| action.name() == name)
+            .chain_err (|| format!("unknown action '{}'", name))
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str (self.name())
+    }
+}
+
+/// The clients a server answers (all of them by default), and those which may send each of the
+/// restricted requests (any client it answers, unless restricted).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Acl {
+    clients: Option<Vec<IpRange>>,
+    actions: BTreeMap<Action, Vec<IpRange>>
+}
+
+impl Acl {
+    /// Answers only the clients whose address is in one of `ranges`.
+    pub fn allow_clients (mut self, ranges: Vec<IpRange>) -> Acl {
+        self.clients = Some (ranges);
+        self
+    }
+
+    /// Accepts `action` only from the clients whose address is in one of `ranges` (and which are
+    /// answered at all).
+    pub fn restrict (mut self, action: Action, ranges: Vec<IpRange>) -> Acl {
+        self.actions.insert (action, ranges);
+        self
+    }
+
+    /// Whether the client at `ip` is answered.
+    pub fn allows_client (&self, ip: IpAddr) -> bool {
+        self.clients.as_ref().is_none_or (|ranges| in_any (ranges, ip))
+    }
+
+    /// Whether `action` is accepted from the client at `ip`.
+    pub fn allows (&self, action: Action, ip: IpAddr) -> bool {
+        self.allows_client (ip)
+            && self.actions.get (&action).is_none_or (|ranges| in_any (ranges, ip))
+    }
+}
+
+fn in_any (ranges: &[IpRange], ip: IpAddr) -> bool {
+    ranges.iter().any (|range| range.contains (ip))
+}
+
+/// What a token allows, besides the requests which only read the state of the server (`status`,
+/// `health` and `history`), which any authenticated client may send.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Renew,
+    SetAvailability,
+    Subscribe,
+    /// Every request, including reloading the configuration and switching renewers.
+    Admin
+}
+
+impl Scope {
+    pub const ALL: [Scope; 4] = [
+        Scope::Renew, Scope::SetAvailability, Scope::Subscribe, Scope::Admin
+    ];
+
+    /// The name of the scope in the configuration, e.g. "set_availability".
+    pub fn name (&self) -> &'static str {
+        match *self {
+            Scope::Renew => "renew",
+            Scope::SetAvailability => "set_availability",
+            Scope::Subscribe => "subscribe",
+            Scope::Admin => "admin"
+        }
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Error;
+
+    fn from_str (name: &str) -> Result<Scope> {
+        Scope::ALL.iter().copied().find (|scope| scope.name() == name)
+            .chain_err (|| format!("unknown scope '{}'", name))
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str (self.name())
+    }
+}
+
+/// A key which a client proves to know as it would the pre-shared one (see `Server::psk`), so
+/// that several clients sharing a server can be told apart by `name` and allowed different
+/// requests. The name is logged along with their requests, and sent along with the events they
+/// cause (see `EventPayload::requested_by`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Token {
+    pub name: String,
+    pub secret: Secret<String>,
+    pub scopes: BTreeSet<Scope>
+}
+
+impl Token {
+    /// Whether `action` is accepted from a client which authenticated with this token.
+    pub fn allows (&self, action: Action) -> bool {
+        let scope = match action {
+            Action::Status | Action::Health | Action::History => return true,
+            Action::Renew => Scope::Renew,
+            Action::SetAvailability => Scope::SetAvailability,
+            Action::Subscribe => Scope::Subscribe,
+            Action::Reload | Action::SetRenewer => Scope::Admin
+        };
+        self.scopes.contains (&scope) || self.scopes.contains (&Scope::Admin)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges (ranges: &[&str]) -> Vec<IpRange> {
+        ranges.iter().map (|range| range.parse().unwrap()).collect()
+    }
+
+    #[test]
+    fn ranges_contain_their_addresses() {
+        let lan: IpRange = "192.168.1.0/24".parse().unwrap();
+        assert!(lan.contains ([192, 168, 1, 42].into()));
+        assert!(!lan.contains ([192, 168, 2, 1].into()));
+        assert!(lan.contains ("::ffff:192.168.1.42".parse().unwrap()));
+        assert!(!lan.contains ("fd00::1".parse().unwrap()));
+        assert!("0.0.0.0/0".parse::<IpRange>().unwrap().contains ([10, 0, 0, 1].into()));
+        let host: IpRange = "fd00::1".parse().unwrap();
+        assert_eq!(host.to_string(), "fd00::1/128");
+        assert!(host.contains ("fd00::1".parse().unwrap()));
+        assert!(!host.contains ("fd00::2".parse().unwrap()));
+        assert!("192.168.1.0/33".parse::<IpRange>().is_err());
+        assert!("router/24".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn actions_are_restricted_to_allowed_clients() {
+        let acl = Acl::default()
+            .allow_clients (ranges (&["192.168.1.0/24", "::1"]))
+            .restrict (Action::SetAvailability, ranges (&["192.168.1.10", "10.0.0.1"]));
+        let (laptop, phone, stranger) = ([192, 168, 1, 10].into(), [192, 168, 1, 20].into(),
+            [10, 0, 0, 1].into());
+        assert!(acl.allows (Action::SetAvailability, laptop));
+        assert!(!acl.allows (Action::SetAvailability, phone));
+        assert!(acl.allows (Action::Renew, phone));
+        // Restricted actions are still only accepted from the allowed clients.
+        assert!(!acl.allows_client (stranger));
+        assert!(!acl.allows (Action::SetAvailability, stranger));
+        assert!(Acl::default().allows (Action::Reload, stranger));
+        assert_eq!("set_availability".parse::<Action>().unwrap(), Action::SetAvailability);
+        assert!("toggle".parse::<Action>().is_err());
+    }
+
+    #[test]
+    fn tokens_allow_their_scopes() {
+        let token = |scopes: &[Scope]| Token {
+            name: "alice".into(),
+            secret: "secret".into(),
+            scopes: scopes.iter().copied().collect()
+        };
+        let renew = token (&[Scope::Renew]);
+        assert!(renew.allows (Action::Renew) && renew.allows (Action::History));
+        assert!(!renew.allows (Action::SetAvailability) && !renew.allows (Action::Reload));
+        let admin = token (&[Scope::Admin]);
+        assert!(Action::ALL.iter().all (|&action| admin.allows (action)));
+        assert_eq!("admin".parse::<Scope>().unwrap(), Scope::Admin);
+    }
+}
@@ -0,0 +1,423 @@
+//! A client of the Xenon protocol, which sends requests to a server.
+
+mod socks5;
+
+pub use self::socks5::Proxy;
+
+use crate::config::Secret;
+use crate::errors::*;
+use crate::notifier::{OnEvent, Shutdown};
+use crate::protocol::{
+    self, Capabilities, ErrorCode, Health, HistoryEntry, Packet, RenewAvailability, RenewOutcome,
+    Status
+};
+use crate::resolver::{self, Resolver};
+#[cfg(feature = "tls")]
+use crate::tls::ClientTls;
+use std::io::prelude::*;
+use std::io::{BufReader, BufWriter};
+use std::net::{Shutdown as SocketShutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
+
+// How long a subscribed client waits before subscribing again, once the connection is lost.
+const RESUBSCRIBE_DELAY: Duration = Duration::from_secs (5);
+// How often a subscribed client checks whether it's asked to stop.
+const SHUTDOWN_POLL: Duration = Duration::from_secs (1);
+
+/// A client of the server listening on `connect_to`. Every request uses its own connection,
+/// unless they are kept open (see `keep_alive`). The capabilities of the server are fetched on
+/// the first connection, so that unsupported requests fail before being sent.
+#[derive(Clone, Debug)]
+pub struct Client {
+    connect_to: Resolver,
+    proxy: Option<Proxy>,
+    psk: Option<Secret<String>>,
+    #[cfg(feature = "tls")]
+    tls: Option<ClientTls>,
+    keep_alive: bool,
+    kept: KeptConnection,
+    // `None` once fetched from servers which don't advertise them. Shared by clones.
+    capabilities: Arc<OnceLock<Option<Capabilities>>>
+}
+
+// An open connection to the server.
+#[derive(Debug)]
+enum Connection {
+    Plain(TcpStream),
+    #[cfg(feature = "tls")]
+    Tls(crate::tls::Stream)
+}
+
+impl Read for &Connection {
+    fn read (&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match **self {
+            Connection::Plain(ref stream) => (&*stream).read (buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref stream) => (&*stream).read (buf)
+        }
+    }
+}
+
+impl Write for &Connection {
+    fn write (&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match **self {
+            Connection::Plain(ref stream) => (&*stream).write (buf),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref stream) => (&*stream).write (buf)
+        }
+    }
+
+    fn flush (&mut self) -> std::io::Result<()> {
+        match **self {
+            Connection::Plain(ref stream) => (&*stream).flush(),
+            #[cfg(feature = "tls")]
+            Connection::Tls(ref stream) => (&*stream).flush()
+        }
+    }
+}
+
+// The connection kept open by `Client::keep_alive`, which clones of the client don't share.
+#[derive(Debug, Default)]
+struct KeptConnection (Mutex<Option<Connection>>);
+
+impl Clone for KeptConnection {
+    fn clone (&self) -> KeptConnection {
+        KeptConnection::default()
+    }
+}
+
+impl Client {
+    /// A client of the server at `connect_to`, e.g. "127.0.0.1:5454". Host names are resolved
+    /// again every `resolver::DEFAULT_INTERVAL`, see `resolve_interval`.
+    pub fn new<A: Into<String>> (connect_to: A) -> Client {
+        Client {
+            connect_to: Resolver::new (connect_to, resolver::DEFAULT_INTERVAL),
+            proxy: None,
+            psk: None,
+            #[cfg(feature = "tls")]
+            tls: None,
+            keep_alive: false,
+            kept: KeptConnection::default(),
+            capabilities: Arc::default()
+        }
+    }
+
+    /// Resolves the address of the server again once `interval` elapses, keeping the last known
+    /// one if that fails.
+    pub fn resolve_interval (mut self, interval: Duration) -> Client {
+        self.connect_to = Resolver::new (self.connect_to.name(), interval);
+        self
+    }
+
+    /// Connects to the server through `proxy`, which resolves its address instead of the client.
+    pub fn proxy (mut self, proxy: Proxy) -> Client {
+        self.proxy = Some (proxy);
+        self
+    }
+
+    /// Authenticates with the pre-shared key `psk`, which the server must require as well (see
+    /// `Server::psk`).
+    pub fn psk (mut self, psk: Secret<String>) -> Client {
+        self.psk = Some (psk);
+        self
+    }
+
+    /// Talks to the server over TLS, see `tls::ClientTls`.
+    #[cfg(feature = "tls")]
+    pub fn tls (mut self, tls: ClientTls) -> Client {
+        self.tls = Some (tls);
+        self
+    }
+
+    /// Keeps the connection to the server open between requests, rather than connecting (and
+    /// authenticating) for each of them. Servers close connections left idle for a while (see
+    /// `Server::idle_timeout`), in which case the request is sent again over a new one.
+    pub fn keep_alive (mut self) -> Client {
+        self.keep_alive = true;
+        self
+    }
+
+    pub fn connect_to (&self) -> &str {
+        self.connect_to.name()
+    }
+
+    /// Sends `packet` to the server, and returns its response.
+    pub fn request (&self, packet: &Packet) -> Result<Packet> {
+        self.ensure_supported (packet)?;
+        if !self.keep_alive {
+            return self.exchange (&self.connect()?, packet);
+        }
+        let mut kept = self.kept.0.lock().unwrap_or_else (|poisoned| poisoned.into_inner());
+        if let Some(connection) = kept.take() {
+            match self.exchange (&connection, packet) {
+                Ok(response) => {
+                    *kept = Some (connection);
+                    return Ok(response);
+                },
+                Err(err) => debug!(target: "client",
+                    "the connection to {} was closed ({}), connecting again",
+                    self.connect_to.name(), err)
+            }
+        }
+        let connection = self.connect()?;
+        let response = self.exchange (&connection, packet)?;
+        *kept = Some (connection);
+        Ok(response)
+    }
+
+    // Connects to the server, over TLS if configured, and authenticates if needed.
+    fn connect (&self) -> Result<Connection> {
+        self.open().map (|(connection, _)| connection)
+    }
+
+    // Like `connect`, along with a handle to the socket beneath the connection. Fetches the
+    // capabilities of the server, unless already known.
+    fn open (&self) -> Result<(Connection, TcpStream)> {
+        let (connection, socket) = self.open_connection()?;
+        if self.capabilities.get().is_some() {
+            return Ok((connection, socket));
+        }
+        let capabilities = match self.exchange (&connection, &Packet::CapabilitiesRequest)? {
+            Packet::Capabilities(capabilities) => Some (capabilities),
+            // Servers which predate capabilities can't read the request.
+            Packet::Error { code: ErrorCode::Unsupported | ErrorCode::Internal, .. } => None,
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        };
+        debug!(target: "client", "the capabilities of {} are {:?}",
+            self.connect_to.name(), capabilities);
+        let keep_alive = capabilities.as_ref().is_some_and (|capabilities| capabilities.keep_alive);
+        let _ = self.capabilities.set (capabilities);
+        match keep_alive {
+            true => Ok((connection, socket)),
+            // The server closes the connection after the request.
+            false => self.open_connection()
+        }
+    }
+
+    // Connects to the server, over TLS if configured, and authenticates if needed.
+    fn open_connection (&self) -> Result<(Connection, TcpStream)> {
+        let connect_to = self.connect_to.name();
+        info!(target: "client", "connecting to {}...", connect_to);
+        let stream = match self.proxy {
+            Some(ref proxy) => proxy.connect (connect_to)?,
+            None => TcpStream::connect (&self.connect_to.resolve()?[..])
+                .chain_err (|| format!("failed to connect to {}", connect_to))?
+        };
+        let socket = stream.try_clone().chain_err (|| "failed to clone the I/O stream")?;
+        let connection = Connection::Plain (stream);
+        #[cfg(feature = "tls")]
+        let connection = match (connection, &self.tls) {
+            (Connection::Plain(stream), Some(tls)) =>
+                Connection::Tls (tls.connect (stream, connect_to)?),
+            (connection, _) => connection
+        };
+        if let Some(ref psk) = self.psk {
+            match Packet::receive (&mut &connection, &connect_to)? {
+                Packet::AuthChallenge(nonce) => {
+                    let response = protocol::auth_response (psk, &nonce);
+                    let mut writer = BufWriter::new (&connection);
+                    Packet::AuthResponse (response).send (&mut writer, &connect_to)?;
+                    writer.flush().chain_err (|| "failed to flush the I/O stream")?;
+                },
+                Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+                response => bail!("expected an authentication challenge, received {:?}", response)
+            }
+        }
+        Ok((connection, socket))
+    }
+
+    // Sends `packet` through `connection`, and returns the response.
+    fn exchange (&self, connection: &Connection, packet: &Packet) -> Result<Packet> {
+        self.ensure_supported (packet)?;
+        let connect_to = self.connect_to.name();
+        let mut reader = BufReader::new (connection);
+        let mut writer = BufWriter::new (connection);
+        packet.send (&mut writer, &connect_to)?;
+        writer.flush()
+            .chain_err (|| "failed to flush the I/O stream")?;
+        match Packet::receive (&mut reader, &connect_to)? {
+            Packet::AuthChallenge(_) => bail!("the server requires a pre-shared key"),
+            response => Ok(response)
+        }
+    }
+
+    // Fails if the server is known not to support `request`, rather than letting it refuse it.
+    fn ensure_supported (&self, request: &Packet) -> Result<()> {
+        match self.capabilities.get() {
+            Some(Some(capabilities)) if !capabilities.supports (request) => bail!(
+                "the server at {} doesn't support {}", self.connect_to.name(), describe (request)
+            ),
+            _ => Ok(())
+        }
+    }
+
+    /// What the server supports, fetched on the first connection. `None` if the server doesn't
+    /// advertise it, as it predates capabilities.
+    pub fn capabilities (&self) -> Result<Option<Capabilities>> {
+        if self.capabilities.get().is_none() {
+            self.connect()?;
+        }
+        Ok(self.capabilities.get().cloned().flatten())
+    }
+
+    /// Sends `packet` to the server, failing unless the server accepts it. Like every request,
+    /// it fails with `Error::Refused` if the server refuses it, whose code tells why.
+    pub fn send (&self, packet: &Packet) -> Result<()> {
+        match self.request (packet)? {
+            Packet::Ok => Ok(()),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+    }
+
+    /// Asks the server to obtain a new IP address, returning what the server knows about the
+    /// renewal (see `RenewOutcome`). Fails if the server verifies renewals and the address didn't
+    /// change.
+    pub fn renew_ip (&self) -> Result<RenewOutcome> {
+        self.renew_ip_with (None)
+    }
+
+    /// Like `renew_ip`, but the address is renewed by the renewer of the server named `renewer`
+    /// (its key in `server.renewer`, e.g. "wan2") if given, rather than by the one in use.
+    pub fn renew_ip_with (&self, renewer: Option<&str>) -> Result<RenewOutcome> {
+        let renewer = renewer.map (String::from);
+        match self.request (&Packet::FreshIPRequest { renewer })? {
+            Packet::Ok => Ok(RenewOutcome::default()),
+            Packet::Renewed(outcome) => Ok(outcome),
+            Packet::IPUnchanged(ip) =>
+                bail!("the IP address was renewed, but it's still {}", ip),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+    }
+
+    /// Sets whether the server can renew the IP address.
+    pub fn set_renewing_availability (&self, availability: RenewAvailability) -> Result<()> {
+        self.send (&Packet::SetRenewingAvailable (availability))
+    }
+
+    /// Asks the server to reload its logging, metrics and notifier configuration, authenticated
+    /// by its admin `token`.
+    pub fn reload_config (&self, token: Secret<String>) -> Result<()> {
+        self.send (&Packet::ReloadConfig { token })
+    }
+
+    /// Asks the server for its status, including what the router tells about its link.
+    pub fn status (&self) -> Result<Status> {
+        match self.request (&Packet::StatusRequest)? {
+            Packet::Status(status) => Ok(status),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+    }
+
+    /// Asks the server about the health of its renewer, along with how long ago it was checked.
+    pub fn health (&self) -> Result<(Health, Duration)> {
+        match self.request (&Packet::HealthRequest)? {
+            Packet::Health { health, age } => Ok((health, age)),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+    }
+
+    /// Asks the server for its latest `limit` renewals, most recent first.
+    pub fn history (&self, limit: u16) -> Result<Vec<HistoryEntry>> {
+        match self.request (&Packet::HistoryRequest { limit })? {
+            Packet::History(entries) => Ok(entries),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+    }
+
+    /// Subscribes to the events of the server (see `Packet::Subscribe`), calling `on_event` for
+    /// every event until it breaks or `shutdown` is requested. Once subscribed, the client
+    /// subscribes again whenever the connection is lost, e.g. because the server restarted.
+    pub fn subscribe (&self, on_event: &mut OnEvent, shutdown: &Shutdown) -> Result<()> {
+        let mut subscribed = false;
+        while !shutdown.is_requested() {
+            match self.follow (on_event, shutdown, &mut subscribed) {
+                Ok(()) => break,
+                Err(err) if subscribed && !matches!(err, Error::Refused(..)) => {
+                    warn!(target: "client", "lost the events of {} ({}), subscribing again in {}s",
+                        self.connect_to.name(), err, RESUBSCRIBE_DELAY.as_secs());
+                    shutdown.wait_timeout (RESUBSCRIBE_DELAY);
+                },
+                Err(err) => return Err(err)
+            }
+        }
+        Ok(())
+    }
+
+    // Subscribes to the events of the server over a new connection, and follows them until
+    // `on_event` breaks or `shutdown` is requested.
+    fn follow (&self, on_event: &mut OnEvent, shutdown: &Shutdown, subscribed: &mut bool)
+        -> Result<()>
+    {
+        let (connection, socket) = self.open()?;
+        self.ensure_supported (&Packet::Subscribe)?;
+        let connect_to = self.connect_to.name();
+        // Events may follow the answer right away, so the same reader must read both.
+        let mut reader = BufReader::new (&connection);
+        let mut writer = BufWriter::new (&connection);
+        Packet::Subscribe.send (&mut writer, &connect_to)?;
+        writer.flush().chain_err (|| "failed to flush the I/O stream")?;
+        match Packet::receive (&mut reader, &connect_to)? {
+            Packet::Ok => (),
+            Packet::AuthChallenge(_) => bail!("the server requires a pre-shared key"),
+            Packet::Error { code, detail } => bail!(Error::Refused (code, detail)),
+            response => bail!("received unknown packet: {:?}", response)
+        }
+        info!(target: "client", "subscribed to the events of {}", connect_to);
+        *subscribed = true;
+        // Events come from the server, unless it's reached through a proxy.
+        let from = socket.peer_addr().ok().filter (|_| self.proxy.is_none());
+        let done = AtomicBool::new (false);
+        thread::scope (|scope| {
+            // Reads block until an event arrives, so the socket is shut down to stop them.
+            scope.spawn (|| {
+                while !done.load (Ordering::SeqCst) {
+                    if shutdown.wait_timeout (SHUTDOWN_POLL) {
+                        let _ = socket.shutdown (SocketShutdown::Both);
+                        break;
+                    }
+                }
+            });
+            let followed = loop {
+                match Packet::receive (&mut reader, &connect_to) {
+                    Ok(Packet::Event(event, payload)) => {
+                        if on_event (event, &payload, from).is_break() {
+                            break Ok(());
+                        }
+                    },
+                    Ok(packet) =>
+                        break Err(format!("received unknown packet: {:?}", packet).into()),
+                    Err(_) if shutdown.is_requested() => break Ok(()),
+                    Err(err) => break Err(Error::from (err))
+                }
+            };
+            done.store (true, Ordering::SeqCst);
+            followed
+        })
+    }
+
+    /// Asks the server to switch to its renewer `renewer`, authenticated by its admin `token`.
+    pub fn set_renewer (&self, renewer: &str, token: Secret<String>) -> Result<()> {
+        self.send (&Packet::SetRenewer { renewer: renewer.into(), token })
+    }
+}
+
+// What `request` asks the server, for errors.
+fn describe (request: &Packet) -> &'static str {
+    match *request {
+        Packet::ReloadConfig { .. } => "reloading its configuration",
+        Packet::SetRenewer { .. } => "switching renewers",
+        Packet::StatusRequest => "reporting its status",
+        Packet::HealthRequest => "reporting the health of its renewer",
+        Packet::HistoryRequest { .. } => "keeping a history of renewals",
+        _ => "this request"
+    }
+}
@@ -0,0 +1,181 @@
+//! Connections through a SOCKS5 proxy (RFC 1928), such as the one of `ssh -D` or Tor.
+
+use crate::config::Secret;
+use crate::errors::*;
+use std::io::prelude::*;
+use std::net::{IpAddr, TcpStream};
+
+/// A SOCKS5 proxy, optionally authenticated with a username and a password (RFC 1929). Host
+/// names are resolved by the proxy, so that names only known to it can be reached.
+#[derive(Clone, Debug)]
+pub struct Proxy {
+    /// Address and port of the proxy, e.g. "127.0.0.1:1080".
+    pub addr: String,
+    pub credentials: Option<(String, Secret<String>)>
+}
+
+const VERSION: u8 = 5;
+const NO_AUTHENTICATION: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHODS: u8 = 0xff;
+const CONNECT: u8 = 1;
+const IPV4: u8 = 1;
+const DOMAIN_NAME: u8 = 3;
+const IPV6: u8 = 4;
+
+impl Proxy {
+    /// Connects to `target` (e.g. "home.example.org:5454") through the proxy.
+    pub fn connect (&self, target: &str) -> Result<TcpStream> {
+        let (host, port) = split_host_port (target)
+            .chain_err (|| format!("'{}' is not a valid address and port", target))?;
+        let mut stream = TcpStream::connect (&self.addr)
+            .chain_err (|| format!("failed to connect to the proxy {}", self.addr))?;
+        self.handshake (&mut stream, host, port)
+            .chain_err (|| format!("the proxy {} failed to connect to {}", self.addr, target))?;
+        debug!(target: "client", "connected to {} through the proxy {}", target, self.addr);
+        Ok(stream)
+    }
+
+    fn handshake<S: Read + Write> (&self, stream: &mut S, host: &str, port: u16) -> Result<()> {
+        // Methods negotiation.
+        let methods: &[u8] = match self.credentials {
+            Some(_) => &[NO_AUTHENTICATION, USERNAME_PASSWORD],
+            None => &[NO_AUTHENTICATION]
+        };
+        let mut greeting = vec![VERSION, methods.len() as u8];
+        greeting.extend_from_slice (methods);
+        stream.write_all (&greeting).chain_err (|| "failed to send the greeting")?;
+        let mut reply = [0; 2];
+        stream.read_exact (&mut reply).chain_err (|| "failed to receive the chosen method")?;
+        ensure!(reply[0] == VERSION, "the proxy doesn't speak SOCKS5");
+        match (reply[1], self.credentials.as_ref()) {
+            (NO_AUTHENTICATION, _) => (),
+            (USERNAME_PASSWORD, Some((username, password))) =>
+                authenticate (stream, username, password.expose())?,
+            (NO_ACCEPTABLE_METHODS, _) if self.credentials.is_none() =>
+                bail!("the proxy requires authentication"),
+            (method, _) => bail!("the proxy chose an unsupported method ({})", method)
+        }
+
+        // The connection request, with the host as is unless it's an IP address.
+        let mut request = vec![VERSION, CONNECT, 0];
+        match host.parse::<IpAddr>() {
+            Ok(IpAddr::V4(ip)) => {
+                request.push (IPV4);
+                request.extend_from_slice (&ip.octets());
+            },
+            Ok(IpAddr::V6(ip)) => {
+                request.push (IPV6);
+                request.extend_from_slice (&ip.octets());
+            },
+            Err(_) => {
+                ensure!(host.len() <= 255, "the host name is too long");
+                request.extend_from_slice (&[DOMAIN_NAME, host.len() as u8]);
+                request.extend_from_slice (host.as_bytes());
+            }
+        }
+        request.extend_from_slice (&port.to_be_bytes());
+        stream.write_all (&request).chain_err (|| "failed to send the connection request")?;
+
+        let mut reply = [0; 4];
+        stream.read_exact (&mut reply).chain_err (|| "failed to receive the connection reply")?;
+        ensure!(reply[0] == VERSION, "the proxy doesn't speak SOCKS5");
+        match reply[1] {
+            0 => (),
+            1 => bail!("general SOCKS server failure"),
+            2 => bail!("connection not allowed by ruleset"),
+            3 => bail!("network unreachable"),
+            4 => bail!("host unreachable"),
+            5 => bail!("connection refused"),
+            6 => bail!("TTL expired"),
+            code => bail!("the proxy refused the connection (error {})", code)
+        }
+        // Skip the address the proxy bound to, followed by its port.
+        let length = match reply[3] {
+            IPV4 => 4,
+            IPV6 => 16,
+            DOMAIN_NAME => {
+                let mut length = [0];
+                stream.read_exact (&mut length).chain_err (|| "failed to receive the reply")?;
+                length[0].into()
+            },
+            kind => bail!("the proxy replied with an unknown type of address ({})", kind)
+        };
+        let mut bound = vec![0; length + 2];
+        stream.read_exact (&mut bound).chain_err (|| "failed to receive the reply")?;
+        Ok(())
+    }
+}
+
+// Authenticates with a username and a password (RFC 1929).
+fn authenticate<S: Read + Write> (stream: &mut S, username: &str, password: &str) -> Result<()> {
+    ensure!(
+        username.len() <= 255 && password.len() <= 255,
+        "the username and the password of the proxy can't be longer than 255 bytes"
+    );
+    let mut request = vec![1, username.len() as u8];
+    request.extend_from_slice (username.as_bytes());
+    request.push (password.len() as u8);
+    request.extend_from_slice (password.as_bytes());
+    stream.write_all (&request).chain_err (|| "failed to send the credentials")?;
+    let mut reply = [0; 2];
+    stream.read_exact (&mut reply).chain_err (|| "failed to receive the authentication reply")?;
+    ensure!(reply[1] == 0, "the proxy rejected the credentials");
+    Ok(())
+}
+
+// Splits "host:port" (or "[ipv6]:port") into its host (without brackets) and its port.
+fn split_host_port (target: &str) -> Option<(&str, u16)> {
+    let (host, port) = target.rsplit_once (':')?;
+    let host = host.strip_prefix ('[').and_then (|h| h.strip_suffix (']')).unwrap_or (host);
+    Some ((host, port.parse().ok()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn connects_through_the_proxy() {
+        let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+        let proxy = Proxy {
+            addr: listener.local_addr().unwrap().to_string(),
+            credentials: Some (("xenon".into(), "hunter2".into()))
+        };
+        let server = thread::spawn (move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            // Reads `length` bytes, then answers with `reply`.
+            let mut exchange = |length: usize, reply: &[u8]| {
+                let mut buf = vec![0; length];
+                stream.read_exact (&mut buf).unwrap();
+                stream.write_all (reply).unwrap();
+                buf
+            };
+            let greeting = exchange (4, b"\x05\x02");
+            let credentials = exchange (15, b"\x01\x00");
+            let request = exchange (
+                5 + "home.example.org".len() + 2,
+                b"\x05\x00\x00\x01\x7f\x00\x00\x01\x15\x4ehello"
+            );
+            (greeting, credentials, request)
+        });
+
+        let mut stream = proxy.connect ("home.example.org:5454").unwrap();
+        let mut hello = String::new();
+        stream.read_to_string (&mut hello).unwrap();
+        assert_eq!(hello, "hello");
+        let (greeting, credentials, request) = server.join().unwrap();
+        assert_eq!(greeting, b"\x05\x02\x00\x02");
+        assert_eq!(credentials, b"\x01\x05xenon\x07hunter2");
+        assert_eq!(request, b"\x05\x01\x00\x03\x10home.example.org\x15\x4e");
+    }
+
+    #[test]
+    fn hosts_and_ports_are_split() {
+        assert_eq!(split_host_port ("[fd00::1]:5454"), Some (("fd00::1", 5454)));
+        assert_eq!(split_host_port ("router:5454"), Some (("router", 5454)));
+        assert_eq!(split_host_port ("router"), None);
+    }
+}
@@ -0,0 +1,72 @@
+//! Durations written as a number followed by a unit, e.g. "90s", "2h" or "1h30m".
+
+use super::Result;
+use std::fmt::Write;
+use std::time::Duration;
+
+// The units, along with their length in seconds.
+const UNITS: &[(char, u64)] = &[('d', 86400), ('h', 3600), ('m', 60), ('s', 1)];
+
+/// Parses a duration such as "2h", "1h30m" or "45s". Units are days (`d`), hours (`h`), minutes
+/// (`m`) and seconds (`s`).
+pub fn parse_duration (duration: &str) -> Result<Duration> {
+    let mut seconds: u64 = 0;
+    let mut number = String::new();
+    for c in duration.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push (c);
+            continue;
+        }
+        let unit = UNITS.iter()
+            .find (|&&(unit, _)| unit == c)
+            .map (|&(_, length)| length);
+        match (unit, number.parse::<u64>()) {
+            (Some(length), Ok(n)) => seconds = n.checked_mul (length)
+                .and_then (|n| seconds.checked_add (n))
+                .ok_or_else (|| format!("the duration '{}' is too long", duration))?,
+            _ => bail!(
+                "invalid duration '{}', expected e.g. \"2h\", \"1h30m\" or \"45s\"", duration
+            )
+        }
+        number.clear();
+    }
+    ensure!(
+        number.is_empty() && seconds > 0,
+        "invalid duration '{}', expected e.g. \"2h\", \"1h30m\" or \"45s\"", duration
+    );
+    Ok(Duration::from_secs (seconds))
+}
+
+/// Formats a duration as `parse_duration` parses it, e.g. "1h30m". Fractions of a second are
+/// dropped.
+pub fn format_duration (duration: Duration) -> String {
+    let mut seconds = duration.as_secs();
+    if seconds == 0 {
+        return "0s".into();
+    }
+    let mut formatted = String::new();
+    for &(unit, length) in UNITS {
+        if seconds >= length {
+            write!(formatted, "{}{}", seconds / length, unit).unwrap();
+            seconds %= length;
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations_are_parsed_and_formatted() {
+        assert_eq!(parse_duration ("2h").unwrap(), Duration::from_secs (7200));
+        assert_eq!(parse_duration ("1h30m").unwrap(), Duration::from_secs (5400));
+        assert_eq!(parse_duration ("1d12h").unwrap(), Duration::from_secs (129600));
+        assert_eq!(format_duration (Duration::from_secs (5400)), "1h30m");
+        assert_eq!(format_duration (Duration::from_secs (90061)), "1d1h1m1s");
+        for invalid in &["", "2", "h", "2x", "0s", "-1h", "99999999999999999999d"] {
+            assert!(parse_duration (invalid).is_err(), "'{}' was parsed", invalid);
+        }
+    }
+}
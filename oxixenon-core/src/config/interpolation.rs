@@ -0,0 +1,139 @@
+//! Substitution of environment variables in configuration values.
+
+use super::{Result, ResultExt};
+use std::env;
+
+/// Replaces references to environment variables in every string of `value` (recursively).
+///
+/// The supported syntax is:
+/// - `${NAME}`: the value of `NAME`, or an empty string if it's not set;
+/// - `${NAME:-default}`: the value of `NAME`, or `default` if it's not set or empty;
+/// - `${NAME:?}` or `${NAME:?message}`: the value of `NAME`, or an error if it's not set or empty;
+/// - `$${`: a literal `${`.
+pub(super) fn interpolate_env_vars (value: &mut toml::Value, path: &str) -> Result<()> {
+    match *value {
+        toml::Value::String(ref mut string) if string.contains ('$') => {
+            *string = interpolate (string, &|name| env::var (name).ok())
+                .chain_err (|| format!("invalid value for option '{}'", path))?;
+        },
+        toml::Value::Array(ref mut array) => {
+            for (index, item) in array.iter_mut().enumerate() {
+                interpolate_env_vars (item, &format!("{}[{}]", path, index))?;
+            }
+        },
+        toml::Value::Table(ref mut table) => {
+            for (key, item) in table.iter_mut() {
+                let item_path =
+                    if path.is_empty() { key.clone() } else { format!("{}.{}", path, key) };
+                interpolate_env_vars (item, &item_path)?;
+            }
+        },
+        _ => ()
+    }
+    Ok(())
+}
+
+// Errors only tell the name of the variable and the offset of its reference in `input`, which is
+// often a secret (e.g. "${ROUTER_PW}x").
+fn interpolate (input: &str, lookup: &dyn Fn(&str) -> Option<String>) -> Result<String> {
+    let mut output = String::with_capacity (input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find ('$') {
+        output.push_str (&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with ("$${") {
+            output.push_str ("${");
+            rest = &rest[3..];
+            continue;
+        }
+        if !rest.starts_with ("${") {
+            output.push ('$');
+            rest = &rest[1..];
+            continue;
+        }
+        let offset = input.len() - rest.len();
+        let end = rest.find ('}')
+            .chain_err (|| format!("unterminated variable reference at offset {}", offset))?;
+        let expression = &rest[2..end];
+        rest = &rest[end + 1..];
+        // Split "NAME:-default" and "NAME:?message" into their components.
+        let (name, modifier) = match expression.find (':') {
+            Some(index) => (&expression[..index], Some(&expression[index + 1..])),
+            None        => (expression, None)
+        };
+        ensure!(
+            !name.is_empty() && name.chars().all (|c| c.is_ascii_alphanumeric() || c == '_'),
+            "invalid environment variable name '{}' at offset {}", name, offset
+        );
+        ensure!(
+            modifier.is_none_or (|m| m.starts_with ('-') || m.starts_with ('?')),
+            "invalid modifier for environment variable '{}' at offset {}", name, offset
+        );
+        let value = lookup (name).filter (|v| !v.is_empty() || modifier.is_none());
+        match (value, modifier) {
+            (Some(value), _) => output.push_str (&value),
+            (None, None) => (),
+            (None, Some(modifier)) => {
+                let (kind, argument) = modifier.split_at (1);
+                if kind == "-" {
+                    output.push_str (argument);
+                } else {
+                    bail!("environment variable '{}' is required but not set{}{}",
+                        name, if argument.is_empty() { "" } else { ": " }, argument)
+                }
+            }
+        }
+    }
+    output.push_str (rest);
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lookup (name: &str) -> Option<String> {
+        match name {
+            "ROUTER_PW" => Some ("hunter2".into()),
+            "EMPTY"     => Some ("".into()),
+            _           => None
+        }
+    }
+
+    #[test]
+    fn interpolates_variables() {
+        assert_eq!(interpolate ("${ROUTER_PW}", &lookup).unwrap(), "hunter2");
+        assert_eq!(interpolate ("pw=${ROUTER_PW}!", &lookup).unwrap(), "pw=hunter2!");
+        assert_eq!(interpolate ("${MISSING}", &lookup).unwrap(), "");
+        assert_eq!(interpolate ("${EMPTY}", &lookup).unwrap(), "");
+        assert_eq!(interpolate ("no variables, $5", &lookup).unwrap(), "no variables, $5");
+        assert_eq!(interpolate ("$${ROUTER_PW}", &lookup).unwrap(), "${ROUTER_PW}");
+    }
+
+    #[test]
+    fn interpolates_defaults_and_required_variables() {
+        assert_eq!(interpolate ("${MISSING:-admin}", &lookup).unwrap(), "admin");
+        assert_eq!(interpolate ("${EMPTY:-admin}", &lookup).unwrap(), "admin");
+        assert_eq!(interpolate ("${ROUTER_PW:-admin}", &lookup).unwrap(), "hunter2");
+        assert_eq!(interpolate ("${ROUTER_PW:?}", &lookup).unwrap(), "hunter2");
+        assert!(interpolate ("${MISSING:?}", &lookup).is_err());
+        assert!(interpolate ("${EMPTY:?password needed}", &lookup).is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_references() {
+        assert!(interpolate ("${ROUTER_PW", &lookup).is_err());
+        assert!(interpolate ("${}", &lookup).is_err());
+        assert!(interpolate ("${A B}", &lookup).is_err());
+        assert!(interpolate ("${ROUTER_PW:+x}", &lookup).is_err());
+    }
+
+    #[test]
+    fn errors_dont_reveal_values() {
+        let error = |input| interpolate (input, &lookup).unwrap_err().to_string();
+        assert_eq!(error ("hunter2${ROUTER_PW"), "unterminated variable reference at offset 7");
+        assert_eq!(error ("hunter2${A B}"), "invalid environment variable name 'A B' at offset 7");
+        assert_eq!(error ("hunter2${ROUTER_PW:+x}"),
+            "invalid modifier for environment variable 'ROUTER_PW' at offset 7");
+    }
+}
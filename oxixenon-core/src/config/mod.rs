@@ -0,0 +1,1249 @@
+use crate::acl::{Acl, Action, IpRange, Scope, Token};
+use crate::schedule::Schedule;
+use crate::errors::Cause;
+use crate::protocol;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+mod duration;
+mod interpolation;
+#[cfg(feature = "config-schema")]
+pub mod schema;
+mod search_paths;
+mod secret;
+mod section;
+
+pub use self::duration::{parse_duration, format_duration};
+pub use self::search_paths::{search_paths, find_config_file};
+pub use self::secret::Secret;
+pub use self::section::Section;
+pub(crate) use self::section::SourceMap;
+
+// config::Error type
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    #[error("missing configuration option: {0}")]
+    MissingOption(String),
+    #[error("invalid configuration option: {0}")]
+    InvalidOption(String),
+    #[error("unknown configuration option '{0}'{line}", line = line_suffix (*.1))]
+    UnknownOption(String, Option<usize>),
+    #[error("invalid configuration option '{0}'{line}: {2}", line = line_suffix (*.1))]
+    InvalidValue(String, Option<usize>, String)
+}
+
+chained_error!(Error);
+
+// The line of an option, if known, as shown by errors.
+fn line_suffix (line: Option<usize>) -> String {
+    line.map (|l| format!(" (line {})", l)).unwrap_or_default()
+}
+
+// Configuration models
+#[derive(Debug)]
+pub enum ClientAction {
+    /// Renews the IP address with the renewer of the server, or with the given one among its
+    /// configured renewers.
+    RenewIP(Option<String>),
+    SetRenewingAvailability(protocol::RenewAvailability),
+    SubscribeToNotifications(NotificationsOutput, NotificationsSource),
+    /// Reloads the configuration of the server, which requires the admin token as `SetRenewer`.
+    ReloadConfig,
+    /// Shows the status of the server, see `protocol::Status`.
+    Status,
+    /// Shows the health of the renewer of the server, see `protocol::Health`.
+    Health,
+    /// Shows the latest renewals of the server (at most the given number), see
+    /// `protocol::HistoryEntry`.
+    History(u16),
+    /// Switches the server to another of its renewers (see `ServerConfig::renewers`), which
+    /// requires `ClientConfig::admin_token`, or a token of scope `admin` as `ClientConfig::psk`.
+    SetRenewer(String),
+    /// Shows an icon in the notification area (Windows only), see `notification_toasts::tray`.
+    Tray
+}
+
+/// How the events received by `ClientAction::SubscribeToNotifications` are shown, besides being
+/// shown as toasts (if enabled).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationsOutput {
+    /// Logged.
+    Log,
+    /// Written to STDOUT as JSON objects, one per line (while messages are logged to STDERR, see
+    /// `logging::reserve_stdout`).
+    Ndjson
+}
+
+/// Where `ClientAction::SubscribeToNotifications` receives the events from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotificationsSource {
+    /// The notifier, e.g. multicast packets.
+    Notifier,
+    /// The connection to the server, see `protocol::Packet::Subscribe`.
+    Server
+}
+
+impl fmt::Display for ClientAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ClientAction::RenewIP(None) => write!(f, "renew ip"),
+            ClientAction::RenewIP(Some(ref renewer)) =>
+                write!(f, "renew ip with renewer {}", renewer),
+            ClientAction::SetRenewingAvailability(ref availability) =>
+                write!(f, "set renewal availability to {}", availability),
+            ClientAction::SubscribeToNotifications(_, NotificationsSource::Notifier) =>
+                write!(f, "listen to notifications"),
+            ClientAction::SubscribeToNotifications(_, NotificationsSource::Server) =>
+                write!(f, "listen to the notifications of the server"),
+            ClientAction::ReloadConfig => write!(f, "reload the server configuration"),
+            ClientAction::Status => write!(f, "show the server status"),
+            ClientAction::Health => write!(f, "show the health of the server renewer"),
+            ClientAction::History(limit) =>
+                write!(f, "show the latest {} renewals of the server", limit),
+            ClientAction::SetRenewer(ref renewer) =>
+                write!(f, "switch the server to renewer {}", renewer),
+            ClientAction::Tray => write!(f, "show the notification area icon")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ClientConfig {
+    pub connect_to: String,
+    /// How often a host name in `connect_to` is resolved again, see `resolver::Resolver`.
+    pub resolve_interval: Option<Duration>,
+    /// The SOCKS5 proxy to connect to the server through, if any.
+    pub proxy: Option<ProxyConfig>,
+    pub action: ClientAction,
+    /// The configuration of the notification toasts, see `notification_toasts::ToastTemplates`.
+    pub toasts: Section,
+    /// Bursts of identical events received within this window are coalesced into a single
+    /// toast, see `notifier::Coalescer`.
+    pub coalesce_events: Option<Duration>,
+    /// Authenticates the requests reserved to administrators, see `ServerConfig::admin_token`.
+    pub admin_token: Option<Secret<String>>,
+    /// Authenticates the client to the server, see `ServerConfig::psk`.
+    pub psk: Option<Secret<String>>,
+    /// Talks to the server over TLS, if set.
+    pub tls: Option<ClientTlsConfig>
+}
+
+/// TLS for the connection to the server, see `tls::ClientTls`.
+#[derive(Debug)]
+pub struct ClientTlsConfig {
+    /// The CAs which sign the certificate of the server, or the certificate itself (PEM).
+    pub ca: PathBuf,
+    /// The name which the certificate of the server is checked against, if not the host in
+    /// `connect_to`.
+    pub server_name: Option<String>,
+    /// The certificate chain presented to the server and its private key (PEM), if the server
+    /// requires one.
+    pub identity: Option<(PathBuf, PathBuf)>
+}
+
+/// A SOCKS5 proxy, see `client::Proxy`.
+#[derive(Debug)]
+pub struct ProxyConfig {
+    pub addr: String,
+    /// The credentials of the proxy, which are either both set or both missing.
+    pub credentials: Option<(String, Secret<String>)>
+}
+
+#[derive(Debug, Clone)]
+pub struct RenewerConfig {
+    /// The renewer implementation, e.g. "fritzbox".
+    pub name: String,
+    /// The name of this instance, i.e. its key in `server.renewer`. It's the same as `name`
+    /// unless the section specifies the implementation with the `type` option.
+    pub instance: String,
+    pub config: Section
+}
+
+#[derive(Debug)]
+pub struct ServerConfig {
+    pub bind_to: String,
+    /// Where the server also listens for clients speaking the JSON encoding of the protocol, see
+    /// `protocol::Encoding::Json`.
+    pub json_bind_to: Option<String>,
+    pub renewer: RenewerConfig,
+    /// The other renewers configured in `server.renewer`, which administrators can switch to
+    /// while the server is running.
+    pub renewers: Vec<RenewerConfig>,
+    /// The token required by the requests reserved to administrators (such as reloading the
+    /// configuration and switching renewers) from clients without a token of scope `admin`,
+    /// which are refused when missing.
+    pub admin_token: Option<Secret<String>>,
+    /// The key which clients must prove to know before any of their requests is accepted, if
+    /// any, see `server::Server::psk`.
+    pub psk: Option<Secret<String>>,
+    /// The clients which are answered, and those which may send each request, see
+    /// `server::Server::acl`.
+    pub acl: Acl,
+    /// The tokens which clients can authenticate with instead of the pre-shared key, see
+    /// `server::Server::token`.
+    pub tokens: Vec<Token>,
+    /// Bursts of identical events within this window are coalesced into a single notification,
+    /// see `notifier::Coalescer`.
+    pub coalesce_events: Option<Duration>,
+    /// The host name sent along with the events, if not the one of the machine, see
+    /// `server::Server::origin`.
+    pub hostname: Option<String>,
+    /// How long the connections of clients are kept open between requests, if not the default
+    /// one, see `server::Server::idle_timeout`.
+    pub idle_timeout: Option<Duration>,
+    /// How many clients are answered at once, if not the default number, see
+    /// `server::Server::max_clients`.
+    pub max_clients: Option<usize>,
+    /// How long renewal requests are gathered into a single renewal, if not the default window,
+    /// see `server::Server::coalesce_renewals`.
+    pub coalesce_renewals: Option<Duration>,
+    /// How many renewals are kept in the history, if not the default number, see
+    /// `server::Server::history_size`.
+    pub history_size: Option<usize>,
+    /// Where the state which survives restarts is kept, see `state::StateDir`.
+    pub state_dir: Option<PathBuf>,
+    /// How often the session of the renewer with the router is refreshed, if it is, see
+    /// `server::Server::keepalive`.
+    pub keepalive_interval: Option<Duration>,
+    /// How often the health of the renewer is checked, if it is, see
+    /// `server::Server::check_health`.
+    pub health_check_interval: Option<Duration>,
+    /// When the IP address is renewed without being requested, see `schedule::Schedule`.
+    pub schedule: Vec<Schedule>,
+    /// How the public IP address is checked for changes which weren't requested, if it is.
+    pub ip_check: Option<IpCheckConfig>,
+    /// How renewals are verified, if they are.
+    pub verify_renewal: Option<VerifyRenewalConfig>,
+    /// Talks to clients over TLS, if set.
+    pub tls: Option<ServerTlsConfig>
+}
+
+/// TLS for the connections of clients, see `tls::ServerTls`.
+#[derive(Debug)]
+pub struct ServerTlsConfig {
+    /// The certificate chain presented to clients and its private key (PEM).
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// The CAs which sign the certificates that clients must present (PEM), if they must.
+    pub client_ca: Option<PathBuf>
+}
+
+/// Periodic checks of the public IP address, see `server::Server::check_ip`.
+#[derive(Debug)]
+pub struct IpCheckConfig {
+    pub interval: Duration,
+    /// The URL answering with the address as plain text, or `None` to ask the renewer.
+    pub url: Option<String>,
+    /// The STUN server telling the address, e.g. "stun.l.google.com:19302". At most one of `url`
+    /// and `stun` is set.
+    pub stun: Option<String>
+}
+
+/// Checks that renewals changed the public IP address, see `server::Server::verify_renewals`.
+#[derive(Debug)]
+pub struct VerifyRenewalConfig {
+    /// How long the address can take to change.
+    pub timeout: Duration,
+    /// How often the address is read while waiting for it to change.
+    pub interval: Duration,
+    /// Where the address is read from, as in `IpCheckConfig`.
+    pub url: Option<String>,
+    pub stun: Option<String>
+}
+
+/// What this instance runs: a server, a client, or both ("all-in-one"), in which case the client
+/// listens for the events of its own server.
+#[derive(Debug)]
+pub struct Mode {
+    pub server: Option<ServerConfig>,
+    pub client: Option<ClientConfig>
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.server, &self.client) {
+            (Some(_), Some(_)) => write!(f, "all-in-one mode"),
+            (Some(_), None) => write!(f, "server mode"),
+            _ => write!(f, "client mode")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct NotifierConfig {
+    pub name: String,
+    pub config: Section
+}
+
+#[derive(Debug)]
+pub struct LogBackendConfig {
+    pub name: String,
+    pub config: Section
+}
+
+#[derive(Debug)]
+pub struct LogConfig {
+    pub level: String,
+    pub backends: Vec<LogBackendConfig>,
+    /// Whether protocol packets are hexdumped at trace level.
+    pub trace_packets: bool
+}
+
+#[derive(Debug)]
+pub struct MetricsExporterConfig {
+    pub name: String,
+    pub config: Section
+}
+
+#[derive(Debug, Default)]
+pub struct MetricsConfig {
+    /// The enabled exporters, see `metrics::init`. Metrics are not reported when empty.
+    pub exporters: Vec<MetricsExporterConfig>
+}
+
+#[derive(Debug)]
+pub struct Config {
+    pub mode: Mode,
+    pub notifier: NotifierConfig,
+    pub logging: LogConfig,
+    pub metrics: MetricsConfig,
+    /// Problems found in the configuration file which are not fatal (e.g. unknown options).
+    /// They are collected here as the logger is not ready yet while parsing the configuration.
+    pub warnings: Vec<String>
+}
+
+// Layout of the configuration file. The configuration of each component is kept as a raw value
+// and is only deserialized by the component itself (see `Section`). Documentation comments end up
+// in the schema of the configuration.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ConfigFile {
+    /// Whether unknown options are errors rather than warnings.
+    // Read before deserializing the rest of the file, see `parse_config`.
+    #[serde(default)]
+    #[allow(dead_code)]
+    strict: bool,
+    /// Whether this instance runs as a "server", a "client" or both ("all-in-one").
+    mode: Option<String>,
+    /// The notifier used to notify events.
+    notifier_name: Option<String>,
+    /// Bursts of identical events within this window (e.g. "30s") are coalesced into a single
+    /// notification.
+    coalesce_events: Option<String>,
+    /// The directory where the state which survives restarts (such as the availability of
+    /// renewals) is kept. Nothing is kept when missing.
+    state_dir: Option<String>,
+    /// The configuration of each notifier.
+    #[serde(default)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "crate::notifier::config_schema"))]
+    notifier: BTreeMap<String, toml::Value>,
+    logging: LoggingSection,
+    #[serde(default)]
+    metrics: MetricsSection,
+    #[serde(default)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "schema::server"))]
+    server: Option<ServerSection>,
+    #[serde(default)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "schema::client"))]
+    client: Option<ClientSection>
+}
+
+// The options of `server` and `client` holding the admin token and the pre-shared key.
+const ADMIN_TOKEN: &str = "admin_token";
+const PSK: &str = "psk";
+// The options of `server` and `client` which can also be read from a file or from an environment
+// variable (like the secrets of components, see `Section::with_secrets`).
+const SERVER_SECRETS: &[&str] = &[ADMIN_TOKEN, PSK];
+const CLIENT_SECRETS: &[&str] = &[ADMIN_TOKEN, PSK, "proxy_password"];
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct LoggingSection {
+    /// One of "off", "error", "warn", "info", "debug", "trace".
+    verbosity: Option<String>,
+    /// The enabled logging backends.
+    backends: Vec<String>,
+    /// Whether every protocol packet sent or received is hexdumped (requires verbosity "trace").
+    #[serde(default)]
+    trace_packets: bool,
+    // The configuration of each backend, e.g. `logging.file`.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "crate::logging::config_schema"))]
+    backend_configs: BTreeMap<String, toml::Value>
+}
+
+#[derive(Deserialize, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct MetricsSection {
+    /// The enabled metrics exporters, e.g. "statsd".
+    #[serde(default)]
+    exporters: Vec<String>,
+    // The configuration of each exporter, e.g. `metrics.statsd`.
+    #[serde(flatten)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "crate::metrics::config_schema"))]
+    exporter_configs: BTreeMap<String, toml::Value>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ServerSection {
+    /// IP address and port to bind to.
+    bind_to: Option<String>,
+    /// IP address and port to also bind to for the clients speaking the JSON encoding of the
+    /// protocol (requires the feature `json-protocol`).
+    json_bind_to: Option<String>,
+    /// The renewer used to renew the IP address.
+    renewer_name: Option<String>,
+    /// The token required to switch renewers.
+    admin_token: Option<Secret<String>>,
+    /// The pre-shared key which clients must prove to know before any of their requests is
+    /// accepted.
+    psk: Option<Secret<String>>,
+    /// The clients which are answered, as IP addresses or CIDR ranges (e.g. "192.168.1.0/24").
+    /// Defaults to all of them.
+    allowed_clients: Option<Vec<String>>,
+    /// The requests (e.g. "set_availability") accepted only from some of the allowed clients, and
+    /// those clients, as in `allowed_clients`.
+    #[serde(default)]
+    restricted_actions: BTreeMap<String, Vec<String>>,
+    /// The tokens which clients can authenticate with instead of the pre-shared key, by name.
+    #[serde(default)]
+    tokens: BTreeMap<String, TokenSection>,
+    /// The host name sent along with the events, defaults to the one of the machine.
+    hostname: Option<String>,
+    /// How long the connections of clients are kept open between requests (e.g. "30s"),
+    /// defaults to 5 seconds. "0s" closes them after the first request.
+    idle_timeout: Option<String>,
+    /// How many clients are answered at once, defaults to 8.
+    max_clients: Option<usize>,
+    /// How long the renewals requested after the first one are coalesced with it (e.g. "2s"),
+    /// defaults to 1 second. "0s" only coalesces those requested during another renewal.
+    coalesce_renewals: Option<String>,
+    /// How many of the latest renewals are kept in the history shown by `client history`,
+    /// defaults to 100. The history is kept in `state_dir` too, if set.
+    history_size: Option<usize>,
+    /// How often the session of the renewer with the router is refreshed (e.g. "5m"), so that
+    /// renewals don't have to log in first.
+    keepalive_interval: Option<String>,
+    /// How often the health of the renewer is checked (e.g. "15m"), so that broken credentials
+    /// are found before the next renewal fails.
+    health_check_interval: Option<String>,
+    /// When the IP address is renewed without being requested, as cron expressions (e.g.
+    /// "30 4 * * *") or like "every day at 04:30", in the local timezone.
+    #[serde(default)]
+    schedule: Vec<String>,
+    /// Periodic checks of the public IP address, notifying the changes which weren't requested
+    /// (e.g. when the ISP reassigns it).
+    ip_check: Option<IpCheckSection>,
+    /// Checks that renewals change the public IP address, reporting those which don't.
+    verify_renewal: Option<VerifyRenewalSection>,
+    /// TLS for the connections of clients (requires the feature `tls`).
+    tls: Option<ServerTlsSection>,
+    /// The configuration of each renewer.
+    #[serde(default)]
+    #[cfg_attr(feature = "config-schema", schemars(schema_with = "schema::renewers"))]
+    renewer: BTreeMap<String, toml::Value>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ClientSection {
+    /// Address and port of the server.
+    connect_to: Option<String>,
+    /// How often a host name in `connect_to` is resolved again (e.g. "10m"), keeping the last
+    /// known address if that fails. Defaults to 5 minutes.
+    resolve_interval: Option<String>,
+    /// Address and port of a SOCKS5 proxy to connect to the server through.
+    proxy: Option<String>,
+    /// The credentials of the proxy, if it requires them.
+    proxy_username: Option<String>,
+    proxy_password: Option<Secret<String>>,
+    /// The admin token of the server, required to switch renewers.
+    admin_token: Option<Secret<String>>,
+    /// The pre-shared key of the server, if it requires one.
+    psk: Option<Secret<String>>,
+    /// TLS for the connection to the server (requires the feature `tls`).
+    tls: Option<ClientTlsSection>,
+    action: Option<ClientActionSection>,
+    /// The templates of the notification toasts.
+    #[cfg_attr(
+        all(feature = "config-schema", feature = "client"),
+        schemars(with = "Option<crate::notification_toasts::ToastTemplates>")
+    )]
+    #[cfg_attr(
+        all(feature = "config-schema", not(feature = "client")),
+        schemars(with = "Option<serde_json::Value>")
+    )]
+    toasts: Option<toml::Value>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ClientActionSection {
+    /// One of "renew", "notifications", "set_availability", "set_renewer", "reload", "status",
+    /// "health", "history", "tray".
+    name: Option<String>,
+    renew: Option<RenewSection>,
+    history: Option<HistorySection>,
+    notifications: Option<NotificationsSection>,
+    set_availability: Option<SetAvailabilitySection>,
+    set_renewer: Option<SetRenewerSection>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct IpCheckSection {
+    /// How often the address is checked, e.g. "5m".
+    interval: String,
+    /// A URL answering with the address as plain text (e.g. "https://api.ipify.org"). When
+    /// missing, the address is the one reported by the renewer.
+    url: Option<String>,
+    /// A STUN server (e.g. "stun.l.google.com:19302"), instead of `url`.
+    stun: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct TokenSection {
+    /// The secret which clients prove to know, as they would the pre-shared key.
+    secret: Secret<String>,
+    /// What the clients using the token may request: "renew", "set_availability", "subscribe"
+    /// and "admin" (everything). The status, the health and the history are always allowed.
+    #[serde(default)]
+    scopes: Vec<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ServerTlsSection {
+    /// The certificate chain presented to clients (PEM file).
+    cert: String,
+    /// The private key of the certificate (PEM file).
+    key: String,
+    /// The CAs which sign the certificates that clients must present (PEM file). When missing,
+    /// clients don't present any.
+    client_ca: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct ClientTlsSection {
+    /// The CAs which sign the certificate of the server, or the certificate itself if
+    /// self-signed (PEM file).
+    ca: String,
+    /// The name which the certificate of the server is checked against. Defaults to the host in
+    /// `connect_to`.
+    server_name: Option<String>,
+    /// The certificate chain presented to the server, if it requires one (PEM file).
+    cert: Option<String>,
+    /// The private key of the certificate (PEM file).
+    key: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct VerifyRenewalSection {
+    /// How long the address can take to change, e.g. "5m". Defaults to 2 minutes.
+    timeout: Option<String>,
+    /// How often the address is read while waiting for it to change. Defaults to 5 seconds.
+    interval: Option<String>,
+    /// A URL answering with the address as plain text (e.g. "https://api.ipify.org"). When
+    /// missing, the address is the one reported by the renewer.
+    url: Option<String>,
+    /// A STUN server (e.g. "stun.l.google.com:19302"), instead of `url`.
+    stun: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct RenewSection {
+    /// The renewer of the server which renews the address (its key in `server.renewer`, e.g.
+    /// "wan2"), rather than the one in use.
+    renewer: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct HistorySection {
+    /// How many of the latest renewals are shown, defaults to 10.
+    limit: Option<u16>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct SetRenewerSection {
+    /// The name of the renewer to switch to, i.e. its key in `server.renewer`.
+    renewer: String
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct NotificationsSection {
+    /// How the events are shown: "log" (the default) or "ndjson" (written to STDOUT as JSON
+    /// objects, one per line).
+    output: Option<String>,
+    /// Where the events come from: "notifier" (the default) or "server" (over the connection to
+    /// the server, which works wherever the server can be reached).
+    source: Option<String>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct SetAvailabilitySection {
+    available: bool,
+    /// The reason of the unavailability, required when `available` is false.
+    reason: Option<String>,
+    /// How long renewals stay unavailable, e.g. "2h" or "1h30m". Forever if missing.
+    #[serde(rename = "for")]
+    for_: Option<String>
+}
+
+// Renewals made unavailable due to `reason`, for `duration` (if specified) parsed from `option`.
+fn unavailability (reason: String, duration: Option<&str>, option: &str)
+    -> Result<protocol::RenewAvailability>
+{
+    Ok(match duration {
+        Some(duration) => protocol::RenewAvailability::UnavailableFor (
+            reason,
+            parse_duration (duration).chain_err (|| format!("invalid option '{}'", option))?
+        ),
+        None => protocol::RenewAvailability::Unavailable (reason)
+    })
+}
+
+// The clients answered by a server, given `server.allowed_clients` and
+// `server.restricted_actions`.
+fn acl (clients: Option<Vec<String>>, actions: BTreeMap<String, Vec<String>>) -> Result<Acl> {
+    fn ranges (ranges: &[String], option: &str) -> Result<Vec<IpRange>> {
+        ranges.iter()
+            .map (|range| range.parse().chain_err (|| format!("invalid option '{}'", option)))
+            .collect()
+    }
+    let mut acl = Acl::default();
+    if let Some(clients) = clients {
+        acl = acl.allow_clients (ranges (&clients, "server.allowed_clients")?);
+    }
+    for (action, clients) in actions {
+        let option = format!("server.restricted_actions.{}", action);
+        let action = action.parse::<Action>()
+            .chain_err (|| format!("invalid option '{}'", option))?;
+        acl = acl.restrict (action, ranges (&clients, &option)?);
+    }
+    Ok(acl)
+}
+
+// The tokens accepted by a server, given `server.tokens`.
+fn tokens (sections: BTreeMap<String, TokenSection>) -> Result<Vec<Token>> {
+    sections.into_iter()
+        .map (|(name, section)| {
+            let scopes = section.scopes.iter()
+                .map (|scope| scope.parse::<Scope>())
+                .collect::<std::result::Result<_, _>>()
+                .chain_err (|| format!("invalid option 'server.tokens.{}.scopes'", name))?;
+            Ok(Token { name, secret: section.secret, scopes })
+        })
+        .collect()
+}
+
+/// Command line arguments, which take precedence over the environment and the configuration file
+/// (see `Config::parse_config`). Programs without command line arguments can use `NoArgs`.
+pub trait Args {
+    /// The value of the argument `name`, if it was specified.
+    fn value_of (&self, name: &str) -> Option<&str>;
+    /// Whether the flag `name` was specified.
+    fn is_present (&self, name: &str) -> bool;
+    /// The subcommand which was specified, if any, along with its arguments.
+    fn subcommand (&self) -> Option<(&str, Box<dyn Args + '_>)>;
+}
+
+/// No command line arguments: everything comes from the environment and the configuration file.
+pub struct NoArgs;
+
+impl Args for NoArgs {
+    fn value_of (&self, _name: &str) -> Option<&str> {
+        None
+    }
+
+    fn is_present (&self, _name: &str) -> bool {
+        false
+    }
+
+    fn subcommand (&self) -> Option<(&str, Box<dyn Args + '_>)> {
+        None
+    }
+}
+
+/// Returns the value of the environment variable which can be used in place of the command line
+/// argument `arg` (e.g. `OXIXENON_NOTIFIER` for `notifier`), if it is set and not empty.
+pub fn env_arg (arg: &str) -> Option<String> {
+    env::var (format!("OXIXENON_{}", arg.to_uppercase()))
+        .ok()
+        .filter (|value| !value.is_empty())
+}
+
+// Like `env_arg`, for command line flags: any value other than "0" and "false" enables them.
+fn env_flag (arg: &str) -> bool {
+    env_arg (arg).is_some_and (|value| value != "0" && !value.eq_ignore_ascii_case ("false"))
+}
+
+impl Config {
+    /// Parses the configuration file `config_path`, along with the command line arguments and
+    /// the environment. The run mode is the subcommand of `args`, and the client action is its
+    /// own subcommand.
+    pub fn parse_config(config_path: &Path, args: &dyn Args) -> Result<Config> {
+        // Options are searched in command line arguments, then in the environment (see `env_arg`)
+        // and finally in the configuration file.
+        macro_rules! arg_or_cfg_option {
+            (from [$args:expr] get $arg:expr, from [$config:expr] named $option:expr) => {{
+                let from_config: Option<&str> = $config;
+                $args.and_then (|a| a.value_of ($arg))
+                     .map (String::from)
+                     .or_else (|| env_arg ($arg))
+                     .or_else (|| from_config.map (String::from))
+                     .chain_err (|| format!(
+                        "can't retrieve option '{}' from either command line arguments, \
+                         environment or config",
+                        $option
+                     ))
+            }}
+        }
+        // slurp the config file and parse it
+        let mut config_str = String::new();
+        File::open (config_path)
+            .chain_err (|| format!("can't open configuration file '{}'", config_path.display()))?
+            .read_to_string (&mut config_str)
+            .chain_err (|| format!("can't read configuration file '{}'", config_path.display()))?;
+        let mut config = config_str.parse::<toml::Value>()
+            .chain_err (|| format!("can't parse configuration file '{}'", config_path.display()))?;
+        interpolation::interpolate_env_vars (&mut config, "")
+            .chain_err (|| format!("can't parse configuration file '{}'", config_path.display()))?;
+        for &(name, secrets) in &[("server", SERVER_SECRETS), ("client", CLIENT_SECRETS)] {
+            if let Some(toml::Value::Table(table)) = config.get_mut (name) {
+                for secret in secrets {
+                    let path = format!("{}.{}", name, secret);
+                    if let Some(value) = section::resolve_secret (table, secret, &path)? {
+                        table.insert (secret.to_string(), toml::Value::String (value));
+                    }
+                }
+            }
+        }
+        let token_tables = config.get_mut ("server").and_then (|server| server.get_mut ("tokens"));
+        if let Some(toml::Value::Table(token_tables)) = token_tables {
+            for (name, token) in token_tables.iter_mut() {
+                if let toml::Value::Table(token) = token {
+                    let path = format!("server.tokens.{}.secret", name);
+                    if let Some(value) = section::resolve_secret (token, "secret", &path)? {
+                        token.insert ("secret".into(), toml::Value::String (value));
+                    }
+                }
+            }
+        }
+        // In strict mode, unknown options are errors rather than warnings.
+        let strict = args.is_present ("strict_config") || env_flag ("strict_config") ||
+            config.get ("strict").and_then (|strict| strict.as_bool()).unwrap_or (false);
+        let source = Arc::new (SourceMap::new (&config_str));
+        let (config, mut warnings) =
+            section::deserialize::<ConfigFile> (config, "", &source, strict)
+                .chain_err (|| format!(
+                    "can't parse configuration file '{}'", config_path.display()
+                ))?;
+        let section = |path: String, value: Option<toml::Value>|
+            Section::new (path, value, source.clone()).strict (strict);
+
+        // parse logging options
+        let logging = {
+            let LoggingSection {
+                verbosity, backends, trace_packets, mut backend_configs
+            } = config.logging;
+            // Determine verbosity. It can be specified in four ways, in order of priority:
+            // - command line argument "verbose" (sets verbosity to "debug")
+            // - command line argument "level"
+            // - environment variable "OXIXENON_LEVEL"
+            // - configuration file option "verbosity"
+            let verbosity = if args.is_present ("verbose") {
+                "debug".into()
+            } else {
+                arg_or_cfg_option!(
+                    from [Some(args)]                               get "level",
+                    from [verbosity.as_deref()]                 named "logging.verbosity"
+                )?
+            };
+            // Parse backends and their configuration.
+            let backends: Vec<LogBackendConfig> = backends
+                .into_iter()
+                .map (|name| LogBackendConfig {
+                    config: section (
+                        format!("logging.{}", name),
+                        backend_configs.remove (&name)
+                    ),
+                    name
+                })
+                .collect();
+            // Tables are the configuration of backends which are not enabled, but anything else
+            // is an option which doesn't exist.
+            for (name, value) in &backend_configs {
+                if value.is_table() {
+                    continue;
+                }
+                warnings.push (
+                    section::unknown_option (format!("logging.{}", name), &source, strict)?
+                );
+            }
+            LogConfig {
+                level: verbosity,
+                backends,
+                trace_packets
+            }
+        };
+
+        // parse metrics exporters, as done for logging backends
+        let metrics = {
+            let MetricsSection { exporters, mut exporter_configs } = config.metrics;
+            let exporters = exporters
+                .into_iter()
+                .map (|name| MetricsExporterConfig {
+                    config: section (
+                        format!("metrics.{}", name),
+                        exporter_configs.remove (&name)
+                    ),
+                    name
+                })
+                .collect();
+            for (name, value) in &exporter_configs {
+                if value.is_table() {
+                    continue;
+                }
+                warnings.push (
+                    section::unknown_option (format!("metrics.{}", name), &source, strict)?
+                );
+            }
+            MetricsConfig { exporters }
+        };
+
+        // parse notifiers
+        let notifier = {
+            let mut notifier_configs = config.notifier;
+            let chosen_notifier = arg_or_cfg_option!(
+                from [Some(args)]                       get "notifier",
+                from [config.notifier_name.as_deref()] named "notifier_name"
+            )?;
+            NotifierConfig {
+                config: section (
+                    format!("notifier.{}", chosen_notifier),
+                    notifier_configs.remove (&chosen_notifier)
+                ),
+                name: chosen_notifier
+            }
+        };
+
+        let coalesce_events = env_arg ("coalesce_events")
+            .or (config.coalesce_events)
+            .map (|window| parse_duration (&window))
+            .transpose()
+            .chain_err (|| "invalid option 'coalesce_events'")?;
+
+        let mode: Mode = {
+            // get subcommand and related args
+            let subcommand = args.subcommand();
+            let subcommand_args = subcommand.as_ref().map (|(_, args)| &**args);
+            // get run mode
+            let mode_str = subcommand.as_ref()
+                .map (|(name, _)| name.to_string())
+                .or_else (|| env_arg ("mode"))
+                .or (config.mode)
+                .chain_err (|| "can't retrieve option 'mode' from either arguments, environment \
+                                or config")?;
+
+            ensure!(
+                ["server", "client", "all-in-one"].contains (&mode_str.as_str()),
+                "unknown run mode: {}", mode_str
+            );
+            // An all-in-one instance runs a server, along with a client handling its events.
+            let all_in_one = mode_str == "all-in-one";
+
+            let server = match mode_str.as_str() {
+                "server" | "all-in-one" => {
+                    // requested server mode, get server table
+                    let mut server_section = config.server
+                        .chain_err (|| Error::MissingOption ("server".into()))?;
+                    // try to retrieve the chosen renewer first from command line arguments,
+                    // then from the config file.
+                    let chosen_renewer = arg_or_cfg_option!(
+                        from [subcommand_args]                          get "renewer",
+                        from [server_section.renewer_name.as_deref()] named "server.renewer_name"
+                    )?;
+
+                    // The same implementation can be used by multiple instances, each one with its
+                    // own section: `type` tells which implementation is used by an instance.
+                    let renewer = |instance: String, mut renewer_section: Option<toml::Value>| {
+                        let path = format!("server.renewer.{}", instance);
+                        let renewer_type = match renewer_section.as_mut()
+                            .and_then (|section| section.as_table_mut())
+                            .and_then (|table| table.remove ("type"))
+                        {
+                            Some(toml::Value::String(renewer_type)) => renewer_type,
+                            Some(_) => {
+                                let option = format!("{}.type", path);
+                                let line = source.line_of (&option);
+                                let reason = "expected a string".into();
+                                bail!(Error::InvalidValue (option, line, reason))
+                            },
+                            None => instance.clone()
+                        };
+                        Ok(RenewerConfig {
+                            name: renewer_type,
+                            instance,
+                            config: section (path, renewer_section)
+                        })
+                    };
+                    let renewer_section = server_section.renewer.remove (&chosen_renewer);
+                    let chosen = renewer (chosen_renewer, renewer_section)?;
+                    let renewers = std::mem::take (&mut server_section.renewer).into_iter()
+                        .filter (|(_, renewer_section)| renewer_section.is_table())
+                        .map (|(instance, section)| renewer (instance, Some (section)))
+                        .collect::<Result<_>>()?;
+                    let keepalive_interval = server_section.keepalive_interval.take()
+                        .map (|interval| parse_duration (&interval))
+                        .transpose()
+                        .chain_err (|| "invalid option 'server.keepalive_interval'")?;
+                    ensure!(
+                        keepalive_interval != Some (Duration::ZERO),
+                        "'server.keepalive_interval' can't be 0"
+                    );
+                    let idle_timeout = server_section.idle_timeout.take()
+                        .map (|timeout| parse_duration (&timeout))
+                        .transpose()
+                        .chain_err (|| "invalid option 'server.idle_timeout'")?;
+                    let max_clients = server_section.max_clients.take();
+                    ensure!(max_clients != Some (0), "'server.max_clients' can't be 0");
+                    let coalesce_renewals = server_section.coalesce_renewals.take()
+                        .map (|window| parse_duration (&window))
+                        .transpose()
+                        .chain_err (|| "invalid option 'server.coalesce_renewals'")?;
+                    let acl = acl (
+                        server_section.allowed_clients.take(),
+                        std::mem::take (&mut server_section.restricted_actions)
+                    )?;
+                    let tokens = tokens (std::mem::take (&mut server_section.tokens))?;
+                    let health_check_interval = server_section.health_check_interval.take()
+                        .map (|interval| parse_duration (&interval))
+                        .transpose()
+                        .chain_err (|| "invalid option 'server.health_check_interval'")?;
+                    ensure!(
+                        health_check_interval != Some (Duration::ZERO),
+                        "'server.health_check_interval' can't be 0"
+                    );
+                    let schedule = std::mem::take (&mut server_section.schedule).iter()
+                        .map (|schedule| schedule.parse::<Schedule>())
+                        .collect::<std::result::Result<_, _>>()
+                        .chain_err (|| "invalid option 'server.schedule'")?;
+                    let ip_check = match server_section.ip_check.take() {
+                        Some(ip_check) => {
+                            let interval = parse_duration (&ip_check.interval)
+                                .chain_err (|| "invalid option 'server.ip_check.interval'")?;
+                            ensure!(!interval.is_zero(), "'server.ip_check.interval' can't be 0");
+                            ensure!(
+                                ip_check.url.is_none() || ip_check.stun.is_none(),
+                                "only one of 'server.ip_check.url' and 'server.ip_check.stun' \
+                                 can be set"
+                            );
+                            Some (IpCheckConfig {
+                                interval,
+                                url: ip_check.url,
+                                stun: ip_check.stun
+                            })
+                        },
+                        None => None
+                    };
+                    let verify_renewal = match server_section.verify_renewal.take() {
+                        Some(verify) => {
+                            let duration = |value: Option<String>, name, default| match value {
+                                Some(value) => parse_duration (&value).chain_err (|| format!(
+                                    "invalid option 'server.verify_renewal.{}'", name
+                                )),
+                                None => Ok(Duration::from_secs (default))
+                            };
+                            let interval = duration (verify.interval, "interval", 5)?;
+                            ensure!(
+                                !interval.is_zero(),
+                                "'server.verify_renewal.interval' can't be 0"
+                            );
+                            ensure!(
+                                verify.url.is_none() || verify.stun.is_none(),
+                                "only one of 'server.verify_renewal.url' and \
+                                 'server.verify_renewal.stun' can be set"
+                            );
+                            Some (VerifyRenewalConfig {
+                                timeout: duration (verify.timeout, "timeout", 120)?,
+                                interval,
+                                url: verify.url,
+                                stun: verify.stun
+                            })
+                        },
+                        None => None
+                    };
+
+                    Some (ServerConfig {
+                        bind_to: server_section.bind_to.take()
+                            .chain_err (|| Error::MissingOption ("server.bind_to".into()))?,
+                        json_bind_to: server_section.json_bind_to.take(),
+                        renewer: chosen,
+                        renewers,
+                        admin_token: env_arg (ADMIN_TOKEN).map (Secret::new)
+                            .or (server_section.admin_token),
+                        psk: env_arg (PSK).map (Secret::new).or (server_section.psk),
+                        acl,
+                        tokens,
+                        coalesce_events,
+                        hostname: server_section.hostname.take(),
+                        history_size: server_section.history_size.take(),
+                        idle_timeout,
+                        max_clients,
+                        coalesce_renewals,
+                        state_dir: env_arg ("state_dir").or (config.state_dir).map (PathBuf::from),
+                        keepalive_interval,
+                        health_check_interval,
+                        schedule,
+                        ip_check,
+                        verify_renewal,
+                        tls: server_section.tls.map (|tls| ServerTlsConfig {
+                            cert: tls.cert.into(),
+                            key: tls.key.into(),
+                            client_ca: tls.client_ca.map (PathBuf::from)
+                        })
+                    })
+                },
+                _ => None
+            };
+
+            let client = match mode_str.as_str() {
+                "client" | "all-in-one" => {
+                    // requested client mode, get client table (optional for all-in-one instances)
+                    let client_section = match config.client {
+                        Some(client_section) => client_section,
+                        None if all_in_one => ClientSection {
+                            connect_to: None,
+                            resolve_interval: None,
+                            proxy: None,
+                            proxy_username: None,
+                            proxy_password: None,
+                            admin_token: None,
+                            psk: None,
+                            tls: None,
+                            action: None,
+                            toasts: None
+                        },
+                        None => bail!(Error::MissingOption ("client".into()))
+                    };
+                    let action_section = client_section.action;
+                    // parse CLI arguments. All-in-one instances always listen for notifications.
+                    let action_name = if all_in_one {
+                        Some ("notifications".to_string())
+                    } else {
+                        subcommand_args
+                            .and_then (|s| s.subcommand()) // try CLI first
+                            .map (|(name, _)| name.to_string())
+                            .or_else (|| env_arg ("action")) // then OXIXENON_ACTION
+                            .or_else (|| // otherwise get client.action.name
+                                action_section.as_ref().and_then (|a| a.name.clone()))
+                    }.chain_err (|| "can't retrieve option 'client.action.name' from either \
+                                     arguments, environment or config")?;
+                    let action = match action_name.as_str() {
+                        // ./bin client renew [--renewer <renewer>]
+                        "renew" => ClientAction::RenewIP (
+                            subcommand_args
+                                .and_then (|s| s.subcommand())
+                                .and_then (|(_, args)| args.value_of ("renewer").map (String::from))
+                                .or_else (|| action_section.as_ref()
+                                    .and_then (|a| a.renew.as_ref())
+                                    .and_then (|r| r.renewer.clone()))
+                        ),
+                        "notifications" => {
+                            // ./bin client notifications [--output ...] [--source ...], or
+                            // ./bin all-in-one [--output ...]
+                            let notifications_args = subcommand_args
+                                .and_then (|s| s.subcommand())
+                                .map (|(_, args)| args);
+                            let output = if all_in_one {
+                                subcommand_args.and_then (|s| s.value_of ("output"))
+                                    .map (String::from)
+                            } else {
+                                notifications_args.as_ref()
+                                    .and_then (|args| args.value_of ("output").map (String::from))
+                            }.or_else (|| action_section.as_ref()
+                                    .and_then (|a| a.notifications.as_ref())
+                                    .and_then (|n| n.output.clone()));
+                            // The server of all-in-one handles its own events.
+                            let source = if all_in_one {
+                                None
+                            } else {
+                                notifications_args.as_ref()
+                                    .and_then (|args| args.value_of ("source").map (String::from))
+                                    .or_else (|| action_section
+                                        .and_then (|a| a.notifications)
+                                        .and_then (|n| n.source))
+                            };
+                            ClientAction::SubscribeToNotifications (match output.as_deref() {
+                                None | Some("log") => NotificationsOutput::Log,
+                                Some("ndjson") => NotificationsOutput::Ndjson,
+                                Some(output) => bail!(
+                                    "invalid option 'client.action.notifications.output': {}, \
+                                     must be \"log\" or \"ndjson\"",
+                                    output
+                                )
+                            }, match source.as_deref() {
+                                None | Some("notifier") => NotificationsSource::Notifier,
+                                Some("server") => NotificationsSource::Server,
+                                Some(source) => bail!(
+                                    "invalid option 'client.action.notifications.source': {}, \
+                                     must be \"notifier\" or \"server\"",
+                                    source
+                                )
+                            })
+                        },
+                        "reload" => ClientAction::ReloadConfig,
+                        "status" => ClientAction::Status,
+                        "health" => ClientAction::Health,
+                        // ./bin client history [--limit <limit>]
+                        "history" => ClientAction::History (
+                            match subcommand_args
+                                .and_then (|s| s.subcommand())
+                                .and_then (|(_, args)| args.value_of ("limit").map (String::from))
+                            {
+                                Some(limit) => limit.parse()
+                                    .chain_err (|| format!("invalid limit '{}'", limit))?,
+                                None => action_section
+                                    .and_then (|a| a.history)
+                                    .and_then (|h| h.limit)
+                                    .unwrap_or (10)
+                            }
+                        ),
+                        "tray" => ClientAction::Tray,
+                        "set_renewer" => ClientAction::SetRenewer (
+                            // ./bin client set_renewer <renewer>
+                            match subcommand_args
+                                .and_then (|s| s.subcommand())
+                                .and_then (|(_, args)| args.value_of ("renewer").map (String::from))
+                            {
+                                Some(renewer) => renewer,
+                                None => action_section
+                                    .and_then (|a| a.set_renewer)
+                                    .chain_err (|| Error::MissingOption (
+                                        "client.action.set_renewer".into()
+                                    ))?
+                                    .renewer
+                            }
+                        ),
+                        "set_availability" => {
+                            // get args of client-mode subcommand, that is
+                            // ./bin client set_availability [args]
+                            let args = subcommand_args
+                                .and_then (|s| s.subcommand())
+                                .map (|(_, args)| args);
+                            if let Some(args) = args {
+                                ClientAction::SetRenewingAvailability (
+                                    match args.value_of ("availability").unwrap() {
+                                        "available"   => protocol::RenewAvailability::Available,
+                                        "unavailable" => unavailability (
+                                            args
+                                                .value_of ("reason")
+                                                .chain_err (|| "the availability reason \
+                                                                'client.action.set_availability \
+                                                                .reason' is mandatory")?
+                                                .into(),
+                                            args.value_of ("for"),
+                                            "--for"
+                                        )?,
+                                        _ => unreachable!()
+                                    }
+                                )
+                            } else {
+                                let section = action_section
+                                    .and_then (|a| a.set_availability)
+                                    .chain_err (|| Error::MissingOption (
+                                        "client.action.set_availability".into()
+                                    ))?;
+                                ClientAction::SetRenewingAvailability (
+                                    if section.available {
+                                        protocol::RenewAvailability::Available
+                                    } else {
+                                        unavailability (
+                                            section.reason.chain_err (|| Error::MissingOption (
+                                                "client.action.set_availability.reason".into()
+                                            ))?,
+                                            section.for_.as_deref(),
+                                            "client.action.set_availability.for"
+                                        )?
+                                    }
+                                )
+                            }
+                        },
+                        _ => bail!("unknown client action 'client.action.name': {}", action_name)
+                    };
+                    // All-in-one instances connect to their own server by default.
+                    let connect_to = client_section.connect_to.as_deref().or (
+                        server.as_ref().map (|server| server.bind_to.as_str())
+                    );
+                    let resolve_interval = client_section.resolve_interval.as_deref()
+                        .map (parse_duration)
+                        .transpose()
+                        .chain_err (|| "invalid option 'client.resolve_interval'")?;
+                    let credentials = match (client_section.proxy_username,
+                        client_section.proxy_password)
+                    {
+                        (Some(username), Some(password)) => Some ((username, password)),
+                        (None, None) => None,
+                        _ => bail!("'client.proxy_username' and 'client.proxy_password' must be \
+                            set together")
+                    };
+                    let proxy = client_section.proxy
+                        .map (|addr| ProxyConfig { addr, credentials });
+                    let tls = match client_section.tls {
+                        Some(tls) => Some (ClientTlsConfig {
+                            ca: tls.ca.into(),
+                            server_name: tls.server_name,
+                            identity: match (tls.cert, tls.key) {
+                                (Some(cert), Some(key)) => Some ((cert.into(), key.into())),
+                                (None, None) => None,
+                                _ => bail!("'client.tls.cert' and 'client.tls.key' must be set \
+                                    together")
+                            }
+                        }),
+                        None => None
+                    };
+                    Some (ClientConfig {
+                        connect_to: arg_or_cfg_option!(
+                            from [subcommand_args]    get "connect_to",
+                            from [connect_to]      named "client.connect_to"
+                        )?,
+                        resolve_interval,
+                        proxy,
+                        action,
+                        toasts: section ("client.toasts".into(), client_section.toasts),
+                        coalesce_events,
+                        admin_token: env_arg (ADMIN_TOKEN).map (Secret::new)
+                            .or (client_section.admin_token),
+                        psk: env_arg (PSK).map (Secret::new).or (client_section.psk),
+                        tls
+                    })
+                },
+                _ => None
+            };
+
+            Mode { server, client }
+        };
+
+        Ok(Config { mode, notifier, logging, metrics, warnings })
+    }
+}
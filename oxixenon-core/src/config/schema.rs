@@ -0,0 +1,202 @@
+//! Generation of a JSON Schema of the configuration file, used by `oxixenon config-schema`.
+//!
+//! The schema is generated from the same structs the configuration is deserialized into, and
+//! only describes the components (renewers, notifiers and logging backends) which are compiled
+//! into the binary.
+
+use schemars::JsonSchema;
+use schemars::gen::{SchemaGenerator, SchemaSettings};
+use schemars::schema::{
+    ArrayValidation, InstanceType, Metadata, Schema, SchemaObject, SubschemaValidation
+};
+use serde_json::Value;
+
+/// Returns the JSON Schema of the configuration file.
+pub fn config_schema() -> Value {
+    // Components have different configuration structs with the same name (`Config`), which
+    // would clash if they were moved to `definitions`.
+    let generator = SchemaSettings::draft07()
+        .with (|settings| settings.inline_subschemas = true)
+        .into_generator();
+    let mut schema = generator.into_root_schema_for::<super::ConfigFile>();
+    schema.schema.metadata().title = Some ("oxixenon configuration".into());
+    serde_json::to_value (schema).expect ("schemas can always be serialized")
+}
+
+/// Returns the schema of the section of a component, deserialized into `T` with the secrets
+/// listed in `secrets` (see [`Section::parse_with_secrets`](../struct.Section.html)).
+pub fn section<T: JsonSchema> (gen: &mut SchemaGenerator, secrets: &[&str]) -> Schema {
+    let mut schema = with_secrets::<T> (gen, secrets);
+    schema.object().additional_properties = Some (Box::new (Schema::Bool (false)));
+    schema.into()
+}
+
+// The schema of `T`, whose options named in `secrets` can also be read from a file or from an
+// environment variable.
+fn with_secrets<T: JsonSchema> (gen: &mut SchemaGenerator, secrets: &[&str]) -> SchemaObject {
+    let mut schema = gen.subschema_for::<T>().into_object();
+    let object = schema.object();
+    // Secrets can also be read from a file or from an environment variable.
+    for secret in secrets {
+        object.required.remove (*secret);
+        for (suffix, description) in &[
+            ("file", "Path of a file which contains"),
+            ("env", "Name of an environment variable which contains")
+        ] {
+            object.properties.insert (
+                format!("{}_{}", secret, suffix),
+                described (gen.subschema_for::<String>(), format!("{} '{}'", description, secret))
+            );
+        }
+    }
+    schema
+}
+
+/// Adds the options of `T` to the schema of `section`, e.g. those which every component accepts
+/// apart from its own ones.
+pub fn with_options<T: JsonSchema> (gen: &mut SchemaGenerator, section: Schema) -> Schema {
+    let mut section = match section {
+        Schema::Object(section) => section,
+        // Sections accepting anything also accept these options.
+        Schema::Bool(_) => return section
+    };
+    let mut options = gen.subschema_for::<T>().into_object();
+    section.object().properties.append (&mut options.object().properties);
+    section.into()
+}
+
+/// Returns the schema of a section which requires no configuration.
+pub fn empty_section() -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some (InstanceType::Object.into()),
+        ..SchemaObject::default()
+    };
+    schema.object().additional_properties = Some (Box::new (Schema::Bool (false)));
+    schema.into()
+}
+
+/// Returns the schema of a table which contains the sections of the components in `sections`,
+/// keyed by their names (e.g. `[notifier.multicast]`).
+pub fn sections (sections: Vec<(&str, Schema)>) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some (InstanceType::Object.into()),
+        ..SchemaObject::default()
+    };
+    for (name, section) in sections {
+        schema.object().properties.insert (name.into(), section);
+    }
+    // Sections of components which are not compiled in are ignored.
+    schema.into()
+}
+
+/// Like `sections`, but each component can also be configured multiple times with different
+/// names, by specifying the component with `type` (see `RenewerConfig`).
+pub fn instances (sections: Vec<(&str, Schema)>) -> Schema {
+    let mut schema = SchemaObject {
+        instance_type: Some (InstanceType::Object.into()),
+        ..SchemaObject::default()
+    };
+    let mut named = Vec::new();
+    for (name, section) in sections {
+        let with_type = |required: bool| {
+            let mut section = section.clone().into_object();
+            let object = section.object();
+            object.properties.insert ("type".into(), SchemaObject {
+                const_value: Some (Value::String (name.into())),
+                ..SchemaObject::default()
+            }.into());
+            if required {
+                object.required.insert ("type".into());
+            }
+            Schema::from (section)
+        };
+        schema.object().properties.insert (name.into(), with_type (false));
+        named.push (with_type (true));
+    }
+    schema.object().additional_properties = Some (Box::new (SchemaObject {
+        subschemas: Some (Box::new (SubschemaValidation {
+            any_of: Some (named),
+            ..SubschemaValidation::default()
+        })),
+        ..SchemaObject::default()
+    }.into()));
+    schema.into()
+}
+
+/// Returns the schema of a section which can also be repeated as an array of tables (e.g.
+/// `[[logging.file]]`).
+pub fn one_or_many (section: Schema) -> Schema {
+    SchemaObject {
+        subschemas: Some (Box::new (SubschemaValidation {
+            any_of: Some (vec![
+                section.clone(),
+                SchemaObject {
+                    instance_type: Some (InstanceType::Array.into()),
+                    array: Some (Box::new (ArrayValidation {
+                        items: Some (section.into()),
+                        ..ArrayValidation::default()
+                    })),
+                    ..SchemaObject::default()
+                }.into()
+            ]),
+            ..SubschemaValidation::default()
+        })),
+        ..SchemaObject::default()
+    }.into()
+}
+
+// The schema of `server.renewer`.
+pub(super) fn renewers (gen: &mut SchemaGenerator) -> Schema {
+    #[cfg(feature = "server")]
+    return crate::renewer::config_schema (gen);
+    #[cfg(not(feature = "server"))]
+    return instances (Vec::new());
+}
+
+// The schema of `server` and `client`, see `super::SERVER_SECRETS`.
+pub(super) fn server (gen: &mut SchemaGenerator) -> Schema {
+    with_secrets::<Option<super::ServerSection>> (gen, super::SERVER_SECRETS).into()
+}
+
+pub(super) fn client (gen: &mut SchemaGenerator) -> Schema {
+    with_secrets::<Option<super::ClientSection>> (gen, super::CLIENT_SECRETS).into()
+}
+
+fn described (schema: Schema, description: String) -> Schema {
+    let mut schema = schema.into_object();
+    schema.metadata = Some (Box::new (Metadata {
+        description: Some (description),
+        ..Metadata::default()
+    }));
+    schema.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn schema_describes_compiled_components() {
+        let schema = config_schema();
+        let properties = &schema["properties"];
+        assert!(properties["notifier"]["properties"]["multicast"]["properties"]["addr"].is_object());
+        let file = &properties["logging"]["properties"]["file"]["anyOf"];
+        assert!(file[0]["properties"]["path"].is_object());
+        assert!(file[1]["items"]["properties"]["targets"].is_object());
+        let renewers = &properties["server"]["properties"]["renewer"];
+        assert!(renewers["properties"]["dummy"].is_object());
+        assert_eq!(
+            renewers["properties"].as_object().unwrap().contains_key ("dlink"),
+            cfg!(feature = "renewer-dlink")
+        );
+    }
+
+    #[cfg(feature = "renewer-dlink")]
+    #[test]
+    fn secrets_can_be_read_from_files() {
+        let schema = config_schema();
+        let dlink = &schema["properties"]["server"]["properties"]["renewer"]["properties"]["dlink"];
+        assert!(dlink["properties"]["password_file"].is_object());
+        assert!(!dlink["required"].as_array().unwrap().contains (&"password".into()));
+    }
+}
@@ -0,0 +1,69 @@
+//! Standard locations of the configuration file, used when `--config` isn't specified.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+const FILE_NAME: &str = "config.toml";
+
+/// Returns the locations where the configuration file is searched, in order of priority:
+/// - `$XDG_CONFIG_HOME/oxixenon/config.toml` (or `~/.config/oxixenon/config.toml`);
+/// - `/etc/oxixenon/config.toml` (not on Windows);
+/// - `%APPDATA%\oxixenon\config.toml` (only on Windows);
+/// - `config.toml` in the current directory.
+pub fn search_paths() -> Vec<PathBuf> {
+    search_paths_with (&|name| env::var_os (name).map (PathBuf::from))
+}
+
+/// Returns the first existing configuration file among the ones listed by `search_paths`.
+pub fn find_config_file() -> Option<PathBuf> {
+    search_paths().into_iter().find (|path| path.is_file())
+}
+
+fn search_paths_with (lookup: &dyn Fn(&str) -> Option<PathBuf>) -> Vec<PathBuf> {
+    // Relative values are ignored, as mandated by the XDG base directory specification.
+    let absolute = |path: PathBuf| Some(path).filter (|path| path.is_absolute());
+    let mut paths = Vec::new();
+    if cfg!(not(windows)) {
+        let config_home = lookup ("XDG_CONFIG_HOME")
+            .and_then (absolute)
+            .or_else (|| lookup ("HOME").and_then (absolute).map (|home| home.join (".config")));
+        if let Some(config_home) = config_home {
+            paths.push (config_home.join ("oxixenon").join (FILE_NAME));
+        }
+        paths.push (Path::new ("/etc/oxixenon").join (FILE_NAME));
+    } else if let Some(app_data) = lookup ("APPDATA").and_then (absolute) {
+        paths.push (app_data.join ("oxixenon").join (FILE_NAME));
+    }
+    paths.push (PathBuf::from (FILE_NAME));
+    paths
+}
+
+#[cfg(all(test, not(windows)))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefers_xdg_config_home() {
+        let paths = search_paths_with (&|name| match name {
+            "XDG_CONFIG_HOME" => Some ("/home/xenon/.cfg".into()),
+            "HOME"            => Some ("/home/xenon".into()),
+            _                 => None
+        });
+        assert_eq!(paths, vec![
+            PathBuf::from ("/home/xenon/.cfg/oxixenon/config.toml"),
+            PathBuf::from ("/etc/oxixenon/config.toml"),
+            PathBuf::from ("config.toml")
+        ]);
+    }
+
+    #[test]
+    fn falls_back_to_home() {
+        let paths = search_paths_with (&|name| match name {
+            "XDG_CONFIG_HOME" => Some ("relative".into()),
+            "HOME"            => Some ("/home/xenon".into()),
+            _                 => None
+        });
+        assert_eq!(paths[0], PathBuf::from ("/home/xenon/.config/oxixenon/config.toml"));
+        assert_eq!(search_paths_with (&|_| None).len(), 2);
+    }
+}
@@ -0,0 +1,85 @@
+//! A wrapper which keeps credentials out of logs and debug output.
+
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+/// A secret value, such as a password or a session token. Its `Debug` and `Display`
+/// implementations print `***`: the wrapped value must be accessed explicitly with
+/// [`expose`](#method.expose).
+///
+/// Secrets can be deserialized from the configuration like the values they wrap.
+#[derive(Clone, Default, PartialEq, Eq)]
+pub struct Secret<T>(T);
+
+impl<T> Secret<T> {
+    pub fn new (value: T) -> Self {
+        Secret(value)
+    }
+
+    /// Returns the secret value. Make sure it doesn't end up in a log message!
+    pub fn expose (&self) -> &T {
+        &self.0
+    }
+
+    pub fn into_inner (self) -> T {
+        self.0
+    }
+}
+
+impl<T> From<T> for Secret<T> {
+    fn from (value: T) -> Self {
+        Secret(value)
+    }
+}
+
+impl<'a> From<&'a str> for Secret<String> {
+    fn from (value: &'a str) -> Self {
+        Secret(value.into())
+    }
+}
+
+impl<T> fmt::Debug for Secret<T> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl<T> fmt::Display for Secret<T> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "***")
+    }
+}
+
+impl<'de, T: Deserialize<'de>> Deserialize<'de> for Secret<T> {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize (deserializer).map (Secret)
+    }
+}
+
+#[cfg(feature = "config-schema")]
+impl<T: schemars::JsonSchema> schemars::JsonSchema for Secret<T> {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        T::schema_name()
+    }
+
+    fn json_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        T::json_schema (gen)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secrets_are_redacted() {
+        let secret: Secret<String> = "hunter2".into();
+        assert_eq!(format!("{} {:?}", secret, secret), "*** ***");
+        assert_eq!(format!("{:?}", Some (secret.clone())), "Some(***)");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+}
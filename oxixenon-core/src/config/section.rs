@@ -0,0 +1,446 @@
+//! Typed access to the sections of the configuration file.
+
+use super::{Error, Result, ResultExt};
+use serde::de::{self, Deserialize, DeserializeOwned, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::sync::Arc;
+use toml::Spanned;
+
+/// A section of the configuration file (e.g. `server.renewer.dlink`), which can be deserialized
+/// into the typed configuration of a component with [`parse`](#method.parse).
+///
+/// Sections which are missing from the configuration file behave like empty tables, so that
+/// components which don't require any configuration can still be configured.
+///
+/// In strict mode, unknown options are errors rather than warnings.
+#[derive(Clone)]
+pub struct Section {
+    path: String,
+    value: Option<toml::Value>,
+    source: Arc<SourceMap>,
+    strict: bool
+}
+
+impl Section {
+    pub(crate) fn new (path: String, value: Option<toml::Value>, source: Arc<SourceMap>) -> Self {
+        Section { path, value, source, strict: false }
+    }
+
+    /// Returns an empty section with the specified path, not backed by any configuration file.
+    pub fn empty<T: Into<String>> (path: T) -> Self {
+        Section::new (path.into(), None, Arc::new (SourceMap::default()))
+    }
+
+    pub(crate) fn strict (self, strict: bool) -> Self {
+        Section { strict, ..self }
+    }
+
+    /// Returns the fully qualified name of this section.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns whether this section is present in the configuration file.
+    pub fn is_present(&self) -> bool {
+        self.value.is_some()
+    }
+
+    /// Splits this section into the options named in `keys` and the other ones, e.g. to handle
+    /// the options which every component accepts apart from those of the component.
+    pub fn split (&self, keys: &[&str]) -> (Section, Section) {
+        let (mut selected, mut rest) = (None, self.value.clone());
+        if let Some(toml::Value::Table(ref mut table)) = rest {
+            let mut options = toml::value::Table::new();
+            for key in keys {
+                if let Some(value) = table.remove (*key) {
+                    options.insert (key.to_string(), value);
+                }
+            }
+            selected = Some (toml::Value::Table (options));
+        }
+        (Section { value: selected, ..self.clone() }, Section { value: rest, ..self.clone() })
+    }
+
+    /// Deserializes this section. Unknown options are reported as warnings (or errors, in strict
+    /// mode).
+    pub fn parse<T: DeserializeOwned> (&self) -> Result<T> {
+        let (value, warnings) = self.parse_with_warnings()?;
+        for warning in warnings {
+            warn!(target: "config", "{}", warning);
+        }
+        Ok(value)
+    }
+
+    /// Deserializes this section, returning the warnings about unknown options instead of
+    /// logging them. Useful when the logger is not ready yet.
+    pub fn parse_with_warnings<T: DeserializeOwned> (&self) -> Result<(T, Vec<String>)> {
+        let value = self.value.clone()
+            .unwrap_or_else (|| toml::Value::Table (toml::value::Table::new()));
+        deserialize (value, &self.path, &self.source, self.strict)
+    }
+
+    /// Like [`parse`](#method.parse), but the options named in `secrets` (e.g. `password`) can
+    /// also be read from a file (`password_file`) or from an environment variable
+    /// (`password_env`).
+    pub fn parse_with_secrets<T: DeserializeOwned> (&self, secrets: &[&str]) -> Result<T> {
+        self.with_secrets (secrets)?.parse()
+    }
+
+    /// Returns a copy of this section where the options named in `secrets` have been read from
+    /// `[secret]_file` or `[secret]_env`, if specified (see
+    /// [`parse_with_secrets`](#method.parse_with_secrets)).
+    pub fn with_secrets (&self, secrets: &[&str]) -> Result<Section> {
+        let mut value = self.value.clone();
+        if let Some(toml::Value::Table(ref mut table)) = value {
+            for secret in secrets {
+                let path = format!("{}.{}", self.path, secret);
+                if let Some(resolved) = resolve_secret (table, secret, &path)? {
+                    table.insert (secret.to_string(), toml::Value::String (resolved));
+                }
+            }
+        }
+        Ok(Section { value, ..self.clone() })
+    }
+}
+
+// The contents of the section are not printed, as they might contain credentials.
+impl fmt::Debug for Section {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct ("Section")
+            .field ("path", &self.path)
+            .field ("present", &self.is_present())
+            .finish()
+    }
+}
+
+// Removes `[key]_file` and `[key]_env` from `table`, returning the secret they point to.
+pub(super) fn resolve_secret (table: &mut toml::value::Table, key: &str, path: &str)
+    -> Result<Option<String>>
+{
+    let file = table.remove (&format!("{}_file", key));
+    let env = table.remove (&format!("{}_env", key));
+    let sources = [table.contains_key (key), file.is_some(), env.is_some()]
+        .iter()
+        .filter (|present| **present)
+        .count();
+    ensure!(
+        sources <= 1,
+        "only one of '{path}', '{path}_file' and '{path}_env' can be specified", path = path
+    );
+    if let Some(file) = file {
+        let file = file.as_str()
+            .chain_err (|| Error::InvalidOption (format!("{}_file", path)))?;
+        let mut secret = String::new();
+        File::open (file)
+            .and_then (|mut f| f.read_to_string (&mut secret))
+            .chain_err (|| format!("can't read '{}_file' from '{}'", path, file))?;
+        // Files usually end with a newline, which is never part of the secret.
+        let len = secret.trim_end_matches (['\r', '\n']).len();
+        secret.truncate (len);
+        Ok(Some(secret))
+    } else if let Some(env) = env {
+        let env = env.as_str()
+            .chain_err (|| Error::InvalidOption (format!("{}_env", path)))?;
+        std::env::var (env)
+            .map (Some)
+            .chain_err (|| format!(
+                "can't read '{}_env' from environment variable '{}'", path, env
+            ))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Deserializes `value`, which is found at `path` in the configuration file. Errors are
+/// decorated with the full path of the offending option and (if known) with its line number.
+/// Unknown options are returned as warnings, unless `strict` is set.
+pub(crate) fn deserialize<T: DeserializeOwned> (
+    value: toml::Value,
+    path: &str,
+    source: &SourceMap,
+    strict: bool
+) -> Result<(T, Vec<String>)> {
+    let join = |inner: String| match (path.is_empty(), inner.as_str()) {
+        (_, "" | ".") => path.to_string(),
+        (true, _) => inner,
+        // Elements of arrays, e.g. "[1].path".
+        (false, _) if inner.starts_with ('[') => format!("{}{}", path, inner),
+        (false, _) => format!("{}.{}", path, inner)
+    };
+    let mut unknown = Vec::new();
+    let mut record_unknown = |ignored: serde_ignored::Path| unknown.push (ignored_path (&ignored));
+    let deserializer = serde_ignored::Deserializer::new (value, &mut record_unknown);
+    let result = serde_path_to_error::deserialize (deserializer);
+    let value: T = match result {
+        Ok(value) => value,
+        Err(error) => {
+            let full_path = join (error.path().to_string());
+            let line = source.line_of (&full_path);
+            bail!(Error::InvalidValue (full_path, line, error.into_inner().to_string()))
+        }
+    };
+    let warnings = unknown.into_iter()
+        .map (|option| unknown_option (join (option), source, strict))
+        .collect::<Result<_>>()?;
+    Ok((value, warnings))
+}
+
+// Formats the path of an unknown option like the ones reported by `serde_path_to_error`, i.e.
+// with array indexes in brackets ("file[1].path" rather than "file.1.path").
+fn ignored_path (path: &serde_ignored::Path) -> String {
+    use serde_ignored::Path;
+    match *path {
+        Path::Root => String::new(),
+        Path::Seq { parent, index } => format!("{}[{}]", ignored_path (parent), index),
+        Path::Map { parent, ref key } => match ignored_path (parent) {
+            parent if parent.is_empty() => key.clone(),
+            parent => format!("{}.{}", parent, key)
+        },
+        Path::Some { parent } |
+        Path::NewtypeStruct { parent } |
+        Path::NewtypeVariant { parent } => ignored_path (parent)
+    }
+}
+
+/// Returns the warning about the unknown option `option`, or an error in strict mode.
+pub(crate) fn unknown_option (option: String, source: &SourceMap, strict: bool) -> Result<String> {
+    let line = source.line_of (&option);
+    ensure!(!strict, Error::UnknownOption (option, line));
+    Ok(match line {
+        Some(line) => format!("unknown configuration option '{}' (line {}) ignored", option, line),
+        None => format!("unknown configuration option '{}' ignored", option)
+    })
+}
+
+/// Maps the options of a configuration file to their position in the file.
+#[derive(Debug, Default)]
+pub(crate) struct SourceMap {
+    source: String,
+    root: Option<Node>
+}
+
+impl SourceMap {
+    pub fn new (source: &str) -> Self {
+        SourceMap { source: source.into(), root: toml::from_str (source).ok() }
+    }
+
+    /// Returns the line where the option with the fully qualified name `path` is defined.
+    pub fn line_of (&self, path: &str) -> Option<usize> {
+        let mut node = self.root.as_ref()?;
+        let mut span = None;
+        for segment in path.split ('.') {
+            // "backends[1]" is the second element of "backends".
+            let (key, index) = match segment.find ('[') {
+                Some(start) => (
+                    &segment[..start],
+                    segment[start + 1..].trim_end_matches (']').parse::<usize>().ok()
+                ),
+                None => (segment, None)
+            };
+            let child = match *node {
+                Node::Table(ref entries) =>
+                    entries.iter().find (|(k, _)| k == key).map (|(_, v)| v)?,
+                _ => return None
+            };
+            let child = match (index, child.get_ref()) {
+                (None, _) => child,
+                (Some(index), Node::Array(items)) => items.get (index)?,
+                _ => return None
+            };
+            span = Some((child.start(), child.end()));
+            node = child.get_ref();
+        }
+        match span? {
+            // Tables defined with a [header] don't have a span.
+            (0, 0) => None,
+            (start, _) => Some(self.source.get (..start)?.matches ('\n').count() + 1)
+        }
+    }
+}
+
+// A TOML document which only keeps the structure and the position of each value.
+#[derive(Debug)]
+enum Node {
+    Table(Vec<(String, Spanned<Node>)>),
+    Array(Vec<Spanned<Node>>),
+    Scalar
+}
+
+impl<'de> Deserialize<'de> for Node {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any (NodeVisitor)
+    }
+}
+
+struct NodeVisitor;
+
+impl<'de> Visitor<'de> for NodeVisitor {
+    type Value = Node;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str ("any TOML value")
+    }
+
+    fn visit_bool<E: de::Error> (self, _: bool) -> std::result::Result<Node, E> { Ok(Node::Scalar) }
+    fn visit_i64<E: de::Error> (self, _: i64) -> std::result::Result<Node, E> { Ok(Node::Scalar) }
+    fn visit_u64<E: de::Error> (self, _: u64) -> std::result::Result<Node, E> { Ok(Node::Scalar) }
+    fn visit_f64<E: de::Error> (self, _: f64) -> std::result::Result<Node, E> { Ok(Node::Scalar) }
+    fn visit_str<E: de::Error> (self, _: &str) -> std::result::Result<Node, E> { Ok(Node::Scalar) }
+
+    fn visit_seq<A: SeqAccess<'de>> (self, mut seq: A) -> std::result::Result<Node, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push (item);
+        }
+        Ok(Node::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>> (self, mut map: A) -> std::result::Result<Node, A::Error> {
+        let mut entries = Vec::new();
+        while let Some(key) = map.next_key::<String>()? {
+            entries.push ((key, map.next_value()?));
+        }
+        Ok(Node::Table(entries))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    const SOURCE: &str = "\
+        [logging]\n\
+        backends = ['stdout', 5]\n\
+        file = { path = 'a.log' }\n\
+        \n\
+        [server.renewer.acme]\n\
+        ip = 1\n\
+        pasword = 'typo'\n";
+
+    #[derive(Debug, Deserialize)]
+    #[allow(dead_code)]
+    struct Acme {
+        ip: String,
+        #[serde(default)]
+        password: String
+    }
+
+    fn section (path: &str, value: &str) -> Section {
+        let source = Arc::new (SourceMap::new (SOURCE));
+        Section::new (path.into(), Some(value.parse().unwrap()), source)
+    }
+
+    #[test]
+    fn finds_lines_of_options() {
+        let map = SourceMap::new (SOURCE);
+        assert_eq!(map.line_of ("logging.backends"), Some(2));
+        assert_eq!(map.line_of ("logging.backends[1]"), Some(2));
+        assert_eq!(map.line_of ("logging.file.path"), Some(3));
+        assert_eq!(map.line_of ("server.renewer.acme.pasword"), Some(7));
+        assert_eq!(map.line_of ("server.renewer.acme"), None);
+        assert_eq!(map.line_of ("server.bind_to"), None);
+    }
+
+    #[test]
+    fn errors_contain_path_and_line() {
+        let error = section ("server.renewer.acme", "ip = 1\npasword = 'typo'")
+            .parse::<Acme>()
+            .unwrap_err();
+        match error {
+            Error::InvalidValue(path, line, _) => {
+                assert_eq!(path, "server.renewer.acme.ip");
+                assert_eq!(line, Some(6));
+            },
+            kind => panic!("unexpected error: {}", kind)
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_reported() {
+        let (acme, warnings) = section ("server.renewer.acme", "ip = '1'\npasword = 'typo'")
+            .parse_with_warnings::<Acme>()
+            .unwrap();
+        assert_eq!(acme.ip, "1");
+        assert_eq!(warnings, vec![
+            "unknown configuration option 'server.renewer.acme.pasword' (line 7) ignored"
+        ]);
+    }
+
+    #[test]
+    fn paths_of_array_elements_use_brackets() {
+        #[derive(Debug, Deserialize)]
+        #[allow(dead_code)]
+        struct Renewers {
+            acme: Vec<Acme>
+        }
+        let (_, warnings) = section ("server.renewer", "acme = [{ ip = '1' }, { ip = '2', x = 1 }]")
+            .parse_with_warnings::<Renewers>()
+            .unwrap();
+        assert_eq!(warnings, vec![
+            "unknown configuration option 'server.renewer.acme[1].x' ignored"
+        ]);
+        let error = section ("server.renewer", "acme = [{ ip = 1 }]")
+            .parse::<Renewers>()
+            .unwrap_err();
+        match error {
+            Error::InvalidValue(path, ..) => assert_eq!(path, "server.renewer.acme[0].ip"),
+            kind => panic!("unexpected error: {}", kind)
+        }
+    }
+
+    #[test]
+    fn unknown_options_are_rejected_in_strict_mode() {
+        let error = section ("server.renewer.acme", "ip = '1'\npasword = 'typo'")
+            .strict (true)
+            .parse::<Acme>()
+            .unwrap_err();
+        match error {
+            Error::UnknownOption(path, line) => {
+                assert_eq!(path, "server.renewer.acme.pasword");
+                assert_eq!(line, Some(7));
+            },
+            kind => panic!("unexpected error: {}", kind)
+        }
+    }
+
+    #[test]
+    fn missing_sections_behave_like_empty_tables() {
+        #[derive(Deserialize)]
+        struct Optional {
+            #[serde(default)]
+            value: Option<String>
+        }
+        let section = Section::empty ("server.renewer.acme");
+        assert!(!section.is_present());
+        assert!(section.parse::<Optional>().unwrap().value.is_none());
+        assert!(section.parse::<Acme>().is_err());
+    }
+
+    #[test]
+    fn reads_secrets_from_files_and_environment() {
+        let path = std::env::temp_dir().join (format!("oxixenon-secret-{}", std::process::id()));
+        std::fs::write (&path, "from-file\n").unwrap();
+        std::env::set_var ("OXIXENON_TEST_SECRET", "from-env");
+
+        let parse = |value: String| section ("server.renewer.acme", &value)
+            .parse_with_secrets::<Acme> (&["password"])
+            .map (|acme| acme.password);
+        assert_eq!(parse ("ip = ''\npassword = 'inline'".into()).unwrap(), "inline");
+        assert_eq!(
+            parse (format!("ip = ''\npassword_file = '{}'", path.display())).unwrap(),
+            "from-file"
+        );
+        assert_eq!(
+            parse ("ip = ''\npassword_env = 'OXIXENON_TEST_SECRET'".into()).unwrap(),
+            "from-env"
+        );
+        assert!(parse ("ip = ''\npassword = 'x'\npassword_env = 'OXIXENON_TEST_SECRET'".into())
+            .is_err());
+        assert!(parse ("ip = ''\npassword_env = 'OXIXENON_TEST_UNSET'".into()).is_err());
+        assert!(parse ("ip = ''\npassword_file = 1".into()).is_err());
+        std::fs::remove_file (path).unwrap();
+    }
+}
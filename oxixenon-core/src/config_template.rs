@@ -0,0 +1,212 @@
+//! Generation of commented starter configuration files, used by `oxixenon init`.
+//!
+//! Only the sections which are relevant to the chosen components are emitted. Values which have
+//! to be changed by the user are left as placeholders.
+
+use crate::config::Result;
+use std::fmt::Write;
+
+/// Renewers which can be used in a starter configuration.
+pub const RENEWERS: &[&str] =
+    &["dlink", "fritzbox", "tr064", "fritzbox-local", "dummy", "chaos"];
+
+/// Notifiers which can be used in a starter configuration.
+pub const NOTIFIERS: &[&str] = &["multicast", "none"];
+
+/// The components of a starter configuration.
+#[derive(Debug)]
+pub struct Template<'a> {
+    /// Either "server" or "client".
+    pub mode: &'a str,
+    /// The renewer, only used in server mode.
+    pub renewer: &'a str,
+    pub notifier: &'a str
+}
+
+impl<'a> Default for Template<'a> {
+    fn default() -> Self {
+        Template { mode: "server", renewer: "dummy", notifier: "multicast" }
+    }
+}
+
+impl<'a> Template<'a> {
+    /// Renders the starter configuration.
+    pub fn render(&self) -> Result<String> {
+        ensure!(
+            self.mode == "server" || self.mode == "client",
+            "invalid mode '{}', must be one of 'server', 'client'", self.mode
+        );
+        ensure!(
+            NOTIFIERS.contains (&self.notifier),
+            "unknown notifier '{}', must be one of {}", self.notifier, NOTIFIERS.join (", ")
+        );
+        let is_server = self.mode == "server";
+        let mut out = String::new();
+        // Writing to a String can't fail.
+        macro_rules! emit {
+            ($($arg:tt)*) => { writeln!(out, $($arg)*).unwrap() }
+        }
+        emit!("# oxixenon configuration, generated by `oxixenon init`.");
+        emit!("# See config.example.toml for a description of every available option.");
+        emit!();
+        emit!("# Whether this instance will run as a server or a client.");
+        emit!("mode = \"{}\"", self.mode);
+        emit!();
+        emit!("# What notifier will be used to notify events.");
+        emit!("notifier_name = \"{}\"", self.notifier);
+        emit!();
+        emit!("[logging]");
+        emit!("# The logging verbosity. Valid values are \"off\", \"error\", \"warn\", \"info\", \
+            \"debug\", \"trace\".");
+        emit!("verbosity = \"info\"");
+        emit!();
+        emit!("# What logging backends are enabled. Available backends: stdout, file, syslog.");
+        emit!("backends = [\"stdout\"]");
+        emit!();
+        emit!("# Uncomment (and add \"file\" to `backends`) to also log to a file.");
+        emit!("#file = {{ path = \"/var/log/oxixenon.log\" }}");
+        if self.notifier == "multicast" {
+            emit!();
+            emit!("[notifier.multicast]");
+            emit!("# Where the UDP socket will be bound to.");
+            if is_server {
+                emit!("# When sending notifications, use any IP address and \"0\" as the port.");
+                emit!("bind_addr = \"0.0.0.0:0\"");
+            } else {
+                emit!("# When receiving notifications, use the same port number of `addr`.");
+                emit!("bind_addr = \"0.0.0.0:5454\"");
+            }
+            emit!();
+            emit!("# The multicast IP address and port where notifications are sent to. IPv6");
+            emit!("# groups (e.g. \"[ff02::5454]:5454\") require an IPv6 `bind_addr`.");
+            emit!("addr = \"239.255.54.54:5454\"");
+        }
+        emit!();
+        if is_server {
+            self.render_server (&mut out)?;
+        } else {
+            emit!("[client]");
+            emit!("# Address and port of the oxixenon server.");
+            emit!("connect_to = \"SERVER_ADDRESS:5454\"");
+            emit!();
+            emit!("# The default action, used when no action is specified on the command line.");
+            emit!("# Available actions: renew, notifications, set_availability,");
+            emit!("# reload.");
+            emit!("[client.action]");
+            emit!("name = \"renew\"");
+        }
+        Ok(out)
+    }
+
+    fn render_server(&self, out: &mut String) -> Result<()> {
+        ensure!(
+            RENEWERS.contains (&self.renewer),
+            "unknown renewer '{}', must be one of {}", self.renewer, RENEWERS.join (", ")
+        );
+        macro_rules! emit {
+            ($($arg:tt)*) => { writeln!(out, $($arg)*).unwrap() }
+        }
+        emit!("[server]");
+        emit!("# IP address and port to bind to.");
+        emit!("bind_to = \"0.0.0.0:5454\"");
+        emit!();
+        emit!("# What renewer to use.");
+        emit!("renewer_name = \"{}\"", self.renewer);
+        match self.renewer {
+            "dlink" => {
+                emit!();
+                emit!("[server.renewer.dlink]");
+                emit!("# IP address (IPv4 or IPv6) of the router.");
+                emit!("ip = \"ROUTER_IP\"");
+                emit!();
+                emit!("# Username and password used to login. The password can also be read from \
+                    a file");
+                emit!("# (`password_file`) or an environment variable (`password_env`).");
+                emit!("username = \"admin\"");
+                emit!("password = \"ROUTER_PASSWORD\"");
+                emit!();
+                emit!("# The network interface whose address will be renewed: everything after \
+                    \"/netif/\"");
+                emit!("# in the URL of the interface's page in the router's web interface.");
+                emit!("interface = \"pppif?if=1\"");
+            },
+            "fritzbox" => {
+                emit!();
+                emit!("[server.renewer.fritzbox]");
+                emit!("# IP address (IPv4 or IPv6) or hostname of the router.");
+                emit!("ip = \"fritz.box\"");
+                emit!();
+                emit!("# Username and password used to login. The username is optional. The \
+                    password can also");
+                emit!("# be read from a file (`password_file`) or an environment variable \
+                    (`password_env`).");
+                emit!("#username = \"ROUTER_USERNAME\"");
+                emit!("password = \"ROUTER_PASSWORD\"");
+            },
+            "tr064" => {
+                emit!();
+                emit!("[server.renewer.tr064]");
+                emit!("# IP address (IPv4 or IPv6) or hostname of the router, optionally followed \
+                    by the port of");
+                emit!("# TR-064 (49000 by default), which must be enabled in the network settings \
+                    of the router.");
+                emit!("ip = \"fritz.box\"");
+                emit!();
+                emit!("# Username and password used to login. The password can also be read from \
+                    a file");
+                emit!("# (`password_file`) or an environment variable (`password_env`).");
+                emit!("username = \"ROUTER_USERNAME\"");
+                emit!("password = \"ROUTER_PASSWORD\"");
+            },
+            "chaos" => {
+                emit!();
+                emit!("[server.renewer.chaos]");
+                emit!("# Simulates a router. How long each renewal takes, and the probabilities \
+                    (from 0 to 1) that");
+                emit!("# it fails or keeps the same IP address.");
+                emit!("delay = \"2s\"");
+                emit!("failure_rate = 0.2");
+                emit!("same_ip_rate = 0.1");
+            },
+            // "fritzbox-local" and "dummy" require no configuration.
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_template_is_valid_toml() {
+        for mode in &["server", "client"] {
+            for renewer in RENEWERS {
+                for notifier in NOTIFIERS {
+                    let rendered = Template { mode, renewer, notifier }.render().unwrap();
+                    let value = rendered.parse::<toml::Value>().unwrap();
+                    assert_eq!(value["mode"].as_str(), Some (*mode));
+                    assert_eq!(value.get ("server").is_some(), *mode == "server");
+                    assert_eq!(value.get ("client").is_some(), *mode == "client");
+                    assert_eq!(value.get ("notifier").is_some(), *notifier == "multicast");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn only_the_chosen_renewer_is_configured() {
+        let rendered = Template { renewer: "dlink", ..Template::default() }.render().unwrap();
+        let value = rendered.parse::<toml::Value>().unwrap();
+        let renewers = value["server"]["renewer"].as_table().unwrap();
+        assert_eq!(renewers.keys().collect::<Vec<_>>(), vec!["dlink"]);
+    }
+
+    #[test]
+    fn unknown_components_are_rejected() {
+        assert!(Template { mode: "both", ..Template::default() }.render().is_err());
+        assert!(Template { renewer: "acme", ..Template::default() }.render().is_err());
+        assert!(Template { notifier: "smoke", ..Template::default() }.render().is_err());
+    }
+}
@@ -0,0 +1,218 @@
+//! The main error type, along with the helpers shared by the error types of every module.
+//!
+//! Each module defines its own `Error` enum with `thiserror`, which includes a `Msg` variant for
+//! plain messages and a `Chained` one for errors with a cause (see `ResultExt::chain_err`),
+//! through the `chained_error!` macro. Errors keep their causes, which can be walked with
+//! `ErrorExt`.
+
+use std::error::Error as StdError;
+use crate::config;
+use crate::protocol;
+use crate::notifier;
+use crate::resolver;
+use crate::state;
+#[cfg(feature = "server")]
+use crate::renewer;
+
+/// The cause of a chained error.
+pub type Cause = Box<dyn StdError + Send + Sync>;
+
+/// Implements, for the error enum `$error` (which must have the variants `Msg(String)` and
+/// `Chained(Box<$error>, #[source] Cause)`), the conversions from messages, the `Result` alias
+/// and the `ResultExt` trait.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! chained_error {
+    ($error:ident) => {
+        /// A result with the error type of this module.
+        pub type Result<T> = ::std::result::Result<T, $error>;
+
+        impl From<String> for $error {
+            fn from (message: String) -> Self {
+                $error::Msg (message)
+            }
+        }
+
+        impl<'a> From<&'a str> for $error {
+            fn from (message: &'a str) -> Self {
+                $error::Msg (message.to_string())
+            }
+        }
+
+        /// Adds context to errors.
+        pub trait ResultExt<T> {
+            /// Replaces the error (or `None`) with the one returned by `callback`, keeping the
+            /// original error as its cause.
+            fn chain_err<K: Into<$error>, F: FnOnce() -> K> (self, callback: F) -> Result<T>;
+        }
+
+        impl<T, E> ResultExt<T> for ::std::result::Result<T, E>
+            where E: ::std::error::Error + Send + Sync + 'static
+        {
+            fn chain_err<K: Into<$error>, F: FnOnce() -> K> (self, callback: F) -> Result<T> {
+                self.map_err (|error| {
+                    $error::Chained (Box::new (callback().into()), Box::new (error))
+                })
+            }
+        }
+
+        impl<T> ResultExt<T> for Option<T> {
+            fn chain_err<K: Into<$error>, F: FnOnce() -> K> (self, callback: F) -> Result<T> {
+                self.ok_or_else (|| callback().into())
+            }
+        }
+    };
+}
+
+/// Returns early with an error, built either from a value convertible to it or from a message
+/// with `format!`-style arguments.
+#[macro_export]
+macro_rules! bail {
+    ($error:expr) => {
+        return Err($error.into())
+    };
+    ($fmt:expr, $($arg:tt)+) => {
+        return Err(format!($fmt, $($arg)+).into())
+    };
+}
+
+/// Returns early with an error (as `bail!`) if the condition isn't satisfied.
+#[macro_export]
+macro_rules! ensure {
+    ($condition:expr, $error:expr) => {
+        if !($condition) {
+            $crate::bail!($error);
+        }
+    };
+    ($condition:expr, $fmt:expr, $($arg:tt)+) => {
+        if !($condition) {
+            $crate::bail!($fmt, $($arg)+);
+        }
+    };
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    #[error(transparent)]
+    Protocol(#[from] protocol::Error),
+    #[error(transparent)]
+    Config(#[from] config::Error),
+    #[error(transparent)]
+    Notifier(#[from] notifier::Error),
+    #[error(transparent)]
+    State(#[from] state::Error),
+    #[error(transparent)]
+    Resolver(#[from] resolver::Error),
+    #[cfg(feature = "server")]
+    #[error(transparent)]
+    Renewer(#[from] renewer::Error),
+    /// The server refused a request, for the reason told by the code and described by the
+    /// detail, if any.
+    #[error("the server refused the request ({code}){detail}", code = .0,
+        detail = .1.as_ref().map (|detail| format!(": {}", detail)).unwrap_or_default())]
+    Refused(protocol::ErrorCode, Option<String>)
+}
+
+chained_error!(Error);
+
+impl Error {
+    /// A message describing the error which can be safely sent to clients, as it never includes
+    /// the underlying causes (which may contain details about the router or the configuration).
+    pub fn client_message (&self) -> String {
+        match *self {
+            Error::Msg(ref message) => message.clone(),
+            Error::Chained(ref error, _) => error.client_message(),
+            Error::Protocol(ref error) => error.to_string(),
+            Error::Notifier(_) => "failed to send notifications".into(),
+            #[cfg(feature = "server")]
+            Error::Renewer(_) => "failed to renew the IP address".into(),
+            _ => "unexpected error".into()
+        }
+    }
+
+    /// The class of the error sent to clients along with `client_message`: failures of renewers
+    /// (even when they are the cause of another error) are `RenewerFailure`, and invalid packets
+    /// are `Unsupported`.
+    pub fn client_code (&self) -> protocol::ErrorCode {
+        use crate::protocol::ErrorCode;
+        match *self {
+            Error::Chained(ref error, ref cause) => {
+                #[cfg(feature = "server")]
+                if cause.is::<renewer::Error>() {
+                    return ErrorCode::RenewerFailure;
+                }
+                if cause.is::<protocol::Error>() {
+                    return ErrorCode::Unsupported;
+                }
+                match cause.downcast_ref::<Error>().map (Error::client_code) {
+                    Some(code) if code != ErrorCode::Internal => code,
+                    _ => error.client_code()
+                }
+            },
+            #[cfg(feature = "server")]
+            Error::Renewer(_) => ErrorCode::RenewerFailure,
+            Error::Protocol(_) => ErrorCode::Unsupported,
+            Error::Refused(code, _) => code,
+            _ => ErrorCode::Internal
+        }
+    }
+}
+
+/// Walks the causes of errors.
+pub trait ErrorExt: StdError {
+    /// The messages of the causes of the error, starting from the closest one.
+    fn causes (&self) -> Vec<String> {
+        let mut causes = Vec::new();
+        let mut cause = self.source();
+        while let Some(error) = cause {
+            causes.push (error.to_string());
+            cause = error.source();
+        }
+        causes
+    }
+
+    /// The error followed by each of its causes, one per line.
+    fn display_chain (&self) -> String {
+        let mut chain = format!("Error: {}", self);
+        for cause in self.causes() {
+            chain += &format!("\nCaused by: {}", cause);
+        }
+        chain
+    }
+}
+
+impl<E: StdError + ?Sized> ErrorExt for E {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn client_messages_hide_causes() {
+        let error = Err::<(), _>(protocol::Error::from ("invalid packet"))
+            .chain_err (|| "can't read the packet")
+            .unwrap_err();
+        assert_eq!(error.client_message(), "can't read the packet");
+        assert_eq!(error.causes(), vec!["invalid packet".to_string()]);
+        let error = Error::from (notifier::Error::from ("no route to host"));
+        assert_eq!(error.client_message(), "failed to send notifications");
+        assert_eq!(error.display_chain(), "Error: no route to host");
+    }
+
+    #[cfg(feature = "server")]
+    #[test]
+    fn client_codes_look_at_causes() {
+        use crate::protocol::ErrorCode;
+        let error = Err::<(), _>(renewer::Error::from ("the router is on fire"))
+            .chain_err (|| "failed to renew the IP address")
+            .unwrap_err();
+        assert_eq!(error.client_code(), ErrorCode::RenewerFailure);
+        let error = Err::<(), _>(error).chain_err (|| "failed to renew").unwrap_err();
+        assert_eq!(error.client_code(), ErrorCode::RenewerFailure);
+        assert_eq!(Error::from ("unexpected").client_code(), ErrorCode::Internal);
+    }
+}
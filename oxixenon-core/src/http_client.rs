@@ -0,0 +1,737 @@
+//! A basic HTTP client.
+//! 
+//! You may ask: "why didn't you use Reqwest or Hyper?" The answer is that I didn't want to bundle
+//! all the dependencies required by Hyper, so I implemented it by myself.
+//! 
+//! **Note:** no advanced HTTP features are implemented (such as chunking)!
+
+
+use std::{io, time};
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::io::prelude::*;
+use std::net::{Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use http::Response;
+use crate::config::Secret;
+use crate::errors::Cause;
+use http::header::{HeaderValue};
+
+pub use http::header;
+pub use http::Request;
+
+const FIVE_SECONDS: time::Duration = time::Duration::from_secs(5);
+
+// Headers and query parameters which carry credentials. Their values are never logged.
+const SENSITIVE_HEADERS: &[&str] =
+    &["authorization", "cookie", "proxy-authorization", "set-cookie", "x-sentry-auth"];
+const SENSITIVE_PARAMS: &[&str] = &["auth", "password", "pwd", "response", "sid", "token"];
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    #[error(transparent)]
+    Io(#[from] ::std::io::Error)
+}
+
+chained_error!(Error);
+
+type RequestBody = String;
+
+/// A bidirectional byte stream on which a single HTTP exchange takes place.
+pub trait Connection: Read + Write {}
+
+impl<T: Read + Write> Connection for T {}
+
+/// A trait for objects which can open [`Connection`](trait.Connection.html)s to a remote host.
+///
+/// `make_request` never touches the network directly, but goes through a `Transport`. This allows
+/// to replace the real network with canned responses (see
+/// [`MockTransport`](struct.MockTransport.html)).
+pub trait Transport: Send {
+    /// Opens a new connection to `host:port`.
+    fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>>;
+
+    /// Opens a new connection to `host:port` secured by TLS, for `https` URIs. Transports don't
+    /// support it unless they say otherwise.
+    fn connect_tls(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        bail!("this transport can't connect to host {}:{} using TLS", host, port)
+    }
+}
+
+/// The default transport, which connects to remote hosts using TCP (and TLS for `https` URIs,
+/// verifying their certificates against the system certificates).
+#[derive(Debug, Default)]
+pub struct TcpTransport;
+
+impl Transport for TcpTransport {
+    fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        Ok(Box::new (tcp_connect (host, port)?))
+    }
+
+    fn connect_tls(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        TlsTransport::default().connect_tls (host, port)
+    }
+}
+
+/// A transport which verifies the certificates of TLS servers against the ones of a PEM file,
+/// e.g. the self-signed certificate of a router. Plain connections are made using TCP.
+#[derive(Debug, Default)]
+pub struct TlsTransport {
+    #[cfg_attr(not(feature = "http-client-tls"), allow(dead_code))]
+    ca_file: Option<String>
+}
+
+impl TlsTransport {
+    /// Creates a transport verifying TLS servers against the certificates in `ca_file`, rather
+    /// than the system ones.
+    pub fn with_ca_file (ca_file: impl Into<String>) -> TlsTransport {
+        TlsTransport { ca_file: Some (ca_file.into()) }
+    }
+}
+
+impl Transport for TlsTransport {
+    fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        Ok(Box::new (tcp_connect (host, port)?))
+    }
+
+    #[cfg(feature = "http-client-tls")]
+    fn connect_tls(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        use openssl::ssl::{SslConnector, SslMethod};
+        let mut connector = SslConnector::builder (SslMethod::tls_client())
+            .chain_err (|| "failed to initialize TLS")?;
+        if let Some(ref ca_file) = self.ca_file {
+            connector.set_ca_file (ca_file)
+                .chain_err (|| format!("can't load the certificates in '{}'", ca_file))?;
+        }
+        let stream = connector.build().connect (host, tcp_connect (host, port)?)
+            .chain_err (|| format!("TLS handshake with host {}:{} failed", host, port))?;
+        Ok(Box::new (stream))
+    }
+
+    #[cfg(not(feature = "http-client-tls"))]
+    fn connect_tls(&mut self, _host: &str, _port: u16) -> Result<Box<dyn Connection>> {
+        bail!("HTTPS requires oxixenon to be compiled with the feature 'http-client-tls'")
+    }
+}
+
+fn tcp_connect (host: &str, port: u16) -> Result<TcpStream> {
+    let stream = each_addr (
+        (host, port),
+        |addr| TcpStream::connect_timeout (addr, FIVE_SECONDS)
+    ).chain_err (|| format!("failed to connect to host {}:{}", host, port))?;
+    stream.set_read_timeout (Some (FIVE_SECONDS))
+        .chain_err (|| "failed to set read timeout to five seconds")?;
+    Ok(stream)
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    responses: VecDeque<Vec<u8>>,
+    requests: Vec<String>
+}
+
+/// An in-memory transport which replies to each request with a canned response.
+///
+/// Responses are consumed in the same order they have been queued, and every request written
+/// to the transport is recorded and can be inspected with `requests()`. Cloned instances share
+/// the same state, so a clone can be kept around after handing the transport to a client.
+#[derive(Debug, Clone, Default)]
+pub struct MockTransport(Arc<Mutex<MockState>>);
+
+impl MockTransport {
+    /// Creates a new transport with no queued responses.
+    pub fn new() -> MockTransport {
+        MockTransport::default()
+    }
+
+    /// Queues a raw HTTP response (status line, headers and body).
+    pub fn push_response<T: Into<Vec<u8>>> (&self, response: T) -> &Self {
+        self.0.lock().unwrap().responses.push_back (response.into());
+        self
+    }
+
+    /// Returns the raw requests received so far, in order.
+    pub fn requests(&self) -> Vec<String> {
+        self.0.lock().unwrap().requests.clone()
+    }
+}
+
+impl Transport for MockTransport {
+    fn connect(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        let response = self.0.lock().unwrap().responses.pop_front()
+            .chain_err (|| format!("no canned response left for {}:{}", host, port))?;
+        Ok(Box::new (MockConnection {
+            response: io::Cursor::new (response),
+            request: Vec::new(),
+            state: self.0.clone()
+        }))
+    }
+
+    // Canned responses don't care about TLS.
+    fn connect_tls(&mut self, host: &str, port: u16) -> Result<Box<dyn Connection>> {
+        self.connect (host, port)
+    }
+}
+
+struct MockConnection {
+    response: io::Cursor<Vec<u8>>,
+    request: Vec<u8>,
+    state: Arc<Mutex<MockState>>
+}
+
+impl Read for MockConnection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.response.read (buf)
+    }
+}
+
+impl Write for MockConnection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.request.write (buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for MockConnection {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.state.lock() {
+            let request = String::from_utf8_lossy (&self.request).into_owned();
+            state.requests.push (request);
+        }
+    }
+}
+
+/// A trait for objects which can be converted to `RequestBody` (`String`) values.
+pub trait ToRequestBody {
+    /// Converts this object to a `RequestBody`.
+    fn to_request_body(self) -> RequestBody;
+    /// The length that this object will have once converted.
+    fn len(&self) -> usize;
+    /// Whether this object will be empty once converted.
+    fn is_empty(&self) -> bool { self.len() == 0 }
+}
+
+impl ToRequestBody for String {
+    fn to_request_body(self) -> RequestBody { self }
+    fn len(&self) -> usize { String::len (self) }
+}
+
+impl<'a> ToRequestBody for HashMap<&'a str, &'a str>
+{
+    fn to_request_body(self) -> RequestBody {
+        let mut output = String::new();
+        for (key, value) in self.iter() {
+            // TODO: perform proper urlencoding
+            output += format!("{}={}&", key, value).as_str();
+        }
+        output.pop();
+        output
+    }
+    fn len(&self) -> usize {
+        self.len() * 2 + self.iter().map (|(k, v)| k.len() + v.len()).sum::<usize>() - 1
+    }
+}
+
+/// Performs an HTTP request with a [`Request<Option<T>>`](struct.Request.html) object, using
+/// the default [`TcpTransport`](struct.TcpTransport.html).
+pub fn make_request<T>(request: Request<Option<T>>) -> Result<Response<String>>
+    where T: ToRequestBody
+{
+    make_request_with (&mut TcpTransport, request)
+}
+
+/// Builds the base URL of a web interface (e.g. "https://192.168.1.1:8443") from its `scheme`
+/// ("http" unless specified, or "https"), its `host` and its `port` (the default of the scheme
+/// unless specified).
+pub fn base_url (scheme: Option<&str>, host: &str, port: Option<u16>) -> Result<String> {
+    let scheme = scheme.unwrap_or ("http");
+    ensure!(
+        scheme == "http" || scheme == "https",
+        "unsupported scheme '{}', expected 'http' or 'https'", scheme
+    );
+    Ok(match port {
+        Some(port) => format!("{}://{}:{}", scheme, uri_host (host), port),
+        None => format!("{}://{}", scheme, uri_host (host))
+    })
+}
+
+/// Formats `host` (a host name or an IP address) to be used in URIs, by enclosing IPv6 addresses
+/// in brackets.
+pub fn uri_host (host: &str) -> Cow<'_, str> {
+    if host.parse::<Ipv6Addr>().is_ok() {
+        Cow::Owned (format!("[{}]", host))
+    } else {
+        Cow::Borrowed (host)
+    }
+}
+
+/// The cookies set by `res` (without their attributes), joined as in a `Cookie` header, if any.
+pub fn cookies (res: &Response<String>) -> Option<Secret<String>> {
+    let cookies = res.headers().get_all (header::SET_COOKIE).iter()
+        .filter_map (|cookie| cookie.to_str().ok())
+        .filter_map (|cookie| cookie.split (';').next())
+        .map (str::trim)
+        .collect::<Vec<_>>();
+    if cookies.is_empty() { None } else { Some (cookies.join ("; ").into()) }
+}
+
+/// Performs an HTTP request with a [`Request<Option<T>>`](struct.Request.html) object over the
+/// specified [`Transport`](trait.Transport.html).
+pub fn make_request_with<T>(transport: &mut dyn Transport, mut request: Request<Option<T>>)
+    -> Result<Response<String>>
+    where T: ToRequestBody
+{
+    let host = request.uri().host().chain_err (|| "missing host in request URI")?;
+    // IPv6 addresses are enclosed in brackets in URIs (and in the host header), but not when
+    // connecting.
+    let host = host.strip_prefix ('[').and_then (|h| h.strip_suffix (']')).unwrap_or (host);
+    let https = request.uri().scheme_str() == Some ("https");
+    let port = request.uri().port_u16().unwrap_or (if https { 443 } else { 80 });
+    let mut stream = if https {
+        transport.connect_tls (host, port)?
+    } else {
+        transport.connect (host, port)?
+    };
+    let mut writer = io::BufWriter::new (&mut stream);
+
+    {
+        let path = request.uri().path_and_query().map (|p| p.as_str()).unwrap_or ("/");
+        trace!("requesting {} {}", request.method(), redact_query (path));
+        // begin writing our HTTP request
+        write!(writer, "{method} {path} HTTP/1.1\r\n",
+            method = request.method(),
+            path = path
+        )?;
+    }
+
+    // fixup headers
+    if !request.headers().contains_key (header::HOST) {
+        let host_header_port = request
+            .uri()
+            .port_u16()
+            .map (|p| format!(":{}", p))
+            .unwrap_or ("".into());
+        let host_header = HeaderValue::from_str (format!(
+            "{}{}",
+            request.uri().host().unwrap(),
+            host_header_port
+        ).as_str()).chain_err (|| "failed to create HTTP host header")?;
+        request.headers_mut().insert (header::HOST, host_header);
+    }
+    let is_post = http::Method::POST == *request.method();
+    if is_post {
+        let body_len = request.body()
+            .as_ref()
+            .expect ("Missing request body in POST request")
+            .len();
+        request.headers_mut().insert (
+            header::CONTENT_LENGTH,
+            body_len.into()
+        );
+        if !request.headers().contains_key (header::CONTENT_TYPE) {
+            request.headers_mut().insert (
+                header::CONTENT_TYPE,
+                HeaderValue::from_static ("application/x-www-form-urlencoded")
+            );
+        }
+    }
+    request.headers_mut().insert (header::CONNECTION, HeaderValue::from_static ("close"));
+
+    // write headers
+    for (key, value) in request.headers().iter() {
+        let value = value.to_str()
+            .chain_err (|| format!("failed to retrieve header's '{}' value", key.as_str()))?;
+        trace!("request header: {} => {}", key.as_str(), redact_header (key.as_str(), value));
+        write!(writer, "{}: {}\r\n", key.as_str(), value)?;
+    }
+    
+    write!(writer, "\r\n")?;
+
+    if is_post {
+        // write body
+        let body = request
+            .into_body()
+            .unwrap()
+            .to_request_body();
+        write!(
+            writer,
+            "{}{newline}",
+            body,
+            newline = if body.ends_with ("\r\n") { "" } else { "\r\n" }
+        )?;
+    }
+
+    writer.flush()?;
+    drop (writer);
+
+    // read the HTTP response
+    trace!("waiting for a response...");
+    read_response (io::BufReader::new (stream))
+}
+
+/// The reason phrase of a response's status line (e.g. `Not Found` in `HTTP/1.1 404 Not Found`).
+///
+/// `http::Response` has no field for it, so it is stored in the response's extensions:
+/// `response.extensions().get::<ReasonPhrase>()`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ReasonPhrase(pub String);
+
+// Parses the status line and the headers of a response. Repeated headers are all preserved
+// (use `headers().get_all` to retrieve them), obsolete line folding is unfolded, and header lines
+// which can't be parsed are skipped instead of failing the whole response.
+fn read_response<R: BufRead>(mut reader: R) -> Result<Response<String>> {
+    let mut response_builder = Response::builder();
+    let mut status_line_seen = false;
+    let mut expecting_headers = true;
+    // name and value of the last header, which may still be continued by a folded line
+    let mut pending_header: Option<(String, String)> = None;
+    let mut body = String::new();
+    let mut raw_line = Vec::new();
+    loop {
+        raw_line.clear();
+        if reader.read_until (b'\n', &mut raw_line)? == 0 {
+            break;
+        }
+        let line = String::from_utf8_lossy (&raw_line);
+        let line = line.trim_end_matches (['\r', '\n']);
+        if !status_line_seen {
+            if !line.starts_with ("HTTP/") {
+                continue;
+            }
+            status_line_seen = true;
+            let (version, status_code, reason) = parse_status_line (line)?;
+            trace!("received status code: {} (reason: '{}')", status_code, reason);
+            response_builder = response_builder
+                .version (version)
+                .status (status_code)
+                .extension (ReasonPhrase (reason.into()));
+        } else if expecting_headers {
+            if line.is_empty() {
+                expecting_headers = false;
+                response_builder = append_header (response_builder, pending_header.take());
+            } else if line.starts_with ([' ', '\t']) {
+                // obs-fold: this line continues the value of the previous header
+                match pending_header {
+                    Some((_, ref mut value)) => {
+                        value.push (' ');
+                        value.push_str (line.trim());
+                    },
+                    None => debug!("ignoring folded header line without a header: {}", line)
+                }
+            } else {
+                response_builder = append_header (response_builder, pending_header.take());
+                match line.split_once (':') {
+                    Some((name, value)) =>
+                        pending_header = Some((name.trim().into(), value.trim().into())),
+                    None => debug!("ignoring malformed header line: {}", line)
+                }
+            }
+        } else {
+            body += line;
+            body.push ('\n');
+        }
+    }
+    ensure!(status_line_seen, "the server did not send a valid HTTP response");
+    response_builder = append_header (response_builder, pending_header.take());
+    response_builder.body (body).chain_err (|| "failed to build HTTP response object")
+}
+
+fn parse_status_line (line: &str) -> Result<(http::Version, http::StatusCode, &str)> {
+    // HTTP-version SP status-code SP [ reason-phrase ]
+    let mut parts = line.splitn (3, ' ');
+    let version = match parts.next() {
+        Some("HTTP/1.0") => http::Version::HTTP_10,
+        Some("HTTP/1.1") => http::Version::HTTP_11,
+        Some("HTTP/2") | Some("HTTP/2.0") => http::Version::HTTP_2,
+        _ => bail!("unsupported HTTP version in status line: {}", line)
+    };
+    let status_code = parts.next()
+        .and_then (|code| http::StatusCode::from_bytes (code.as_bytes()).ok())
+        .chain_err (|| format!("invalid status code: {}", line))?;
+    Ok((version, status_code, parts.next().unwrap_or ("").trim()))
+}
+
+fn append_header (builder: http::response::Builder, header: Option<(String, String)>)
+    -> http::response::Builder
+{
+    let (name, value) = match header {
+        Some(header) => header,
+        None => return builder
+    };
+    match (header::HeaderName::from_bytes (name.as_bytes()), HeaderValue::from_str (&value)) {
+        (Ok(name), Ok(value)) => {
+            trace!(
+                "response header: {} => {}",
+                name, redact_header (name.as_str(), value.to_str().unwrap_or ("<binary>"))
+            );
+            builder.header (name, value)
+        },
+        _ => {
+            debug!("ignoring invalid header: {}: {}", name, redact_header (&name, &value));
+            builder
+        }
+    }
+}
+
+/// Performs a `GET` request to a given URI.
+// Returns the value of a header as it can be logged.
+fn redact_header<'a> (name: &str, value: &'a str) -> &'a str {
+    if SENSITIVE_HEADERS.iter().any (|header| header.eq_ignore_ascii_case (name)) {
+        "***"
+    } else {
+        value
+    }
+}
+
+// Returns a path whose sensitive query parameters (such as `sid`) are redacted.
+fn redact_query (path: &str) -> Cow<'_, str> {
+    let (path, query) = match path.split_once ('?') {
+        Some(parts) => parts,
+        None => return Cow::Borrowed (path)
+    };
+    let query: Vec<Cow<'_, str>> = query.split ('&')
+        .map (|pair| match pair.split_once ('=') {
+            Some((key, _)) if SENSITIVE_PARAMS.iter().any (|p| p.eq_ignore_ascii_case (key)) =>
+                Cow::Owned (format!("{}=***", key)),
+            _ => Cow::Borrowed (pair)
+        })
+        .collect();
+    Cow::Owned (format!("{}?{}", path, query.join ("&")))
+}
+
+pub fn get (uri: &str) -> Result<Response<String>> {
+    let req: Request<Option<String>> = Request::builder().uri (uri).body (None)
+        .chain_err (|| "failed to build HTTP request object")?;
+    make_request (req)
+}
+
+/// Performs a `GET` request to a given URI over the specified transport.
+pub fn get_with (transport: &mut dyn Transport, uri: &str) -> Result<Response<String>> {
+    let req: Request<Option<String>> = Request::builder().uri (uri).body (None)
+        .chain_err (|| "failed to build HTTP request object")?;
+    make_request_with (transport, req)
+}
+
+/// Starts building a `POST` request to a given URI.
+pub fn build_post<'a>(uri: &'a str) -> PostRequestBuilder<'a> {
+    PostRequestBuilder::new().uri(uri)
+}
+
+/// A builder for HTTP `POST` requests.
+pub struct PostRequestBuilder<'a> {
+    builder: http::request::Builder,
+    data: Option<HashMap<&'a str, &'a str>>
+}
+
+impl<'a> Default for PostRequestBuilder<'a> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a> PostRequestBuilder<'a> {
+    /// Creates a new builder.
+    pub fn new() -> PostRequestBuilder<'a> {
+        PostRequestBuilder {
+            builder: Request::builder().method (http::Method::POST),
+            data: Some(HashMap::new())
+        }
+    }
+
+    /// Returns a mutable reference to the associated `Builder` object.
+    pub fn builder(&mut self) -> &mut http::request::Builder {
+        &mut self.builder
+    }
+
+    /// Sets the URI of this request builder.
+    pub fn uri (mut self, uri: &'a str) -> Self {
+        self.builder = self.builder.uri (uri);
+        self
+    }
+
+    /// Adds an element to the `application/x-www-form-urlencoded` fields of this builder.
+    pub fn put (mut self, key: &'a str, value: &'a str) -> Self {
+        self.data.as_mut().expect ("PostRequestBuilder already used").insert (key, value);
+        self
+    }
+
+    /// Consumes this builder and produces a `Request<T>` with a type suitable for use in
+    /// `make_request`.
+    pub fn build (mut self) -> http::Result<Request<Option<HashMap<&'a str, &'a str>>>> {
+        let map = self.data.take().expect ("PostRequestBuilder already used");
+        self.builder.body (if map.is_empty() { None } else { Some (map) })
+    }
+
+    /// Consumes this builder and executes the built request.
+    pub fn build_and_execute (self) -> Result<Response<String>> {
+        make_request (self.build().chain_err (|| "failed to build HTTP request object")?)
+    }
+
+    /// Consumes this builder and executes the built request over the specified transport.
+    pub fn build_and_execute_with (self, transport: &mut dyn Transport)
+        -> Result<Response<String>>
+    {
+        let request = self.build().chain_err (|| "failed to build HTTP request object")?;
+        make_request_with (transport, request)
+    }
+}
+
+// taken from std/net/mod.rs
+fn each_addr<A: ToSocketAddrs, F, T>(addr: A, mut f: F) -> io::Result<T>
+    where F: FnMut(&SocketAddr) -> io::Result<T>
+{
+    let mut last_err = None;
+    for addr in addr.to_socket_addrs()? {
+        match f(&addr) {
+            Ok(l) => return Ok(l),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    Err(last_err.unwrap_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput,
+                   "could not resolve to any addresses")
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn get_writes_request_and_parses_response() {
+        let transport = MockTransport::new();
+        transport.push_response (
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\n\r\nhello\r\nworld\r\n"
+        );
+        let res = get_with (&mut transport.clone(), "http://router.lan:8080/status?x=1").unwrap();
+        assert_eq!(res.status(), 200);
+        assert_eq!(res.headers()[header::CONTENT_TYPE], "text/plain");
+        assert_eq!(res.body(), "hello\nworld\n");
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].starts_with ("GET /status?x=1 HTTP/1.1\r\n"));
+        assert!(requests[0].contains ("host: router.lan:8080\r\n"));
+        assert!(requests[0].contains ("connection: close\r\n"));
+    }
+
+    #[test]
+    fn post_writes_form_body() {
+        let transport = MockTransport::new();
+        transport.push_response ("HTTP/1.1 204 No Content\r\n\r\n");
+        let res = build_post ("http://router.lan/login")
+            .put ("user", "admin")
+            .build_and_execute_with (&mut transport.clone())
+            .unwrap();
+        assert_eq!(res.status(), 204);
+
+        let request = &transport.requests()[0];
+        assert!(request.starts_with ("POST /login HTTP/1.1\r\n"));
+        assert!(request.contains ("content-length: 10\r\n"));
+        assert!(request.contains ("content-type: application/x-www-form-urlencoded\r\n"));
+        assert!(request.ends_with ("\r\n\r\nuser=admin\r\n"));
+    }
+
+    #[test]
+    fn repeated_headers_are_preserved() {
+        let res = read_response (
+            "HTTP/1.1 302 Found\r\nSet-Cookie: a=1\r\nSet-Cookie: b=2\r\n\r\n".as_bytes()
+        ).unwrap();
+        let cookies = res.headers().get_all (header::SET_COOKIE).iter().collect::<Vec<_>>();
+        assert_eq!(cookies, vec!["a=1", "b=2"]);
+    }
+
+    #[test]
+    fn folded_and_malformed_headers_are_tolerated() {
+        let res = read_response (
+            "HTTP/1.1 200 OK\r\nX-Long: first\r\n  second\r\n\tthird\r\n\
+            not a header\r\nBad Name: x\r\nServer: router\r\n\r\nbody\r\n".as_bytes()
+        ).unwrap();
+        assert_eq!(res.headers()["x-long"], "first second third");
+        assert_eq!(res.headers()[header::SERVER], "router");
+        assert_eq!(res.headers().len(), 2);
+        assert_eq!(res.body(), "body\n");
+    }
+
+    #[test]
+    fn status_line_is_parsed() {
+        let res = read_response ("HTTP/1.0 404 Not Found Here\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(res.status(), 404);
+        assert_eq!(res.version(), http::Version::HTTP_10);
+        assert_eq!(
+            res.extensions().get::<ReasonPhrase>(),
+            Some (&ReasonPhrase ("Not Found Here".into()))
+        );
+
+        let res = read_response ("HTTP/1.1 204\r\n\r\n".as_bytes()).unwrap();
+        assert_eq!(res.status(), 204);
+        assert_eq!(res.extensions().get::<ReasonPhrase>(), Some (&ReasonPhrase ("".into())));
+
+        assert!(read_response ("HTTP/1.1 abc OK\r\n\r\n".as_bytes()).is_err());
+        assert!(read_response ("garbage\r\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn credentials_are_redacted() {
+        assert_eq!(redact_header ("Set-Cookie", "sid=abc"), "***");
+        assert_eq!(redact_header ("Location", "/"), "/");
+        assert_eq!(redact_query ("/login_sid.lua"), "/login_sid.lua");
+        assert_eq!(redact_query ("/data.lua?sid=abc&page=x&SID"), "/data.lua?sid=***&page=x&SID");
+    }
+
+    #[test]
+    fn base_urls_are_built() {
+        assert_eq!(base_url (None, "192.168.1.1", None).unwrap(), "http://192.168.1.1");
+        let url = base_url (Some ("https"), "fd00::1", Some (8443)).unwrap();
+        assert_eq!(url, "https://[fd00::1]:8443");
+        assert!(base_url (Some ("ftp"), "192.168.1.1", None).is_err());
+    }
+
+    #[test]
+    fn cookies_are_joined_without_attributes() {
+        let response = Response::builder()
+            .header (header::SET_COOKIE, "sid=abc; path=/; HttpOnly")
+            .header (header::SET_COOKIE, "lang=en")
+            .body (String::new())
+            .unwrap();
+        assert_eq!(cookies (&response).unwrap().expose(), "sid=abc; lang=en");
+        assert!(cookies (&Response::new (String::new())).is_none());
+    }
+
+    #[test]
+    fn missing_canned_response_is_an_error() {
+        assert!(get_with (&mut MockTransport::new(), "http://router.lan/").is_err());
+    }
+
+    #[test]
+    fn ipv6_literals_are_supported() {
+        use std::net::TcpListener;
+        assert_eq!(uri_host ("fd00::1"), "[fd00::1]");
+        assert_eq!(uri_host ("192.168.1.1"), "192.168.1.1");
+        assert_eq!(uri_host ("fritz.box"), "fritz.box");
+        // Skipped on hosts without IPv6.
+        let listener = match TcpListener::bind ("[::1]:0") {
+            Ok(listener) => listener,
+            Err(_) => return
+        };
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn (move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = io::BufReader::new (&stream);
+            let mut request = String::new();
+            while reader.read_line (&mut request).unwrap() > 2 {}
+            (&stream).write_all (b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok").unwrap();
+            request
+        });
+        let res = get (&format!("http://{}:{}/status", uri_host ("::1"), port)).unwrap();
+        assert_eq!(res.status(), 200);
+        assert!(server.join().unwrap().contains (&format!("host: [::1]:{}\r\n", port)));
+    }
+}
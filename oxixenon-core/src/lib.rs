@@ -0,0 +1,58 @@
+//! The core of Xenon: its protocol, client, renewers and notifiers, without its command line
+//! interface (which lives in the `oxixenon` binary).
+//!
+//! - [`protocol`](protocol/index.html): the packets exchanged by clients and servers.
+//! - [`client`](client/index.html): a client which sends requests to a server (feature
+//!   `client`).
+//! - [`server`](server/index.html): a server which renews the IP address when clients ask for it
+//!   (feature `server`).
+//! - [`renewer`](renewer/index.html): the renewers, which ask routers for a new IP address
+//!   (feature `server`).
+//! - [`notifier`](notifier/index.html): the notifiers, which send and receive events.
+//! - [`config`](config/index.html): the configuration, parsed from a file along with the
+//!   environment and, optionally, command line arguments (see `config::Args`).
+//! - [`logging`](logging/index.html): the logging backends.
+//! - [`state`](state/index.html): the state which survives restarts, see `state_dir`.
+//! - [`acl`](acl/index.html): which clients a server answers, and which requests they may send.
+//! - [`schedule`](schedule/index.html): when a server renews the IP address on its own.
+//! - [`resolver`](resolver/index.html): host names which are resolved again while running.
+//! - [`tls`](tls/index.html): TLS for the connections between clients and servers (feature
+//!   `tls`).
+//! - [`metrics`](metrics/index.html): the metrics reported by the server, the renewers and the
+//!   notifiers, along with their exporters.
+//! - [`errors`](errors/index.html): the error types.
+//!
+//! Everything which is public and not hidden from the documentation follows semantic versioning,
+//! along with the feature flags, which are the same as the binary's.
+
+#[cfg(all(windows, feature = "client-toasts"))]
+#[macro_use]
+extern crate winapi;
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+pub mod errors;
+pub mod acl;
+#[cfg(feature = "client")]
+pub mod client;
+pub mod config;
+pub mod config_template;
+pub mod logging;
+pub mod metrics;
+pub mod protocol;
+pub mod resolver;
+pub mod schedule;
+pub mod state;
+#[cfg(feature = "server")]
+pub mod renewer;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "http-client")]
+pub mod http_client;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod notifier;
+
+#[cfg(feature = "client")]
+pub mod notification_toasts;
@@ -0,0 +1,159 @@
+//! The `file` logging backend, which writes messages to one or more files.
+
+use super::{now, Correlated, LogBackend};
+use crate::config::Section;
+use crate::errors::*;
+use log::LevelFilter;
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+use serde::Deserialize;
+use serde::de::{self, Deserializer, MapAccess, SeqAccess, Visitor};
+use std::fmt;
+
+// Configuration of the `file` backend, in `logging.file`: either a single table or an array of
+// tables (`[[logging.file]]`), one for each file.
+struct FileOutputs(Vec<FileConfig>);
+
+impl<'de> Deserialize<'de> for FileOutputs {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> std::result::Result<Self, D::Error> {
+        struct FileOutputsVisitor;
+
+        impl<'de> Visitor<'de> for FileOutputsVisitor {
+            type Value = FileOutputs;
+
+            fn expecting (&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a table or an array of tables")
+            }
+
+            fn visit_map<A: MapAccess<'de>> (self, map: A)
+                -> std::result::Result<FileOutputs, A::Error>
+            {
+                let config = FileConfig::deserialize (de::value::MapAccessDeserializer::new (map))?;
+                Ok(FileOutputs(vec![config]))
+            }
+
+            fn visit_seq<A: SeqAccess<'de>> (self, seq: A)
+                -> std::result::Result<FileOutputs, A::Error>
+            {
+                Vec::deserialize (de::value::SeqAccessDeserializer::new (seq)).map (FileOutputs)
+            }
+        }
+
+        deserializer.deserialize_any (FileOutputsVisitor)
+    }
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct FileConfig {
+    /// Path of the log file.
+    path: String,
+    /// The most verbose level written to this file, e.g. "warn" writes only errors and warnings.
+    /// Defaults to `logging.verbosity`.
+    max_level: Option<LevelName>,
+    /// The least verbose level written to this file, e.g. "debug" writes only debug and trace
+    /// messages. Defaults to "error".
+    min_level: Option<LevelName>,
+    /// Only messages logged by these modules (and their submodules) are written to this file,
+    /// e.g. ["protocol", "renewer::dlink"]. Defaults to every module.
+    #[serde(default)]
+    targets: Vec<String>
+}
+
+impl FileConfig {
+    fn accepts (&self, metadata: &log::Metadata) -> bool {
+        let target = metadata.target().trim_start_matches ("oxixenon::");
+        self.min_level.is_none_or (|level| metadata.level() >= LevelFilter::from (level)) &&
+            (self.targets.is_empty() || self.targets.iter().any (|prefix| {
+                target.strip_prefix (prefix.as_str())
+                    .is_some_and (|rest| rest.is_empty() || rest.starts_with ("::"))
+            }))
+    }
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum LevelName {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace
+}
+
+impl From<LevelName> for LevelFilter {
+    fn from (level: LevelName) -> Self {
+        match level {
+            LevelName::Off   => LevelFilter::Off,
+            LevelName::Error => LevelFilter::Error,
+            LevelName::Warn  => LevelFilter::Warn,
+            LevelName::Info  => LevelFilter::Info,
+            LevelName::Debug => LevelFilter::Debug,
+            LevelName::Trace => LevelFilter::Trace
+        }
+    }
+}
+
+pub(super) struct Backend;
+
+impl LogBackend for Backend {
+    fn build (&self, config: &Section, level: LevelFilter)
+        -> Result<(Box<dyn log::Log>, Vec<String>)>
+    {
+        let (FileOutputs(configs), warnings) = config.parse_with_warnings::<FileOutputs>()
+            .chain_err (|| "the logging backend 'file' is not configured correctly")?;
+        let mut dispatch = fern::Dispatch::new();
+        for config in configs {
+            let log_file = fern::log_file (&config.path)
+                .chain_err (|| format!("can't open log file '{}'", config.path))?;
+            let max_level = config.max_level.map_or (level, LevelFilter::from);
+            dispatch = dispatch.chain (
+                fern::Dispatch::new()
+                    .format (|out, message, record| {
+                        // 1970-01-01 12:34:56 INFO <module> message
+                        out.finish (format_args!(
+                            "{} {} <{}> {}",
+                            now (true),
+                            record.level(),
+                            record.target().replace ("oxixenon::", ""),
+                            Correlated(message)
+                        ))
+                    })
+                    .level (max_level)
+                    .filter (move |metadata| config.accepts (metadata))
+                    .chain (log_file)
+            );
+        }
+        Ok((dispatch.into_log().1, warnings))
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, gen: &mut SchemaGenerator) -> Schema {
+        use crate::config::schema;
+        schema::one_or_many (schema::section::<FileConfig> (gen, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_outputs_filter_levels_and_targets() {
+        let config = FileConfig {
+            path: String::new(),
+            max_level: None,
+            min_level: Some (LevelName::Info),
+            targets: vec!["renewer".into()]
+        };
+        let metadata = |level, target| {
+            log::Metadata::builder().level (level).target (target).build()
+        };
+        assert!(config.accepts (&metadata (log::Level::Info, "renewer::dlink")));
+        assert!(config.accepts (&metadata (log::Level::Info, "oxixenon::renewer")));
+        assert!(!config.accepts (&metadata (log::Level::Info, "renewers")));
+        assert!(!config.accepts (&metadata (log::Level::Warn, "renewer::dlink")));
+    }
+}
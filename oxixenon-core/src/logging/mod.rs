@@ -0,0 +1,393 @@
+mod stdout;
+mod file;
+#[cfg(all(not(windows), feature = "syslog-backend"))]
+mod syslog;
+#[cfg(feature = "sentry-backend")]
+mod sentry;
+
+use crate::errors::*;
+use std::fmt;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::sync::{Arc, RwLock};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use log::LevelFilter;
+use crate::config::{LogConfig, Section};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+
+thread_local! {
+    // The correlation id of the messages logged by this thread, see `correlate`.
+    static CORRELATION_ID: RefCell<Option<String>> = const { RefCell::new (None) };
+}
+
+/// Generates a short id which is prepended to every message logged by the current thread (e.g.
+/// while handling a connection, including the messages of the renewer and of the notifier),
+/// until the returned guard is dropped.
+pub fn correlate() -> CorrelationGuard {
+    static COUNTER: AtomicU64 = AtomicU64::new (0);
+    // Random ids rather than sequential ones, so that they don't repeat across restarts.
+    let id = format!(
+        "{:08x}",
+        RandomState::new().hash_one (COUNTER.fetch_add (1, Ordering::Relaxed)) as u32
+    );
+    correlate_as (id)
+}
+
+/// Prepends `id` to every message logged by the current thread as `correlate` does, e.g. while
+/// working on behalf of another thread, whose id it is (see `correlation_id`).
+pub fn correlate_as (id: String) -> CorrelationGuard {
+    let previous = CORRELATION_ID.with (|current| current.replace (Some (id.clone())));
+    CorrelationGuard { id, previous }
+}
+
+/// Restores the previous correlation id of the thread when dropped, see `correlate`.
+pub struct CorrelationGuard {
+    id: String,
+    previous: Option<String>
+}
+
+impl CorrelationGuard {
+    pub fn id (&self) -> &str {
+        &self.id
+    }
+}
+
+impl Drop for CorrelationGuard {
+    fn drop (&mut self) {
+        CORRELATION_ID.with (|current| *current.borrow_mut() = self.previous.take());
+    }
+}
+
+// The error chain being logged by `log_error_chain` on this thread.
+#[derive(Clone)]
+#[cfg_attr(not(feature = "sentry-backend"), allow(dead_code))]
+enum ErrorChain {
+    // The error itself is being logged, and these are its causes.
+    Error(Vec<String>),
+    // The causes of the error are being logged, each in its own message.
+    Causes
+}
+
+thread_local! {
+    static ERROR_CHAIN: RefCell<Option<ErrorChain>> = const { RefCell::new (None) };
+}
+
+/// Used by `log_error_with_chain!` to log an error followed by each of its `causes`. Backends
+/// which report whole errors (such as `sentry`) can retrieve the causes while the error is
+/// logged, and ignore the messages of the causes.
+#[doc(hidden)]
+pub fn log_error_chain (causes: Vec<String>, log_error: impl FnOnce(), log_cause: impl Fn(&str)) {
+    let set = |chain| ERROR_CHAIN.with (|current| *current.borrow_mut() = chain);
+    set (Some (ErrorChain::Error (causes.clone())));
+    log_error();
+    set (Some (ErrorChain::Causes));
+    for cause in &causes {
+        log_cause (cause);
+    }
+    set (None);
+}
+
+#[cfg_attr(not(feature = "sentry-backend"), allow(dead_code))]
+fn current_error_chain() -> Option<ErrorChain> {
+    ERROR_CHAIN.with (|current| current.borrow().clone())
+}
+
+/// Returns the correlation id of the current thread (see `correlate`), if any. Backends which
+/// don't use the formatted messages can use it to tag what they log.
+pub fn correlation_id() -> Option<String> {
+    CORRELATION_ID.with (|id| id.borrow().clone())
+}
+
+// Whether STDOUT carries data rather than messages, see `reserve_stdout`.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new (false);
+
+/// Makes the `stdout` backend write every message to STDERR, as STDOUT carries data (such as the
+/// events written by `client notifications --output ndjson`). Must be called before `init`.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store (true, Ordering::Relaxed);
+}
+
+// The current time as shown by the `stdout` (e.g. "12:34:56") and `file` (with the date, e.g.
+// "1970-01-01 12:34:56") backends.
+fn now (with_date: bool) -> String {
+    format_time (std::time::SystemTime::now(), with_date)
+}
+
+/// Formats `time` as the logging backends do (e.g. "12:34:56", or "1970-01-01 12:34:56" with the
+/// date), in the local timezone.
+#[cfg(feature = "local-time")]
+pub fn format_time (time: std::time::SystemTime, with_date: bool) -> String {
+    chrono::DateTime::<chrono::Local>::from (time)
+        .format (if with_date { "%Y-%m-%d %H:%M:%S" } else { "%H:%M:%S" })
+        .to_string()
+}
+
+/// Without `local-time` (and thus chrono), times are formatted in UTC, e.g. "12:34:56Z".
+#[cfg(not(feature = "local-time"))]
+pub fn format_time (time: std::time::SystemTime, with_date: bool) -> String {
+    use std::time::UNIX_EPOCH;
+    utc (time.duration_since (UNIX_EPOCH).map_or (0, |d| d.as_secs()), with_date)
+}
+
+// Formats the seconds since the epoch as `format_time` does.
+#[cfg(not(feature = "local-time"))]
+fn utc (seconds: u64, with_date: bool) -> String {
+    let time = format!(
+        "{:02}:{:02}:{:02}Z", seconds / 3600 % 24, seconds / 60 % 60, seconds % 60
+    );
+    if !with_date {
+        return time;
+    }
+    // Converts the days since the epoch to a date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = seconds / 86400 + 719468;
+    let (era, day_of_era) = (days / 146097, days % 146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096)
+        / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = era * 400 + year_of_era + u64::from (month <= 2);
+    format!("{}-{:02}-{:02} {}", year, month, day, time)
+}
+
+// A message prefixed with the correlation id of the current thread, if any.
+struct Correlated<'a, T: fmt::Display>(&'a T);
+
+impl<'a, T: fmt::Display> fmt::Display for Correlated<'a, T> {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        CORRELATION_ID.with (|id| match *id.borrow() {
+            Some(ref id) => write!(f, "[{}] {}", id, self.0),
+            None => write!(f, "{}", self.0)
+        })
+    }
+}
+
+/// A logging backend, enabled by listing its name in `logging.backends`.
+///
+/// Besides the built-in backends, custom ones can be added with `register_backend`.
+pub trait LogBackend: Send + Sync {
+    /// Builds the logger described by `config` (the section named after the backend in
+    /// `[logging]`, which may be empty), along with any warning about its configuration.
+    /// `level` is the verbosity specified by the user: messages less verbose than it are
+    /// already filtered out.
+    fn build (&self, config: &Section, level: LevelFilter)
+        -> Result<(Box<dyn log::Log>, Vec<String>)>;
+
+    /// Returns the schema of the configuration of the backend, see `config::schema`.
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, _gen: &mut SchemaGenerator) -> Schema {
+        Schema::Bool (true)
+    }
+}
+
+// Backends added by `register_backend`, by name.
+static BACKENDS: RwLock<BTreeMap<String, Arc<dyn LogBackend>>> = RwLock::new (BTreeMap::new());
+
+/// Adds a custom logging backend, which can be enabled by listing `name` in `logging.backends`
+/// and configured in `[logging.<name>]`. Replaces any backend with the same name, including the
+/// built-in ones. Must be called before `init` to be used by the initial configuration.
+pub fn register_backend<B: LogBackend + 'static> (name: &str, backend: B) {
+    BACKENDS.write().unwrap_or_else (|e| e.into_inner())
+        .insert (name.to_string(), Arc::new (backend));
+}
+
+// The backends which are always available (if enabled at compile time).
+fn builtin_backends() -> Vec<(&'static str, Arc<dyn LogBackend>)> {
+    #[allow(unused_mut)]
+    let mut backends: Vec<(&'static str, Arc<dyn LogBackend>)> = vec![
+        ("stdout", Arc::new (stdout::Backend)),
+        ("file",   Arc::new (file::Backend))
+    ];
+    #[cfg(all(not(windows), feature = "syslog-backend"))]
+    backends.push (("syslog", Arc::new (syslog::Backend)));
+    #[cfg(feature = "sentry-backend")]
+    backends.push (("sentry", Arc::new (sentry::Backend)));
+    backends
+}
+
+// Looks up a backend by name, preferring the registered ones.
+fn find_backend (name: &str) -> Option<Arc<dyn LogBackend>> {
+    if let Some(backend) = BACKENDS.read().unwrap_or_else (|e| e.into_inner()).get (name) {
+        return Some (backend.clone());
+    }
+    builtin_backends().into_iter()
+        .find (|&(builtin, _)| builtin == name)
+        .map (|(_, backend)| backend)
+}
+
+/// Returns the schema of the configuration of the logging backends, see `config::schema`.
+#[cfg(feature = "config-schema")]
+pub fn config_schema (gen: &mut SchemaGenerator) -> Schema {
+    let mut backends: BTreeMap<String, Arc<dyn LogBackend>> = builtin_backends().into_iter()
+        .map (|(name, backend)| (name.to_string(), backend))
+        .collect();
+    backends.extend (BACKENDS.read().unwrap_or_else (|e| e.into_inner()).clone());
+    let sections = backends.iter()
+        .map (|(name, backend)| (name.as_str(), backend.config_schema (gen)))
+        .collect();
+    crate::config::schema::sections (sections)
+}
+
+#[macro_export]
+macro_rules! log_error_with_chain {
+    (target: $target:expr, $level:expr, $error:ident, $($arg:tt)+) => {
+        $crate::logging::log_error_chain (
+            $crate::errors::ErrorExt::causes (&$error),
+            || log!(target: $target, $level, $($arg)+),
+            |cause| log!(target: $target, $level, "- caused by: {}", cause)
+        )
+    };
+    ($level:expr, $error:ident, $($arg:tt)+) =>
+        (log_error_with_chain!(target: module_path!(), $level, $error, $($arg)+));
+    ($error:ident, $($arg:tt)+) =>
+        (log_error_with_chain!(log::Level::Error, $error, $($arg)+));
+}
+
+// The global logger. It forwards every message to the dispatcher built from the configuration,
+// which can be swapped at any time by `reload`.
+struct ReloadableLogger {
+    dispatcher: RwLock<Option<Box<dyn log::Log>>>
+}
+
+impl log::Log for ReloadableLogger {
+    fn enabled (&self, metadata: &log::Metadata) -> bool {
+        match *self.dispatcher.read().unwrap_or_else (|e| e.into_inner()) {
+            Some(ref dispatcher) => dispatcher.enabled (metadata),
+            None => false
+        }
+    }
+
+    fn log (&self, record: &log::Record) {
+        if let Some(ref dispatcher) = *self.dispatcher.read().unwrap_or_else (|e| e.into_inner()) {
+            dispatcher.log (record)
+        }
+    }
+
+    fn flush (&self) {
+        if let Some(ref dispatcher) = *self.dispatcher.read().unwrap_or_else (|e| e.into_inner()) {
+            dispatcher.flush()
+        }
+    }
+}
+
+static LOGGER: ReloadableLogger = ReloadableLogger { dispatcher: RwLock::new (None) };
+
+/// Initializes the global logger with the user-specified configuration.
+pub fn init (config: &LogConfig) -> Result<()> {
+    log::set_logger (&LOGGER).chain_err (|| "can't initialize the main logger")?;
+    reload (config)
+}
+
+/// Replaces the configuration of the global logger (initialized by `init`) without losing any
+/// message. If the new configuration is invalid, the current one is kept.
+pub fn reload (config: &LogConfig) -> Result<()> {
+    let (log_level, dispatcher, warnings) = build (config)?;
+    let previous = LOGGER.dispatcher.write().unwrap_or_else (|e| e.into_inner())
+        .replace (dispatcher);
+    if let Some(previous) = previous {
+        previous.flush();
+    }
+    log::set_max_level (log_level);
+    crate::protocol::set_trace_packets (config.trace_packets);
+    for warning in warnings {
+        warn!(target: "config", "{}", warning);
+    }
+    Ok(())
+}
+
+// Builds the dispatcher described by the configuration, along with its maximum level and any
+// warning about the configuration of the backends.
+fn build (config: &LogConfig) -> Result<(LevelFilter, Box<dyn log::Log>, Vec<String>)> {
+    let log_level: LevelFilter = config.level.parse()
+        .chain_err (|| format!("invalid option 'logging.verbosity': {}", config.level))?;
+    let mut fern = fern::Dispatch::new().level (log_level);
+    // Warnings about the configuration of the backends, logged once the dispatcher is in place.
+    let mut warnings = Vec::new();
+    for backend_config in &config.backends {
+        let backend = find_backend (&backend_config.name).chain_err (|| format!(
+            "unknown logging backend '{}', if it exists, make sure it is enabled",
+            backend_config.name
+        ))?;
+        let (logger, mut backend_warnings) = backend.build (&backend_config.config, log_level)?;
+        warnings.append (&mut backend_warnings);
+        fern = fern.chain (logger);
+    }
+    let (log_level, dispatcher) = fern.into_log();
+    Ok((log_level, dispatcher, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn correlation_ids_are_scoped() {
+        assert_eq!(Correlated(&"message").to_string(), "message");
+        let outer = correlate();
+        {
+            let inner = correlate();
+            assert_ne!(inner.id(), outer.id());
+            assert_eq!(Correlated(&"message").to_string(), format!("[{}] message", inner.id()));
+        }
+        assert_eq!(Correlated(&"message").to_string(), format!("[{}] message", outer.id()));
+        drop (outer);
+        assert_eq!(Correlated(&"message").to_string(), "message");
+    }
+
+    #[cfg(not(feature = "local-time"))]
+    #[test]
+    fn utc_timestamps() {
+        assert_eq!(utc (0, true), "1970-01-01 00:00:00Z");
+        assert_eq!(utc (951782400, true), "2000-02-29 00:00:00Z");
+        assert_eq!(utc (1735689598, true), "2024-12-31 23:59:58Z");
+        assert_eq!(utc (1735689598, false), "23:59:58Z");
+    }
+
+    #[test]
+    fn registered_backends_are_used() {
+        use std::sync::Mutex;
+
+        struct Collector(Arc<Mutex<Vec<String>>>);
+
+        impl log::Log for Collector {
+            fn enabled (&self, _: &log::Metadata) -> bool { true }
+            fn log (&self, record: &log::Record) {
+                self.0.lock().unwrap().push (record.args().to_string());
+            }
+            fn flush (&self) {}
+        }
+
+        struct CollectorBackend(Arc<Mutex<Vec<String>>>);
+
+        impl LogBackend for CollectorBackend {
+            fn build (&self, _: &Section, _: LevelFilter)
+                -> Result<(Box<dyn log::Log>, Vec<String>)>
+            {
+                Ok((Box::new (Collector(self.0.clone())), vec![]))
+            }
+        }
+
+        let messages = Arc::new (Mutex::new (Vec::new()));
+        register_backend ("collector", CollectorBackend(messages.clone()));
+        let config = |name: &str| LogConfig {
+            level: "info".into(),
+            backends: vec![crate::config::LogBackendConfig {
+                name: name.into(),
+                config: Section::empty (format!("logging.{}", name))
+            }],
+            trace_packets: false
+        };
+        let (_, dispatcher, _) = build (&config ("collector")).unwrap();
+        for &(level, message) in &[(log::Level::Info, "kept"), (log::Level::Debug, "dropped")] {
+            dispatcher.log (&log::Record::builder().args (format_args!("{}", message))
+                .level (level).build());
+        }
+        assert_eq!(*messages.lock().unwrap(), vec!["kept".to_string()]);
+        assert!(build (&config ("nonexistent")).is_err());
+    }
+}
@@ -0,0 +1,122 @@
+//! The `stdout` logging backend, which writes errors to STDERR and everything else to STDOUT.
+
+use super::{now, Correlated, LogBackend, STDOUT_RESERVED};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+use crate::config::Section;
+use crate::errors::*;
+use log::LevelFilter;
+use serde::Deserialize;
+use std::{env, fmt, io};
+use std::io::IsTerminal;
+use std::sync::atomic::Ordering;
+
+// Configuration of the `stdout` backend, in `logging.stdout`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct StdoutConfig {
+    /// Whether messages are colored: "always", "auto" (only when writing to a terminal and
+    /// NO_COLOR is not set) or "never".
+    #[serde(default)]
+    color: ColorMode
+}
+
+#[derive(Deserialize, Default, Clone, Copy)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum ColorMode {
+    Always,
+    #[default]
+    Auto,
+    Never
+}
+
+impl ColorMode {
+    // Whether colors should be used when writing to `stream`.
+    fn enabled_for<T: IsTerminal> (self, stream: &T) -> bool {
+        match self {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            // See https://no-color.org. Windows consoles don't always understand ANSI escapes.
+            ColorMode::Auto => stream.is_terminal() &&
+                env::var_os ("NO_COLOR").is_none_or (|value| value.is_empty()) &&
+                env::var_os ("TERM").is_none_or (|term| term != "dumb") &&
+                !cfg!(windows)
+        }
+    }
+}
+
+// Used to display data on "stdout". `file` uses a slightly different formatter which also displays
+// the date, and never uses colors.
+fn terminal_formatter (colored: bool)
+    -> impl Fn(fern::FormatCallback, &fmt::Arguments, &log::Record) + Sync + Send + 'static
+{
+    move |out, message, record| {
+        let time = now (false);
+        let target = record.target().replace ("oxixenon::", "");
+        if colored {
+            // 12:34:56 INFO <module> message, with a colored level and a dimmed module
+            out.finish (format_args!(
+                "{} \x1b[{}m{}\x1b[0m \x1b[2m<{}>\x1b[0m {}",
+                time, level_color (record.level()), record.level(), target, Correlated(message)
+            ))
+        } else {
+            // 12:34:56 INFO <module> message
+            out.finish (format_args!(
+                "{} {} <{}> {}", time, record.level(), target, Correlated(message)
+            ))
+        }
+    }
+}
+
+// The ANSI SGR parameters used for each level.
+fn level_color (level: log::Level) -> &'static str {
+    match level {
+        log::Level::Error => "1;31", // bold red
+        log::Level::Warn  => "33",   // yellow
+        log::Level::Info  => "32",   // green
+        log::Level::Debug => "34",   // blue
+        log::Level::Trace => "35"    // magenta
+    }
+}
+
+pub(super) struct Backend;
+
+impl LogBackend for Backend {
+    fn build (&self, config: &Section, _: LevelFilter)
+        -> Result<(Box<dyn log::Log>, Vec<String>)>
+    {
+        let (config, warnings) = config.parse_with_warnings::<StdoutConfig>()
+            .chain_err (|| "the logging backend 'stdout' is not configured correctly")?;
+        if STDOUT_RESERVED.load (Ordering::Relaxed) {
+            // Log everything to STDERR, see `reserve_stdout`.
+            let (_, logger) = fern::Dispatch::new()
+                .format (terminal_formatter (config.color.enabled_for (&io::stderr())))
+                .chain (io::stderr())
+                .into_log();
+            return Ok((logger, warnings));
+        }
+        let (_, logger) = fern::Dispatch::new()
+            .chain (
+                // Log only errors to STDERR.
+                fern::Dispatch::new()
+                    .format (terminal_formatter (config.color.enabled_for (&io::stderr())))
+                    .level (LevelFilter::Error)
+                    .chain (io::stderr())
+            )
+            .chain (
+                // Log everything else to STDOUT.
+                fern::Dispatch::new()
+                    .format (terminal_formatter (config.color.enabled_for (&io::stdout())))
+                    .filter (|metadata| metadata.level() != LevelFilter::Error)
+                    .chain (io::stdout())
+            )
+            .into_log();
+        Ok((logger, warnings))
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, gen: &mut SchemaGenerator) -> Schema {
+        crate::config::schema::section::<StdoutConfig> (gen, &[])
+    }
+}
@@ -0,0 +1,345 @@
+//! The `syslog` logging backend.
+//!
+//! Messages are formatted according to RFC 3164 (the traditional BSD format) or RFC 5424, and sent
+//! to the local syslog daemon or to a remote collector over UDP, TCP or TLS (RFC 5425). Stream
+//! transports (TCP and TLS) use octet-counting framing, i.e. each message is preceded by its
+//! length and a space.
+
+use super::{Correlated, LogBackend};
+use crate::config::Section;
+use crate::errors::*;
+use log::LevelFilter;
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+use std::io::{self, Write};
+use std::net::{TcpStream, UdpSocket};
+use std::os::unix::net::{UnixDatagram, UnixStream};
+use std::process;
+use std::sync::Mutex;
+use serde::Deserialize;
+
+// The `daemon` facility.
+const FACILITY: u8 = 3;
+const APP_NAME: &str = "oxixenon";
+
+// Configuration of the `syslog` backend, in `logging.syslog`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct SyslogConfig {
+    /// Format of the messages: "rfc3164" or "rfc5424".
+    #[serde(default)]
+    format: SyslogFormat,
+    #[serde(default)]
+    protocol: SyslogProtocol,
+    hostname: Option<String>,
+    /// Path to syslogd's UNIX socket, used by the "unix" protocol.
+    unix_socket_path: Option<String>,
+    /// Local address, used by the "udp" protocol.
+    local_addr: Option<String>,
+    /// Address of the syslog server, used by the "tcp", "udp" and "tls" protocols.
+    server_addr: Option<String>,
+    /// Private enterprise number used to add the module and the level of each message to the
+    /// structured data of RFC 5424 messages.
+    enterprise_id: Option<u32>,
+    /// Path of a PEM file with the certificates used to verify the server, used by the "tls"
+    /// protocol. Defaults to the system certificates.
+    #[cfg(feature = "syslog-tls")]
+    tls_ca_file: Option<String>,
+    /// Path of a PEM file with the client certificate, if required by the server.
+    #[cfg(feature = "syslog-tls")]
+    tls_cert_file: Option<String>,
+    /// Path of a PEM file with the private key of the client certificate.
+    #[cfg(feature = "syslog-tls")]
+    tls_key_file: Option<String>,
+    /// Name verified against the server certificate. Defaults to the host of `server_addr`.
+    #[cfg(feature = "syslog-tls")]
+    tls_server_name: Option<String>
+}
+
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum SyslogFormat {
+    #[default]
+    Rfc3164,
+    Rfc5424
+}
+
+#[derive(Deserialize, Default)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+enum SyslogProtocol {
+    #[default]
+    Unix,
+    Tcp,
+    Udp,
+    Tls
+}
+
+// Formats records according to the configured RFC.
+struct Formatter {
+    format: SyslogFormat,
+    hostname: Option<String>,
+    enterprise_id: Option<u32>,
+    pid: u32
+}
+
+impl Formatter {
+    fn format (&self, record: &log::Record) -> String {
+        let priority = FACILITY << 3 | severity (record.level());
+        let module = record.target().replace ("oxixenon::", "");
+        match self.format {
+            SyslogFormat::Rfc3164 => {
+                // <30>Jan 01 12:34:56 hostname oxixenon[42]: message
+                let hostname = self.hostname.as_ref().map (|name| format!("{} ", name));
+                format!(
+                    "<{}>{} {}{}[{}]: {}",
+                    priority, chrono::Local::now().format ("%b %e %T"),
+                    hostname.unwrap_or_default(), APP_NAME, self.pid, Correlated(record.args())
+                )
+            },
+            SyslogFormat::Rfc5424 => {
+                // <30>1 1970-01-01T12:34:56.789Z hostname oxixenon 42 module [sd] message
+                let mut data = format!(
+                    "[origin software=\"{}\" swVersion=\"{}\"]", APP_NAME, env!("CARGO_PKG_VERSION")
+                );
+                if let Some(enterprise_id) = self.enterprise_id {
+                    data += &format!(
+                        "[{}@{} module=\"{}\" level=\"{}\"]",
+                        APP_NAME, enterprise_id, escape_param (&module), record.level()
+                    );
+                }
+                format!(
+                    "<{}>1 {} {} {} {} {} {} {}",
+                    priority,
+                    chrono::Utc::now().to_rfc3339_opts (chrono::SecondsFormat::Millis, true),
+                    header_field (self.hostname.as_deref().unwrap_or ("-"), 255),
+                    APP_NAME, self.pid,
+                    header_field (&module, 32),
+                    data,
+                    Correlated(record.args())
+                )
+            }
+        }
+    }
+}
+
+// Maps a log level to a syslog severity.
+fn severity (level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn  => 4,
+        log::Level::Info  => 6,
+        log::Level::Debug | log::Level::Trace => 7
+    }
+}
+
+// Header fields of RFC 5424 messages must be non-empty printable ASCII strings without spaces.
+fn header_field (value: &str, max_len: usize) -> String {
+    let value: String = value.chars()
+        .filter (|c| c.is_ascii_graphic())
+        .take (max_len)
+        .collect();
+    if value.is_empty() { "-".into() } else { value }
+}
+
+// Escapes the characters which can't appear verbatim in structured data parameter values.
+fn escape_param (value: &str) -> String {
+    let mut escaped = String::with_capacity (value.len());
+    for c in value.chars() {
+        if c == '"' || c == '\\' || c == ']' {
+            escaped.push ('\\');
+        }
+        escaped.push (c);
+    }
+    escaped
+}
+
+// Where messages are sent.
+enum Transport {
+    UnixDatagram(UnixDatagram),
+    UnixStream(UnixStream),
+    Udp(UdpSocket),
+    Tcp(TcpStream),
+    #[cfg(feature = "syslog-tls")]
+    Tls(Box<openssl::ssl::SslStream<TcpStream>>)
+}
+
+impl Transport {
+    fn connect (config: &SyslogConfig) -> Result<Self> {
+        let server_addr = || config.server_addr.as_ref()
+            .chain_err (|| "this syslog protocol requires a server addr");
+        Ok(match config.protocol {
+            SyslogProtocol::Unix => match config.unix_socket_path {
+                Some(ref path) => Self::connect_unix (path)?,
+                None => Self::connect_unix ("/dev/log")
+                    .or_else (|_| Self::connect_unix ("/var/run/syslog"))?
+            },
+            SyslogProtocol::Udp => {
+                let socket = UdpSocket::bind (
+                    config.local_addr.as_ref()
+                        .chain_err (|| "syslog UDP protocol requires a local addr")?
+                ).chain_err (|| "can't bind the syslog UDP socket")?;
+                socket.connect (server_addr()?)
+                    .chain_err (|| "can't connect to the syslog server")?;
+                Transport::Udp(socket)
+            },
+            SyslogProtocol::Tcp => Transport::Tcp(
+                TcpStream::connect (server_addr()?)
+                    .chain_err (|| "can't connect to the syslog server")?
+            ),
+            #[cfg(feature = "syslog-tls")]
+            SyslogProtocol::Tls => Self::connect_tls (config, server_addr()?)?,
+            #[cfg(not(feature = "syslog-tls"))]
+            SyslogProtocol::Tls =>
+                bail!("syslog TLS protocol requires oxixenon to be compiled with 'syslog-tls'")
+        })
+    }
+
+    fn connect_unix (path: &str) -> Result<Self> {
+        let socket = UnixDatagram::unbound().chain_err (|| "can't create a UNIX socket")?;
+        match socket.connect (path) {
+            Ok(()) => Ok(Transport::UnixDatagram(socket)),
+            // Some syslog daemons listen on a stream socket instead.
+            Err(_) => UnixStream::connect (path)
+                .map (Transport::UnixStream)
+                .chain_err (|| format!("can't connect to syslog socket '{}'", path))
+        }
+    }
+
+    #[cfg(feature = "syslog-tls")]
+    fn connect_tls (config: &SyslogConfig, server_addr: &str) -> Result<Self> {
+        use openssl::ssl::{SslConnector, SslFiletype, SslMethod};
+        let mut connector = SslConnector::builder (SslMethod::tls_client())
+            .chain_err (|| "can't initialize TLS")?;
+        if let Some(ref ca_file) = config.tls_ca_file {
+            connector.set_ca_file (ca_file)
+                .chain_err (|| format!("can't load the certificates in '{}'", ca_file))?;
+        }
+        if let Some(ref cert_file) = config.tls_cert_file {
+            let key_file = config.tls_key_file.as_ref()
+                .chain_err (|| "'tls_cert_file' requires 'tls_key_file'")?;
+            connector.set_certificate_chain_file (cert_file)
+                .chain_err (|| format!("can't load the certificate in '{}'", cert_file))?;
+            connector.set_private_key_file (key_file, SslFiletype::PEM)
+                .chain_err (|| format!("can't load the private key in '{}'", key_file))?;
+        }
+        let server_name = match config.tls_server_name {
+            Some(ref name) => name.as_str(),
+            // Strip the port and the brackets around IPv6 addresses.
+            None => server_addr.rsplit_once (':').map_or (server_addr, |(host, _)| host)
+                .trim_start_matches ('[').trim_end_matches (']')
+        };
+        let stream = TcpStream::connect (server_addr)
+            .chain_err (|| "can't connect to the syslog server")?;
+        let stream = connector.build().connect (server_name, stream)
+            .chain_err (|| "TLS handshake with the syslog server failed")?;
+        Ok(Transport::Tls(Box::new (stream)))
+    }
+
+    fn send (&mut self, message: &str) -> io::Result<()> {
+        match *self {
+            Transport::UnixDatagram(ref socket) => socket.send (message.as_bytes()).map (|_| ()),
+            Transport::UnixStream(ref mut stream) => {
+                stream.write_all (message.as_bytes())?;
+                stream.write_all (&[0])
+            },
+            Transport::Udp(ref socket) => socket.send (message.as_bytes()).map (|_| ()),
+            Transport::Tcp(ref mut stream) =>
+                stream.write_all (format!("{} {}", message.len(), message).as_bytes()),
+            #[cfg(feature = "syslog-tls")]
+            Transport::Tls(ref mut stream) =>
+                stream.write_all (format!("{} {}", message.len(), message).as_bytes())
+        }
+    }
+}
+
+// A logger which sends messages to syslog.
+struct SyslogLogger {
+    formatter: Formatter,
+    config: SyslogConfig,
+    // `None` after a failure, until the connection is established again.
+    transport: Mutex<Option<Transport>>
+}
+
+impl log::Log for SyslogLogger {
+    fn enabled (&self, _: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log (&self, record: &log::Record) {
+        let message = self.formatter.format (record);
+        let mut transport = self.transport.lock().unwrap_or_else (|e| e.into_inner());
+        // Stream connections to remote collectors can drop: reconnect once before giving up.
+        for _ in 0..2 {
+            if transport.is_none() {
+                *transport = Transport::connect (&self.config).ok();
+            }
+            match transport.as_mut().map (|transport| transport.send (&message)) {
+                Some(Ok(())) => return,
+                Some(Err(_)) => *transport = None,
+                None => break
+            }
+        }
+        eprintln!("can't send message to syslog: {}", message);
+    }
+
+    fn flush (&self) {}
+}
+
+pub(super) struct Backend;
+
+impl LogBackend for Backend {
+    fn build (&self, config: &Section, _: LevelFilter)
+        -> Result<(Box<dyn log::Log>, Vec<String>)>
+    {
+        let (config, warnings) = config.parse_with_warnings::<SyslogConfig>()
+            .chain_err (|| "the logging backend 'syslog' is not configured correctly")?;
+        let transport = Transport::connect (&config)
+            .chain_err (|| "syslog initialization error")?;
+        let logger = SyslogLogger {
+            formatter: Formatter {
+                format: config.format,
+                hostname: config.hostname.clone(),
+                enterprise_id: config.enterprise_id,
+                pid: process::id()
+            },
+            config,
+            transport: Mutex::new (Some (transport))
+        };
+        Ok((Box::new (logger), warnings))
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, gen: &mut SchemaGenerator) -> Schema {
+        crate::config::schema::section::<SyslogConfig> (gen, &[])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_rfc5424_messages() {
+        let formatter = Formatter {
+            format: SyslogFormat::Rfc5424,
+            hostname: None,
+            enterprise_id: Some (32473),
+            pid: 42
+        };
+        let message = formatter.format (
+            &log::Record::builder()
+                .args (format_args!("renewed"))
+                .level (log::Level::Warn)
+                .target ("oxixenon::renewer \"x\"")
+                .build()
+        );
+        assert!(message.starts_with ("<28>1 "));
+        assert!(message.ends_with (&format!(
+            " - oxixenon 42 renewer\"x\" [origin software=\"oxixenon\" swVersion=\"{}\"]\
+             [oxixenon@32473 module=\"renewer \\\"x\\\"\" level=\"WARN\"] renewed",
+            env!("CARGO_PKG_VERSION")
+        )));
+    }
+}
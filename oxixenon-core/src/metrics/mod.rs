@@ -0,0 +1,132 @@
+//! Metrics reported by the server, the renewers and the notifiers: counters, gauges and timers,
+//! sent to the exporters enabled in `metrics.exporters` (e.g. `statsd`). Without exporters,
+//! reporting a metric does nothing.
+
+mod statsd;
+
+use crate::config::{MetricsConfig, Section};
+use crate::errors::*;
+use std::collections::BTreeMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+
+/// Where metrics are reported to, built by a `MetricsExporter`. Names are dot-separated, e.g.
+/// "server.requests".
+pub trait Metrics: Send + Sync {
+    /// Adds `value` to the counter `name`.
+    fn counter (&self, name: &str, value: u64);
+    /// Sets the gauge `name` to `value`.
+    fn gauge (&self, name: &str, value: f64);
+    /// Records that the operation `name` took `duration`.
+    fn timer (&self, name: &str, duration: Duration);
+}
+
+/// An exporter of metrics, enabled by listing its name in `metrics.exporters`.
+///
+/// Besides the built-in exporters, custom ones can be added with `register_exporter`.
+pub trait MetricsExporter: Send + Sync {
+    /// Builds the exporter described by `config` (the section named after the exporter in
+    /// `[metrics]`, which may be empty), along with any warning about its configuration.
+    fn build (&self, config: &Section) -> Result<(Box<dyn Metrics>, Vec<String>)>;
+
+    /// Returns the schema of the configuration of the exporter, see `config::schema`.
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, _gen: &mut SchemaGenerator) -> Schema {
+        Schema::Bool (true)
+    }
+}
+
+// Exporters added by `register_exporter`, by name.
+static EXPORTERS: RwLock<BTreeMap<String, Arc<dyn MetricsExporter>>> =
+    RwLock::new (BTreeMap::new());
+
+/// Adds a custom exporter, which can be enabled by listing `name` in `metrics.exporters` and
+/// configured in `[metrics.<name>]`. Replaces any exporter with the same name, including the
+/// built-in ones. Must be called before `init` to be used by the initial configuration.
+pub fn register_exporter<E: MetricsExporter + 'static> (name: &str, exporter: E) {
+    EXPORTERS.write().unwrap_or_else (|e| e.into_inner())
+        .insert (name.to_string(), Arc::new (exporter));
+}
+
+// The exporters which are always available.
+fn builtin_exporters() -> Vec<(&'static str, Arc<dyn MetricsExporter>)> {
+    vec![("statsd", Arc::new (statsd::Exporter))]
+}
+
+// Looks up an exporter by name, preferring the registered ones.
+fn find_exporter (name: &str) -> Option<Arc<dyn MetricsExporter>> {
+    if let Some(exporter) = EXPORTERS.read().unwrap_or_else (|e| e.into_inner()).get (name) {
+        return Some (exporter.clone());
+    }
+    builtin_exporters().into_iter()
+        .find (|&(builtin, _)| builtin == name)
+        .map (|(_, exporter)| exporter)
+}
+
+/// Returns the schema of the configuration of the exporters, see `config::schema`.
+#[cfg(feature = "config-schema")]
+pub fn config_schema (gen: &mut SchemaGenerator) -> Schema {
+    let mut exporters: BTreeMap<String, Arc<dyn MetricsExporter>> = builtin_exporters()
+        .into_iter()
+        .map (|(name, exporter)| (name.to_string(), exporter))
+        .collect();
+    exporters.extend (EXPORTERS.read().unwrap_or_else (|e| e.into_inner()).clone());
+    let sections = exporters.iter()
+        .map (|(name, exporter)| (name.as_str(), exporter.config_schema (gen)))
+        .collect();
+    crate::config::schema::sections (sections)
+}
+
+// The exporters in use, see `init`.
+static METRICS: RwLock<Vec<Box<dyn Metrics>>> = RwLock::new (Vec::new());
+
+/// Replaces the exporters in use with the ones described by the configuration. If the new
+/// configuration is invalid, the current exporters are kept.
+pub fn init (config: &MetricsConfig) -> Result<()> {
+    let mut exporters = Vec::new();
+    for exporter_config in &config.exporters {
+        let exporter = find_exporter (&exporter_config.name).chain_err (|| format!(
+            "unknown metrics exporter '{}', if it exists, make sure it is enabled",
+            exporter_config.name
+        ))?;
+        let (metrics, warnings) = exporter.build (&exporter_config.config)?;
+        for warning in warnings {
+            warn!(target: "config", "{}", warning);
+        }
+        exporters.push (metrics);
+    }
+    *METRICS.write().unwrap_or_else (|e| e.into_inner()) = exporters;
+    Ok(())
+}
+
+// Reports to every exporter in use.
+fn report (metric: impl Fn(&dyn Metrics)) {
+    for metrics in METRICS.read().unwrap_or_else (|e| e.into_inner()).iter() {
+        metric (metrics.as_ref());
+    }
+}
+
+/// Adds `value` to the counter `name`.
+pub fn counter (name: &str, value: u64) {
+    report (|metrics| metrics.counter (name, value));
+}
+
+/// Sets the gauge `name` to `value`.
+pub fn gauge (name: &str, value: f64) {
+    report (|metrics| metrics.gauge (name, value));
+}
+
+/// Records that the operation `name` took `duration`.
+pub fn timer (name: &str, duration: Duration) {
+    report (|metrics| metrics.timer (name, duration));
+}
+
+/// Runs `operation`, recording how long it took as the timer `name`.
+pub fn time<T> (name: &str, operation: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let result = operation();
+    timer (name, started.elapsed());
+    result
+}
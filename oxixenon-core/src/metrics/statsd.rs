@@ -0,0 +1,119 @@
+//! The `statsd` exporter, which sends metrics to a statsd server over UDP.
+
+use super::{Metrics, MetricsExporter};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+use crate::config::Section;
+use crate::errors::*;
+use serde::Deserialize;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs, UdpSocket};
+use std::time::Duration;
+
+// Configuration of the `statsd` exporter, in `metrics.statsd`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct StatsdConfig {
+    /// The address and port of the statsd server, e.g. "127.0.0.1:8125".
+    address: String,
+    /// Prepended to the name of every metric, followed by a dot. Defaults to "oxixenon".
+    #[serde(default = "default_prefix")]
+    prefix: String
+}
+
+fn default_prefix() -> String {
+    "oxixenon".into()
+}
+
+pub struct Exporter;
+
+impl MetricsExporter for Exporter {
+    fn build (&self, config: &Section) -> Result<(Box<dyn Metrics>, Vec<String>)> {
+        let (config, warnings) = config.parse_with_warnings::<StatsdConfig>()
+            .chain_err (|| "the metrics exporter 'statsd' is not configured correctly")?;
+        let address = config.address.to_socket_addrs()
+            .chain_err (|| "failed to parse 'metrics.statsd.address' as a socket address")?
+            .next()
+            .chain_err (|| "failed to resolve 'metrics.statsd.address'")?;
+        let bind_addr: SocketAddr = match address {
+            SocketAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+            SocketAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into()
+        };
+        let socket = UdpSocket::bind (bind_addr)
+            .chain_err (|| format!("failed to bind to {}", bind_addr))?;
+        socket.connect (address)
+            .chain_err (|| format!("failed to connect to the statsd server {}", address))?;
+        Ok((Box::new (Statsd { socket, prefix: config.prefix }), warnings))
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (&self, gen: &mut SchemaGenerator) -> Schema {
+        crate::config::schema::section::<StatsdConfig> (gen, &[])
+    }
+}
+
+struct Statsd {
+    socket: UdpSocket,
+    prefix: String
+}
+
+impl Statsd {
+    // Sends a metric such as "oxixenon.server.requests:1|c". Metrics which can't be sent are
+    // lost, as statsd doesn't expect them to be delivered anyway.
+    fn send (&self, name: &str, value: impl fmt::Display, kind: &str) {
+        let line = if self.prefix.is_empty() {
+            format!("{}:{}|{}", name, value, kind)
+        } else {
+            format!("{}.{}:{}|{}", self.prefix, name, value, kind)
+        };
+        let _ = self.socket.send (line.as_bytes());
+    }
+}
+
+impl Metrics for Statsd {
+    fn counter (&self, name: &str, value: u64) {
+        self.send (name, value, "c");
+    }
+
+    fn gauge (&self, name: &str, value: f64) {
+        self.send (name, value, "g");
+    }
+
+    fn timer (&self, name: &str, duration: Duration) {
+        self.send (name, duration.as_millis(), "ms");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SourceMap;
+    use std::sync::Arc;
+
+    #[test]
+    fn sends_metrics_over_udp() {
+        let server = UdpSocket::bind ("127.0.0.1:0").unwrap();
+        server.set_read_timeout (Some (Duration::from_secs (5))).unwrap();
+        let source = format!("address = '{}'\nprefix = 'xenon'\n", server.local_addr().unwrap());
+        let map = Arc::new (SourceMap::new (&source));
+        let config = Section::new ("metrics.statsd".into(), Some (source.parse().unwrap()), map);
+        let (metrics, warnings) = Exporter.build (&config).unwrap();
+        assert!(warnings.is_empty());
+
+        metrics.counter ("server.requests", 1);
+        metrics.gauge ("server.available", 0.5);
+        metrics.timer ("renewer.duration", Duration::from_millis (1500));
+        let mut buf = [0; 64];
+        let lines: Vec<String> = (0..3)
+            .map (|_| {
+                let length = server.recv (&mut buf).unwrap();
+                String::from_utf8_lossy (&buf[..length]).into_owned()
+            })
+            .collect();
+        assert_eq!(lines, vec![
+            "xenon.server.requests:1|c",
+            "xenon.server.available:0.5|g",
+            "xenon.renewer.duration:1500|ms"
+        ]);
+    }
+}
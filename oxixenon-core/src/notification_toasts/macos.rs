@@ -0,0 +1,78 @@
+//! Support for notifications on macOS for events, posted to the Notification Center by
+//! `osascript`.
+use std::process::Command;
+use std::result;
+use super::Error as NotificationError;
+use super::{Toast, ToastSound};
+
+#[derive(Default)]
+pub struct NotificationToasts;
+
+impl NotificationToasts {
+    pub fn new() -> NotificationToasts {
+        NotificationToasts
+    }
+
+    pub fn send_toast (&self, toast: &Toast) -> result::Result<(), NotificationError> {
+        let output = Command::new ("osascript")
+            .arg ("-e")
+            .arg (script (toast))
+            .output()
+            .map_err (|e| NotificationError(format!("can't run osascript: {}", e)))?;
+        if !output.status.success() {
+            return Err(NotificationError(format!(
+                "osascript failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy (&output.stderr).trim()
+            )))
+        }
+        Ok(())
+    }
+}
+
+// The AppleScript which shows `toast`. Notifications are shown with the title of the toast as
+// their title, and with its body (if any) as their text. `display notification` can't replace
+// previous notifications and has no scenarios or durations, so they are ignored. Without a
+// sound name, notifications are shown with the behavior configured in System Settings, which is
+// all "default" and "silent" can ask for.
+fn script (toast: &Toast) -> String {
+    let (text, title) = if toast.body.is_empty() {
+        (&toast.title, None)
+    } else {
+        (&toast.body, Some (&toast.title))
+    };
+    let mut script = format!("display notification {}", quote (text));
+    if let Some(title) = title {
+        script += &format!(" with title {}", quote (title));
+    }
+    if toast.sound == ToastSound::Alarm {
+        script += " sound name \"Sosumi\"";
+    }
+    script
+}
+
+// Quotes `value` as an AppleScript string literal.
+fn quote (value: &str) -> String {
+    format!("\"{}\"", value.replace ('\\', "\\\\").replace ('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_toasts() {
+        let toast = Toast {
+            title: "IP renewed".into(),
+            body: r#"Sent by "C:\"#.into(),
+            tag: None,
+            sound: ToastSound::Silent,
+            scenario: Default::default(),
+            duration: Default::default()
+        };
+        assert_eq!(
+            script (&toast),
+            r#"display notification "Sent by \"C:\\" with title "IP renewed""#
+        );
+    }
+}
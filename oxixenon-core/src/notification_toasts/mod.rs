@@ -0,0 +1,45 @@
+use std::{fmt, error};
+
+#[derive(Debug)]
+pub struct Error(String);
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for Error {
+    fn cause(&self) -> Option<&dyn error::Error> {
+        None
+    }
+}
+
+mod template;
+
+pub use self::template::{Toast, ToastDuration, ToastScenario, ToastSound, ToastTemplates};
+
+#[cfg(all(windows, feature = "client-toasts"))]
+mod shortcut;
+#[cfg(all(windows, feature = "client-toasts"))]
+mod win32;
+
+#[cfg(all(windows, feature = "client-toasts"))]
+pub use self::win32::*;
+
+#[cfg(all(windows, feature = "client-tray"))]
+pub mod tray;
+
+#[cfg(all(target_os = "macos", feature = "client-notifications-macos"))]
+mod macos;
+
+#[cfg(all(target_os = "macos", feature = "client-notifications-macos"))]
+pub use self::macos::*;
+
+// This ensures that there's no possibility at all to compile oxixenon with notification_toasts
+// enabled on an unsupported platform.
+#[cfg(any(
+    all(not(windows), feature = "client-toasts"),
+    all(not(target_os = "macos"), feature = "client-notifications-macos")
+))]
+pub use unsupported_platform;
@@ -0,0 +1,126 @@
+//! Creation of Start Menu shortcuts with an AppUserModelId, which Windows requires to show the
+//! toasts of desktop applications.
+#![allow(non_snake_case)]
+
+use std::{io, mem, ptr};
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+use winapi::{Class, Interface};
+use winapi::ctypes::{c_int, c_void};
+use winapi::shared::minwindef::{BOOL, DWORD, TRUE, WORD};
+use winapi::shared::winerror::{HRESULT, SUCCEEDED};
+use winapi::shared::wtypes::VT_LPWSTR;
+use winapi::shared::wtypesbase::{CLSCTX_INPROC_SERVER, LPCOLESTR, LPOLESTR};
+use winapi::um::combaseapi::{CoCreateInstance, CoInitializeEx, CoUninitialize};
+use winapi::um::objbase::COINIT_APARTMENTTHREADED;
+use winapi::um::objidl::{IPersist, IPersistVtbl};
+use winapi::um::propidl::PROPVARIANT;
+use winapi::um::propkey::PKEY_AppUserModel_ID;
+use winapi::um::propsys::IPropertyStore;
+use winapi::um::shobjidl_core::ShellLink;
+use winapi::um::unknwnbase::{IUnknown, IUnknownVtbl};
+use winapi::um::winnt::{LPCWSTR, LPWSTR};
+
+// winapi only defines the class of shell links, not their interfaces. Pointers to structures
+// which aren't used here are declared as `c_void`.
+RIDL!{#[uuid(0x000214f9, 0x0000, 0x0000, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46)]
+interface IShellLinkW(IShellLinkWVtbl): IUnknown(IUnknownVtbl) {
+    fn GetPath(pszFile: LPWSTR, cch: c_int, pfd: *mut c_void, fFlags: DWORD,) -> HRESULT,
+    fn GetIDList(ppidl: *mut *mut c_void,) -> HRESULT,
+    fn SetIDList(pidl: *const c_void,) -> HRESULT,
+    fn GetDescription(pszName: LPWSTR, cch: c_int,) -> HRESULT,
+    fn SetDescription(pszName: LPCWSTR,) -> HRESULT,
+    fn GetWorkingDirectory(pszDir: LPWSTR, cch: c_int,) -> HRESULT,
+    fn SetWorkingDirectory(pszDir: LPCWSTR,) -> HRESULT,
+    fn GetArguments(pszArgs: LPWSTR, cch: c_int,) -> HRESULT,
+    fn SetArguments(pszArgs: LPCWSTR,) -> HRESULT,
+    fn GetHotkey(pwHotkey: *mut WORD,) -> HRESULT,
+    fn SetHotkey(wHotkey: WORD,) -> HRESULT,
+    fn GetShowCmd(piShowCmd: *mut c_int,) -> HRESULT,
+    fn SetShowCmd(iShowCmd: c_int,) -> HRESULT,
+    fn GetIconLocation(pszIconPath: LPWSTR, cch: c_int, piIcon: *mut c_int,) -> HRESULT,
+    fn SetIconLocation(pszIconPath: LPCWSTR, iIcon: c_int,) -> HRESULT,
+    fn SetRelativePath(pszPathRel: LPCWSTR, dwReserved: DWORD,) -> HRESULT,
+    fn Resolve(hwnd: *mut c_void, fFlags: DWORD,) -> HRESULT,
+    fn SetPath(pszFile: LPCWSTR,) -> HRESULT,
+}}
+
+RIDL!{#[uuid(0x0000010b, 0x0000, 0x0000, 0xc0, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x46)]
+interface IPersistFile(IPersistFileVtbl): IPersist(IPersistVtbl) {
+    fn IsDirty() -> HRESULT,
+    fn Load(pszFileName: LPCOLESTR, dwMode: DWORD,) -> HRESULT,
+    fn Save(pszFileName: LPCOLESTR, fRemember: BOOL,) -> HRESULT,
+    fn SaveCompleted(pszFileName: LPCOLESTR,) -> HRESULT,
+    fn GetCurFile(ppszFileName: *mut LPOLESTR,) -> HRESULT,
+}}
+
+// An owned reference to a COM object, released when dropped.
+struct ComPtr<T: Interface>(*mut T);
+
+impl<T: Interface> ComPtr<T> {
+    unsafe fn query<U: Interface> (&self) -> io::Result<ComPtr<U>> {
+        let mut object: *mut c_void = ptr::null_mut();
+        check ((*(self.0 as *mut IUnknown)).QueryInterface (&U::uuidof(), &mut object))?;
+        Ok(ComPtr(object as *mut U))
+    }
+}
+
+impl<T: Interface> Drop for ComPtr<T> {
+    fn drop (&mut self) {
+        unsafe { (*(self.0 as *mut IUnknown)).Release(); }
+    }
+}
+
+fn check (result: HRESULT) -> io::Result<()> {
+    if SUCCEEDED(result) {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error (result))
+    }
+}
+
+// A NUL-terminated UTF-16 string.
+pub(super) fn wide<T: AsRef<OsStr>> (value: T) -> Vec<u16> {
+    value.as_ref().encode_wide().chain (Some (0)).collect()
+}
+
+/// Creates (or replaces) the shortcut `path`, which starts `target` and has the specified
+/// AppUserModelId.
+pub fn create (path: &Path, target: &Path, app_user_model_id: &str) -> io::Result<()> {
+    unsafe {
+        // COM may already be initialized by this thread, possibly with another concurrency model.
+        let initialized = SUCCEEDED(CoInitializeEx (ptr::null_mut(), COINIT_APARTMENTTHREADED));
+        let result = create_impl (path, target, app_user_model_id);
+        if initialized {
+            CoUninitialize();
+        }
+        result
+    }
+}
+
+unsafe fn create_impl (path: &Path, target: &Path, app_user_model_id: &str) -> io::Result<()> {
+    let mut link: *mut c_void = ptr::null_mut();
+    check (CoCreateInstance (
+        &ShellLink::uuidof(),
+        ptr::null_mut(),
+        CLSCTX_INPROC_SERVER,
+        &IShellLinkW::uuidof(),
+        &mut link
+    ))?;
+    let link = ComPtr(link as *mut IShellLinkW);
+    check ((*link.0).SetPath (wide (target).as_ptr()))?;
+    if let Some(directory) = target.parent() {
+        check ((*link.0).SetWorkingDirectory (wide (directory).as_ptr()))?;
+    }
+    // The property store copies the value, which can thus stay owned by us.
+    let mut id = wide (app_user_model_id);
+    let mut value: PROPVARIANT = mem::zeroed();
+    value.vt = VT_LPWSTR as u16;
+    *value.data.pwszVal_mut() = id.as_mut_ptr();
+    let store = link.query::<IPropertyStore>()?;
+    check ((*store.0).SetValue (&PKEY_AppUserModel_ID, &value))?;
+    check ((*store.0).Commit())?;
+    let file = link.query::<IPersistFile>()?;
+    check ((*file.0).Save (wide (path).as_ptr(), TRUE))
+}
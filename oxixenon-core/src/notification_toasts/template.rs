@@ -0,0 +1,351 @@
+//! Templates of the title and of the body of notification toasts, configured in `client.toasts`,
+//! along with their sound, scenario and duration, and grouping of the toasts of repeated events.
+
+use crate::logging;
+use crate::protocol::{Event, EventPayload};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+
+const PLACEHOLDERS: &[&str] = &[
+    "event", "description", "source", "origin", "old_ip", "new_ip", "time", "count", "requested_by"
+];
+
+// Configuration of the toasts, in `client.toasts`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub struct ToastTemplates {
+    /// Template of the title of the toasts, defaults to "{description}". Available placeholders:
+    /// {event}, {description}, {source} (the address which sent the event), {origin} (the host
+    /// name of the server), {old_ip}, {new_ip}, {time} (when the event happened), {count} (how
+    /// many events the toast stands for, see `coalesce_events`) and {requested_by} (the token of
+    /// the client which caused it, see `server.tokens`).
+    #[serde(default = "default_title")]
+    #[cfg_attr(feature = "config-schema", schemars(with = "String"))]
+    title: Template,
+    /// Template of the body of the toasts, defaults to "Request sent by {source}". An empty body
+    /// shows toasts with just the title.
+    #[serde(default = "default_body")]
+    #[cfg_attr(feature = "config-schema", schemars(with = "String"))]
+    body: Template,
+    /// Templates used for specific events, by event name (e.g. "ip_renewed"). They default to
+    /// `title` and `body`.
+    #[serde(default)]
+    events: BTreeMap<Event, EventTemplates>,
+    /// Whether the toast of an event replaces the previous toast of the same event (on platforms
+    /// which support it), rather than stacking. Defaults to true.
+    #[serde(default = "default_replace")]
+    replace: bool,
+    /// Whether the title of the toasts shows how many events of the same kind have been received,
+    /// e.g. "An IP renewal has been requested (3)". Defaults to false.
+    #[serde(default)]
+    show_count: bool,
+    /// The sound played by the toasts: "default", "silent" or "alarm".
+    #[serde(default)]
+    sound: ToastSound,
+    /// The scenario of the toasts: "default", "reminder" and "alarm" stay on screen until they are
+    /// dismissed, "urgent" is shown even when notifications are silenced.
+    #[serde(default)]
+    scenario: ToastScenario,
+    /// How long the toasts stay on screen: "short" (the default) or "long".
+    #[serde(default)]
+    duration: ToastDuration,
+    // How many times each event has been received, by name.
+    #[serde(skip)]
+    counts: RefCell<BTreeMap<&'static str, u32>>
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct EventTemplates {
+    #[cfg_attr(feature = "config-schema", schemars(with = "Option<String>"))]
+    title: Option<Template>,
+    #[cfg_attr(feature = "config-schema", schemars(with = "Option<String>"))]
+    body: Option<Template>,
+    sound: Option<ToastSound>,
+    scenario: Option<ToastScenario>,
+    duration: Option<ToastDuration>
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ToastSound {
+    #[default]
+    Default,
+    Silent,
+    Alarm
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ToastScenario {
+    #[default]
+    Default,
+    Reminder,
+    Alarm,
+    Urgent
+}
+
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum ToastDuration {
+    #[default]
+    Short,
+    Long
+}
+
+fn default_title() -> Template {
+    Template::try_from ("{description}".to_string()).unwrap()
+}
+
+fn default_body() -> Template {
+    Template::try_from ("Request sent by {source}".to_string()).unwrap()
+}
+
+fn default_replace() -> bool {
+    true
+}
+
+impl Default for ToastTemplates {
+    fn default() -> Self {
+        ToastTemplates {
+            title: default_title(),
+            body: default_body(),
+            events: BTreeMap::new(),
+            replace: default_replace(),
+            show_count: false,
+            sound: ToastSound::default(),
+            scenario: ToastScenario::default(),
+            duration: ToastDuration::default(),
+            counts: RefCell::default()
+        }
+    }
+}
+
+/// A rendered toast.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Toast {
+    pub title: String,
+    /// May be empty.
+    pub body: String,
+    /// Toasts with the same tag replace each other, if any.
+    pub tag: Option<&'static str>,
+    pub sound: ToastSound,
+    pub scenario: ToastScenario,
+    pub duration: ToastDuration
+}
+
+impl Toast {
+    /// A toast which isn't about an event, with the default options.
+    pub fn new<T: Into<String>, B: Into<String>> (title: T, body: B) -> Toast {
+        Toast {
+            title: title.into(),
+            body: body.into(),
+            tag: None,
+            sound: ToastSound::default(),
+            scenario: ToastScenario::default(),
+            duration: ToastDuration::default()
+        }
+    }
+}
+
+impl ToastTemplates {
+    /// Renders the toast of `event` (along with its `payload`), sent by `source`. Every call
+    /// counts as a new occurrence of `event`.
+    pub fn render (&self, event: Event, payload: &EventPayload, source: Option<SocketAddr>)
+        -> Toast
+    {
+        self.render_burst (event, payload, source, 1)
+    }
+
+    /// Like `render`, for a toast which stands for `burst` occurrences of `event` coalesced
+    /// together (see `notifier::Coalescer`), the latest one sent by `source`.
+    pub fn render_burst (
+        &self,
+        event: Event,
+        payload: &EventPayload,
+        source: Option<SocketAddr>,
+        burst: u32
+    ) -> Toast {
+        let count = {
+            let mut counts = self.counts.borrow_mut();
+            let count = counts.entry (event.name()).or_insert (0);
+            *count += burst;
+            *count
+        };
+        let unknown = || "unknown".to_string();
+        let value = |placeholder: &str| match placeholder {
+            "event" => event.to_string(),
+            "description" => event.extended_descr().to_string(),
+            "source" => source.map_or_else (unknown, |source| source.to_string()),
+            "origin" => payload.origin.clone().unwrap_or_else (unknown),
+            "old_ip" => payload.outcome.old_ip.map_or_else (unknown, |ip| ip.to_string()),
+            "new_ip" => payload.outcome.new_ip.map_or_else (unknown, |ip| ip.to_string()),
+            "time" => payload.timestamp
+                .map_or_else (unknown, |timestamp| logging::format_time (timestamp, false)),
+            "count" => burst.to_string(),
+            "requested_by" => payload.requested_by.clone().unwrap_or_else (unknown),
+            _ => unreachable!()
+        };
+        // Events carrying data use the templates of their kind.
+        let templates = self.events.iter()
+            .find (|(configured, _)| configured.name() == event.name())
+            .map (|(_, templates)| templates);
+        let mut title = templates.and_then (|t| t.title.as_ref()).unwrap_or (&self.title)
+            .render (&value);
+        if self.show_count && count > 1 {
+            title += &format!(" ({})", count);
+        }
+        Toast {
+            title,
+            body: templates.and_then (|t| t.body.as_ref()).unwrap_or (&self.body)
+                .render (&value),
+            tag: if self.replace { Some (event.name()) } else { None },
+            sound: templates.and_then (|t| t.sound).unwrap_or (self.sound),
+            scenario: templates.and_then (|t| t.scenario).unwrap_or (self.scenario),
+            duration: templates.and_then (|t| t.duration).unwrap_or (self.duration)
+        }
+    }
+}
+
+// A string with `{placeholder}`s. Braces are escaped by doubling them.
+#[derive(Debug)]
+struct Template(Vec<Segment>);
+
+#[derive(Debug)]
+enum Segment {
+    Text(String),
+    Placeholder(&'static str)
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from (template: String) -> Result<Self, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with ('{') => { chars.next(); text.push ('{') },
+                '}' if chars.as_str().starts_with ('}') => { chars.next(); text.push ('}') },
+                '{' => {
+                    let (name, rest) = chars.as_str().split_once ('}')
+                        .ok_or_else (|| format!("unclosed placeholder in \"{}\"", template))?;
+                    let placeholder = PLACEHOLDERS.iter().find (|&&p| p == name)
+                        .ok_or_else (|| format!(
+                            "unknown placeholder '{{{}}}', must be one of {}",
+                            name,
+                            PLACEHOLDERS.iter()
+                                .map (|p| format!("{{{}}}", p))
+                                .collect::<Vec<_>>()
+                                .join (", ")
+                        ))?;
+                    if !text.is_empty() {
+                        segments.push (Segment::Text (std::mem::take (&mut text)));
+                    }
+                    segments.push (Segment::Placeholder (placeholder));
+                    chars = rest.chars();
+                },
+                '}' => return Err (format!("unmatched '}}' in \"{}\", use '}}}}'", template)),
+                _ => text.push (c)
+            }
+        }
+        if !text.is_empty() {
+            segments.push (Segment::Text (text));
+        }
+        Ok(Template(segments))
+    }
+}
+
+impl<'de> Deserialize<'de> for Template {
+    fn deserialize<D: serde::Deserializer<'de>> (deserializer: D) -> Result<Self, D::Error> {
+        Template::try_from (String::deserialize (deserializer)?).map_err (serde::de::Error::custom)
+    }
+}
+
+impl Template {
+    fn render (&self, value: &dyn Fn(&str) -> String) -> String {
+        self.0.iter().map (|segment| match *segment {
+            Segment::Text(ref text) => text.clone(),
+            Segment::Placeholder(placeholder) => value (placeholder)
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::RenewOutcome;
+
+    #[test]
+    fn renders_templates() {
+        let templates: ToastTemplates = toml::from_str (r#"
+            body = "{{{source}}}"
+            duration = "long"
+            [events.ip_renewed]
+            title = "New IP: {new_ip}"
+            sound = "alarm"
+            [events.renewal_failed]
+            title = "{event} on {origin}"
+        "#).unwrap();
+        let source = "10.0.0.2:5454".parse().ok();
+        let payload = EventPayload::default();
+        assert_eq!(templates.render (Event::IPRenewed, &payload, source), Toast {
+            title: "New IP: unknown".into(),
+            body: "{10.0.0.2:5454}".into(),
+            tag: Some ("ip_renewed"),
+            sound: ToastSound::Alarm,
+            scenario: ToastScenario::Default,
+            duration: ToastDuration::Long
+        });
+        let payload = EventPayload {
+            origin: Some ("router".into()),
+            outcome: RenewOutcome { old_ip: None, new_ip: "192.0.2.1".parse().ok() },
+            ..EventPayload::default()
+        };
+        let toast = templates.render (Event::IPRenewed, &payload, None);
+        assert_eq!(toast.title, "New IP: 192.0.2.1");
+        // Events carrying data use the templates of their kind.
+        let toast = templates.render (Event::RenewalFailed ("timeout".into()), &payload, None);
+        assert_eq!(toast.title, "renewal failed: timeout on router");
+        let payload = EventPayload::default();
+        assert_eq!(ToastTemplates::default().render (Event::IPRenewed, &payload, None), Toast {
+            title: "An IP renewal has been requested".into(),
+            body: "Request sent by unknown".into(),
+            tag: Some ("ip_renewed"),
+            sound: ToastSound::Default,
+            scenario: ToastScenario::Default,
+            duration: ToastDuration::Short
+        });
+        for invalid in &["{nope}", "{source", "}"] {
+            assert!(Template::try_from (invalid.to_string()).is_err());
+        }
+    }
+
+    #[test]
+    fn counts_repeated_events() {
+        let templates: ToastTemplates = toml::from_str (r#"
+            title = "{event}"
+            body = "{count} in a row"
+            replace = false
+            show_count = true
+        "#).unwrap();
+        let titles: Vec<_> = (0..3)
+            .map (|_| templates.render (Event::IPRenewed, &EventPayload::default(), None))
+            .map (|toast| (toast.title, toast.tag))
+            .collect();
+        assert_eq!(titles, vec![
+            ("ip renewed".to_string(), None),
+            ("ip renewed (2)".to_string(), None),
+            ("ip renewed (3)".to_string(), None)
+        ]);
+        // Coalesced events count as many occurrences.
+        let toast = templates.render_burst (Event::IPRenewed, &EventPayload::default(), None, 4);
+        assert_eq!((toast.title.as_str(), toast.body.as_str()), ("ip renewed (7)", "4 in a row"));
+    }
+}
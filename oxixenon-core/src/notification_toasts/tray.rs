@@ -0,0 +1,196 @@
+//! The notification area icon of the `tray` client action, with a context menu of commands.
+use std::cell::RefCell;
+use std::{io, mem, ptr, result};
+use winapi::shared::basetsd::UINT_PTR;
+use winapi::shared::minwindef::{LOWORD, LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::{HMENU, HWND, POINT};
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::shellapi::{
+    Shell_NotifyIconW, NOTIFYICONDATAW, NIF_ICON, NIF_MESSAGE, NIF_TIP, NIM_ADD, NIM_DELETE
+};
+use winapi::um::winuser::{
+    AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DestroyWindow,
+    DispatchMessageW, GetCursorPos, GetMessageW, KillTimer, LoadIconW, PostMessageW,
+    PostQuitMessage, RegisterClassW, SetForegroundWindow, SetTimer, TrackPopupMenu,
+    TranslateMessage, IDI_APPLICATION, MF_SEPARATOR, MF_STRING, MSG, TPM_RIGHTBUTTON, WM_APP,
+    WM_COMMAND, WM_LBUTTONUP, WM_NULL, WM_RBUTTONUP, WM_TIMER, WNDCLASSW
+};
+use super::Error as NotificationError;
+use super::shortcut::wide;
+
+/// How long renewals stay unavailable after `TrayCommand::SetUnavailable`.
+pub const UNAVAILABILITY_MS: u32 = 60 * 60 * 1000;
+
+/// The commands of the context menu, handled by the callback passed to `run`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TrayCommand {
+    Renew,
+    /// Renewals should be made unavailable: `TrayCommand::SetAvailable` follows after
+    /// `UNAVAILABILITY_MS`, unless it's chosen earlier from the menu.
+    SetUnavailable,
+    SetAvailable,
+    ShowStatus
+}
+
+// The message sent by the icon to the window when it's clicked.
+const WM_TRAY: UINT = WM_APP + 1;
+// The identifiers of the items of the menu.
+const ID_RENEW: u16 = 1;
+const ID_SET_UNAVAILABLE: u16 = 2;
+const ID_SET_AVAILABLE: u16 = 3;
+const ID_SHOW_STATUS: u16 = 4;
+const ID_QUIT: u16 = 5;
+const AVAILABILITY_TIMER: UINT_PTR = 1;
+
+struct Tray {
+    on_command: Box<dyn FnMut(TrayCommand)>,
+    unavailable: bool
+}
+
+thread_local! {
+    // The state of the icon, used by `window_proc`.
+    static TRAY: RefCell<Option<Tray>> = const { RefCell::new (None) };
+}
+
+fn last_error (action: &str) -> NotificationError {
+    NotificationError(format!("{}: {}", action, io::Error::last_os_error()))
+}
+
+/// Shows the icon with the tooltip `tooltip` and handles its menu until "Quit" is chosen. Commands
+/// are handled by `on_command`, on the calling thread.
+pub fn run<F: FnMut(TrayCommand) + 'static> (tooltip: &str, on_command: F)
+    -> result::Result<(), NotificationError>
+{
+    unsafe {
+        let instance = GetModuleHandleW (ptr::null());
+        let class_name = wide ("OxixenonTray");
+        let class = WNDCLASSW {
+            style: 0,
+            lpfnWndProc: Some (window_proc),
+            cbClsExtra: 0,
+            cbWndExtra: 0,
+            hInstance: instance,
+            hIcon: ptr::null_mut(),
+            hCursor: ptr::null_mut(),
+            hbrBackground: ptr::null_mut(),
+            lpszMenuName: ptr::null(),
+            lpszClassName: class_name.as_ptr()
+        };
+        if RegisterClassW (&class) == 0 {
+            return Err(last_error ("can't register the window class"));
+        }
+        // The window is never shown: it only receives the messages of the icon and of its menu.
+        let window = CreateWindowExW (
+            0, class_name.as_ptr(), class_name.as_ptr(), 0, 0, 0, 0, 0,
+            ptr::null_mut(), ptr::null_mut(), instance, ptr::null_mut()
+        );
+        if window.is_null() {
+            return Err(last_error ("can't create the window"));
+        }
+        let mut icon: NOTIFYICONDATAW = mem::zeroed();
+        icon.cbSize = mem::size_of::<NOTIFYICONDATAW>() as u32;
+        icon.hWnd = window;
+        icon.uID = 1;
+        icon.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+        icon.uCallbackMessage = WM_TRAY;
+        icon.hIcon = LoadIconW (ptr::null_mut(), IDI_APPLICATION);
+        // The tooltip is truncated to fit, keeping the trailing NUL. The structure is packed on
+        // 32-bit platforms, so its fields can't be borrowed.
+        let mut tip = [0; 128];
+        let tooltip = wide (tooltip);
+        let length = tooltip.len().min (tip.len()) - 1;
+        tip[..length].copy_from_slice (&tooltip[..length]);
+        icon.szTip = tip;
+        if Shell_NotifyIconW (NIM_ADD, &mut icon) == 0 {
+            DestroyWindow (window);
+            return Err(last_error ("can't add the icon to the notification area"));
+        }
+        TRAY.with (|tray| *tray.borrow_mut() = Some (Tray {
+            on_command: Box::new (on_command),
+            unavailable: false
+        }));
+        let mut message: MSG = mem::zeroed();
+        while GetMessageW (&mut message, ptr::null_mut(), 0, 0) > 0 {
+            TranslateMessage (&message);
+            DispatchMessageW (&message);
+        }
+        Shell_NotifyIconW (NIM_DELETE, &mut icon);
+        DestroyWindow (window);
+        TRAY.with (|tray| tray.borrow_mut().take());
+        Ok(())
+    }
+}
+
+// Runs the callback with `command`, after updating the availability shown by the menu.
+fn dispatch (command: TrayCommand) {
+    TRAY.with (|tray| if let Some(ref mut tray) = *tray.borrow_mut() {
+        match command {
+            TrayCommand::SetUnavailable => tray.unavailable = true,
+            TrayCommand::SetAvailable => tray.unavailable = false,
+            _ => ()
+        }
+        (tray.on_command) (command)
+    })
+}
+
+unsafe fn append_item (menu: HMENU, id: u16, label: &str) {
+    AppendMenuW (menu, MF_STRING, id as UINT_PTR, wide (label).as_ptr());
+}
+
+unsafe fn show_menu (window: HWND) {
+    let unavailable = TRAY.with (|tray| tray.borrow().as_ref().is_some_and (|t| t.unavailable));
+    let menu = CreatePopupMenu();
+    append_item (menu, ID_RENEW, "Renew now");
+    if unavailable {
+        append_item (menu, ID_SET_AVAILABLE, "Set available");
+    } else {
+        append_item (menu, ID_SET_UNAVAILABLE, "Set unavailable for 1 hour");
+    }
+    append_item (menu, ID_SHOW_STATUS, "Show status");
+    AppendMenuW (menu, MF_SEPARATOR, 0, ptr::null());
+    append_item (menu, ID_QUIT, "Quit");
+    let mut cursor: POINT = mem::zeroed();
+    GetCursorPos (&mut cursor);
+    // Without these, the menu isn't dismissed when clicking elsewhere.
+    SetForegroundWindow (window);
+    TrackPopupMenu (menu, TPM_RIGHTBUTTON, cursor.x, cursor.y, 0, window, ptr::null());
+    PostMessageW (window, WM_NULL, 0, 0);
+    DestroyMenu (menu);
+}
+
+unsafe extern "system" fn window_proc (
+    window: HWND, message: UINT, wparam: WPARAM, lparam: LPARAM
+) -> LRESULT {
+    match message {
+        WM_TRAY => {
+            let mouse_message = lparam as UINT;
+            if mouse_message == WM_RBUTTONUP || mouse_message == WM_LBUTTONUP {
+                show_menu (window);
+            }
+            0
+        },
+        WM_COMMAND => {
+            match LOWORD (wparam as u32) {
+                ID_RENEW => dispatch (TrayCommand::Renew),
+                ID_SET_UNAVAILABLE => {
+                    SetTimer (window, AVAILABILITY_TIMER, UNAVAILABILITY_MS, None);
+                    dispatch (TrayCommand::SetUnavailable)
+                },
+                ID_SET_AVAILABLE => {
+                    KillTimer (window, AVAILABILITY_TIMER);
+                    dispatch (TrayCommand::SetAvailable)
+                },
+                ID_SHOW_STATUS => dispatch (TrayCommand::ShowStatus),
+                ID_QUIT => PostQuitMessage (0),
+                _ => ()
+            }
+            0
+        },
+        WM_TIMER if wparam == AVAILABILITY_TIMER => {
+            KillTimer (window, AVAILABILITY_TIMER);
+            dispatch (TrayCommand::SetAvailable);
+            0
+        },
+        _ => DefWindowProcW (window, message, wparam, lparam)
+    }
+}
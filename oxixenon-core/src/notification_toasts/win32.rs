@@ -1,8 +1,8 @@
 //! Support for notification toasts on win32 for events.
-extern crate winrt;
 
-use std::{result, env, path, ffi};
+use std::{result, env, fs, path, ffi};
 use super::Error as NotificationError;
+use super::{shortcut, Toast, ToastDuration, ToastScenario, ToastSound};
 use winrt::*;
 use winrt::windows::data::xml::dom::*;
 use winrt::windows::ui::notifications::*;
@@ -10,19 +10,53 @@ use winrt::windows::ui::notifications::*;
 const ICON_FILE_NAME: &str = "oxixenon.png";
 const SHORTCUT_NAME: &str = "Xenon.lnk";
 const APP_USER_MODEL_ID: &str = "RobertoFrenna.Xenon";
+const TOAST_GROUP: &str = "events";
+const ICON: &[u8] = include_bytes!("../../../oxixenon.png");
+
+// Where the shortcut required by toasts is expected to be.
+fn shortcut_path() -> path::PathBuf {
+    let app_data = match env::var ("APPDATA") {
+        Ok(val) => val,
+        Err(e) => panic!("Can't retrieve APPDATA: {}", e)
+    };
+    let mut path = path::PathBuf::from(app_data);
+    path.push (r"Microsoft\Windows\Start Menu\Programs");
+    path.push (SHORTCUT_NAME);
+    path
+}
+
+/// What `setup` did.
+pub struct Setup {
+    pub shortcut: path::PathBuf,
+    /// The icon written next to the binary, if it wasn't there already.
+    pub icon: Option<path::PathBuf>
+}
+
+/// Creates the Start Menu shortcut required by toasts (replacing any existing one), and writes
+/// the icon of the toasts next to the binary if it's missing.
+pub fn setup() -> result::Result<Setup, NotificationError> {
+    let binary = env::current_exe().map_err (|e|
+        NotificationError(format!("can't find the path of the binary: {}", e)))?;
+    let shortcut = shortcut_path();
+    shortcut::create (&shortcut, &binary, APP_USER_MODEL_ID).map_err (|e| NotificationError(
+        format!("can't create the shortcut '{}': {}", shortcut.to_string_lossy(), e)))?;
+    let icon = binary.with_file_name (ICON_FILE_NAME);
+    let icon = if icon.exists() {
+        None
+    } else {
+        fs::write (&icon, ICON).map_err (|e| NotificationError(
+            format!("can't write the icon '{}': {}", icon.to_string_lossy(), e)))?;
+        Some(icon)
+    };
+    Ok(Setup { shortcut, icon })
+}
 
 pub struct NotificationToasts(Option<RuntimeContext>);
 
 impl NotificationToasts {
     pub fn new() -> NotificationToasts {
         // Check if the shortcut to make toast notifications work has been installed or not.
-        let app_data = match env::var ("APPDATA") {
-            Ok(val) => val,
-            Err(e) => panic!("Can't retrieve APPDATA: {}", e)
-        };
-        let mut path = path::PathBuf::from(app_data);
-        path.push (r"Microsoft\Windows\Start Menu\Programs");
-        path.push (SHORTCUT_NAME);
+        let path = shortcut_path();
         if !path.exists() {
             warn!("notification toasts are not configured properly");
             warn!(
@@ -30,13 +64,13 @@ impl NotificationToasts {
                 path.to_string_lossy(),
                 APP_USER_MODEL_ID
             );
-            warn!("Please read https://git.io/fNyEC for further information.");
+            warn!("Run `oxixenon setup-toasts` to create it.");
         }
         NotificationToasts(Some(RuntimeContext::init()))
     }
 
-    pub fn send_toast (&self, message: &str) -> result::Result<(), NotificationError> {
-        if let Err(err) = self.send_toast_impl (message) {
+    pub fn send_toast (&self, toast: &Toast) -> result::Result<(), NotificationError> {
+        if let Err(err) = self.send_toast_impl (toast) {
             return Err(NotificationError(format!("WinRT/WinAPI error: {:?}", err)))
         }
         Ok(())
@@ -67,7 +101,7 @@ impl NotificationToasts {
         None
     }
 
-    fn send_toast_impl (&self, message: &str) -> Result<()> {
+    fn send_toast_impl (&self, toast: &Toast) -> Result<()> {
         macro_rules! wrap_optional {
             // NOTE: this probably isn't the smartest error to use in this case but there
             // isn't something better.
@@ -88,7 +122,8 @@ impl NotificationToasts {
                 )
             )
         }
-        let is_message_multiline = message.contains ("\n");
+        // Toasts without a body use the single-line templates.
+        let is_message_multiline = !toast.body.is_empty();
         let toast_xml = match Self::find_icon_path() {
             Some(icon_path) => {
                 // Use "ToastImageAndText02" as the base toast template if we got an icon.
@@ -128,22 +163,68 @@ impl NotificationToasts {
         let toast_text_tags = wrap_optional_result!(
             toast_xml.get_elements_by_tag_name (&FastHString::new ("text"))
         );
-        let mut message_lines = message.lines();
         wrap_optional_result!(toast_text_tags.item(0)).append_child (
-            text_node!(toast_xml, message_lines.next().unwrap())
+            text_node!(toast_xml, &toast.title)
         )?;
         if is_message_multiline {
             wrap_optional_result!(toast_text_tags.item(1)).append_child (
-                text_node!(toast_xml, message_lines.next().unwrap())
+                text_node!(toast_xml, &toast.body)
             )?;
         }
+        // Set the duration and the scenario of the toast.
+        let toast_tag = {
+            let toast_tags = wrap_optional_result!(
+                toast_xml.get_elements_by_tag_name (&FastHString::new ("toast"))
+            );
+            wrap_optional!(
+                wrap_optional_result!(toast_tags.item(0)).query_interface::<XmlElement>()
+            )
+        };
+        let scenario = match toast.scenario {
+            ToastScenario::Default  => None,
+            ToastScenario::Reminder => Some ("reminder"),
+            ToastScenario::Alarm    => Some ("alarm"),
+            ToastScenario::Urgent   => Some ("urgent")
+        };
+        if let Some(scenario) = scenario {
+            toast_tag.set_attribute (&FastHString::new ("scenario"), &FastHString::new (scenario))?;
+        }
+        // Looping sounds are only played by long toasts.
+        if toast.duration == ToastDuration::Long || toast.sound == ToastSound::Alarm {
+            toast_tag.set_attribute (&FastHString::new ("duration"), &FastHString::new ("long"))?;
+        }
+        // Then, add an <audio> tag to choose the sound.
+        let audio_tag = wrap_optional_result!(
+            toast_xml.create_element (&FastHString::new ("audio"))
+        );
+        match toast.sound {
+            ToastSound::Default => (),
+            ToastSound::Silent =>
+                audio_tag.set_attribute (&FastHString::new ("silent"), &FastHString::new ("true"))?,
+            ToastSound::Alarm => {
+                audio_tag.set_attribute (
+                    &FastHString::new ("src"),
+                    &FastHString::new ("ms-winsoundevent:Notification.Looping.Alarm")
+                )?;
+                audio_tag.set_attribute (&FastHString::new ("loop"), &FastHString::new ("true"))?;
+            }
+        }
+        toast_tag.append_child (&*wrap_optional!(audio_tag.query_interface::<IXmlNode>()))?;
         // Finally, we're ready to create and show the toast.
-        let toast = ToastNotification::create_toast_notification (&*toast_xml)?;
+        let notification = ToastNotification::create_toast_notification (&*toast_xml)?;
+        if let Some(tag) = toast.tag {
+            // Toasts with the same tag and group replace each other.
+            let notification = wrap_optional!(
+                notification.query_interface::<IToastNotification2>()
+            );
+            notification.set_tag (&FastHString::new (tag))?;
+            notification.set_group (&FastHString::new (TOAST_GROUP))?;
+        }
         wrap_optional_result!(
             ToastNotificationManager::create_toast_notifier_with_id (
                 &FastHString::new (APP_USER_MODEL_ID)
             )
-        ).show (&*toast)
+        ).show (&*notification)
     }
 }
 
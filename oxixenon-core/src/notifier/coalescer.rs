@@ -0,0 +1,126 @@
+//! Coalescing of bursts of identical events, e.g. the renewals requested by a flapping PPPoE
+//! session, into a single notification carrying how many events it stands for.
+
+use crate::config::format_duration;
+use crate::protocol::Event;
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Receives each event along with the data of its latest occurrence and how many events it stands
+// for.
+type Deliver<T> = dyn FnMut(Event, T, u32) + Send;
+
+/// Delivers the first event of a burst at once, while the identical ones following it within
+/// `window` are held back and delivered as a single event when the window elapses. The window
+/// restarts as long as events keep coming, so that a storm results in one event per window.
+///
+/// Each event carries some data (e.g. the address which sent it): the one of the latest
+/// occurrence is delivered.
+pub struct Coalescer<T> {
+    window: Duration,
+    // The bursts in progress, along with the events held back so far.
+    bursts: Arc<Mutex<BTreeMap<Event, Burst<T>>>>,
+    deliver: Arc<Mutex<Box<Deliver<T>>>>
+}
+
+impl<T> Clone for Coalescer<T> {
+    fn clone (&self) -> Self {
+        Coalescer {
+            window: self.window,
+            bursts: self.bursts.clone(),
+            deliver: self.deliver.clone()
+        }
+    }
+}
+
+struct Burst<T> {
+    held: u32,
+    latest: Option<T>
+}
+
+impl<T: Send + 'static> Coalescer<T> {
+    pub fn new<F> (window: Duration, deliver: F) -> Coalescer<T>
+        where F: FnMut(Event, T, u32) + Send + 'static
+    {
+        Coalescer {
+            window,
+            bursts: Arc::new (Mutex::new (BTreeMap::new())),
+            deliver: Arc::new (Mutex::new (Box::new (deliver)))
+        }
+    }
+
+    /// Delivers `event` at once if it starts a burst, otherwise holds it back.
+    pub fn push (&self, event: Event, data: T) {
+        self.push_counted (event, data, 1)
+    }
+
+    /// Like `push`, for an event which already stands for `count` events, e.g. one coalesced by
+    /// the server which sent it.
+    pub fn push_counted (&self, event: Event, data: T, count: u32) {
+        {
+            let mut bursts = self.bursts.lock().unwrap();
+            if let Some(burst) = bursts.get_mut (&event) {
+                burst.held += count;
+                burst.latest = Some (data);
+                return;
+            }
+            bursts.insert (event.clone(), Burst { held: 0, latest: None });
+        }
+        (self.deliver.lock().unwrap()) (event.clone(), data, count);
+
+        let (window, bursts, deliver) = (self.window, self.bursts.clone(), self.deliver.clone());
+        thread::spawn (move || loop {
+            thread::sleep (window);
+            let (held, latest) = {
+                let mut bursts = bursts.lock().unwrap();
+                let burst = bursts.get_mut (&event).unwrap();
+                match burst.latest.take() {
+                    Some(latest) => (std::mem::take (&mut burst.held), latest),
+                    None => {
+                        bursts.remove (&event);
+                        return;
+                    }
+                }
+            };
+            debug!(target: "notifier", "coalesced {} \"{}\" events received within {}",
+                held, event, format_duration (window));
+            (deliver.lock().unwrap()) (event.clone(), latest, held);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+
+    #[test]
+    fn bursts_are_coalesced() {
+        let (sender, delivered) = mpsc::channel();
+        let coalescer = Coalescer::new (Duration::from_millis (200), move |event, data, count| {
+            sender.send ((event, data, count)).unwrap()
+        });
+        for data in 1..=5 {
+            coalescer.push (Event::IPRenewed, data);
+        }
+        coalescer.push (Event::UnavailabilityExpired, 6);
+        let timeout = Duration::from_secs (5);
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::IPRenewed, 1, 1)));
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::UnavailabilityExpired, 6, 1)));
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::IPRenewed, 5, 4)));
+
+        // Once a window elapses without events, the next one is delivered at once.
+        thread::sleep (Duration::from_secs (1));
+        coalescer.push (Event::IPRenewed, 7);
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::IPRenewed, 7, 1)));
+
+        // Events which already stand for several ones count as such.
+        coalescer.push_counted (Event::UnavailabilityExpired, 8, 3);
+        coalescer.push_counted (Event::UnavailabilityExpired, 9, 2);
+        coalescer.push (Event::UnavailabilityExpired, 10);
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::UnavailabilityExpired, 8, 3)));
+        assert_eq!(delivered.recv_timeout (timeout), Ok((Event::UnavailabilityExpired, 10, 3)));
+    }
+}
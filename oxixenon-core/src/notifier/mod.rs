@@ -0,0 +1,223 @@
+use crate::config;
+use crate::protocol::{Event, EventPayload};
+use crate::errors::Cause;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::ops::ControlFlow;
+use std::sync::{OnceLock, RwLock};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    #[error(transparent)]
+    Config(#[from] config::Error)
+}
+
+chained_error!(Error);
+
+// Built-in notifiers. They also need to be registered in `Registry::builtin()`.
+#[cfg(feature = "notifier-multicast")] mod multicast;
+mod noop;
+
+mod coalescer;
+mod shutdown;
+pub use self::coalescer::Coalescer;
+pub use self::shutdown::Shutdown;
+
+/// Receives the events heard by `Notifier::listen`, along with their payload and where they come
+/// from (if known). Returning `ControlFlow::Break` stops listening, e.g. once the awaited event is
+/// received.
+pub type OnEvent<'a> =
+    dyn FnMut(Event, &EventPayload, Option<SocketAddr>) -> ControlFlow<()> + 'a;
+
+// Notifiers are shared with the thread which reloads the configuration, hence `Send`.
+pub trait Notifier: Send {
+    fn from_config (notifier: &config::NotifierConfig) -> Result<Self>
+        where Self: Sized;
+    fn notify (&mut self, event: Event) -> Result<()>;
+    /// Like `notify`, along with what's known about the event (see `EventPayload`). Notifiers
+    /// which can't carry it only notify the event.
+    fn notify_with_payload (&mut self, event: Event, _payload: &EventPayload) -> Result<()> {
+        self.notify (event)
+    }
+    /// Calls `on_event` for every event received, until it breaks or `shutdown` is requested.
+    fn listen (&mut self, on_event: &mut OnEvent, shutdown: &Shutdown) -> Result<()>;
+
+    /// Returns the schema of the configuration of the notifier, see `config::schema`.
+    #[cfg(feature = "config-schema")]
+    fn config_schema (_gen: &mut SchemaGenerator) -> Schema
+        where Self: Sized
+    {
+        Schema::Bool (true)
+    }
+}
+
+/// Builds a notifier from its configuration, see `Registry::register`.
+pub type Constructor = fn(&config::NotifierConfig) -> Result<Box<dyn Notifier>>;
+
+// How a notifier is built, along with the schema of its configuration.
+#[derive(Clone, Copy)]
+struct NotifierType {
+    build: Constructor,
+    #[cfg(feature = "config-schema")]
+    config_schema: fn(&mut SchemaGenerator) -> Schema
+}
+
+impl NotifierType {
+    fn of<N: Notifier + 'static>() -> NotifierType {
+        NotifierType {
+            build: build::<N>,
+            #[cfg(feature = "config-schema")]
+            config_schema: N::config_schema
+        }
+    }
+}
+
+fn build<N: Notifier + 'static> (notifier: &config::NotifierConfig) -> Result<Box<dyn Notifier>> {
+    Ok(Box::new (N::from_config (notifier)?))
+}
+
+/// The notifiers which can be chosen by name in the configuration, each configured in
+/// `[notifier.<name>]`.
+///
+/// `get_notifier` looks notifiers up in the global registry (see `Registry::global`), which starts
+/// with the built-in notifiers: programs using `oxixenon-core` can add their own to it, before
+/// the configuration is used.
+#[derive(Clone, Default)]
+pub struct Registry {
+    notifiers: BTreeMap<String, NotifierType>
+}
+
+// The registry used by `get_notifier`, created on first use.
+static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+
+impl Registry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// A registry with the built-in notifiers (those enabled at compile time).
+    pub fn builtin() -> Self {
+        let mut registry = Registry::new();
+        registry.register_type::<noop::Notifier> ("none");
+        #[cfg(feature = "notifier-multicast")]
+        registry.register_type::<multicast::Notifier> ("multicast");
+        registry
+    }
+
+    /// The registry used by `get_notifier` and by the schema of the configuration, which starts
+    /// with the built-in notifiers.
+    pub fn global() -> &'static RwLock<Registry> {
+        REGISTRY.get_or_init (|| RwLock::new (Registry::builtin()))
+    }
+
+    /// Adds the notifier `name`, built by `constructor`, replacing any notifier with the same
+    /// name. Its configuration isn't described by the schema: see `register_type` for that.
+    pub fn register (&mut self, name: &str, constructor: Constructor) {
+        self.notifiers.insert (name.to_string(), NotifierType {
+            build: constructor,
+            #[cfg(feature = "config-schema")]
+            config_schema: |_| Schema::Bool (true)
+        });
+    }
+
+    /// Adds the notifier `name`, built by `N::from_config` and whose configuration is described
+    /// by `N::config_schema`, replacing any notifier with the same name.
+    pub fn register_type<N: Notifier + 'static> (&mut self, name: &str) {
+        self.notifiers.insert (name.to_string(), NotifierType::of::<N>());
+    }
+
+    /// Removes the notifier `name`, returning whether there was one.
+    pub fn unregister (&mut self, name: &str) -> bool {
+        self.notifiers.remove (name).is_some()
+    }
+
+    pub fn contains (&self, name: &str) -> bool {
+        self.notifiers.contains_key (name)
+    }
+
+    /// The names of the notifiers, in alphabetical order.
+    pub fn names (&self) -> impl Iterator<Item = &str> {
+        self.notifiers.keys().map (String::as_str)
+    }
+
+    // Looks up a notifier by name.
+    fn get (&self, name: &str) -> Option<NotifierType> {
+        self.notifiers.get (name).copied()
+            // "noop" is the former name of "none".
+            .or_else (|| if name == "noop" { self.get ("none") } else { None })
+    }
+}
+
+/// Adds a custom notifier to the global registry (see `Registry::register_type`), which can be
+/// used by choosing `name` as the notifier and configured in `[notifier.<name>]`. Replaces any
+/// notifier with the same name, including the built-in ones.
+pub fn register_notifier<N: Notifier + 'static> (name: &str) {
+    Registry::global().write().unwrap_or_else (|e| e.into_inner()).register_type::<N> (name);
+}
+
+/// Returns the schema of the `notifier` table, see `config::schema`.
+#[cfg(feature = "config-schema")]
+pub fn config_schema (gen: &mut SchemaGenerator) -> Schema {
+    let registry = Registry::global().read().unwrap_or_else (|e| e.into_inner()).clone();
+    let sections = registry.notifiers.iter()
+        .map (|(name, notifier)| (name.as_str(), (notifier.config_schema) (gen)))
+        .collect();
+    crate::config::schema::sections (sections)
+}
+
+pub fn get_notifier (notifier: &config::NotifierConfig) -> Result<Box<dyn Notifier>> {
+    let registry = Registry::global().read().unwrap_or_else (|e| e.into_inner());
+    match registry.get (&notifier.name) {
+        Some(notifier_type) => {
+            // Notifiers could use the registry while being built.
+            drop (registry);
+            (notifier_type.build) (notifier)
+        },
+        None => bail!(
+            "invalid notifier name '{}', must be one of {}",
+            notifier.name,
+            registry.names()
+                .map (|name| format!("'{}'", name))
+                .collect::<Vec<_>>()
+                .join (", ")
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Section;
+
+    fn notifier (name: &str) -> config::NotifierConfig {
+        config::NotifierConfig {
+            name: name.into(),
+            config: Section::empty (format!("notifier.{}", name))
+        }
+    }
+
+    #[test]
+    fn registered_notifiers_are_used() {
+        Registry::global().write().unwrap().register ("imaginary", |notifier| {
+            ensure!(!notifier.config.is_present(), "not so imaginary");
+            Ok(Box::new (noop::Notifier))
+        });
+        get_notifier (&notifier ("imaginary")).unwrap().notify (Event::IPRenewed).unwrap();
+        assert!(get_notifier (&notifier ("noop")).is_ok());
+        let error = get_notifier (&notifier ("nonexistent")).err().unwrap().to_string();
+        assert!(error.starts_with ("invalid notifier name 'nonexistent', must be one of "));
+        assert!(error.contains ("'imaginary'") && error.contains ("'none'"));
+
+        let mut registry = Registry::builtin();
+        assert!(registry.contains ("none") && !registry.contains ("imaginary"));
+        assert!(registry.unregister ("none") && registry.get ("noop").is_none());
+        assert!(Registry::new().names().next().is_none());
+    }
+}
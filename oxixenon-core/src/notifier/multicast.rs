@@ -0,0 +1,210 @@
+use super::{Notifier as NotifierTrait, OnEvent, Result, ResultExt, Shutdown};
+use crate::config;
+use crate::metrics;
+use crate::protocol::{self, Direction, Packet, Event, EventPayload};
+use crate::resolver::{self, Resolver};
+use serde::Deserialize;
+use std::io;
+use std::net::{UdpSocket, IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::time::Duration;
+
+// How often a listener checks whether it has been asked to stop.
+const SHUTDOWN_POLL: Duration = Duration::from_millis (250);
+
+// Configuration of the notifier, in `[notifier.multicast]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+struct Config {
+    /// The multicast IP address and port where notifications are sent to.
+    addr: String,
+    /// Where the UDP socket is bound to.
+    bind_addr: String,
+    /// How often a host name in `addr` is resolved again (e.g. "10m"), keeping the last known
+    /// address if that fails. Defaults to 5 minutes.
+    resolve_interval: Option<String>
+}
+
+pub struct Notifier {
+    bind_addr: SocketAddr,
+    addr: Resolver
+}
+
+impl Notifier {
+    // The multicast group, resolved again when it's time to.
+    fn group (&self) -> Result<SocketAddr> {
+        let addr = self.addr.resolve()
+            .chain_err (|| "failed to resolve 'notifier.multicast.addr'")?[0];
+        ensure!(
+            addr.is_ipv4() == self.bind_addr.is_ipv4(),
+            "'notifier.multicast.addr' now resolves to {}, which doesn't match the family of \
+            'notifier.multicast.bind_addr'", addr
+        );
+        Ok(addr)
+    }
+}
+
+// Joins or leaves the multicast group `addr` on the default interface.
+fn membership (socket: &UdpSocket, addr: SocketAddr, join: bool) -> io::Result<()> {
+    match (addr.ip(), join) {
+        (IpAddr::V4(ref ip), true) => socket.join_multicast_v4 (ip, &Ipv4Addr::UNSPECIFIED),
+        (IpAddr::V4(ref ip), false) => socket.leave_multicast_v4 (ip, &Ipv4Addr::UNSPECIFIED),
+        (IpAddr::V6(ref ip), true) => socket.join_multicast_v6 (ip, 0),
+        (IpAddr::V6(ref ip), false) => socket.leave_multicast_v6 (ip, 0)
+    }
+}
+
+impl NotifierTrait for Notifier {
+    fn from_config (notifier: &config::NotifierConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = notifier.config.parse()
+            .chain_err (|| "the notifier 'multicast' is not configured correctly")?;
+        let interval = config.resolve_interval.as_deref()
+            .map (config::parse_duration)
+            .transpose()
+            .chain_err (|| "invalid option 'notifier.multicast.resolve_interval'")?
+            .unwrap_or (resolver::DEFAULT_INTERVAL);
+        // Get addr and bind_addr
+        let resolver = Resolver::new (config.addr, interval)
+            .accepting (|addr| addr.ip().is_multicast());
+        let addr = resolver.resolve()
+            .chain_err (|| "failed to find a multicast address for 'notifier.multicast.addr'")?[0];
+        // The socket must be of the same family (IPv4 or IPv6) as the multicast group.
+        let bind_addr = config.bind_addr
+            .to_socket_addrs()
+            .chain_err (|| "failed to parse 'notifier.multicast.bind_addr' as a socket address")?
+            .find (|&bind_addr| bind_addr.is_ipv4() == addr.is_ipv4())
+            .chain_err (|| format!(
+                "failed to find an {} address for 'notifier.multicast.bind_addr'",
+                if addr.is_ipv4() { "IPv4" } else { "IPv6" }
+            ))?;
+        trace!(target: "notifier::multicast", "initialized, addr = {}, bind_addr = {}",
+            addr, bind_addr);
+        Ok(Self {
+            addr: resolver,
+            bind_addr
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn notify (&mut self, event: Event) -> Result<()> {
+        self.notify_with_payload (event, &EventPayload::default())
+    }
+
+    fn notify_with_payload (&mut self, event: Event, payload: &EventPayload) -> Result<()> {
+        let addr = self.group()?;
+        let socket = UdpSocket::bind (self.bind_addr)
+            .chain_err (|| format!("failed to bind to {}", self.bind_addr))?;
+        let mut vec: Vec<u8> = Vec::new();
+        let packet = Packet::Event(event.clone(), payload.clone());
+        packet.write (&mut vec)
+            .chain_err (|| format!("failed to write event packet '{}' to a local buffer", event))?;
+        protocol::trace_packet (Direction::Sent, &addr, Some (&packet), &vec);
+        socket.send_to (&vec, addr)
+            .chain_err (|| format!("failed to send event packet '{}' to {}", event, addr))?;
+        debug!(target: "notifier::multicast", "successfully notified event \"{}\"", event);
+        Ok(())
+    }
+
+    fn listen (&mut self, on_event: &mut OnEvent, shutdown: &Shutdown) -> Result<()> {
+        let socket = UdpSocket::bind (self.bind_addr)
+            .chain_err (|| format!("failed to bind to {}", self.bind_addr))?;
+        let mut group = self.group()?;
+        membership (&socket, group, true)
+            .chain_err (|| format!("failed to join multicast group '{}'", group))?;
+        socket.set_read_timeout (Some (SHUTDOWN_POLL))
+            .chain_err (|| "failed to set the timeout of the multicast socket")?;
+        // Large enough for events along with their payload.
+        let mut buf = vec![0; 512];
+        while !shutdown.is_requested() {
+            // Follow the group if its name resolves to another address.
+            let current = self.group()?;
+            if current != group {
+                info!(target: "notifier::multicast", "moving from group {} to {}", group, current);
+                let _ = membership (&socket, group, false);
+                membership (&socket, current, true)
+                    .chain_err (|| format!("failed to join multicast group '{}'", current))?;
+                group = current;
+            }
+            let (number_of_bytes, src_addr) = match socket.recv_from (&mut buf) {
+                Ok(received) => received,
+                Err(ref error) if error.kind() == io::ErrorKind::WouldBlock ||
+                    error.kind() == io::ErrorKind::TimedOut => continue,
+                Err(error) => return Err(error)
+                    .chain_err (|| "failed to receive data from multicast socket")
+            };
+            let mut slice = &buf[..number_of_bytes];
+            let result = Packet::read (&mut slice);
+            protocol::trace_packet (
+                Direction::Received, &src_addr, result.as_ref().ok(), &buf[..number_of_bytes]
+            );
+
+            match result {
+                Ok(packet) => {
+                    if let Packet::Event(event, payload) = packet {
+                        match payload.is_empty() {
+                            true => debug!(target: "notifier::multicast",
+                                "received event \"{}\"", event),
+                            false => debug!(target: "notifier::multicast",
+                                "received event \"{}\" ({})", event, payload)
+                        }
+                        metrics::counter ("notifier.received", 1);
+                        if on_event (event, &payload, Some (src_addr)).is_break() {
+                            break;
+                        }
+                    }
+                },
+                Err(error) =>
+                    warn!(target: "notifier::multicast", "can't decode incoming packet: {}", error)
+            }
+        }
+        debug!(target: "notifier::multicast", "stopped listening");
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Section, SourceMap};
+    use std::sync::Arc;
+
+    fn from_config (addr: &str, bind_addr: &str) -> Result<Notifier> {
+        let source = format!("addr = '{}'\nbind_addr = '{}'\n", addr, bind_addr);
+        let map = Arc::new (SourceMap::new (&source));
+        let config = Section::new ("notifier.multicast".into(), Some(source.parse().unwrap()), map);
+        Notifier::from_config (&config::NotifierConfig { name: "multicast".into(), config })
+    }
+
+    #[test]
+    fn supports_ipv6_groups() {
+        let notifier = from_config ("[ff02::5454]:5454", "[::]:0").unwrap();
+        assert_eq!(notifier.group().unwrap(), "[ff02::5454]:5454".parse().unwrap());
+        assert!(notifier.bind_addr.is_ipv6());
+        assert!(from_config ("239.255.54.54:5454", "0.0.0.0:0").is_ok());
+    }
+
+    #[test]
+    fn rejects_mismatched_families_and_unicast_addresses() {
+        assert!(from_config ("[ff02::5454]:5454", "0.0.0.0:0").is_err());
+        assert!(from_config ("239.255.54.54:5454", "[::]:0").is_err());
+        assert!(from_config ("[fd00::1]:5454", "[::]:0").is_err());
+    }
+
+    #[test]
+    fn listening_stops_when_asked() {
+        let mut notifier = from_config ("239.255.54.54:5454", "0.0.0.0:0").unwrap();
+        let shutdown = Shutdown::new();
+        let requester = shutdown.clone();
+        std::thread::spawn (move || {
+            std::thread::sleep (Duration::from_millis (100));
+            requester.request();
+        });
+        let mut on_event = |event, _: &_, _| panic!("unexpected event {}", event);
+        notifier.listen (&mut on_event, &shutdown).unwrap();
+    }
+}
@@ -1,7 +1,6 @@
-use super::{Notifier as NotifierTrait, Result};
+use super::{Notifier as NotifierTrait, OnEvent, Result, Shutdown};
 use crate::config;
 use crate::protocol::Event;
-use std::net::SocketAddr;
 
 pub struct Notifier;
 impl NotifierTrait for Notifier {
@@ -11,9 +10,14 @@ impl NotifierTrait for Notifier {
         Ok(Notifier)
     }
 
+    #[cfg(feature = "config-schema")]
+    fn config_schema (_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::empty_section()
+    }
+
     fn notify (&mut self, _event: Event) -> Result<()> { Ok(()) }
 
-    fn listen(&mut self, _on_event: &dyn Fn(Event, Option<SocketAddr>) -> ()) -> Result<()> {
+    fn listen (&mut self, _on_event: &mut OnEvent, _shutdown: &Shutdown) -> Result<()> {
         bail!("Can't listen for notifications with this notifier. Try using a real one")
     }
 }
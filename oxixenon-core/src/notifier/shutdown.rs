@@ -0,0 +1,61 @@
+//! A token asking listening notifiers to stop, see `Notifier::listen`.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Asks a listening notifier to stop. Clones share the same state, so that a clone kept by
+/// another thread (e.g. one handling a signal) can stop the listener.
+#[derive(Clone, Default)]
+pub struct Shutdown {
+    requested: Arc<(Mutex<bool>, Condvar)>
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Asks the listener to stop, which returns as soon as it notices.
+    pub fn request (&self) {
+        let (ref requested, ref condvar) = *self.requested;
+        *requested.lock().unwrap_or_else (|e| e.into_inner()) = true;
+        condvar.notify_all();
+    }
+
+    pub fn is_requested (&self) -> bool {
+        *self.requested.0.lock().unwrap_or_else (|e| e.into_inner())
+    }
+
+    /// Waits until the shutdown is requested or `timeout` elapses, returning whether it was
+    /// requested. Useful to listeners which poll their source of events.
+    pub fn wait_timeout (&self, timeout: Duration) -> bool {
+        let (ref requested, ref condvar) = *self.requested;
+        let requested = requested.lock().unwrap_or_else (|e| e.into_inner());
+        let (requested, _) = condvar
+            .wait_timeout_while (requested, timeout, |requested| !*requested)
+            .unwrap_or_else (|e| e.into_inner());
+        *requested
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Instant;
+
+    #[test]
+    fn waiting_stops_when_requested() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout (Duration::from_millis (10)));
+        let requester = shutdown.clone();
+        let started = Instant::now();
+        thread::spawn (move || {
+            thread::sleep (Duration::from_millis (100));
+            requester.request();
+        });
+        assert!(shutdown.wait_timeout (Duration::from_secs (10)));
+        assert!(started.elapsed() < Duration::from_secs (5));
+        assert!(shutdown.is_requested());
+    }
+}
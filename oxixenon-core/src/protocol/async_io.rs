@@ -0,0 +1,94 @@
+//! Reading and writing packets with tokio (feature `async-protocol`), so that async servers and
+//! clients don't need a thread per connection.
+//!
+//! Packets aren't prefixed by their length, so the bytes received so far are parsed by
+//! `Packet::read` until it finds a whole packet, rather than duplicating the parser.
+
+use super::*;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt};
+
+impl Packet {
+    /// Like [`read`](#method.read), from an async reader (e.g. a `tokio::io::BufReader`). Only
+    /// the bytes of the packet are consumed, so that the following packet can be read as well.
+    pub async fn read_async<R: AsyncBufRead + Unpin> (reader: &mut R) -> Result<Self> {
+        let mut bytes = Vec::new();
+        loop {
+            let available = reader.fill_buf().await
+                .chain_err (|| "failed to read the packet")?;
+            ensure!(!available.is_empty(), "the connection was closed");
+            let received = available.len();
+            bytes.extend_from_slice (available);
+            let mut partial = Partial { bytes: &bytes, exhausted: false };
+            let packet = Packet::read (&mut partial);
+            if partial.exhausted {
+                // The packet continues in the bytes yet to be received.
+                reader.consume (received);
+                continue;
+            }
+            // The bytes of the following packets are left to the reader.
+            let unread = partial.bytes.len();
+            reader.consume (received - unread);
+            return packet;
+        }
+    }
+
+    /// Like [`write`](#method.write), to an async writer. The packet is written as a whole, but
+    /// not flushed.
+    pub async fn write_async<W: AsyncWrite + Unpin> (&self, writer: &mut W) -> Result<()> {
+        let mut bytes = Vec::new();
+        self.write (&mut bytes)?;
+        writer.write_all (&bytes).await.chain_err (|| "failed to write packet")
+    }
+}
+
+// The bytes received so far, which tells whether `Packet::read` needed more of them: reads past
+// them fail rather than being read as the end of the packet (strings are read until their length
+// or the end, whichever comes first).
+struct Partial<'a> {
+    bytes: &'a [u8],
+    exhausted: bool
+}
+
+impl Read for Partial<'_> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.bytes.is_empty() && !buf.is_empty() {
+            self.exhausted = true;
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.bytes.read (buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::BufReader;
+
+    fn block_on<F: std::future::Future> (future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread().build().unwrap().block_on (future)
+    }
+
+    #[test]
+    fn packets_are_read_and_written_asynchronously() {
+        let packets = [
+            Packet::FreshIPRequest { renewer: Some ("wan2".into()) },
+            Packet::Error { code: ErrorCode::Unavailable, detail: Some ("gaming".into()) },
+            Packet::Subscribe
+        ];
+        let mut bytes = Vec::new();
+        block_on (async {
+            for packet in &packets {
+                packet.write_async (&mut bytes).await.unwrap();
+            }
+        });
+        // Received a byte at a time, as over a slow connection.
+        let mut reader = BufReader::with_capacity (1, &bytes[..]);
+        block_on (async {
+            for packet in &packets {
+                let read = Packet::read_async (&mut reader).await.unwrap();
+                assert_eq!(&read, packet);
+            }
+            assert!(Packet::read_async (&mut reader).await.is_err());
+        });
+    }
+}
@@ -0,0 +1,499 @@
+//! The JSON encoding of packets (feature `json-protocol`), for tools which can't implement the
+//! binary one, such as shell scripts with `jq`: each packet is an object on its own line, whose
+//! `type` is the name of the packet in snake case (e.g. `{"type":"status_request"}`). The fields
+//! of each packet are described in the README.
+
+use super::*;
+use serde_json::{json, Map, Value};
+
+// The longest line read, so that peers can't make the server buffer arbitrary amounts.
+const MAX_LINE: u64 = 64 * 1024;
+
+impl Packet {
+    /// Like [`receive`](#method.receive), for a packet encoded in JSON.
+    pub fn receive_json<R: BufRead> (reader: &mut R, peer: &dyn fmt::Display) -> Result<Self> {
+        let mut line = Vec::new();
+        reader.by_ref().take (MAX_LINE).read_until (b'\n', &mut line)
+            .chain_err (|| "failed to read the JSON packet")?;
+        ensure!(!line.is_empty(), "the connection was closed");
+        ensure!(
+            line.len() < MAX_LINE as usize || line.ends_with (b"\n"),
+            "the JSON packet is longer than {} bytes", MAX_LINE
+        );
+        let packet = serde_json::from_slice::<Value> (&line)
+            .chain_err (|| "the packet isn't valid JSON")
+            .and_then (|value| Packet::from_json (&value));
+        if tracing_packets() {
+            let secrets = packet.as_ref().map_or (Vec::new(), |packet| {
+                packet.json_secret_ranges (&line)
+            });
+            trace (Direction::Received, peer, packet.as_ref().ok(), &line, &secrets);
+        }
+        packet
+    }
+
+    /// Like [`send`](#method.send), encoding the packet in JSON.
+    pub fn send_json (&self, writer: &mut dyn Write, peer: &dyn fmt::Display) -> Result<()> {
+        let mut line = serde_json::to_vec (&self.to_json())
+            .chain_err (|| "failed to encode the packet in JSON")?;
+        line.push (b'\n');
+        if tracing_packets() {
+            trace (Direction::Sent, peer, Some (self), &line, &self.json_secret_ranges (&line));
+        }
+        writer.write_all (&line).chain_err (|| "failed to write packet")
+    }
+
+    /// The name of the packet in the JSON encoding, e.g. "fresh_ip_request".
+    pub fn json_type (&self) -> &'static str {
+        match *self {
+            Packet::FreshIPRequest { .. } => "fresh_ip_request",
+            Packet::SetRenewingAvailable(_) => "set_renewing_available",
+            Packet::ReloadConfig { .. } => "reload_config",
+            Packet::SetRenewer { .. } => "set_renewer",
+            Packet::StatusRequest => "status_request",
+            Packet::HealthRequest => "health_request",
+            Packet::AuthResponse(_) => "auth_response",
+            Packet::Ok => "ok",
+            Packet::Error { .. } => "error",
+            Packet::Event(..) => "event",
+            Packet::Status(_) => "status",
+            Packet::IPUnchanged(_) => "ip_unchanged",
+            Packet::Health { .. } => "health",
+            Packet::Renewed(_) => "renewed",
+            Packet::AuthChallenge(_) => "auth_challenge",
+            Packet::HistoryRequest { .. } => "history_request",
+            Packet::History(_) => "history",
+            Packet::Subscribe => "subscribe",
+            Packet::CapabilitiesRequest => "capabilities_request",
+            Packet::Capabilities(_) => "capabilities"
+        }
+    }
+
+    /// The JSON encoding of the packet. Unknown values are `null`, durations and timestamps are
+    /// in seconds and nonces in hexadecimal.
+    pub fn to_json (&self) -> Value {
+        let mut object = match *self {
+            Packet::FreshIPRequest { ref renewer } => json!({ "renewer": renewer }),
+            Packet::SetRenewingAvailable(ref availability) =>
+                json!({ "availability": availability_to_json (availability) }),
+            Packet::StatusRequest | Packet::HealthRequest | Packet::Ok | Packet::Subscribe |
+            Packet::CapabilitiesRequest => json!({}),
+            Packet::ReloadConfig { ref token } => json!({ "token": token.expose() }),
+            Packet::SetRenewer { ref renewer, ref token } =>
+                json!({ "renewer": renewer, "token": token.expose() }),
+            Packet::AuthResponse(ref response) => json!({ "response": hex (response) }),
+            Packet::Error { code, ref detail } => json!({ "code": code.name(), "detail": detail }),
+            Packet::Event(ref event, ref payload) => {
+                let timestamp = payload.timestamp
+                    .map (|time| {
+                        time.duration_since (UNIX_EPOCH).map_or (0, |since| since.as_secs())
+                    });
+                let mut object = json!({
+                    "event": event.name(),
+                    "timestamp": timestamp,
+                    "origin": payload.origin,
+                    "old_ip": payload.outcome.old_ip,
+                    "new_ip": payload.outcome.new_ip,
+                    "requested_by": payload.requested_by,
+                    "coalesced": payload.coalesced
+                });
+                match *event {
+                    Event::RenewalFailed(ref reason) => object["reason"] = json!(reason),
+                    Event::AvailabilityChanged(ref availability) =>
+                        object["availability"] = availability_to_json (availability),
+                    _ => ()
+                }
+                object
+            },
+            Packet::Status(ref status) => json!({
+                "renewer": status.renewer,
+                "availability": availability_to_json (&status.availability),
+                "link": status.link.as_ref().map (|link| json!({
+                    "state": link.state,
+                    "uptime": link.uptime.map (|uptime| uptime.as_secs()),
+                    "downstream_rate": link.downstream_rate,
+                    "upstream_rate": link.upstream_rate,
+                    "external_ip": link.external_ip
+                }))
+            }),
+            Packet::IPUnchanged(ip) => json!({ "ip": ip }),
+            Packet::Health { ref health, age } => json!({
+                "reachable": health.reachable,
+                "credentials_valid": health.credentials_valid,
+                "logged_in": health.logged_in,
+                "problem": health.problem,
+                "age": age.as_secs()
+            }),
+            Packet::Renewed(ref outcome) =>
+                json!({ "old_ip": outcome.old_ip, "new_ip": outcome.new_ip }),
+            Packet::AuthChallenge(ref nonce) => json!({ "nonce": hex (nonce) }),
+            Packet::HistoryRequest { limit } => json!({ "limit": limit }),
+            Packet::History(ref entries) => json!({
+                "entries": entries.iter().map (history_entry_to_json).collect::<Vec<_>>()
+            }),
+            Packet::Capabilities(ref capabilities) => json!({
+                "version": capabilities.version,
+                "renewer": capabilities.renewer,
+                "auth": capabilities.auth,
+                "keep_alive": capabilities.keep_alive,
+                "reload": capabilities.reload,
+                "set_renewer": capabilities.set_renewer,
+                "status": capabilities.status,
+                "history": capabilities.history,
+                "schedule": capabilities.schedule
+            })
+        };
+        object["type"] = json!(self.json_type());
+        object
+    }
+
+    /// Decodes a packet from its JSON encoding (see `to_json`). Fields which are `null` or
+    /// missing are unknown.
+    pub fn from_json (value: &Value) -> Result<Packet> {
+        let fields = Fields (value.as_object().chain_err (|| "the packet isn't an object")?);
+        let packet = match fields.required_string ("type")?.as_str() {
+            "fresh_ip_request" => Packet::FreshIPRequest { renewer: fields.string ("renewer")? },
+            "set_renewing_available" => Packet::SetRenewingAvailable (
+                availability_from_json (&fields.object ("availability")?)?
+            ),
+            "reload_config" => Packet::ReloadConfig {
+                token: fields.string ("token")?.unwrap_or_default().into()
+            },
+            "set_renewer" => Packet::SetRenewer {
+                renewer: fields.required_string ("renewer")?,
+                token: fields.string ("token")?.unwrap_or_default().into()
+            },
+            "status_request" => Packet::StatusRequest,
+            "health_request" => Packet::HealthRequest,
+            "subscribe" => Packet::Subscribe,
+            "capabilities_request" => Packet::CapabilitiesRequest,
+            "auth_response" => Packet::AuthResponse (fields.nonce ("response")?),
+            "ok" => Packet::Ok,
+            "error" => Packet::Error {
+                code: fields.string ("code")?
+                    .and_then (|code| ErrorCode::ALL.iter().cloned().find (|c| c.name() == code))
+                    .unwrap_or (ErrorCode::Internal),
+                detail: fields.string ("detail")?
+            },
+            "event" => {
+                let name = fields.required_string ("event")?;
+                let event = match Event::from_name (&name) {
+                    Some(Event::RenewalFailed(_)) =>
+                        Event::RenewalFailed (fields.string ("reason")?.unwrap_or_default()),
+                    Some(Event::AvailabilityChanged(_)) => Event::AvailabilityChanged (
+                        availability_from_json (&fields.object ("availability")?)?
+                    ),
+                    Some(event) => event,
+                    None => bail!("unknown event: {}", name)
+                };
+                Packet::Event (event, EventPayload {
+                    timestamp: fields.u64 ("timestamp")?.and_then (|seconds| {
+                        UNIX_EPOCH.checked_add (Duration::from_secs (seconds))
+                    }),
+                    origin: fields.string ("origin")?,
+                    outcome: RenewOutcome {
+                        old_ip: fields.ip ("old_ip")?,
+                        new_ip: fields.ip ("new_ip")?
+                    },
+                    requested_by: fields.string ("requested_by")?,
+                    coalesced: fields.u32 ("coalesced")?
+                })
+            },
+            "status" => Packet::Status (Status {
+                renewer: fields.string ("renewer")?,
+                availability: availability_from_json (&fields.object ("availability")?)?,
+                link: match fields.value ("link") {
+                    Some(_) => {
+                        let link = fields.object ("link")?;
+                        Some (LinkInfo {
+                            state: link.string ("state")?,
+                            uptime: link.u64 ("uptime")?.map (Duration::from_secs),
+                            downstream_rate: link.u32 ("downstream_rate")?,
+                            upstream_rate: link.u32 ("upstream_rate")?,
+                            external_ip: link.ip ("external_ip")?
+                        })
+                    },
+                    None => None
+                }
+            }),
+            "ip_unchanged" => Packet::IPUnchanged (
+                fields.ip ("ip")?.chain_err (|| "missing field 'ip'")?
+            ),
+            "health" => Packet::Health {
+                health: Health {
+                    reachable: fields.bool ("reachable")?,
+                    credentials_valid: fields.bool ("credentials_valid")?,
+                    logged_in: fields.bool ("logged_in")?,
+                    problem: fields.string ("problem")?
+                },
+                age: Duration::from_secs (fields.u64 ("age")?.unwrap_or_default())
+            },
+            "renewed" => Packet::Renewed (RenewOutcome {
+                old_ip: fields.ip ("old_ip")?,
+                new_ip: fields.ip ("new_ip")?
+            }),
+            "auth_challenge" => Packet::AuthChallenge (fields.nonce ("nonce")?),
+            // Without a limit, the whole history is requested.
+            "history_request" => Packet::HistoryRequest {
+                limit: fields.u64 ("limit")?
+                    .map_or (u16::MAX, |limit| u16::try_from (limit).unwrap_or (u16::MAX))
+            },
+            "history" => Packet::History (
+                fields.array ("entries")?.iter()
+                    .map (|entry| {
+                        let entry = Fields (entry.as_object().chain_err (|| "invalid entry")?);
+                        history_entry_from_json (&entry)
+                    })
+                    .collect::<Result<Vec<_>>>()
+                    .chain_err (|| "invalid field 'entries'")?
+            ),
+            "capabilities" => Packet::Capabilities (Capabilities {
+                version: fields.u64 ("version")?
+                    .map_or (0, |version| u16::try_from (version).unwrap_or (u16::MAX)),
+                renewer: fields.string ("renewer")?,
+                auth: fields.bool ("auth")?.unwrap_or_default(),
+                keep_alive: fields.bool ("keep_alive")?.unwrap_or_default(),
+                reload: fields.bool ("reload")?.unwrap_or_default(),
+                set_renewer: fields.bool ("set_renewer")?.unwrap_or_default(),
+                status: fields.bool ("status")?.unwrap_or_default(),
+                history: fields.bool ("history")?.unwrap_or_default(),
+                schedule: fields.bool ("schedule")?.unwrap_or_default()
+            }),
+            other => bail!("unknown packet type: {}", other)
+        };
+        Ok(packet)
+    }
+
+    // Byte ranges of `line` (the JSON encoding of the packet) which contain secrets, redacted by
+    // packet traces as in `secret_ranges`. The whole line is redacted if a secret can't be found
+    // in it, e.g. as the peer escaped it differently.
+    fn json_secret_ranges (&self, line: &[u8]) -> Vec<Range<usize>> {
+        let token = match *self {
+            Packet::SetRenewer { ref token, .. } | Packet::ReloadConfig { ref token }
+                if !token.expose().is_empty() => token.expose(),
+            _ => return Vec::new()
+        };
+        let encoded = Value::from (token.as_str()).to_string();
+        let encoded = &encoded.as_bytes()[1..encoded.len() - 1];
+        let ranges = (0..(line.len() + 1).saturating_sub (encoded.len()))
+            .filter (|&start| line[start..].starts_with (encoded))
+            .map (|start| Range { start, end: start + encoded.len() })
+            .collect::<Vec<_>>();
+        if ranges.is_empty() {
+            return vec![Range { start: 0, end: line.len() }];
+        }
+        ranges
+    }
+}
+
+// The fields of a JSON object, where `null` is the same as missing.
+struct Fields<'a> (&'a Map<String, Value>);
+
+impl<'a> Fields<'a> {
+    fn value (&self, name: &str) -> Option<&'a Value> {
+        self.0.get (name).filter (|value| !value.is_null())
+    }
+
+    // The value of the field `name` converted by `convert`, failing if it can't be.
+    fn get<T, F> (&self, name: &str, convert: F) -> Result<Option<T>>
+        where F: FnOnce(&'a Value) -> Option<T>
+    {
+        match self.value (name) {
+            Some(value) => convert (value)
+                .map (Some)
+                .chain_err (|| format!("invalid field '{}'", name)),
+            None => Ok(None)
+        }
+    }
+
+    fn string (&self, name: &str) -> Result<Option<String>> {
+        self.get (name, |value| value.as_str().map (String::from))
+    }
+
+    fn required_string (&self, name: &str) -> Result<String> {
+        self.string (name)?.chain_err (|| format!("missing field '{}'", name))
+    }
+
+    fn bool (&self, name: &str) -> Result<Option<bool>> {
+        self.get (name, Value::as_bool)
+    }
+
+    fn u64 (&self, name: &str) -> Result<Option<u64>> {
+        self.get (name, Value::as_u64)
+    }
+
+    fn u32 (&self, name: &str) -> Result<Option<u32>> {
+        self.get (name, |value| value.as_u64().and_then (|value| u32::try_from (value).ok()))
+    }
+
+    fn ip (&self, name: &str) -> Result<Option<IpAddr>> {
+        self.get (name, |value| value.as_str().and_then (|ip| ip.parse().ok()))
+    }
+
+    fn nonce (&self, name: &str) -> Result<[u8; AUTH_LEN]> {
+        self.get (name, |value| value.as_str().and_then (unhex))?
+            .chain_err (|| format!("missing field '{}'", name))
+    }
+
+    fn array (&self, name: &str) -> Result<&'a Vec<Value>> {
+        self.get (name, Value::as_array)?.chain_err (|| format!("missing field '{}'", name))
+    }
+
+    fn object (&self, name: &str) -> Result<Fields<'a>> {
+        self.get (name, |value| value.as_object().map (Fields))?
+            .chain_err (|| format!("missing field '{}'", name))
+    }
+}
+
+// Representation: `{"available": true}` or `{"available": false, "reason": ..., "seconds": ...}`,
+// where `seconds` tells for how long renewals are unavailable, if limited.
+fn availability_to_json (availability: &RenewAvailability) -> Value {
+    match *availability {
+        RenewAvailability::Available => json!({ "available": true }),
+        RenewAvailability::Unavailable(ref reason) =>
+            json!({ "available": false, "reason": reason, "seconds": null }),
+        RenewAvailability::UnavailableFor(ref reason, duration) =>
+            json!({ "available": false, "reason": reason, "seconds": duration.as_secs() })
+    }
+}
+
+fn availability_from_json (fields: &Fields) -> Result<RenewAvailability> {
+    if fields.bool ("available")?.chain_err (|| "missing field 'available'")? {
+        return Ok(RenewAvailability::Available);
+    }
+    let reason = fields.string ("reason")?.filter (|reason| !reason.is_empty())
+        .chain_err (|| "unavailable renewals require a reason")?;
+    Ok(match fields.u64 ("seconds")? {
+        Some(seconds) => RenewAvailability::UnavailableFor (reason, Duration::from_secs (seconds)),
+        None => RenewAvailability::Unavailable (reason)
+    })
+}
+
+// Representation: `{"timestamp": ..., "peer": ..., "renewer": ..., "outcome": ...}`, where the
+// outcome is "renewed" (along with `old_ip` and `new_ip`), "unchanged" (along with `ip`) or
+// "failed" (along with `reason`).
+fn history_entry_to_json (entry: &HistoryEntry) -> Value {
+    let timestamp = entry.timestamp.duration_since (UNIX_EPOCH).map_or (0, |since| since.as_secs());
+    let mut object = json!({
+        "timestamp": timestamp,
+        "peer": entry.peer,
+        "renewer": entry.renewer
+    });
+    match entry.outcome {
+        HistoryOutcome::Renewed(ref outcome) => {
+            object["outcome"] = json!("renewed");
+            object["old_ip"] = json!(outcome.old_ip);
+            object["new_ip"] = json!(outcome.new_ip);
+        },
+        HistoryOutcome::Unchanged(ip) => {
+            object["outcome"] = json!("unchanged");
+            object["ip"] = json!(ip);
+        },
+        HistoryOutcome::Failed(ref reason) => {
+            object["outcome"] = json!("failed");
+            object["reason"] = json!(reason);
+        }
+    }
+    object
+}
+
+fn history_entry_from_json (fields: &Fields) -> Result<HistoryEntry> {
+    let outcome = match fields.required_string ("outcome")?.as_str() {
+        "renewed" => HistoryOutcome::Renewed (RenewOutcome {
+            old_ip: fields.ip ("old_ip")?,
+            new_ip: fields.ip ("new_ip")?
+        }),
+        "unchanged" => HistoryOutcome::Unchanged (
+            fields.ip ("ip")?.chain_err (|| "missing field 'ip'")?
+        ),
+        "failed" => HistoryOutcome::Failed (fields.string ("reason")?.unwrap_or_default()),
+        other => bail!("unknown outcome: {}", other)
+    };
+    Ok(HistoryEntry {
+        timestamp: fields.u64 ("timestamp")?
+            .and_then (|seconds| UNIX_EPOCH.checked_add (Duration::from_secs (seconds)))
+            .unwrap_or (UNIX_EPOCH),
+        peer: fields.string ("peer")?,
+        renewer: fields.string ("renewer")?,
+        outcome
+    })
+}
+
+fn hex (bytes: &[u8]) -> String {
+    bytes.iter().map (|byte| format!("{:02x}", byte)).collect()
+}
+
+fn unhex (hex: &str) -> Option<[u8; AUTH_LEN]> {
+    if hex.len() != AUTH_LEN * 2 || !hex.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; AUTH_LEN];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix (&hex[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some (bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Encodes `packet` in JSON and decodes it back.
+    fn roundtrip (packet: &Packet) -> (String, Packet) {
+        let mut line = Vec::new();
+        packet.send_json (&mut line, &"test").unwrap();
+        let decoded = Packet::receive_json (&mut line.as_slice(), &"test").unwrap();
+        (String::from_utf8 (line).unwrap(), decoded)
+    }
+
+    #[test]
+    fn packets_roundtrip() {
+        let availability =
+            RenewAvailability::UnavailableFor ("gaming".into(), Duration::from_secs (60));
+        let (line, decoded) = roundtrip (&Packet::SetRenewingAvailable (availability.clone()));
+        assert_eq!(line, "{\"availability\":{\"available\":false,\"reason\":\"gaming\",\
+            \"seconds\":60},\"type\":\"set_renewing_available\"}\n");
+        assert!(matches!(
+            decoded, Packet::SetRenewingAvailable (decoded) if decoded == availability
+        ));
+
+        let payload = EventPayload {
+            timestamp: Some (UNIX_EPOCH + Duration::from_secs (1500000000)),
+            origin: Some ("router".into()),
+            outcome: RenewOutcome { old_ip: None, new_ip: Some ("192.0.2.1".parse().unwrap()) },
+            requested_by: Some ("alice".into()),
+            coalesced: Some (3)
+        };
+        let event = Event::RenewalFailed ("the router is on fire".into());
+        match roundtrip (&Packet::Event (event.clone(), payload.clone())).1 {
+            Packet::Event (decoded, decoded_payload) => {
+                assert_eq!(decoded, event);
+                assert_eq!(decoded_payload, payload);
+            },
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+
+        let nonce = [0xab; AUTH_LEN];
+        assert!(matches!(roundtrip (&Packet::AuthChallenge (nonce)).1,
+            Packet::AuthChallenge (decoded) if decoded == nonce));
+    }
+
+    #[test]
+    fn requests_are_lenient() {
+        // Missing fields are unknown, and the newline can be omitted at the end of the stream.
+        let request = b"{\"type\":\"fresh_ip_request\"}";
+        let packet = Packet::receive_json (&mut request.as_slice(), &"test");
+        assert!(matches!(packet, Ok(Packet::FreshIPRequest { renewer: None })));
+        let error = Packet::receive_json (&mut b"{\"type\":\"reboot\"}\n".as_slice(), &"test");
+        assert_eq!(error.unwrap_err().to_string(), "unknown packet type: reboot");
+    }
+
+    #[test]
+    fn tokens_are_redacted() {
+        let packet = Packet::SetRenewer { renewer: "lte".into(), token: "s3cr\"t".into() };
+        let line = packet.to_json().to_string();
+        let ranges = packet.json_secret_ranges (line.as_bytes());
+        assert_eq!(ranges.len(), 1);
+        assert_eq!(&line[ranges[0].clone()], "s3cr\\\"t");
+    }
+}
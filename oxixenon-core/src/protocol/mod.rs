@@ -0,0 +1,1836 @@
+//use errors::*;
+use byteorder::{ReadBytesExt, WriteBytesExt, NetworkEndian};
+use std::fmt;
+use std::error;
+use std::io::{self, BufRead, Read, Write};
+use std::net::IpAddr;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, Ordering};
+use serde::de::{self, Deserialize, Deserializer};
+use crate::config::{format_duration, Secret};
+use crate::errors::Cause;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[cfg(feature = "json-protocol")]
+mod json;
+#[cfg(feature = "async-protocol")]
+mod async_io;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+// protocol::Error, linked to the main error type errors::Error.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause)
+}
+
+chained_error!(Error);
+
+trait WriteString {
+    fn write_u16_string (&mut self, str: Option<&str>) -> Result<()>;
+}
+
+trait ReadString {
+    fn read_u16_string (&mut self) -> Result<Option<String>>;
+}
+
+impl<W: Write + ?Sized> WriteString for W {
+    fn write_u16_string(&mut self, str: Option<&str>) -> Result<()> {
+        let len = str.as_ref().map (|s| s.len()).unwrap_or (0);
+        ensure!(
+            len <= u16::MAX.into(),
+            "invalid string length given to write_u16_string: {}", len
+        );
+        self.write_u16::<NetworkEndian>(len as u16).chain_err (|| "can't write string length")?;
+        if let Some(msg) = str {
+            write!(self, "{}", msg).chain_err (|| "can't write string contents")?;
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read + ?Sized> ReadString for R {
+    fn read_u16_string (&mut self) -> Result<Option<String>> {
+        let msg_length = self.read_u16::<NetworkEndian>()
+            .chain_err (|| "failed to read expected u16 string length")?;
+        trace!("read_u16_string: received msg_length: {}", msg_length);
+        let mut msg_buffer: Vec<u8> = Vec::with_capacity (msg_length.into());
+        self.take (msg_length.into()).read_to_end (&mut msg_buffer)
+            .chain_err (|| format!("failed to read string content of {} bytes", msg_length))?;
+        // The contents aren't traced, as they can be secrets (e.g. the token of `SetRenewer`).
+        Ok(if !msg_buffer.is_empty() { String::from_utf8(msg_buffer).ok() } else { None })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Event {
+    IPRenewed,
+    /// Renewals are available again, as they were made unavailable for a limited time (see
+    /// `RenewAvailability::UnavailableFor`).
+    UnavailabilityExpired,
+    /// The public IP address changed although no renewal was requested, e.g. because the ISP
+    /// reassigned it (see `Server::check_ip`).
+    IPChanged,
+    /// The renewer succeeded, but the public IP address didn't change (see
+    /// `Server::verify_renewals`).
+    IPUnchanged,
+    /// A renewal is about to start, followed by `IPRenewed`, `IPUnchanged` or `RenewalFailed`.
+    RenewalStarted,
+    /// A renewal failed, for the given reason.
+    RenewalFailed(String),
+    /// The availability of renewals was changed (see `Server::set_availability`).
+    AvailabilityChanged(RenewAvailability),
+    /// The server is shutting down, and won't notify anything else.
+    ServerShuttingDown
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::IPRenewed => write!(f, "ip renewed"),
+            Event::UnavailabilityExpired => write!(f, "unavailability expired"),
+            Event::IPChanged => write!(f, "ip changed"),
+            Event::IPUnchanged => write!(f, "ip unchanged"),
+            Event::RenewalStarted => write!(f, "renewal started"),
+            Event::RenewalFailed(ref reason) if reason.is_empty() => write!(f, "renewal failed"),
+            Event::RenewalFailed(ref reason) => write!(f, "renewal failed: {}", reason),
+            Event::AvailabilityChanged(ref availability) =>
+                write!(f, "availability changed to {}", availability),
+            Event::ServerShuttingDown => write!(f, "server shutting down")
+        }
+    }
+}
+
+// Representation (packet number not included): the event number, followed by
+// - RenewalFailed: serialization of the reason string
+// - AvailabilityChanged: serialization of the availability (see `RenewAvailability`)
+impl Event {
+    /// Every kind of event. Those carrying data carry placeholders (e.g. an empty reason).
+    pub const ALL: &'static [Event] = &[
+        Event::IPRenewed, Event::UnavailabilityExpired, Event::IPChanged, Event::IPUnchanged,
+        Event::RenewalStarted, Event::RenewalFailed (String::new()),
+        Event::AvailabilityChanged (RenewAvailability::Available), Event::ServerShuttingDown
+    ];
+    /// The names of `ALL`, see `name`.
+    pub const NAMES: &'static [&'static str] = &[
+        "ip_renewed", "unavailability_expired", "ip_changed", "ip_unchanged", "renewal_started",
+        "renewal_failed", "availability_changed", "server_shutting_down"
+    ];
+
+    /// Looks up an event by its name, see `name`. Events carrying data carry placeholders, as in
+    /// `ALL`.
+    pub fn from_name (name: &str) -> Option<Event> {
+        Event::ALL.iter().find (|event| event.name() == name).cloned()
+    }
+
+    pub fn extended_descr(&self) -> &'static str {
+        match *self {
+            Event::IPRenewed => "An IP renewal has been requested",
+            Event::UnavailabilityExpired => "Renewals are available again",
+            Event::IPChanged => "The IP address changed without a renewal",
+            Event::IPUnchanged => "The IP address was renewed, but it didn't change",
+            Event::RenewalStarted => "An IP renewal has started",
+            Event::RenewalFailed(_) => "An IP renewal has failed",
+            Event::AvailabilityChanged(_) => "The availability of renewals has changed",
+            Event::ServerShuttingDown => "The server is shutting down"
+        }
+    }
+
+    /// The name of the event in the configuration, e.g. in `client.toasts.events`. Events of the
+    /// same kind share it, whatever their data.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Event::IPRenewed => "ip_renewed",
+            Event::UnavailabilityExpired => "unavailability_expired",
+            Event::IPChanged => "ip_changed",
+            Event::IPUnchanged => "ip_unchanged",
+            Event::RenewalStarted => "renewal_started",
+            Event::RenewalFailed(_) => "renewal_failed",
+            Event::AvailabilityChanged(_) => "availability_changed",
+            Event::ServerShuttingDown => "server_shutting_down"
+        }
+    }
+
+    /// The number of the event in `Packet::Event`.
+    pub fn number(&self) -> u8 {
+        match *self {
+            Event::IPRenewed => 0,
+            Event::UnavailabilityExpired => 1,
+            Event::IPChanged => 2,
+            Event::IPUnchanged => 3,
+            Event::RenewalStarted => 4,
+            Event::RenewalFailed(_) => 5,
+            Event::AvailabilityChanged(_) => 6,
+            Event::ServerShuttingDown => 7
+        }
+    }
+
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let number = reader.read_u8().chain_err (|| "failed to read the event number")?;
+        let event = match number {
+            5 => Event::RenewalFailed (
+                reader.read_u16_string()
+                    .chain_err (|| "failed to read Event::RenewalFailed reason")?
+                    .unwrap_or_default()
+            ),
+            6 => Event::AvailabilityChanged (
+                RenewAvailability::read (reader)
+                    .chain_err (|| "failed to read Event::AvailabilityChanged availability")?
+            ),
+            _ => Event::ALL.iter().find (|event| event.number() == number).cloned()
+                .chain_err (|| format!("unknown event number: {}", number))?
+        };
+        Ok(event)
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_u8 (self.number())
+            .chain_err (|| format!("failed to write event number '{}'", self.number()))?;
+        match *self {
+            Event::RenewalFailed(ref reason) => writer.write_u16_string (Some (reason))
+                .chain_err (|| "failed to write Event::RenewalFailed reason")?,
+            Event::AvailabilityChanged(ref availability) => availability.write (writer)
+                .chain_err (|| "failed to write Event::AvailabilityChanged availability")?,
+            _ => ()
+        }
+        Ok(())
+    }
+}
+
+// Events are deserialized from their names (see `Event::name`).
+impl<'de> Deserialize<'de> for Event {
+    fn deserialize<D: Deserializer<'de>> (deserializer: D) -> std::result::Result<Self, D::Error> {
+        let name = String::deserialize (deserializer)?;
+        Event::from_name (&name).ok_or_else (|| de::Error::unknown_variant (&name, Event::NAMES))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum RenewAvailability {
+    Available,
+    Unavailable(String),
+    /// Unavailable for a limited time, after which renewals are available again.
+    UnavailableFor(String, Duration)
+}
+
+impl fmt::Display for RenewAvailability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenewAvailability::Available => write!(f, "available"),
+            RenewAvailability::Unavailable(ref msg) => write!(f, "unavailable due to \"{}\"", msg),
+            RenewAvailability::UnavailableFor(ref msg, duration) => write!(
+                f, "unavailable due to \"{}\" for {}", msg, format_duration (duration)
+            )
+        }
+    }
+}
+
+// Representation (packet number not included):
+// - Available: \x00
+// - Unavailable: \x01 + serialization of the associated string
+// - UnavailableFor: \x02 + seconds (u32) + serialization of the associated string
+impl RenewAvailability {
+    /// Why renewals are unavailable, unless they're available.
+    pub fn reason (&self) -> Option<&str> {
+        match *self {
+            RenewAvailability::Available => None,
+            RenewAvailability::Unavailable(ref reason) |
+            RenewAvailability::UnavailableFor(ref reason, _) => Some (reason)
+        }
+    }
+
+    fn repr (&self) -> u8 {
+        match *self {
+            RenewAvailability::Available         => 0,
+            RenewAvailability::Unavailable(_)    => 1,
+            RenewAvailability::UnavailableFor(..) => 2
+        }
+    }
+
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let variant = reader.read_u8().chain_err (|| "failed to read RenewAvailability variant")?;
+        match variant {
+            0 /* available */   => Ok(RenewAvailability::Available),
+            1 /* unavailable */ => {
+                let reason = reader.read_u16_string()
+                    .chain_err (|| "failed to read RenewAvailability reason string")?  // Result<T>
+                    .chain_err (|| "RenewAvailability reason string can't be empty")?; // Option<T>
+                Ok(RenewAvailability::Unavailable(reason))
+            },
+            2 /* unavailable for */ => {
+                let seconds = reader.read_u32::<NetworkEndian>()
+                    .chain_err (|| "failed to read RenewAvailability duration")?;
+                let reason = reader.read_u16_string()
+                    .chain_err (|| "failed to read RenewAvailability reason string")?
+                    .chain_err (|| "RenewAvailability reason string can't be empty")?;
+                Ok(RenewAvailability::UnavailableFor(reason, Duration::from_secs (seconds.into())))
+            },
+            _ => bail!("unknown RenewAvailability variant: {}", variant)
+        }
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_u8 (self.repr())
+            .chain_err (|| "failed to write RenewAvailability variant")?;
+        match *self {
+            RenewAvailability::Available => (),
+            RenewAvailability::Unavailable(ref reason) => {
+                writer.write_u16_string (Some (reason))
+                    .chain_err (|| "failed to write RenewAvailability reason")?;
+            },
+            RenewAvailability::UnavailableFor(ref reason, duration) => {
+                let seconds = u32::try_from (duration.as_secs())
+                    .chain_err (|| "RenewAvailability duration is too long")?;
+                writer.write_u32::<NetworkEndian>(seconds)
+                    .chain_err (|| "failed to write RenewAvailability duration")?;
+                writer.write_u16_string (Some (reason))
+                    .chain_err (|| "failed to write RenewAvailability reason")?;
+            }
+        };
+        Ok(())
+    }
+}
+
+/// What a router tells about its connection to the Internet, see `Renewer::link_info`. Routers
+/// only fill in what they expose.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LinkInfo {
+    /// The state of the connection as reported by the router, e.g. "Connected" or "Training".
+    pub state: Option<String>,
+    /// How long the connection has been up.
+    pub uptime: Option<Duration>,
+    /// The rate at which the link is synchronized, in kbit/s.
+    pub downstream_rate: Option<u32>,
+    pub upstream_rate: Option<u32>,
+    pub external_ip: Option<IpAddr>
+}
+
+impl fmt::Display for LinkInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ref state) = self.state {
+            parts.push (format!("state \"{}\"", state));
+        }
+        if let Some(uptime) = self.uptime {
+            parts.push (format!("up for {}", format_duration (uptime)));
+        }
+        if self.downstream_rate.is_some() || self.upstream_rate.is_some() {
+            let rate = |rate: Option<u32>| rate.map_or ("?".into(), |rate| rate.to_string());
+            parts.push (format!("{}/{} kbit/s",
+                rate (self.downstream_rate), rate (self.upstream_rate)));
+        }
+        if let Some(external_ip) = self.external_ip {
+            parts.push (format!("external IP {}", external_ip));
+        }
+        if parts.is_empty() {
+            write!(f, "no information")
+        } else {
+            write!(f, "{}", parts.join (", "))
+        }
+    }
+}
+
+// Representation: a byte whose bits tell which fields follow (state, uptime, downstream rate,
+// upstream rate and external IP, from the least significant one), followed by them: the state
+// and the external IP are strings, the uptime (in seconds) and the rates are u32.
+impl LinkInfo {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let fields = reader.read_u8().chain_err (|| "failed to read LinkInfo fields")?;
+        let read_u32 = |reader: &mut dyn Read, bit: u8, name: &str| -> Result<Option<u32>> {
+            if fields & bit == 0 {
+                return Ok(None);
+            }
+            reader.read_u32::<NetworkEndian>()
+                .map (Some)
+                .chain_err (|| format!("failed to read LinkInfo {}", name))
+        };
+        let mut link = LinkInfo::default();
+        if fields & 1 != 0 {
+            link.state = reader.read_u16_string()
+                .chain_err (|| "failed to read LinkInfo state")?;
+        }
+        link.uptime = read_u32 (reader, 2, "uptime")?
+            .map (|seconds| Duration::from_secs (seconds.into()));
+        link.downstream_rate = read_u32 (reader, 4, "downstream rate")?;
+        link.upstream_rate = read_u32 (reader, 8, "upstream rate")?;
+        if fields & 16 != 0 {
+            link.external_ip = reader.read_u16_string()
+                .chain_err (|| "failed to read LinkInfo external IP")?
+                .chain_err (|| "LinkInfo external IP can't be empty")?
+                .parse().ok();
+        }
+        Ok(link)
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        let fields = [
+            self.state.is_some(),
+            self.uptime.is_some(),
+            self.downstream_rate.is_some(),
+            self.upstream_rate.is_some(),
+            self.external_ip.is_some()
+        ].iter().enumerate().fold (0, |fields, (bit, &present)| fields | (present as u8) << bit);
+        writer.write_u8 (fields).chain_err (|| "failed to write LinkInfo fields")?;
+        if let Some(ref state) = self.state {
+            writer.write_u16_string (Some (state))
+                .chain_err (|| "failed to write LinkInfo state")?;
+        }
+        let uptime = self.uptime
+            .map (|uptime| u32::try_from (uptime.as_secs()).unwrap_or (u32::MAX));
+        for value in [uptime, self.downstream_rate, self.upstream_rate].iter().flatten() {
+            writer.write_u32::<NetworkEndian>(*value)
+                .chain_err (|| "failed to write LinkInfo")?;
+        }
+        if let Some(external_ip) = self.external_ip {
+            writer.write_u16_string (Some (&external_ip.to_string()))
+                .chain_err (|| "failed to write LinkInfo external IP")?;
+        }
+        Ok(())
+    }
+}
+
+/// What's known about a renewal, returned by `Renewer::renew_ip`. Renewers only fill in what they
+/// can tell, e.g. the address the router reported before reconnecting.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RenewOutcome {
+    /// The public IP address before the renewal.
+    pub old_ip: Option<IpAddr>,
+    /// The public IP address after the renewal.
+    pub new_ip: Option<IpAddr>
+}
+
+impl RenewOutcome {
+    /// Whether nothing is known about the renewal.
+    pub fn is_empty (&self) -> bool {
+        self.old_ip.is_none() && self.new_ip.is_none()
+    }
+}
+
+impl fmt::Display for RenewOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.old_ip, self.new_ip) {
+            (Some(old_ip), Some(new_ip)) => write!(f, "from {} to {}", old_ip, new_ip),
+            (Some(old_ip), None) => write!(f, "from {}", old_ip),
+            (None, Some(new_ip)) => write!(f, "to {}", new_ip),
+            (None, None) => write!(f, "no information")
+        }
+    }
+}
+
+// Representation: a byte whose bits tell which addresses follow (the old and the new one, from the
+// least significant one), followed by them as strings.
+impl RenewOutcome {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let fields = reader.read_u8().chain_err (|| "failed to read RenewOutcome fields")?;
+        let mut read_ip = |bit: u8, name: &str| -> Result<Option<IpAddr>> {
+            if fields & bit == 0 {
+                return Ok(None);
+            }
+            reader.read_u16_string()
+                .chain_err (|| format!("failed to read RenewOutcome {} IP", name))?
+                .chain_err (|| format!("RenewOutcome {} IP can't be empty", name))?
+                .parse()
+                .map (Some)
+                .chain_err (|| format!("RenewOutcome contains an invalid {} IP", name))
+        };
+        let old_ip = read_ip (1, "old")?;
+        let new_ip = read_ip (2, "new")?;
+        Ok(RenewOutcome { old_ip, new_ip })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        let fields = self.old_ip.is_some() as u8 | (self.new_ip.is_some() as u8) << 1;
+        writer.write_u8 (fields).chain_err (|| "failed to write RenewOutcome fields")?;
+        for ip in [self.old_ip, self.new_ip].iter().flatten() {
+            writer.write_u16_string (Some (&ip.to_string()))
+                .chain_err (|| "failed to write RenewOutcome IP")?;
+        }
+        Ok(())
+    }
+}
+
+/// What's known about an event besides what happened, see `Packet::Event`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EventPayload {
+    /// When the event happened, to the second.
+    pub timestamp: Option<SystemTime>,
+    /// The host name of the server which notified the event.
+    pub origin: Option<String>,
+    /// What's known about the renewal which caused the event (empty for events not caused by
+    /// renewals).
+    pub outcome: RenewOutcome,
+    /// The name of the token of the client whose request caused the event, if it authenticated
+    /// with one (see `acl::Token`).
+    pub requested_by: Option<String>,
+    /// How many identical events this one stands for, if a burst of them was coalesced (see
+    /// `notifier::Coalescer`).
+    pub coalesced: Option<u32>
+}
+
+impl EventPayload {
+    /// The payload of an event happening now on `origin`.
+    pub fn now (origin: Option<String>, outcome: RenewOutcome) -> EventPayload {
+        EventPayload {
+            timestamp: Some (SystemTime::now()),
+            origin,
+            outcome,
+            requested_by: None,
+            coalesced: None
+        }
+    }
+
+    /// Whether nothing is known about the event.
+    pub fn is_empty (&self) -> bool {
+        self.timestamp.is_none() && self.origin.is_none() && self.outcome.is_empty()
+            && self.requested_by.is_none() && self.coalesced.is_none()
+    }
+}
+
+// Shows the origin, the outcome, who requested it and how many events were coalesced, e.g. "by
+// router, from 203.0.113.7 to 192.0.2.1, requested by alice, 3 events coalesced".
+impl fmt::Display for EventPayload {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut parts = Vec::new();
+        if let Some(ref origin) = self.origin {
+            parts.push (format!("by {}", origin));
+        }
+        if !self.outcome.is_empty() ||
+            parts.is_empty() && self.requested_by.is_none() && self.coalesced.is_none()
+        {
+            parts.push (self.outcome.to_string());
+        }
+        if let Some(ref requested_by) = self.requested_by {
+            parts.push (format!("requested by {}", requested_by));
+        }
+        if let Some(coalesced) = self.coalesced {
+            parts.push (format!("{} events coalesced", coalesced));
+        }
+        f.write_str (&parts.join (", "))
+    }
+}
+
+impl From<RenewOutcome> for EventPayload {
+    fn from (outcome: RenewOutcome) -> EventPayload {
+        EventPayload { outcome, ..EventPayload::default() }
+    }
+}
+
+// Representation: the seconds since the Unix epoch (`u64`, 0 if unknown), the origin (string, empty
+// if unknown) and the outcome (as in `Renewed`).
+impl EventPayload {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let seconds = reader.read_u64::<NetworkEndian>()
+            .chain_err (|| "failed to read EventPayload timestamp")?;
+        let origin = reader.read_u16_string().chain_err (|| "failed to read EventPayload origin")?;
+        let outcome = RenewOutcome::read (reader)
+            .chain_err (|| "failed to read EventPayload outcome")?;
+        let timestamp = Some (seconds)
+            .filter (|seconds| *seconds > 0)
+            .and_then (|seconds| UNIX_EPOCH.checked_add (Duration::from_secs (seconds)));
+        Ok(EventPayload { timestamp, origin, outcome, requested_by: None, coalesced: None })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        let seconds = self.timestamp
+            .and_then (|timestamp| timestamp.duration_since (UNIX_EPOCH).ok())
+            .map_or (0, |elapsed| elapsed.as_secs());
+        writer.write_u64::<NetworkEndian>(seconds)
+            .chain_err (|| "failed to write EventPayload timestamp")?;
+        writer.write_u16_string (self.origin.as_deref())
+            .chain_err (|| "failed to write EventPayload origin")?;
+        self.outcome.write (writer).chain_err (|| "failed to write EventPayload outcome")
+    }
+}
+
+/// The status of a server, sent in response to `Packet::StatusRequest`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Status {
+    /// The renewer in use, i.e. its key in `server.renewer`, if the server knows it.
+    pub renewer: Option<String>,
+    pub availability: RenewAvailability,
+    /// What the router tells about its link, if the renewer supports it and the router answered.
+    pub link: Option<LinkInfo>
+}
+
+// Representation: the renewer (string, empty if unknown), the availability (as in
+// `SetRenewingAvailable`) and \x00 without link information, or \x01 followed by it.
+impl Status {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let renewer = reader.read_u16_string().chain_err (|| "failed to read Status renewer")?;
+        let availability = RenewAvailability::read (reader)
+            .chain_err (|| "failed to read Status availability")?;
+        let link = match reader.read_u8().chain_err (|| "failed to read Status link")? {
+            0 => None,
+            _ => Some (LinkInfo::read (reader)?)
+        };
+        Ok(Status { renewer, availability, link })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_u16_string (self.renewer.as_deref())
+            .chain_err (|| "failed to write Status renewer")?;
+        self.availability.write (writer)
+            .chain_err (|| "failed to write Status availability")?;
+        writer.write_u8 (self.link.is_some() as u8)
+            .chain_err (|| "failed to write Status link")?;
+        if let Some(ref link) = self.link {
+            link.write (writer)?;
+        }
+        Ok(())
+    }
+}
+
+/// The version of the protocol spoken by this build, advertised in `Capabilities`. Bumped whenever
+/// requests are added, so that clients can tell whether a server supports them.
+pub const PROTOCOL_VERSION: u16 = 1;
+
+/// What a server supports, sent in response to `Packet::CapabilitiesRequest`. Servers which don't
+/// answer it predate version 1 of the protocol.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The version of the protocol spoken by the server, see `PROTOCOL_VERSION`.
+    pub version: u16,
+    /// The renewer in use, i.e. its key in `server.renewer`, if the server knows it.
+    pub renewer: Option<String>,
+    /// Whether clients must authenticate with the pre-shared key of the server.
+    pub auth: bool,
+    /// Whether the server keeps connections open between requests, see `Server::idle_timeout`.
+    pub keep_alive: bool,
+    /// Whether clients can ask the server to reload its configuration, as administrators.
+    pub reload: bool,
+    /// Whether clients can switch the server to another renewer, with its admin token.
+    pub set_renewer: bool,
+    /// Whether clients can ask the server for its status and for the health of its renewer.
+    pub status: bool,
+    /// Whether the server keeps a history of renewals, see `Server::history_size`.
+    pub history: bool,
+    /// Whether the server renews the IP address on its own, on a schedule.
+    pub schedule: bool
+}
+
+impl Capabilities {
+    /// Whether the server answers `request`, rather than refusing it as unsupported.
+    pub fn supports (&self, request: &Packet) -> bool {
+        match *request {
+            Packet::ReloadConfig { .. } => self.reload,
+            Packet::SetRenewer { .. } => self.set_renewer,
+            Packet::StatusRequest | Packet::HealthRequest => self.status,
+            Packet::HistoryRequest { .. } => self.history,
+            // Every other request is part of version 1.
+            _ => true
+        }
+    }
+}
+
+// Representation: the version (`u16`), the renewer (string, empty if unknown) and a byte of flags
+// telling, from the least significant bit, whether auth, keep-alive, reloading, switching
+// renewers, the status, the history and the schedule are supported.
+impl Capabilities {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let version = reader.read_u16::<NetworkEndian>()
+            .chain_err (|| "failed to read Capabilities version")?;
+        let renewer = reader.read_u16_string()
+            .chain_err (|| "failed to read Capabilities renewer")?;
+        let flags = reader.read_u8().chain_err (|| "failed to read Capabilities flags")?;
+        Ok(Capabilities {
+            version,
+            renewer,
+            auth: flags & 0b0000001 != 0,
+            keep_alive: flags & 0b0000010 != 0,
+            reload: flags & 0b0000100 != 0,
+            set_renewer: flags & 0b0001000 != 0,
+            status: flags & 0b0010000 != 0,
+            history: flags & 0b0100000 != 0,
+            schedule: flags & 0b1000000 != 0
+        })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_u16::<NetworkEndian>(self.version)
+            .chain_err (|| "failed to write Capabilities version")?;
+        writer.write_u16_string (self.renewer.as_deref())
+            .chain_err (|| "failed to write Capabilities renewer")?;
+        let flags = self.auth as u8 | (self.keep_alive as u8) << 1 | (self.reload as u8) << 2 |
+            (self.set_renewer as u8) << 3 | (self.status as u8) << 4 | (self.history as u8) << 5 |
+            (self.schedule as u8) << 6;
+        writer.write_u8 (flags).chain_err (|| "failed to write Capabilities flags")?;
+        Ok(())
+    }
+}
+
+/// The health of a renewer, see `Renewer::health_check`. Renewers only fill in what they can tell.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the router answers.
+    pub reachable: Option<bool>,
+    /// Whether the router accepts the credentials of the renewer.
+    pub credentials_valid: Option<bool>,
+    /// Whether the renewer has a session with the router.
+    pub logged_in: Option<bool>,
+    /// What's wrong, if anything.
+    pub problem: Option<String>
+}
+
+impl Health {
+    /// A router which doesn't answer.
+    pub fn unreachable<S: Into<String>> (problem: S) -> Health {
+        Health { reachable: Some (false), problem: Some (problem.into()), ..Health::default() }
+    }
+
+    /// A router which accepted the credentials of the renewer, which is logged in.
+    pub fn logged_in() -> Health {
+        Health {
+            reachable: Some (true),
+            credentials_valid: Some (true),
+            logged_in: Some (true),
+            problem: None
+        }
+    }
+
+    /// A router which answers, but refuses the credentials of the renewer.
+    pub fn login_failed<S: Into<String>> (problem: S) -> Health {
+        Health {
+            reachable: Some (true),
+            credentials_valid: Some (false),
+            logged_in: Some (false),
+            problem: Some (problem.into())
+        }
+    }
+
+    /// Whether nothing is known to be wrong.
+    pub fn is_healthy (&self) -> bool {
+        self.problem.is_none() &&
+            [self.reachable, self.credentials_valid, self.logged_in].iter()
+                .all (|&flag| flag != Some (false))
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let flag = |flag: Option<bool>, yes: &'static str, no: &'static str|
+            flag.map (|flag| if flag { yes } else { no });
+        let mut parts: Vec<String> = [
+            flag (self.reachable, "reachable", "unreachable"),
+            flag (self.credentials_valid, "credentials valid", "credentials refused"),
+            flag (self.logged_in, "logged in", "not logged in")
+        ].iter().flatten().map (|part| part.to_string()).collect();
+        if let Some(ref problem) = self.problem {
+            parts.push (format!("problem \"{}\"", problem));
+        }
+        let verdict = if self.is_healthy() { "healthy" } else { "unhealthy" };
+        match parts.is_empty() {
+            true => write!(f, "{}", verdict),
+            false => write!(f, "{} ({})", verdict, parts.join (", "))
+        }
+    }
+}
+
+// Representation: a byte whose bits tell, from the least significant one, whether `reachable`
+// is known, its value, the same for `credentials_valid` and `logged_in`, and whether a problem
+// (a string) follows.
+impl Health {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let fields = reader.read_u8().chain_err (|| "failed to read Health fields")?;
+        let flag = |bit: u8| if fields & (1 << bit) != 0 {
+            Some (fields & (1 << (bit + 1)) != 0)
+        } else {
+            None
+        };
+        let problem = match fields & (1 << 6) {
+            0 => None,
+            _ => Some (reader.read_u16_string()
+                .chain_err (|| "failed to read Health problem")?
+                .unwrap_or_default())
+        };
+        Ok(Health {
+            reachable: flag (0),
+            credentials_valid: flag (2),
+            logged_in: flag (4),
+            problem
+        })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        let fields = [self.reachable, self.credentials_valid, self.logged_in].iter()
+            .enumerate()
+            .fold (0, |fields, (i, flag)| match *flag {
+                Some(value) => fields | 1 << (i * 2) | (value as u8) << (i * 2 + 1),
+                None => fields
+            }) | (self.problem.is_some() as u8) << 6;
+        writer.write_u8 (fields).chain_err (|| "failed to write Health fields")?;
+        if let Some(ref problem) = self.problem {
+            writer.write_u16_string (Some (problem))
+                .chain_err (|| "failed to write Health problem")?;
+        }
+        Ok(())
+    }
+}
+
+/// A renewal attempted by a server, see `Packet::History`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    /// When the renewal started, to the second.
+    pub timestamp: SystemTime,
+    /// The address of the client which requested the renewal, `None` if the server renewed on
+    /// its own.
+    pub peer: Option<String>,
+    /// The renewer which renewed, i.e. its key in `server.renewer`, if the server knows it.
+    pub renewer: Option<String>,
+    pub outcome: HistoryOutcome
+}
+
+/// How a renewal in the history went.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HistoryOutcome {
+    /// The IP address was renewed, along with what's known about the renewal.
+    Renewed(RenewOutcome),
+    /// The renewer succeeded, but the IP address (the one given) didn't change.
+    Unchanged(IpAddr),
+    /// The renewal failed, for the given reason.
+    Failed(String)
+}
+
+impl fmt::Display for HistoryOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HistoryOutcome::Renewed(ref outcome) if outcome.is_empty() => write!(f, "renewed"),
+            HistoryOutcome::Renewed(ref outcome) => write!(f, "renewed {}", outcome),
+            HistoryOutcome::Unchanged(ip) => write!(f, "renewed, but still {}", ip),
+            HistoryOutcome::Failed(ref reason) => write!(f, "failed: {}", reason)
+        }
+    }
+}
+
+// Representation: the seconds since the Unix epoch (`u64`), the peer and the renewer (strings,
+// empty if unknown), then \x00 followed by the outcome (as in `Renewed`), \x01 followed by the
+// unchanged IP address (string) or \x02 followed by the reason of the failure (string).
+impl HistoryEntry {
+    fn read (reader: &mut dyn Read) -> Result<Self> {
+        let seconds = reader.read_u64::<NetworkEndian>()
+            .chain_err (|| "failed to read HistoryEntry timestamp")?;
+        let peer = reader.read_u16_string().chain_err (|| "failed to read HistoryEntry peer")?;
+        let renewer = reader.read_u16_string()
+            .chain_err (|| "failed to read HistoryEntry renewer")?;
+        let outcome = match reader.read_u8().chain_err (|| "failed to read HistoryEntry outcome")? {
+            0 => HistoryOutcome::Renewed (
+                RenewOutcome::read (reader).chain_err (|| "failed to read HistoryEntry outcome")?
+            ),
+            1 => HistoryOutcome::Unchanged (
+                reader.read_u16_string()
+                    .chain_err (|| "failed to read HistoryEntry address")?
+                    .chain_err (|| "HistoryEntry requires an address")?
+                    .parse()
+                    .chain_err (|| "HistoryEntry contains an invalid address")?
+            ),
+            2 => HistoryOutcome::Failed (
+                reader.read_u16_string()
+                    .chain_err (|| "failed to read HistoryEntry reason")?
+                    .unwrap_or_default()
+            ),
+            outcome => bail!("unknown HistoryEntry outcome: {}", outcome)
+        };
+        let timestamp = UNIX_EPOCH.checked_add (Duration::from_secs (seconds))
+            .chain_err (|| "HistoryEntry contains an invalid timestamp")?;
+        Ok(HistoryEntry { timestamp, peer, renewer, outcome })
+    }
+
+    fn write (&self, writer: &mut dyn Write) -> Result<()> {
+        let seconds = self.timestamp.duration_since (UNIX_EPOCH)
+            .map_or (0, |since| since.as_secs());
+        writer.write_u64::<NetworkEndian>(seconds)
+            .chain_err (|| "failed to write HistoryEntry timestamp")?;
+        writer.write_u16_string (self.peer.as_deref())
+            .chain_err (|| "failed to write HistoryEntry peer")?;
+        writer.write_u16_string (self.renewer.as_deref())
+            .chain_err (|| "failed to write HistoryEntry renewer")?;
+        match self.outcome {
+            HistoryOutcome::Renewed(ref outcome) => {
+                writer.write_u8 (0).chain_err (|| "failed to write HistoryEntry outcome")?;
+                outcome.write (writer).chain_err (|| "failed to write HistoryEntry outcome")
+            },
+            HistoryOutcome::Unchanged(ip) => {
+                writer.write_u8 (1).chain_err (|| "failed to write HistoryEntry outcome")?;
+                writer.write_u16_string (Some (&ip.to_string()))
+                    .chain_err (|| "failed to write HistoryEntry address")
+            },
+            HistoryOutcome::Failed(ref reason) => {
+                writer.write_u8 (2).chain_err (|| "failed to write HistoryEntry outcome")?;
+                writer.write_u16_string (Some (reason))
+                    .chain_err (|| "failed to write HistoryEntry reason")
+            }
+        }
+    }
+}
+
+/// The class of an error sent to clients (see `Packet::Error`), so that they can tell failures
+/// apart without parsing the message which describes them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// Renewals are unavailable, see `RenewAvailability`.
+    Unavailable,
+    /// The renewer failed to renew the IP address, or timed out.
+    RenewerFailure,
+    /// The client didn't authenticate, or isn't allowed to make the request.
+    Unauthorized,
+    /// The request is invalid, or this server doesn't support it.
+    Unsupported,
+    /// The client made too many requests, and should try again later.
+    RateLimited,
+    /// Any other failure, including the errors of older servers (which carry no code).
+    Internal
+}
+
+impl ErrorCode {
+    /// Every error code.
+    pub const ALL: &'static [ErrorCode] = &[
+        ErrorCode::Unavailable, ErrorCode::RenewerFailure, ErrorCode::Unauthorized,
+        ErrorCode::Unsupported, ErrorCode::RateLimited, ErrorCode::Internal
+    ];
+
+    /// The name of the error code, e.g. "renewer_failure".
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ErrorCode::Unavailable => "unavailable",
+            ErrorCode::RenewerFailure => "renewer_failure",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::Unsupported => "unsupported",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Internal => "internal"
+        }
+    }
+
+    /// The number of the error code in `Packet::Error`.
+    pub fn number(&self) -> u8 {
+        match *self {
+            ErrorCode::Unavailable => 0,
+            ErrorCode::RenewerFailure => 1,
+            ErrorCode::Unauthorized => 2,
+            ErrorCode::Unsupported => 3,
+            ErrorCode::RateLimited => 4,
+            ErrorCode::Internal => 5
+        }
+    }
+
+    // Codes unknown to this version (sent by newer servers) are read as `Internal`.
+    fn from_number (number: u8) -> ErrorCode {
+        ErrorCode::ALL.iter().cloned().find (|code| code.number() == number)
+            .unwrap_or (ErrorCode::Internal)
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Packet {
+    // client -> server
+    /// Asks for a fresh IP address, renewed by the renewer of the server or by another of its
+    /// renewers (its key in `server.renewer`, e.g. "wan2").
+    FreshIPRequest { renewer: Option<String> },
+    SetRenewingAvailable(RenewAvailability),
+    /// Reloads the configuration of the server, authenticated by the admin token of the server
+    /// (empty for clients which authenticated with a token of scope `admin`).
+    ReloadConfig { token: Secret<String> },
+    /// Switches the server to another of its renewers, authenticated by the admin token of the
+    /// server.
+    SetRenewer { renewer: String, token: Secret<String> },
+    /// Asks the server for its status, answered with `Status`.
+    StatusRequest,
+    /// Asks the server about the health of its renewer, answered with `Health`.
+    HealthRequest,
+    /// Answers `AuthChallenge` with the `auth_response` to its nonce, before the first request
+    /// of the connection.
+    AuthResponse([u8; AUTH_LEN]),
+    /// Asks the server for its latest `limit` renewals, answered with `History`.
+    HistoryRequest { limit: u16 },
+    /// Subscribes to the events of the server, answered with `Ok`: the server then sends every
+    /// `Event` over the connection, until the client disconnects.
+    Subscribe,
+    /// Asks the server what it supports, answered with `Capabilities`.
+    CapabilitiesRequest,
+    // server -> client
+    Ok,
+    /// The request failed: `code` tells why, and `detail` describes it.
+    Error { code: ErrorCode, detail: Option<String> },
+    /// An event, along with what's known about it.
+    Event(Event, EventPayload),
+    Status(Status),
+    /// Sent instead of `Ok` in response to `FreshIPRequest` when the renewer succeeded, but the
+    /// IP address (the one given) didn't change, see `Server::verify_renewals`.
+    IPUnchanged(IpAddr),
+    /// The health of the renewer, as found by the latest check (`age` ago), see
+    /// `Server::check_health`.
+    Health { health: Health, age: Duration },
+    /// Sent instead of `Ok` in response to `FreshIPRequest` when the renewal succeeded and
+    /// something is known about it (see `RenewOutcome`).
+    Renewed(RenewOutcome),
+    /// Sent by servers which require a pre-shared key as soon as a client connects, along with a
+    /// nonce: requests are only accepted after the client answers with `AuthResponse`.
+    AuthChallenge([u8; AUTH_LEN]),
+    /// The renewals attempted by the server, most recent first, see `Server::history`.
+    History(Vec<HistoryEntry>),
+    Capabilities(Capabilities)
+}
+
+/// The length of the nonce of `Packet::AuthChallenge` and of `Packet::AuthResponse`.
+pub const AUTH_LEN: usize = 32;
+
+/// The answer to the nonce of an `AuthChallenge`: its HMAC-SHA256, keyed by the pre-shared key
+/// `psk`.
+#[cfg(any(feature = "client", feature = "server"))]
+pub fn auth_response (psk: &Secret<String>, nonce: &[u8; AUTH_LEN]) -> [u8; AUTH_LEN] {
+    use hmac::{Hmac, Mac};
+    let mut mac = Hmac::<sha2::Sha256>::new_varkey (psk.expose().as_bytes())
+        .expect ("HMAC accepts keys of any length");
+    mac.input (nonce);
+    let mut response = [0; AUTH_LEN];
+    response.copy_from_slice (&mac.result().code());
+    response
+}
+
+use std::ops::Deref;
+
+impl<T: Deref<Target = dyn error::Error>> From<T> for Packet {
+    fn from(error: T) -> Self {
+        Packet::Error { code: ErrorCode::Internal, detail: Some (error.to_string()) }
+    }
+}
+
+// Whether packets are traced, see `set_trace_packets`.
+static TRACE_PACKETS: AtomicBool = AtomicBool::new (false);
+
+/// Enables or disables the hexdump (at trace level) of every packet sent with
+/// [`Packet::send`](enum.Packet.html#method.send), received with
+/// [`Packet::receive`](enum.Packet.html#method.receive) or passed to `trace_packet`.
+pub fn set_trace_packets (enabled: bool) {
+    TRACE_PACKETS.store (enabled, Ordering::Relaxed);
+}
+
+fn tracing_packets() -> bool {
+    TRACE_PACKETS.load (Ordering::Relaxed) && log_enabled!(target: "protocol", log::Level::Trace)
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum Direction {
+    Sent,
+    Received
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Direction::Sent     => write!(f, "sent to"),
+            Direction::Received => write!(f, "received from")
+        }
+    }
+}
+
+/// Logs the hexdump of `bytes`, the serialization of `packet` (if it could be decoded), if packet
+/// traces are enabled. Secrets contained in the packet are redacted.
+pub fn trace_packet (
+    direction: Direction,
+    peer: &dyn fmt::Display,
+    packet: Option<&Packet>,
+    bytes: &[u8]
+) {
+    if !tracing_packets() {
+        return;
+    }
+    let secrets = packet.map (Packet::secret_ranges).unwrap_or_default();
+    trace (direction, peer, packet, bytes, &secrets);
+}
+
+// Logs the hexdump of `bytes` as `trace_packet`, redacting the bytes in `secrets`.
+fn trace (
+    direction: Direction,
+    peer: &dyn fmt::Display,
+    packet: Option<&Packet>,
+    bytes: &[u8],
+    secrets: &[Range<usize>]
+) {
+    let decoded = match packet {
+        Some(packet) => format!("{:?}", packet),
+        None => "undecodable packet".into()
+    };
+    trace!(target: "protocol", "{} {} {} ({} bytes)\n{}",
+        decoded, direction, peer, bytes.len(), hexdump (bytes, secrets));
+}
+
+// Formats `bytes` as lines of 16 bytes in hexadecimal and ASCII, replacing the bytes in `secrets`
+// with asterisks:
+// 0000  03 00 2a                                          |..*|
+fn hexdump (bytes: &[u8], secrets: &[Range<usize>]) -> String {
+    let redacted = |offset: usize| secrets.iter().any (|range| range.contains (&offset));
+    let mut lines = Vec::new();
+    for (line, chunk) in bytes.chunks (16).enumerate() {
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for (i, &byte) in chunk.iter().enumerate() {
+            if redacted (line * 16 + i) {
+                hex += "** ";
+                ascii.push ('*');
+            } else {
+                hex += &format!("{:02x} ", byte);
+                let printable = byte.is_ascii_graphic() || byte == b' ';
+                ascii.push (if printable { byte as char } else { '.' });
+            }
+        }
+        lines.push (format!("{:04x}  {:<48} |{}|", line * 16, hex, ascii));
+    }
+    lines.join ("\n")
+}
+
+// A reader which keeps a copy of the bytes read, used to trace received packets.
+struct RecordingReader<'a> {
+    inner: &'a mut dyn Read,
+    bytes: Vec<u8>
+}
+
+impl<'a> Read for RecordingReader<'a> {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read (buf)?;
+        self.bytes.extend_from_slice (&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// How packets are encoded, see `Packet::send_as` and `Packet::receive_as`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// The binary encoding, described in the README.
+    Binary,
+    /// A JSON object per line, for tools which can't implement the binary encoding (feature
+    /// `json-protocol`), see `Packet::to_json`.
+    #[cfg(feature = "json-protocol")]
+    Json
+}
+
+// Packet numbers
+const PACKET_FRESH_IP_REQUEST:  u8 = 0;
+const PACKET_OK:                u8 = 1;
+const PACKET_ERROR:             u8 = 2;
+const PACKET_EVENT:             u8 = 3;
+const PACKET_SET_RENEW_AVAIL:   u8 = 4;
+const PACKET_RELOAD_CONFIG:     u8 = 5;
+const PACKET_SET_RENEWER:       u8 = 6;
+const PACKET_STATUS_REQUEST:    u8 = 7;
+const PACKET_STATUS:            u8 = 8;
+const PACKET_IP_UNCHANGED:      u8 = 9;
+const PACKET_HEALTH_REQUEST:    u8 = 10;
+const PACKET_HEALTH:            u8 = 11;
+// `FreshIPRequest` selecting a renewer, which older servers refuse rather than misread.
+const PACKET_FRESH_IP_REQUEST_WITH_RENEWER: u8 = 12;
+const PACKET_RENEWED:           u8 = 13;
+// `Event` along with the outcome of a renewal, which older clients ignore rather than misread.
+const PACKET_EVENT_WITH_OUTCOME: u8 = 14;
+const PACKET_AUTH_CHALLENGE:    u8 = 15;
+const PACKET_AUTH_RESPONSE:     u8 = 16;
+// `Event` along with its whole payload, which older clients ignore rather than misread.
+const PACKET_EVENT_WITH_PAYLOAD: u8 = 17;
+// `Error` along with its code. Older servers send `PACKET_ERROR`, whose errors are `Internal`.
+const PACKET_ERROR_WITH_CODE:   u8 = 18;
+const PACKET_HISTORY_REQUEST:   u8 = 19;
+const PACKET_HISTORY:           u8 = 20;
+const PACKET_SUBSCRIBE:         u8 = 21;
+const PACKET_CAPABILITIES_REQUEST: u8 = 22;
+const PACKET_CAPABILITIES:      u8 = 23;
+// `Event` along with its whole payload and who requested it, which older clients ignore rather
+// than misread.
+const PACKET_EVENT_WITH_REQUESTER: u8 = 24;
+// `Event` standing for several coalesced ones, along with its whole payload and who requested
+// it, which older clients ignore rather than misread.
+const PACKET_EVENT_COALESCED:   u8 = 25;
+// `ReloadConfig` along with the admin token, which older servers refuse rather than misread.
+const PACKET_RELOAD_CONFIG_WITH_TOKEN: u8 = 26;
+
+impl Packet {
+    pub fn packet_no(&self) -> u8 {
+        match *self {
+            Packet::FreshIPRequest { renewer: None } => PACKET_FRESH_IP_REQUEST,
+            Packet::FreshIPRequest { .. }   => PACKET_FRESH_IP_REQUEST_WITH_RENEWER,
+            Packet::Ok                      => PACKET_OK,
+            Packet::SetRenewingAvailable(_) => PACKET_SET_RENEW_AVAIL,
+            Packet::ReloadConfig { ref token } if token.expose().is_empty() =>
+                PACKET_RELOAD_CONFIG,
+            Packet::ReloadConfig { .. }     => PACKET_RELOAD_CONFIG_WITH_TOKEN,
+            Packet::SetRenewer { .. }       => PACKET_SET_RENEWER,
+            Packet::StatusRequest           => PACKET_STATUS_REQUEST,
+            Packet::Error { .. }            => PACKET_ERROR_WITH_CODE,
+            Packet::Event(_, ref payload) if payload.is_empty() => PACKET_EVENT,
+            Packet::Event(_, EventPayload { coalesced: Some(_), .. }) => PACKET_EVENT_COALESCED,
+            Packet::Event(_, EventPayload { requested_by: Some(_), .. }) =>
+                PACKET_EVENT_WITH_REQUESTER,
+            Packet::Event(_, EventPayload { timestamp: None, origin: None, .. }) =>
+                PACKET_EVENT_WITH_OUTCOME,
+            Packet::Event(..)               => PACKET_EVENT_WITH_PAYLOAD,
+            Packet::Status(..)              => PACKET_STATUS,
+            Packet::IPUnchanged(..)         => PACKET_IP_UNCHANGED,
+            Packet::HealthRequest           => PACKET_HEALTH_REQUEST,
+            Packet::Health { .. }           => PACKET_HEALTH,
+            Packet::Renewed(..)             => PACKET_RENEWED,
+            Packet::AuthChallenge(..)       => PACKET_AUTH_CHALLENGE,
+            Packet::AuthResponse(..)        => PACKET_AUTH_RESPONSE,
+            Packet::HistoryRequest { .. }   => PACKET_HISTORY_REQUEST,
+            Packet::History(..)             => PACKET_HISTORY,
+            Packet::Subscribe               => PACKET_SUBSCRIBE,
+            Packet::CapabilitiesRequest     => PACKET_CAPABILITIES_REQUEST,
+            Packet::Capabilities(..)        => PACKET_CAPABILITIES
+        }
+    }
+
+    /// Like [`read`](#method.read), but the packet is traced as received from `peer` (see
+    /// `set_trace_packets`).
+    pub fn receive (reader: &mut dyn Read, peer: &dyn fmt::Display) -> Result<Self> {
+        if !tracing_packets() {
+            return Self::read (reader);
+        }
+        let mut recorder = RecordingReader { inner: reader, bytes: Vec::new() };
+        let packet = Self::read (&mut recorder);
+        trace_packet (Direction::Received, peer, packet.as_ref().ok(), &recorder.bytes);
+        packet
+    }
+
+    /// Like [`write`](#method.write), but the packet is traced as sent to `peer` (see
+    /// `set_trace_packets`).
+    pub fn send (&self, writer: &mut dyn Write, peer: &dyn fmt::Display) -> Result<()> {
+        if !tracing_packets() {
+            return self.write (writer);
+        }
+        let mut bytes = Vec::new();
+        self.write (&mut bytes)?;
+        trace_packet (Direction::Sent, peer, Some (self), &bytes);
+        writer.write_all (&bytes).chain_err (|| "failed to write packet")
+    }
+
+    /// Like [`receive`](#method.receive), for a packet in `encoding`.
+    pub fn receive_as<R: BufRead> (encoding: Encoding, reader: &mut R, peer: &dyn fmt::Display)
+        -> Result<Self>
+    {
+        match encoding {
+            Encoding::Binary => Self::receive (reader, peer),
+            #[cfg(feature = "json-protocol")]
+            Encoding::Json => Self::receive_json (reader, peer)
+        }
+    }
+
+    /// Like [`send`](#method.send), in `encoding`.
+    pub fn send_as (&self, encoding: Encoding, writer: &mut dyn Write, peer: &dyn fmt::Display)
+        -> Result<()>
+    {
+        match encoding {
+            Encoding::Binary => self.send (writer, peer),
+            #[cfg(feature = "json-protocol")]
+            Encoding::Json => self.send_json (writer, peer)
+        }
+    }
+
+    // Byte ranges of the serialized packet which contain secrets, redacted by packet traces.
+    fn secret_ranges (&self) -> Vec<Range<usize>> {
+        match *self {
+            // The token follows the packet number and its length.
+            Packet::ReloadConfig { ref token } =>
+                vec![Range { start: 1 + 2, end: 1 + 2 + token.expose().len() }],
+            // The token follows the packet number, the renewer and the length of the token.
+            Packet::SetRenewer { ref renewer, ref token } => {
+                let start = 1 + 2 + renewer.len() + 2;
+                vec![Range { start, end: start + token.expose().len() }]
+            },
+            _ => Vec::new()
+        }
+    }
+
+    pub fn read(reader: &mut dyn Read) -> Result<Self> {
+        let packet_no = reader.read_u8().chain_err (|| "failed to read packet number")?;
+        trace!("Packet::read: received packet number: {}", packet_no);
+
+        let packet = match packet_no {
+            PACKET_FRESH_IP_REQUEST => Packet::FreshIPRequest { renewer: None },
+            PACKET_FRESH_IP_REQUEST_WITH_RENEWER => Packet::FreshIPRequest {
+                renewer: Some (reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::FreshIPRequest renewer")?
+                    .chain_err (|| "Packet::FreshIPRequest requires a renewer")?)
+            },
+            PACKET_OK => Packet::Ok,
+            PACKET_RELOAD_CONFIG => Packet::ReloadConfig { token: String::new().into() },
+            PACKET_RELOAD_CONFIG_WITH_TOKEN => Packet::ReloadConfig {
+                token: reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::ReloadConfig token")?
+                    .unwrap_or_default()
+                    .into()
+            },
+            PACKET_STATUS_REQUEST => Packet::StatusRequest,
+            PACKET_HEALTH_REQUEST => Packet::HealthRequest,
+            PACKET_SUBSCRIBE => Packet::Subscribe,
+            PACKET_CAPABILITIES_REQUEST => Packet::CapabilitiesRequest,
+            PACKET_CAPABILITIES => Packet::Capabilities(
+                Capabilities::read (reader).chain_err (|| "failed to read Packet::Capabilities")?
+            ),
+            PACKET_HEALTH => Packet::Health {
+                age: Duration::from_secs (reader.read_u32::<NetworkEndian>()
+                    .chain_err (|| "failed to read Packet::Health age")?.into()),
+                health: Health::read (reader).chain_err (|| "failed to read Packet::Health")?
+            },
+            PACKET_STATUS => Packet::Status(
+                Status::read (reader).chain_err (|| "failed to read Packet::Status")?
+            ),
+            PACKET_IP_UNCHANGED => Packet::IPUnchanged(
+                reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::IPUnchanged address")?
+                    .chain_err (|| "Packet::IPUnchanged requires an address")?
+                    .parse()
+                    .chain_err (|| "Packet::IPUnchanged contains an invalid address")?
+            ),
+            PACKET_SET_RENEWER => Packet::SetRenewer {
+                renewer: reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::SetRenewer renewer")?
+                    .chain_err (|| "Packet::SetRenewer requires a renewer")?,
+                token: reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::SetRenewer token")?
+                    .unwrap_or_default()
+                    .into()
+            },
+            PACKET_SET_RENEW_AVAIL => {
+                Packet::SetRenewingAvailable(
+                    RenewAvailability::read (reader)
+                        .chain_err (|| "failed to read Packet::RenewAvailability")?
+                )
+            },
+            PACKET_ERROR => Packet::Error {
+                code: ErrorCode::Internal,
+                detail: reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::Error reason")?
+            },
+            PACKET_ERROR_WITH_CODE => Packet::Error {
+                code: ErrorCode::from_number (
+                    reader.read_u8().chain_err (|| "failed to read Packet::Error code")?
+                ),
+                detail: reader.read_u16_string()
+                    .chain_err (|| "failed to read Packet::Error detail")?
+            },
+            PACKET_RENEWED => Packet::Renewed(
+                RenewOutcome::read (reader).chain_err (|| "failed to read Packet::Renewed")?
+            ),
+            PACKET_AUTH_CHALLENGE => {
+                let mut nonce = [0; AUTH_LEN];
+                reader.read_exact (&mut nonce)
+                    .chain_err (|| "failed to read Packet::AuthChallenge nonce")?;
+                Packet::AuthChallenge(nonce)
+            },
+            PACKET_AUTH_RESPONSE => {
+                let mut response = [0; AUTH_LEN];
+                reader.read_exact (&mut response)
+                    .chain_err (|| "failed to read Packet::AuthResponse")?;
+                Packet::AuthResponse(response)
+            },
+            PACKET_HISTORY_REQUEST => Packet::HistoryRequest {
+                limit: reader.read_u16::<NetworkEndian>()
+                    .chain_err (|| "failed to read Packet::HistoryRequest limit")?
+            },
+            PACKET_HISTORY => {
+                let count = reader.read_u16::<NetworkEndian>()
+                    .chain_err (|| "failed to read Packet::History length")?;
+                let entries = (0..count)
+                    .map (|_| HistoryEntry::read (reader))
+                    .collect::<Result<Vec<_>>>()
+                    .chain_err (|| "failed to read Packet::History")?;
+                Packet::History(entries)
+            },
+            PACKET_EVENT | PACKET_EVENT_WITH_OUTCOME | PACKET_EVENT_WITH_PAYLOAD |
+            PACKET_EVENT_WITH_REQUESTER | PACKET_EVENT_COALESCED => {
+                let event = Event::read (reader).chain_err (|| "failed to read Packet::Event")?;
+                let payload = match packet_no {
+                    PACKET_EVENT_WITH_OUTCOME => RenewOutcome::read (reader)
+                        .chain_err (|| "failed to read Packet::Event outcome")?
+                        .into(),
+                    PACKET_EVENT_WITH_PAYLOAD => EventPayload::read (reader)
+                        .chain_err (|| "failed to read Packet::Event payload")?,
+                    PACKET_EVENT_WITH_REQUESTER | PACKET_EVENT_COALESCED => {
+                        let mut payload = EventPayload::read (reader)
+                            .chain_err (|| "failed to read Packet::Event payload")?;
+                        payload.requested_by = reader.read_u16_string()
+                            .chain_err (|| "failed to read Packet::Event requester")?;
+                        if packet_no == PACKET_EVENT_COALESCED {
+                            payload.coalesced = Some (reader.read_u32::<NetworkEndian>()
+                                .chain_err (|| "failed to read Packet::Event count")?);
+                        }
+                        payload
+                    },
+                    _ => EventPayload::default()
+                };
+                Packet::Event(event, payload)
+            },
+            _ => bail!("unknown packet number: {}", packet_no)
+        };
+
+        trace!("Packet::read: finished parsing packet: {:#?}", packet);
+        Ok(packet)
+    }
+
+    pub fn write(&self, writer: &mut dyn Write) -> Result<()> {
+        writer.write_u8 (self.packet_no()).chain_err (|| "failed to write packet number")?;
+        match *self {
+            Packet::FreshIPRequest { renewer: None } | Packet::Ok | Packet::StatusRequest |
+            Packet::HealthRequest | Packet::Subscribe | Packet::CapabilitiesRequest => (),
+            Packet::ReloadConfig { ref token } => if !token.expose().is_empty() {
+                writer.write_u16_string (Some (token.expose()))
+                    .chain_err (|| "failed to write Packet::ReloadConfig token")?
+            },
+            Packet::FreshIPRequest { renewer: Some (ref renewer) } =>
+                writer.write_u16_string (Some (renewer))
+                    .chain_err (|| "failed to write Packet::FreshIPRequest renewer")?,
+            Packet::SetRenewingAvailable (ref availability) =>
+                availability.write (writer).chain_err (|| "failed to write RenewAvailability")?,
+            Packet::SetRenewer { ref renewer, ref token } => {
+                writer.write_u16_string (Some (renewer))
+                    .chain_err (|| "failed to write Packet::SetRenewer renewer")?;
+                writer.write_u16_string (Some (token.expose()))
+                    .chain_err (|| "failed to write Packet::SetRenewer token")?;
+            },
+            Packet::Error { code, ref detail } => {
+                writer.write_u8 (code.number())
+                    .chain_err (|| format!("failed to write error code '{}'", code))?;
+                writer.write_u16_string (detail.as_deref())
+                    .chain_err (|| "failed to write Packet::Error detail")?
+            },
+            Packet::Event (ref evt, ref payload) => {
+                evt.write (writer).chain_err (|| format!("failed to write event '{}'", evt))?;
+                match self.packet_no() {
+                    PACKET_EVENT_WITH_OUTCOME => payload.outcome.write (writer)
+                        .chain_err (|| "failed to write Packet::Event outcome")?,
+                    PACKET_EVENT_WITH_PAYLOAD => payload.write (writer)
+                        .chain_err (|| "failed to write Packet::Event payload")?,
+                    PACKET_EVENT_WITH_REQUESTER => {
+                        payload.write (writer)
+                            .chain_err (|| "failed to write Packet::Event payload")?;
+                        writer.write_u16_string (payload.requested_by.as_deref())
+                            .chain_err (|| "failed to write Packet::Event requester")?
+                    },
+                    PACKET_EVENT_COALESCED => {
+                        payload.write (writer)
+                            .chain_err (|| "failed to write Packet::Event payload")?;
+                        writer.write_u16_string (payload.requested_by.as_deref())
+                            .chain_err (|| "failed to write Packet::Event requester")?;
+                        writer.write_u32::<NetworkEndian>(payload.coalesced.unwrap_or (1))
+                            .chain_err (|| "failed to write Packet::Event count")?
+                    },
+                    _ => ()
+                }
+            },
+            Packet::Status (ref status) =>
+                status.write (writer).chain_err (|| "failed to write Packet::Status")?,
+            Packet::IPUnchanged (ip) => writer.write_u16_string (Some (&ip.to_string()))
+                .chain_err (|| "failed to write Packet::IPUnchanged address")?,
+            Packet::Health { ref health, age } => {
+                let seconds = u32::try_from (age.as_secs()).unwrap_or (u32::MAX);
+                writer.write_u32::<NetworkEndian>(seconds)
+                    .chain_err (|| "failed to write Packet::Health age")?;
+                health.write (writer).chain_err (|| "failed to write Packet::Health")?;
+            },
+            Packet::Renewed (ref outcome) =>
+                outcome.write (writer).chain_err (|| "failed to write Packet::Renewed")?,
+            Packet::AuthChallenge (ref nonce) => writer.write_all (nonce)
+                .chain_err (|| "failed to write Packet::AuthChallenge nonce")?,
+            Packet::AuthResponse (ref response) => writer.write_all (response)
+                .chain_err (|| "failed to write Packet::AuthResponse")?,
+            Packet::HistoryRequest { limit } => writer.write_u16::<NetworkEndian>(limit)
+                .chain_err (|| "failed to write Packet::HistoryRequest limit")?,
+            Packet::History (ref entries) => {
+                let count = u16::try_from (entries.len()).unwrap_or (u16::MAX);
+                writer.write_u16::<NetworkEndian>(count)
+                    .chain_err (|| "failed to write Packet::History length")?;
+                for entry in &entries[..count.into()] {
+                    entry.write (writer).chain_err (|| "failed to write Packet::History")?;
+                }
+            },
+            Packet::Capabilities (ref capabilities) => capabilities.write (writer)
+                .chain_err (|| "failed to write Packet::Capabilities")?
+        }
+        Ok(())
+    }   
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hexdumps_redact_secrets() {
+        let dump = hexdump (b"\x02\x00\x05hello world, again!", &[3..6, 6..8]);
+        assert_eq!(dump, "\
+            0000  02 00 05 ** ** ** ** ** 20 77 6f 72 6c 64 2c 20  |...***** world, |\n\
+            0010  61 67 61 69 6e 21                                |again!|");
+    }
+
+    #[test]
+    fn events_are_named() {
+        let names: Vec<_> = Event::ALL.iter().map (|event| event.name()).collect();
+        assert_eq!(names, Event::NAMES);
+        assert_eq!(Event::from_name ("ip_renewed"), Some (Event::IPRenewed));
+        assert_eq!(Event::from_name ("ip renewed"), None);
+        let numbers: Vec<_> = Event::ALL.iter().map (|event| event.number()).collect();
+        assert_eq!(numbers, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn events_carry_their_data() {
+        let events = [
+            Event::RenewalFailed ("the router is on fire".into()),
+            Event::RenewalFailed (String::new()),
+            Event::AvailabilityChanged (
+                RenewAvailability::UnavailableFor ("gaming".into(), Duration::from_secs (60))
+            ),
+            Event::ServerShuttingDown
+        ];
+        let mut bytes = Vec::new();
+        for event in &events {
+            Packet::Event (event.clone(), EventPayload::default()).write (&mut bytes).unwrap();
+        }
+        let mut reader = bytes.as_slice();
+        for event in events {
+            match Packet::read (&mut reader).unwrap() {
+                Packet::Event (read, _) => assert_eq!(read, event),
+                packet => panic!("unexpected packet {:?}", packet)
+            }
+        }
+        assert_eq!(Event::RenewalFailed ("timeout".into()).to_string(), "renewal failed: timeout");
+    }
+
+    #[test]
+    fn renewer_tokens_are_redacted() {
+        let packet = Packet::SetRenewer { renewer: "lte".into(), token: "hunter2".into() };
+        let mut bytes = Vec::new();
+        packet.write (&mut bytes).unwrap();
+        assert_eq!(bytes, b"\x06\x00\x03lte\x00\x07hunter2");
+        assert_eq!(format!("{:?}", packet), "SetRenewer { renewer: \"lte\", token: *** }");
+        assert_eq!(
+            hexdump (&bytes, &packet.secret_ranges()),
+            "0000  06 00 03 6c 74 65 00 07 ** ** ** ** ** ** **     |...lte..*******|"
+        );
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::SetRenewer { renewer, token } => {
+                assert_eq!(renewer, "lte");
+                assert_eq!(token.expose(), "hunter2");
+            },
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        // Reloads carry a token only if one is set.
+        let packet = Packet::ReloadConfig { token: "hunter2".into() };
+        let mut bytes = Vec::new();
+        packet.write (&mut bytes).unwrap();
+        assert_eq!(hexdump (&bytes, &packet.secret_ranges()),
+            "0000  1a 00 07 ** ** ** ** ** ** **                    |...*******|");
+        assert_eq!(Packet::read (&mut bytes.as_slice()).unwrap(), packet);
+        let mut bytes = Vec::new();
+        Packet::ReloadConfig { token: "".into() }.write (&mut bytes).unwrap();
+        assert_eq!(bytes, b"\x05");
+    }
+
+    #[test]
+    fn renewer_tokens_are_not_logged() {
+        use std::sync::Mutex;
+
+        static LOGGED: Mutex<Vec<String>> = Mutex::new (Vec::new());
+
+        struct Collector;
+
+        impl log::Log for Collector {
+            fn enabled (&self, _: &log::Metadata) -> bool { true }
+            fn log (&self, record: &log::Record) {
+                LOGGED.lock().unwrap().push (record.args().to_string());
+            }
+            fn flush (&self) {}
+        }
+
+        log::set_logger (&Collector).unwrap();
+        log::set_max_level (log::LevelFilter::Trace);
+        set_trace_packets (true);
+        let packet = Packet::SetRenewer { renewer: "lte".into(), token: "hunter2".into() };
+        let mut bytes = Vec::new();
+        packet.write (&mut bytes).unwrap();
+        let read = Packet::read (&mut bytes.as_slice()).unwrap();
+        trace_packet (Direction::Received, &"client", Some (&read), &bytes);
+        set_trace_packets (false);
+        // The token as text, as bytes and in the hexdump.
+        let token = ["hunter2", "104, 117, 110, 116, 101, 114, 50", "68 75 6e 74 65 72 32"];
+        let logged = LOGGED.lock().unwrap();
+        assert!(logged.iter().any (|message| message.contains ("SetRenewer")));
+        for message in logged.iter() {
+            assert!(!token.iter().any (|token| message.contains (token)), "{}", message);
+        }
+    }
+
+    #[cfg(any(feature = "client", feature = "server"))]
+    #[test]
+    fn auth_handshakes_roundtrip() {
+        let nonce = [0x2a; AUTH_LEN];
+        let response = auth_response (&"secret".into(), &nonce);
+        assert_eq!(&response[..8], b"\xcc\x4f\xc3\xbd\xb8\xa7\x79\x59");
+        assert_ne!(auth_response (&"Secret".into(), &nonce), response);
+        let mut bytes = Vec::new();
+        Packet::AuthChallenge (nonce).write (&mut bytes).unwrap();
+        Packet::AuthResponse (response).write (&mut bytes).unwrap();
+        assert_eq!(bytes.len(), 2 * (1 + AUTH_LEN));
+        let mut reader = bytes.as_slice();
+        match Packet::read (&mut reader).unwrap() {
+            Packet::AuthChallenge (read) => assert_eq!(read, nonce),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match Packet::read (&mut reader).unwrap() {
+            Packet::AuthResponse (read) => assert_eq!(read, response),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(Packet::read (&mut &b"\x0f\x00"[..]).is_err());
+    }
+
+    #[test]
+    fn renewals_select_renewers() {
+        let mut bytes = Vec::new();
+        Packet::FreshIPRequest { renewer: None }.write (&mut bytes).unwrap();
+        Packet::FreshIPRequest { renewer: Some ("wan2".into()) }.write (&mut bytes).unwrap();
+        // Requests without a renewer are the same as before.
+        assert_eq!(bytes, b"\x00\x0c\x00\x04wan2");
+        let mut reader = bytes.as_slice();
+        for expected in [None, Some ("wan2")] {
+            match Packet::read (&mut reader).unwrap() {
+                Packet::FreshIPRequest { renewer } => assert_eq!(renewer.as_deref(), expected),
+                packet => panic!("unexpected packet {:?}", packet)
+            }
+        }
+    }
+
+    #[test]
+    fn renew_outcomes_roundtrip() {
+        let outcome = RenewOutcome {
+            old_ip: Some ("203.0.113.7".parse().unwrap()),
+            new_ip: Some ("2001:db8::1".parse().unwrap())
+        };
+        assert_eq!(outcome.to_string(), "from 203.0.113.7 to 2001:db8::1");
+        let mut bytes = Vec::new();
+        Packet::Event (Event::UnavailabilityExpired, EventPayload::default())
+            .write (&mut bytes).unwrap();
+        Packet::Event (Event::IPRenewed, outcome.clone().into()).write (&mut bytes).unwrap();
+        Packet::Renewed (outcome.clone()).write (&mut bytes).unwrap();
+        // Events without an outcome are the same as before.
+        assert_eq!(&bytes[..6], b"\x03\x01\x0e\x00\x03\x00");
+        let mut reader = bytes.as_slice();
+        match Packet::read (&mut reader).unwrap() {
+            Packet::Event (Event::UnavailabilityExpired, read) => assert!(read.is_empty()),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match Packet::read (&mut reader).unwrap() {
+            Packet::Event (Event::IPRenewed, read) => assert_eq!(read, outcome.clone().into()),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match Packet::read (&mut reader).unwrap() {
+            Packet::Renewed (read) => assert_eq!(read, outcome),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(reader.is_empty());
+    }
+
+    #[test]
+    fn event_payloads_roundtrip() {
+        let payload = EventPayload {
+            timestamp: Some (UNIX_EPOCH + Duration::from_secs (1500000000)),
+            origin: Some ("router".into()),
+            outcome: RenewOutcome { old_ip: None, new_ip: Some ("192.0.2.1".parse().unwrap()) },
+            requested_by: None,
+            coalesced: None
+        };
+        assert_eq!(payload.to_string(), "by router, to 192.0.2.1");
+        let mut bytes = Vec::new();
+        Packet::Event (Event::IPRenewed, payload.clone()).write (&mut bytes).unwrap();
+        assert_eq!(bytes, [
+            b"\x11\x00\x00\x00\x00\x00\x59\x68\x2f\x00\x00\x06router".as_slice(),
+            b"\x02\x00\x09192.0.2.1"
+        ].concat());
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Event (Event::IPRenewed, read) => assert_eq!(read, payload),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        // Who requested it follows the rest of the payload.
+        let payload = EventPayload { requested_by: Some ("alice".into()), ..payload };
+        assert_eq!(payload.to_string(), "by router, to 192.0.2.1, requested by alice");
+        let mut bytes = Vec::new();
+        Packet::Event (Event::IPRenewed, payload.clone()).write (&mut bytes).unwrap();
+        assert_eq!(bytes[0], 24);
+        assert!(bytes.ends_with (b"\x00\x05alice"));
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Event (Event::IPRenewed, read) => assert_eq!(read, payload),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        // As does how many events were coalesced.
+        let payload = EventPayload { coalesced: Some (3), ..payload };
+        assert_eq!(payload.to_string(),
+            "by router, to 192.0.2.1, requested by alice, 3 events coalesced");
+        let mut bytes = Vec::new();
+        Packet::Event (Event::IPRenewed, payload.clone()).write (&mut bytes).unwrap();
+        assert_eq!(bytes[0], 25);
+        assert!(bytes.ends_with (b"\x00\x05alice\x00\x00\x00\x03"));
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Event (Event::IPRenewed, read) => assert_eq!(read, payload),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+    }
+
+    #[test]
+    fn errors_carry_their_code() {
+        let mut bytes = Vec::new();
+        let detail = Some ("Renewal unavailable: gaming".to_string());
+        Packet::Error { code: ErrorCode::Unavailable, detail }.write (&mut bytes).unwrap();
+        assert_eq!(bytes, b"\x12\x00\x00\x1bRenewal unavailable: gaming");
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Error { code: ErrorCode::Unavailable, detail: Some (detail) } =>
+                assert_eq!(detail, "Renewal unavailable: gaming"),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        // Older servers send no code, and newer ones may send unknown ones.
+        for bytes in [b"\x02\x00\x04oops".as_slice(), b"\x12\x2a\x00\x04oops"] {
+            match Packet::read (&mut &*bytes).unwrap() {
+                Packet::Error { code: ErrorCode::Internal, detail: Some (detail) } =>
+                    assert_eq!(detail, "oops"),
+                packet => panic!("unexpected packet {:?}", packet)
+            }
+        }
+    }
+
+    #[test]
+    fn limited_unavailability_roundtrips() {
+        let duration = Duration::from_secs (7200);
+        let availability = RenewAvailability::UnavailableFor ("gaming".into(), duration);
+        let mut bytes = Vec::new();
+        Packet::SetRenewingAvailable (availability).write (&mut bytes).unwrap();
+        assert_eq!(bytes, b"\x04\x02\x00\x00\x1c\x20\x00\x06gaming");
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::SetRenewingAvailable (RenewAvailability::UnavailableFor (reason, duration)) => {
+                assert_eq!(reason, "gaming");
+                assert_eq!(duration, Duration::from_secs (7200));
+            },
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+    }
+
+    #[test]
+    fn status_roundtrips() {
+        let link = LinkInfo {
+            state: Some ("Connected".into()),
+            uptime: Some (Duration::from_secs (3600)),
+            downstream_rate: Some (100_000),
+            upstream_rate: None,
+            external_ip: Some ("203.0.113.7".parse().unwrap())
+        };
+        assert_eq!(link.to_string(),
+            "state \"Connected\", up for 1h, 100000/? kbit/s, external IP 203.0.113.7");
+        let status = Status {
+            renewer: Some ("dsl".into()),
+            availability: RenewAvailability::Available,
+            link: Some (link.clone())
+        };
+        let mut bytes = Vec::new();
+        Packet::Status (status).write (&mut bytes).unwrap();
+        assert_eq!(&bytes[..9], b"\x08\x00\x03dsl\x00\x01\x17");
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Status (status) => {
+                assert_eq!(status.renewer.as_deref(), Some ("dsl"));
+                assert!(status.availability.reason().is_none());
+                assert_eq!(status.link, Some (link));
+            },
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+    }
+
+    #[test]
+    fn health_roundtrips() {
+        let health = Health::login_failed ("wrong password");
+        assert_eq!(health.to_string(), "unhealthy (reachable, credentials refused, not logged in, \
+            problem \"wrong password\")");
+        let mut bytes = Vec::new();
+        let age = Duration::from_secs (90);
+        Packet::Health { health: health.clone(), age }.write (&mut bytes).unwrap();
+        assert_eq!(&bytes[..6], b"\x0b\x00\x00\x00\x5a\x57");
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Health { health: read, age } => {
+                assert_eq!(read, health);
+                assert_eq!(age, Duration::from_secs (90));
+            },
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(Health::default().is_healthy());
+        assert_eq!(Health::logged_in().to_string(),
+            "healthy (reachable, credentials valid, logged in)");
+    }
+
+    #[test]
+    fn capabilities_roundtrip() {
+        let capabilities = Capabilities {
+            version: PROTOCOL_VERSION,
+            renewer: Some ("dsl".into()),
+            auth: true,
+            keep_alive: true,
+            reload: false,
+            set_renewer: true,
+            status: true,
+            history: false,
+            schedule: true
+        };
+        let mut bytes = Vec::new();
+        Packet::Capabilities (capabilities.clone()).write (&mut bytes).unwrap();
+        assert_eq!(bytes, b"\x17\x00\x01\x00\x03dsl\x5b");
+        match Packet::read (&mut bytes.as_slice()).unwrap() {
+            Packet::Capabilities (read) => assert_eq!(read, capabilities),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(!capabilities.supports (&Packet::ReloadConfig { token: "secret".into() }));
+        assert!(capabilities.supports (&Packet::StatusRequest));
+    }
+
+    #[test]
+    fn history_roundtrips() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs (1500000000);
+        let entries = vec![
+            HistoryEntry {
+                timestamp,
+                peer: Some ("192.0.2.7:50000".into()),
+                renewer: Some ("dsl".into()),
+                outcome: HistoryOutcome::Renewed (RenewOutcome {
+                    old_ip: None,
+                    new_ip: Some ("203.0.113.7".parse().unwrap())
+                })
+            },
+            HistoryEntry {
+                timestamp,
+                peer: None,
+                renewer: None,
+                outcome: HistoryOutcome::Unchanged ("203.0.113.7".parse().unwrap())
+            },
+            HistoryEntry {
+                timestamp,
+                peer: None,
+                renewer: Some ("lte".into()),
+                outcome: HistoryOutcome::Failed ("the router is on fire".into())
+            }
+        ];
+        assert_eq!(entries[0].outcome.to_string(), "renewed to 203.0.113.7");
+        assert_eq!(entries[1].outcome.to_string(), "renewed, but still 203.0.113.7");
+        let mut bytes = Vec::new();
+        Packet::HistoryRequest { limit: 10 }.write (&mut bytes).unwrap();
+        Packet::History (entries.clone()).write (&mut bytes).unwrap();
+        assert_eq!(&bytes[..6], b"\x13\x00\x0a\x14\x00\x03");
+        let mut reader = bytes.as_slice();
+        match Packet::read (&mut reader).unwrap() {
+            Packet::HistoryRequest { limit } => assert_eq!(limit, 10),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        match Packet::read (&mut reader).unwrap() {
+            Packet::History (read) => assert_eq!(read, entries),
+            packet => panic!("unexpected packet {:?}", packet)
+        }
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+//! Generators of arbitrary packets for [proptest](https://docs.rs/proptest) (feature `testing`),
+//! so that programs embedding the protocol can test how they handle any packet.
+//!
+//! The generated packets survive being written and read back: optional strings are never empty
+//! (they would be read as missing), and durations and timestamps are whole seconds which fit in
+//! their representation.
+
+use super::*;
+use proptest::collection::vec;
+use proptest::option;
+use proptest::prelude::*;
+
+/// Any packet, of either direction.
+pub fn packet() -> impl Strategy<Value = Packet> {
+    prop_oneof![
+        option::of (name()).prop_map (|renewer| Packet::FreshIPRequest { renewer }),
+        availability().prop_map (Packet::SetRenewingAvailable),
+        text().prop_map (|token| Packet::ReloadConfig { token: token.into() }),
+        (name(), text()).prop_map (|(renewer, token)| Packet::SetRenewer {
+            renewer,
+            token: token.into()
+        }),
+        Just (Packet::StatusRequest),
+        Just (Packet::HealthRequest),
+        nonce().prop_map (Packet::AuthResponse),
+        any::<u16>().prop_map (|limit| Packet::HistoryRequest { limit }),
+        Just (Packet::Subscribe),
+        Just (Packet::CapabilitiesRequest),
+        Just (Packet::Ok),
+        (error_code(), option::of (name()))
+            .prop_map (|(code, detail)| Packet::Error { code, detail }),
+        (event(), event_payload()).prop_map (|(event, payload)| Packet::Event (event, payload)),
+        status().prop_map (Packet::Status),
+        ip().prop_map (Packet::IPUnchanged),
+        (health(), seconds (0)).prop_map (|(health, age)| Packet::Health { health, age }),
+        renew_outcome().prop_map (Packet::Renewed),
+        nonce().prop_map (Packet::AuthChallenge),
+        vec (history_entry(), 0..8).prop_map (Packet::History),
+        capabilities().prop_map (Packet::Capabilities)
+    ]
+}
+
+/// Any event, carrying any data.
+pub fn event() -> impl Strategy<Value = Event> {
+    prop_oneof![
+        Just (Event::IPRenewed),
+        Just (Event::UnavailabilityExpired),
+        Just (Event::IPChanged),
+        Just (Event::IPUnchanged),
+        Just (Event::RenewalStarted),
+        text().prop_map (Event::RenewalFailed),
+        availability().prop_map (Event::AvailabilityChanged),
+        Just (Event::ServerShuttingDown)
+    ]
+}
+
+pub fn event_payload() -> impl Strategy<Value = EventPayload> {
+    let (timestamp, origin) = (option::of (timestamp (1)), option::of (name()));
+    (timestamp, origin, renew_outcome(), option::of (name()), option::of (any::<u32>()))
+        .prop_map (|(timestamp, origin, outcome, requested_by, coalesced)| EventPayload {
+            timestamp, origin, outcome, requested_by, coalesced
+        })
+}
+
+pub fn availability() -> impl Strategy<Value = RenewAvailability> {
+    prop_oneof![
+        Just (RenewAvailability::Available),
+        name().prop_map (RenewAvailability::Unavailable),
+        (name(), seconds (0))
+            .prop_map (|(reason, duration)| RenewAvailability::UnavailableFor (reason, duration))
+    ]
+}
+
+pub fn status() -> impl Strategy<Value = Status> {
+    (option::of (name()), availability(), option::of (link_info()))
+        .prop_map (|(renewer, availability, link)| Status { renewer, availability, link })
+}
+
+pub fn link_info() -> impl Strategy<Value = LinkInfo> {
+    (
+        option::of (name()),
+        option::of (seconds (0)),
+        option::of (any::<u32>()),
+        option::of (any::<u32>()),
+        option::of (ip())
+    ).prop_map (|(state, uptime, downstream_rate, upstream_rate, external_ip)| LinkInfo {
+        state, uptime, downstream_rate, upstream_rate, external_ip
+    })
+}
+
+pub fn renew_outcome() -> impl Strategy<Value = RenewOutcome> {
+    (option::of (ip()), option::of (ip()))
+        .prop_map (|(old_ip, new_ip)| RenewOutcome { old_ip, new_ip })
+}
+
+pub fn health() -> impl Strategy<Value = Health> {
+    (
+        option::of (any::<bool>()),
+        option::of (any::<bool>()),
+        option::of (any::<bool>()),
+        option::of (text())
+    ).prop_map (|(reachable, credentials_valid, logged_in, problem)| Health {
+        reachable, credentials_valid, logged_in, problem
+    })
+}
+
+pub fn history_entry() -> impl Strategy<Value = HistoryEntry> {
+    let outcome = prop_oneof![
+        renew_outcome().prop_map (HistoryOutcome::Renewed),
+        ip().prop_map (HistoryOutcome::Unchanged),
+        text().prop_map (HistoryOutcome::Failed)
+    ];
+    (timestamp (0), option::of (name()), option::of (name()), outcome)
+        .prop_map (|(timestamp, peer, renewer, outcome)| HistoryEntry {
+            timestamp, peer, renewer, outcome
+        })
+}
+
+pub fn capabilities() -> impl Strategy<Value = Capabilities> {
+    (any::<u16>(), option::of (name()), any::<[bool; 7]>())
+        .prop_map (|(version, renewer, flags)| {
+            let [auth, keep_alive, reload, set_renewer, status, history, schedule] = flags;
+            Capabilities {
+                version, renewer, auth, keep_alive, reload, set_renewer, status, history, schedule
+            }
+        })
+}
+
+pub fn error_code() -> impl Strategy<Value = ErrorCode> {
+    proptest::sample::select (ErrorCode::ALL)
+}
+
+pub fn ip() -> impl Strategy<Value = IpAddr> {
+    any::<IpAddr>()
+}
+
+// A string which may be empty.
+fn text() -> impl Strategy<Value = String> {
+    "\\PC{0,32}"
+}
+
+// A string which can't be empty, e.g. where empty strings are read as missing.
+fn name() -> impl Strategy<Value = String> {
+    "\\PC{1,32}"
+}
+
+fn nonce() -> impl Strategy<Value = [u8; AUTH_LEN]> {
+    any::<[u8; AUTH_LEN]>()
+}
+
+// Whole seconds, at least `min`, which fit in a `u32`.
+fn seconds (min: u32) -> impl Strategy<Value = Duration> {
+    (min..=u32::MAX).prop_map (|seconds| Duration::from_secs (seconds.into()))
+}
+
+// A time after the Unix epoch, to the second.
+fn timestamp (min: u32) -> impl Strategy<Value = SystemTime> {
+    seconds (min).prop_map (|since| UNIX_EPOCH + since)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    proptest! {
+        #[test]
+        fn packets_roundtrip (packet in packet()) {
+            let mut bytes = Vec::new();
+            packet.write (&mut bytes).unwrap();
+            let mut reader = bytes.as_slice();
+            prop_assert_eq!(Packet::read (&mut reader).unwrap(), packet);
+            prop_assert!(reader.is_empty(), "{} bytes left over", reader.len());
+        }
+
+        // What's read from any bytes (if anything) is written and read back unchanged, as in the
+        // fuzz target `packet_read`.
+        #[test]
+        fn any_bytes_are_read_safely (bytes in vec (any::<u8>(), 0..256)) {
+            if let Ok(packet) = Packet::read (&mut bytes.as_slice()) {
+                let mut written = Vec::new();
+                packet.write (&mut written).unwrap();
+                prop_assert_eq!(Packet::read (&mut written.as_slice()).unwrap(), packet);
+            }
+        }
+    }
+}
@@ -0,0 +1,213 @@
+//! A renewer wrapping other renewers, which are tried in order until one of them succeeds: e.g.
+//! the web interface of the router first, and a reboot of the modem as the last resort.
+
+use super::{Error, Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{RenewerConfig, Section};
+use crate::log_error_with_chain;
+use serde::Deserialize;
+
+// Configuration of the renewer, in `[server.renewer.chain]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The renewers to try, in order, by their name in `server.renewer`, e.g.
+    /// `["tr064", "reboot"]`.
+    renewers: Vec<String>
+}
+
+struct Member {
+    instance: String,
+    renewer: Box<dyn RenewerTrait>,
+    // Whether `init` succeeded: members failing it are initialized again before renewing.
+    initialized: bool
+}
+
+impl Member {
+    fn init (&mut self) -> Result<()> {
+        if !self.initialized {
+            self.renewer.init()
+                .chain_err (|| format!("the renewer '{}' failed to initialize", self.instance))?;
+            self.initialized = true;
+        }
+        Ok(())
+    }
+}
+
+pub struct Renewer {
+    members: Vec<Member>
+}
+
+impl Renewer {
+    /// Builds the chain described by `renewer`, whose members are looked up among `renewers`
+    /// (the configured instances). Members without a section (e.g. "fritzbox-local") are built
+    /// with an empty one.
+    pub(super) fn build (renewer: &RenewerConfig, renewers: &[RenewerConfig]) -> Result<Self> {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        ensure!(
+            !config.renewers.is_empty(),
+            "option '{}.renewers' can't be empty", renewer.config.path()
+        );
+        let members = config.renewers.into_iter()
+            .map (|instance| {
+                let member = renewers.iter()
+                    .find (|member| member.instance == instance)
+                    .cloned()
+                    .unwrap_or_else (|| RenewerConfig {
+                        name: instance.clone(),
+                        instance: instance.clone(),
+                        config: Section::empty (format!("server.renewer.{}", instance))
+                    });
+                // Chains can't be nested, or a chain could end up containing itself.
+                ensure!(
+                    member.name != renewer.name,
+                    "the renewer '{}' can't contain the chain '{}'", renewer.instance, instance
+                );
+                let built = super::get_renewer (&member, renewers)
+                    .chain_err (|| format!(
+                        "failed to build the renewer '{}' of '{}'", instance, renewer.instance
+                    ))?;
+                Ok(Member { instance, renewer: built, initialized: false })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Renewer { members })
+    }
+}
+
+// Logs why a member failed, before moving on to the next one.
+fn warn_with_chain (error: &Error) {
+    log_error_with_chain!(target: "renewer::chain", log::Level::Warn, error, "{}", error);
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        Self::build (renewer, &[])
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        crate::config::schema::section::<Config> (gen, &[])
+    }
+
+    // Succeeds if any member initializes: the others are tried again when renewing.
+    fn init (&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for member in &mut self.members {
+            result = member.init();
+            if let Err(ref error) = result {
+                warn_with_chain (error);
+            }
+        }
+        if self.members.iter().any (|member| member.initialized) {
+            return Ok(());
+        }
+        result.chain_err (|| "none of the renewers of the chain initialized")
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let mut result = Ok(RenewOutcome::default());
+        for member in &mut self.members {
+            result = member.init().and_then (|_| member.renewer.renew_ip()
+                .chain_err (|| format!("the renewer '{}' failed", member.instance)));
+            match result {
+                Ok(_) => {
+                    info!(target: "renewer::chain", "renewed by '{}'", member.instance);
+                    return result;
+                },
+                Err(ref error) => warn_with_chain (error)
+            }
+        }
+        result.chain_err (|| "all the renewers of the chain failed")
+    }
+
+    // The link as reported by the first member which supports it.
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        for member in self.members.iter_mut().filter (|member| member.initialized) {
+            if let Some(info) = member.renewer.link_info()? {
+                return Ok(Some (info));
+            }
+        }
+        Ok(None)
+    }
+
+    // Keeps alive the sessions of the initialized members, failing if any of them fails.
+    fn keepalive (&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for member in self.members.iter_mut().filter (|member| member.initialized) {
+            let kept = member.renewer.keepalive()
+                .chain_err (|| format!("the renewer '{}' failed", member.instance));
+            if let Err(ref error) = kept {
+                warn_with_chain (error);
+                result = kept;
+            }
+        }
+        result.chain_err (|| "failed to keep alive the sessions of the chain")
+    }
+
+    // The chain is as healthy as its healthiest member, as renewals fall back to the others.
+    fn health_check (&mut self) -> Result<Health> {
+        let mut unhealthy = None;
+        for member in &mut self.members {
+            let health = member.init().and_then (|_| member.renewer.health_check())
+                .unwrap_or_else (|error| Health {
+                    problem: Some (error.to_string()),
+                    ..Health::default()
+                });
+            if health.is_healthy() {
+                return Ok(health);
+            }
+            unhealthy.get_or_insert ((member.instance.clone(), health));
+        }
+        let (instance, mut health) = unhealthy.chain_err (|| "the chain has no renewers")?;
+        let problem = health.problem.take().unwrap_or_else (|| "unhealthy".into());
+        health.problem = Some (format!("the renewer '{}': {}", instance, problem));
+        Ok(health)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SourceMap;
+    use std::sync::Arc;
+
+    fn renewer (instance: &str, name: &str, config: &str) -> RenewerConfig {
+        let map = Arc::new (SourceMap::new (config));
+        let path = format!("server.renewer.{}", instance);
+        RenewerConfig {
+            name: name.into(),
+            instance: instance.into(),
+            config: Section::new (path, Some (config.parse().unwrap()), map)
+        }
+    }
+
+    #[test]
+    fn members_are_tried_in_order() {
+        let renewers = [
+            renewer ("flaky", "chaos", "failure_rate = 1.0"),
+            renewer ("chain", "chain", "renewers = ['flaky', 'dummy']")
+        ];
+        let mut chain = Renewer::build (&renewers[1], &renewers).unwrap();
+        chain.init().unwrap();
+        chain.renew_ip().unwrap();
+
+        // Every member failing fails the chain.
+        let renewers = [
+            renewer ("flaky", "chaos", "failure_rate = 1.0"),
+            renewer ("chain", "chain", "renewers = ['flaky']")
+        ];
+        let mut chain = Renewer::build (&renewers[1], &renewers).unwrap();
+        chain.init().unwrap();
+        assert!(chain.renew_ip().is_err());
+
+        // Unknown renewers and nested chains are refused.
+        for members in ["[]", "['nonexistent']", "['chain']"] {
+            let chain = renewer ("chain", "chain", &format!("renewers = {}", members));
+            assert!(Renewer::build (&chain, std::slice::from_ref (&chain)).is_err(), "{}", members);
+        }
+    }
+}
@@ -0,0 +1,187 @@
+//! A renewer which only simulates a router, with artificial delays and failures, to exercise
+//! clients, notifiers and dashboards without touching real hardware.
+
+use super::{LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, Ipv4Addr};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.chaos]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// How long initializing the renewer takes, e.g. "10s".
+    init_delay: Option<String>,
+    /// How long each renewal takes, e.g. "3s".
+    delay: Option<String>,
+    /// The probability (from 0 to 1) that a renewal fails.
+    #[serde(default)]
+    failure_rate: f64,
+    /// The probability (from 0 to 1) that a successful renewal keeps the same IP address.
+    #[serde(default)]
+    same_ip_rate: f64,
+    /// Seeds the outcomes, so that they're the same across runs. Random when missing.
+    seed: Option<u64>
+}
+
+pub struct Renewer {
+    init_delay: Duration,
+    delay: Duration,
+    failure_rate: f64,
+    same_ip_rate: f64,
+    outcomes: Outcomes,
+    // The last octet of the simulated IP address (in 198.51.100.0/24), and when it was assigned.
+    ip: u8,
+    connected_at: Instant
+}
+
+/// Decides simulated outcomes, through a xorshift generator. Also used by the `dummy` renewer.
+pub(super) struct Outcomes(u64);
+
+impl Outcomes {
+    /// Seeded with `seed` so that the outcomes are the same across runs, or randomly (per
+    /// `instance`) without one.
+    pub(super) fn new (seed: Option<u64>, instance: &str) -> Outcomes {
+        let seed = seed.unwrap_or_else (|| RandomState::new().hash_one (instance));
+        // Scrambled, so that close seeds don't start with similar outcomes. Never 0.
+        Outcomes (seed.wrapping_mul (0x9e37_79b9_7f4a_7c15) | 1)
+    }
+
+    /// Whether an outcome with probability `rate` happens.
+    pub(super) fn happens (&mut self, rate: f64) -> bool {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        // The 53 most significant bits make a uniformly distributed float in [0, 1).
+        ((self.0 >> 11) as f64 / (1u64 << 53) as f64) < rate
+    }
+}
+
+/// Fails unless `rate`, the option `option` of `renewer`, is a probability.
+pub(super) fn check_rate (renewer: &config::RenewerConfig, option: &str, rate: f64) -> Result<()> {
+    ensure!(
+        (0.0..=1.0).contains (&rate),
+        "option '{}.{}' must be between 0 and 1", renewer.config.path(), option
+    );
+    Ok(())
+}
+
+impl Renewer {
+    fn external_ip (&self) -> IpAddr {
+        Ipv4Addr::new (198, 51, 100, self.ip).into()
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let duration = |option: &str, duration: Option<String>| match duration {
+            Some(duration) => parse_duration (&duration)
+                .chain_err (|| format!("invalid option '{}.{}'", renewer.config.path(), option)),
+            None => Ok(Duration::ZERO)
+        };
+        let rates = [("failure_rate", config.failure_rate), ("same_ip_rate", config.same_ip_rate)];
+        for (option, rate) in rates {
+            check_rate (renewer, option, rate)?;
+        }
+        Ok(Renewer {
+            init_delay: duration ("init_delay", config.init_delay)?,
+            delay: duration ("delay", config.delay)?,
+            failure_rate: config.failure_rate,
+            same_ip_rate: config.same_ip_rate,
+            outcomes: Outcomes::new (config.seed, &renewer.instance),
+            ip: 1,
+            connected_at: Instant::now()
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        debug!(target: "renewer::chaos", "initializing for {:?}", self.init_delay);
+        thread::sleep (self.init_delay);
+        Ok(())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        thread::sleep (self.delay);
+        if self.outcomes.happens (self.failure_rate) {
+            bail!("simulated failure of the renewal");
+        }
+        let old_ip = self.external_ip();
+        if self.outcomes.happens (self.same_ip_rate) {
+            info!(target: "renewer::chaos", "simulating a renewal which keeps the same IP address");
+        } else {
+            self.ip = self.ip % 254 + 1;
+        }
+        self.connected_at = Instant::now();
+        Ok(RenewOutcome { old_ip: Some (old_ip), new_ip: Some (self.external_ip()) })
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        Ok(Some (LinkInfo {
+            state: Some ("Connected".into()),
+            uptime: Some (Duration::from_secs (self.connected_at.elapsed().as_secs())),
+            downstream_rate: Some (100_000),
+            upstream_rate: Some (40_000),
+            external_ip: Some (self.external_ip())
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RenewerConfig, Section, SourceMap};
+    use std::sync::Arc;
+
+    fn chaos (config: &str) -> Result<Renewer> {
+        let source = format!("seed = 42\n{}", config);
+        let map = Arc::new (SourceMap::new (&source));
+        let path = "server.renewer.chaos".into();
+        Renewer::from_config (&RenewerConfig {
+            name: "chaos".into(),
+            instance: "chaos".into(),
+            config: Section::new (path, Some (source.parse().unwrap()), map)
+        })
+    }
+
+    fn external_ip (renewer: &mut Renewer) -> Option<std::net::IpAddr> {
+        renewer.link_info().unwrap().unwrap().external_ip
+    }
+
+    #[test]
+    fn outcomes_follow_the_rates() {
+        let mut renewer = chaos ("").unwrap();
+        let ip = external_ip (&mut renewer);
+        let outcome = renewer.renew_ip().unwrap();
+        assert_ne!(external_ip (&mut renewer), ip);
+        assert_eq!((outcome.old_ip, outcome.new_ip), (ip, external_ip (&mut renewer)));
+
+        let mut renewer = chaos ("failure_rate = 1.0").unwrap();
+        assert!(renewer.renew_ip().is_err());
+        let mut renewer = chaos ("same_ip_rate = 1.0").unwrap();
+        let ip = external_ip (&mut renewer);
+        renewer.renew_ip().unwrap();
+        assert_eq!(external_ip (&mut renewer), ip);
+
+        let mut renewer = chaos ("failure_rate = 0.5").unwrap();
+        let failures = (0..1000).filter (|_| renewer.renew_ip().is_err()).count();
+        assert!((400..600).contains (&failures), "{} renewals out of 1000 failed", failures);
+
+        assert!(chaos ("failure_rate = 2.0").is_err());
+        assert!(chaos ("delay = \"soon\"").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! Renews the IP address by renewing the DHCP lease of an interface of the machine running the
+//! server, for hosts which get the public address directly (e.g. behind a modem in bridge mode).
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config;
+use serde::Deserialize;
+use std::env;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+// Configuration of the renewer, in `[server.renewer.dhcp-local]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The interface whose lease is renewed, e.g. "eth0".
+    interface: String,
+    /// The DHCP client managing the interface, detected unless specified.
+    client: Option<Client>
+}
+
+/// The supported DHCP clients, in order of detection.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Client {
+    /// Releases the lease with `dhclient -r`, then asks for another one with `dhclient`.
+    Dhclient,
+    /// Asks for another lease with `dhcpcd --rebind`.
+    Dhcpcd
+}
+
+impl Client {
+    fn program (self) -> &'static str {
+        match self {
+            Client::Dhclient => "dhclient",
+            Client::Dhcpcd => "dhcpcd"
+        }
+    }
+
+    // Where the client is installed among the directories of `path` (formatted like `PATH`).
+    fn locate (self, path: &OsStr) -> Option<PathBuf> {
+        env::split_paths (path)
+            .map (|dir| dir.join (self.program()))
+            .find (|program| program.is_file())
+    }
+
+    // The client installed in the directories of `path`, along with where, if any.
+    fn detect (path: &OsStr) -> Option<(Client, PathBuf)> {
+        [Client::Dhclient, Client::Dhcpcd].iter()
+            .find_map (|client| Some ((*client, client.locate (path)?)))
+    }
+}
+
+pub struct Renewer {
+    interface: String,
+    client: Option<Client>,
+    // Where the client is installed, once found.
+    program: Option<PathBuf>,
+    // Where the client is looked for, along with `PATH`.
+    search_path: Vec<PathBuf>
+}
+
+impl Renewer {
+    // The client, along with where it's installed.
+    fn client (&mut self) -> Result<(Client, PathBuf)> {
+        if let (Some(client), Some(ref program)) = (self.client, &self.program) {
+            return Ok((client, program.clone()));
+        }
+        // The clients are usually in sbin, which isn't in the `PATH` of every user.
+        let path = env::var_os ("PATH").unwrap_or_default();
+        let path = env::join_paths (env::split_paths (&path).chain (self.search_path.clone()))
+            .chain_err (|| "failed to build the search path of the DHCP clients")?;
+        let (client, program) = match self.client {
+            Some(client) => (client, client.locate (&path)
+                .chain_err (|| format!("{} isn't installed", client.program()))?),
+            None => Client::detect (&path)
+                .chain_err (|| "neither dhclient nor dhcpcd are installed")?
+        };
+        debug!(target: "renewer::dhcp-local", "using {}", program.display());
+        self.client = Some (client);
+        self.program = Some (program.clone());
+        Ok((client, program))
+    }
+}
+
+// Runs `program`, failing unless it exits with status 0.
+fn run (program: &Path, args: &[&str]) -> Result<()> {
+    let status = Command::new (program)
+        .args (args)
+        .stdin (Stdio::null())
+        .stdout (Stdio::null())
+        .stderr (Stdio::null())
+        .status()
+        .chain_err (|| format!("failed to run '{}'", program.display()))?;
+    ensure!(
+        status.success(),
+        "'{} {}' failed with {}", program.display(), args.join (" "), status
+    );
+    Ok(())
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        ensure!(
+            !config.interface.is_empty(),
+            "option '{}.interface' can't be empty", renewer.config.path()
+        );
+        Ok(Renewer {
+            interface: config.interface,
+            client: config.client,
+            program: None,
+            search_path: vec!["/sbin".into(), "/usr/sbin".into(), "/usr/local/sbin".into()]
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.client().map (|_| ())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let (client, program) = self.client()?;
+        let interface = self.interface.as_str();
+        match client {
+            Client::Dhclient => {
+                run (&program, &["-r", interface])?;
+                run (&program, &[interface])?;
+            },
+            Client::Dhcpcd => run (&program, &["--rebind", interface])?
+        }
+        info!(target: "renewer::dhcp-local", "successfully renewed the lease of '{}'", interface);
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn clients_are_detected() {
+        let dir = env::temp_dir().join (format!("oxixenon-dhcp-{}", process::id()));
+        let (first, second) = (dir.join ("bin"), dir.join ("sbin"));
+        fs::create_dir_all (&first).unwrap();
+        fs::create_dir_all (&second).unwrap();
+        let path = env::join_paths ([&first, &second]).unwrap();
+        assert_eq!(Client::detect (&path), None);
+
+        fs::write (second.join ("dhcpcd"), "").unwrap();
+        assert_eq!(Client::detect (&path), Some ((Client::Dhcpcd, second.join ("dhcpcd"))));
+        // dhclient is preferred when both are installed.
+        fs::write (first.join ("dhclient"), "").unwrap();
+        assert_eq!(Client::detect (&path), Some ((Client::Dhclient, first.join ("dhclient"))));
+        fs::remove_dir_all (&dir).unwrap();
+    }
+}
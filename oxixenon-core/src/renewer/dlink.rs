@@ -0,0 +1,497 @@
+use super::{igd, Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client;
+use serde::Deserialize;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Configuration of the renewer, in `[server.renewer.dlink]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address of the router.
+    ip: String,
+    /// The scheme of the web interface, "http" (the default) or "https".
+    scheme: Option<String>,
+    /// The port of the web interface, if it isn't the default one of the scheme.
+    port: Option<u16>,
+    /// Path of a PEM file with the certificates used to verify the web interface over HTTPS (e.g.
+    /// its self-signed certificate). Defaults to the system certificates.
+    tls_ca_file: Option<String>,
+    username: String,
+    password: Secret<String>,
+    /// The network interface whose address is renewed (e.g. "pppif?if=1"), or "auto" to pick the
+    /// active WAN interface listed by the dashboard after logging in.
+    interface: String,
+    /// The URL of the UPnP description of the router (e.g.
+    /// "http://192.168.1.1:5000/rootDesc.xml"), through which the link information is read.
+    upnp_description: Option<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+// The value of `interface` which discovers it.
+const AUTO_INTERFACE: &str = "auto";
+// The path of the settings of the interfaces, followed by their name in the links of the dashboard.
+const NETIF_PATH: &str = "/ui/dboard/settings/netif/";
+
+pub struct Renewer {
+    // The base URL of the web interface, e.g. "http://192.168.1.1".
+    url: String,
+    username: String,
+    password: Secret<String>,
+    // None until discovered, with `interface = "auto"`.
+    interface: Option<String>,
+    upnp_description: Option<String>,
+    sid_cookie: Option<Secret<String>>,
+    try_count: u8,
+    transport: Box<dyn http_client::Transport>
+}
+
+impl Renewer {
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::dlink", "trying to login using specified credentials");
+        let login_url = format!("{}/ui/login", self.url);
+        let res = http_client::get_with (&mut *self.transport, login_url.as_str())
+            .chain_err (|| format!("HTTP request to '{}' failed", login_url))?;
+        ensure!(res.status().is_success(), "failed to request the login page");
+        let mut lines = res.body().lines();
+        // TODO: regexps are much better for this purpose. But too many dependencies, argh!
+        let nonce = lines.find (|l| l.contains ("\"nonce\" value=\""));
+        let nonce = Self::_extract_field_value (nonce, '"')
+            .chain_err (|| "failed to extract 'nonce' from the login page")?;
+        let csrf_tok = lines.next();
+        let csrf_tok = Self::_extract_field_value (csrf_tok, '\'')
+            .chain_err (|| "failed to extract 'csrf token' from the login page")?;
+        trace!(target: "renewer::dlink", "extracted nonce = {}, csrf_tok = ***", nonce);
+        // Encrypt the password with the retrieved nonce
+        let mut mac = HmacSha256::new_varkey (nonce.as_bytes()).expect ("Can't create HmacSha256");
+        mac.input (self.password.expose().as_bytes());
+
+        let hashed_pwd: String = mac
+            .result()
+            .code()
+            .into_iter()
+            .map (|b| format!("{:02x}", b)) // convert bytes to lower-case hex nibbles
+            .collect();
+
+        // We're ready to try our login.
+        let res = http_client::build_post (login_url.as_str())
+            .put ("code1", csrf_tok)
+            .put ("language", "IT")
+            .put ("login", "Login")
+            .put ("nonce", nonce)
+            .put ("userName", self.username.as_str())
+            .put ("userPwd", hashed_pwd.as_str())
+            .build_and_execute_with (&mut *self.transport)
+            .chain_err (|| format!("HTTP request to login at '{}' failed", login_url))?;
+
+        ensure!(
+            res.status().is_redirection(),
+            "failed to login, got status '{}' instead of redirection", res.status()
+        );
+
+        let headers = res.headers();
+        info!(target: "renewer::dlink", "login OK, redirected to {}",
+            headers.get (http_client::header::LOCATION)
+                .and_then (|l| l.to_str().ok())
+                .unwrap_or ("<unknown>"));
+
+        // Some firmwares set more than one cookie: keep all of them (without their attributes)
+        // and send them back together.
+        let cookies = headers.get_all (http_client::header::SET_COOKIE)
+            .iter()
+            .filter_map (|c| c.to_str().ok())
+            .filter_map (|c| c.split (';').next())
+            .map (str::trim)
+            .filter (|c| !c.is_empty())
+            .collect::<Vec<_>>();
+        self.sid_cookie =
+            if cookies.is_empty() { None } else { Some (cookies.join ("; ").into()) };
+
+        Ok(())
+    }
+
+    // Picks the WAN interface whose address is renewed among the ones listed by the dashboard: the
+    // active one, or the only one. Fails listing them if that's ambiguous.
+    fn discover_interface (&mut self) -> Result<String> {
+        let dashboard_url = format!("{}/ui/dboard", self.url);
+        let mut logged_in = false;
+        let res = loop {
+            if self.sid_cookie.is_none() {
+                self.login()?;
+                logged_in = true;
+            }
+            let sid_cookie = self.sid_cookie.as_ref().expect ("sid must be present after login");
+            let request = http_client::Request::builder()
+                .uri (dashboard_url.as_str())
+                .header ("Cookie", sid_cookie.expose().as_str())
+                .body (None::<String>)
+                .unwrap();
+            let res = http_client::make_request_with (&mut *self.transport, request)
+                .chain_err (|| format!("HTTP request to '{}' failed", dashboard_url))?;
+            let expired = res.headers()
+                .get (http_client::header::LOCATION)
+                .is_some_and (|location| location == "/ui/login");
+            if !expired || logged_in {
+                break res;
+            }
+            debug!(target: "renewer::dlink", "sid expired, logging in again");
+            self.sid_cookie = None;
+        };
+        ensure!(
+            res.status().is_success(),
+            "failed to request the dashboard, got status {}", res.status()
+        );
+        let interfaces = wan_interfaces (res.body());
+        let active = interfaces.iter().filter (|(_, active)| *active).collect::<Vec<_>>();
+        let interface = match (active.as_slice(), interfaces.as_slice()) {
+            ([(interface, _)], _) | ([], [(interface, _)]) => interface.clone(),
+            (_, []) => bail!("the dashboard doesn't list any WAN interface"),
+            _ => bail!(
+                "can't tell which WAN interface to renew, set 'interface' to one of: {}",
+                interfaces.iter()
+                    .map (|(name, active)| match active {
+                        true => format!("{} (active)", name),
+                        false => name.clone()
+                    })
+                    .collect::<Vec<_>>()
+                    .join (", ")
+            )
+        };
+        info!(target: "renewer::dlink", "renewing the address of the interface '{}'", interface);
+        self.interface = Some (interface.clone());
+        Ok(interface)
+    }
+
+    // given <input name="..." value="abc" /> and " returns abc
+    // NOTE: does not work with escaped values. e.g. <... value="abc\"def" />
+    fn _extract_field_value (input: Option<&str>, delimiter: char) -> Option<&str> {
+        let pattern = format!("value={}", delimiter);
+        let mut split = input?.split (pattern.as_str());
+        split.nth (1)?.split (delimiter).nth(0)
+    }
+}
+
+// Whether `c` can be part of the name of an interface, which is passed inside URLs.
+fn is_interface_char (c: char) -> bool {
+    c.is_ascii_alphabetic() || c.is_ascii_digit() || c == '?' || c == '='
+}
+
+// The WAN interfaces linked by the dashboard, along with whether they look active: the text
+// following each link (up to the next one) tells that it's connected or up.
+fn wan_interfaces (dashboard: &str) -> Vec<(String, bool)> {
+    let mut interfaces: Vec<(String, bool)> = Vec::new();
+    for row in dashboard.split (NETIF_PATH).skip (1) {
+        let end = row.find (|c| !is_interface_char (c)).unwrap_or (row.len());
+        let (name, rest) = row.split_at (end);
+        if name.is_empty() {
+            continue;
+        }
+        let rest = rest.to_ascii_lowercase();
+        let active = rest.contains (">up<")
+            || (rest.contains ("connected") && !rest.contains ("disconnected"));
+        match interfaces.iter_mut().find (|(known, _)| known == name) {
+            Some((_, known_active)) => *known_active |= active,
+            None => interfaces.push ((name.to_string(), active))
+        }
+    }
+    interfaces
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config(renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        // since interface is directly passed inside an URL, ensure it doesn't have any invalid
+        // characters
+        ensure!(
+            config.interface.chars().all (is_interface_char),
+            "option 'server.renewer.dlink.interface' contains invalid characters, allowed: {}",
+            "a-z, 0-9, ?, ="
+        );
+        let url = http_client::base_url (config.scheme.as_deref(), &config.ip, config.port)
+            .chain_err (|| format!("invalid option '{}.scheme'", renewer.config.path()))?;
+        let transport: Box<dyn http_client::Transport> = match config.tls_ca_file {
+            Some(ca_file) => Box::new (http_client::TlsTransport::with_ca_file (ca_file)),
+            None => Box::new (http_client::TcpTransport)
+        };
+
+        Ok(Self {
+            url,
+            username: config.username,
+            password: config.password,
+            interface: Some (config.interface).filter (|interface| interface != AUTO_INTERFACE),
+            upnp_description: config.upnp_description,
+            sid_cookie: None,
+            try_count: 0,
+            transport
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        // Request the router's page and try to login using the specified credentials.
+        self.login()?;
+        if self.interface.is_none() {
+            self.discover_interface()?;
+        }
+        Ok(())
+    }
+
+    fn renew_ip(&mut self) -> Result<RenewOutcome> {
+        let interface = match self.interface {
+            Some(ref interface) => interface.clone(),
+            None => self.discover_interface()?
+        };
+        // try to request the ip renewal page. If we're redirected to the login page,
+        // then we need to login again as the sid has expired.
+        let renewal_url = format!("{}{}{}&action=reset", self.url, NETIF_PATH, interface);
+
+        let sid_cookie = match self.sid_cookie {
+            Some(ref value) => {
+                debug!(target: "renewer::dlink", "trying to reuse existing sid to renew");
+                value
+            },
+            None => {
+                self.login()?;
+                self.sid_cookie.as_ref().expect ("sid must be present after login")
+            }
+        };
+        let request = http_client::Request::builder()
+            .uri (renewal_url.as_str())
+            .header ("Cookie", sid_cookie.expose().as_str())
+            .body (None::<String>)
+            .unwrap();
+
+        let request = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", renewal_url))?;
+
+        ensure!(
+            request.status().is_redirection(),
+            "failed to renew the IP address, got status {}",
+            request.status()
+        );
+
+        // get redirect path
+        let location = request.headers()
+            .get (http_client::header::LOCATION)
+            .and_then (|l| l.to_str().ok())
+            .chain_err (|| "failed to renew the IP address, redirected to an unknown location")?;
+        match location {
+            "/ui/login" => {
+                ensure!(
+                    self.try_count < 3,
+                    "failed to renew the IP address, too many retries - credentials are OK?"
+                );
+                debug!(target: "renewer::dlink", "sid expired. clearing and re-running");
+                self.sid_cookie = None;
+                self.try_count += 1;
+                return self.renew_ip();
+            },
+            path => {
+                self.try_count = 0;
+                trace!(target: "renewer::dlink", "redirected to \"{}\", assuming success", path);
+                info!(target: "renewer::dlink", "successfully asked for another IP");
+            }
+        }
+        Ok(RenewOutcome::default())
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        match self.upnp_description {
+            Some(ref description) => igd::link_info (&mut *self.transport, description).map (Some),
+            None => Ok(None)
+        }
+    }
+
+    // Requesting the dashboard with the cookie refreshes the session, which is logged in again
+    // once the router redirects to the login page.
+    fn keepalive (&mut self) -> Result<()> {
+        let sid_cookie = match self.sid_cookie {
+            Some(ref value) => value,
+            None => return self.login()
+        };
+        let dashboard_url = format!("{}/ui/dboard", self.url);
+        let request = http_client::Request::builder()
+            .uri (dashboard_url.as_str())
+            .header ("Cookie", sid_cookie.expose().as_str())
+            .body (None::<String>)
+            .unwrap();
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", dashboard_url))?;
+        let expired = res.headers()
+            .get (http_client::header::LOCATION)
+            .is_some_and (|location| location == "/ui/login");
+        if expired {
+            debug!(target: "renewer::dlink", "sid expired, logging in again");
+            self.sid_cookie = None;
+            return self.login();
+        }
+        ensure!(
+            res.status().is_success(),
+            "failed to refresh the session, got status {}", res.status()
+        );
+        Ok(())
+    }
+
+    // Refreshing the session (logging in again if it expired) tells whether the credentials are
+    // still accepted, once the router answers.
+    fn health_check (&mut self) -> Result<Health> {
+        let login_url = format!("{}/ui/login", self.url);
+        if http_client::get_with (&mut *self.transport, login_url.as_str()).is_err() {
+            return Ok(Health::unreachable (format!("'{}' doesn't answer", login_url)));
+        }
+        Ok(match self.keepalive() {
+            Ok(()) => Health::logged_in(),
+            Err(error) => Health::login_failed (error.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    const LOGIN_PAGE: &str = "HTTP/1.1 200 OK\r\n\r\n\
+        <input type=\"hidden\" name=\"nonce\" value=\"n0nc3\" />\r\n\
+        <input type=\"hidden\" name=\"code1\" value='csrf' />\r\n";
+
+    fn renewer(transport: &MockTransport) -> Renewer {
+        Renewer {
+            url: "http://192.168.1.1".into(),
+            username: "admin".into(),
+            password: "admin".into(),
+            interface: Some ("pppif?if=1".into()),
+            upnp_description: None,
+            sid_cookie: None,
+            try_count: 0,
+            transport: Box::new (transport.clone())
+        }
+    }
+
+    #[test]
+    fn login_sends_hashed_password_and_stores_cookie() {
+        let transport = MockTransport::new();
+        transport
+            .push_response (LOGIN_PAGE)
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard\r\n\
+                Set-Cookie: sid=abcdef; path=/\r\n\r\n");
+        let mut renewer = renewer (&transport);
+        renewer.init().unwrap();
+        assert_eq!(renewer.sid_cookie.unwrap().expose(), "sid=abcdef");
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("GET /ui/login HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with ("POST /ui/login HTTP/1.1\r\n"));
+        assert!(requests[1].contains ("nonce=n0nc3"));
+        assert!(requests[1].contains ("code1=csrf"));
+        assert!(requests[1].contains (
+            "userPwd=d4f9324fae8ab2658d3ae6edf9ab7c221a87026cf41eac936037b16c950a77e2"));
+    }
+
+    #[test]
+    fn login_keeps_every_cookie() {
+        let transport = MockTransport::new();
+        transport
+            .push_response (LOGIN_PAGE)
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard\r\n\
+                Set-Cookie: sid=abcdef; path=/\r\nSet-Cookie: lang=IT; path=/\r\n\r\n");
+        let mut renewer = renewer (&transport);
+        renewer.init().unwrap();
+        assert_eq!(renewer.sid_cookie.unwrap().expose(), "sid=abcdef; lang=IT");
+    }
+
+    #[test]
+    fn renew_logs_in_again_when_sid_expired() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/login\r\n\r\n")
+            .push_response (LOGIN_PAGE)
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard\r\n\
+                Set-Cookie: sid=fresh; path=/\r\n\r\n")
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard/settings/netif\r\n\r\n");
+        let mut renewer = renewer (&transport);
+        renewer.sid_cookie = Some ("sid=stale".into());
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].contains ("cookie: sid=stale\r\n"));
+        assert!(requests[3].starts_with (
+            "GET /ui/dboard/settings/netif/pppif?if=1&action=reset HTTP/1.1\r\n"));
+        assert!(requests[3].contains ("cookie: sid=fresh\r\n"));
+    }
+
+    #[test]
+    fn keepalive_logs_in_again_when_sid_expired() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<html></html>\r\n")
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/login\r\n\r\n")
+            .push_response (LOGIN_PAGE)
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard\r\n\
+                Set-Cookie: sid=fresh; path=/\r\n\r\n");
+        let mut renewer = renewer (&transport);
+        renewer.sid_cookie = Some ("sid=valid".into());
+        renewer.keepalive().unwrap();
+        renewer.keepalive().unwrap();
+        assert_eq!(renewer.sid_cookie.unwrap().expose(), "sid=fresh");
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 4);
+        assert!(requests[0].starts_with ("GET /ui/dboard HTTP/1.1\r\n"));
+        assert!(requests[0].contains ("cookie: sid=valid\r\n"));
+        assert!(requests[2].starts_with ("GET /ui/login HTTP/1.1\r\n"));
+    }
+
+    #[test]
+    fn interface_is_discovered() {
+        let dashboard = "HTTP/1.1 200 OK\r\n\r\n<table>\
+            <tr><td><a href=\"/ui/dboard/settings/netif/pppif?if=1\">VDSL</a></td>\
+            <td>Disconnected</td></tr>\
+            <tr><td><a href=\"/ui/dboard/settings/netif/pppif?if=2\">FTTH</a></td>\
+            <td>Connected</td></tr></table>\r\n";
+        let transport = MockTransport::new();
+        transport
+            .push_response (LOGIN_PAGE)
+            .push_response ("HTTP/1.1 302 Found\r\nLocation: /ui/dboard\r\n\
+                Set-Cookie: sid=abcdef; path=/\r\n\r\n")
+            .push_response (dashboard);
+        let mut renewer = renewer (&transport);
+        renewer.interface = None;
+        renewer.init().unwrap();
+        assert_eq!(renewer.interface.as_deref(), Some ("pppif?if=2"));
+        assert!(transport.requests()[2].starts_with ("GET /ui/dboard HTTP/1.1\r\n"));
+
+        // Several active interfaces are listed in the error.
+        let interfaces = wan_interfaces (&dashboard.replace ("Disconnected", "Up"));
+        assert_eq!(interfaces, [("pppif?if=1".into(), true), ("pppif?if=2".into(), true)]);
+        transport.push_response (dashboard.replace ("Disconnected", "Connected"));
+        renewer.interface = None;
+        let error = renewer.discover_interface().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "can't tell which WAN interface to renew, set 'interface' to one of: \
+             pppif?if=1 (active), pppif?if=2 (active)"
+        );
+    }
+
+    #[test]
+    fn login_fails_without_nonce() {
+        let transport = MockTransport::new();
+        transport.push_response ("HTTP/1.1 200 OK\r\n\r\n<html></html>\r\n");
+        assert!(renewer (&transport).init().is_err());
+    }
+}
@@ -0,0 +1,282 @@
+//! Renews the IP address of D-Link routers whose firmware speaks HNAP1 (the SOAP protocol of
+//! their web interface since the DIR-8xx series) rather than the `/ui/login` flow of the `dlink`
+//! renewer. Every action is signed with HMAC-MD5, using a key derived during the login.
+
+use super::{igd, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Configuration of the renewer, in `[server.renewer.dlink-hnap]`. The options are the same as the
+// ones of the `dlink` renewer, where they apply.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address of the router.
+    ip: String,
+    /// "Admin" unless specified, the only user of most firmwares.
+    username: Option<String>,
+    password: Secret<String>,
+    /// The HNAP action which restarts the WAN connection. Defaults to "RenewWanConnection".
+    action: Option<String>,
+    /// The arguments of the action, e.g. `{ Action = "Renew" }`.
+    #[serde(default)]
+    arguments: BTreeMap<String, String>,
+    /// The URL of the UPnP description of the router, through which the link information is read.
+    upnp_description: Option<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const NAMESPACE: &str = "http://purenetworks.com/HNAP1/";
+const DEFAULT_ACTION: &str = "RenewWanConnection";
+// The key signing the login request, before the private key is known.
+const LOGIN_KEY: &str = "withoutloginkey";
+
+// What the login establishes.
+struct Session {
+    private_key: Secret<String>,
+    cookie: Secret<String>
+}
+
+pub struct Renewer {
+    ip: String,
+    username: String,
+    password: Secret<String>,
+    action: String,
+    arguments: BTreeMap<String, String>,
+    upnp_description: Option<String>,
+    session: Option<Session>,
+    transport: Box<dyn http_client::Transport>
+}
+
+impl Renewer {
+    // Calls `action` with `arguments`, signing it with the key of the session (if any).
+    fn call (&mut self, action: &str, arguments: &[(&str, &str)]) -> Result<String> {
+        let soap_action = format!("\"{}{}\"", NAMESPACE, action);
+        let url = format!("http://{}/HNAP1/", self.ip);
+        let key = self.session.as_ref().map_or (LOGIN_KEY, |session| session.private_key.expose());
+        // Timestamps are in milliseconds, wrapped as the web interface does.
+        let time = SystemTime::now().duration_since (UNIX_EPOCH).unwrap_or_default().as_millis()
+            % 2_000_000_000_000;
+        let auth = hmac_md5 (key, &format!("{}{}", time, soap_action));
+        let arguments: String = arguments.iter()
+            .map (|(name, value)| format!("<{0}>{1}</{0}>", name, escape (value)))
+            .collect();
+        let envelope = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\"?>\
+            <soap:Envelope xmlns:xsi=\"http://www.w3.org/2001/XMLSchema-instance\" \
+            xmlns:xsd=\"http://www.w3.org/2001/XMLSchema\" \
+            xmlns:soap=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+            <soap:Body><{action} xmlns=\"{namespace}\">{arguments}</{action}></soap:Body>\
+            </soap:Envelope>",
+            action = action, namespace = NAMESPACE, arguments = arguments
+        );
+        let mut request = Request::post (url.as_str())
+            .header (header::CONTENT_TYPE, "text/xml; charset=utf-8")
+            .header ("SOAPAction", soap_action.as_str())
+            .header ("HNAP_AUTH", format!("{} {}", auth, time));
+        if let Some(ref session) = self.session {
+            request = request.header (header::COOKIE, format!("uid={}", session.cookie.expose()));
+        }
+        let request = request.body (Some (envelope))
+            .chain_err (|| "failed to build HTTP request object")?;
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+        ensure!(res.status().is_success(), "{} failed with status {}", action, res.status());
+        Ok(res.into_body())
+    }
+
+    // The login handshake: the router answers a request with a challenge, a cookie and a public
+    // key, from which both sides derive the private key signing the following actions.
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::dlink-hnap", "trying to login using specified credentials");
+        self.session = None;
+        let username = self.username.clone();
+        let response = self.call ("Login", &[
+            ("Action", "request"), ("Username", &username), ("LoginPassword", ""), ("Captcha", "")
+        ])?;
+        let field = |name| igd::tag (&response, name)
+            .filter (|value| !value.is_empty())
+            .chain_err (|| format!("failed to extract '{}' from the login response", name));
+        let (challenge, cookie, public_key) =
+            (field ("Challenge")?, field ("Cookie")?, field ("PublicKey")?);
+        let private_key = hmac_md5 (
+            &format!("{}{}", public_key, self.password.expose()), challenge
+        );
+        let login_password = hmac_md5 (&private_key, challenge);
+        self.session = Some (Session {
+            private_key: private_key.into(),
+            cookie: cookie.into()
+        });
+        let response = self.call ("Login", &[
+            ("Action", "login"), ("Username", &username), ("LoginPassword", &login_password),
+            ("Captcha", "")
+        ])?;
+        if igd::tag (&response, "LoginResult") != Some ("success") {
+            self.session = None;
+            bail!("login failed, check your credentials!");
+        }
+        info!(target: "renewer::dlink-hnap", "login OK");
+        Ok(())
+    }
+}
+
+// The result of an action, e.g. "OK" for `<RenewWanConnectionResult>OK</...>`.
+fn result<'a> (response: &'a str, action: &str) -> Option<&'a str> {
+    igd::tag (response, &format!("{}Result", action))
+}
+
+// HMAC-MD5 (RFC 2104) of `message`, as uppercase hexadecimal digits like the web interface.
+fn hmac_md5 (key: &str, message: &str) -> String {
+    const BLOCK: usize = 64;
+    let mut key = key.as_bytes().to_vec();
+    if key.len() > BLOCK {
+        key = md5::compute (&key).0.to_vec();
+    }
+    key.resize (BLOCK, 0);
+    let pad = |byte: u8| key.iter().map (|k| k ^ byte).collect::<Vec<_>>();
+    let mut inner = pad (0x36);
+    inner.extend_from_slice (message.as_bytes());
+    let mut outer = pad (0x5c);
+    outer.extend_from_slice (&md5::compute (&inner).0);
+    format!("{:X}", md5::compute (&outer))
+}
+
+fn escape (value: &str) -> String {
+    value.replace ('&', "&amp;").replace ('<', "&lt;").replace ('>', "&gt;")
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        Ok(Renewer {
+            ip: http_client::uri_host (&config.ip).into_owned(),
+            username: config.username.unwrap_or_else (|| "Admin".into()),
+            password: config.password,
+            action: config.action.unwrap_or_else (|| DEFAULT_ACTION.into()),
+            arguments: config.arguments,
+            upnp_description: config.upnp_description,
+            session: None,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let action = self.action.clone();
+        let arguments = self.arguments.clone();
+        let arguments: Vec<_> = arguments.iter()
+            .map (|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        // An expired session makes the router answer without a result: login and try again.
+        for attempt in 0..2 {
+            if self.session.is_none() {
+                self.login()?;
+            }
+            let response = self.call (&action, &arguments)?;
+            match result (&response, &action) {
+                Some("OK") | Some("REBOOT") => {
+                    info!(target: "renewer::dlink-hnap", "successfully asked for another IP");
+                    return Ok(RenewOutcome::default());
+                },
+                Some(result) => bail!("{} failed with result '{}'", action, result),
+                None if attempt == 0 => {
+                    debug!(target: "renewer::dlink-hnap", "session expired, logging in again");
+                    self.session = None;
+                },
+                None => ()
+            }
+        }
+        bail!("{} failed, the router didn't answer with a result", action)
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        match self.upnp_description {
+            Some(ref description) => igd::link_info (&mut *self.transport, description).map (Some),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn response (action: &str, contents: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+            <soap:Envelope><soap:Body><{0}Response xmlns=\"{1}\">{2}</{0}Response></soap:Body>\
+            </soap:Envelope>\r\n", action, NAMESPACE, contents)
+    }
+
+    #[test]
+    fn hmac_md5_matches_rfc_2202() {
+        let key = String::from_utf8 (vec![0x0b; 16]).unwrap();
+        assert_eq!(hmac_md5 (&key, "Hi There"), "9294727A3638BB1C13F48EF8158BFC9D");
+        let long_key = "a".repeat (80);
+        assert_eq!(hmac_md5 (&long_key, "x").len(), 32);
+    }
+
+    #[test]
+    fn login_signs_the_following_actions() {
+        let transport = MockTransport::new();
+        transport
+            .push_response (response ("Login", "<LoginResult>OK</LoginResult>\
+                <Challenge>CH4LL3NG3</Challenge><Cookie>c00k13</Cookie>\
+                <PublicKey>PUBL1CK3Y</PublicKey>"))
+            .push_response (response ("Login", "<LoginResult>success</LoginResult>"))
+            .push_response (response ("RenewWanConnection", ""))
+            .push_response (response ("Login", "<Challenge>CH4LL3NG3</Challenge>\
+                <Cookie>n3w</Cookie><PublicKey>PUBL1CK3Y</PublicKey>"))
+            .push_response (response ("Login", "<LoginResult>success</LoginResult>"))
+            .push_response (response ("RenewWanConnection",
+                "<RenewWanConnectionResult>OK</RenewWanConnectionResult>"));
+        let mut renewer = Renewer {
+            ip: "192.168.0.1".into(),
+            username: "Admin".into(),
+            password: "hunter2".into(),
+            action: DEFAULT_ACTION.into(),
+            arguments: BTreeMap::new(),
+            upnp_description: None,
+            session: None,
+            transport: Box::new (transport.clone())
+        };
+        renewer.init().unwrap();
+        // The first renewal isn't answered with a result, as if the session expired.
+        renewer.renew_ip().unwrap();
+
+        let private_key = hmac_md5 ("PUBL1CK3Yhunter2", "CH4LL3NG3");
+        let requests = transport.requests();
+        assert!(requests[0].contains ("<Action>request</Action><Username>Admin</Username>"));
+        assert!(requests[1].contains (&format!(
+            "<LoginPassword>{}</LoginPassword>", hmac_md5 (&private_key, "CH4LL3NG3")
+        )));
+        assert!(requests[1].contains ("cookie: uid=c00k13\r\n"));
+        let soap_action = "\"http://purenetworks.com/HNAP1/RenewWanConnection\"";
+        assert!(requests[2].contains (&format!("soapaction: {}\r\n", soap_action)));
+        assert!(requests[5].contains ("cookie: uid=n3w\r\n"));
+        // Actions are signed with the private key along with the time.
+        let auth = requests[2].lines()
+            .find_map (|line| line.strip_prefix ("hnap_auth: "))
+            .unwrap();
+        let (signature, time) = auth.split_once (' ').unwrap();
+        assert_eq!(signature, hmac_md5 (&private_key, &format!("{}{}", time, soap_action)));
+    }
+}
@@ -0,0 +1,116 @@
+//! A renewer which does nothing by default. Its options turn it into a test harness for
+//! deployments, with delays and failures to exercise the retries of clients and notifications.
+
+use super::chaos::{check_rate, Outcomes};
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use serde::Deserialize;
+use std::thread;
+use std::time::Duration;
+
+// Configuration of the renewer, in `[server.renewer.dummy]`. Every option is optional.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// How long each renewal takes, e.g. "3s".
+    delay: Option<String>,
+    /// The probability (from 0 to 1) that a renewal fails.
+    #[serde(default)]
+    failure_rate: f64,
+    /// Fails every renewal after the first `fail_after` ones, e.g. to see what happens once the
+    /// router stops cooperating.
+    fail_after: Option<u64>,
+    /// Seeds the failures decided by `failure_rate`, so that they're the same across runs.
+    seed: Option<u64>
+}
+
+pub struct Renewer {
+    delay: Duration,
+    failure_rate: f64,
+    fail_after: Option<u64>,
+    outcomes: Outcomes,
+    // How many renewals were requested so far, logged with each of them.
+    renewals: u64
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        check_rate (renewer, "failure_rate", config.failure_rate)?;
+        let delay = match config.delay {
+            Some(delay) => parse_duration (&delay)
+                .chain_err (|| format!("invalid option '{}.delay'", renewer.config.path()))?,
+            None => Duration::ZERO
+        };
+        Ok(Renewer {
+            delay,
+            failure_rate: config.failure_rate,
+            fail_after: config.fail_after,
+            outcomes: Outcomes::new (config.seed, &renewer.instance),
+            renewals: 0
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        self.renewals += 1;
+        let renewal = self.renewals;
+        thread::sleep (self.delay);
+        if let Some(fail_after) = self.fail_after {
+            ensure!(
+                renewal <= fail_after,
+                "simulated failure of renewal #{}, after {} renewals", renewal, fail_after
+            );
+        }
+        ensure!(
+            !self.outcomes.happens (self.failure_rate),
+            "simulated failure of renewal #{}", renewal
+        );
+        info!(target: "renewer::dummy", "renewal #{} succeeded", renewal);
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{RenewerConfig, Section, SourceMap};
+    use std::sync::Arc;
+
+    fn dummy (config: &str) -> Result<Renewer> {
+        let map = Arc::new (SourceMap::new (config));
+        let path = "server.renewer.dummy".into();
+        Renewer::from_config (&RenewerConfig {
+            name: "dummy".into(),
+            instance: "dummy".into(),
+            config: Section::new (path, Some (config.parse().unwrap()), map)
+        })
+    }
+
+    #[test]
+    fn renewals_fail_as_configured() {
+        let mut renewer = dummy ("").unwrap();
+        assert!((0..100).all (|_| renewer.renew_ip().is_ok()));
+
+        let mut renewer = dummy ("fail_after = 2").unwrap();
+        renewer.renew_ip().unwrap();
+        renewer.renew_ip().unwrap();
+        let error = renewer.renew_ip().unwrap_err();
+        assert_eq!(error.to_string(), "simulated failure of renewal #3, after 2 renewals");
+
+        let mut renewer = dummy ("failure_rate = 1.0").unwrap();
+        assert_eq!(renewer.renew_ip().unwrap_err().to_string(), "simulated failure of renewal #1");
+
+        assert!(dummy ("failure_rate = -1.0").is_err());
+        assert!(dummy ("delay = \"soon\"").is_err());
+    }
+}
@@ -0,0 +1,158 @@
+//! Renews the IP address by running a command, so that any router can be integrated with a
+//! script rather than a renewer written in Rust.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.exec]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The command which renews the IP address, followed by its arguments, e.g.
+    /// ["/usr/local/bin/renew-ip", "--interface", "ppp0"]. It succeeds if it exits with status 0.
+    command: Vec<String>,
+    /// A command run when the renewer is initialized (e.g. to check the credentials), along with
+    /// its arguments. Optional.
+    check: Option<Vec<String>>,
+    /// Environment variables set for both commands.
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    /// How long the commands can run before being killed, e.g. "2m". Defaults to 1 minute.
+    timeout: Option<String>
+}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs (60);
+// How often a running command is checked for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis (50);
+
+pub struct Renewer {
+    command: Vec<String>,
+    check: Option<Vec<String>>,
+    env: BTreeMap<String, String>,
+    timeout: Duration
+}
+
+impl Renewer {
+    // Runs `command`, failing unless it exits with status 0 within the timeout.
+    fn run (&self, command: &[String]) -> Result<()> {
+        let (program, args) = command.split_first().expect ("commands are never empty");
+        let mut child = Command::new (program)
+            .args (args)
+            .envs (&self.env)
+            .stdin (Stdio::null())
+            .stdout (Stdio::null())
+            .stderr (Stdio::piped())
+            .spawn()
+            .chain_err (|| format!("failed to run '{}'", program))?;
+        // Read what the command writes on STDERR meanwhile, so that it never blocks on a full
+        // pipe.
+        let mut stderr = child.stderr.take().expect ("STDERR is piped");
+        let output = thread::spawn (move || {
+            let mut output = String::new();
+            let _ = stderr.read_to_string (&mut output);
+            output
+        });
+        let started = Instant::now();
+        let status = loop {
+            if let Some(status) = child.try_wait()
+                .chain_err (|| format!("failed to wait for '{}'", program))?
+            {
+                break status;
+            }
+            if started.elapsed() >= self.timeout {
+                let _ = child.kill();
+                let _ = child.wait();
+                bail!("'{}' didn't finish within {}", program,
+                    config::format_duration (self.timeout));
+            }
+            thread::sleep (POLL_INTERVAL);
+        };
+        let output = output.join().unwrap_or_default();
+        for line in output.lines() {
+            debug!(target: "renewer::exec", "{}: {}", program, line);
+        }
+        match output.lines().rev().find (|line| !line.trim().is_empty()) {
+            _ if status.success() => Ok(()),
+            Some(line) => bail!("'{}' failed with {}: {}", program, status, line.trim()),
+            None => bail!("'{}' failed with {}", program, status)
+        }
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let path = renewer.config.path();
+        ensure!(!config.command.is_empty(), "option '{}.command' can't be empty", path);
+        ensure!(
+            config.check.as_ref().is_none_or (|check| !check.is_empty()),
+            "option '{}.check' can't be empty", path
+        );
+        let timeout = match config.timeout {
+            Some(timeout) => parse_duration (&timeout)
+                .chain_err (|| format!("invalid option '{}.timeout'", path))?,
+            None => DEFAULT_TIMEOUT
+        };
+        Ok(Renewer { command: config.command, check: config.check, env: config.env, timeout })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        match self.check {
+            Some(ref check) => self.run (check).chain_err (|| "the check command failed"),
+            None => Ok(())
+        }
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        self.run (&self.command)?;
+        info!(target: "renewer::exec", "successfully ran '{}'", self.command[0]);
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    fn sh (script: &str, timeout: Duration) -> Renewer {
+        Renewer {
+            command: vec!["sh".into(), "-c".into(), script.into()],
+            check: Some (vec!["sh".into(), "-c".into(), "test \"$ROUTER\" = 10.0.0.1".into()]),
+            env: Some (("ROUTER".to_string(), "10.0.0.1".to_string())).into_iter().collect(),
+            timeout
+        }
+    }
+
+    #[test]
+    fn exit_statuses_are_mapped() {
+        let mut renewer = sh ("exit 0", DEFAULT_TIMEOUT);
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+
+        let mut renewer = sh ("echo 'wrong password' >&2; exit 3", DEFAULT_TIMEOUT);
+        let error = renewer.renew_ip().unwrap_err().to_string();
+        assert!(error.ends_with ("exit status: 3: wrong password"), "{}", error);
+
+        renewer.env.clear();
+        assert!(renewer.init().is_err());
+        let started = Instant::now();
+        assert!(sh ("sleep 10", Duration::from_millis (200)).renew_ip().is_err());
+        assert!(started.elapsed() < Duration::from_secs (5));
+    }
+}
@@ -0,0 +1,461 @@
+//! Renews the IP address of the gateways of French ISPs through their APIs: the Livebox of Orange
+//! by disabling and enabling its PPP connection through the sysbus JSON API at `/ws`, and the
+//! Freebox of Free by resetting its connection through the Freebox OS REST API at `/api/`, which
+//! authenticates applications with a token granted on the display of the Freebox.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use http::Response;
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.fr-box]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The gateway, "livebox" unless specified.
+    #[serde(default)]
+    flavor: Flavor,
+    /// IP address (or hostname) of the gateway. Defaults to "192.168.1.1" for the Livebox, and
+    /// to "mafreebox.freebox.fr" for the Freebox.
+    ip: Option<String>,
+    /// The user of the Livebox, "admin" unless specified.
+    username: Option<String>,
+    /// The password of the Livebox.
+    password: Option<Secret<String>>,
+    /// The token of the application on the Freebox. When missing, the renewer asks the Freebox
+    /// for one, to be granted on its display, and fails telling it.
+    app_token: Option<Secret<String>>,
+    /// The identifier of the application on the Freebox, "fr.oxixenon" unless specified.
+    app_id: Option<String>
+}
+
+/// The gateways.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Flavor {
+    /// The Livebox of Orange, through its sysbus JSON API.
+    #[default]
+    Livebox,
+    /// The Freebox of Free, through the Freebox OS API.
+    Freebox
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password", "app_token"];
+
+const DEFAULT_LIVEBOX_IP: &str = "192.168.1.1";
+const DEFAULT_FREEBOX_IP: &str = "mafreebox.freebox.fr";
+const DEFAULT_APP_ID: &str = "fr.oxixenon";
+// The content type of the requests to the sysbus API.
+const SYSBUS_CONTENT_TYPE: &str = "application/x-sah-ws-4-call+json";
+// The version of the Freebox OS API, prefixing its paths.
+const FREEBOX_API: &str = "/api/v8";
+// How long the token of the application can wait to be granted on the display of the Freebox.
+const AUTHORIZATION_TIMEOUT: Duration = Duration::from_secs (120);
+const AUTHORIZATION_POLL_INTERVAL: Duration = Duration::from_secs (2);
+
+// The credentials of each gateway.
+enum Credentials {
+    Livebox {
+        username: String,
+        password: Secret<String>
+    },
+    Freebox {
+        app_id: String,
+        app_token: Option<Secret<String>>
+    }
+}
+
+// What the login establishes.
+enum Session {
+    Livebox {
+        context_id: Secret<String>,
+        cookies: Option<Secret<String>>
+    },
+    Freebox {
+        session_token: Secret<String>
+    }
+}
+
+pub struct Renewer {
+    ip: String,
+    credentials: Credentials,
+    session: Option<Session>,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    fn request (&mut self, method: http::Method, path: &str, body: Option<&Value>)
+        -> Result<Response<String>>
+    {
+        let url = format!("http://{}{}", self.ip, path);
+        let mut request = Request::builder().method (method).uri (url.as_str());
+        match self.session {
+            Some(Session::Livebox { ref context_id, ref cookies }) => {
+                request = request
+                    .header (header::AUTHORIZATION, format!("X-Sah {}", context_id.expose()))
+                    .header ("X-Context", context_id.expose().as_str());
+                if let Some(ref cookies) = cookies {
+                    request = request.header (header::COOKIE, cookies.expose().as_str());
+                }
+            },
+            Some(Session::Freebox { ref session_token }) =>
+                request = request.header ("X-Fbx-App-Auth", session_token.expose().as_str()),
+            None if matches!(self.credentials, Credentials::Livebox { .. }) =>
+                request = request.header (header::AUTHORIZATION, "X-Sah-Login"),
+            None => ()
+        }
+        if body.is_some() {
+            let content_type = match self.credentials {
+                Credentials::Livebox { .. } => SYSBUS_CONTENT_TYPE,
+                Credentials::Freebox { .. } => "application/json"
+            };
+            request = request.header (header::CONTENT_TYPE, content_type);
+        }
+        let request = request.body (body.map (Value::to_string))
+            .chain_err (|| "failed to build HTTP request object")?;
+        http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))
+    }
+
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::fr-box", "trying to login using specified credentials");
+        self.session = None;
+        let session = match self.credentials {
+            Credentials::Livebox { .. } => self.login_livebox()?,
+            Credentials::Freebox { .. } => self.login_freebox()?
+        };
+        self.session = Some (session);
+        info!(target: "renewer::fr-box", "login OK");
+        Ok(())
+    }
+
+    // Creating a context of the web interface returns its identifier, sent with the following
+    // requests along with the cookies.
+    fn login_livebox (&mut self) -> Result<Session> {
+        let (username, password) = match self.credentials {
+            Credentials::Livebox { ref username, ref password } => (username, password),
+            _ => unreachable!("only called with the credentials of a Livebox")
+        };
+        let body = json!({
+            "service": "sah.Device.Information",
+            "method": "createContext",
+            "parameters": {
+                "applicationName": "webui",
+                "username": username,
+                "password": password.expose()
+            }
+        });
+        let res = self.request (http::Method::POST, "/ws", Some (&body))?;
+        ensure!(res.status().is_success(), "login failed with status {}", res.status());
+        let response = parse_json (&res)?;
+        match response["data"]["contextID"].as_str() {
+            Some(context_id) if response["status"] == 0 => Ok(Session::Livebox {
+                context_id: context_id.to_string().into(),
+                cookies: http_client::cookies (&res)
+            }),
+            _ => bail!("login failed ({}), check your credentials!", sysbus_error (&response))
+        }
+    }
+
+    // The password of the session is the HMAC-SHA1 of the challenge given by the Freebox, keyed
+    // with the token of the application.
+    fn login_freebox (&mut self) -> Result<Session> {
+        let app_token = match self.credentials {
+            Credentials::Freebox { app_token: Some(ref app_token), .. } => app_token.clone(),
+            _ => bail!("the Freebox granted no token to the application yet")
+        };
+        let app_id = match self.credentials {
+            Credentials::Freebox { ref app_id, .. } => app_id.clone(),
+            _ => unreachable!()
+        };
+        let login = self.freebox (http::Method::GET, "/login/", None)?
+            .chain_err (|| "the Freebox refused to give a login challenge")?;
+        let challenge = login["challenge"].as_str()
+            .chain_err (|| "the Freebox answered without a login challenge")?;
+        let password = freebox_password (app_token.expose(), challenge)?;
+        let body = json!({ "app_id": app_id, "password": password });
+        let session = self.freebox (http::Method::POST, "/login/session/", Some (&body))?
+            .chain_err (|| "login failed, was the token of the application revoked?")?;
+        let session_token = session["session_token"].as_str()
+            .chain_err (|| "the Freebox answered without a session token")?;
+        Ok(Session::Freebox { session_token: session_token.to_string().into() })
+    }
+
+    // Asks the Freebox for a token of the application, which the user has to grant on its
+    // display, and fails telling it: it must be configured, as the Freebox only shows it once.
+    fn authorize_freebox (&mut self) -> Result<()> {
+        let app_id = match self.credentials {
+            Credentials::Freebox { ref app_id, .. } => app_id.clone(),
+            _ => unreachable!("only called with the credentials of a Freebox")
+        };
+        let body = json!({
+            "app_id": app_id,
+            "app_name": "Xenon",
+            "app_version": env!("CARGO_PKG_VERSION"),
+            "device_name": "oxixenon"
+        });
+        let authorization = self.freebox (http::Method::POST, "/login/authorize/", Some (&body))?
+            .chain_err (|| "the Freebox refused to authorize the application")?;
+        let (app_token, track_id) = match (
+            authorization["app_token"].as_str(), authorization["track_id"].as_u64()
+        ) {
+            (Some(app_token), Some(track_id)) => (app_token.to_string(), track_id),
+            _ => bail!("the Freebox answered without a token for the application")
+        };
+        warn!(
+            target: "renewer::fr-box",
+            "grant access to '{}' on the display of the Freebox, within {}",
+            app_id, config::format_duration (AUTHORIZATION_TIMEOUT)
+        );
+        let started = Instant::now();
+        loop {
+            let path = format!("/login/authorize/{}", track_id);
+            let status = self.freebox (http::Method::GET, &path, None)?
+                .chain_err (|| "the Freebox refused to tell the status of the authorization")?;
+            match status["status"].as_str() {
+                Some("granted") => bail!(
+                    "the Freebox granted access to '{}': set 'app_token' to '{}' and restart",
+                    app_id, app_token
+                ),
+                Some("pending") if started.elapsed() < AUTHORIZATION_TIMEOUT =>
+                    thread::sleep (AUTHORIZATION_POLL_INTERVAL),
+                Some("pending") | Some("timeout") =>
+                    bail!("access to '{}' wasn't granted in time on the Freebox", app_id),
+                status => bail!(
+                    "the Freebox didn't grant access to '{}' ({})",
+                    app_id, status.unwrap_or ("unknown")
+                )
+            }
+        }
+    }
+
+    // Calls the Freebox OS API, returning the result of successful calls, or `None` if the session
+    // isn't valid (anymore).
+    fn freebox (&mut self, method: http::Method, path: &str, body: Option<&Value>)
+        -> Result<Option<Value>>
+    {
+        let res = self.request (method, &format!("{}{}", FREEBOX_API, path), body)?;
+        let response = parse_json (&res)?;
+        if response["success"] == true {
+            return Ok(Some (response["result"].clone()));
+        }
+        match response["error_code"].as_str() {
+            Some("auth_required" | "invalid_token" | "invalid_session") => Ok(None),
+            error => bail!(
+                "the request to '{}' failed with '{}': {}",
+                path, error.unwrap_or ("unknown"), response["msg"].as_str().unwrap_or ("")
+            )
+        }
+    }
+
+    // Calls a method of a service of the sysbus API, returning `None` if the session expired.
+    fn sysbus (&mut self, service: &str, method: &str, parameters: Value) -> Result<Option<Value>> {
+        let body = json!({ "service": service, "method": method, "parameters": parameters });
+        let res = self.request (http::Method::POST, "/ws", Some (&body))?;
+        if res.status() == http::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        ensure!(res.status().is_success(), "the request failed with status {}", res.status());
+        let response = parse_json (&res)?;
+        if response["errors"].is_array() {
+            let expired = response["errors"].as_array().unwrap().iter()
+                .any (|error| error["description"] == "Permission denied");
+            ensure!(
+                expired,
+                "calling '{}.{}' failed: {}", service, method, sysbus_error (&response)
+            );
+            return Ok(None);
+        }
+        Ok(Some (response))
+    }
+
+    // Disables and enables the PPP connection of the Livebox. Returns `false` if the session
+    // expired.
+    fn reconnect_livebox (&mut self) -> Result<bool> {
+        for (number, enable) in [0, 1].into_iter().enumerate() {
+            let parameters = json!({
+                "name": "Enable",
+                "value": enable,
+                "flag": "ppp",
+                "traverse": "down"
+            });
+            if self.sysbus ("NeMo.Intf.data", "setFirstParameter", parameters)?.is_none() {
+                ensure!(number == 0, "the session expired while reconnecting");
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // Resets the connection of the Freebox. Returns `false` if the session expired.
+    fn reset_freebox (&mut self) -> Result<bool> {
+        Ok(self.freebox (http::Method::POST, "/connection/reset/", Some (&json!({})))?.is_some())
+    }
+}
+
+fn parse_json (res: &Response<String>) -> Result<Value> {
+    serde_json::from_str (res.body()).chain_err (|| "the gateway answered with invalid JSON")
+}
+
+// The description of the errors of a response of the sysbus API.
+fn sysbus_error (response: &Value) -> String {
+    let errors = response["errors"].as_array().cloned().unwrap_or_default();
+    let descriptions = errors.iter()
+        .filter_map (|error| error["description"].as_str())
+        .collect::<Vec<_>>();
+    if descriptions.is_empty() { "unknown error".into() } else { descriptions.join (", ") }
+}
+
+// The password of a session of the Freebox: HMAC-SHA1 (app_token, challenge), as hexadecimal.
+fn freebox_password (app_token: &str, challenge: &str) -> Result<String> {
+    let key = PKey::hmac (app_token.as_bytes()).chain_err (|| "failed to create the HMAC key")?;
+    let hmac = Signer::new (MessageDigest::sha1(), &key)
+        .and_then (|mut signer| {
+            signer.update (challenge.as_bytes())?;
+            signer.sign_to_vec()
+        })
+        .chain_err (|| "failed to compute the HMAC of the challenge")?;
+    Ok(hmac.iter().map (|byte| format!("{:02x}", byte)).collect())
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let (credentials, default_ip) = match config.flavor {
+            Flavor::Livebox => (Credentials::Livebox {
+                username: config.username.unwrap_or_else (|| "admin".into()),
+                password: config.password.chain_err (|| format!(
+                    "option '{}.password' is required by the Livebox", renewer.config.path()
+                ))?
+            }, DEFAULT_LIVEBOX_IP),
+            Flavor::Freebox => (Credentials::Freebox {
+                app_id: config.app_id.unwrap_or_else (|| DEFAULT_APP_ID.into()),
+                app_token: config.app_token
+            }, DEFAULT_FREEBOX_IP)
+        };
+        Ok(Renewer {
+            ip: http_client::uri_host (config.ip.as_deref().unwrap_or (default_ip)).into_owned(),
+            credentials,
+            session: None,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        if let Credentials::Freebox { app_token: None, .. } = self.credentials {
+            return self.authorize_freebox();
+        }
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let reconnect = |renewer: &mut Self| match renewer.credentials {
+            Credentials::Livebox { .. } => renewer.reconnect_livebox(),
+            Credentials::Freebox { .. } => renewer.reset_freebox()
+        };
+        if self.session.is_none() {
+            self.login()?;
+        }
+        if !reconnect (self)? {
+            debug!(target: "renewer::fr-box", "session expired, logging in again");
+            self.login()?;
+            ensure!(reconnect (self)?, "the gateway rejected the new session");
+        }
+        info!(target: "renewer::fr-box", "successfully reconnected");
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn renewer (transport: &MockTransport, credentials: Credentials) -> Renewer {
+        Renewer {
+            ip: "192.168.1.1".into(),
+            credentials,
+            session: None,
+            transport: Box::new (transport.clone())
+        }
+    }
+
+    #[test]
+    fn livebox_reconnects_with_its_context() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 200 OK\r\nSet-Cookie: 9f1d/sessid=s3ss; path=/\r\n\r\n\
+                {\"status\":0,\"data\":{\"contextID\":\"c0nt3xt\",\"username\":\"admin\"}}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{\"status\":true}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{\"status\":true}");
+        let mut renewer = renewer (&transport, Credentials::Livebox {
+            username: "admin".into(),
+            password: "hunter2".into()
+        });
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("POST /ws HTTP/1.1\r\n"));
+        assert!(requests[0].contains ("authorization: X-Sah-Login\r\n"));
+        assert!(requests[0].contains ("\"password\":\"hunter2\""));
+        assert!(requests[1].contains ("authorization: X-Sah c0nt3xt\r\n"));
+        assert!(requests[1].contains ("x-context: c0nt3xt\r\n"));
+        assert!(requests[1].contains ("cookie: 9f1d/sessid=s3ss\r\n"));
+        assert!(requests[1].contains ("\"value\":0"));
+        assert!(requests[2].contains ("\"value\":1"));
+    }
+
+    #[test]
+    fn freebox_answers_the_challenge() {
+        assert_eq!(
+            freebox_password ("t0k3n", "ch4ll3ng3").unwrap(),
+            "3c99175743f7404f5bc74ff8df9b68a2f3ac0f65"
+        );
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n\
+                {\"success\":true,\"result\":{\"logged_in\":false,\"challenge\":\"ch4ll3ng3\"}}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n\
+                {\"success\":true,\"result\":{\"session_token\":\"s3ss10n\"}}")
+            .push_response ("HTTP/1.1 403 Forbidden\r\n\r\n\
+                {\"success\":false,\"error_code\":\"auth_required\",\"msg\":\"\"}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n\
+                {\"success\":true,\"result\":{\"logged_in\":false,\"challenge\":\"ch4ll3ng3\"}}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n\
+                {\"success\":true,\"result\":{\"session_token\":\"fr3sh\"}}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{\"success\":true}");
+        let mut renewer = renewer (&transport, Credentials::Freebox {
+            app_id: DEFAULT_APP_ID.into(),
+            app_token: Some ("t0k3n".into())
+        });
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("GET /api/v8/login/ HTTP/1.1\r\n"));
+        assert!(requests[1].contains ("\"password\":\"3c99175743f7404f5bc74ff8df9b68a2f3ac0f65\""));
+        assert!(requests[2].starts_with ("POST /api/v8/connection/reset/ HTTP/1.1\r\n"));
+        assert!(requests[2].contains ("x-fbx-app-auth: s3ss10n\r\n"));
+        assert!(requests[5].contains ("x-fbx-app-auth: fr3sh\r\n"));
+    }
+}
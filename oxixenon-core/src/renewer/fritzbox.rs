@@ -0,0 +1,416 @@
+use super::{
+    hex_decode, igd, Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt
+};
+use crate::config::{self, Secret};
+use crate::http_client;
+use hmac::{Hmac, Mac};
+use md5;
+use serde::Deserialize;
+use sha2::Sha256;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Configuration of the renewer, in `[server.renewer.fritzbox]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the router.
+    ip: String,
+    /// The scheme of the web interface, "http" (the default) or "https".
+    scheme: Option<String>,
+    /// The port of the web interface, if it isn't the default one of the scheme.
+    port: Option<u16>,
+    /// Path of a PEM file with the certificates used to verify the web interface over HTTPS (e.g.
+    /// the self-signed certificate of the FritzBox). Defaults to the system certificates.
+    tls_ca_file: Option<String>,
+    username: Option<String>,
+    password: Secret<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+pub struct Renewer {
+    ip: String,
+    // The base URL of the web interface, e.g. "http://fritz.box".
+    url: String,
+    username: Option<String>,
+    password: Secret<String>,
+    sid: Option<Secret<String>>,
+    transport: Box<dyn http_client::Transport>
+}
+
+impl Renewer {
+    fn check_and_retrieve_sid(&mut self) -> Result<()> {
+        info!(target: "renewer::fritzbox", "trying to login using specified credentials");
+
+        // Asking for version 2 gets a PBKDF2 challenge from the firmwares supporting it (FritzOS
+        // 7.24 and later), older ones ignore it.
+        let login_url = format!("{}/login_sid.lua?version=2", self.url);
+
+        let login_url_with_pre_existing_sid = format!("{}{}", login_url, match self.sid.as_ref() {
+            None => "".into(),
+            Some(sid) => format!("&sid={}", sid.expose())
+        });
+
+        // This returns something like:
+        // <SessionInfo>
+        //   <SID>0000000000000000</SID>
+        //   <Challenge>aabbccdd</Challenge> (or 2$10000$5A1711$2000$5A1722 with PBKDF2)
+        //   <BlockTime>0</BlockTime>
+        //   <Rights/>
+        // </SessionInfo>
+        //
+        // If SID is not all zeroes, the SID we have is already valid.
+        // If BlockTime is different than 0, then a login attempt failed.
+        // Challenge is used to actually perform the login.
+
+        let res = http_client::get_with(&mut *self.transport, &login_url_with_pre_existing_sid)
+            .chain_err(|| format!("HTTP request to '{}' failed", login_url))?;
+        ensure!(res.status().is_success(), "failed to request the login page");
+
+        let body = res.body();
+
+        // See if we already have a valid SID.
+        if self.set_sid_if_valid(body).is_ok() {
+            info!(target: "renewer::fritzbox", "login unnecessary - pre-existing SID is still valid");
+            return Ok(());
+        }
+
+        // SID invalid, we need to login.
+        let challenge = Self::extract_xml_tag(body, "Challenge")
+            .chain_err(|| "failed to extract login challenge")?;
+
+        debug!(target: "renewer::fritzbox", "challenge is {}", challenge);
+
+        let response = match challenge.strip_prefix("2$") {
+            Some(challenge) => pbkdf2_response(challenge, self.password.expose())?,
+            None => md5_response(challenge, self.password.expose())
+        };
+
+        // Newer FritzOS versions don't support empty usernames. Try to find a valid username
+        // by finding an User tag with the 'last=1' field set.
+        let username = match self.username.as_ref() {
+            Some(username) => username,
+            // note: not the most robust way to extract a tag with an attribute... but it works.
+            None => Self::extract_xml_tag(body, r#"User last="1""#).unwrap_or("")
+        };
+
+        // Login is a POST request to the same url containing the parameters:
+        // ["username": "...",  "response": "{challenge}-md5({challenge-pwd})"] (or the PBKDF2
+        // response, see `pbkdf2_response`)
+        let res = http_client::build_post(&login_url)
+            .put("username", username)
+            .put("response", &response)
+            .build_and_execute_with(&mut *self.transport)
+            .chain_err(|| format!("HTTP request to login at '{}' failed", login_url))?;
+
+        let body = res.body();
+
+        debug!(target: "renewer::fritzbox", "login attempt finished - blocktime is {}",
+            Self::extract_xml_tag(body, "BlockTime").unwrap_or("N/A"));
+
+        // Check SID and return if OK.
+        self.set_sid_if_valid(body)
+    }
+
+    fn extract_xml_tag<'a>(source: &'a str, field: &'static str) -> Option<&'a str> {
+        // This is a rough text processing function to extract content of XMl tags.
+        // Find the tag itself first.
+        let full_tag = format!("<{}>", field);
+        let field_start = source.find(&full_tag)?;
+        // Get the contents of the tag plus whatever is after.
+        let field_content_unclamped = source.get((field_start + full_tag.len())..)?;
+        // Find the closing bracket.
+        let field_end = field_content_unclamped.find("<")?;
+        // Clamp to the closing bracket to obtain just the value.
+        field_content_unclamped.get(..field_end)
+    }
+
+    fn set_sid_if_valid(&mut self, document: &str) -> Result<()> {
+        match Self::extract_xml_tag(document, "SID") {
+            Some(sid) if sid.contains(|c| c != '0') => {
+                self.sid = Some(sid.into());
+                info!(target: "renewer::fritzbox", "login OK");
+                Ok(())
+            },
+            _ => bail!("login failed, check your credentials!")
+        }
+    }
+
+    // Disconnects the link, and asks to connect it again.
+    fn reconnect(&mut self) -> Result<()> {
+        let sid = match self.sid.as_ref() {
+            None => {
+                self.check_and_retrieve_sid()?;
+                self.sid.as_ref().expect("SID must have been correctly fetched")
+            },
+            Some(sid) => sid
+        };
+
+        let data_url = format!("{}/data.lua", self.url);
+        let res = http_client::build_post(&data_url)
+            .put("xhr", "1")
+            .put("sid", sid.expose())
+            .put("page", "netMoni")
+            .put("xhrId", "reconnect")
+            .put("disconnect", "true")
+            .build_and_execute_with(&mut *self.transport)
+            .chain_err(|| "HTTP request to renewal URL failed")?;
+
+        // New versions of FritzOS do not return a 403 anymore when the SID is invalid, but just
+        // attempt to redirect to the homepage.
+        if res.status().as_u16() == 403 || res.status().as_u16() == 303 {
+            // Oops! Invalid SID. Invalidate it and login again.
+            self.sid = None;
+            return self.reconnect();
+        }
+
+        ensure!(
+            res.status().is_success(),
+            "IP address renewal failed - server returned {}", res.status()
+        );
+
+        // Send a "connect" request too to speed things up. Ignore errors.
+        let _ = http_client::build_post(&data_url)
+            .put("xhr", "1")
+            .put("sid", sid.expose())
+            .put("page", "netMoni")
+            .put("xhrId", "reconnect")
+            .put("connect", "true")
+            .build_and_execute_with(&mut *self.transport);
+
+        info!(target: "renewer::fritzbox", "successfully asked for another IP");
+
+        Ok(())
+    }
+}
+
+// Answers the MD5 `challenge` of older firmwares.
+fn md5_response(challenge: &str, password: &str) -> String {
+    // Passwords needs to be encoded to UTF-16 and any codepoints above 255 needs to be replaced
+    // with a dot.
+    let password_bytes = format!("{}-{}", challenge, password)
+        .chars()
+        .map(|c| if c as u32 > 255 { '.' } else { c })
+        .collect::<String>()
+        .encode_utf16()
+        .flat_map(|v| v.to_le_bytes().to_vec())
+        .collect::<Vec<_>>();
+    format!("{}-{:x}", challenge, md5::compute(password_bytes))
+}
+
+// Answers the PBKDF2 `challenge` (without its "2$" prefix), "<iter1>$<salt1>$<iter2>$<salt2>"
+// with hex-encoded salts: the response is "<salt2>$" followed by the hex encoding of
+// PBKDF2(PBKDF2(password, salt1, iter1), salt2, iter2).
+fn pbkdf2_response(challenge: &str, password: &str) -> Result<String> {
+    let parts: Vec<_> = challenge.split('$').collect();
+    let (iter1, salt1, iter2, salt2) = match parts[..] {
+        [iter1, salt1, iter2, salt2] => (iter1, salt1, iter2, salt2),
+        _ => bail!("invalid PBKDF2 login challenge '2${}'", challenge)
+    };
+    let iterations = |iterations: &str| iterations.parse::<u32>().ok()
+        .filter(|&iterations| iterations > 0)
+        .chain_err(|| format!("invalid iterations in the login challenge '2${}'", challenge));
+    let salt = |salt: &str| hex_decode(salt)
+        .chain_err(|| format!("invalid salt in the login challenge '2${}'", challenge));
+    let hash1 = pbkdf2_sha256(password.as_bytes(), &salt(salt1)?, iterations(iter1)?);
+    let hash2 = pbkdf2_sha256(&hash1, &salt(salt2)?, iterations(iter2)?);
+    let hash2: String = hash2.iter().map(|b| format!("{:02x}", b)).collect();
+    Ok(format!("{}${}", salt2, hash2))
+}
+
+// PBKDF2 with HMAC-SHA256, deriving a single block (as long as the hash).
+fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> Vec<u8> {
+    let mac = HmacSha256::new_varkey(password).expect("Can't create HmacSha256");
+    let mut block = mac.clone();
+    block.input(salt);
+    block.input(&1u32.to_be_bytes());
+    let mut u = block.result().code();
+    let mut result = u.to_vec();
+    for _ in 1..iterations {
+        let mut block = mac.clone();
+        block.input(&u);
+        u = block.result().code();
+        result.iter_mut().zip(u.iter()).for_each(|(r, b)| *r ^= b);
+    }
+    result
+}
+
+// The URL of the UPnP description of the router at `ip`, which FritzBoxes expose unless "Transmit
+// status information over UPnP" is disabled.
+fn igd_description(ip: &str) -> String {
+    format!("http://{}:49000/igddesc.xml", ip)
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config(renewer: &config::RenewerConfig) -> Result<Self> where Self: Sized {
+        let config: Config = renewer.config.parse_with_secrets(SECRETS)
+            .chain_err(|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let url = http_client::base_url(config.scheme.as_deref(), &config.ip, config.port)
+            .chain_err(|| format!("invalid option '{}.scheme'", renewer.config.path()))?;
+        let transport: Box<dyn http_client::Transport> = match config.tls_ca_file {
+            Some(ca_file) => Box::new(http_client::TlsTransport::with_ca_file(ca_file)),
+            None => Box::new(http_client::TcpTransport)
+        };
+
+        Ok(Self {
+            ip: http_client::uri_host(&config.ip).into_owned(),
+            url,
+            username: config.username,
+            password: config.password,
+            sid: None,
+            transport
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config>(gen, SECRETS)
+    }
+
+    fn init(&mut self) -> Result<()> {
+        self.check_and_retrieve_sid()
+    }
+
+    fn renew_ip(&mut self) -> Result<RenewOutcome> {
+        // The new address isn't known until the link is up again, unlike the old one.
+        let description = igd_description(&self.ip);
+        let old_ip = igd::external_ip(&mut *self.transport, &description)
+            .unwrap_or_else(|error| {
+                debug!(target: "renewer::fritzbox", "can't read the external IP: {}", error);
+                None
+            });
+        self.reconnect()?;
+        Ok(RenewOutcome { old_ip, new_ip: None })
+    }
+
+    fn link_info(&mut self) -> Result<Option<LinkInfo>> {
+        igd::link_info(&mut *self.transport, &igd_description(&self.ip)).map(Some)
+    }
+
+    // Checking the SID refreshes the session, or logs in again once it expired.
+    fn keepalive(&mut self) -> Result<()> {
+        self.check_and_retrieve_sid()
+    }
+
+    // Logging in (unless the SID is still valid) tells whether the credentials are still
+    // accepted, once the router answers.
+    fn health_check(&mut self) -> Result<Health> {
+        let login_url = format!("{}/login_sid.lua", self.url);
+        if http_client::get_with(&mut *self.transport, &login_url).is_err() {
+            return Ok(Health::unreachable(format!("'{}' doesn't answer", login_url)));
+        }
+        Ok(match self.check_and_retrieve_sid() {
+            Ok(()) => Health::logged_in(),
+            Err(error) => Health::login_failed(error.to_string())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn session_info(sid: &str) -> String {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+            <SessionInfo><SID>{}</SID><Challenge>1234567z</Challenge>\
+            <BlockTime>0</BlockTime><Users><User last=\"1\">fritz1234</User></Users>\
+            </SessionInfo>\r\n",
+            sid
+        )
+    }
+
+    fn renewer(transport: &MockTransport) -> Renewer {
+        Renewer {
+            ip: "fritz.box".into(),
+            url: "http://fritz.box".into(),
+            username: None,
+            password: "äbc".into(),
+            sid: None,
+            transport: Box::new(transport.clone())
+        }
+    }
+
+    #[test]
+    fn login_answers_md5_challenge() {
+        let transport = MockTransport::new();
+        transport
+            .push_response(session_info("0000000000000000"))
+            .push_response(session_info("0123456789abcdef"));
+        let mut renewer = renewer(&transport);
+        renewer.init().unwrap();
+        assert_eq!(renewer.sid.as_ref().map(|sid| sid.expose().as_str()), Some("0123456789abcdef"));
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with("GET /login_sid.lua?version=2 HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with("POST /login_sid.lua?version=2 HTTP/1.1\r\n"));
+        assert!(requests[1].contains("response=1234567z-9e224a41eeefa284df7bb0f26c2913e2"));
+        assert!(requests[1].contains("username=fritz1234"));
+    }
+
+    #[test]
+    fn login_answers_pbkdf2_challenge() {
+        // The example of AVM's documentation.
+        assert_eq!(
+            pbkdf2_response("10000$5A1711$2000$5A1722", "1example!").unwrap(),
+            "5A1722$1798a1672bca7c6463d6b245f82b53703b0f50813401b03e4045a5861e689adb"
+        );
+        assert!(pbkdf2_response("10000$5A1711$2000", "1example!").is_err());
+        assert!(pbkdf2_response("10000$5A1711$0$5A1722", "1example!").is_err());
+        assert!(pbkdf2_response("10000$5Z1711$2000$5A1722", "1example!").is_err());
+
+        let transport = MockTransport::new();
+        transport
+            .push_response(session_info("0000000000000000")
+                .replace("1234567z", "2$10000$5A1711$2000$5A1722"))
+            .push_response(session_info("0123456789abcdef"));
+        let mut renewer = renewer(&transport);
+        renewer.password = "1example!".into();
+        renewer.init().unwrap();
+        assert!(transport.requests()[1].contains("response=5A1722$1798a1672bca7c6463d6b245f82b53"));
+    }
+
+    #[test]
+    fn login_fails_with_wrong_credentials() {
+        let transport = MockTransport::new();
+        transport
+            .push_response(session_info("0000000000000000"))
+            .push_response(session_info("0000000000000000"));
+        assert!(renewer(&transport).init().is_err());
+    }
+
+    #[test]
+    fn renew_retries_with_fresh_sid_on_redirect() {
+        let transport = MockTransport::new();
+        transport
+            .push_response("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n<root><serviceList>\
+                <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1\
+                </serviceType><controlURL>/igdupnp/control/WANIPConn1</controlURL></service>\
+                </serviceList></root>")
+            .push_response("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n<s:Envelope>\
+                <NewExternalIPAddress>203.0.113.7</NewExternalIPAddress></s:Envelope>")
+            .push_response("HTTP/1.1 303 See Other\r\nLocation: /\r\n\r\n")
+            .push_response(session_info("0000000000000000"))
+            .push_response(session_info("0123456789abcdef"))
+            .push_response("HTTP/1.1 200 OK\r\n\r\n{}\r\n")
+            .push_response("HTTP/1.1 200 OK\r\n\r\n{}\r\n");
+        let mut renewer = renewer(&transport);
+        renewer.sid = Some("fedcba9876543210".into());
+        let outcome = renewer.renew_ip().unwrap();
+        assert_eq!(outcome.old_ip, Some("203.0.113.7".parse().unwrap()));
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 7);
+        assert!(requests[1].contains("<u:GetExternalIPAddress"));
+        assert!(requests[2].contains("sid=fedcba9876543210"));
+        assert!(requests[5].starts_with("POST /data.lua HTTP/1.1\r\n"));
+        assert!(requests[5].contains("sid=0123456789abcdef"));
+        assert!(requests[5].contains("disconnect=true"));
+        assert!(requests[6].contains("connect=true"));
+    }
+}
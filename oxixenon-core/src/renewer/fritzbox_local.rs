@@ -1,4 +1,4 @@
-use super::{Renewer as RenewerTrait, Result, ResultExt};
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
 use crate::config;
 use std::process::{Command, Stdio};
 
@@ -12,6 +12,11 @@ impl RenewerTrait for Renewer {
         Ok(Self {})
     }
 
+    #[cfg(feature = "config-schema")]
+    fn config_schema (_gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::empty_section()
+    }
+
     fn init (&mut self) -> Result<()> {
         use std::path::Path;
         // Check if CTLMGR_CTL_PATH exists.
@@ -26,7 +31,7 @@ impl RenewerTrait for Renewer {
         Ok(())
     }
 
-    fn renew_ip (&mut self) -> Result<()> {
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
         macro_rules! exec_command {
             (param $arg:expr, error_msg $err:expr) => {
                 Command::new (CTLMGR_CTL_PATH)
@@ -43,6 +48,7 @@ impl RenewerTrait for Renewer {
             }
         }
         exec_command!(param "settings/cmd_disconnect", error_msg "failed to disconnect network")?;
-        exec_command!(param "settings/cmd_connect",    error_msg "failed to reconnect network")
+        exec_command!(param "settings/cmd_connect",    error_msg "failed to reconnect network")?;
+        Ok(RenewOutcome::default())
     }
 }
@@ -0,0 +1,329 @@
+//! A renewer whose HTTP requests are declared in the configuration, so that routers whose web
+//! interface is simple enough (e.g. a login form followed by a "reconnect" button) can be
+//! supported without writing Rust.
+//!
+//! Each step sends a request and checks its response, and can extract values from it (e.g. a
+//! session token) to be used by the following steps as `{name}` in their URL, headers and body.
+//! Cookies set by the router are sent back by the following steps.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+// Configuration of the renewer, in `[server.renewer.generic-http]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// Available to the steps as `{username}`.
+    username: Option<String>,
+    /// Available to the steps as `{password}`.
+    password: Option<Secret<String>>,
+    /// Other values available to the steps, e.g. `{ ip = "192.168.1.1" }` for `{ip}`.
+    #[serde(default)]
+    variables: BTreeMap<String, String>,
+    /// Steps run by `init` (e.g. to check that the router is reachable) before renewing.
+    /// Optional.
+    #[serde(default)]
+    init_steps: Vec<Step>,
+    /// The steps which renew the IP address, run in order.
+    steps: Vec<Step>
+}
+
+/// A request, along with what its response must look like.
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Step {
+    /// The HTTP method, "GET" unless specified (or "POST" when there's a body).
+    method: Option<String>,
+    /// The URL of the request, e.g. "http://{ip}/login.cgi".
+    url: String,
+    /// Additional headers of the request.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// Fields sent as an URL-encoded form.
+    form: Option<BTreeMap<String, String>>,
+    /// A raw body, sent as is (set the `Content-Type` header accordingly).
+    body: Option<String>,
+    /// The accepted statuses of the response. Any 2xx status is accepted unless specified.
+    status: Option<Vec<u16>>,
+    /// Text which the body of the response must contain.
+    expect: Option<String>,
+    /// Values extracted from the body of the response, as patterns where `{}` marks the value,
+    /// e.g. `{ token = "name=\"token\" value=\"{}\"" }`. The value ends where the text after
+    /// `{}` begins, or at the end of the line.
+    #[serde(default)]
+    extract: BTreeMap<String, String>,
+    /// Like `extract`, but searching the headers of the response, one per line as
+    /// "name: value" (with lowercase names), e.g. `{ location = "location: {}" }`.
+    #[serde(default)]
+    extract_headers: BTreeMap<String, String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+pub struct Renewer {
+    variables: BTreeMap<String, String>,
+    init_steps: Vec<Step>,
+    steps: Vec<Step>,
+    // The cookies set by the router, by name.
+    cookies: BTreeMap<String, String>,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    fn run (&mut self, steps: &[Step]) -> Result<()> {
+        for (number, step) in steps.iter().enumerate() {
+            self.run_step (step)
+                .chain_err (|| format!("step {} ({}) failed", number + 1, step.url))?;
+        }
+        Ok(())
+    }
+
+    fn run_step (&mut self, step: &Step) -> Result<()> {
+        let url = expand (&step.url, &self.variables)?;
+        let body = match (&step.form, &step.body) {
+            (Some(_), Some(_)) => bail!("a step can't have both a form and a body"),
+            (Some(form), None) => Some (form.iter()
+                .map (|(name, value)| Ok(format!(
+                    "{}={}", encode (name), encode (&expand (value, &self.variables)?)
+                )))
+                .collect::<Result<Vec<_>>>()?
+                .join ("&")),
+            (None, Some(body)) => Some (expand (body, &self.variables)?),
+            (None, None) => None
+        };
+        let method = match step.method {
+            Some(ref method) => method.to_ascii_uppercase(),
+            None if body.is_some() => "POST".into(),
+            None => "GET".into()
+        };
+        let mut request = Request::builder().method (method.as_str()).uri (url.as_str());
+        if step.form.is_some() {
+            request = request.header (header::CONTENT_TYPE, "application/x-www-form-urlencoded");
+        }
+        if !self.cookies.is_empty() {
+            let cookies = self.cookies.iter()
+                .map (|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>();
+            request = request.header (header::COOKIE, cookies.join ("; "));
+        }
+        for (name, value) in &step.headers {
+            request = request.header (name.as_str(), expand (value, &self.variables)?);
+        }
+        // Requests with a body must always have one, even if empty.
+        let body = body.or_else (|| (method == "POST" || method == "PUT").then (String::new));
+        let request = request.body (body)
+            .chain_err (|| format!("invalid request {} {}", method, url))?;
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+
+        let status = res.status();
+        match step.status {
+            Some(ref accepted) => ensure!(
+                accepted.contains (&status.as_u16()),
+                "got status {}, expected one of {:?}", status, accepted
+            ),
+            None => ensure!(status.is_success(), "got status {}", status)
+        }
+        if let Some(ref expect) = step.expect {
+            ensure!(res.body().contains (expect.as_str()), "the response doesn't contain '{}'",
+                expect);
+        }
+        for cookie in res.headers().get_all (header::SET_COOKIE) {
+            let cookie = cookie.to_str().unwrap_or_default();
+            let pair = cookie.split (';').next().and_then (|pair| pair.split_once ('='));
+            if let Some((name, value)) = pair {
+                self.cookies.insert (name.trim().to_string(), value.trim().to_string());
+            }
+        }
+        let headers = res.headers().iter()
+            .map (|(name, value)| format!("{}: {}\n", name, value.to_str().unwrap_or_default()))
+            .collect::<String>();
+        let sources = [(&step.extract, res.body().as_str()), (&step.extract_headers, &headers)];
+        for (patterns, source) in sources {
+            for (name, pattern) in patterns {
+                let value = extract (source, pattern)
+                    .chain_err (|| format!("can't extract '{}' from the response", name))?;
+                trace!(target: "renewer::generic-http", "extracted '{}'", name);
+                self.variables.insert (name.clone(), value.to_string());
+            }
+        }
+        Ok(())
+    }
+}
+
+// Replaces the `{name}` placeholders of `template` with their variables. `{{` and `}}` are
+// literal braces.
+fn expand (template: &str, variables: &BTreeMap<String, String>) -> Result<String> {
+    let mut expanded = String::with_capacity (template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find (['{', '}']) {
+        expanded.push_str (&rest[..start]);
+        rest = &rest[start..];
+        if rest.starts_with ("{{") || rest.starts_with ("}}") {
+            expanded.push_str (&rest[..1]);
+            rest = &rest[2..];
+            continue;
+        }
+        let end = rest.find ('}')
+            .filter (|_| rest.starts_with ('{'))
+            .chain_err (|| format!("unbalanced braces in '{}'", template))?;
+        let name = &rest[1..end];
+        let value = variables.get (name)
+            .chain_err (|| format!("unknown variable '{}' in '{}'", name, template))?;
+        expanded.push_str (value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str (rest);
+    Ok(expanded)
+}
+
+// Extracts the value marked by `{}` in `pattern` from `source`.
+fn extract<'a> (source: &'a str, pattern: &str) -> Option<&'a str> {
+    let (before, after) = pattern.split_once ("{}")?;
+    let start = source.find (before)? + before.len();
+    let rest = &source[start..];
+    let end = if after.is_empty() {
+        rest.find (['\r', '\n']).unwrap_or (rest.len())
+    } else {
+        rest.find (after)?
+    };
+    Some (&rest[..end])
+}
+
+// Percent-encodes a form field (`application/x-www-form-urlencoded`).
+fn encode (value: &str) -> String {
+    value.bytes()
+        .map (|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'*' =>
+                (byte as char).to_string(),
+            b' ' => "+".into(),
+            _ => format!("%{:02X}", byte)
+        })
+        .collect()
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        ensure!(
+            !config.steps.is_empty(),
+            "option '{}.steps' must have at least a step", renewer.config.path()
+        );
+        let mut variables = config.variables;
+        if let Some(username) = config.username {
+            variables.insert ("username".into(), username);
+        }
+        if let Some(password) = config.password {
+            variables.insert ("password".into(), password.expose().clone());
+        }
+        Ok(Renewer {
+            variables,
+            init_steps: config.init_steps,
+            steps: config.steps,
+            cookies: BTreeMap::new(),
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        let steps = self.init_steps.clone();
+        self.run (&steps)
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        // Each renewal starts a new session.
+        self.cookies.clear();
+        let steps = self.steps.clone();
+        self.run (&steps)?;
+        info!(target: "renewer::generic-http", "successfully asked for another IP");
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    #[test]
+    fn templates_are_expanded() {
+        let variables = [("ip".to_string(), "10.0.0.1".to_string())].into_iter().collect();
+        assert_eq!(expand ("http://{ip}/{{x}}", &variables).unwrap(), "http://10.0.0.1/{x}");
+        assert!(expand ("http://{host}/", &variables).is_err());
+        assert!(expand ("http://{ip/", &variables).is_err());
+
+        let page = "<input name=\"token\" value=\"t0k3n\">\nsid: 1234\r\n";
+        assert_eq!(extract (page, "name=\"token\" value=\"{}\""), Some ("t0k3n"));
+        assert_eq!(extract (page, "sid: {}"), Some ("1234"));
+        assert_eq!(extract (page, "nonce={}&"), None);
+        assert_eq!(encode ("a b&c=ä"), "a+b%26c%3D%C3%A4");
+    }
+
+    #[test]
+    fn steps_reuse_extracted_values_and_cookies() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<input name=\"csrf\" value=\"c5rf\">\r\n")
+            .push_response ("HTTP/1.1 302 Found\r\nSet-Cookie: session=abc; path=/\r\n\
+                Location: /home\r\n\r\n")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{\"result\":\"ok\"}\r\n");
+        let step = |method: Option<&str>, url: &str| Step {
+            method: method.map (String::from),
+            url: url.into(),
+            headers: BTreeMap::new(),
+            form: None,
+            body: None,
+            status: None,
+            expect: None,
+            extract: BTreeMap::new(),
+            extract_headers: BTreeMap::new()
+        };
+        let mut login = step (None, "http://{ip}/login");
+        login.form = Some ([("user", "{username}"), ("csrf", "{csrf}")].iter()
+            .map (|&(name, value)| (name.to_string(), value.to_string()))
+            .collect());
+        login.status = Some (vec![302]);
+        login.extract_headers.insert ("home".into(), "location: {}".into());
+        let mut reconnect = step (Some ("post"), "http://{ip}{home}?action=reconnect");
+        reconnect.expect = Some ("\"ok\"".into());
+        let mut page = step (None, "http://{ip}/login");
+        page.extract.insert ("csrf".into(), "name=\"csrf\" value=\"{}\"".into());
+
+        let mut renewer = Renewer {
+            variables: [("ip", "10.0.0.1"), ("username", "admin user")].iter()
+                .map (|&(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            init_steps: Vec::new(),
+            steps: vec![page, login, reconnect],
+            cookies: BTreeMap::new(),
+            transport: Box::new (transport.clone())
+        };
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("GET /login HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with ("POST /login HTTP/1.1\r\n"));
+        assert!(requests[1].ends_with ("csrf=c5rf&user=admin+user\r\n"));
+        assert!(requests[2].starts_with ("POST /home?action=reconnect HTTP/1.1\r\n"));
+        assert!(requests[2].contains ("cookie: session=abc\r\n"));
+
+        // A response which doesn't match fails the renewal.
+        transport.push_response ("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        let error = renewer.renew_ip().unwrap_err();
+        assert!(error.to_string().starts_with ("step 1 (http://{ip}/login) failed"));
+    }
+}
@@ -0,0 +1,215 @@
+//! Link information read through UPnP IGD (Internet Gateway Device), which many routers expose
+//! without authentication. Used by the renewers whose routers support it, see `link_info`.
+
+use super::{LinkInfo, Result, ResultExt};
+use crate::http_client::{self, header, Request, Transport};
+use std::time::Duration;
+
+const COMMON_INTERFACE: &str = "WANCommonInterfaceConfig";
+// The service of the connection, depending on whether the router uses PPP or not.
+const CONNECTIONS: &[&str] = &["WANPPPConnection", "WANIPConnection"];
+
+/// Reads the link information of the router described by `description`, the URL of its UPnP
+/// device description (e.g. "http://192.168.1.1:49000/igddesc.xml"). Fields which the router
+/// doesn't expose (or whose action fails) are left empty.
+///
+/// The state is the one of the physical link while it isn't up (e.g. "Initializing" while a DSL
+/// link is training), the one of the connection otherwise (e.g. "Connected").
+pub(super) fn link_info (transport: &mut dyn Transport, description: &str) -> Result<LinkInfo> {
+    let services = fetch_services (transport, description)?;
+    let mut link = LinkInfo::default();
+    let mut physical_state = None;
+    if let Some((service, url)) = services.iter().find (|(s, _)| s.contains (COMMON_INTERFACE)) {
+        if let Some(response) = call (transport, url, service, "GetCommonLinkProperties") {
+            physical_state = tag (&response, "NewPhysicalLinkStatus").map (String::from);
+            // Rates are reported in bit/s.
+            let rate = |name| tag (&response, name)
+                .and_then (|rate| rate.parse::<u64>().ok())
+                .map (|rate| u32::try_from (rate / 1000).unwrap_or (u32::MAX));
+            link.downstream_rate = rate ("NewLayer1DownstreamMaxBitRate");
+            link.upstream_rate = rate ("NewLayer1UpstreamMaxBitRate");
+        }
+    }
+    let connection = CONNECTIONS.iter()
+        .find_map (|name| services.iter().find (|(s, _)| s.contains (name)));
+    if let Some((service, url)) = connection {
+        if let Some(response) = call (transport, url, service, "GetStatusInfo") {
+            link.state = tag (&response, "NewConnectionStatus").map (String::from);
+            link.uptime = tag (&response, "NewUptime")
+                .and_then (|uptime| uptime.parse().ok())
+                .map (Duration::from_secs);
+        }
+        if let Some(response) = call (transport, url, service, "GetExternalIPAddress") {
+            link.external_ip = tag (&response, "NewExternalIPAddress")
+                .and_then (|ip| ip.parse().ok());
+        }
+    }
+    match physical_state {
+        Some(state) if state != "Up" => link.state = Some (state),
+        Some(state) if link.state.is_none() => link.state = Some (state),
+        _ => ()
+    }
+    Ok(link)
+}
+
+/// Reads only the external IP address of the router described by `description`, as in
+/// `link_info`. Returns `None` if the router doesn't expose it.
+#[cfg(feature = "renewer-fritzbox")]
+pub(super) fn external_ip (transport: &mut dyn Transport, description: &str)
+    -> Result<Option<std::net::IpAddr>>
+{
+    let services = fetch_services (transport, description)?;
+    let connection = CONNECTIONS.iter()
+        .find_map (|name| services.iter().find (|(s, _)| s.contains (name)));
+    Ok(connection
+        .and_then (|(service, url)| call (transport, url, service, "GetExternalIPAddress"))
+        .and_then (|response| tag (&response, "NewExternalIPAddress")?.parse().ok()))
+}
+
+// Requests the device description at `description`, returning its services (see `services`).
+pub(super) fn fetch_services (transport: &mut dyn Transport, description: &str)
+    -> Result<Vec<(String, String)>>
+{
+    let res = http_client::get_with (transport, description)
+        .chain_err (|| format!("HTTP request to '{}' failed", description))?;
+    ensure!(
+        res.status().is_success(),
+        "failed to request the UPnP description, got status {}", res.status()
+    );
+    let base: http::Uri = description.parse()
+        .chain_err (|| format!("invalid UPnP description URL '{}'", description))?;
+    let base = format!("{}://{}",
+        base.scheme_str().unwrap_or ("http"),
+        base.authority().map (|a| a.as_str()).unwrap_or_default());
+    Ok(services (res.body(), &base))
+}
+
+// The services listed in a device description, as their type and their control URL (resolved
+// against `base`).
+pub(super) fn services (description: &str, base: &str) -> Vec<(String, String)> {
+    description.split ("<service>")
+        .skip (1)
+        .filter_map (|service| {
+            let control = tag (service, "controlURL")?;
+            let url = if control.starts_with ("http") {
+                control.to_string()
+            } else {
+                format!("{}/{}", base, control.trim_start_matches ('/'))
+            };
+            Some ((tag (service, "serviceType")?.to_string(), url))
+        })
+        .collect()
+}
+
+// Calls the SOAP `action` (which has no arguments) of `service`, returning the response. Failures
+// are only logged, as routers rarely implement every action.
+fn call (transport: &mut dyn Transport, url: &str, service: &str, action: &str) -> Option<String> {
+    let request = soap_request (url, service, action).body (Some (envelope (service, action)));
+    let response = request.map_err (|e| e.to_string())
+        .and_then (|request| http_client::make_request_with (transport, request)
+            .map_err (|e| e.to_string()));
+    match response {
+        Ok(response) if response.status().is_success() => Some (response.into_body()),
+        Ok(response) => {
+            debug!(target: "renewer::igd", "{} failed with status {}", action, response.status());
+            None
+        },
+        Err(error) => {
+            debug!(target: "renewer::igd", "{} failed: {}", action, error);
+            None
+        }
+    }
+}
+
+// A request for the SOAP `action` of `service`, without its body (see `envelope`).
+pub(super) fn soap_request (url: &str, service: &str, action: &str) -> http::request::Builder {
+    Request::post (url)
+        .header (header::CONTENT_TYPE, "text/xml; charset=\"utf-8\"")
+        .header ("SOAPAction", format!("\"{}#{}\"", service, action))
+}
+
+// The SOAP envelope calling `action` (which has no arguments) of `service`.
+pub(super) fn envelope (service: &str, action: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\"?>\
+        <s:Envelope xmlns:s=\"http://schemas.xmlsoap.org/soap/envelope/\" \
+        s:encodingStyle=\"http://schemas.xmlsoap.org/soap/encoding/\">\
+        <s:Body><u:{action} xmlns:u=\"{service}\"/></s:Body></s:Envelope>",
+        action = action, service = service
+    )
+}
+
+// Extracts the contents of the first `<name>` tag of `source`, ignoring any namespace prefix of
+// the tag.
+pub(super) fn tag<'a> (source: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("{}>", name);
+    let start = source.match_indices (&open)
+        .map (|(index, _)| index)
+        .find (|&index| matches!(source[..index].chars().last(), Some('<') | Some(':')))?;
+    let contents = &source[start + open.len()..];
+    Some (contents[..contents.find ('<')?].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    const DESCRIPTION: &str = "HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+        <root><device><serviceList>\
+        <service><serviceType>urn:schemas-upnp-org:service:WANCommonInterfaceConfig:1\
+        </serviceType><controlURL>/igdupnp/control/WANCommonIFC1</controlURL></service>\
+        <service><serviceType>urn:schemas-upnp-org:service:WANIPConnection:1</serviceType>\
+        <controlURL>/igdupnp/control/WANIPConn1</controlURL></service>\
+        </serviceList></device></root>\r\n";
+
+    fn soap_response (contents: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+            <s:Envelope><s:Body><u:Response>{}</u:Response></s:Body></s:Envelope>\r\n", contents)
+    }
+
+    #[test]
+    fn link_info_is_read_from_the_services() {
+        let transport = MockTransport::new();
+        transport
+            .push_response (DESCRIPTION)
+            .push_response (soap_response ("<NewPhysicalLinkStatus>Up</NewPhysicalLinkStatus>\
+                <NewLayer1UpstreamMaxBitRate>40000000</NewLayer1UpstreamMaxBitRate>\
+                <NewLayer1DownstreamMaxBitRate>100000000</NewLayer1DownstreamMaxBitRate>"))
+            .push_response (soap_response ("<NewConnectionStatus>Connected</NewConnectionStatus>\
+                <NewUptime>3600</NewUptime>"))
+            .push_response ("HTTP/1.1 500 Internal Server Error\r\n\r\n");
+        let link = link_info (&mut transport.clone(), "http://fritz.box:49000/igddesc.xml")
+            .unwrap();
+        assert_eq!(link, LinkInfo {
+            state: Some ("Connected".into()),
+            uptime: Some (Duration::from_secs (3600)),
+            downstream_rate: Some (100_000),
+            upstream_rate: Some (40_000),
+            external_ip: None
+        });
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("GET /igddesc.xml HTTP/1.1\r\n"));
+        assert!(requests[1].starts_with ("POST /igdupnp/control/WANCommonIFC1 HTTP/1.1\r\n"));
+        assert!(requests[2].contains (
+            "soapaction: \"urn:schemas-upnp-org:service:WANIPConnection:1#GetStatusInfo\"\r\n"));
+        assert!(requests[3].contains ("<u:GetExternalIPAddress"));
+    }
+
+    #[test]
+    fn training_links_report_their_physical_state() {
+        let transport = MockTransport::new();
+        transport
+            .push_response (DESCRIPTION)
+            .push_response (soap_response (
+                "<NewPhysicalLinkStatus>Initializing</NewPhysicalLinkStatus>"))
+            .push_response (soap_response (
+                "<NewConnectionStatus>Connecting</NewConnectionStatus>"))
+            .push_response (soap_response ("<NewExternalIPAddress></NewExternalIPAddress>"));
+        let link = link_info (&mut transport.clone(), "http://fritz.box:49000/igddesc.xml")
+            .unwrap();
+        assert_eq!(link.state.as_deref(), Some ("Initializing"));
+        assert_eq!(link.external_ip, None);
+    }
+}
@@ -0,0 +1,433 @@
+use crate::config;
+use crate::errors::Cause;
+use std::collections::BTreeMap;
+use std::sync::{OnceLock, RwLock};
+use std::time::Duration;
+pub use crate::protocol::{Health, LinkInfo, RenewOutcome};
+#[cfg(feature = "config-schema")]
+use schemars::{gen::SchemaGenerator, schema::Schema};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    #[error(transparent)]
+    Config(#[from] config::Error),
+    /// An operation of the renewer took longer than its `operation_timeout`.
+    #[error("timed out after {}", config::format_duration (*.0))]
+    TimedOut(Duration)
+}
+
+impl Error {
+    /// The timeout which caused the error, if the error (or its innermost cause) is a timeout.
+    pub fn timeout (&self) -> Option<Duration> {
+        match *self {
+            Error::TimedOut(timeout) => Some (timeout),
+            Error::Chained(_, ref cause) => cause.downcast_ref::<Error>()?.timeout(),
+            _ => None
+        }
+    }
+}
+
+chained_error!(Error);
+
+// Built-in renewers. They also need to be registered in `Registry::builtin()`.
+#[cfg(feature = "renewer-dlink")] mod dlink;
+#[cfg(feature = "renewer-dlink-hnap")] mod dlink_hnap;
+#[cfg(feature = "renewer-netgear")] mod netgear;
+#[cfg(feature = "renewer-tplink")] mod tplink;
+#[cfg(feature = "renewer-zyxel")] mod zyxel;
+#[cfg(feature = "renewer-fr-box")] mod fr_box;
+#[cfg(feature = "renewer-speedport")] mod speedport;
+#[cfg(feature = "renewer-snmp")] mod snmp;
+#[cfg(feature = "renewer-plugin")] mod plugin;
+#[cfg(feature = "renewer-fritzbox-local")] mod fritzbox_local;
+#[cfg(feature = "renewer-pppd-local")] mod pppd_local;
+#[cfg(feature = "renewer-dhcp-local")] mod dhcp_local;
+#[cfg(all(windows, feature = "renewer-windows-local"))] mod windows_local;
+#[cfg(feature = "renewer-reboot")] mod reboot;
+#[cfg(feature = "renewer-fritzbox")] mod fritzbox;
+#[cfg(feature = "renewer-tr064")] mod tr064;
+#[cfg(feature = "renewer-telnet")] mod telnet;
+#[cfg(feature = "renewer-exec")] mod exec;
+#[cfg(feature = "renewer-generic-http")] mod generic_http;
+#[cfg(any(
+    feature = "renewer-dlink", feature = "renewer-dlink-hnap", feature = "renewer-fritzbox",
+    feature = "renewer-netgear", feature = "renewer-reboot", feature = "renewer-tr064"
+))]
+mod igd;
+// Like notification toasts, the `windows-local` renewer can't be compiled on other platforms.
+#[cfg(all(not(windows), feature = "renewer-windows-local"))]
+pub use unsupported_platform;
+mod chain;
+mod chaos;
+mod dummy;
+mod retry;
+mod timeout;
+
+pub trait Renewer {
+    fn from_config(renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized;
+    fn init(&mut self) -> Result<()> { Ok(()) }
+    /// Renews the IP address, telling what it knows about the renewal (see `RenewOutcome`): an
+    /// empty outcome if nothing.
+    fn renew_ip(&mut self) -> Result<RenewOutcome>;
+
+    /// Asks the router about its link to the Internet (see `LinkInfo`), if the renewer supports
+    /// it: returns `None` otherwise. Shown by `client status`, and logged when a renewal fails.
+    fn link_info(&mut self) -> Result<Option<LinkInfo>> { Ok(None) }
+
+    /// Refreshes the session of the renewer with the router (logging in again if it expired), if
+    /// the renewer has one, so that renewals don't have to log in first. Called every
+    /// `server.keepalive_interval`, see `Server::keepalive`.
+    fn keepalive(&mut self) -> Result<()> { Ok(()) }
+
+    /// Checks whether the next renewal can succeed, as far as the renewer can tell: whether the
+    /// router is reachable, accepts the credentials and so on (see `Health`). Called every
+    /// `server.health_check_interval`, and reported by `client health`.
+    fn health_check(&mut self) -> Result<Health> { Ok(Health::default()) }
+
+    /// Returns the schema of the configuration of the renewer, see `config::schema`.
+    #[cfg(feature = "config-schema")]
+    fn config_schema (_gen: &mut SchemaGenerator) -> Schema
+        where Self: Sized
+    {
+        Schema::Bool (true)
+    }
+}
+
+/// Builds a renewer from its configuration, see `Registry::register`.
+pub type Constructor = fn(&config::RenewerConfig) -> Result<Box<dyn Renewer>>;
+
+// Builds a renewer, along with the configured instances (which renewers such as `chain` can wrap).
+type Build = fn(&config::RenewerConfig, &[config::RenewerConfig]) -> Result<Box<dyn Renewer>>;
+
+// How the renewers of a type are built, along with the schema of their configuration.
+#[derive(Clone, Copy)]
+struct RenewerType {
+    build: Builder,
+    #[cfg(feature = "config-schema")]
+    config_schema: fn(&mut SchemaGenerator) -> Schema
+}
+
+#[derive(Clone, Copy)]
+enum Builder {
+    Constructor(Constructor),
+    // Renewers wrapping other instances, such as `chain`.
+    WithInstances(Build)
+}
+
+impl RenewerType {
+    fn of<R: Renewer + 'static>() -> RenewerType {
+        RenewerType {
+            build: Builder::Constructor (construct::<R>),
+            #[cfg(feature = "config-schema")]
+            config_schema: R::config_schema
+        }
+    }
+
+    // Replaces `Renewer::from_config` with `build`.
+    fn with_build (mut self, build: Build) -> RenewerType {
+        self.build = Builder::WithInstances (build);
+        self
+    }
+
+    fn build (&self, renewer: &config::RenewerConfig, renewers: &[config::RenewerConfig])
+        -> Result<Box<dyn Renewer>>
+    {
+        match self.build {
+            Builder::Constructor(constructor) => constructor (renewer),
+            Builder::WithInstances(build) => build (renewer, renewers)
+        }
+    }
+}
+
+fn construct<R: Renewer + 'static> (renewer: &config::RenewerConfig) -> Result<Box<dyn Renewer>> {
+    Ok(Box::new (R::from_config (renewer)?))
+}
+
+/// The renewers which can be chosen by name in the configuration (as the renewer, or as the
+/// `type` of a renewer), each configured in `[server.renewer.<name>]`.
+///
+/// `get_renewer` looks renewers up in the global registry (see `Registry::global`), which starts
+/// with the built-in renewers: programs using `oxixenon-core` can add their own to it, before the
+/// configuration is used.
+#[derive(Clone, Default)]
+pub struct Registry {
+    renewers: BTreeMap<String, RenewerType>
+}
+
+// The registry used by `get_renewer`, created on first use.
+static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+
+impl Registry {
+    /// An empty registry.
+    pub fn new() -> Self {
+        Registry::default()
+    }
+
+    /// A registry with the built-in renewers (those enabled at compile time).
+    pub fn builtin() -> Self {
+        let mut registry = Registry::new();
+        registry.register_type::<dummy::Renewer> ("dummy");
+        registry.register_type::<chaos::Renewer> ("chaos");
+        registry.insert ("chain", RenewerType::of::<chain::Renewer>().with_build (
+            |renewer, renewers| Ok(Box::new (chain::Renewer::build (renewer, renewers)?))
+        ));
+        #[cfg(feature = "renewer-dlink")]
+        registry.register_type::<dlink::Renewer> ("dlink");
+        #[cfg(feature = "renewer-dlink-hnap")]
+        registry.register_type::<dlink_hnap::Renewer> ("dlink-hnap");
+        #[cfg(feature = "renewer-netgear")]
+        registry.register_type::<netgear::Renewer> ("netgear");
+        #[cfg(feature = "renewer-tplink")]
+        registry.register_type::<tplink::Renewer> ("tplink");
+        #[cfg(feature = "renewer-zyxel")]
+        registry.register_type::<zyxel::Renewer> ("zyxel");
+        #[cfg(feature = "renewer-fr-box")]
+        registry.register_type::<fr_box::Renewer> ("fr-box");
+        #[cfg(feature = "renewer-speedport")]
+        registry.register_type::<speedport::Renewer> ("speedport");
+        #[cfg(feature = "renewer-snmp")]
+        registry.register_type::<snmp::Renewer> ("snmp");
+        #[cfg(feature = "renewer-plugin")]
+        registry.register_type::<plugin::Renewer> ("plugin");
+        #[cfg(feature = "renewer-fritzbox-local")]
+        registry.register_type::<fritzbox_local::Renewer> ("fritzbox-local");
+        #[cfg(feature = "renewer-pppd-local")]
+        registry.register_type::<pppd_local::Renewer> ("pppd-local");
+        #[cfg(feature = "renewer-dhcp-local")]
+        registry.register_type::<dhcp_local::Renewer> ("dhcp-local");
+        #[cfg(all(windows, feature = "renewer-windows-local"))]
+        registry.register_type::<windows_local::Renewer> ("windows-local");
+        #[cfg(feature = "renewer-reboot")]
+        registry.register_type::<reboot::Renewer> ("reboot");
+        #[cfg(feature = "renewer-fritzbox")]
+        registry.register_type::<fritzbox::Renewer> ("fritzbox");
+        #[cfg(feature = "renewer-tr064")]
+        registry.register_type::<tr064::Renewer> ("tr064");
+        #[cfg(feature = "renewer-telnet")]
+        registry.register_type::<telnet::Renewer> ("telnet");
+        #[cfg(feature = "renewer-exec")]
+        registry.register_type::<exec::Renewer> ("exec");
+        #[cfg(feature = "renewer-generic-http")]
+        registry.register_type::<generic_http::Renewer> ("generic-http");
+        registry
+    }
+
+    /// The registry used by `get_renewer` and by the schema of the configuration, which starts
+    /// with the built-in renewers.
+    pub fn global() -> &'static RwLock<Registry> {
+        REGISTRY.get_or_init (|| RwLock::new (Registry::builtin()))
+    }
+
+    /// Adds the renewer `name`, built by `constructor`, replacing any renewer with the same name.
+    /// Its configuration isn't described by the schema: see `register_type` for that.
+    pub fn register (&mut self, name: &str, constructor: Constructor) {
+        self.insert (name, RenewerType {
+            build: Builder::Constructor (constructor),
+            #[cfg(feature = "config-schema")]
+            config_schema: |_| Schema::Bool (true)
+        });
+    }
+
+    /// Adds the renewer `name`, built by `R::from_config` and whose configuration is described by
+    /// `R::config_schema`, replacing any renewer with the same name.
+    pub fn register_type<R: Renewer + 'static> (&mut self, name: &str) {
+        self.insert (name, RenewerType::of::<R>());
+    }
+
+    /// Removes the renewer `name`, returning whether there was one.
+    pub fn unregister (&mut self, name: &str) -> bool {
+        self.renewers.remove (name).is_some()
+    }
+
+    pub fn contains (&self, name: &str) -> bool {
+        self.renewers.contains_key (name)
+    }
+
+    /// The names of the renewers, in alphabetical order.
+    pub fn names (&self) -> impl Iterator<Item = &str> {
+        self.renewers.keys().map (String::as_str)
+    }
+
+    fn insert (&mut self, name: &str, renewer: RenewerType) {
+        self.renewers.insert (name.to_string(), renewer);
+    }
+
+    fn get (&self, name: &str) -> Option<RenewerType> {
+        self.renewers.get (name).copied()
+    }
+}
+
+/// Adds a custom renewer to the global registry (see `Registry::register_type`), which can be
+/// used by choosing `name` as the renewer (or as the `type` of a renewer) and configured in
+/// `[server.renewer.<name>]`. Replaces any renewer with the same name, including the built-in
+/// ones.
+pub fn register_renewer<R: Renewer + 'static> (name: &str) {
+    Registry::global().write().unwrap_or_else (|e| e.into_inner()).register_type::<R> (name);
+}
+
+// Looks up a renewer by name in the global registry.
+fn find_renewer (name: &str) -> Option<RenewerType> {
+    Registry::global().read().unwrap_or_else (|e| e.into_inner()).get (name)
+}
+
+/// Returns the schema of the `server.renewer` table, see `config::schema`.
+#[cfg(feature = "config-schema")]
+pub fn config_schema (gen: &mut SchemaGenerator) -> Schema {
+    let registry = Registry::global().read().unwrap_or_else (|e| e.into_inner()).clone();
+    // Every renewer also accepts the options of retries and timeouts.
+    let sections = registry.renewers.iter()
+        .map (|(name, renewer)| {
+            let section = (renewer.config_schema) (gen);
+            let section = crate::config::schema::with_options::<retry::Config> (gen, section);
+            (name.as_str(), crate::config::schema::with_options::<timeout::Config> (gen, section))
+        })
+        .collect();
+    crate::config::schema::instances (sections)
+}
+
+/// Builds the renewer described by `renewer`. `renewers` are the other instances configured in
+/// `server.renewer` (see `ServerConfig::renewers`), which renewers such as `chain` can wrap.
+///
+/// Every renewer also accepts `max_attempts` and `backoff_ms` in its section, which make failed
+/// renewals be attempted again (see `retry::Config`), and `operation_timeout`, which limits how
+/// long each of its operations can take (see `timeout::Config`).
+pub fn get_renewer (renewer: &config::RenewerConfig, renewers: &[config::RenewerConfig])
+    -> Result<Box<dyn Renewer>>
+{
+    let (retry, section) = renewer.config.split (retry::OPTIONS);
+    let (timeout, section) = section.split (timeout::OPTIONS);
+    let renewer = &config::RenewerConfig { config: section, ..renewer.clone() };
+    match find_renewer (&renewer.name) {
+        // Each attempt of a renewal is limited by the timeout.
+        Some(renewer_type) => timeout::wrap (renewer_type, renewer, renewers, &timeout)
+            .and_then (|built| retry::wrap (built, &retry))
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            )),
+        None if renewer.name != renewer.instance => bail!(
+            "invalid type '{}' of renewer '{}' - if applicable, ensure this renewer is enabled",
+            renewer.name, renewer.instance
+        ),
+        None => bail!(
+            "invalid renewer name '{}' - if applicable, ensure this renewer is enabled",
+            renewer.name
+        )
+    }
+}
+
+// Decodes `hex`, a string of hexadecimal digits, as some routers send binary data.
+#[cfg(any(feature = "renewer-fritzbox", feature = "renewer-speedport"))]
+fn hex_decode (hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of (2) || !hex.is_ascii() {
+        return None;
+    }
+    (0..hex.len()).step_by (2)
+        .map (|i| u8::from_str_radix (&hex[i..i + 2], 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Section, SourceMap};
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::sync::Arc;
+
+    struct Failing;
+
+    impl Renewer for Failing {
+        fn from_config (_renewer: &config::RenewerConfig) -> Result<Self> {
+            Ok(Failing)
+        }
+
+        fn renew_ip (&mut self) -> Result<RenewOutcome> {
+            bail!("the router is on fire")
+        }
+    }
+
+    #[test]
+    fn registered_renewers_are_used() {
+        register_renewer::<Failing> ("failing");
+        let renewer = |name: &str, instance: &str| config::RenewerConfig {
+            name: name.into(),
+            instance: instance.into(),
+            config: Section::empty (format!("server.renewer.{}", instance))
+        };
+        let error = get_renewer (&renewer ("failing", "upstairs"), &[]).unwrap()
+            .renew_ip()
+            .unwrap_err();
+        assert_eq!(error.to_string(), "the router is on fire");
+        assert!(get_renewer (&renewer ("dummy", "dummy"), &[]).is_ok());
+        assert!(get_renewer (&renewer ("nonexistent", "nonexistent"), &[]).is_err());
+
+        // Constructors can be registered as well.
+        Registry::global().write().unwrap().register ("acme", |renewer| {
+            ensure!(renewer.instance == "downstairs", "wrong floor");
+            Ok(Box::new (Failing))
+        });
+        assert!(get_renewer (&renewer ("acme", "downstairs"), &[]).unwrap().renew_ip().is_err());
+        let error = get_renewer (&renewer ("acme", "upstairs"), &[]).err().unwrap();
+        assert_eq!(error.to_string(), "the renewer 'upstairs' is not configured correctly");
+    }
+
+    #[test]
+    fn builtin_renewers_are_registered() {
+        let mut registry = Registry::builtin();
+        assert!(["chain", "chaos", "dummy"].iter().all (|name| registry.contains (name)));
+        assert!(registry.names().zip (registry.names().skip (1)).all (|(a, b)| a < b));
+        // Registering replaces the built-in renewers.
+        registry.register ("dummy", |_| Ok(Box::new (Failing)));
+        let dummy = config::RenewerConfig {
+            name: "dummy".into(),
+            instance: "dummy".into(),
+            config: Section::empty ("server.renewer.dummy")
+        };
+        let mut built = registry.get ("dummy").unwrap().build (&dummy, &[]).unwrap();
+        assert!(built.renew_ip().is_err());
+        assert!(registry.unregister ("dummy"));
+        assert!(!registry.contains ("dummy") && !registry.unregister ("dummy"));
+        assert!(Registry::new().names().next().is_none());
+    }
+
+    // Fails the renewals until the third one.
+    static FLAKY_RENEWALS: AtomicU32 = AtomicU32::new (0);
+
+    struct Flaky;
+
+    impl Renewer for Flaky {
+        fn from_config (_renewer: &config::RenewerConfig) -> Result<Self> {
+            Ok(Flaky)
+        }
+
+        fn renew_ip (&mut self) -> Result<RenewOutcome> {
+            ensure!(FLAKY_RENEWALS.fetch_add (1, Ordering::SeqCst) >= 2, "the router is busy");
+            Ok(RenewOutcome::default())
+        }
+    }
+
+    #[test]
+    fn failed_renewals_are_retried() {
+        register_renewer::<Flaky> ("flaky");
+        let renewer = |max_attempts: u32| {
+            let source = format!("max_attempts = {}\nbackoff_ms = 1", max_attempts);
+            let map = Arc::new (SourceMap::new (&source));
+            config::RenewerConfig {
+                name: "flaky".into(),
+                instance: "flaky".into(),
+                config: Section::new ("server.renewer.flaky".into(), source.parse().ok(), map)
+                    .strict (true)
+            }
+        };
+        let error = get_renewer (&renewer (2), &[]).unwrap().renew_ip().unwrap_err();
+        assert_eq!(error.to_string(), "failed 2 times");
+        FLAKY_RENEWALS.store (0, Ordering::SeqCst);
+        get_renewer (&renewer (3), &[]).unwrap().renew_ip().unwrap();
+        assert_eq!(FLAKY_RENEWALS.load (Ordering::SeqCst), 3);
+        assert!(get_renewer (&renewer (0), &[]).is_err());
+    }
+}
@@ -0,0 +1,291 @@
+//! Renews the IP address of Netgear routers (such as the Nighthawk and DGN series) through the
+//! SOAP interface used by the Netgear genie app, at `/soap/server_sa/`.
+
+use super::{igd, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+// Configuration of the renewer, in `[server.renewer.netgear]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the router, optionally followed by the port of the SOAP
+    /// interface (5000 unless specified, some firmwares use 80 instead, e.g. "192.168.1.1:80").
+    ip: String,
+    /// "admin" unless specified.
+    username: Option<String>,
+    password: Secret<String>,
+    /// The action which renews the address, as "Service:Method", e.g. "DeviceConfig:Reboot"
+    /// (the default) to reboot the router.
+    action: Option<String>,
+    /// The arguments of the action, e.g. `{ NewConnectionType = "PPPoE" }`.
+    #[serde(default)]
+    arguments: BTreeMap<String, String>,
+    /// The URL of the UPnP description of the router, through which the link information is read.
+    upnp_description: Option<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const PORT: u16 = 5000;
+const DEFAULT_ACTION: &str = "DeviceConfig:Reboot";
+// The routers accept any session ID in the header of the envelope, as long as it's there: this is
+// the one of the genie app.
+const SESSION_ID: &str = "A7D88AE69687E58D9A00";
+// The response code meaning that the session is missing or expired.
+const UNAUTHORIZED: &str = "401";
+
+pub struct Renewer {
+    // The host and the port of the SOAP interface, as they appear in URLs.
+    authority: String,
+    username: String,
+    password: Secret<String>,
+    service: String,
+    method: String,
+    arguments: BTreeMap<String, String>,
+    upnp_description: Option<String>,
+    // The cookies set by the login, if any.
+    cookies: Option<Secret<String>>,
+    logged_in: bool,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    // Calls `method` of `service`, returning the response code of the router ("000" on success).
+    fn call (&mut self, service: &str, method: &str, arguments: &[(&str, &str)])
+        -> Result<String>
+    {
+        let url = format!("http://{}/soap/server_sa/", self.authority);
+        let namespace = format!("urn:NETGEAR-ROUTER:service:{}:1", service);
+        let arguments: String = arguments.iter()
+            .map (|(name, value)| format!("<{0}>{1}</{0}>", name, escape (value)))
+            .collect();
+        let envelope = format!(
+            "<?xml version=\"1.0\" encoding=\"utf-8\" ?>\
+            <SOAP-ENV:Envelope xmlns:SOAP-ENV=\"http://schemas.xmlsoap.org/soap/envelope/\">\
+            <SOAP-ENV:Header><SessionID>{session}</SessionID></SOAP-ENV:Header>\
+            <SOAP-ENV:Body><M1:{method} xmlns:M1=\"{namespace}\">{arguments}</M1:{method}>\
+            </SOAP-ENV:Body></SOAP-ENV:Envelope>",
+            session = SESSION_ID, method = method, namespace = namespace, arguments = arguments
+        );
+        let mut request = Request::post (url.as_str())
+            .header (header::CONTENT_TYPE, "text/xml; charset=utf-8")
+            .header ("SOAPAction", format!("{}#{}", namespace, method));
+        if let Some(ref cookies) = self.cookies {
+            request = request.header (header::COOKIE, cookies.expose().as_str());
+        }
+        let request = request.body (Some (envelope))
+            .chain_err (|| "failed to build HTTP request object")?;
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+        // Keep the session cookie set by the login (without the attributes).
+        let cookies = res.headers().get_all (header::SET_COOKIE).iter()
+            .filter_map (|cookie| cookie.to_str().ok())
+            .filter_map (|cookie| cookie.split (';').next())
+            .map (str::trim)
+            .collect::<Vec<_>>();
+        if !cookies.is_empty() {
+            self.cookies = Some (cookies.join ("; ").into());
+        }
+        if res.status() == http::StatusCode::UNAUTHORIZED {
+            return Ok(UNAUTHORIZED.into());
+        }
+        ensure!(res.status().is_success(), "{} failed with status {}", method, res.status());
+        igd::tag (res.body(), "ResponseCode")
+            .map (String::from)
+            .chain_err (|| format!("{} was answered without a response code", method))
+    }
+
+    // Logs in with `SOAPLogin`, falling back to `Authenticate` on older firmwares which don't
+    // have it.
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::netgear", "trying to login using specified credentials");
+        self.cookies = None;
+        self.logged_in = false;
+        let (username, password) = (self.username.clone(), self.password.clone());
+        match self.call ("DeviceConfig", "SOAPLogin", &[
+            ("Username", &username), ("Password", password.expose())
+        ]) {
+            Ok(ref code) if succeeded (code) => {
+                info!(target: "renewer::netgear", "login OK");
+                self.logged_in = true;
+                return Ok(());
+            },
+            Ok(code) => debug!(target: "renewer::netgear", "SOAPLogin failed with code {}", code),
+            Err(error) => debug!(target: "renewer::netgear", "SOAPLogin failed: {}", error)
+        }
+        let code = self.call ("ParentalControl", "Authenticate", &[
+            ("NewUsername", &username), ("NewPassword", password.expose())
+        ])?;
+        ensure!(succeeded (&code), "login failed with code {}, check your credentials!", code);
+        info!(target: "renewer::netgear", "login OK");
+        self.logged_in = true;
+        Ok(())
+    }
+
+    // Calls the action between `ConfigurationStarted` and `ConfigurationFinished`, as changes
+    // must be. Returns `false` if the session expired.
+    fn configure (&mut self) -> Result<bool> {
+        let code = self.call ("DeviceConfig", "ConfigurationStarted", &[
+            ("NewSessionID", SESSION_ID)
+        ])?;
+        if code == UNAUTHORIZED {
+            return Ok(false);
+        }
+        ensure!(succeeded (&code), "ConfigurationStarted failed with code {}", code);
+        let (service, method) = (self.service.clone(), self.method.clone());
+        let arguments = self.arguments.clone();
+        let arguments: Vec<_> = arguments.iter()
+            .map (|(name, value)| (name.as_str(), value.as_str()))
+            .collect();
+        let code = self.call (&service, &method, &arguments)?;
+        ensure!(succeeded (&code), "{} failed with code {}", method, code);
+        // A rebooting router may not answer anymore, which doesn't make the renewal fail.
+        match self.call ("DeviceConfig", "ConfigurationFinished", &[
+            ("NewStatus", "ChangesApplied")
+        ]) {
+            Ok(ref code) if succeeded (code) => (),
+            Ok(code) => debug!(
+                target: "renewer::netgear", "ConfigurationFinished failed with code {}", code
+            ),
+            Err(error) => debug!(
+                target: "renewer::netgear", "ConfigurationFinished failed: {}", error
+            )
+        }
+        Ok(true)
+    }
+}
+
+// Whether a response code means success, i.e. "000" (or "0000" on some firmwares).
+fn succeeded (code: &str) -> bool {
+    code.parse::<u32>() == Ok(0)
+}
+
+fn escape (value: &str) -> String {
+    value.replace ('&', "&amp;").replace ('<', "&lt;").replace ('>', "&gt;")
+}
+
+// Splits "host:port" into the authority of the URLs, using the default port if it's missing.
+fn authority (ip: &str) -> String {
+    match ip.rsplit_once (':') {
+        Some((host, port)) if !host.contains (':') && port.parse::<u16>().is_ok() =>
+            format!("{}:{}", host, port),
+        _ => format!("{}:{}", http_client::uri_host (ip), PORT)
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let action = config.action.unwrap_or_else (|| DEFAULT_ACTION.into());
+        let (service, method) = match action.split_once (':') {
+            Some((service, method)) if !service.is_empty() && !method.is_empty() =>
+                (service.to_string(), method.to_string()),
+            _ => bail!(
+                "option '{}.action' must be formatted as \"Service:Method\"",
+                renewer.config.path()
+            )
+        };
+        Ok(Renewer {
+            authority: authority (&config.ip),
+            username: config.username.unwrap_or_else (|| "admin".into()),
+            password: config.password,
+            service,
+            method,
+            arguments: config.arguments,
+            upnp_description: config.upnp_description,
+            cookies: None,
+            logged_in: false,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        if !self.logged_in {
+            self.login()?;
+        }
+        if !self.configure()? {
+            debug!(target: "renewer::netgear", "session expired, logging in again");
+            self.login()?;
+            ensure!(self.configure()?, "the router rejected the new session");
+        }
+        info!(target: "renewer::netgear", "successfully called {}:{}", self.service, self.method);
+        Ok(RenewOutcome::default())
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        match self.upnp_description {
+            Some(ref description) => igd::link_info (&mut *self.transport, description).map (Some),
+            None => Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn response (code: &str) -> String {
+        format!("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n\
+            <soap-env:Envelope><soap-env:Body><ResponseCode>{}</ResponseCode></soap-env:Body>\
+            </soap-env:Envelope>\r\n", code)
+    }
+
+    #[test]
+    fn older_firmwares_authenticate() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+            .push_response (response ("000"))
+            .push_response (response ("401"))
+            .push_response (response ("0000"))
+            .push_response (response ("000"))
+            .push_response (response ("000"))
+            .push_response (response ("000"));
+        let mut renewer = Renewer {
+            authority: authority ("192.168.1.1"),
+            username: "admin".into(),
+            password: "hunter<2>".into(),
+            service: "DeviceConfig".into(),
+            method: "Reboot".into(),
+            arguments: BTreeMap::new(),
+            upnp_description: None,
+            cookies: None,
+            logged_in: false,
+            transport: Box::new (transport.clone())
+        };
+        renewer.init().unwrap();
+        // The renewal finds the session expired, and logs in again.
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("POST /soap/server_sa/ HTTP/1.1\r\n"));
+        assert!(requests[0].contains ("host: 192.168.1.1:5000\r\n"));
+        assert!(requests[0].contains (
+            "soapaction: urn:NETGEAR-ROUTER:service:DeviceConfig:1#SOAPLogin\r\n"
+        ));
+        assert!(requests[1].contains ("<NewPassword>hunter&lt;2&gt;</NewPassword>"));
+        assert!(requests[2].contains ("#ConfigurationStarted\r\n"));
+        assert!(requests[3].contains ("#SOAPLogin\r\n"));
+        assert!(requests[5].contains ("#Reboot\r\n"));
+        assert!(requests[6].contains ("<NewStatus>ChangesApplied</NewStatus>"));
+    }
+}
@@ -0,0 +1,283 @@
+//! A renewer implemented by a shared library (e.g. `libacme_renewer.so`) which is loaded at
+//! runtime, so that renewers for specific routers can be shipped out of tree, in any language
+//! which can expose a C ABI. The ABI is described in `EXTENDING_XENON.md`.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config;
+use serde::Deserialize;
+use std::ffi::{c_char, c_int, c_void, CString};
+use std::mem;
+
+// Configuration of the renewer, in `[server.renewer.plugin]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// Path of the shared library, e.g. "/usr/lib/xenon/libacme_renewer.so".
+    path: String,
+    /// The configuration of the plugin, which is passed to it as JSON.
+    #[cfg_attr(
+        feature = "config-schema",
+        schemars(with = "Option<std::collections::BTreeMap<String, serde_json::Value>>")
+    )]
+    config: Option<toml::value::Table>
+}
+
+// The version of the ABI, which plugins return from `xenon_renewer_abi`.
+const ABI_VERSION: u32 = 1;
+// The size of the buffer where plugins write why they failed.
+const ERROR_SIZE: usize = 1024;
+
+type Abi = unsafe extern "C" fn() -> u32;
+type New = unsafe extern "C" fn(*const c_char, *mut c_char, usize) -> *mut c_void;
+type Call = unsafe extern "C" fn(*mut c_void, *mut c_char, usize) -> c_int;
+type Free = unsafe extern "C" fn(*mut c_void);
+
+// The functions exported by a plugin.
+struct Functions {
+    new: New,
+    init: Option<Call>,
+    renew: Call,
+    free: Free
+}
+
+pub struct Renewer {
+    functions: Functions,
+    // What `xenon_renewer_new` returned, freed when the renewer is dropped.
+    handle: *mut c_void,
+    path: String,
+    // Unloaded once the renewer is freed (`None` in tests, where the functions are built in).
+    _library: Option<Library>
+}
+
+impl Renewer {
+    fn new (functions: Functions, config: &str, path: String, library: Option<Library>)
+        -> Result<Self>
+    {
+        let config = CString::new (config).chain_err (|| "the configuration contains a NUL")?;
+        let mut error = [0; ERROR_SIZE];
+        // SAFETY: the ABI requires `xenon_renewer_new` to only read the configuration, and to
+        // write at most `ERROR_SIZE` bytes of the error.
+        let handle = unsafe { (functions.new) (config.as_ptr(), error.as_mut_ptr(), ERROR_SIZE) };
+        ensure!(!handle.is_null(), "{}", message (&error, "xenon_renewer_new", None));
+        Ok(Renewer { functions, handle, path, _library: library })
+    }
+
+    // Calls `function` of the plugin, failing with the error it wrote if it doesn't return 0.
+    fn call (&mut self, function: Call, name: &str) -> Result<()> {
+        let mut error = [0; ERROR_SIZE];
+        // SAFETY: `handle` was returned by `xenon_renewer_new`, and the buffer of the error is
+        // as large as the plugin is told.
+        let status = unsafe { function (self.handle, error.as_mut_ptr(), ERROR_SIZE) };
+        ensure!(status == 0, "{}", message (&error, name, Some (status)));
+        Ok(())
+    }
+}
+
+// Frees the renewer of the plugin, before the library is unloaded.
+impl Drop for Renewer {
+    fn drop (&mut self) {
+        // SAFETY: `handle` was returned by `xenon_renewer_new`, and isn't used anymore.
+        unsafe { (self.functions.free) (self.handle) }
+    }
+}
+
+// The error written by a plugin, or what failed if it didn't write any.
+fn message (error: &[c_char], function: &str, status: Option<c_int>) -> String {
+    let bytes = error.iter()
+        .take_while (|byte| **byte != 0)
+        .map (|byte| *byte as u8)
+        .collect::<Vec<_>>();
+    match status {
+        _ if !bytes.is_empty() => String::from_utf8_lossy (&bytes).into_owned(),
+        Some(status) => format!("{} failed with status {}", function, status),
+        None => format!("{} failed", function)
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let library = Library::open (&config.path)?;
+        let required = |name| library.function (name)
+            .chain_err (|| format!("'{}' doesn't export '{}'", config.path, name));
+        // SAFETY: the ABI gives these functions these signatures.
+        let functions = unsafe {
+            let abi = mem::transmute::<*const c_void, Abi> (required ("xenon_renewer_abi")?)();
+            ensure!(
+                abi == ABI_VERSION,
+                "'{}' implements version {} of the ABI of plugins, instead of {}",
+                config.path, abi, ABI_VERSION
+            );
+            Functions {
+                new: mem::transmute::<*const c_void, New> (required ("xenon_renewer_new")?),
+                init: library.function ("xenon_renewer_init")
+                    .map (|init| mem::transmute::<*const c_void, Call> (init)),
+                renew: mem::transmute::<*const c_void, Call> (required ("xenon_renewer_renew")?),
+                free: mem::transmute::<*const c_void, Free> (required ("xenon_renewer_free")?)
+            }
+        };
+        let json = serde_json::to_string (&config.config.unwrap_or_default())
+            .chain_err (|| format!("invalid option '{}.config'", renewer.config.path()))?;
+        Self::new (functions, &json, config.path, Some (library))
+            .chain_err (|| format!("'{}' failed to create the renewer", renewer.instance))
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        match self.functions.init {
+            Some(init) => self.call (init, "xenon_renewer_init"),
+            None => Ok(())
+        }
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        self.call (self.functions.renew, "xenon_renewer_renew")?;
+        info!(target: "renewer::plugin", "'{}' renewed the IP address", self.path);
+        Ok(RenewOutcome::default())
+    }
+}
+
+// A loaded shared library, unloaded when dropped.
+struct Library (*mut c_void);
+
+impl Library {
+    #[cfg(unix)]
+    fn open (path: &str) -> Result<Library> {
+        let c_path = CString::new (path).chain_err (|| "the path contains a NUL")?;
+        // SAFETY: the path is NUL-terminated. Loading runs the initializers of the library,
+        // which is trusted like the rest of the configuration.
+        let handle = unsafe { libc::dlopen (c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if handle.is_null() {
+            bail!("failed to load '{}': {}", path, dl_error());
+        }
+        Ok(Library (handle))
+    }
+
+    #[cfg(windows)]
+    fn open (path: &str) -> Result<Library> {
+        use std::os::windows::ffi::OsStrExt;
+        let wide_path = std::ffi::OsStr::new (path).encode_wide()
+            .chain (std::iter::once (0))
+            .collect::<Vec<_>>();
+        // SAFETY: the path is NUL-terminated.
+        let handle = unsafe { winapi::um::libloaderapi::LoadLibraryW (wide_path.as_ptr()) };
+        if handle.is_null() {
+            bail!("failed to load '{}': {}", path, std::io::Error::last_os_error());
+        }
+        Ok(Library (handle as *mut c_void))
+    }
+
+    // The address of the function `name`, if the library exports it.
+    fn function (&self, name: &str) -> Option<*const c_void> {
+        let name = CString::new (name).ok()?;
+        // SAFETY: the handle is valid until the library is dropped, and the name is
+        // NUL-terminated.
+        #[cfg(unix)]
+        let address = unsafe { libc::dlsym (self.0, name.as_ptr()) as *const c_void };
+        #[cfg(windows)]
+        let address = unsafe {
+            winapi::um::libloaderapi::GetProcAddress (self.0 as _, name.as_ptr()) as *const c_void
+        };
+        Some (address).filter (|address| !address.is_null())
+    }
+}
+
+impl Drop for Library {
+    fn drop (&mut self) {
+        // SAFETY: nothing of the library is used anymore.
+        #[cfg(unix)]
+        unsafe { libc::dlclose (self.0) };
+        #[cfg(windows)]
+        unsafe { winapi::um::libloaderapi::FreeLibrary (self.0 as _) };
+    }
+}
+
+#[cfg(unix)]
+fn dl_error() -> String {
+    // SAFETY: `dlerror` returns NULL or a NUL-terminated string, valid until the next call.
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        return "unknown error".into();
+    }
+    unsafe { std::ffi::CStr::from_ptr (error) }.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static FREED: AtomicBool = AtomicBool::new (false);
+
+    // A plugin whose renewer counts its renewals, failing the second one.
+    unsafe extern "C" fn new (config: *const c_char, error: *mut c_char, size: usize)
+        -> *mut c_void
+    {
+        let config = std::ffi::CStr::from_ptr (config).to_str().unwrap();
+        if config != r#"{"renewals":0}"# {
+            let message = b"bad config\0";
+            ptr::copy_nonoverlapping (message.as_ptr() as *const c_char, error, message.len());
+            assert!(message.len() <= size);
+            return ptr::null_mut();
+        }
+        Box::into_raw (Box::new (0u32)) as *mut c_void
+    }
+
+    unsafe extern "C" fn renew (renewer: *mut c_void, error: *mut c_char, _: usize) -> c_int {
+        let renewals = &mut *(renewer as *mut u32);
+        *renewals += 1;
+        if *renewals == 2 {
+            let message = b"the router is on fire\0";
+            ptr::copy_nonoverlapping (message.as_ptr() as *const c_char, error, message.len());
+            return 1;
+        }
+        0
+    }
+
+    unsafe extern "C" fn fail (_: *mut c_void, _: *mut c_char, _: usize) -> c_int {
+        -1
+    }
+
+    unsafe extern "C" fn free (renewer: *mut c_void) {
+        drop (Box::from_raw (renewer as *mut u32));
+        FREED.store (true, Ordering::SeqCst);
+    }
+
+    fn functions (init: Option<Call>) -> Functions {
+        Functions { new, init, renew, free }
+    }
+
+    #[test]
+    fn plugins_are_called() {
+        let mut renewer = Renewer::new (functions (None), r#"{"renewals":0}"#, "test".into(), None)
+            .unwrap();
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+        assert_eq!(renewer.renew_ip().unwrap_err().to_string(), "the router is on fire");
+        renewer.renew_ip().unwrap();
+        drop (renewer);
+        assert!(FREED.load (Ordering::SeqCst));
+
+        let mut renewer = Renewer::new (functions (Some (fail)), "{}", "test".into(), None);
+        assert_eq!(renewer.as_ref().err().unwrap().to_string(), "bad config");
+        renewer = Renewer::new (functions (Some (fail)), r#"{"renewals":0}"#, "test".into(), None);
+        let error = renewer.unwrap().init().unwrap_err();
+        assert_eq!(error.to_string(), "xenon_renewer_init failed with status -1");
+    }
+
+    #[test]
+    fn missing_libraries_are_reported() {
+        let error = Library::open ("/nonexistent/libacme_renewer.so").err().unwrap();
+        assert!(error.to_string().starts_with ("failed to load '/nonexistent/libacme_renewer.so'"));
+    }
+}
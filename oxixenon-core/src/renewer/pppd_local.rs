@@ -0,0 +1,210 @@
+//! Renews the IP address by restarting the PPP connection of the machine running the server, for
+//! those who terminate PPPoE with pppd on a Linux box rather than on a router.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.pppd-local]`. All options are optional.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// How the connection is restarted, "pon" unless specified.
+    #[serde(default)]
+    method: Method,
+    /// The peer of the connection, as in `/etc/ppp/peers`. Defaults to "provider".
+    peer: Option<String>,
+    /// The interface of the connection. Defaults to "ppp0".
+    interface: Option<String>,
+    /// How long the connection can take to go down and to come back up, e.g. "1m". Defaults to
+    /// 30 seconds.
+    timeout: Option<String>
+}
+
+/// The ways of restarting the connection.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Method {
+    /// Runs `poff` and `pon` with the peer.
+    #[default]
+    Pon,
+    /// Sends SIGHUP to pppd, which hangs up and dials again (it must run with `persist`).
+    Signal
+}
+
+const DEFAULT_PEER: &str = "provider";
+const DEFAULT_INTERFACE: &str = "ppp0";
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs (30);
+// How often the interface is checked while waiting for it.
+const POLL_INTERVAL: Duration = Duration::from_millis (250);
+
+pub struct Renewer {
+    method: Method,
+    peer: String,
+    interface: String,
+    timeout: Duration,
+    // Where the peers, the interfaces and the PID files of pppd are.
+    peers_dir: PathBuf,
+    interfaces_dir: PathBuf,
+    run_dir: PathBuf
+}
+
+impl Renewer {
+    // The index of the interface, which changes each time pppd creates it again, or `None` if it
+    // doesn't exist.
+    fn interface_index (&self) -> Option<u32> {
+        fs::read_to_string (self.interfaces_dir.join (&self.interface).join ("ifindex"))
+            .ok()
+            .and_then (|index| index.trim().parse().ok())
+    }
+
+    // Waits until the index of the interface satisfies `condition`.
+    fn wait_for (&self, what: &str, condition: impl Fn (Option<u32>) -> bool) -> Result<()> {
+        let started = Instant::now();
+        while !condition (self.interface_index()) {
+            ensure!(
+                started.elapsed() < self.timeout,
+                "'{}' didn't {} within {}", self.interface, what,
+                config::format_duration (self.timeout)
+            );
+            thread::sleep (POLL_INTERVAL);
+        }
+        Ok(())
+    }
+
+    fn pid_file (&self) -> PathBuf {
+        self.run_dir.join (format!("{}.pid", self.interface))
+    }
+
+    // The PID of pppd, which it writes in the first line of its PID file.
+    fn pid (&self) -> Result<u32> {
+        let path = self.pid_file();
+        let contents = fs::read_to_string (&path)
+            .chain_err (|| format!("failed to read '{}' (is pppd running?)", path.display()))?;
+        contents.lines().next()
+            .and_then (|pid| pid.trim().parse().ok())
+            .chain_err (|| format!("'{}' doesn't contain a PID", path.display()))
+    }
+}
+
+// Runs `program`, failing unless it exits with status 0.
+fn run (program: &str, args: &[&str]) -> Result<()> {
+    let status = Command::new (program)
+        .args (args)
+        .stdin (Stdio::null())
+        .stdout (Stdio::null())
+        .stderr (Stdio::null())
+        .status()
+        .chain_err (|| format!("failed to run '{}'", program))?;
+    ensure!(status.success(), "'{}' failed with {}", program, status);
+    Ok(())
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let timeout = match config.timeout {
+            Some(timeout) => parse_duration (&timeout)
+                .chain_err (|| format!("invalid option '{}.timeout'", renewer.config.path()))?,
+            None => DEFAULT_TIMEOUT
+        };
+        Ok(Renewer {
+            method: config.method,
+            peer: config.peer.unwrap_or_else (|| DEFAULT_PEER.into()),
+            interface: config.interface.unwrap_or_else (|| DEFAULT_INTERFACE.into()),
+            timeout,
+            peers_dir: "/etc/ppp/peers".into(),
+            interfaces_dir: "/sys/class/net".into(),
+            run_dir: "/var/run".into()
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        match self.method {
+            Method::Pon => {
+                let peer = self.peers_dir.join (&self.peer);
+                ensure!(
+                    peer.is_file(),
+                    "the peer '{}' doesn't exist, it should be in '{}'",
+                    self.peer, self.peers_dir.display()
+                );
+            },
+            Method::Signal => { self.pid()?; }
+        }
+        Ok(())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let before = self.interface_index();
+        match self.method {
+            Method::Pon => {
+                run ("poff", &[&self.peer])?;
+                self.wait_for ("go down", |index| index.is_none())?;
+                run ("pon", &[&self.peer])?;
+            },
+            Method::Signal =>
+                run ("kill", &["-HUP", &self.pid()?.to_string()])
+                    .chain_err (|| "failed to signal pppd")?
+        }
+        // pppd creates the interface again once the connection is up.
+        self.wait_for ("come back up", |index| index.is_some() && index != before)?;
+        info!(target: "renewer::pppd-local", "successfully restarted '{}'", self.interface);
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::process;
+
+    #[test]
+    fn pppd_is_signalled() {
+        let dir = std::env::temp_dir().join (format!("oxixenon-pppd-{}", process::id()));
+        fs::create_dir_all (dir.join ("ppp0")).unwrap();
+        fs::write (dir.join ("ppp0").join ("ifindex"), "7\n").unwrap();
+        // A process standing in for pppd, which SIGHUP terminates.
+        let mut pppd = Command::new ("sleep").arg ("30").spawn().unwrap();
+        fs::write (dir.join ("ppp0.pid"), format!("{}\nppp0\n", pppd.id())).unwrap();
+
+        let mut renewer = Renewer {
+            method: Method::Signal,
+            peer: DEFAULT_PEER.into(),
+            interface: DEFAULT_INTERFACE.into(),
+            timeout: Duration::from_secs (5),
+            peers_dir: dir.clone(),
+            interfaces_dir: dir.clone(),
+            run_dir: dir.clone()
+        };
+        renewer.init().unwrap();
+        let index = dir.join ("ppp0").join ("ifindex");
+        let reconnect = thread::spawn (move || {
+            thread::sleep (Duration::from_millis (300));
+            fs::write (index, "8\n").unwrap();
+        });
+        renewer.renew_ip().unwrap();
+        reconnect.join().unwrap();
+        assert!(!pppd.wait().unwrap().success());
+
+        // The peer must exist for `pon`.
+        renewer.method = Method::Pon;
+        assert!(renewer.init().is_err());
+        fs::remove_dir_all (&dir).unwrap();
+    }
+}
@@ -0,0 +1,251 @@
+//! Renews the IP address by rebooting the router (or the modem), for ISPs which only assign
+//! another address after a full restart. The reboot is triggered by an HTTP request of choice, or
+//! through the UPnP action `DeviceConfig:1#Reboot`, and the renewal waits for the device to go
+//! down and to come back up.
+
+use super::{igd, Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use crate::http_client::{self, Request, Transport};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.reboot]`. Either `url` or `upnp_description`
+// must be set.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The URL which reboots the device, e.g. "http://192.168.100.1/reboot.cgi".
+    url: Option<String>,
+    /// The method of the request to `url`. Defaults to "POST".
+    method: Option<String>,
+    /// Headers of the request to `url`, e.g. `{ Authorization = "Basic ..." }`.
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    /// The body of the request to `url`.
+    body: Option<String>,
+    /// The URL of the UPnP description of the device, whose `DeviceConfig` service reboots it (its
+    /// WAN services also report the state of the link).
+    upnp_description: Option<String>,
+    /// How long the device can take to go down and to come back up, e.g. "10m". Defaults to 5
+    /// minutes, "0s" doesn't wait at all.
+    wait_timeout: Option<String>,
+    /// How often the device is checked while waiting for it. Defaults to 5 seconds.
+    wait_interval: Option<String>
+}
+
+const DEFAULT_METHOD: &str = "POST";
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs (5 * 60);
+const DEFAULT_WAIT_INTERVAL: Duration = Duration::from_secs (5);
+// How long each check of the device waits for the connection.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs (2);
+
+// How the reboot is triggered.
+enum Trigger {
+    Http {
+        url: String,
+        method: http::Method,
+        headers: BTreeMap<String, String>,
+        body: Option<String>
+    },
+    Upnp {
+        description: String
+    }
+}
+
+pub struct Renewer {
+    trigger: Trigger,
+    // The host and the port which are checked while waiting for the device, e.g. "10.0.0.1:80".
+    address: String,
+    wait_timeout: Duration,
+    wait_interval: Duration,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    fn reboot (&mut self) -> Result<()> {
+        let (url, request) = match self.trigger {
+            Trigger::Http { ref url, ref method, ref headers, ref body } => {
+                let mut request = Request::builder().method (method.clone()).uri (url.as_str());
+                for (name, value) in headers {
+                    request = request.header (name.as_str(), value.as_str());
+                }
+                (url.clone(), request.body (body.clone()))
+            },
+            Trigger::Upnp { ref description } => {
+                let services = igd::fetch_services (&mut *self.transport, description)?;
+                let (service, url) = services.into_iter()
+                    .find (|(service, _)| service.contains (":DeviceConfig:"))
+                    .chain_err (|| "the device doesn't expose the DeviceConfig service")?;
+                let request = igd::soap_request (&url, &service, "Reboot")
+                    .body (Some (igd::envelope (&service, "Reboot")));
+                (url, request)
+            }
+        };
+        let request = request.chain_err (|| "failed to build HTTP request object")?;
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+        ensure!(res.status().is_success(), "the reboot failed with status {}", res.status());
+        Ok(())
+    }
+
+    fn reachable (&self) -> bool {
+        self.address.to_socket_addrs()
+            .ok()
+            .and_then (|mut addrs| addrs.next())
+            .is_some_and (|addr| TcpStream::connect_timeout (&addr, CONNECT_TIMEOUT).is_ok())
+    }
+
+    // Waits for the device to stop answering, and then to answer again.
+    fn wait (&self) -> Result<()> {
+        let started = Instant::now();
+        let mut down = false;
+        loop {
+            let reachable = self.reachable();
+            if reachable && down {
+                return Ok(());
+            }
+            down |= !reachable;
+            ensure!(
+                started.elapsed() < self.wait_timeout,
+                "the device didn't {} within {}",
+                if down { "come back up" } else { "go down" },
+                config::format_duration (self.wait_timeout)
+            );
+            thread::sleep (self.wait_interval);
+        }
+    }
+}
+
+// The host and the port of `url`, e.g. "10.0.0.1:80" for "http://10.0.0.1/reboot.cgi".
+fn address (url: &str) -> Option<String> {
+    let uri: http::Uri = url.parse().ok()?;
+    let default_port = if uri.scheme_str() == Some ("https") { 443 } else { 80 };
+    let port = uri.port_u16().unwrap_or (default_port);
+    Some (format!("{}:{}", uri.host()?, port))
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let path = renewer.config.path();
+        let trigger = match (config.url, config.upnp_description) {
+            (Some(url), None) => Trigger::Http {
+                url,
+                method: config.method.as_deref().unwrap_or (DEFAULT_METHOD).parse()
+                    .chain_err (|| format!("invalid option '{}.method'", path))?,
+                headers: config.headers,
+                body: config.body
+            },
+            (None, Some(description)) => Trigger::Upnp { description },
+            _ => bail!("exactly one of '{0}.url' and '{0}.upnp_description' must be set", path)
+        };
+        let url = match trigger {
+            Trigger::Http { ref url, .. } => url,
+            Trigger::Upnp { ref description } => description
+        };
+        let address = address (url).chain_err (|| format!("invalid URL '{}'", url))?;
+        let duration = |value: Option<String>, name, default| match value {
+            Some(value) => parse_duration (&value)
+                .chain_err (|| format!("invalid option '{}.{}'", path, name)),
+            None => Ok(default)
+        };
+        Ok(Renewer {
+            trigger,
+            address,
+            wait_timeout: duration (config.wait_timeout, "wait_timeout", DEFAULT_WAIT_TIMEOUT)?,
+            wait_interval: duration (
+                config.wait_interval, "wait_interval", DEFAULT_WAIT_INTERVAL
+            )?,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    fn init (&mut self) -> Result<()> {
+        ensure!(self.reachable(), "the device isn't reachable at {}", self.address);
+        Ok(())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        self.reboot()?;
+        info!(target: "renewer::reboot", "the device is rebooting");
+        if !self.wait_timeout.is_zero() {
+            self.wait()?;
+            info!(target: "renewer::reboot", "the device is back up");
+        }
+        Ok(RenewOutcome::default())
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        match self.trigger {
+            Trigger::Upnp { ref description } =>
+                igd::link_info (&mut *self.transport, description).map (Some),
+            Trigger::Http { .. } => Ok(None)
+        }
+    }
+
+    fn health_check (&mut self) -> Result<Health> {
+        Ok(match self.reachable() {
+            true => Health { reachable: Some (true), ..Health::default() },
+            false => Health::unreachable (format!("nothing answers at {}", self.address))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+    use std::net::TcpListener;
+
+    #[test]
+    fn the_renewal_waits_for_the_reboot() {
+        let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 200 OK\r\nContent-Type: text/xml\r\n\r\n<root><serviceList>\
+                <service><serviceType>urn:dslforum-org:service:DeviceConfig:1</serviceType>\
+                <controlURL>/upnp/control/deviceconfig</controlURL></service>\
+                </serviceList></root>")
+            .push_response ("HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n");
+        let mut renewer = Renewer {
+            trigger: Trigger::Upnp { description: format!("http://{}/tr64desc.xml", address) },
+            address: address.clone(),
+            wait_timeout: Duration::from_secs (5),
+            wait_interval: Duration::from_millis (50),
+            transport: Box::new (transport.clone())
+        };
+        renewer.init().unwrap();
+        // The device goes down, and comes back up a bit later.
+        drop (listener);
+        let device = thread::spawn (move || {
+            thread::sleep (Duration::from_millis (300));
+            let listener = TcpListener::bind (address).unwrap();
+            thread::sleep (Duration::from_secs (1));
+            drop (listener);
+        });
+        let started = Instant::now();
+        renewer.renew_ip().unwrap();
+        assert!(started.elapsed() >= Duration::from_millis (300));
+        device.join().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[1].starts_with ("POST /upnp/control/deviceconfig HTTP/1.1\r\n"));
+        assert!(requests[1].contains (
+            "soapaction: \"urn:dslforum-org:service:DeviceConfig:1#Reboot\"\r\n"
+        ));
+    }
+}
@@ -0,0 +1,98 @@
+//! Retries of failed renewals, configured in the section of any renewer with `max_attempts` and
+//! `backoff_ms` (see `get_renewer`), so that transient failures of the router don't reach the
+//! clients right away.
+
+use super::{Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config;
+use crate::log_error_with_chain;
+use crate::metrics;
+use serde::Deserialize;
+use std::thread;
+use std::time::Duration;
+
+// The options of every renewer section, which `get_renewer` handles on behalf of the renewer.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// How many times a renewal is attempted before failing. Defaults to 1, i.e. no retries.
+    max_attempts: Option<u32>,
+    /// How long to wait before the first retry, in milliseconds, doubling before each of the
+    /// following ones. Defaults to 1000.
+    backoff_ms: Option<u64>
+}
+
+// The names of the options in `Config`.
+pub(super) const OPTIONS: &[&str] = &["max_attempts", "backoff_ms"];
+
+const DEFAULT_BACKOFF: Duration = Duration::from_secs (1);
+
+/// Wraps a renewer, attempting each renewal up to `max_attempts` times.
+pub struct Renewer {
+    renewer: Box<dyn RenewerTrait>,
+    max_attempts: u32,
+    backoff: Duration
+}
+
+/// Wraps `renewer` as configured by `section` (the retry options split from its section), or
+/// returns it as is if it's only attempted once.
+pub(super) fn wrap (renewer: Box<dyn RenewerTrait>, section: &config::Section)
+    -> Result<Box<dyn RenewerTrait>>
+{
+    let config: Config = section.parse()?;
+    let max_attempts = config.max_attempts.unwrap_or (1);
+    ensure!(max_attempts > 0, "option '{}.max_attempts' can't be 0", section.path());
+    if max_attempts == 1 {
+        return Ok(renewer);
+    }
+    let backoff = config.backoff_ms.map_or (DEFAULT_BACKOFF, Duration::from_millis);
+    Ok(Box::new (Renewer { renewer, max_attempts, backoff }))
+}
+
+impl RenewerTrait for Renewer {
+    // Retries only exist as wrappers of other renewers, see `wrap`.
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        bail!("retries of the renewer '{}' can't be built on their own", renewer.instance)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.renewer.init()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            let error = match self.renewer.renew_ip() {
+                Ok(outcome) => return Ok(outcome),
+                Err(error) => error
+            };
+            if attempt == self.max_attempts {
+                return Err(error).chain_err (|| format!("failed {} times", attempt));
+            }
+            log_error_with_chain!(
+                target: "renewer::retry",
+                log::Level::Warn,
+                error, "attempt {}/{} failed, retrying in {}: {}",
+                attempt, self.max_attempts, config::format_duration (backoff), error
+            );
+            metrics::counter ("renewer.retries", 1);
+            thread::sleep (backoff);
+            backoff = backoff.saturating_mul (2);
+            attempt += 1;
+        }
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        self.renewer.link_info()
+    }
+
+    fn keepalive (&mut self) -> Result<()> {
+        self.renewer.keepalive()
+    }
+
+    fn health_check (&mut self) -> Result<Health> {
+        self.renewer.health_check()
+    }
+}
@@ -0,0 +1,846 @@
+//! Renews the IP address of modems and ONTs which can only be managed through SNMP, by setting the
+//! `ifAdminStatus` of their WAN interface down and then up again. SNMP v2c authenticates with the
+//! community, SNMP v3 with the user-based security model (HMAC-MD5 or HMAC-SHA, optionally with
+//! AES encryption).
+
+use super::{Health, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration, Secret};
+use openssl::hash::{hash, MessageDigest};
+use openssl::pkey::PKey;
+use openssl::sign::Signer;
+use openssl::symm::{self, Cipher};
+use serde::Deserialize;
+use std::io;
+use std::net::{Ipv6Addr, ToSocketAddrs, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.snmp]`. SNMP v3 is used when `username` is
+// set, SNMP v2c otherwise.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the device, optionally followed by the port (161 unless
+    /// specified, e.g. "192.168.100.1:1161").
+    ip: String,
+    /// The `ifIndex` of the WAN interface, as listed by `snmpwalk <ip> ifDescr`.
+    if_index: u32,
+    /// The community with write access, for SNMP v2c. Defaults to "private".
+    community: Option<Secret<String>>,
+    /// The user, for SNMP v3.
+    username: Option<String>,
+    /// The authentication protocol of the user. Defaults to "sha".
+    auth_protocol: Option<AuthProtocol>,
+    /// The authentication password of the user, if it has one.
+    auth_password: Option<Secret<String>>,
+    /// The privacy password of the user, if it has one: the requests are then encrypted with
+    /// AES-128 (DES isn't supported).
+    priv_password: Option<Secret<String>>,
+    /// How long the interface stays down, e.g. "10s". Defaults to 5 seconds.
+    down_time: Option<String>,
+    /// How long each request waits for an answer, e.g. "5s". Defaults to 3 seconds.
+    timeout: Option<String>
+}
+
+/// The authentication protocols of SNMP v3 users.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum AuthProtocol {
+    /// HMAC-MD5-96.
+    Md5,
+    /// HMAC-SHA-96.
+    Sha
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["community", "auth_password", "priv_password"];
+
+const PORT: u16 = 161;
+const DEFAULT_COMMUNITY: &str = "private";
+const DEFAULT_DOWN_TIME: Duration = Duration::from_secs (5);
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs (3);
+// How many times a request is sent before giving up, as datagrams can get lost.
+const ATTEMPTS: usize = 3;
+
+// `ifAdminStatus` (IF-MIB), followed by the ifIndex, and its values.
+const IF_ADMIN_STATUS: &[u32] = &[1, 3, 6, 1, 2, 1, 2, 2, 1, 7];
+const UP: i64 = 1;
+const DOWN: i64 = 2;
+
+// The counters of USM (RFC 3414) with which agents report errors, followed by ".0".
+const USM_STATS: &[u32] = &[1, 3, 6, 1, 6, 3, 15, 1, 1];
+const UNSUPPORTED_SEC_LEVELS: u32 = 1;
+const NOT_IN_TIME_WINDOWS: u32 = 2;
+const UNKNOWN_USER_NAMES: u32 = 3;
+const UNKNOWN_ENGINE_IDS: u32 = 4;
+const WRONG_DIGESTS: u32 = 5;
+const DECRYPTION_ERRORS: u32 = 6;
+
+// BER tags.
+const INTEGER: u8 = 0x02;
+const OCTET_STRING: u8 = 0x04;
+const NULL: u8 = 0x05;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const SEQUENCE: u8 = 0x30;
+const GET_REQUEST: u8 = 0xa0;
+const RESPONSE: u8 = 0xa2;
+const SET_REQUEST: u8 = 0xa3;
+const REPORT: u8 = 0xa8;
+
+// The header of SNMP v3 messages: the flags, the security model of USM and the size of the
+// largest message which can be received.
+const AUTH_FLAG: u8 = 0x01;
+const PRIV_FLAG: u8 = 0x02;
+const REPORTABLE_FLAG: u8 = 0x04;
+const USM_MODEL: i64 = 3;
+const MAX_MESSAGE_SIZE: i64 = 65507;
+// The length of the truncated HMACs authenticating the messages.
+const MAC_LENGTH: usize = 12;
+
+// The names of the error statuses of responses (RFC 3416).
+const ERROR_STATUSES: &[&str] = &[
+    "noError", "tooBig", "noSuchName", "badValue", "readOnly", "genErr", "noAccess", "wrongType",
+    "wrongLength", "wrongEncoding", "wrongValue", "noCreation", "inconsistentValue",
+    "resourceUnavailable", "commitFailed", "undoFailed", "authorizationError", "notWritable",
+    "inconsistentName"
+];
+
+impl AuthProtocol {
+    fn digest (self) -> MessageDigest {
+        match self {
+            AuthProtocol::Md5 => MessageDigest::md5(),
+            AuthProtocol::Sha => MessageDigest::sha1()
+        }
+    }
+
+    // The key of a password (RFC 3414, A.2): the hash of a megabyte of repetitions of it.
+    fn password_key (self, password: &str) -> Result<Vec<u8>> {
+        ensure!(password.len() >= 8, "the password must be at least 8 characters long");
+        let data = password.bytes().cycle().take (1 << 20).collect::<Vec<_>>();
+        Ok(hash (self.digest(), &data).chain_err (|| "failed to hash the password")?.to_vec())
+    }
+
+    // Localizes the key of a password for the engine `engine_id`.
+    fn localize (self, key: &[u8], engine_id: &[u8]) -> Result<Vec<u8>> {
+        let data = [key, engine_id, key].concat();
+        Ok(hash (self.digest(), &data).chain_err (|| "failed to localize the key")?.to_vec())
+    }
+
+    fn mac (self, key: &[u8], message: &[u8]) -> Result<Vec<u8>> {
+        let key = PKey::hmac (key).chain_err (|| "failed to create the HMAC key")?;
+        let mut mac = Signer::new (self.digest(), &key)
+            .and_then (|mut signer| {
+                signer.update (message)?;
+                signer.sign_to_vec()
+            })
+            .chain_err (|| "failed to compute the HMAC of the message")?;
+        mac.truncate (MAC_LENGTH);
+        Ok(mac)
+    }
+}
+
+// The device, and how long it has to answer.
+struct Agent {
+    addr: String,
+    timeout: Duration
+}
+
+impl Agent {
+    // Sends `message` until an answer for which `answers` is true is received.
+    fn exchange (&self, message: &[u8], answers: impl Fn (&[u8]) -> bool) -> Result<Vec<u8>> {
+        let addr = self.addr.to_socket_addrs()
+            .chain_err (|| format!("failed to resolve '{}'", self.addr))?
+            .next()
+            .chain_err (|| format!("'{}' doesn't resolve to any address", self.addr))?;
+        let local = if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" };
+        let socket = UdpSocket::bind (local)
+            .and_then (|socket| socket.connect (addr).map (|_| socket))
+            .chain_err (|| format!("failed to create a socket for {}", self.addr))?;
+        socket.set_read_timeout (Some (self.timeout))
+            .chain_err (|| "failed to set the timeout of the socket")?;
+        let mut buffer = vec![0; 65536];
+        for _ in 0..ATTEMPTS {
+            socket.send (message)
+                .chain_err (|| format!("failed to send a request to {}", self.addr))?;
+            let started = Instant::now();
+            while started.elapsed() < self.timeout {
+                match socket.recv (&mut buffer) {
+                    Ok(length) if answers (&buffer[..length]) =>
+                        return Ok(buffer[..length].to_vec()),
+                    // Late answers to the previous attempts, or garbage.
+                    Ok(_) => continue,
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                        || e.kind() == io::ErrorKind::TimedOut => break,
+                    Err(e) => return Err(e)
+                        .chain_err (|| format!("failed to receive from {}", self.addr))
+                }
+            }
+            debug!(target: "renewer::snmp", "{} didn't answer, trying again", self.addr);
+        }
+        bail!("{} didn't answer after {} attempts", self.addr, ATTEMPTS)
+    }
+}
+
+// The user of SNMP v3, and what's known about the engine of the device.
+struct Usm {
+    username: String,
+    // The keys of the passwords, localized for the engine once it's known.
+    auth: Option<(AuthProtocol, Secret<Vec<u8>>)>,
+    privacy: Option<Secret<Vec<u8>>>,
+    engine: Option<Engine>,
+    message_id: i32,
+    // Makes the IV of each encrypted message unique.
+    salt: u64
+}
+
+struct Engine {
+    id: Vec<u8>,
+    boots: u32,
+    time: u32,
+    // When `time` was received, to tell the current time of the engine.
+    received: Instant,
+    auth_key: Secret<Vec<u8>>,
+    priv_key: Secret<Vec<u8>>
+}
+
+impl Engine {
+    fn clock (&self) -> (u32, u32) {
+        let elapsed = self.received.elapsed().as_secs().min (u32::MAX as u64) as u32;
+        (self.boots, self.time.saturating_add (elapsed))
+    }
+}
+
+// What's read from the answers of SNMP v3 agents.
+struct Answer {
+    engine_id: Vec<u8>,
+    boots: u32,
+    time: u32,
+    pdu: Pdu
+}
+
+impl Usm {
+    // Sends `pdu`, learning the engine of the device first, and synchronizing again with it once
+    // if it rebooted or its clock drifted.
+    fn request (&mut self, agent: &Agent, pdu: &[u8]) -> Result<Pdu> {
+        if self.engine.is_none() {
+            self.discover (agent)?;
+        }
+        for attempt in 0..2 {
+            let answer = self.send (agent, pdu)?;
+            if answer.pdu.tag != REPORT {
+                return Ok(answer.pdu);
+            }
+            match answer.pdu.report_counter() {
+                Some(NOT_IN_TIME_WINDOWS) if attempt == 0 => {
+                    debug!(target: "renewer::snmp", "synchronizing with the clock of the engine");
+                    let engine = self.engine.as_mut().expect ("discovered");
+                    engine.boots = answer.boots;
+                    engine.time = answer.time;
+                    engine.received = Instant::now();
+                },
+                Some(UNKNOWN_ENGINE_IDS) if attempt == 0 => self.discover (agent)?,
+                Some(UNKNOWN_USER_NAMES) =>
+                    bail!("the device doesn't know the user '{}'", self.username),
+                Some(WRONG_DIGESTS) => bail!("the device refused the authentication password"),
+                Some(DECRYPTION_ERRORS) => bail!("the device refused the privacy password"),
+                Some(UNSUPPORTED_SEC_LEVELS) => bail!(
+                    "the user '{}' doesn't support the configured passwords", self.username
+                ),
+                _ => bail!("the device reported {}", format_oid (&answer.pdu.report_oid()))
+            }
+        }
+        bail!("the device kept refusing the clock of its engine")
+    }
+
+    // Learns the identifier, the boots and the time of the engine of the device, with a request
+    // which it must answer with a report.
+    fn discover (&mut self, agent: &Agent) -> Result<()> {
+        self.engine = None;
+        let answer = self.send (agent, &pdu (GET_REQUEST, 0, &[]))
+            .chain_err (|| "failed to discover the engine of the device")?;
+        ensure!(
+            answer.pdu.tag == REPORT && !answer.engine_id.is_empty(),
+            "the device didn't tell the identifier of its engine"
+        );
+        let (auth_key, priv_key) = match self.auth {
+            Some((protocol, ref key)) => (
+                protocol.localize (key.expose(), &answer.engine_id)?,
+                match self.privacy {
+                    Some(ref key) => protocol.localize (key.expose(), &answer.engine_id)?,
+                    None => Vec::new()
+                }
+            ),
+            None => (Vec::new(), Vec::new())
+        };
+        self.engine = Some (Engine {
+            id: answer.engine_id,
+            boots: answer.boots,
+            time: answer.time,
+            received: Instant::now(),
+            auth_key: auth_key.into(),
+            priv_key: priv_key.into()
+        });
+        Ok(())
+    }
+
+    fn send (&mut self, agent: &Agent, pdu: &[u8]) -> Result<Answer> {
+        self.message_id = next_id (self.message_id);
+        let message_id = self.message_id as i64;
+        let message = self.message (pdu)?;
+        let answer = agent.exchange (&message, |answer| {
+            let mut reader = Reader::sequence (answer);
+            reader.integer().ok() == Some (3)
+                && reader.nested (SEQUENCE).and_then (|mut header| header.integer()).ok()
+                    == Some (message_id)
+        })?;
+        self.parse (&answer).chain_err (|| "the device answered with an invalid message")
+    }
+
+    // Wraps `pdu` in a message, authenticated and encrypted as configured once the engine is
+    // known (discovery requests are neither).
+    fn message (&mut self, pdu: &[u8]) -> Result<Vec<u8>> {
+        let engine = self.engine.as_ref();
+        let engine_id = engine.map_or (&[][..], |engine| &engine.id);
+        let (boots, time) = engine.map_or ((0, 0), Engine::clock);
+        let scoped = sequence (SEQUENCE, &[&octets (engine_id), &octets (b""), pdu]);
+        let mut flags = REPORTABLE_FLAG;
+        let (data, salt) = match engine {
+            Some(engine) if self.privacy.is_some() => {
+                flags |= PRIV_FLAG;
+                self.salt = self.salt.wrapping_add (1);
+                let salt = self.salt.to_be_bytes();
+                let iv = [&boots.to_be_bytes()[..], &time.to_be_bytes(), &salt].concat();
+                let key = &engine.priv_key.expose()[..16];
+                let encrypted = symm::encrypt (Cipher::aes_128_cfb128(), key, Some (&iv), &scoped)
+                    .chain_err (|| "failed to encrypt the request")?;
+                (octets (&encrypted), salt.to_vec())
+            },
+            _ => (scoped, Vec::new())
+        };
+        let auth = engine.zip (self.auth.as_ref());
+        if auth.is_some() {
+            flags |= AUTH_FLAG;
+        }
+        let username = if engine.is_some() { self.username.as_bytes() } else { b"" };
+        let mac = if auth.is_some() { vec![0; MAC_LENGTH] } else { Vec::new() };
+        let priv_params = octets (&salt);
+        let security = sequence (SEQUENCE, &[
+            &octets (engine_id), &integer (boots as i64), &integer (time as i64),
+            &octets (username), &octets (&mac), &priv_params
+        ]);
+        let header = sequence (SEQUENCE, &[
+            &integer (self.message_id as i64), &integer (MAX_MESSAGE_SIZE), &octets (&[flags]),
+            &integer (USM_MODEL)
+        ]);
+        let mut message = sequence (SEQUENCE, &[&integer (3), &header, &octets (&security), &data]);
+        if let Some((engine, (protocol, _))) = auth {
+            // The HMAC is computed with its field zeroed, which is right before the privacy
+            // parameters, at the end of the security parameters.
+            let end = message.len() - data.len() - priv_params.len();
+            let mac = protocol.mac (engine.auth_key.expose(), &message)?;
+            message[end - MAC_LENGTH..end].copy_from_slice (&mac);
+        }
+        Ok(message)
+    }
+
+    // Reads an answer, checking its HMAC and decrypting it if needed.
+    fn parse (&self, message: &[u8]) -> Result<Answer> {
+        let mut reader = Reader::sequence (message);
+        ensure!(reader.integer()? == 3, "not an SNMP v3 message");
+        let mut header = reader.nested (SEQUENCE)?;
+        header.integer()?;
+        header.integer()?;
+        let flags = header.read (OCTET_STRING)?.first().copied().unwrap_or (0);
+        let mut security = Reader (reader.read (OCTET_STRING)?).nested (SEQUENCE)?;
+        let engine_id = security.read (OCTET_STRING)?.to_vec();
+        let boots = security.unsigned()?;
+        let time = security.unsigned()?;
+        security.read (OCTET_STRING)?;
+        let mac = security.read (OCTET_STRING)?;
+        let salt = security.read (OCTET_STRING)?;
+        let keys = self.engine.as_ref().zip (self.auth.as_ref());
+        if flags & AUTH_FLAG != 0 {
+            let (engine, (protocol, _)) = keys.chain_err (|| "unexpected authenticated answer")?;
+            ensure!(mac.len() == MAC_LENGTH, "invalid HMAC");
+            // `mac` is a slice of `message`.
+            let start = mac.as_ptr() as usize - message.as_ptr() as usize;
+            let mut zeroed = message.to_vec();
+            zeroed[start..start + MAC_LENGTH].fill (0);
+            ensure!(
+                protocol.mac (engine.auth_key.expose(), &zeroed)? == mac,
+                "the answer isn't authenticated with the key of the user"
+            );
+        }
+        let scoped = if flags & PRIV_FLAG != 0 {
+            let engine = keys.filter (|_| self.privacy.is_some())
+                .chain_err (|| "unexpected encrypted answer")?.0;
+            let iv = [&boots.to_be_bytes()[..], &time.to_be_bytes(), salt].concat();
+            let key = &engine.priv_key.expose()[..16];
+            let data = reader.read (OCTET_STRING)?;
+            symm::decrypt (Cipher::aes_128_cfb128(), key, Some (&iv), data)
+                .chain_err (|| "failed to decrypt the answer")?
+        } else {
+            reader.0.to_vec()
+        };
+        let mut scoped = Reader::sequence (&scoped);
+        scoped.read (OCTET_STRING)?;
+        scoped.read (OCTET_STRING)?;
+        Ok(Answer { engine_id, boots, time, pdu: Pdu::parse (scoped.0)? })
+    }
+}
+
+enum Security {
+    Community(Secret<String>),
+    Usm(Usm)
+}
+
+pub struct Renewer {
+    agent: Agent,
+    if_index: u32,
+    security: Security,
+    down_time: Duration,
+    request_id: i32
+}
+
+impl Renewer {
+    // Sends a request for `varbinds` (pairs of OIDs and values), failing unless it's carried out.
+    fn request (&mut self, tag: u8, varbinds: &[(&[u32], Vec<u8>)]) -> Result<Pdu> {
+        self.request_id = next_id (self.request_id);
+        let request_id = self.request_id as i64;
+        let pdu = pdu (tag, self.request_id, varbinds);
+        let response = match self.security {
+            Security::Community(ref community) => {
+                let message = sequence (SEQUENCE, &[
+                    &integer (1), &octets (community.expose().as_bytes()), &pdu
+                ]);
+                let answer = self.agent.exchange (&message, |answer| {
+                    parse_v2c (answer).is_ok_and (|pdu| pdu.request_id == request_id)
+                }).chain_err (|| "no answer, is the community right?")?;
+                parse_v2c (&answer)?
+            },
+            Security::Usm(ref mut usm) => usm.request (&self.agent, &pdu)?
+        };
+        ensure!(
+            response.tag == RESPONSE && response.request_id == request_id,
+            "the device answered with an unexpected message"
+        );
+        if response.error_status != 0 {
+            let status = usize::try_from (response.error_status).ok()
+                .and_then (|status| ERROR_STATUSES.get (status))
+                .unwrap_or (&"unknown");
+            bail!("the device refused the request with '{}'", status);
+        }
+        Ok(response)
+    }
+
+    fn if_admin_status (&self) -> Vec<u32> {
+        [IF_ADMIN_STATUS, &[self.if_index]].concat()
+    }
+
+    fn set_admin_status (&mut self, status: i64) -> Result<()> {
+        let oid = self.if_admin_status();
+        self.request (SET_REQUEST, &[(&oid, integer (status))]).map (|_| ())
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let path = renewer.config.path();
+        let duration = |value: Option<String>, name, default| match value {
+            Some(value) => parse_duration (&value)
+                .chain_err (|| format!("invalid option '{}.{}'", path, name)),
+            None => Ok(default)
+        };
+        let addr = match config.ip.rsplit_once (':') {
+            Some((host, port)) if !host.contains (':') && port.parse::<u16>().is_ok() =>
+                config.ip.clone(),
+            _ if config.ip.parse::<Ipv6Addr>().is_ok() => format!("[{}]:{}", config.ip, PORT),
+            _ => format!("{}:{}", config.ip, PORT)
+        };
+        let security = match config.username {
+            None => {
+                ensure!(
+                    config.auth_password.is_none() && config.priv_password.is_none(),
+                    "options '{0}.auth_password' and '{0}.priv_password' need '{0}.username'", path
+                );
+                let community = config.community.unwrap_or_else (|| DEFAULT_COMMUNITY.into());
+                Security::Community (community)
+            },
+            Some(username) => {
+                ensure!(
+                    config.community.is_none(),
+                    "options '{0}.community' and '{0}.username' can't be both set", path
+                );
+                ensure!(
+                    config.auth_password.is_some() || config.priv_password.is_none(),
+                    "option '{0}.priv_password' needs '{0}.auth_password'", path
+                );
+                let protocol = config.auth_protocol.unwrap_or (AuthProtocol::Sha);
+                let key = |password: Option<Secret<String>>, name| password
+                    .map (|password| protocol.password_key (password.expose()))
+                    .transpose()
+                    .chain_err (|| format!("invalid option '{}.{}'", path, name));
+                let auth = key (config.auth_password, "auth_password")?;
+                let privacy = key (config.priv_password, "priv_password")?;
+                Security::Usm (Usm {
+                    username,
+                    auth: auth.map (|key| (protocol, key.into())),
+                    privacy: privacy.map (Secret::new),
+                    engine: None,
+                    message_id: random()? as i32,
+                    salt: random()? as u64
+                })
+            }
+        };
+        Ok(Renewer {
+            agent: Agent { addr, timeout: duration (config.timeout, "timeout", DEFAULT_TIMEOUT)? },
+            if_index: config.if_index,
+            security,
+            down_time: duration (config.down_time, "down_time", DEFAULT_DOWN_TIME)?,
+            request_id: random()? as i32
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let index = self.if_index;
+        self.set_admin_status (DOWN)
+            .chain_err (|| format!("failed to set the interface {} down", index))?;
+        info!(target: "renewer::snmp", "set the interface {} down", index);
+        thread::sleep (self.down_time);
+        self.set_admin_status (UP)
+            .chain_err (|| format!("failed to set the interface {} up again, it's down", index))?;
+        info!(target: "renewer::snmp", "set the interface {} up again", index);
+        Ok(RenewOutcome::default())
+    }
+
+    // Reading the status of the interface tells whether the device answers and accepts the
+    // credentials, though not whether they can write.
+    fn health_check (&mut self) -> Result<Health> {
+        let oid = self.if_admin_status();
+        let status = self.request (GET_REQUEST, &[(&oid, tlv (NULL, &[]))])
+            .map (|response| response.varbinds.first().and_then (|(_, value)| *value));
+        let healthy = Health {
+            reachable: Some (true),
+            credentials_valid: Some (true),
+            ..Health::default()
+        };
+        Ok(match status {
+            Ok(Some(UP)) => healthy,
+            Ok(_) => Health {
+                problem: Some (format!("the interface {} isn't up", self.if_index)),
+                ..healthy
+            },
+            Err(error) => Health { problem: Some (error.to_string()), ..Health::default() }
+        })
+    }
+}
+
+// The identifier of the next request, which must be positive.
+fn next_id (id: i32) -> i32 {
+    id.checked_add (1).unwrap_or (1).max (1)
+}
+
+fn random() -> Result<u32> {
+    let mut bytes = [0; 4];
+    openssl::rand::rand_bytes (&mut bytes).chain_err (|| "failed to generate a random number")?;
+    Ok(u32::from_be_bytes (bytes) >> 1)
+}
+
+fn format_oid (oid: &[u32]) -> String {
+    oid.iter().map (u32::to_string).collect::<Vec<_>>().join (".")
+}
+
+// BER encoding of the values of the messages.
+
+fn tlv (tag: u8, content: &[u8]) -> Vec<u8> {
+    let mut encoded = vec![tag];
+    if content.len() < 0x80 {
+        encoded.push (content.len() as u8);
+    } else {
+        let length = content.len().to_be_bytes();
+        let zeros = length.iter().take_while (|byte| **byte == 0).count();
+        encoded.push (0x80 | (length.len() - zeros) as u8);
+        encoded.extend_from_slice (&length[zeros..]);
+    }
+    encoded.extend_from_slice (content);
+    encoded
+}
+
+fn integer (value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    // The shortest two's complement: leading bytes which only repeat the sign are dropped.
+    let start = (0..7)
+        .find (|&i| !matches!((bytes[i], bytes[i + 1] & 0x80), (0, 0) | (0xff, 0x80)))
+        .unwrap_or (7);
+    tlv (INTEGER, &bytes[start..])
+}
+
+fn octets (value: &[u8]) -> Vec<u8> {
+    tlv (OCTET_STRING, value)
+}
+
+fn sequence (tag: u8, items: &[&[u8]]) -> Vec<u8> {
+    tlv (tag, &items.concat())
+}
+
+fn oid (arcs: &[u32]) -> Vec<u8> {
+    let mut content = vec![(arcs[0] * 40 + arcs[1]) as u8];
+    for &arc in &arcs[2..] {
+        let groups = (0..5).rev()
+            .map (|group| (arc >> (7 * group)) as u8 & 0x7f)
+            .skip_while (|&byte| byte == 0)
+            .collect::<Vec<_>>();
+        if groups.is_empty() {
+            content.push (0);
+        }
+        for (i, byte) in groups.iter().enumerate() {
+            content.push (if i + 1 < groups.len() { byte | 0x80 } else { *byte });
+        }
+    }
+    tlv (OBJECT_IDENTIFIER, &content)
+}
+
+fn pdu (tag: u8, request_id: i32, varbinds: &[(&[u32], Vec<u8>)]) -> Vec<u8> {
+    let varbinds = varbinds.iter()
+        .map (|(name, value)| sequence (SEQUENCE, &[&oid (name), value]))
+        .collect::<Vec<_>>();
+    let varbinds = varbinds.iter().map (Vec::as_slice).collect::<Vec<_>>();
+    sequence (tag, &[
+        &integer (request_id as i64), &integer (0), &integer (0), &sequence (SEQUENCE, &varbinds)
+    ])
+}
+
+fn parse_v2c (message: &[u8]) -> Result<Pdu> {
+    let mut reader = Reader::sequence (message);
+    ensure!(reader.integer()? == 1, "not an SNMP v2c message");
+    reader.read (OCTET_STRING)?;
+    Pdu::parse (reader.0)
+}
+
+// A PDU, with the integer values of its variable bindings.
+struct Pdu {
+    tag: u8,
+    request_id: i64,
+    error_status: i64,
+    varbinds: Vec<(Vec<u32>, Option<i64>)>
+}
+
+impl Pdu {
+    fn parse (data: &[u8]) -> Result<Pdu> {
+        let (tag, content) = Reader (data).any()?;
+        let mut reader = Reader (content);
+        let request_id = reader.integer()?;
+        let error_status = reader.integer()?;
+        reader.integer()?;
+        let mut list = reader.nested (SEQUENCE)?;
+        let mut varbinds = Vec::new();
+        while !list.0.is_empty() {
+            let mut varbind = list.nested (SEQUENCE)?;
+            let name = varbind.oid()?;
+            let value = match varbind.any()? {
+                (INTEGER, value) => Some (decode_integer (value)?),
+                _ => None
+            };
+            varbinds.push ((name, value));
+        }
+        Ok(Pdu { tag, request_id, error_status, varbinds })
+    }
+
+    fn report_oid (&self) -> Vec<u32> {
+        self.varbinds.first().map (|(name, _)| name.clone()).unwrap_or_default()
+    }
+
+    // The USM counter of a report, e.g. `NOT_IN_TIME_WINDOWS`.
+    fn report_counter (&self) -> Option<u32> {
+        let name = self.report_oid();
+        match name.strip_prefix (USM_STATS)? {
+            [counter, 0] => Some (*counter),
+            _ => None
+        }
+    }
+}
+
+fn decode_integer (content: &[u8]) -> Result<i64> {
+    ensure!(!content.is_empty() && content.len() <= 8, "invalid integer");
+    let sign = if content[0] & 0x80 != 0 { -1 } else { 0 };
+    Ok(content.iter().fold (sign, |value, byte| value << 8 | *byte as i64))
+}
+
+// Reads BER values one after another.
+struct Reader<'a> (&'a [u8]);
+
+impl<'a> Reader<'a> {
+    // Reads the content of the sequence in `data`, which is empty if it isn't one.
+    fn sequence (data: &'a [u8]) -> Self {
+        Reader (Reader (data).read (SEQUENCE).unwrap_or_default())
+    }
+
+    fn any (&mut self) -> Result<(u8, &'a [u8])> {
+        let data = self.0;
+        ensure!(data.len() >= 2, "the message is truncated");
+        let (length, header) = match data[1] {
+            length if length < 0x80 => (length as usize, 2),
+            length => {
+                let count = (length & 0x7f) as usize;
+                ensure!((1..=4).contains (&count) && data.len() >= 2 + count, "invalid length");
+                let length = data[2..2 + count].iter()
+                    .fold (0, |length, byte| length << 8 | *byte as usize);
+                (length, 2 + count)
+            }
+        };
+        ensure!(data.len() - header >= length, "the message is truncated");
+        self.0 = &data[header + length..];
+        Ok((data[0], &data[header..header + length]))
+    }
+
+    fn read (&mut self, tag: u8) -> Result<&'a [u8]> {
+        let (found, content) = self.any()?;
+        ensure!(found == tag, "expected the tag {:#04x}, found {:#04x}", tag, found);
+        Ok(content)
+    }
+
+    fn nested (&mut self, tag: u8) -> Result<Reader<'a>> {
+        self.read (tag).map (Reader)
+    }
+
+    fn integer (&mut self) -> Result<i64> {
+        decode_integer (self.read (INTEGER)?)
+    }
+
+    fn unsigned (&mut self) -> Result<u32> {
+        let value = self.integer()?;
+        u32::try_from (value).ok().chain_err (|| format!("invalid unsigned integer {}", value))
+    }
+
+    fn oid (&mut self) -> Result<Vec<u32>> {
+        let content = self.read (OBJECT_IDENTIFIER)?;
+        ensure!(!content.is_empty(), "invalid object identifier");
+        let mut arcs = vec![(content[0] / 40) as u32, (content[0] % 40) as u32];
+        let mut arc = 0u32;
+        for byte in &content[1..] {
+            arc = arc << 7 | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                arcs.push (arc);
+                arc = 0;
+            }
+        }
+        Ok(arcs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example of RFC 3414, A.3.
+    #[test]
+    fn keys_are_localized() {
+        let engine_id = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2];
+        let hex = |bytes: Vec<u8>| {
+            bytes.iter().map (|byte| format!("{:02x}", byte)).collect::<String>()
+        };
+        let key = |protocol: AuthProtocol| {
+            let key = protocol.password_key ("maplesyrup").unwrap();
+            hex (protocol.localize (&key, &engine_id).unwrap())
+        };
+        assert_eq!(key (AuthProtocol::Md5), "526f5eed9fcce26f8964c2930787d82b");
+        assert_eq!(key (AuthProtocol::Sha), "6695febc9288e36282235fc7151f128497b38f3f");
+        assert!(AuthProtocol::Sha.password_key ("short").is_err());
+    }
+
+    #[test]
+    fn values_are_encoded() {
+        assert_eq!(integer (0), [INTEGER, 1, 0]);
+        assert_eq!(integer (128), [INTEGER, 2, 0, 0x80]);
+        assert_eq!(integer (-129), [INTEGER, 2, 0xff, 0x7f]);
+        assert_eq!(oid (&[1, 3, 6, 1, 4, 1, 2680, 0]), [6, 8, 0x2b, 6, 1, 4, 1, 0x94, 0x78, 0]);
+        for value in [0, 1, 127, 128, -1, -128, -129, 65535, i32::MAX as i64] {
+            assert_eq!(Reader (&integer (value)).integer().unwrap(), value);
+        }
+        let long = tlv (OCTET_STRING, &[7; 300]);
+        assert_eq!(&long[..4], [OCTET_STRING, 0x82, 1, 44]);
+        assert_eq!(Reader (&long).read (OCTET_STRING).unwrap(), [7; 300]);
+    }
+
+    #[test]
+    fn the_interface_is_bounced() {
+        let socket = UdpSocket::bind ("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap().to_string();
+        let device = thread::spawn (move || {
+            let mut buffer = [0; 1500];
+            (0..2).map (|_| {
+                let (length, peer) = socket.recv_from (&mut buffer).unwrap();
+                let mut reader = Reader::sequence (&buffer[..length]);
+                assert_eq!(reader.integer().unwrap(), 1);
+                assert_eq!(reader.read (OCTET_STRING).unwrap(), b"s3cr3t");
+                let request = Pdu::parse (reader.0).unwrap();
+                assert_eq!(request.tag, SET_REQUEST);
+                let (name, value) = request.varbinds[0].clone();
+                let response = pdu (RESPONSE, request.request_id as i32, &[
+                    (&name, integer (value.unwrap()))
+                ]);
+                let message = sequence (SEQUENCE, &[&integer (1), &octets (b"s3cr3t"), &response]);
+                socket.send_to (&message, peer).unwrap();
+                (format_oid (&name), value.unwrap())
+            }).collect::<Vec<_>>()
+        });
+
+        let mut renewer = Renewer {
+            agent: Agent { addr, timeout: Duration::from_secs (5) },
+            if_index: 4,
+            security: Security::Community ("s3cr3t".into()),
+            down_time: Duration::ZERO,
+            request_id: i32::MAX
+        };
+        renewer.renew_ip().unwrap();
+        let oid = "1.3.6.1.2.1.2.2.1.7.4".to_string();
+        assert_eq!(device.join().unwrap(), [(oid.clone(), DOWN), (oid, UP)]);
+    }
+
+    #[test]
+    fn messages_are_authenticated_and_encrypted() {
+        let protocol = AuthProtocol::Sha;
+        let engine_id = b"\x80\x00\x1f\x88\x04engine";
+        let localize = |password| {
+            protocol.localize (&protocol.password_key (password).unwrap(), engine_id).unwrap()
+        };
+        let mut usm = Usm {
+            username: "xenon".into(),
+            auth: Some ((protocol, protocol.password_key ("authpass").unwrap().into())),
+            privacy: Some (protocol.password_key ("privpass").unwrap().into()),
+            engine: Some (Engine {
+                id: engine_id.to_vec(),
+                boots: 3,
+                time: 1234,
+                received: Instant::now(),
+                auth_key: localize ("authpass").into(),
+                priv_key: localize ("privpass").into()
+            }),
+            message_id: 1,
+            salt: 42
+        };
+        let oid = [IF_ADMIN_STATUS, &[4]].concat();
+        let message = usm.message (&pdu (SET_REQUEST, 7, &[(&oid, integer (DOWN))])).unwrap();
+        let answer = usm.parse (&message).unwrap();
+        assert_eq!((answer.engine_id, answer.boots), (engine_id.to_vec(), 3));
+        assert_eq!(answer.pdu.tag, SET_REQUEST);
+        assert_eq!(answer.pdu.varbinds, [(oid, Some (DOWN))]);
+        // The encrypted PDU doesn't show the OID, and tampering is noticed.
+        assert!(!message.windows (5).any (|window| window == [0x2b, 6, 1, 2, 1]));
+        let mut tampered = message.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(usm.parse (&tampered).is_err());
+    }
+}
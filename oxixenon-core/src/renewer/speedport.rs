@@ -0,0 +1,332 @@
+//! Renews the IP address of the Speedport routers of Deutsche Telekom (Smart 3, Hybrid and the
+//! firmwares of the Smart 4 sharing their web API) by asking them to reconnect the DSL link. The
+//! login answers a challenge with the SHA-256 hash of the password, and the following requests are
+//! encrypted with AES-CCM, with a key derived from the password and the challenge.
+
+use super::{hex_decode, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use http::Response;
+use openssl::hash::MessageDigest;
+use openssl::cipher::Cipher;
+use openssl::cipher_ctx::CipherCtx;
+use openssl::error::ErrorStack;
+use serde::Deserialize;
+use serde_json::Value;
+
+// Configuration of the renewer, in `[server.renewer.speedport]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the router, "speedport.ip" unless specified.
+    ip: Option<String>,
+    /// The device password, printed on the back of the router.
+    password: Secret<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const DEFAULT_IP: &str = "speedport.ip";
+// The CSRF token of the requests made before logging in.
+const NULL_TOKEN: &str = "nulltoken";
+// The page whose source contains the CSRF token of the session.
+const TOKEN_PAGE: &str = "/html/content/overview/index.html";
+// The iterations of PBKDF2 deriving the key of the session, and its length.
+const KEY_ITERATIONS: usize = 1000;
+const KEY_LENGTH: usize = 16;
+// The length of the tag of the encrypted requests and responses.
+const TAG_LENGTH: usize = 8;
+
+// What the login establishes.
+struct Session {
+    cookies: Secret<String>,
+    // The AES-128-CCM key of the requests, and the challenge giving their nonce and their
+    // additional data.
+    key: Secret<Vec<u8>>,
+    challenge: String
+}
+
+pub struct Renewer {
+    ip: String,
+    password: Secret<String>,
+    session: Option<Session>,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    // Posts a form (before logging in) to `path`, returning the answer along with the cookies it
+    // set.
+    fn post_form (&mut self, path: &str, form: &[(&str, &str)])
+        -> Result<(Value, Option<Secret<String>>)>
+    {
+        let url = format!("http://{}{}", self.ip, path);
+        let mut request = http_client::build_post (&url);
+        for (key, value) in form {
+            request = request.put (key, value);
+        }
+        let res = request.build_and_execute_with (&mut *self.transport)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+        ensure!(res.status().is_success(), "the request failed with status {}", res.status());
+        let response = serde_json::from_str (res.body())
+            .chain_err (|| "the router answered with invalid JSON")?;
+        Ok((response, http_client::cookies (&res)))
+    }
+
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::speedport", "trying to login using specified credentials");
+        self.session = None;
+        let (response, _) = self.post_form ("/data/Login.json", &[
+            ("csrf_token", NULL_TOKEN), ("showpw", "0"), ("challengev", "null")
+        ])?;
+        let challenge = var (&response, "challengev")
+            .filter (|challenge| challenge.len() >= 48 && challenge.is_ascii())
+            .chain_err (|| "the router answered without a valid login challenge")?
+            .to_string();
+        let hash = sha256_hex (&format!("{}:{}", challenge, self.password.expose()));
+        let (response, cookies) = self.post_form ("/data/Login.json", &[
+            ("csrf_token", NULL_TOKEN), ("showpw", "0"), ("password", &hash)
+        ])?;
+        match var (&response, "login") {
+            Some("success") => (),
+            Some("failed") if var (&response, "login_locked") == Some ("1") =>
+                bail!("login failed, the router is locked after too many attempts"),
+            result => bail!(
+                "login failed with result '{}', check your credentials!",
+                result.unwrap_or ("unknown")
+            )
+        }
+        let cookies = cookies.chain_err (|| "the router answered without a session")?;
+        let key = session_key (self.password.expose(), &challenge)?;
+        self.session = Some (Session { cookies, key: key.into(), challenge });
+        info!(target: "renewer::speedport", "login OK");
+        Ok(())
+    }
+
+    fn get (&mut self, path: &str) -> Result<Response<String>> {
+        let url = format!("http://{}{}", self.ip, path);
+        let session = self.session.as_ref().expect ("only called with a session");
+        let request = Request::builder()
+            .uri (url.as_str())
+            .header (header::COOKIE, session.cookies.expose().as_str())
+            .body (None::<String>)
+            .chain_err (|| "failed to build HTTP request object")?;
+        http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))
+    }
+
+    // The CSRF token of the session, read from the source of a page, or `None` if the session
+    // expired (and the router redirects to the login page).
+    fn csrf_token (&mut self) -> Result<Option<String>> {
+        let res = self.get (TOKEN_PAGE)?;
+        if res.status().is_redirection() {
+            return Ok(None);
+        }
+        ensure!(
+            res.status().is_success(),
+            "failed to request '{}', got status {}", TOKEN_PAGE, res.status()
+        );
+        Ok(extract_csrf_token (res.body()))
+    }
+
+    // Asks the router to reconnect the DSL link. Returns `false` if the session expired.
+    fn reconnect (&mut self) -> Result<bool> {
+        let csrf_token = match self.csrf_token()? {
+            Some(csrf_token) => csrf_token,
+            None => return Ok(false)
+        };
+        let session = self.session.as_ref().expect ("only called with a session");
+        let form = format!("req_connect=reconnect&csrf_token={}", csrf_token);
+        let body = encrypt (session.key.expose(), &session.challenge, form.as_bytes())?;
+        let url = format!("http://{}/data/Connect.json", self.ip);
+        let request = Request::builder()
+            .method (http::Method::POST)
+            .uri (url.as_str())
+            .header (header::COOKIE, session.cookies.expose().as_str())
+            .body (Some (body))
+            .chain_err (|| "failed to build HTTP request object")?;
+        let res = http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+        ensure!(
+            res.status().is_success(),
+            "failed to reconnect, got status {}", res.status()
+        );
+        let session = self.session.as_ref().expect ("only called with a session");
+        let response = decrypt (session.key.expose(), &session.challenge, res.body().trim())?;
+        let response: Value = serde_json::from_slice (&response)
+            .chain_err (|| "the router answered with invalid JSON")?;
+        match var (&response, "status") {
+            Some("ok") => Ok(true),
+            status => bail!("failed to reconnect, got status '{}'", status.unwrap_or ("unknown"))
+        }
+    }
+}
+
+// The value of the variable `id` in a response, e.g. `[{"varid":"login","varvalue":"success"}]`.
+fn var<'a> (response: &'a Value, id: &str) -> Option<&'a str> {
+    response.as_array()?.iter()
+        .find (|var| var["varid"] == id)
+        .and_then (|var| var["varvalue"].as_str())
+}
+
+// Finds e.g. `var csrf_token = "abc";` in the source of a page.
+fn extract_csrf_token (page: &str) -> Option<String> {
+    let start = page.find ("csrf_token")?;
+    let value = page[start..].split ('"').nth (1)?;
+    Some (value.to_string()).filter (|value| !value.is_empty() && value != NULL_TOKEN)
+}
+
+fn sha256_hex (data: &str) -> String {
+    openssl::sha::sha256 (data.as_bytes()).iter().map (|byte| format!("{:02x}", byte)).collect()
+}
+
+// The key of the session: PBKDF2-SHA1 of the SHA-256 hash of the password, salted with the first
+// 16 characters of the challenge.
+fn session_key (password: &str, challenge: &str) -> Result<Vec<u8>> {
+    let mut key = vec![0; KEY_LENGTH];
+    openssl::pkcs5::pbkdf2_hmac (
+        sha256_hex (password).as_bytes(), &challenge.as_bytes()[..16], KEY_ITERATIONS,
+        MessageDigest::sha1(), &mut key
+    ).chain_err (|| "failed to derive the key of the session")?;
+    Ok(key)
+}
+
+// The nonce and the additional data of the requests, which follow the salt in the challenge.
+fn nonce_and_data (challenge: &str) -> Result<(Vec<u8>, Vec<u8>)> {
+    hex_decode (&challenge[16..32]).zip (hex_decode (&challenge[32..48]))
+        .chain_err (|| "the login challenge isn't hexadecimal")
+}
+
+// Runs AES-128-CCM over `data`, encrypting it unless `tag` (the one to check) is given. CCM needs
+// the lengths of the nonce and of the tag before the key, which `openssl::symm` doesn't allow.
+fn ccm (key: &[u8], challenge: &str, data: &[u8], tag: Option<&[u8]>)
+    -> Result<(Vec<u8>, [u8; TAG_LENGTH])>
+{
+    let (nonce, aad) = nonce_and_data (challenge)?;
+    let run = || -> std::result::Result<_, ErrorStack> {
+        let mut ctx = CipherCtx::new()?;
+        let init = |ctx: &mut CipherCtx, cipher, key, iv| match tag {
+            Some(_) => ctx.decrypt_init (cipher, key, iv),
+            None => ctx.encrypt_init (cipher, key, iv)
+        };
+        init (&mut ctx, Some (Cipher::aes_128_ccm()), None, None)?;
+        ctx.set_iv_length (nonce.len())?;
+        match tag {
+            Some(tag) => ctx.set_tag (tag)?,
+            None => ctx.set_tag_length (TAG_LENGTH)?
+        }
+        init (&mut ctx, None, Some (key), Some (&nonce))?;
+        ctx.set_data_len (data.len())?;
+        ctx.cipher_update (&aad, None)?;
+        let mut output = Vec::new();
+        ctx.cipher_update_vec (data, &mut output)?;
+        let mut computed = [0; TAG_LENGTH];
+        if tag.is_none() {
+            ctx.cipher_final_vec (&mut output)?;
+            ctx.tag (&mut computed)?;
+        }
+        Ok((output, computed))
+    };
+    run().chain_err (|| "AES-CCM failed")
+}
+
+// Encrypts `data`, as hexadecimal followed by the tag.
+fn encrypt (key: &[u8], challenge: &str, data: &[u8]) -> Result<String> {
+    let (mut encrypted, tag) = ccm (key, challenge, data, None)
+        .chain_err (|| "failed to encrypt the request")?;
+    encrypted.extend_from_slice (&tag);
+    Ok(encrypted.iter().map (|byte| format!("{:02x}", byte)).collect())
+}
+
+fn decrypt (key: &[u8], challenge: &str, hex: &str) -> Result<Vec<u8>> {
+    let data = hex_decode (hex)
+        .filter (|data| data.len() >= TAG_LENGTH)
+        .chain_err (|| "the router answered with an invalid encrypted response")?;
+    let (data, tag) = data.split_at (data.len() - TAG_LENGTH);
+    ccm (key, challenge, data, Some (tag))
+        .map (|(decrypted, _)| decrypted)
+        .chain_err (|| "failed to decrypt the response")
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        Ok(Renewer {
+            ip: http_client::uri_host (config.ip.as_deref().unwrap_or (DEFAULT_IP)).into_owned(),
+            password: config.password,
+            session: None,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        if self.session.is_none() {
+            self.login()?;
+        }
+        if !self.reconnect()? {
+            debug!(target: "renewer::speedport", "session expired, logging in again");
+            self.login()?;
+            ensure!(self.reconnect()?, "the router rejected the new session");
+        }
+        info!(target: "renewer::speedport", "successfully asked to reconnect the DSL link");
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    const CHALLENGE: &str = "0123456789abcdef0011223344556677a1b2c3d4e5f60718293a4b5c6d7e8f90";
+
+    #[test]
+    fn login_answers_the_challenge_and_requests_are_encrypted() {
+        let key = session_key ("hunter2", CHALLENGE).unwrap();
+        let reconnected = encrypt (&key, CHALLENGE, br#"[{"varid":"status","varvalue":"ok"}]"#)
+            .unwrap();
+        let transport = MockTransport::new();
+        transport
+            .push_response (format!(
+                "HTTP/1.1 200 OK\r\n\r\n[{{\"varid\":\"challengev\",\"varvalue\":\"{}\"}}]",
+                CHALLENGE
+            ))
+            .push_response ("HTTP/1.1 200 OK\r\nSet-Cookie: SessionID_R3=s3ss; path=/\r\n\r\n\
+                [{\"varid\":\"login\",\"varvalue\":\"success\"}]")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<script>var csrf_token = \"t0k3n\";</script>")
+            .push_response (format!("HTTP/1.1 200 OK\r\n\r\n{}", reconnected));
+        let mut renewer = Renewer {
+            ip: DEFAULT_IP.into(),
+            password: "hunter2".into(),
+            session: None,
+            transport: Box::new (transport.clone())
+        };
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        let hash = sha256_hex (&format!("{}:hunter2", CHALLENGE));
+        assert!(requests[1].contains (&format!("password={}", hash)));
+        assert!(requests[2].starts_with (&format!("GET {} HTTP/1.1\r\n", TOKEN_PAGE)));
+        assert!(requests[2].contains ("cookie: SessionID_R3=s3ss\r\n"));
+        assert!(requests[3].starts_with ("POST /data/Connect.json HTTP/1.1\r\n"));
+        let body = requests[3].rsplit ("\r\n\r\n").next().unwrap().trim();
+        let form = decrypt (&key, CHALLENGE, body).unwrap();
+        assert_eq!(form, b"req_connect=reconnect&csrf_token=t0k3n");
+        assert!(decrypt (&session_key ("hunter3", CHALLENGE).unwrap(), CHALLENGE, body).is_err());
+    }
+}
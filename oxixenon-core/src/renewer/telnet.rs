@@ -0,0 +1,308 @@
+//! Renews the IP address of devices which only expose telnet (such as many older DSL modems), by
+//! running a script of steps: each one waits for some text and/or sends a line, e.g. to login and
+//! restart the WAN connection.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration, Secret};
+use serde::Deserialize;
+use std::io::{self, prelude::*};
+use std::net::{Ipv6Addr, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+// Configuration of the renewer, in `[server.renewer.telnet]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the device, optionally followed by the port (23 unless
+    /// specified, e.g. "192.168.1.1:2323").
+    ip: String,
+    /// Replaces `{username}` in the lines which are sent.
+    username: Option<String>,
+    /// Replaces `{password}` in the lines which are sent.
+    password: Option<Secret<String>>,
+    /// How long each step waits for its text, e.g. "10s". Defaults to 10 seconds.
+    timeout: Option<String>,
+    /// Text which makes the renewal fail as soon as the device sends it, e.g. "Login incorrect".
+    #[serde(default)]
+    errors: Vec<String>,
+    /// The steps of the script, run in order.
+    steps: Vec<Step>
+}
+
+/// A step of the script: waits for `expect` (if set), then sends `send` (if set) followed by a
+/// newline.
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Step {
+    /// Text which the device must send, e.g. "login:".
+    expect: Option<String>,
+    /// A line to send, where `{username}` and `{password}` are replaced by the options.
+    send: Option<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const PORT: u16 = 23;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs (10);
+
+// Telnet commands (RFC 854).
+const IAC: u8 = 255;
+const DONT: u8 = 254;
+const DO: u8 = 253;
+const WONT: u8 = 252;
+const WILL: u8 = 251;
+const SB: u8 = 250;
+const SE: u8 = 240;
+
+pub struct Renewer {
+    addr: String,
+    username: String,
+    password: Secret<String>,
+    timeout: Duration,
+    errors: Vec<String>,
+    steps: Vec<Step>
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        ensure!(
+            !config.steps.is_empty(),
+            "option '{}.steps' must have at least a step", renewer.config.path()
+        );
+        let timeout = match config.timeout {
+            Some(timeout) => parse_duration (&timeout)
+                .chain_err (|| format!("invalid option '{}.timeout'", renewer.config.path()))?,
+            None => DEFAULT_TIMEOUT
+        };
+        let addr = match config.ip.rsplit_once (':') {
+            Some((host, port)) if !host.contains (':') && port.parse::<u16>().is_ok() =>
+                config.ip.clone(),
+            _ if config.ip.parse::<Ipv6Addr>().is_ok() => format!("[{}]:{}", config.ip, PORT),
+            _ => format!("{}:{}", config.ip, PORT)
+        };
+        Ok(Renewer {
+            addr,
+            username: config.username.unwrap_or_default(),
+            password: config.password.unwrap_or_else (|| Secret::new (String::new())),
+            timeout,
+            errors: config.errors,
+            steps: config.steps
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let addr = self.addr.to_socket_addrs()
+            .chain_err (|| format!("failed to resolve '{}'", self.addr))?
+            .next()
+            .chain_err (|| format!("'{}' doesn't resolve to any address", self.addr))?;
+        let stream = TcpStream::connect_timeout (&addr, self.timeout)
+            .chain_err (|| format!("failed to connect to {}", self.addr))?;
+        stream.set_read_timeout (Some (self.timeout))
+            .chain_err (|| "failed to set the timeout of the connection")?;
+        let mut session = Session::new (stream, &self.errors);
+        for (number, step) in self.steps.iter().enumerate() {
+            let result = (|| -> Result<()> {
+                if let Some(ref expect) = step.expect {
+                    session.expect (expect, self.timeout)?;
+                }
+                if let Some(ref send) = step.send {
+                    let line = send.replace ("{username}", &self.username)
+                        .replace ("{password}", self.password.expose());
+                    session.send (&line)?;
+                }
+                Ok(())
+            })();
+            result.chain_err (|| format!("step {} of the script failed", number + 1))?;
+        }
+        info!(target: "renewer::telnet", "successfully ran the script");
+        Ok(RenewOutcome::default())
+    }
+}
+
+// A telnet session which refuses every option the device proposes, so that it behaves like a
+// plain terminal.
+struct Session<'a, S> {
+    stream: S,
+    errors: &'a [String],
+    // The text received and not yet matched by a step.
+    received: String
+}
+
+impl<'a, S: Read + Write> Session<'a, S> {
+    fn new (stream: S, errors: &'a [String]) -> Self {
+        Session { stream, errors, received: String::new() }
+    }
+
+    fn send (&mut self, line: &str) -> Result<()> {
+        // Data bytes equal to IAC must be doubled.
+        let mut data = Vec::with_capacity (line.len() + 2);
+        for &byte in line.as_bytes() {
+            data.push (byte);
+            if byte == IAC {
+                data.push (IAC);
+            }
+        }
+        data.extend_from_slice (b"\r\n");
+        self.stream.write_all (&data).chain_err (|| "failed to send a line")
+    }
+
+    // Waits until the device sends `text`, consuming everything received up to it.
+    fn expect (&mut self, text: &str, timeout: Duration) -> Result<()> {
+        let started = Instant::now();
+        loop {
+            if let Some(error) = self.errors.iter().find (|e| self.received.contains (e.as_str())) {
+                bail!("the device answered '{}'", error);
+            }
+            if let Some(index) = self.received.find (text) {
+                self.received.drain (..index + text.len());
+                return Ok(());
+            }
+            ensure!(
+                started.elapsed() < timeout,
+                "timed out waiting for '{}', received '{}'", text, self.received.trim()
+            );
+            let data = match self.read() {
+                Ok(Some(data)) => data,
+                Ok(None) => bail!("the device closed the connection while waiting for '{}'", text),
+                // The read timed out, which is only an error once the step does.
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock
+                    || e.kind() == io::ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e).chain_err (|| "failed to receive from the device")
+            };
+            let data = String::from_utf8_lossy (&data);
+            trace!(target: "renewer::telnet", "received {:?}", data);
+            self.received.push_str (&data);
+        }
+    }
+
+    // Reads some data, answering the commands interleaved with it. Returns `None` once the
+    // connection is closed.
+    fn read (&mut self) -> io::Result<Option<Vec<u8>>> {
+        let mut buf = [0; 1024];
+        let length = self.stream.read (&mut buf)?;
+        if length == 0 {
+            return Ok(None);
+        }
+        let stream = &mut self.stream;
+        let (data, replies) = parse (&buf[..length], || {
+            let mut byte = [0];
+            stream.read_exact (&mut byte).ok().map (|_| byte[0])
+        });
+        if !replies.is_empty() {
+            self.stream.write_all (&replies)?;
+        }
+        Ok(Some (data))
+    }
+}
+
+// Splits `input` into its data and its commands, returning the data along with the replies to
+// the commands. `more` reads another byte when a command is split across reads.
+fn parse (input: &[u8], mut more: impl FnMut() -> Option<u8>) -> (Vec<u8>, Vec<u8>) {
+    let mut bytes = input.iter().copied();
+    let (mut data, mut replies) = (Vec::new(), Vec::new());
+    while let Some(byte) = bytes.next() {
+        if byte != IAC {
+            data.push (byte);
+            continue;
+        }
+        let mut next = || bytes.next().or_else (&mut more);
+        match next() {
+            Some(IAC) => data.push (IAC),
+            // Refuse every option: the device is answered as a dumb terminal.
+            Some(DO) | Some(DONT) => if let Some(option) = next() {
+                replies.extend_from_slice (&[IAC, WONT, option]);
+            },
+            Some(WILL) | Some(WONT) => if let Some(option) = next() {
+                replies.extend_from_slice (&[IAC, DONT, option]);
+            },
+            // Skip subnegotiations, up to IAC SE.
+            Some(SB) => {
+                let mut previous = 0;
+                while let Some(byte) = next() {
+                    if previous == IAC && byte == SE {
+                        break;
+                    }
+                    previous = byte;
+                }
+            },
+            _ => ()
+        }
+    }
+    (data, replies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn commands_are_refused() {
+        let input = [b'a', IAC, DO, 1, IAC, WILL, 3, IAC, SB, 24, 1, IAC, SE, IAC, IAC, b'b', IAC];
+        // The last command is split across reads.
+        let mut rest = vec![5, DONT];
+        let (data, replies) = parse (&input, || rest.pop());
+        assert_eq!(data, [b'a', IAC, b'b']);
+        assert_eq!(replies, [IAC, WONT, 1, IAC, DONT, 3, IAC, WONT, 5]);
+    }
+
+    #[test]
+    fn the_script_is_run() {
+        let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let device = thread::spawn (move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut received = Vec::new();
+            let mut exchange = |prompt: &[u8]| {
+                stream.write_all (prompt).unwrap();
+                let mut line = Vec::new();
+                let mut byte = [0];
+                while !line.ends_with (b"\r\n") {
+                    stream.read_exact (&mut byte).unwrap();
+                    line.push (byte[0]);
+                }
+                received.push (line);
+            };
+            exchange (&[IAC, DO, 1, b'l', b'o', b'g', b'i', b'n', b':', b' ']);
+            exchange (b"Password: ");
+            exchange (b"\r\nWelcome\r\n> ");
+            received
+        });
+
+        let step = |expect: &str, send: &str| Step {
+            expect: Some (expect.into()),
+            send: Some (send.into())
+        };
+        let mut renewer = Renewer {
+            addr,
+            username: "admin".into(),
+            password: "hunter2".into(),
+            timeout: Duration::from_secs (5),
+            errors: vec!["Login incorrect".into()],
+            steps: vec![
+                step ("login:", "{username}"),
+                step ("Password:", "{password}"),
+                step ("> ", "wan restart")
+            ]
+        };
+        renewer.renew_ip().unwrap();
+        // The refusal of the option comes before the username.
+        let received = device.join().unwrap();
+        assert_eq!(received[0], b"\xff\xfc\x01admin\r\n");
+        assert_eq!(received[1], b"hunter2\r\n");
+        assert_eq!(received[2], b"wan restart\r\n");
+    }
+}
@@ -0,0 +1,225 @@
+//! Timeouts of the operations of renewers, configured in the section of any renewer with
+//! `operation_timeout` (see `get_renewer`). The renewer lives on a worker thread, so that a router
+//! which never answers can't block the server past the timeout.
+
+use super::{
+    Error, Health, LinkInfo, RenewOutcome, Renewer as RenewerTrait, RenewerType, Result,
+    ResultExt
+};
+use crate::config::{self, parse_duration};
+use crate::metrics;
+use serde::Deserialize;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The options of every renewer section, which `get_renewer` handles on behalf of the renewer.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// How long each operation of the renewer (initializing it, renewing the IP address, asking
+    /// about the link, keeping its session alive, checking its health) can take, e.g. "2m".
+    /// Unlimited unless specified.
+    operation_timeout: Option<String>
+}
+
+// The names of the options in `Config`.
+pub(super) const OPTIONS: &[&str] = &["operation_timeout"];
+
+// The operations run by the worker, and their results.
+enum Call {
+    Init,
+    RenewIp,
+    LinkInfo,
+    Keepalive,
+    HealthCheck
+}
+
+enum Reply {
+    Done(Result<()>),
+    Renewed(Result<RenewOutcome>),
+    LinkInfo(Result<Option<LinkInfo>>),
+    Health(Result<Health>)
+}
+
+/// Runs the operations of a renewer on a worker thread, failing with `Error::TimedOut` when they
+/// take longer than the timeout.
+pub struct Renewer {
+    calls: Sender<(u64, Call)>,
+    replies: Receiver<(u64, Reply)>,
+    // The identifier of the next call, as the replies to calls which timed out arrive later.
+    next_call: u64,
+    timeout: Duration
+}
+
+/// Builds the renewer described by `renewer` as `renewer_type`, wrapped as configured by `section`
+/// (the timeout options split from its section) or as is if its operations aren't limited.
+pub(super) fn wrap (
+    renewer_type: RenewerType,
+    renewer: &config::RenewerConfig,
+    renewers: &[config::RenewerConfig],
+    section: &config::Section
+) -> Result<Box<dyn RenewerTrait>> {
+    let config: Config = section.parse()?;
+    let timeout = match config.operation_timeout {
+        Some(timeout) => parse_duration (&timeout)
+            .chain_err (|| format!("invalid option '{}.operation_timeout'", section.path()))?,
+        None => return renewer_type.build (renewer, renewers)
+    };
+    ensure!(!timeout.is_zero(), "option '{}.operation_timeout' can't be 0", section.path());
+    let (calls, worker_calls) = mpsc::channel::<(u64, Call)>();
+    let (worker_replies, replies) = mpsc::channel();
+    let (built_sender, built) = mpsc::channel();
+    let (renewer, renewers) = (renewer.clone(), renewers.to_vec());
+    // Renewers aren't `Send`, so the renewer is built by the worker itself.
+    thread::Builder::new()
+        .name (format!("renewer-{}", renewer.instance))
+        .spawn (move || {
+            let mut renewer = match renewer_type.build (&renewer, &renewers) {
+                Ok(renewer) => renewer,
+                Err(error) => {
+                    let _ = built_sender.send (Err(error));
+                    return;
+                }
+            };
+            let _ = built_sender.send (Ok(()));
+            for (id, call) in worker_calls {
+                let reply = match call {
+                    Call::Init => Reply::Done (renewer.init()),
+                    Call::RenewIp => Reply::Renewed (renewer.renew_ip()),
+                    Call::LinkInfo => Reply::LinkInfo (renewer.link_info()),
+                    Call::Keepalive => Reply::Done (renewer.keepalive()),
+                    Call::HealthCheck => Reply::Health (renewer.health_check())
+                };
+                if worker_replies.send ((id, reply)).is_err() {
+                    break;
+                }
+            }
+        })
+        .chain_err (|| "failed to start the thread of the renewer")?;
+    built.recv().chain_err (|| "the thread of the renewer stopped unexpectedly")??;
+    Ok(Box::new (Renewer { calls, replies, next_call: 0, timeout }))
+}
+
+impl Renewer {
+    // Runs `call` on the worker, waiting for its reply until the timeout. Operations which time out
+    // keep the worker busy, delaying (and possibly timing out) the following ones.
+    fn call (&mut self, call: Call) -> Result<Reply> {
+        let id = self.next_call;
+        self.next_call += 1;
+        self.calls.send ((id, call))
+            .ok()
+            .chain_err (|| "the thread of the renewer stopped unexpectedly")?;
+        let deadline = Instant::now() + self.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since (Instant::now());
+            match self.replies.recv_timeout (remaining) {
+                Ok((reply_id, reply)) if reply_id == id => return Ok(reply),
+                // The late reply of a call which timed out.
+                Ok(_) => continue,
+                Err(RecvTimeoutError::Timeout) => {
+                    metrics::counter ("renewer.timeouts", 1);
+                    return Err(Error::TimedOut (self.timeout));
+                },
+                Err(RecvTimeoutError::Disconnected) =>
+                    bail!("the thread of the renewer stopped unexpectedly")
+            }
+        }
+    }
+
+    fn done (&mut self, call: Call) -> Result<()> {
+        match self.call (call)? {
+            Reply::Done(result) => result,
+            _ => unreachable!()
+        }
+    }
+}
+
+impl RenewerTrait for Renewer {
+    // Timeouts only exist as wrappers of other renewers, see `wrap`.
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        bail!("timeouts of the renewer '{}' can't be built on their own", renewer.instance)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.done (Call::Init)
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        match self.call (Call::RenewIp)? {
+            Reply::Renewed(result) => result,
+            _ => unreachable!()
+        }
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        match self.call (Call::LinkInfo)? {
+            Reply::LinkInfo(result) => result,
+            _ => unreachable!()
+        }
+    }
+
+    fn keepalive (&mut self) -> Result<()> {
+        self.done (Call::Keepalive)
+    }
+
+    fn health_check (&mut self) -> Result<Health> {
+        match self.call (Call::HealthCheck)? {
+            Reply::Health(result) => result,
+            _ => unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Section, SourceMap};
+    use std::sync::Arc;
+
+    // Takes as many milliseconds to renew as its instance says.
+    struct Slow(u64);
+
+    impl RenewerTrait for Slow {
+        fn from_config (renewer: &config::RenewerConfig) -> Result<Self> {
+            Ok(Slow (renewer.instance.parse().unwrap()))
+        }
+
+        fn renew_ip (&mut self) -> Result<RenewOutcome> {
+            thread::sleep (Duration::from_millis (self.0));
+            Ok(RenewOutcome::default())
+        }
+    }
+
+    fn slow (millis: u64, timeout: &str) -> Result<Box<dyn RenewerTrait>> {
+        let options = format!("operation_timeout = '{}'", timeout);
+        let map = Arc::new (SourceMap::new (&options));
+        let path = "server.renewer.slow".to_string();
+        let section = Section::new (path.clone(), Some (options.parse().unwrap()), map);
+        let renewer = config::RenewerConfig {
+            name: "slow".into(),
+            instance: millis.to_string(),
+            config: Section::empty (path)
+        };
+        wrap (RenewerType::of::<Slow>(), &renewer, &[], &section)
+    }
+
+    #[test]
+    fn operations_time_out() {
+        let mut renewer = slow (1500, "1s").unwrap();
+        let started = Instant::now();
+        match renewer.renew_ip() {
+            Err(Error::TimedOut(timeout)) => assert_eq!(timeout, Duration::from_secs (1)),
+            other => panic!("unexpected result: {:?}", other.err())
+        }
+        assert!(started.elapsed() < Duration::from_millis (1400));
+        // The late reply of the renewal is discarded.
+        thread::sleep (Duration::from_millis (600));
+        renewer.init().unwrap();
+
+        slow (0, "1s").unwrap().renew_ip().unwrap();
+        assert!(slow (0, "0s").is_err());
+    }
+}
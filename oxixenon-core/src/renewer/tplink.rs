@@ -0,0 +1,521 @@
+//! Renews the IP address of TP-Link Archer routers by disconnecting and reconnecting the WAN
+//! connection. Recent firmwares encrypt their web API (the password and the requests with RSA
+//! and AES), while older ones login through a cookie and put a token in the URLs.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use http::Response;
+use openssl::base64;
+use openssl::bn::BigNum;
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{self, Cipher};
+use serde::Deserialize;
+use serde_json::Value;
+use std::thread;
+use std::time::Duration;
+
+// Configuration of the renewer, in `[server.renewer.tplink]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address of the router.
+    ip: String,
+    /// "admin" unless specified. Only older firmwares have usernames.
+    username: Option<String>,
+    password: Secret<String>,
+    /// The generation of the firmware, detected unless specified.
+    firmware: Option<Firmware>,
+    /// The form of the web API which disconnects and connects the WAN connection, on encrypted
+    /// firmwares. Defaults to "wan_ipv4_status".
+    wan_form: Option<String>
+}
+
+/// The generations of the firmwares.
+#[derive(Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Firmware {
+    /// Recent firmwares, whose web API (under `/cgi-bin/luci`) is encrypted.
+    Encrypted,
+    /// Older firmwares, which put a token in the URLs (under `/userRpm`).
+    Token
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const DEFAULT_WAN_FORM: &str = "wan_ipv4_status";
+// How long the connection stays down before reconnecting it.
+const RECONNECT_DELAY: Duration = Duration::from_secs (2);
+// The bytes of PKCS #1 v1.5 padding in each RSA block.
+const RSA_PADDING: usize = 11;
+
+// What the login establishes.
+enum Session {
+    Encrypted {
+        stok: Secret<String>,
+        cookies: Option<Secret<String>>,
+        encryption: Encryption
+    },
+    Token {
+        token: Secret<String>,
+        cookie: Secret<String>
+    }
+}
+
+// The keys which encrypt the requests of a session of the encrypted firmwares.
+struct Encryption {
+    // The AES-128-CBC key and IV, as 16 digits each (like the web interface generates them).
+    key: Secret<String>,
+    iv: String,
+    // The RSA key of the router which encrypts the signatures, as hexadecimal modulus and
+    // exponent.
+    signing_key: (String, String),
+    // The sequence number given by the router, to which the length of each request is added.
+    sequence: u64,
+    // The MD5 hash of the credentials, sent in the signatures.
+    hash: Secret<String>
+}
+
+pub struct Renewer {
+    ip: String,
+    username: String,
+    password: Secret<String>,
+    firmware: Option<Firmware>,
+    wan_form: String,
+    session: Option<Session>,
+    reconnect_delay: Duration,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    fn post (&mut self, path: &str, body: String, cookie: Option<&str>)
+        -> Result<Response<String>>
+    {
+        let url = format!("http://{}{}", self.ip, path);
+        let mut request = Request::post (url.as_str())
+            .header (header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .header (header::REFERER, format!("http://{}/", self.ip));
+        if let Some(cookie) = cookie {
+            request = request.header (header::COOKIE, cookie);
+        }
+        let request = request.body (Some (body))
+            .chain_err (|| "failed to build HTTP request object")?;
+        http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", redact_stok (&url)))
+    }
+
+    fn get (&mut self, path: &str, cookie: &str, referer: &str) -> Result<Response<String>> {
+        let url = format!("http://{}{}", self.ip, path);
+        let request = Request::get (url.as_str())
+            .header (header::COOKIE, cookie)
+            .header (header::REFERER, referer)
+            .body (None::<String>)
+            .chain_err (|| "failed to build HTTP request object")?;
+        http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| "HTTP request to the router failed")
+    }
+
+    // Reads one of the RSA keys of the encrypted firmwares (`form` is either "keys" or "auth"),
+    // returning the data of the response.
+    fn read_keys (&mut self, form: &str) -> Result<Value> {
+        let res = self.post (
+            &format!("/cgi-bin/luci/;stok=/login?form={}", form), "operation=read".into(), None
+        )?;
+        ensure!(res.status().is_success(), "failed to read the keys, got status {}", res.status());
+        let response: Value = serde_json::from_str (res.body())
+            .chain_err (|| "the router answered with invalid JSON")?;
+        ensure!(response["success"] == true, "the router refused to give the keys");
+        Ok(response["data"].clone())
+    }
+
+    // Detects the generation of the firmware: only the encrypted ones give the keys.
+    fn firmware (&mut self) -> Result<Firmware> {
+        if let Some(firmware) = self.firmware {
+            return Ok(firmware);
+        }
+        let res = self.post (
+            "/cgi-bin/luci/;stok=/login?form=keys", "operation=read".into(), None
+        )?;
+        let keys = serde_json::from_str::<Value> (res.body()).ok();
+        let firmware = match keys {
+            Some(ref keys) if res.status().is_success() && keys["success"] == true =>
+                Firmware::Encrypted,
+            _ => Firmware::Token
+        };
+        debug!(target: "renewer::tplink", "detected a firmware of type {:?}", firmware);
+        self.firmware = Some (firmware);
+        Ok(firmware)
+    }
+
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::tplink", "trying to login using specified credentials");
+        self.session = None;
+        self.session = Some (match self.firmware()? {
+            Firmware::Encrypted => self.login_encrypted()?,
+            Firmware::Token => self.login_token()?
+        });
+        info!(target: "renewer::tplink", "login OK");
+        Ok(())
+    }
+
+    // The password is encrypted with the first RSA key, and sent in a request encrypted with
+    // random AES keys, which are sent to the router in the signature (encrypted with the second
+    // RSA key).
+    fn login_encrypted (&mut self) -> Result<Session> {
+        let keys = self.read_keys ("keys")?;
+        let password_key = rsa_key (&keys["password"])
+            .chain_err (|| "the router answered without the password key")?;
+        let auth = self.read_keys ("auth")?;
+        let signing_key = rsa_key (&auth["key"])
+            .chain_err (|| "the router answered without the signing key")?;
+        let sequence = auth["seq"].as_u64()
+            .chain_err (|| "the router answered without the sequence number")?;
+        let encryption = Encryption {
+            key: random_digits()?.into(),
+            iv: random_digits()?,
+            signing_key,
+            sequence,
+            hash: format!("{:x}", md5::compute (
+                format!("{}{}", self.username, self.password.expose())
+            )).into()
+        };
+        let password = rsa_encrypt (&password_key, self.password.expose().as_bytes())?;
+        let data = format!("operation=login&password={}", password);
+        let body = encryption.request (&data, true)?;
+        let res = self.post ("/cgi-bin/luci/;stok=/login?form=login", body, None)?;
+        ensure!(res.status().is_success(), "login failed with status {}", res.status());
+        let cookies = res.headers().get_all (header::SET_COOKIE).iter()
+            .filter_map (|cookie| cookie.to_str().ok())
+            .filter_map (|cookie| cookie.split (';').next())
+            .map (str::trim)
+            .collect::<Vec<_>>();
+        let response = encryption.response (res.body())?;
+        if response["success"] != true {
+            bail!("login failed with error '{}', check your credentials!",
+                response["errorcode"].as_str().unwrap_or ("unknown"));
+        }
+        let stok = response["data"]["stok"].as_str()
+            .chain_err (|| "the router answered without a session token")?;
+        Ok(Session::Encrypted {
+            stok: stok.to_string().into(),
+            cookies: if cookies.is_empty() { None } else { Some (cookies.join ("; ").into()) },
+            encryption
+        })
+    }
+
+    // The credentials are sent as a cookie, and the router answers with a redirection to a URL
+    // holding the token.
+    fn login_token (&mut self) -> Result<Session> {
+        let credentials = format!(
+            "{}:{:x}", self.username, md5::compute (self.password.expose().as_bytes())
+        );
+        let cookie = format!(
+            "Authorization=Basic%20{}", base64::encode_block (credentials.as_bytes())
+        );
+        let referer = format!("http://{}/", self.ip);
+        let res = self.get ("/userRpm/LoginRpm.htm?Save=Save", &cookie, &referer)?;
+        ensure!(res.status().is_success(), "login failed with status {}", res.status());
+        // e.g. `window.parent.location.href = "http://192.168.0.1/ABCDEFGHIJKLMNOP/userRpm/..."`
+        let token = res.body().find ("/userRpm/Index.htm")
+            .and_then (|end| res.body()[..end].rsplit ('/').next())
+            .filter (|token| !token.is_empty())
+            .chain_err (|| "login failed, check your credentials!")?;
+        Ok(Session::Token { token: token.to_string().into(), cookie: cookie.into() })
+    }
+
+    // Sends `operation` to the WAN form of an encrypted firmware. Returns `false` if the session
+    // expired.
+    fn wan_operation (&mut self, operation: &str) -> Result<bool> {
+        let (stok, cookies, body) = match self.session {
+            Some(Session::Encrypted { ref stok, ref cookies, ref encryption }) => (
+                stok.expose().clone(),
+                cookies.as_ref().map (|cookies| cookies.expose().clone()),
+                encryption.request (&format!("operation={}", operation), false)?
+            ),
+            _ => unreachable!("only called with a session of an encrypted firmware")
+        };
+        let path = format!("/cgi-bin/luci/;stok={}/admin/network?form={}", stok, self.wan_form);
+        let res = self.post (&path, body, cookies.as_deref())?;
+        if res.status() == http::StatusCode::UNAUTHORIZED
+            || res.status() == http::StatusCode::FORBIDDEN
+        {
+            return Ok(false);
+        }
+        ensure!(res.status().is_success(), "{} failed with status {}", operation, res.status());
+        let response = match self.session {
+            Some(Session::Encrypted { ref encryption, .. }) => encryption.response (res.body())?,
+            _ => unreachable!("only called with a session of an encrypted firmware")
+        };
+        match response["errorcode"].as_str() {
+            _ if response["success"] == true => Ok(true),
+            Some("timeout") | Some("user conflict") => Ok(false),
+            error => bail!("{} failed with error '{}'", operation, error.unwrap_or ("unknown"))
+        }
+    }
+
+    // Loads a page of a token firmware with `query`. Returns `false` if the session expired.
+    fn status_page (&mut self, query: &str) -> Result<bool> {
+        let (token, cookie) = match self.session {
+            Some(Session::Token { ref token, ref cookie }) =>
+                (token.expose().clone(), cookie.expose().clone()),
+            _ => unreachable!("only called with a session of a token firmware")
+        };
+        // The router refuses the requests coming from other pages.
+        let referer = format!("http://{}/{}/userRpm/StatusRpm.htm", self.ip, token);
+        let path = format!("/{}/userRpm/StatusRpm.htm?{}", token, query);
+        let res = self.get (&path, &cookie, &referer)?;
+        // Expired tokens are redirected to the login page.
+        if res.status() == http::StatusCode::UNAUTHORIZED || res.body().contains ("LoginRpm.htm") {
+            return Ok(false);
+        }
+        ensure!(res.status().is_success(), "{} failed with status {}", query, res.status());
+        Ok(true)
+    }
+
+    // Disconnects and connects the WAN connection. Returns `false` if the session expired.
+    fn reconnect (&mut self) -> Result<bool> {
+        let encrypted = match self.session {
+            Some(Session::Encrypted { .. }) => true,
+            Some(Session::Token { .. }) => false,
+            None => return Ok(false)
+        };
+        let step = |renewer: &mut Self, connect: bool| match (encrypted, connect) {
+            (true, false) => renewer.wan_operation ("disconnect"),
+            (true, true) => renewer.wan_operation ("connect"),
+            (false, false) => renewer.status_page ("Disconnect=Disconnect&wan=1"),
+            (false, true) => renewer.status_page ("Connect=Connect&wan=1")
+        };
+        if !step (self, false)? {
+            return Ok(false);
+        }
+        thread::sleep (self.reconnect_delay);
+        ensure!(step (self, true)?, "the session expired while reconnecting");
+        Ok(true)
+    }
+}
+
+impl Encryption {
+    // The body of a request sending `data`: the data is encrypted with AES, and the signature
+    // (along with the AES keys when logging in) with RSA.
+    fn request (&self, data: &str, login: bool) -> Result<String> {
+        let data = symm::encrypt (
+            Cipher::aes_128_cbc(), self.key.expose().as_bytes(), Some (self.iv.as_bytes()),
+            data.as_bytes()
+        ).chain_err (|| "failed to encrypt the request")?;
+        let data = base64::encode_block (&data);
+        let sequence = self.sequence + data.len() as u64;
+        let signature = if login {
+            format!(
+                "k={}&i={}&h={}&s={}", self.key.expose(), self.iv, self.hash.expose(), sequence
+            )
+        } else {
+            format!("h={}&s={}", self.hash.expose(), sequence)
+        };
+        let signature = rsa_encrypt (&self.signing_key, signature.as_bytes())?;
+        Ok(format!("sign={}&data={}", signature, encode (&data)))
+    }
+
+    // Decrypts the response to a request, e.g. `{"data":"<AES encrypted JSON>"}`.
+    fn response (&self, body: &str) -> Result<Value> {
+        let response: Value = serde_json::from_str (body)
+            .chain_err (|| "the router answered with invalid JSON")?;
+        let data = response["data"].as_str()
+            .chain_err (|| "the router answered without encrypted data")?;
+        let data = base64::decode_block (data)
+            .chain_err (|| "the router answered with invalid base64")?;
+        let data = symm::decrypt (
+            Cipher::aes_128_cbc(), self.key.expose().as_bytes(), Some (self.iv.as_bytes()), &data
+        ).chain_err (|| "failed to decrypt the response")?;
+        serde_json::from_slice (&data).chain_err (|| "the router answered with invalid JSON")
+    }
+}
+
+// The modulus and the exponent of an RSA key, e.g. `["D1E79FF1...", "010001"]`.
+fn rsa_key (key: &Value) -> Option<(String, String)> {
+    Some ((key[0].as_str()?.to_string(), key[1].as_str()?.to_string()))
+}
+
+// Encrypts `data` with PKCS #1 v1.5 padding, splitting it in blocks as long as the key allows,
+// and returns the hexadecimal digits of the blocks.
+fn rsa_encrypt ((modulus, exponent): &(String, String), data: &[u8]) -> Result<String> {
+    let key = BigNum::from_hex_str (modulus)
+        .and_then (|n| Ok((n, BigNum::from_hex_str (exponent)?)))
+        .and_then (|(n, e)| Rsa::from_public_components (n, e))
+        .chain_err (|| "the router answered with an invalid RSA key")?;
+    let size = key.size() as usize;
+    ensure!(size > RSA_PADDING, "the RSA key of the router is too short");
+    let mut encrypted = String::new();
+    let mut block = vec![0; size];
+    for chunk in data.chunks (size - RSA_PADDING) {
+        let length = key.public_encrypt (chunk, &mut block, Padding::PKCS1)
+            .chain_err (|| "failed to encrypt with the RSA key of the router")?;
+        encrypted.extend (block[..length].iter().map (|byte| format!("{:02x}", byte)));
+    }
+    Ok(encrypted)
+}
+
+// 16 random digits, as the AES keys generated by the web interface.
+fn random_digits() -> Result<String> {
+    let mut bytes = [0; 16];
+    openssl::rand::rand_bytes (&mut bytes).chain_err (|| "failed to generate random keys")?;
+    Ok(bytes.iter().map (|byte| char::from (b'0' + byte % 10)).collect())
+}
+
+// Encodes the characters of base64 which aren't allowed in forms.
+fn encode (value: &str) -> String {
+    value.replace ('+', "%2B").replace ('/', "%2F").replace ('=', "%3D")
+}
+
+// Hides the session token in URLs which end up in errors.
+fn redact_stok (url: &str) -> String {
+    match (url.find (";stok="), url.find ("/admin/")) {
+        (Some(start), Some(end)) if end > start + 6 =>
+            format!("{}***{}", &url[..start + 6], &url[end..]),
+        _ => url.to_string()
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        Ok(Renewer {
+            ip: http_client::uri_host (&config.ip).into_owned(),
+            username: config.username.unwrap_or_else (|| "admin".into()),
+            password: config.password,
+            firmware: config.firmware,
+            wan_form: config.wan_form.unwrap_or_else (|| DEFAULT_WAN_FORM.into()),
+            session: None,
+            reconnect_delay: RECONNECT_DELAY,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        if self.session.is_none() {
+            self.login()?;
+        }
+        if !self.reconnect()? {
+            debug!(target: "renewer::tplink", "session expired, logging in again");
+            self.login()?;
+            ensure!(self.reconnect()?, "the router rejected the new session");
+        }
+        info!(target: "renewer::tplink", "successfully reconnected the WAN connection");
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn renewer (transport: &MockTransport, firmware: Option<Firmware>) -> Renewer {
+        Renewer {
+            ip: "192.168.0.1".into(),
+            username: "admin".into(),
+            password: "hunter2".into(),
+            firmware,
+            wan_form: DEFAULT_WAN_FORM.into(),
+            session: None,
+            reconnect_delay: Duration::from_millis (0),
+            transport: Box::new (transport.clone())
+        }
+    }
+
+    #[test]
+    fn requests_are_encrypted_and_signed() {
+        let key = Rsa::generate (512).unwrap();
+        let signing_key = (
+            key.n().to_hex_str().unwrap().to_string(), key.e().to_hex_str().unwrap().to_string()
+        );
+        let encryption = Encryption {
+            key: "0123456789012345".into(),
+            iv: "5432109876543210".into(),
+            signing_key,
+            sequence: 1000,
+            hash: "0123456789abcdef0123456789abcdef".into()
+        };
+        let body = encryption.request ("operation=login&password=x", true).unwrap();
+        let (sign, data) = body.strip_prefix ("sign=").unwrap().split_once ("&data=").unwrap();
+        // The signature is too long for one block, and is split in two.
+        assert_eq!(sign.len(), 2 * 2 * 64);
+        let mut signature = Vec::new();
+        for block in sign.as_bytes().chunks (128) {
+            let block = BigNum::from_hex_str (std::str::from_utf8 (block).unwrap()).unwrap();
+            let mut decrypted = vec![0; 64];
+            let block = block.to_vec_padded (64).unwrap();
+            let length = key.private_decrypt (&block, &mut decrypted, Padding::PKCS1).unwrap();
+            signature.extend_from_slice (&decrypted[..length]);
+        }
+        let data = data.replace ("%2B", "+").replace ("%2F", "/").replace ("%3D", "=");
+        assert_eq!(String::from_utf8 (signature).unwrap(), format!(
+            "k=0123456789012345&i=5432109876543210&h=0123456789abcdef0123456789abcdef&s={}",
+            1000 + data.len()
+        ));
+        let decrypted = symm::decrypt (
+            Cipher::aes_128_cbc(), b"0123456789012345", Some (b"5432109876543210"),
+            &base64::decode_block (&data).unwrap()
+        ).unwrap();
+        assert_eq!(decrypted, b"operation=login&password=x");
+        // Responses are encrypted with the same keys.
+        let response = symm::encrypt (
+            Cipher::aes_128_cbc(), b"0123456789012345", Some (b"5432109876543210"),
+            br#"{"success":true,"data":{"stok":"abc"}}"#
+        ).unwrap();
+        let response = format!("{{\"data\":\"{}\"}}", base64::encode_block (&response));
+        assert_eq!(encryption.response (&response).unwrap()["data"]["stok"], "abc");
+    }
+
+    #[test]
+    fn token_firmwares_reconnect() {
+        let transport = MockTransport::new();
+        // Firmwares without keys are detected as token firmwares.
+        transport
+            .push_response ("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<script>window.parent.location.href = \
+                \"http://192.168.0.1/ABCDEFGHIJKLMNOP/userRpm/Index.htm\";</script>")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<script>top.location = \
+                \"/userRpm/LoginRpm.htm\";</script>")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<script>window.parent.location.href = \
+                \"http://192.168.0.1/PONMLKJIHGFEDCBA/userRpm/Index.htm\";</script>")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<html>status</html>")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n<html>status</html>");
+        let mut renewer = renewer (&transport, None);
+        renewer.init().unwrap();
+        // The renewal finds the token expired, and logs in again.
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        assert!(requests[0].starts_with ("POST /cgi-bin/luci/;stok=/login?form=keys HTTP/1.1"));
+        // base64 ("admin:" + md5 ("hunter2"))
+        assert!(requests[1].starts_with ("GET /userRpm/LoginRpm.htm?Save=Save HTTP/1.1"));
+        assert!(requests[1].contains (
+            "cookie: Authorization=Basic%20YWRtaW46MmFiOTYzOTBjN2RiZTM0MzlkZTc0ZDBjOWIwYjE3Njc=\r\n"
+        ));
+        assert!(requests[2].starts_with (
+            "GET /ABCDEFGHIJKLMNOP/userRpm/StatusRpm.htm?Disconnect=Disconnect&wan=1 HTTP/1.1"
+        ));
+        assert!(requests[2].contains (
+            "referer: http://192.168.0.1/ABCDEFGHIJKLMNOP/userRpm/StatusRpm.htm\r\n"
+        ));
+        assert!(requests[5].starts_with (
+            "GET /PONMLKJIHGFEDCBA/userRpm/StatusRpm.htm?Connect=Connect&wan=1 HTTP/1.1"
+        ));
+    }
+}
@@ -0,0 +1,322 @@
+//! Renews the IP address of AVM FRITZ!Box routers through TR-064, the SOAP interface which AVM
+//! documents and keeps stable across FritzOS versions (unlike the web interface used by the
+//! `fritzbox` renewer). It must be enabled in "Home Network > Network > Network Settings > Allow
+//! access for applications", and the user must have the "FRITZ!Box Settings" right.
+
+use super::{igd, LinkInfo, RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Transport};
+use http::Response;
+use serde::Deserialize;
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+
+// Configuration of the renewer, in `[server.renewer.tr064]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address (or hostname) of the router, optionally followed by the port of TR-064
+    /// (49000 unless specified, e.g. "fritz.box:49000").
+    ip: String,
+    /// Required by recent FritzOS versions, which no longer accept logins without a username.
+    username: Option<String>,
+    password: Secret<String>
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const PORT: u16 = 49000;
+// The services which renew the address, depending on whether the router uses PPP or not.
+const CONNECTIONS: &[&str] = &["WANPPPConnection", "WANIPConnection"];
+// The fault reported by some firmwares when `ForceTermination` succeeds, as the connection is
+// still being torn down when answering.
+const DISCONNECT_IN_PROGRESS: u32 = 707;
+
+pub struct Renewer {
+    // The host and the port of TR-064, as they appear in URLs.
+    authority: String,
+    username: String,
+    password: Secret<String>,
+    // The type and the control URL of the connection service, found by `init`.
+    connection: Option<(String, String)>,
+    challenge: Option<Challenge>,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    // Finds the service of the connection in the description of the device: the first one which
+    // is connected, or the first one listed if none is.
+    fn find_connection (&mut self) -> Result<(String, String)> {
+        let description = format!("http://{}/tr64desc.xml", self.authority);
+        let res = http_client::get_with (&mut *self.transport, &description)
+            .chain_err (|| format!("HTTP request to '{}' failed", description))?;
+        ensure!(
+            res.status().is_success(),
+            "failed to request the TR-064 description, got status {} (is TR-064 enabled?)",
+            res.status()
+        );
+        let services = igd::services (res.body(), &format!("http://{}", self.authority));
+        let connections: Vec<_> = CONNECTIONS.iter()
+            .flat_map (|name| services.iter().filter (move |(s, _)| s.contains (name)))
+            .cloned()
+            .collect();
+        ensure!(!connections.is_empty(), "the router doesn't expose any WAN connection service");
+        for (service, url) in &connections {
+            let res = self.call (service, url, "GetStatusInfo")?;
+            let status = ok_or_fault ("GetStatusInfo", &res)?;
+            if igd::tag (status, "NewConnectionStatus") == Some ("Connected") {
+                return Ok((service.clone(), url.clone()));
+            }
+        }
+        Ok(connections[0].clone())
+    }
+
+    // Calls the SOAP `action` (which has no arguments) of `service`, authenticating with HTTP
+    // digest authentication when the router asks for it.
+    fn call (&mut self, service: &str, url: &str, action: &str) -> Result<Response<String>> {
+        let path = url.parse::<http::Uri>()
+            .ok()
+            .and_then (|uri| uri.path_and_query().map (|path| path.to_string()))
+            .chain_err (|| format!("invalid control URL '{}'", url))?;
+        for attempt in 0..2 {
+            let mut request = igd::soap_request (url, service, action);
+            if let Some(ref mut challenge) = self.challenge {
+                let authorization = challenge.authorization (
+                    &self.username, self.password.expose(), "POST", &path
+                );
+                request = request.header (header::AUTHORIZATION, authorization);
+            }
+            let request = request.body (Some (igd::envelope (service, action)))
+                .chain_err (|| "failed to build HTTP request object")?;
+            let res = http_client::make_request_with (&mut *self.transport, request)
+                .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+            if res.status() != http::StatusCode::UNAUTHORIZED {
+                return Ok(res);
+            }
+            // Either the first request, or the nonce expired: answer the new challenge once.
+            ensure!(attempt == 0, "the router rejected the credentials");
+            let challenge = res.headers().get (header::WWW_AUTHENTICATE)
+                .and_then (|challenge| challenge.to_str().ok())
+                .and_then (Challenge::parse)
+                .chain_err (|| "the router asked for an unsupported authentication")?;
+            self.challenge = Some (challenge);
+        }
+        unreachable!("the second attempt always returns")
+    }
+
+    fn connection (&mut self) -> Result<(String, String)> {
+        if self.connection.is_none() {
+            self.connection = Some (self.find_connection()?);
+        }
+        Ok(self.connection.clone().expect ("the connection has just been found"))
+    }
+}
+
+// The body of a successful response, or the UPnP fault it reports.
+fn ok_or_fault<'a> (action: &str, res: &'a Response<String>) -> Result<&'a str> {
+    if res.status().is_success() {
+        return Ok(res.body());
+    }
+    match fault (res.body()) {
+        Some((code, description)) =>
+            bail!("{} failed with UPnP error {} ({})", action, code, description),
+        None => bail!("{} failed with status {}", action, res.status())
+    }
+}
+
+// The code and the description of the UPnP fault in `body`, if any.
+fn fault (body: &str) -> Option<(u32, &str)> {
+    let code = igd::tag (body, "errorCode")?.parse().ok()?;
+    Some ((code, igd::tag (body, "errorDescription").unwrap_or ("no description")))
+}
+
+// A challenge of HTTP digest authentication (RFC 7616), with the MD5 algorithm.
+struct Challenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    // Whether the "auth" quality of protection is supported, along with how many times the
+    // nonce has been used.
+    qop_auth: bool,
+    count: u32
+}
+
+impl Challenge {
+    // Parses a `WWW-Authenticate` header, e.g. `Digest realm="F!Box SOAP-Auth",
+    // nonce="2B1A0C3D", algorithm=MD5, qop="auth"`.
+    fn parse (header: &str) -> Option<Challenge> {
+        let (scheme, params) = header.trim().split_once (' ')?;
+        if !scheme.eq_ignore_ascii_case ("Digest") {
+            return None;
+        }
+        let mut challenge = Challenge {
+            realm: String::new(),
+            nonce: String::new(),
+            opaque: None,
+            qop_auth: false,
+            count: 0
+        };
+        let mut rest = params.trim();
+        while !rest.is_empty() {
+            let (name, after) = rest.split_once ('=')?;
+            // Values are either quoted strings or tokens.
+            let (value, after) = match after.trim_start().strip_prefix ('"') {
+                Some(quoted) => {
+                    let end = quoted.find ('"')?;
+                    (&quoted[..end], &quoted[end + 1..])
+                },
+                None => after.split_once (',').unwrap_or ((after, ""))
+            };
+            match name.trim().to_ascii_lowercase().as_str() {
+                "realm" => challenge.realm = value.to_string(),
+                "nonce" => challenge.nonce = value.to_string(),
+                "opaque" => challenge.opaque = Some (value.to_string()),
+                "qop" => challenge.qop_auth = value.split (',').any (|qop| qop.trim() == "auth"),
+                "algorithm" if !value.trim().eq_ignore_ascii_case ("MD5") => return None,
+                _ => ()
+            }
+            rest = after.trim_start_matches (|c: char| c == ',' || c.is_whitespace());
+        }
+        if challenge.nonce.is_empty() {
+            return None;
+        }
+        Some (challenge)
+    }
+
+    // The `Authorization` header answering the challenge for a request.
+    fn authorization (&mut self, username: &str, password: &str, method: &str, uri: &str)
+        -> String
+    {
+        self.count += 1;
+        let cnonce = format!("{:016x}", RandomState::new().hash_one (self.count));
+        self.answer (username, password, method, uri, &cnonce)
+    }
+
+    fn answer (&self, username: &str, password: &str, method: &str, uri: &str, cnonce: &str)
+        -> String
+    {
+        let hash = |value: String| format!("{:x}", md5::compute (value));
+        let ha1 = hash (format!("{}:{}:{}", username, self.realm, password));
+        let ha2 = hash (format!("{}:{}", method, uri));
+        let mut header = format!(
+            "Digest username=\"{}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", algorithm=MD5",
+            username, self.realm, self.nonce, uri
+        );
+        let response = if self.qop_auth {
+            let count = format!("{:08x}", self.count);
+            header += &format!(", qop=auth, nc={}, cnonce=\"{}\"", count, cnonce);
+            hash (format!("{}:{}:{}:{}:auth:{}", ha1, self.nonce, count, cnonce, ha2))
+        } else {
+            hash (format!("{}:{}:{}", ha1, self.nonce, ha2))
+        };
+        header += &format!(", response=\"{}\"", response);
+        if let Some(ref opaque) = self.opaque {
+            header += &format!(", opaque=\"{}\"", opaque);
+        }
+        header
+    }
+}
+
+// Splits "host:port" into the authority of the URLs, using the default port if it's missing.
+fn authority (ip: &str) -> String {
+    match ip.rsplit_once (':') {
+        Some((host, port)) if !host.contains (':') && port.parse::<u16>().is_ok() =>
+            format!("{}:{}", host, port),
+        _ => format!("{}:{}", http_client::uri_host (ip), PORT)
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        Ok(Renewer {
+            authority: authority (&config.ip),
+            username: config.username.unwrap_or_default(),
+            password: config.password,
+            connection: None,
+            challenge: None,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        // Asking for the status of the connections also checks the credentials.
+        let (service, _) = self.connection()?;
+        info!(target: "renewer::tr064", "renewing the address through {}", service);
+        Ok(())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let (service, url) = self.connection()?;
+        let res = self.call (&service, &url, "ForceTermination")?;
+        match fault (res.body()) {
+            Some((DISCONNECT_IN_PROGRESS, _)) =>
+                debug!(target: "renewer::tr064", "the router is disconnecting"),
+            _ => {
+                ok_or_fault ("ForceTermination", &res)?;
+            }
+        }
+        // Ask to connect again too to speed things up, ignoring errors (the router reconnects on
+        // its own anyway).
+        match self.call (&service, &url, "RequestConnection") {
+            Ok(ref res) => if let Err(error) = ok_or_fault ("RequestConnection", res) {
+                debug!(target: "renewer::tr064", "{}", error);
+            },
+            Err(error) => debug!(target: "renewer::tr064", "{}", error)
+        }
+        info!(target: "renewer::tr064", "successfully asked for another IP");
+        Ok(RenewOutcome::default())
+    }
+
+    fn link_info (&mut self) -> Result<Option<LinkInfo>> {
+        // The UPnP description lives on the same port as TR-064.
+        let description = format!("http://{}/igddesc.xml", self.authority);
+        igd::link_info (&mut *self.transport, &description).map (Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn digest_answers_the_challenge() {
+        // The example of RFC 2617.
+        let mut challenge = Challenge::parse (
+            "Digest realm=\"testrealm@host.com\", qop=\"auth,auth-int\", \
+            nonce=\"dcd98b7102dd2f0e8b11d0f600bfb0c093\", \
+            opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""
+        ).unwrap();
+        challenge.count = 1;
+        let answer = challenge.answer (
+            "Mufasa", "Circle Of Life", "GET", "/dir/index.html", "0a4f113b"
+        );
+        assert!(answer.contains ("nc=00000001, cnonce=\"0a4f113b\""));
+        assert!(answer.contains ("response=\"6629fae49393a05397450978507c4ef1\""));
+        assert!(answer.ends_with ("opaque=\"5ccc069c403ebaf9f0171e9517f40e41\""));
+
+        assert!(Challenge::parse ("Basic realm=\"router\"").is_none());
+        assert!(Challenge::parse ("Digest realm=\"r\", nonce=\"n\", algorithm=SHA-256").is_none());
+        let unquoted = Challenge::parse ("Digest nonce=abc, realm=\"F!Box SOAP-Auth\"").unwrap();
+        assert_eq!((unquoted.nonce.as_str(), unquoted.realm.as_str()), ("abc", "F!Box SOAP-Auth"));
+        assert!(!unquoted.qop_auth);
+    }
+
+    #[test]
+    fn ports_default_to_tr064() {
+        assert_eq!(authority ("fritz.box"), "fritz.box:49000");
+        assert_eq!(authority ("192.168.178.1:49443"), "192.168.178.1:49443");
+        assert_eq!(authority ("fd00::1"), "[fd00::1]:49000");
+    }
+}
@@ -0,0 +1,281 @@
+//! Renews the IP address of the Windows machine running the server, for those who get a public
+//! address directly on it: by releasing and renewing the DHCP lease of an adapter with `ipconfig`,
+//! or by hanging up and dialing again a RAS connection (e.g. PPPoE) through the RAS API.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, parse_duration};
+use serde::Deserialize;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{mem, ptr};
+
+// Configuration of the renewer, in `[server.renewer.windows-local]`. Exactly one of `adapter` and
+// `connection` must be set.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// The adapter whose DHCP lease is renewed, as listed by `ipconfig`, e.g. "Ethernet".
+    adapter: Option<String>,
+    /// The RAS connection which is dialed again, as listed in "Network Connections", e.g.
+    /// "Broadband Connection". Its credentials must be saved.
+    connection: Option<String>,
+    /// How long the connection can take to hang up, e.g. "1m". Defaults to 30 seconds.
+    timeout: Option<String>
+}
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs (30);
+// How often the connection is checked while waiting for it to hang up.
+const POLL_INTERVAL: Duration = Duration::from_millis (250);
+
+// What is renewed.
+enum Target {
+    Adapter(String),
+    Connection(String)
+}
+
+pub struct Renewer {
+    target: Target,
+    timeout: Duration
+}
+
+// Runs `ipconfig` with `args`, failing unless it exits with status 0.
+fn ipconfig (args: &[&str]) -> Result<()> {
+    let output = Command::new ("ipconfig")
+        .args (args)
+        .stdin (Stdio::null())
+        .output()
+        .chain_err (|| "failed to run 'ipconfig'")?;
+    ensure!(
+        output.status.success(),
+        "'ipconfig {}' failed with {}: {}",
+        args[0], output.status, String::from_utf8_lossy (&output.stdout).trim()
+    );
+    Ok(())
+}
+
+impl Renewer {
+    // Hangs up `connection` if it's up, waiting for it to go down, and dials it again with its
+    // saved credentials.
+    fn redial (&self, connection: &str) -> Result<()> {
+        if let Some(handle) = ras::find (connection)? {
+            ras::hang_up (handle)?;
+            info!(target: "renewer::windows-local", "hung up '{}'", connection);
+            let started = Instant::now();
+            while ras::find (connection)?.is_some() {
+                ensure!(
+                    started.elapsed() < self.timeout,
+                    "'{}' didn't hang up within {}",
+                    connection, config::format_duration (self.timeout)
+                );
+                thread::sleep (POLL_INTERVAL);
+            }
+        }
+        ras::dial (connection)?;
+        info!(target: "renewer::windows-local", "dialed '{}' again", connection);
+        Ok(())
+    }
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse()
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        let path = renewer.config.path();
+        let target = match (config.adapter, config.connection) {
+            (Some(adapter), None) => Target::Adapter (adapter),
+            (None, Some(connection)) => Target::Connection (connection),
+            _ => bail!("exactly one of '{0}.adapter' and '{0}.connection' must be set", path)
+        };
+        let timeout = match config.timeout {
+            Some(timeout) => parse_duration (&timeout)
+                .chain_err (|| format!("invalid option '{}.timeout'", path))?,
+            None => DEFAULT_TIMEOUT
+        };
+        Ok(Renewer { target, timeout })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, &[])
+    }
+
+    // Fails early if the connection doesn't exist, or its credentials aren't saved.
+    fn init (&mut self) -> Result<()> {
+        if let Target::Connection(ref connection) = self.target {
+            ras::dial_params (connection)?;
+        }
+        Ok(())
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        match self.target {
+            Target::Adapter(ref adapter) => {
+                ipconfig (&["/release", adapter])?;
+                ipconfig (&["/renew", adapter])?;
+                info!(target: "renewer::windows-local", "renewed the lease of '{}'", adapter);
+            },
+            Target::Connection(ref connection) => self.redial (connection)?
+        }
+        Ok(RenewOutcome::default())
+    }
+}
+
+// The few functions of the RAS API (`rasapi32.dll`) which are needed, along with their structures
+// as laid out since Windows 7. `winapi` doesn't bind them.
+mod ras {
+    use super::{mem, ptr, Result, ResultExt};
+    use winapi::shared::basetsd::ULONG_PTR;
+    use winapi::shared::guiddef::GUID;
+    use winapi::shared::minwindef::{BOOL, DWORD};
+    use winapi::um::winnt::{HANDLE, LPCWSTR, LUID, WCHAR};
+
+    const RAS_MAX_ENTRY_NAME: usize = 256;
+    const RAS_MAX_DEVICE_TYPE: usize = 16;
+    const RAS_MAX_DEVICE_NAME: usize = 128;
+    const RAS_MAX_PHONE_NUMBER: usize = 128;
+    const RAS_MAX_CALLBACK_NUMBER: usize = RAS_MAX_PHONE_NUMBER;
+    const MAX_PATH: usize = 260;
+    const UNLEN: usize = 256;
+    const PWLEN: usize = 256;
+    const DNLEN: usize = 15;
+    const ERROR_BUFFER_TOO_SMALL: DWORD = 603;
+
+    pub(super) type Handle = HANDLE;
+
+    // Only the RAS API reads or writes most of the fields.
+    #[allow(dead_code)]
+    #[repr(C)]
+    struct RasConn {
+        size: DWORD,
+        handle: Handle,
+        entry_name: [WCHAR; RAS_MAX_ENTRY_NAME + 1],
+        device_type: [WCHAR; RAS_MAX_DEVICE_TYPE + 1],
+        device_name: [WCHAR; RAS_MAX_DEVICE_NAME + 1],
+        phonebook: [WCHAR; MAX_PATH],
+        sub_entry: DWORD,
+        guid_entry: GUID,
+        flags: DWORD,
+        luid: LUID,
+        guid_correlation_id: GUID
+    }
+
+    #[allow(dead_code)]
+    #[repr(C)]
+    pub(super) struct DialParams {
+        size: DWORD,
+        entry_name: [WCHAR; RAS_MAX_ENTRY_NAME + 1],
+        phone_number: [WCHAR; RAS_MAX_PHONE_NUMBER + 1],
+        callback_number: [WCHAR; RAS_MAX_CALLBACK_NUMBER + 1],
+        user_name: [WCHAR; UNLEN + 1],
+        password: [WCHAR; PWLEN + 1],
+        domain: [WCHAR; DNLEN + 1],
+        sub_entry: DWORD,
+        callback_id: ULONG_PTR,
+        if_index: DWORD
+    }
+
+    #[link(name = "rasapi32")]
+    extern "system" {
+        fn RasEnumConnectionsW (connections: *mut RasConn, size: *mut DWORD, count: *mut DWORD)
+            -> DWORD;
+        fn RasHangUpW (handle: Handle) -> DWORD;
+        fn RasGetEntryDialParamsW (
+            phonebook: LPCWSTR, params: *mut DialParams, password_saved: *mut BOOL
+        ) -> DWORD;
+        fn RasDialW (
+            extensions: *mut u8, phonebook: LPCWSTR, params: *mut DialParams,
+            notifier_type: DWORD, notifier: *mut u8, handle: *mut Handle
+        ) -> DWORD;
+    }
+
+    // Copies `value` in a nul-terminated buffer, truncating it if needed.
+    fn to_wide<const N: usize> (value: &str, buffer: &mut [WCHAR; N]) {
+        for (slot, unit) in buffer.iter_mut().take (N - 1).zip (value.encode_utf16()) {
+            *slot = unit;
+        }
+    }
+
+    fn from_wide (value: &[WCHAR]) -> String {
+        let end = value.iter().position (|unit| *unit == 0).unwrap_or (value.len());
+        String::from_utf16_lossy (&value[..end])
+    }
+
+    fn check (function: &str, code: DWORD) -> Result<()> {
+        ensure!(code == 0, "{} failed with error {}", function, code);
+        Ok(())
+    }
+
+    /// The handle of `entry`, if it's connected.
+    pub(super) fn find (entry: &str) -> Result<Option<Handle>> {
+        let mut capacity = 1;
+        loop {
+            // SAFETY: `RasConn` is made of integers, arrays of them and a pointer, for which zero
+            // is valid.
+            let mut connections = (0..capacity)
+                .map (|_| unsafe { mem::zeroed::<RasConn>() })
+                .collect::<Vec<_>>();
+            // The size of the first connection tells the version of the structure.
+            connections[0].size = mem::size_of::<RasConn>() as DWORD;
+            let mut size = (capacity * mem::size_of::<RasConn>()) as DWORD;
+            let mut count = 0;
+            // SAFETY: the buffer holds `size` bytes.
+            let code = unsafe {
+                RasEnumConnectionsW (connections.as_mut_ptr(), &mut size, &mut count)
+            };
+            if code == ERROR_BUFFER_TOO_SMALL {
+                capacity = size as usize / mem::size_of::<RasConn>() + 1;
+                continue;
+            }
+            check ("RasEnumConnectionsW", code)?;
+            connections.truncate (count as usize);
+            return Ok(connections.iter()
+                .find (|connection| {
+                    from_wide (&connection.entry_name).eq_ignore_ascii_case (entry)
+                })
+                .map (|connection| connection.handle));
+        }
+    }
+
+    pub(super) fn hang_up (handle: Handle) -> Result<()> {
+        // SAFETY: `handle` was returned by `RasEnumConnectionsW`.
+        check ("RasHangUpW", unsafe { RasHangUpW (handle) })
+    }
+
+    /// The saved credentials of `entry`, in the default phonebook.
+    pub(super) fn dial_params (entry: &str) -> Result<Box<DialParams>> {
+        // SAFETY: `DialParams` is made of integers and arrays of them, for which zero is valid.
+        let mut params: Box<DialParams> = Box::new (unsafe { mem::zeroed() });
+        params.size = mem::size_of::<DialParams>() as DWORD;
+        to_wide (entry, &mut params.entry_name);
+        let mut password_saved = 0;
+        // SAFETY: `params` is initialized and its size is set.
+        let code = unsafe {
+            RasGetEntryDialParamsW (ptr::null(), &mut *params, &mut password_saved)
+        };
+        check ("RasGetEntryDialParamsW", code)
+            .chain_err (|| format!("can't read the connection '{}'", entry))?;
+        ensure!(password_saved != 0, "the password of the connection '{}' isn't saved", entry);
+        Ok(params)
+    }
+
+    /// Dials `entry` with its saved credentials, waiting for it to connect.
+    pub(super) fn dial (entry: &str) -> Result<()> {
+        let mut params = dial_params (entry)?;
+        let mut handle = ptr::null_mut();
+        // SAFETY: without a notifier, `RasDialW` returns once the connection is established or
+        // failed, and doesn't keep `params` around.
+        let code = unsafe {
+            RasDialW (ptr::null_mut(), ptr::null(), &mut *params, 0, ptr::null_mut(), &mut handle)
+        };
+        if code != 0 && !handle.is_null() {
+            // SAFETY: the handle of a failed dial must be released by hanging it up.
+            unsafe { RasHangUpW (handle) };
+        }
+        check ("RasDialW", code).chain_err (|| format!("failed to dial '{}'", entry))
+    }
+}
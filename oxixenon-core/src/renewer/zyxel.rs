@@ -0,0 +1,395 @@
+//! Renews the IP address of ZyXEL VMG routers by restarting their WAN interface through the JSON
+//! web API (whose requests are encrypted with AES, the key being sent with RSA when logging in),
+//! or of Keenetic routers (originally ZyXEL Keenetic) through their RCI API at `/rci/`.
+
+use super::{RenewOutcome, Renewer as RenewerTrait, Result, ResultExt};
+use crate::config::{self, Secret};
+use crate::http_client::{self, header, Request, Transport};
+use http::Response;
+use openssl::base64;
+use openssl::rsa::{Padding, Rsa};
+use openssl::symm::{self, Cipher};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+// Configuration of the renewer, in `[server.renewer.zyxel]`.
+#[derive(Deserialize)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+pub(super) struct Config {
+    /// IP address of the router.
+    ip: String,
+    /// "admin" unless specified.
+    username: Option<String>,
+    password: Secret<String>,
+    /// The API of the router, "vmg" unless specified.
+    #[serde(default)]
+    flavor: Flavor,
+    /// The WAN interface to restart: its name in "Network Setting > Broadband" on VMG routers
+    /// (the first enabled one unless specified), its RCI name on Keenetic routers ("PPPoE0"
+    /// unless specified).
+    interface: Option<String>
+}
+
+/// The APIs of the routers.
+#[derive(Deserialize, Default, Clone, Copy, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "config-schema", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Flavor {
+    /// The JSON API of ZyXEL VMG routers (`/UserLogin`, `/cgi-bin/DAL`).
+    #[default]
+    Vmg,
+    /// The RCI API of Keenetic routers (`/auth`, `/rci/`).
+    Keenetic
+}
+
+// Options which can also be read from a file or an environment variable.
+const SECRETS: &[&str] = &["password"];
+
+const DEFAULT_KEENETIC_INTERFACE: &str = "PPPoE0";
+// The result of successful requests to VMG routers.
+const VMG_SUCCESS: &str = "ZCFG_SUCCESS";
+
+// What the login establishes.
+enum Session {
+    Vmg {
+        // The AES-256-CBC key of the requests, sent to the router when logging in.
+        key: Secret<Vec<u8>>,
+        csrf_token: Secret<String>,
+        cookies: Option<Secret<String>>
+    },
+    Keenetic {
+        cookies: Option<Secret<String>>
+    }
+}
+
+pub struct Renewer {
+    ip: String,
+    username: String,
+    password: Secret<String>,
+    flavor: Flavor,
+    interface: Option<String>,
+    session: Option<Session>,
+    transport: Box<dyn Transport>
+}
+
+impl Renewer {
+    fn request (&mut self, method: http::Method, path: &str, body: Option<&Value>)
+        -> Result<Response<String>>
+    {
+        let url = format!("http://{}{}", self.ip, path);
+        let mut request = Request::builder().method (method).uri (url.as_str());
+        match self.session {
+            Some(Session::Vmg { ref csrf_token, ref cookies, .. }) => {
+                request = request.header ("CSRFToken", csrf_token.expose().as_str());
+                if let Some(ref cookies) = cookies {
+                    request = request.header (header::COOKIE, cookies.expose().as_str());
+                }
+            },
+            Some(Session::Keenetic { cookies: Some(ref cookies) }) =>
+                request = request.header (header::COOKIE, cookies.expose().as_str()),
+            _ => ()
+        }
+        if body.is_some() {
+            request = request.header (header::CONTENT_TYPE, "application/json");
+        }
+        let request = request.body (body.map (Value::to_string))
+            .chain_err (|| "failed to build HTTP request object")?;
+        http_client::make_request_with (&mut *self.transport, request)
+            .chain_err (|| format!("HTTP request to '{}' failed", url))
+    }
+
+    fn login (&mut self) -> Result<()> {
+        info!(target: "renewer::zyxel", "trying to login using specified credentials");
+        self.session = None;
+        let session = match self.flavor {
+            Flavor::Vmg => self.login_vmg(),
+            Flavor::Keenetic => self.login_keenetic()
+        };
+        // Logging in to Keenetic routers sets a temporary session, for the cookies.
+        self.session = None;
+        self.session = Some (session?);
+        info!(target: "renewer::zyxel", "login OK");
+        Ok(())
+    }
+
+    // The credentials are encrypted with a random AES key, which is sent along with them
+    // encrypted with the RSA key of the router.
+    fn login_vmg (&mut self) -> Result<Session> {
+        let res = self.request (http::Method::GET, "/getRSAPublickKey", None)?;
+        ensure!(
+            res.status().is_success(),
+            "failed to request the RSA key, got status {}", res.status()
+        );
+        let response: Value = serde_json::from_str (res.body())
+            .chain_err (|| "the router answered with invalid JSON")?;
+        let public_key = response["RSAPublicKey"].as_str()
+            .chain_err (|| "the router answered without its RSA key")?;
+        let mut key = vec![0; 32];
+        openssl::rand::rand_bytes (&mut key).chain_err (|| "failed to generate a random key")?;
+        let credentials = json!({
+            "Input_Account": self.username,
+            "Input_Passwd": base64::encode_block (self.password.expose().as_bytes()),
+            "currLang": "en",
+            "RememberPassword": 0,
+            "SHA512_password": false
+        });
+        let mut body = vmg_encrypt (&key, &credentials)?;
+        body["key"] = rsa_encrypt (public_key, base64::encode_block (&key).as_bytes())?.into();
+        let res = self.request (http::Method::POST, "/UserLogin", Some (&body))?;
+        ensure!(res.status().is_success(), "login failed with status {}", res.status());
+        let cookies = http_client::cookies (&res);
+        let response = vmg_decrypt (&key, res.body())?;
+        match response["sessionkey"].as_str() {
+            Some(csrf_token) if response["result"] == VMG_SUCCESS => Ok(Session::Vmg {
+                key: key.into(),
+                csrf_token: csrf_token.to_string().into(),
+                cookies
+            }),
+            _ => bail!("login failed with result '{}', check your credentials!",
+                response["result"].as_str().unwrap_or ("unknown"))
+        }
+    }
+
+    // The router answers the first request with a challenge, and expects the SHA-256 hash of it
+    // followed by the MD5 hash of the credentials.
+    fn login_keenetic (&mut self) -> Result<Session> {
+        let res = self.request (http::Method::GET, "/auth", None)?;
+        let cookies = http_client::cookies (&res);
+        if res.status().is_success() {
+            // Logins without a password are already authenticated.
+            return Ok(Session::Keenetic { cookies });
+        }
+        let header = |name| res.headers().get (name)
+            .and_then (|value| value.to_str().ok())
+            .chain_err (|| format!("the router answered without the header '{}'", name));
+        let (realm, challenge) = (header ("X-NDM-Realm")?, header ("X-NDM-Challenge")?);
+        let password = keenetic_password (&self.username, self.password.expose(), realm, challenge);
+        self.session = Some (Session::Keenetic { cookies: cookies.clone() });
+        let credentials = json!({ "login": self.username, "password": password });
+        let res = self.request (http::Method::POST, "/auth", Some (&credentials))?;
+        ensure!(
+            res.status().is_success(),
+            "login failed with status {}, check your credentials!", res.status()
+        );
+        Ok(Session::Keenetic { cookies: http_client::cookies (&res).or (cookies) })
+    }
+
+    // Calls the DAL API of VMG routers, returning the decrypted response, or `None` if the
+    // session expired.
+    fn dal (&mut self, method: http::Method, body: Option<&Value>) -> Result<Option<Value>> {
+        let key = match self.session {
+            Some(Session::Vmg { ref key, .. }) => key.expose().clone(),
+            _ => unreachable!("only called with a session of a VMG router")
+        };
+        let body = body.map (|body| vmg_encrypt (&key, body)).transpose()?;
+        let res = self.request (method, "/cgi-bin/DAL?oid=wan", body.as_ref())?;
+        if res.status() == http::StatusCode::UNAUTHORIZED {
+            return Ok(None);
+        }
+        ensure!(res.status().is_success(), "the request failed with status {}", res.status());
+        let response = vmg_decrypt (&key, res.body())?;
+        match response["result"].as_str() {
+            Some(VMG_SUCCESS) => Ok(Some (response)),
+            Some("Invalid Session") => Ok(None),
+            result => bail!("the request failed with result '{}'", result.unwrap_or ("unknown"))
+        }
+    }
+
+    // Disables and enables the WAN interface of a VMG router. Returns `false` if the session
+    // expired.
+    fn restart_vmg (&mut self) -> Result<bool> {
+        let interfaces = match self.dal (http::Method::GET, None)? {
+            Some(response) => response["Object"].as_array().cloned().unwrap_or_default(),
+            None => return Ok(false)
+        };
+        let interface = interfaces.iter()
+            .find (|interface| match self.interface {
+                Some(ref name) => interface["Name"] == name.as_str(),
+                None => interface["Enable"] == true
+            })
+            .chain_err (|| match self.interface {
+                Some(ref name) => format!("the router has no WAN interface named '{}'", name),
+                None => "the router has no enabled WAN interface".into()
+            })?;
+        let index = interface["Index"].clone();
+        for enable in [false, true] {
+            let body = json!({ "Index": index, "Enable": enable });
+            ensure!(
+                self.dal (http::Method::PUT, Some (&body))?.is_some(),
+                "the session expired while restarting the interface"
+            );
+        }
+        Ok(true)
+    }
+
+    // Brings the interface of a Keenetic router down and up. Returns `false` if the session
+    // expired.
+    fn restart_keenetic (&mut self) -> Result<bool> {
+        let name = self.interface.clone()
+            .unwrap_or_else (|| DEFAULT_KEENETIC_INTERFACE.into());
+        for (number, command) in ["down", "up"].iter().enumerate() {
+            let body = json!({ "name": name, *command: true });
+            let res = self.request (http::Method::POST, "/rci/interface", Some (&body))?;
+            if res.status() == http::StatusCode::UNAUTHORIZED && number == 0 {
+                return Ok(false);
+            }
+            ensure!(
+                res.status().is_success(),
+                "bringing '{}' {} failed with status {}", name, command, res.status()
+            );
+        }
+        Ok(true)
+    }
+}
+
+// The body of a request to VMG routers: `data` encrypted with a random IV.
+fn vmg_encrypt (key: &[u8], data: &Value) -> Result<Value> {
+    let mut iv = [0; 16];
+    openssl::rand::rand_bytes (&mut iv).chain_err (|| "failed to generate a random IV")?;
+    let content = symm::encrypt (
+        Cipher::aes_256_cbc(), key, Some (&iv), data.to_string().as_bytes()
+    ).chain_err (|| "failed to encrypt the request")?;
+    Ok(json!({
+        "content": base64::encode_block (&content),
+        "iv": base64::encode_block (&iv)
+    }))
+}
+
+// Decrypts a response of VMG routers, e.g. `{"content":"<AES encrypted JSON>","iv":"..."}`.
+fn vmg_decrypt (key: &[u8], body: &str) -> Result<Value> {
+    let response: Value = serde_json::from_str (body)
+        .chain_err (|| "the router answered with invalid JSON")?;
+    let field = |name: &str| response[name].as_str()
+        .and_then (|value| base64::decode_block (value).ok())
+        .chain_err (|| format!("the router answered without a valid '{}'", name));
+    let (content, iv) = (field ("content")?, field ("iv")?);
+    // Some firmwares send longer IVs, of which only the first block is used.
+    let iv = iv.get (..16).chain_err (|| "the router answered with a short IV")?;
+    let data = symm::decrypt (Cipher::aes_256_cbc(), key, Some (iv), &content)
+        .chain_err (|| "failed to decrypt the response")?;
+    serde_json::from_slice (&data).chain_err (|| "the router answered with invalid JSON")
+}
+
+// Encrypts `data` with a PEM public key and PKCS #1 v1.5 padding, as base64.
+fn rsa_encrypt (public_key: &str, data: &[u8]) -> Result<String> {
+    let key = Rsa::public_key_from_pem (public_key.as_bytes())
+        .chain_err (|| "the router answered with an invalid RSA key")?;
+    let mut encrypted = vec![0; key.size() as usize];
+    let length = key.public_encrypt (data, &mut encrypted, Padding::PKCS1)
+        .chain_err (|| "failed to encrypt with the RSA key of the router")?;
+    Ok(base64::encode_block (&encrypted[..length]))
+}
+
+// The password expected by Keenetic routers: SHA-256 (challenge + MD5 (login:realm:password)).
+fn keenetic_password (username: &str, password: &str, realm: &str, challenge: &str) -> String {
+    let credentials = format!("{:x}", md5::compute (
+        format!("{}:{}:{}", username, realm, password)
+    ));
+    openssl::sha::sha256 (format!("{}{}", challenge, credentials).as_bytes()).iter()
+        .map (|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+impl RenewerTrait for Renewer {
+    fn from_config (renewer: &config::RenewerConfig) -> Result<Self>
+        where Self: Sized
+    {
+        let config: Config = renewer.config.parse_with_secrets (SECRETS)
+            .chain_err (|| format!(
+                "the renewer '{}' is not configured correctly", renewer.instance
+            ))?;
+        Ok(Renewer {
+            ip: http_client::uri_host (&config.ip).into_owned(),
+            username: config.username.unwrap_or_else (|| "admin".into()),
+            password: config.password,
+            flavor: config.flavor,
+            interface: config.interface,
+            session: None,
+            transport: Box::new (http_client::TcpTransport)
+        })
+    }
+
+    #[cfg(feature = "config-schema")]
+    fn config_schema (gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        config::schema::section::<Config> (gen, SECRETS)
+    }
+
+    fn init (&mut self) -> Result<()> {
+        self.login()
+    }
+
+    fn renew_ip (&mut self) -> Result<RenewOutcome> {
+        let restart = |renewer: &mut Self| match renewer.flavor {
+            Flavor::Vmg => renewer.restart_vmg(),
+            Flavor::Keenetic => renewer.restart_keenetic()
+        };
+        if self.session.is_none() {
+            self.login()?;
+        }
+        if !restart (self)? {
+            debug!(target: "renewer::zyxel", "session expired, logging in again");
+            self.login()?;
+            ensure!(restart (self)?, "the router rejected the new session");
+        }
+        info!(target: "renewer::zyxel", "successfully restarted the WAN interface");
+        Ok(RenewOutcome::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::http_client::MockTransport;
+
+    fn renewer (transport: &MockTransport, flavor: Flavor) -> Renewer {
+        Renewer {
+            ip: "192.168.1.1".into(),
+            username: "admin".into(),
+            password: "hunter2".into(),
+            flavor,
+            interface: None,
+            session: None,
+            transport: Box::new (transport.clone())
+        }
+    }
+
+    #[test]
+    fn vmg_requests_are_encrypted() {
+        let key = [7; 32];
+        let request = vmg_encrypt (&key, &json!({ "Index": 2, "Enable": false })).unwrap();
+        let decrypted = vmg_decrypt (&key, &request.to_string()).unwrap();
+        assert_eq!(decrypted, json!({ "Index": 2, "Enable": false }));
+
+        let rsa = Rsa::generate (1024).unwrap();
+        let pem = String::from_utf8 (rsa.public_key_to_pem().unwrap()).unwrap();
+        let encrypted = base64::decode_block (&rsa_encrypt (&pem, b"secret").unwrap()).unwrap();
+        let mut decrypted = vec![0; 128];
+        let length = rsa.private_decrypt (&encrypted, &mut decrypted, Padding::PKCS1).unwrap();
+        assert_eq!(&decrypted[..length], b"secret");
+    }
+
+    #[test]
+    fn keenetic_answers_the_challenge() {
+        let transport = MockTransport::new();
+        transport
+            .push_response ("HTTP/1.1 401 Unauthorized\r\nX-NDM-Realm: Keenetic Giga\r\n\
+                X-NDM-Challenge: QWERTYUIOPASDFGH\r\nSet-Cookie: _authorized=; Path=/\r\n\
+                Content-Length: 0\r\n\r\n")
+            .push_response ("HTTP/1.1 200 OK\r\nSet-Cookie: session=f00; Path=/\r\n\r\n{}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{}")
+            .push_response ("HTTP/1.1 200 OK\r\n\r\n{}");
+        let mut renewer = renewer (&transport, Flavor::Keenetic);
+        renewer.init().unwrap();
+        renewer.renew_ip().unwrap();
+
+        let requests = transport.requests();
+        let password = keenetic_password ("admin", "hunter2", "Keenetic Giga", "QWERTYUIOPASDFGH");
+        assert!(requests[1].starts_with ("POST /auth HTTP/1.1\r\n"));
+        assert!(requests[1].contains (
+            &json!({ "login": "admin", "password": password }).to_string()
+        ));
+        assert!(requests[2].contains ("cookie: session=f00\r\n"));
+        assert!(requests[2].contains (r#"{"down":true,"name":"PPPoE0"}"#));
+        assert!(requests[3].contains (r#"{"name":"PPPoE0","up":true}"#));
+    }
+}
@@ -0,0 +1,127 @@
+//! Host names resolved again while running, so that long-running clients and notifiers follow a
+//! name whose address changes (e.g. a dynamic DNS name), see `Resolver`.
+
+use crate::config::format_duration;
+use crate::errors::Cause;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause)
+}
+
+chained_error!(Error);
+
+/// How long resolved addresses are used before resolving them again, unless configured
+/// otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs (300);
+
+// The addresses resolved last, along with when.
+type Resolved = Option<(Vec<SocketAddr>, Instant)>;
+
+/// Resolves `name` (e.g. "home.example.org:5454") to socket addresses, and again once they're
+/// older than `interval`. If resolving it again fails, the last known addresses keep being used
+/// (for another interval). Clones share the addresses resolved so far.
+#[derive(Clone, Debug)]
+pub struct Resolver {
+    name: String,
+    interval: Duration,
+    accept: fn(&SocketAddr) -> bool,
+    resolved: Arc<Mutex<Resolved>>
+}
+
+impl Resolver {
+    pub fn new<S: Into<String>> (name: S, interval: Duration) -> Resolver {
+        Resolver {
+            name: name.into(),
+            interval,
+            accept: |_| true,
+            resolved: Arc::new (Mutex::new (None))
+        }
+    }
+
+    /// Only keeps the addresses for which `accept` returns true, e.g. the multicast ones.
+    pub fn accepting (mut self, accept: fn(&SocketAddr) -> bool) -> Resolver {
+        self.accept = accept;
+        self
+    }
+
+    /// The name which is resolved.
+    pub fn name (&self) -> &str {
+        &self.name
+    }
+
+    /// The addresses of the name (never empty), resolved again if they're older than the
+    /// interval. Only fails if the name has never been resolved.
+    pub fn resolve (&self) -> Result<Vec<SocketAddr>> {
+        let mut resolved = self.resolved.lock().unwrap_or_else (|e| e.into_inner());
+        if let Some((ref addrs, at)) = *resolved {
+            if at.elapsed() < self.interval {
+                return Ok(addrs.clone());
+            }
+        }
+        match (self.lookup(), resolved.take()) {
+            (Ok(addrs), previous) => {
+                match previous {
+                    Some((ref previous, _)) if *previous != addrs =>
+                        info!(target: "resolver", "'{}' now resolves to {:?}, instead of {:?}",
+                            self.name, addrs, previous),
+                    _ => trace!(target: "resolver", "'{}' resolves to {:?}", self.name, addrs)
+                }
+                *resolved = Some ((addrs.clone(), Instant::now()));
+                Ok(addrs)
+            },
+            (Err(err), Some((addrs, _))) => {
+                warn!(target: "resolver", "{}, using {:?} for another {}",
+                    err, addrs, format_duration (self.interval));
+                *resolved = Some ((addrs.clone(), Instant::now()));
+                Ok(addrs)
+            },
+            (Err(err), None) => Err(err)
+        }
+    }
+
+    fn lookup (&self) -> Result<Vec<SocketAddr>> {
+        let addrs: Vec<_> = self.name.to_socket_addrs()
+            .chain_err (|| format!("failed to resolve '{}'", self.name))?
+            .filter (self.accept)
+            .collect();
+        ensure!(!addrs.is_empty(), "'{}' doesn't resolve to any suitable address", self.name);
+        Ok(addrs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn addresses_are_kept_for_the_interval() {
+        let resolver = Resolver::new ("127.0.0.1:5454", Duration::from_secs (3600));
+        let addr: SocketAddr = "127.0.0.1:5454".parse().unwrap();
+        assert_eq!(resolver.resolve().unwrap(), vec![addr]);
+        // Cached addresses are used without resolving the name again.
+        let other: SocketAddr = "127.0.0.2:5454".parse().unwrap();
+        *resolver.resolved.lock().unwrap() = Some ((vec![other], Instant::now()));
+        assert_eq!(resolver.clone().resolve().unwrap(), vec![other]);
+
+        let multicast = Resolver::new ("127.0.0.1:5454", Duration::from_secs (1))
+            .accepting (|addr| addr.ip().is_multicast());
+        assert!(multicast.resolve().is_err());
+    }
+
+    #[test]
+    fn last_known_addresses_survive_failures() {
+        let resolver = Resolver::new ("unresolvable.invalid:5454", Duration::from_millis (1));
+        assert!(resolver.resolve().is_err());
+        let addr: SocketAddr = "127.0.0.1:5454".parse().unwrap();
+        *resolver.resolved.lock().unwrap() = Some ((vec![addr], Instant::now()));
+        std::thread::sleep (Duration::from_millis (10));
+        assert_eq!(resolver.resolve().unwrap(), vec![addr]);
+    }
+}
@@ -0,0 +1,300 @@
+//! When a server renews the IP address on its own, as cron expressions (e.g. "30 4 * * *") or
+//! as "every day at 04:30", see `Schedule`.
+
+use crate::errors::Cause;
+use std::fmt;
+use std::str::FromStr;
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause)
+}
+
+chained_error!(Error);
+
+const WEEKDAYS: [&str; 7] = [
+    "sunday", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday"
+];
+
+const MINUTES_PER_DAY: i64 = 24 * 60;
+
+// How far ahead the next match is looked for: the 29th of February may not come for 8 years.
+const SEARCHED_DAYS: i64 = 8 * 366;
+
+// How long `Schedule::wait` sleeps at most before looking at the clock again.
+const TICK: Duration = Duration::from_secs (60);
+
+/// The minutes at which something happens, in the local timezone (in UTC without the feature
+/// `local-time`). They're written either as cron expressions, whose fields are the minute, the
+/// hour, the day of the month, the month and the day of the week (0 or 7 is Sunday, or "mon" to
+/// "sun"), each as `*`, values, ranges and steps (e.g. "0,30", "1-5" or "*/15"), or as "every
+/// day at 04:30" and "every monday at 04:30".
+///
+/// As in cron, when both the day of the month and the day of the week are restricted, the days
+/// matching either are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    expression: String,
+    // Sets of bits, e.g. bit 30 of `minutes` is set when the schedule matches minute 30.
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool
+}
+
+impl Schedule {
+    /// The start of the first minute matching the schedule after `time`, if it ever matches
+    /// again. Times which the clock skips (e.g. when daylight saving time starts) are skipped as
+    /// well, while those which it shows twice match the first time.
+    pub fn next_after (&self, time: SystemTime) -> Option<SystemTime> {
+        self.next_by (wall_clock (time), from_wall_clock)
+    }
+
+    /// Sleeps until the start of the next minute matching the schedule, returning whether there
+    /// was one. The clock is looked at again every minute, so that changes of the system time
+    /// (e.g. once a router without a real-time clock is online) are followed: the minutes it
+    /// jumps over are skipped rather than caught up on.
+    pub fn wait (&self) -> bool {
+        let mut next = self.next_after (SystemTime::now());
+        while let Some(at) = next {
+            let now = SystemTime::now();
+            match at.duration_since (now) {
+                Ok(remaining) if remaining.is_zero() => return true,
+                Err(late) if late.duration() < TICK => return true,
+                Ok(_) => {
+                    // The clock may have gone back since, making an earlier minute the next one.
+                    next = self.next_after (now);
+                    if let Some(at) = next {
+                        thread::sleep (at.duration_since (now).unwrap_or_default().min (TICK));
+                    }
+                },
+                Err(_) => {
+                    debug!(target: "schedule", "the clock jumped past the schedule '{}'", self);
+                    next = self.next_after (now);
+                }
+            }
+        }
+        false
+    }
+
+    // The first minute matching the schedule after `wall` (in seconds since the epoch as shown
+    // by the clock, see `wall_clock`) which `to_time` converts.
+    fn next_by<T> (&self, wall: i64, to_time: impl Fn (i64) -> Option<T>) -> Option<T> {
+        let first = wall.div_euclid (60) + 1;
+        let first_day = first.div_euclid (MINUTES_PER_DAY);
+        for day in first_day..first_day + SEARCHED_DAYS {
+            if !self.matches_day (day) {
+                continue;
+            }
+            let from = if day == first_day { first.rem_euclid (MINUTES_PER_DAY) } else { 0 };
+            for minute in from..MINUTES_PER_DAY {
+                if !has (self.hours, minute / 60) || !has (self.minutes, minute % 60) {
+                    continue;
+                }
+                if let Some(time) = to_time ((day * MINUTES_PER_DAY + minute) * 60) {
+                    return Some (time);
+                }
+            }
+        }
+        None
+    }
+
+    // Whether the schedule matches the day `day` days after the epoch.
+    fn matches_day (&self, day: i64) -> bool {
+        let (month, day_of_month) = month_and_day (day);
+        let in_month = has (self.days, day_of_month);
+        // The epoch was a Thursday.
+        let in_week = has (self.weekdays, (day + 4).rem_euclid (7));
+        has (self.months, month) && if self.days_restricted && self.weekdays_restricted {
+            in_month || in_week
+        } else {
+            in_month && in_week
+        }
+    }
+}
+
+fn has (bits: u64, value: i64) -> bool {
+    bits >> value & 1 == 1
+}
+
+// The month (1 to 12) and the day of the month (1 to 31) of the day `day` days after the epoch,
+// see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn month_and_day (day: i64) -> (i64, i64) {
+    let day_of_era = (day + 719468).rem_euclid (146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096)
+        / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day_of_month = day_of_year - (153 * month + 2) / 5 + 1;
+    (if month < 10 { month + 3 } else { month - 9 }, day_of_month)
+}
+
+// The seconds since the epoch shown by the clock at `time`, in the local timezone.
+#[cfg(feature = "local-time")]
+fn wall_clock (time: SystemTime) -> i64 {
+    chrono::DateTime::<chrono::Local>::from (time).naive_local().timestamp()
+}
+
+// When the clock shows `wall` (see `wall_clock`), the first time if it does twice.
+#[cfg(feature = "local-time")]
+fn from_wall_clock (wall: i64) -> Option<SystemTime> {
+    use chrono::TimeZone;
+    let wall = chrono::NaiveDateTime::from_timestamp_opt (wall, 0)?;
+    chrono::Local.from_local_datetime (&wall).earliest().map (SystemTime::from)
+}
+
+// Without `local-time` (and thus chrono), the clock shows UTC.
+#[cfg(not(feature = "local-time"))]
+fn wall_clock (time: SystemTime) -> i64 {
+    match time.duration_since (std::time::UNIX_EPOCH) {
+        Ok(since) => since.as_secs() as i64,
+        Err(before) => -(before.duration().as_secs() as i64)
+    }
+}
+
+#[cfg(not(feature = "local-time"))]
+fn from_wall_clock (wall: i64) -> Option<SystemTime> {
+    std::time::UNIX_EPOCH.checked_add (Duration::from_secs (u64::try_from (wall).ok()?))
+}
+
+// Parses `field` of a cron expression, whose values go from `min` to `max` (or are `names`,
+// starting from `min`), as a set of bits. Returns whether it's restricted, i.e. not `*`.
+fn field (field: &str, min: i64, max: i64, names: &[&str]) -> Result<(u64, bool)> {
+    let value = |value: &str| -> Result<i64> {
+        let named = names.iter().position (|name| name.get (..3) == Some (value));
+        named.map (|position| position as i64 + min)
+            .or_else (|| value.parse().ok())
+            .filter (|value| (min..=max).contains (value))
+            .chain_err (|| format!("invalid value '{}', expected {} to {}", value, min, max))
+    };
+    let mut bits = 0;
+    for part in field.split (',') {
+        let (range, step) = match part.split_once ('/') {
+            Some((range, step)) => (range, Some (step)),
+            None => (part, None)
+        };
+        let (from, to) = match range.split_once ('-') {
+            _ if range == "*" => (min, max),
+            Some((from, to)) => (value (from)?, value (to)?),
+            None if step.is_some() => (value (range)?, max),
+            None => (value (range)?, value (range)?)
+        };
+        let step = match step {
+            Some(step) => step.parse::<usize>().ok().filter (|&step| step > 0)
+                .chain_err (|| format!("invalid step '{}'", step))?,
+            None => 1
+        };
+        ensure!(from <= to, "invalid range '{}'", range);
+        for value in (from..=to).step_by (step) {
+            bits |= 1 << value;
+        }
+    }
+    Ok((bits, field != "*"))
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str (expression: &str) -> Result<Schedule> {
+        let words = expression.split_whitespace().collect::<Vec<_>>();
+        let fields = match words[..] {
+            ["every", day, "at", time] => {
+                let (hour, minute) = time.split_once (':')
+                    .chain_err (|| format!("invalid time '{}', expected e.g. 04:30", time))?;
+                let weekday = match day {
+                    "day" => "*".to_string(),
+                    day => WEEKDAYS.iter().position (|name| *name == day)
+                        .chain_err (|| format!("unknown day '{}'", day))?
+                        .to_string()
+                };
+                vec![minute.to_string(), hour.to_string(), "*".into(), "*".into(), weekday]
+            },
+            [_, _, _, _, _] => words.iter().map (|word| word.to_string()).collect(),
+            _ => bail!(
+                "invalid schedule '{}', expected e.g. \"30 4 * * *\" or \"every day at 04:30\"",
+                expression
+            )
+        };
+        let parsed = (|| -> Result<Schedule> {
+            let (weekdays, weekdays_restricted) = field (&fields[4], 0, 7, &WEEKDAYS)
+                .chain_err (|| "invalid day of the week")?;
+            let (days, days_restricted) = field (&fields[2], 1, 31, &[])
+                .chain_err (|| "invalid day of the month")?;
+            Ok(Schedule {
+                expression: expression.into(),
+                minutes: field (&fields[0], 0, 59, &[]).chain_err (|| "invalid minute")?.0,
+                hours: field (&fields[1], 0, 23, &[]).chain_err (|| "invalid hour")?.0,
+                days,
+                months: field (&fields[3], 1, 12, &[]).chain_err (|| "invalid month")?.0,
+                // Sunday is both 0 and 7.
+                weekdays: (weekdays | weekdays >> 7) & 0x7f,
+                days_restricted,
+                weekdays_restricted
+            })
+        })();
+        let schedule = parsed.chain_err (|| format!("invalid schedule '{}'", expression))?;
+        ensure!(
+            schedule.next_by (0, Some).is_some(),
+            "the schedule '{}' never matches", expression
+        );
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str (&self.expression)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The next minute matching `schedule` after `wall`, both as "day hh:mm" in days since the
+    // epoch.
+    fn next (schedule: &str, wall: (i64, i64, i64)) -> (i64, i64, i64) {
+        let (day, hour, minute) = wall;
+        let schedule = schedule.parse::<Schedule>().unwrap();
+        let next = schedule.next_by ((day * MINUTES_PER_DAY + hour * 60 + minute) * 60, Some)
+            .unwrap() / 60;
+        (next / MINUTES_PER_DAY, next % MINUTES_PER_DAY / 60, next % 60)
+    }
+
+    #[test]
+    fn schedules_match_their_minutes() {
+        // Day 0 is Thursday the 1st of January 1970.
+        assert_eq!(next ("every day at 04:30", (0, 4, 29)), (0, 4, 30));
+        assert_eq!(next ("every day at 04:30", (0, 4, 30)), (1, 4, 30));
+        assert_eq!(next ("30 4 * * *", (0, 12, 0)), (1, 4, 30));
+        assert_eq!(next ("every monday at 4:05", (0, 0, 0)), (4, 4, 5));
+        assert_eq!(next ("*/15 * * * mon-fri", (1, 23, 59)), (4, 0, 0));
+        assert_eq!(next ("0 0 * * 7", (0, 0, 0)), (3, 0, 0));
+        assert_eq!(next ("0 12 1 2 *", (0, 0, 0)), (31, 12, 0));
+        // Either the 13th or a Friday.
+        assert_eq!(next ("0 0 13 * 5", (0, 0, 0)), (1, 0, 0));
+        assert_eq!(next ("0 0 13 * 5", (1, 0, 0)), (8, 0, 0));
+        assert_eq!(next ("0 0 13 * 5", (8, 0, 0)), (12, 0, 0));
+        // The 29th of February of 1972.
+        assert_eq!(next ("0 0 29 2 *", (0, 0, 0)), (789, 0, 0));
+    }
+
+    #[test]
+    fn invalid_schedules_are_refused() {
+        for schedule in &["every day", "every day at 4", "every someday at 04:30", "60 * * * *",
+            "* 24 * * *", "*/0 * * * *", "5-1 * * * *", "0 0 30 2 *", "* * * *"]
+        {
+            assert!(schedule.parse::<Schedule>().is_err(), "{}", schedule);
+        }
+        assert_eq!("every day at 04:30".parse::<Schedule>().unwrap().to_string(),
+            "every day at 04:30");
+    }
+}
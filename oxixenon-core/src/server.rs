@@ -0,0 +1,1834 @@
+//! A server of the Xenon protocol, which renews the IP address when clients ask for it.
+
+use crate::acl::{Acl, Action, Scope, Token};
+use crate::config::Secret;
+use crate::errors::*;
+use crate::log_error_with_chain;
+use crate::logging;
+use crate::metrics;
+use crate::notifier::{self, Coalescer, Notifier};
+use crate::protocol::{
+    self, Capabilities, Encoding, ErrorCode, Packet, Event, EventPayload, HistoryEntry,
+    HistoryOutcome, RenewAvailability, Status
+};
+use crate::renewer::{Health, LinkInfo, RenewOutcome, Renewer};
+use crate::state::StateDir;
+#[cfg(feature = "tls")]
+use crate::tls;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// The current availability, along with how many times it has been set: a timer making renewals
+// available again does nothing if the availability changed in the meantime.
+struct Availability {
+    current: RenewAvailability,
+    generation: u64
+}
+
+// The availability as kept in the state directory, under "availability".
+#[derive(Serialize, Deserialize)]
+struct StoredAvailability {
+    // Unavailable when set.
+    reason: Option<String>,
+    // When renewals are available again, in seconds since the Unix epoch.
+    until: Option<u64>
+}
+
+const AVAILABILITY_VERSION: u32 = 1;
+
+// A renewal in the history as kept in the state directory, under "history".
+#[derive(Serialize, Deserialize)]
+struct StoredRenewal {
+    // When the renewal started, in seconds since the Unix epoch.
+    timestamp: u64,
+    peer: Option<String>,
+    renewer: Option<String>,
+    old_ip: Option<IpAddr>,
+    // The new address, or the one which didn't change when `unchanged` is set.
+    new_ip: Option<IpAddr>,
+    #[serde(default)]
+    unchanged: bool,
+    // Failed when set.
+    error: Option<String>
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredHistory {
+    // Oldest first.
+    renewals: Vec<StoredRenewal>
+}
+
+const HISTORY_VERSION: u32 = 1;
+
+// How many renewals are kept in the history by default, see `Server::history_size`.
+const HISTORY_SIZE: usize = 100;
+
+// How long clients have to send each request once they started, see `Server::idle_timeout` for
+// the time between requests.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs (5);
+
+/// Where the public IP address is read from, see `Server::check_ip` and
+/// `Server::verify_renewals`.
+#[derive(Debug, Clone)]
+pub enum IpSource {
+    /// The external IP address reported by the router, see `Renewer::link_info`.
+    Renewer,
+    /// A URL answering with the address as plain text, e.g. "https://api.ipify.org". Requires
+    /// the feature `http-client` (and `http-client-tls` for HTTPS).
+    Url(String),
+    /// A STUN server (RFC 5389), e.g. "stun.l.google.com:19302", which tells the address it
+    /// sees the requests coming from.
+    Stun(String)
+}
+
+/// The outcome of a successful renewal, see `Server::renew`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Renewal {
+    /// The IP address was renewed, along with what's known about the renewal: what the renewer
+    /// told, completed by the verification. If renewals are verified, it also changed.
+    Renewed(RenewOutcome),
+    /// The renewer succeeded, but the IP address (the one given) didn't change within the
+    /// timeout of the verification, see `Server::verify_renewals`.
+    Unchanged(IpAddr)
+}
+
+// How renewals are verified, see `Server::verify_renewals`.
+#[derive(Clone)]
+struct Verification {
+    source: IpSource,
+    timeout: Duration,
+    interval: Duration
+}
+
+// The public IP address found by the latest check, see `Server::check_ip`.
+struct IpCheck {
+    last: Option<IpAddr>,
+    // Whether the address has been renewed since the latest check, in which case a change is
+    // expected.
+    renewed: bool
+}
+
+// The clients subscribed to the events (see `Packet::Subscribe`), each served by its own thread.
+type Subscribers = Mutex<Vec<Subscriber>>;
+
+struct Subscriber {
+    events: mpsc::Sender<(Event, EventPayload)>,
+    // Finishes once the client disconnects.
+    thread: thread::JoinHandle<()>
+}
+
+// How many clients can be subscribed at once, as each of them has its own thread.
+const MAX_SUBSCRIBERS: usize = 64;
+
+// What happens to the connection of a client once a request is answered.
+enum Next {
+    Close,
+    // Waits for the next request.
+    KeepOpen,
+    // Sends the events to the client, see `Server::subscribe`.
+    Subscribe
+}
+
+// Handles the events of the server within the process, see `Server::on_event`.
+type OnEvent = Mutex<Box<dyn FnMut(Event, &EventPayload) + Send>>;
+
+// Builds and initializes the renewer with the given name, see `Server::on_set_renewer`.
+type OnSetRenewer = dyn FnMut(&str) -> Result<Box<dyn Renewer>>;
+
+// Something to run on the thread of the server, which owns the renewer, see `Handle`.
+type Job = Box<dyn FnOnce(&mut Server) + Send>;
+
+enum Task {
+    Run(Job),
+    // Sent once every client has been answered, see `Server::serve_encoded`.
+    Stop
+}
+
+// How many clients are answered at once by default, see `Server::max_clients`.
+const MAX_CLIENTS: usize = 8;
+
+// How long renewal requests are gathered by default, see `Server::coalesce_renewals`.
+const RENEWAL_WINDOW: Duration = Duration::from_secs (1);
+
+// A client waiting for a renewal, and where its response goes.
+type Waiting = (Requester, mpsc::Sender<Packet>);
+
+// The client which requested something, and the name of the token it authenticated with (if
+// any), see `Server::token`.
+#[derive(Debug, Clone)]
+struct Requester {
+    peer: SocketAddr,
+    token: Option<String>
+}
+
+impl fmt::Display for Requester {
+    fn fmt (&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.token {
+            Some(ref token) => write!(f, "{} ({})", self.peer, token),
+            None => write!(f, "{}", self.peer)
+        }
+    }
+}
+
+// Who a client proved to be, see `Clients::answer_request`.
+#[derive(Clone, Copy)]
+enum Identity<'t> {
+    // It didn't authenticate yet.
+    Unknown,
+    // It knows the pre-shared key, or it doesn't have to authenticate.
+    Trusted,
+    Token(&'t Token)
+}
+
+// What the server shares with the threads answering its clients, and with the timers of the
+// availability.
+#[derive(Clone)]
+struct Shared {
+    notifier: Arc<Mutex<Box<dyn Notifier>>>,
+    availability: Arc<Mutex<Availability>>,
+    coalescer: Option<Coalescer<EventPayload>>,
+    on_event: Option<Arc<OnEvent>>,
+    subscribers: Arc<Subscribers>,
+    // The host name sent along with the events, see `Server::origin`.
+    origin: Option<String>,
+    state: Option<StateDir>,
+    renewer_name: Arc<Mutex<Option<String>>>,
+    // The health found by the latest check of the renewer, and when, see `Server::check_health`.
+    health: Arc<Mutex<Option<(Health, Instant)>>>,
+    // The latest renewals, oldest first, see `Server::history`.
+    history: Arc<Mutex<VecDeque<HistoryEntry>>>,
+    // Whether the shutdown was notified, see `Server::shut_down`.
+    shutting_down: Arc<AtomicBool>,
+    // The clients waiting for the next renewal, by the renewer they selected (if any), see
+    // `Server::coalesce_renewals`.
+    pending: Arc<Mutex<BTreeMap<Option<String>, Vec<Waiting>>>>
+}
+
+/// A server which answers several clients at once, see [`serve`](#method.serve). Everything
+/// involving the renewer (e.g. renewals) runs on the thread serving the clients, one at a time.
+pub struct Server {
+    renewer: Box<dyn Renewer>,
+    shared: Shared,
+    on_reload: Option<Box<dyn FnMut() -> Result<()>>>,
+    admin_token: Option<Secret<String>>,
+    psk: Option<Secret<String>>,
+    tokens: Vec<Token>,
+    acl: Acl,
+    idle_timeout: Duration,
+    max_clients: usize,
+    renewal_window: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<tls::ServerTls>,
+    on_set_renewer: Option<Box<OnSetRenewer>>,
+    // The other renewers selected by renewal requests, kept for the following ones.
+    selected_renewers: BTreeMap<String, Box<dyn Renewer>>,
+    ip_check: IpCheck,
+    verification: Option<Verification>,
+    history_size: usize,
+    scheduled: bool,
+    // When the IP address last changed, through a renewal or on its own (see `check_ip`).
+    last_change: Option<Instant>,
+    // What the threads answering the clients and the handles ask the server to run.
+    jobs: mpsc::Sender<Task>,
+    queue: mpsc::Receiver<Task>
+}
+
+/// Runs code on the thread of a server, which owns its renewer, from other threads (e.g. those of
+/// timers or signal handlers), see `Server::handle`. Jobs run one at a time while the server
+/// serves its clients.
+#[derive(Clone)]
+pub struct Handle {
+    jobs: mpsc::Sender<Task>,
+    shared: Shared
+}
+
+impl Handle {
+    /// Runs `job` once the server is done with what it's running (e.g. a renewal), without
+    /// waiting for it. Fails if the server is gone.
+    pub fn spawn<F> (&self, job: F) -> Result<()>
+        where F: FnOnce(&mut Server) + Send + 'static
+    {
+        // The job logs with the correlation id of the thread which sent it.
+        let correlation = logging::correlation_id();
+        let job = Box::new (move |server: &mut Server| {
+            let _correlation = correlation.map (logging::correlate_as);
+            job (server)
+        });
+        self.jobs.send (Task::Run (job)).ok().chain_err (|| "the server is gone")
+    }
+
+    /// Runs `job` as [`spawn`](#method.spawn) does, waiting for what it returns.
+    pub fn run<T, F> (&self, job: F) -> Result<T>
+        where T: Send + 'static, F: FnOnce(&mut Server) -> T + Send + 'static
+    {
+        let (sender, result) = mpsc::channel();
+        self.spawn (move |server| {
+            let _ = sender.send (job (server));
+        })?;
+        result.recv().ok().chain_err (|| "the server stopped before answering")
+    }
+
+    /// Notifies `Event::ServerShuttingDown` as `Server::shut_down` does, without waiting for the
+    /// server, e.g. when it takes too long to stop.
+    pub fn shut_down (&self) {
+        self.shared.shut_down();
+    }
+}
+
+impl Server {
+    /// A server renewing the IP address with `renewer` (which must be already initialized) and
+    /// notifying renewals with `notifier`.
+    pub fn new (renewer: Box<dyn Renewer>, notifier: Box<dyn Notifier>) -> Server {
+        let (jobs, queue) = mpsc::channel();
+        Server {
+            renewer,
+            shared: Shared {
+                notifier: Arc::new (Mutex::new (notifier)),
+                availability: Arc::new (Mutex::new (Availability {
+                    current: RenewAvailability::Available,
+                    generation: 0
+                })),
+                coalescer: None,
+                on_event: None,
+                subscribers: Arc::new (Mutex::new (Vec::new())),
+                origin: hostname(),
+                state: None,
+                renewer_name: Arc::new (Mutex::new (None)),
+                health: Arc::new (Mutex::new (None)),
+                history: Arc::new (Mutex::new (VecDeque::new())),
+                shutting_down: Arc::new (AtomicBool::new (false)),
+                pending: Arc::new (Mutex::new (BTreeMap::new()))
+            },
+            on_reload: None,
+            admin_token: None,
+            psk: None,
+            tokens: Vec::new(),
+            acl: Acl::default(),
+            idle_timeout: Duration::from_secs (5),
+            max_clients: MAX_CLIENTS,
+            renewal_window: RENEWAL_WINDOW,
+            #[cfg(feature = "tls")]
+            tls: None,
+            on_set_renewer: None,
+            selected_renewers: BTreeMap::new(),
+            ip_check: IpCheck { last: None, renewed: false },
+            verification: None,
+            history_size: HISTORY_SIZE,
+            scheduled: false,
+            last_change: None,
+            jobs,
+            queue
+        }
+    }
+
+    /// The notifier of the server, which can be replaced while the server is running (e.g. when
+    /// the configuration is reloaded).
+    pub fn notifier (&self) -> Arc<Mutex<Box<dyn Notifier>>> {
+        self.shared.notifier.clone()
+    }
+
+    /// A handle to run code on the thread of the server while it serves its clients, e.g. to
+    /// renew the IP address when a signal arrives.
+    pub fn handle (&self) -> Handle {
+        Handle { jobs: self.jobs.clone(), shared: self.shared.clone() }
+    }
+
+    /// Sets what happens when a client asks to reload the configuration. Without it, such
+    /// requests are refused.
+    pub fn on_reload<F: FnMut() -> Result<()> + 'static> (mut self, on_reload: F) -> Server {
+        self.on_reload = Some (Box::new (on_reload));
+        self
+    }
+
+    /// The name of the renewer, i.e. its key in `server.renewer`, reported by `status`.
+    pub fn renewer_name<S: Into<String>> (self, name: S) -> Server {
+        *self.shared.renewer_name.lock().unwrap() = Some (name.into());
+        self
+    }
+
+    /// Requires `token` from the requests reserved to administrators, which are refused without
+    /// it, unless the client authenticated with a token of scope `admin` (see `token`).
+    pub fn admin_token (mut self, token: Secret<String>) -> Server {
+        self.admin_token = Some (token);
+        self
+    }
+
+    /// Requires clients to prove that they know the pre-shared key `psk` before accepting any of
+    /// their requests: they must answer a nonce with its HMAC (see `Packet::AuthChallenge`).
+    pub fn psk (mut self, psk: Secret<String>) -> Server {
+        self.psk = Some (psk);
+        self
+    }
+
+    /// Also accepts clients which prove to know the secret of `token` as they would the
+    /// pre-shared key (which isn't required to use tokens), and accepts from them only the
+    /// requests allowed by its scopes. The name of the token is logged along with their
+    /// requests, and sent along with the events they cause (see `EventPayload::requested_by`).
+    pub fn token (mut self, token: Token) -> Server {
+        self.tokens.push (token);
+        self
+    }
+
+    /// Answers only the clients allowed by `acl`, and accepts each request only from those which
+    /// may send it. Connections of other clients are closed right away, while the requests they
+    /// may not send are refused as `Unauthorized`.
+    pub fn acl (mut self, acl: Acl) -> Server {
+        self.acl = acl;
+        self
+    }
+
+    /// Keeps the connections of clients open for up to `timeout` after each response (5 seconds
+    /// by default), so that they can send further requests without connecting again. Each open
+    /// connection keeps one of the threads answering clients (see `max_clients`) busy. Zero
+    /// closes connections after the first request.
+    pub fn idle_timeout (mut self, timeout: Duration) -> Server {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Answers up to `max_clients` clients at once (8 by default), each from a thread of its own:
+    /// the others wait to be accepted meanwhile. The clients subscribed to the events don't
+    /// count, see `Packet::Subscribe`.
+    pub fn max_clients (mut self, max_clients: usize) -> Server {
+        self.max_clients = max_clients.max (1);
+        self
+    }
+
+    /// Coalesces the renewals requested by clients within `window` (1 second by default) of the
+    /// first one into a single renewal, whose outcome is sent to all of them. Those requested
+    /// while a renewal is in progress are coalesced into the next one. Renewals requested from
+    /// different renewers (see `Packet::FreshIPRequest`) aren't coalesced with each other.
+    pub fn coalesce_renewals (mut self, window: Duration) -> Server {
+        self.renewal_window = window;
+        self
+    }
+
+    /// Talks to clients over TLS, see `tls::ServerTls`.
+    #[cfg(feature = "tls")]
+    pub fn tls (mut self, tls: tls::ServerTls) -> Server {
+        self.tls = Some (tls);
+        self
+    }
+
+    /// Sets how the renewer requested by an administrator is built (and initialized), replacing
+    /// the current one, as well as those selected by renewal requests (see `renew_with`).
+    /// Without it, such requests are refused.
+    pub fn on_set_renewer<F> (mut self, on_set_renewer: F) -> Server
+        where F: FnMut(&str) -> Result<Box<dyn Renewer>> + 'static
+    {
+        self.on_set_renewer = Some (Box::new (on_set_renewer));
+        self
+    }
+
+    /// Verifies each renewal: after the renewer succeeds, the public IP address is read from
+    /// `source` every `interval` until it differs from the one before the renewal. If it doesn't
+    /// change within `timeout`, the renewal is reported as `Renewal::Unchanged` (and notified as
+    /// `Event::IPUnchanged`) rather than as renewed.
+    pub fn verify_renewals (mut self, source: IpSource, timeout: Duration, interval: Duration)
+        -> Server
+    {
+        self.verification = Some (Verification { source, timeout, interval });
+        self
+    }
+
+    /// Calls `on_event` for every event of the server, besides notifying it: all-in-one instances
+    /// use it to handle the events of their own server. Events are never coalesced.
+    pub fn on_event<F> (mut self, on_event: F) -> Server
+        where F: FnMut(Event, &EventPayload) + Send + 'static
+    {
+        self.shared.on_event = Some (Arc::new (Mutex::new (Box::new (on_event))));
+        self
+    }
+
+    /// The host name sent along with the events (see `EventPayload::origin`), rather than the one
+    /// of the machine. `None` sends none.
+    pub fn origin (mut self, origin: Option<String>) -> Server {
+        self.shared.origin = origin;
+        self
+    }
+
+    /// Keeps the latest `size` renewals in the history (100 by default), see `history`. 0 keeps
+    /// none.
+    pub fn history_size (mut self, size: usize) -> Server {
+        self.history_size = size;
+        let mut history = self.shared.history.lock().unwrap();
+        let excess = history.len().saturating_sub (size);
+        history.drain (..excess);
+        drop (history);
+        self
+    }
+
+    /// Whether the IP address is renewed on a schedule, as advertised to clients. The server
+    /// doesn't renew on its own: whoever runs it calls `renew` as scheduled (see
+    /// `Schedule::wait`).
+    pub fn scheduled (mut self, scheduled: bool) -> Server {
+        self.scheduled = scheduled;
+        self
+    }
+
+    /// Keeps the availability of renewals and the history in `state`, restoring those stored by
+    /// a previous server. An unavailability which expired in the meantime is dropped.
+    pub fn state_dir (mut self, state: StateDir) -> Server {
+        match state.load::<StoredHistory> ("history", HISTORY_VERSION) {
+            Ok(stored) => {
+                let renewals = stored.map_or (Vec::new(), |stored| stored.renewals);
+                let skipped = renewals.len().saturating_sub (self.history_size);
+                *self.shared.history.lock().unwrap() = renewals.into_iter()
+                    .skip (skipped)
+                    .map (HistoryEntry::from)
+                    .collect();
+            },
+            Err(err) => log_error_with_chain!(
+                target: "server",
+                log::Level::Warn,
+                err, "failed to restore the history of renewals: {}", err
+            )
+        }
+        let stored = state.load::<StoredAvailability> ("availability", AVAILABILITY_VERSION);
+        self.shared.state = Some (state);
+        let restored = match stored {
+            Ok(Some(StoredAvailability { reason: Some(reason), until })) => match until {
+                None => Some (RenewAvailability::Unavailable (reason)),
+                Some(until) => UNIX_EPOCH.checked_add (Duration::from_secs (until))
+                    .and_then (|until| until.duration_since (SystemTime::now()).ok())
+                    .map (|remaining| RenewAvailability::UnavailableFor (reason, remaining))
+            },
+            Ok(_) => None,
+            Err(err) => {
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "failed to restore the availability of renewals: {}", err
+                );
+                None
+            }
+        };
+        match restored {
+            Some(availability) => {
+                info!(target: "server", "restored availability: {}", availability);
+                self.shared.apply_availability (availability);
+            },
+            None => self.shared.apply_availability (RenewAvailability::Available)
+        }
+        self
+    }
+
+    /// Coalesces bursts of identical events within `window` into a single notification, see
+    /// `Coalescer`, sent to the notifier and to the subscribed clients. The notification tells how
+    /// many events were coalesced (see `EventPayload::coalesced`).
+    pub fn coalesce_events (mut self, window: Duration) -> Server {
+        let notifier = self.shared.notifier.clone();
+        let subscribers = self.shared.subscribers.clone();
+        // The payload of the latest event of a burst is notified.
+        let deliver = move |event: Event, mut payload: EventPayload, count| {
+            if count > 1 {
+                info!(target: "server", "notifying {} coalesced \"{}\" events", count, event);
+                payload.coalesced = Some (count);
+            }
+            publish (&subscribers, &event, &payload);
+            if let Err(err) = notify_now (&notifier, event.clone(), &payload) {
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "failed to notify the event \"{}\": {}", event, err
+                );
+            }
+        };
+        self.shared.coalescer = Some (Coalescer::new (window, deliver));
+        self
+    }
+
+    /// Answers the clients connecting through `streams` (e.g. `TcpListener::incoming`) from
+    /// `max_clients` threads, until there are no more or one of them fails to connect, and those
+    /// connected so far have been answered. Meanwhile, what involves the renewer (and the jobs
+    /// sent through `handle`) runs on the current thread.
+    pub fn serve<I> (&mut self, streams: I) -> Result<()>
+        where I: IntoIterator<Item = io::Result<TcpStream>>, I::IntoIter: Send
+    {
+        self.serve_with (streams, Encoding::Binary)
+    }
+
+    /// Like [`serve`](#method.serve), for clients whose packets are in `encoding`, e.g. those
+    /// connecting to a port reserved to the JSON encoding.
+    pub fn serve_with<I> (&mut self, streams: I, encoding: Encoding) -> Result<()>
+        where I: IntoIterator<Item = io::Result<TcpStream>>, I::IntoIter: Send
+    {
+        self.serve_encoded (streams.into_iter().map (move |stream| (stream, encoding)))
+    }
+
+    /// Like [`serve`](#method.serve), for clients whose packets are in the encoding paired with
+    /// their stream, e.g. those accepted from listeners of both encodings.
+    pub fn serve_encoded<I> (&mut self, streams: I) -> Result<()>
+        where I: IntoIterator<Item = (io::Result<TcpStream>, Encoding)>, I::IntoIter: Send
+    {
+        let available = self.availability().reason().is_none();
+        metrics::gauge ("server.available", if available { 1.0 } else { 0.0 });
+        let clients = self.clients();
+        let (streams, stop) = (streams.into_iter(), self.jobs.clone());
+        // Clients are handed over to a free thread, rather than queued up.
+        let (connections, accepted) = mpsc::sync_channel (0);
+        let accepted = Mutex::new (accepted);
+        thread::scope (|scope| {
+            let (clients, accepted) = (&clients, &accepted);
+            let threads: Vec<_> = (0..self.max_clients)
+                .map (|_| scope.spawn (move || clients.work (accepted)))
+                .collect();
+            let accepting = scope.spawn (move || {
+                let accepted = accept (streams, connections);
+                for thread in threads {
+                    let _ = thread.join();
+                }
+                // The clients have been answered, so no more jobs come from them.
+                let _ = stop.send (Task::Stop);
+                accepted
+            });
+            while let Ok(Task::Run(job)) = self.queue.recv() {
+                job (self);
+            }
+            accepting.join()
+                .unwrap_or_else (|_| Err("the thread accepting the clients panicked".into()))
+        })
+    }
+
+    // What the threads answering the clients need.
+    fn clients (&self) -> Clients {
+        Clients {
+            shared: self.shared.clone(),
+            handle: self.handle(),
+            admin_token: self.admin_token.clone(),
+            psk: self.psk.clone(),
+            tokens: self.tokens.clone(),
+            acl: self.acl.clone(),
+            idle_timeout: self.idle_timeout,
+            renewal_window: self.renewal_window,
+            #[cfg(feature = "tls")]
+            tls: self.tls.clone(),
+            capabilities: self.capabilities()
+        }
+    }
+
+    /// Renews the IP address and notifies it, as when a client asks for it, unless renewals are
+    /// unavailable. Verified renewals (see `verify_renewals`) only succeed once the address is
+    /// read, and tell whether it changed.
+    ///
+    /// `Event::RenewalStarted` is notified beforehand, and `Event::RenewalFailed` if it fails.
+    /// The renewal is recorded in the history as made by the server on its own.
+    pub fn renew (&mut self) -> Result<Renewal> {
+        self.renew_for (None)
+    }
+
+    // Renews as `renew` does, on behalf of `requester` (if any).
+    fn renew_for (&mut self, requester: Option<&Requester>) -> Result<Renewal> {
+        let renewer = self.shared.renewer_name();
+        self.renew_by (renewer, requester)
+    }
+
+    // Renews as `renew_for` does, with the renewer in use named `renewer` in the history.
+    fn renew_by (&mut self, renewer: Option<String>, requester: Option<&Requester>)
+        -> Result<Renewal>
+    {
+        if let Some(reason) = self.availability().reason() {
+            bail!("Renewal unavailable: {}", reason);
+        }
+        self.shared.announce (Event::RenewalStarted, requester);
+        let started = SystemTime::now();
+        let renewal = self.run_renewal();
+        self.record (started, requester.map (|requester| requester.peer), renewer, &renewal);
+        let renewal = match renewal {
+            Ok(renewal) => renewal,
+            Err(err) => {
+                self.shared.announce (Event::RenewalFailed (err.client_message()), requester);
+                return Err(err);
+            }
+        };
+        let (event, outcome) = match renewal {
+            Renewal::Renewed(ref outcome) => {
+                if !outcome.is_empty() {
+                    info!(target: "server", "renewed the IP address {}", outcome);
+                }
+                (Event::IPRenewed, outcome.clone())
+            },
+            Renewal::Unchanged(ip) => {
+                warn!(target: "server", "the renewal succeeded, but the IP is still {}", ip);
+                metrics::counter ("renewer.unchanged", 1);
+                (Event::IPUnchanged, RenewOutcome::default())
+            }
+        };
+        self.shared.notify (event, self.shared.payload (outcome, requester))?;
+        Ok(renewal)
+    }
+
+    // Renews the IP address with the renewer, verifying the renewal if required.
+    fn run_renewal (&mut self) -> Result<Renewal> {
+        let verification = self.verification.clone();
+        let before = match verification {
+            Some(ref verification) => match self.read_ip (&verification.source) {
+                Ok(ip) => Some (ip),
+                Err(err) => {
+                    log_error_with_chain!(
+                        target: "server",
+                        log::Level::Warn,
+                        err, "can't read the IP address before renewing it: {}", err
+                    );
+                    None
+                }
+            },
+            None => None
+        };
+        // Make sure that the outermost error is something safe to send to the client.
+        let renewed = metrics::time ("renewer.duration", || self.renewer.renew_ip());
+        metrics::counter (
+            if renewed.is_ok() { "renewer.renewals" } else { "renewer.failures" }, 1
+        );
+        if renewed.is_err() {
+            // The state of the link often explains the failure, e.g. a DSL link still training.
+            if let Some(link) = self.link_info() {
+                warn!(target: "server", "renewal failed, the router reports: {}", link);
+            }
+        }
+        // Timeouts are reported as such, as clients may want to try again later.
+        let timeout = renewed.as_ref().err().and_then (|err| err.timeout());
+        let outcome = renewed.chain_err (|| match timeout {
+            Some(timeout) => format!(
+                "the renewal timed out after {}", crate::config::format_duration (timeout)
+            ),
+            None => "failed to renew the IP address".into()
+        })?;
+        self.ip_check.renewed = true;
+        self.last_change = Some (Instant::now());
+        let renewal = match verification {
+            Some(ref verification) => match self.verify (verification, before)
+                .chain_err (|| "failed to verify the renewal")?
+            {
+                // The renewer knows best, the verification fills in the rest.
+                Renewal::Renewed(verified) => Renewal::Renewed (RenewOutcome {
+                    old_ip: outcome.old_ip.or (verified.old_ip),
+                    new_ip: outcome.new_ip.or (verified.new_ip)
+                }),
+                unchanged => unchanged
+            },
+            None => Renewal::Renewed (outcome)
+        };
+        Ok(renewal)
+    }
+
+    // Adds the renewal which started at `timestamp` to the history, keeping it in the state
+    // directory (if any).
+    fn record (
+        &self,
+        timestamp: SystemTime,
+        peer: Option<SocketAddr>,
+        renewer: Option<String>,
+        renewal: &Result<Renewal>
+    ) {
+        if self.history_size == 0 {
+            return;
+        }
+        let outcome = match *renewal {
+            Ok(Renewal::Renewed(ref outcome)) => HistoryOutcome::Renewed (outcome.clone()),
+            Ok(Renewal::Unchanged(ip)) => HistoryOutcome::Unchanged (ip),
+            Err(ref err) => HistoryOutcome::Failed (err.client_message())
+        };
+        let mut history = self.shared.history.lock().unwrap();
+        if history.len() == self.history_size {
+            history.pop_front();
+        }
+        history.push_back (HistoryEntry {
+            timestamp,
+            peer: peer.map (|peer| peer.to_string()),
+            renewer,
+            outcome
+        });
+        let state = match self.shared.state {
+            Some(ref state) => state,
+            None => return
+        };
+        let stored = StoredHistory {
+            renewals: history.iter().cloned().map (StoredRenewal::from).collect()
+        };
+        if let Err(err) = state.store ("history", HISTORY_VERSION, &stored) {
+            log_error_with_chain!(
+                target: "server",
+                log::Level::Warn,
+                err, "failed to store the history of renewals: {}", err
+            );
+        }
+    }
+
+    /// The latest `limit` renewals (at most `history_size`), most recent first, along with who
+    /// requested them and how they went.
+    pub fn history (&self, limit: usize) -> Vec<HistoryEntry> {
+        self.shared.history (limit)
+    }
+
+    /// Notifies `Event::ServerShuttingDown`, right away even if events are coalesced. Meant to be
+    /// called once the server stops handling clients. It's only notified once, even if a handle
+    /// notified it already (see `Handle::shut_down`).
+    pub fn shut_down (&self) {
+        self.shared.shut_down();
+    }
+
+    // Reads the IP address from the source of `verification` until it differs from `before`, the
+    // address before the renewal (any address does if it's unknown).
+    fn verify (&mut self, verification: &Verification, before: Option<IpAddr>) -> Result<Renewal> {
+        let started = Instant::now();
+        let mut last = None;
+        loop {
+            match self.read_ip (&verification.source) {
+                Ok(ip) if Some (ip) != before => {
+                    match before {
+                        Some(before) => info!(target: "server",
+                            "the IP address changed from {} to {}", before, ip),
+                        None => info!(target: "server", "the IP address is now {}", ip)
+                    }
+                    let outcome = RenewOutcome { old_ip: before, new_ip: Some (ip) };
+                    return Ok(Renewal::Renewed (outcome));
+                },
+                Ok(ip) => last = Some (ip),
+                // The connection is usually down for a while.
+                Err(err) => debug!(target: "server", "can't read the IP address yet: {}", err)
+            }
+            if started.elapsed() >= verification.timeout {
+                break;
+            }
+            thread::sleep (verification.interval);
+        }
+        match last {
+            Some(ip) => Ok(Renewal::Unchanged (ip)),
+            None => bail!(
+                "the IP address couldn't be read within {} of the renewal",
+                crate::config::format_duration (verification.timeout)
+            )
+        }
+    }
+
+    // Reads the public IP address from `source`.
+    fn read_ip (&mut self, source: &IpSource) -> Result<IpAddr> {
+        match *source {
+            IpSource::Renewer => self.renewer.link_info()
+                .chain_err (|| "failed to retrieve the link information")?
+                .and_then (|link| link.external_ip)
+                .chain_err (|| "the renewer doesn't report the external IP address"),
+            IpSource::Url(ref url) => fetch_ip (url),
+            IpSource::Stun(ref server) => stun_ip (server)
+        }
+    }
+
+    /// Reads the public IP address from `source`, and notifies `Event::IPChanged` if it changed
+    /// since the previous check although it hasn't been renewed in the meantime (e.g. because
+    /// the ISP reassigned it). Meant to be called periodically.
+    pub fn check_ip (&mut self, source: &IpSource) -> Result<()> {
+        let ip = self.read_ip (source)?;
+        let renewed = std::mem::take (&mut self.ip_check.renewed);
+        match self.ip_check.last.replace (ip) {
+            Some(last) if last != ip && !renewed => {
+                info!(target: "server", "the IP address changed from {} to {} without a renewal",
+                    last, ip);
+                self.last_change = Some (Instant::now());
+                let outcome = RenewOutcome { old_ip: Some (last), new_ip: Some (ip) };
+                self.shared.notify (Event::IPChanged, self.shared.payload (outcome, None))?;
+            },
+            Some(last) if last != ip =>
+                debug!(target: "server", "the IP address was renewed from {} to {}", last, ip),
+            _ => trace!(target: "server", "the IP address is {}", ip)
+        }
+        Ok(())
+    }
+
+    /// Whether the IP address changed (renewed, or on its own as found by `check_ip`) within the
+    /// window in which renewals are coalesced (see `coalesce_renewals`), or clients are waiting
+    /// for a renewal: renewing once more right away, e.g. on schedule, would be redundant.
+    pub fn renewed_recently (&self) -> bool {
+        !self.shared.pending.lock().unwrap().is_empty()
+            || self.last_change.is_some_and (|last| last.elapsed() <= self.renewal_window)
+    }
+
+    /// Refreshes the session of the renewer with the router, so that renewals don't have to log
+    /// in first (see `Renewer::keepalive`). Meant to be called periodically.
+    pub fn keepalive (&mut self) -> Result<()> {
+        let kept = self.renewer.keepalive();
+        metrics::counter (
+            if kept.is_ok() { "renewer.keepalives" } else { "renewer.keepalive_failures" }, 1
+        );
+        kept.chain_err (|| "failed to keep the session of the renewer alive")
+    }
+
+    /// Renews the IP address as `renew` does, but with the renewer named `instance` (its key in
+    /// `server.renewer`) rather than the one in use, e.g. to renew the address of another uplink.
+    /// The renewer is built (see `on_set_renewer`) the first time it's selected.
+    pub fn renew_with (&mut self, instance: &str) -> Result<Renewal> {
+        self.renew_with_for (instance, None)
+    }
+
+    // Renews as `renew_with` does, on behalf of `requester` (if any).
+    fn renew_with_for (&mut self, instance: &str, requester: Option<&Requester>)
+        -> Result<Renewal>
+    {
+        if self.shared.renewer_name().as_deref() == Some (instance) {
+            return self.renew_for (requester);
+        }
+        let selected = match self.selected_renewers.remove (instance) {
+            Some(renewer) => renewer,
+            None => {
+                let build = self.on_set_renewer.as_mut()
+                    .chain_err (|| "this server can't select renewers")?;
+                build (instance)
+                    .chain_err (|| format!("failed to select the renewer '{}'", instance))?
+            }
+        };
+        // The selected renewer stands in for the one in use for the whole renewal, including
+        // its verification and its entry in the history.
+        let current = std::mem::replace (&mut self.renewer, selected);
+        let renewal = self.renew_by (Some (instance.into()), requester);
+        let selected = std::mem::replace (&mut self.renewer, current);
+        self.selected_renewers.insert (instance.into(), selected);
+        renewal
+    }
+
+    // Renews with `renewer` (the one in use if `None`) on behalf of the clients waiting for it,
+    // and sends them the response. The renewal is recorded as requested by the first of them.
+    fn renew_pending (&mut self, renewer: Option<String>) {
+        let waiting = self.shared.pending.lock().unwrap().remove (&renewer).unwrap_or_default();
+        let requester = match waiting.first() {
+            Some((requester, _)) => requester.clone(),
+            None => return
+        };
+        if waiting.len() > 1 {
+            info!(target: "server", "coalescing the renewals requested by {} clients",
+                waiting.len());
+        }
+        let renewal = match renewer {
+            Some(ref renewer) => self.renew_with_for (renewer, Some (&requester)),
+            None => self.renew_for (Some (&requester))
+        };
+        let response = match renewal {
+            Ok(Renewal::Unchanged(ip)) => Packet::IPUnchanged (ip),
+            Ok(Renewal::Renewed(outcome)) if !outcome.is_empty() => Packet::Renewed (outcome),
+            Ok(Renewal::Renewed(_)) => Packet::Ok,
+            Err(err) => {
+                metrics::counter ("server.errors", waiting.len() as u64);
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "client {} produced external error: {}", requester, err
+                );
+                Packet::Error { code: err.client_code(), detail: Some (err.client_message()) }
+            }
+        };
+        for (_, response_to) in waiting {
+            let _ = response_to.send (response.clone());
+        }
+    }
+
+    /// Checks the health of the renewer (see `Renewer::health_check`), logging when it changes,
+    /// and keeps it for the clients asking about it. Meant to be called periodically, so that
+    /// broken credentials are found before the next renewal fails.
+    pub fn check_health (&mut self) -> Health {
+        let health = match self.renewer.health_check() {
+            Ok(health) => health,
+            Err(err) => {
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "failed to check the health of the renewer: {}", err
+                );
+                Health { problem: Some (err.to_string()), ..Health::default() }
+            }
+        };
+        let mut checked = self.shared.health.lock().unwrap();
+        let was_healthy = checked.as_ref().is_none_or (|(health, _)| health.is_healthy());
+        match (was_healthy, health.is_healthy()) {
+            (_, false) => warn!(target: "server", "the renewer is {}", health),
+            (false, true) => info!(target: "server", "the renewer is {} again", health),
+            (true, true) => debug!(target: "server", "the renewer is {}", health)
+        }
+        metrics::gauge ("renewer.healthy", if health.is_healthy() { 1.0 } else { 0.0 });
+        *checked = Some ((health.clone(), Instant::now()));
+        health
+    }
+
+    /// The status of the server, including what the router tells about its link.
+    pub fn status (&mut self) -> Status {
+        Status {
+            renewer: self.shared.renewer_name(),
+            availability: self.availability(),
+            link: self.link_info()
+        }
+    }
+
+    /// What the server supports, as advertised to clients.
+    pub fn capabilities (&self) -> Capabilities {
+        Capabilities {
+            version: protocol::PROTOCOL_VERSION,
+            renewer: self.shared.renewer_name(),
+            auth: self.psk.is_some() || !self.tokens.is_empty(),
+            keep_alive: !self.idle_timeout.is_zero(),
+            reload: self.accepts_admin_requests() && self.on_reload.is_some(),
+            set_renewer: self.accepts_admin_requests() && self.on_set_renewer.is_some(),
+            status: true,
+            history: self.history_size > 0,
+            schedule: self.scheduled
+        }
+    }
+
+    // Whether some client can make the requests reserved to administrators: with the admin token,
+    // or by authenticating with a token of scope `admin`.
+    fn accepts_admin_requests (&self) -> bool {
+        self.admin_token.is_some()
+            || self.tokens.iter().any (|token| token.scopes.contains (&Scope::Admin))
+    }
+
+    // Asks the renewer about the link of the router (logging failures), reporting it as metrics.
+    fn link_info (&mut self) -> Option<LinkInfo> {
+        let link = match self.renewer.link_info() {
+            Ok(link) => link?,
+            Err(err) => {
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "failed to retrieve the link information: {}", err
+                );
+                return None;
+            }
+        };
+        if let Some(uptime) = link.uptime {
+            metrics::gauge ("link.uptime", uptime.as_secs_f64());
+        }
+        if let Some(rate) = link.downstream_rate {
+            metrics::gauge ("link.downstream_rate", rate.into());
+        }
+        if let Some(rate) = link.upstream_rate {
+            metrics::gauge ("link.upstream_rate", rate.into());
+        }
+        Some (link)
+    }
+
+    /// The current availability of renewals.
+    pub fn availability (&self) -> RenewAvailability {
+        self.shared.availability()
+    }
+
+    /// Changes the availability of renewals, as when a client asks for it, and makes them
+    /// available again when it's only meant to change for a while. The change is notified as
+    /// `Event::AvailabilityChanged`.
+    pub fn set_availability (&self, new_availability: RenewAvailability) {
+        self.shared.set_availability (new_availability, None);
+    }
+
+    // Reloads the configuration, as when a client asks for it.
+    fn reload (&mut self) -> Result<()> {
+        let on_reload = self.on_reload.as_mut()
+            .chain_err (|| "this server can't reload its configuration")?;
+        on_reload().chain_err (|| "failed to reload the configuration")
+    }
+
+    // Switches to the renewer named `renewer`, as when an administrator asks for it.
+    fn switch_renewer (&mut self, renewer: String) -> Result<()> {
+        let on_set_renewer = self.on_set_renewer.as_mut()
+            .chain_err (|| "this server can't switch renewers")?;
+        self.renewer = on_set_renewer (&renewer)
+            .chain_err (|| format!("failed to switch to the renewer '{}'", renewer))?;
+        info!(target: "server", "switched to the renewer '{}'", renewer);
+        self.selected_renewers.remove (&renewer);
+        *self.shared.renewer_name.lock().unwrap() = Some (renewer);
+        *self.shared.health.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+impl Shared {
+    fn renewer_name (&self) -> Option<String> {
+        self.renewer_name.lock().unwrap().clone()
+    }
+
+    fn history (&self, limit: usize) -> Vec<HistoryEntry> {
+        self.history.lock().unwrap().iter().rev().take (limit).cloned().collect()
+    }
+
+    // The payload of an event happening now, caused by a renewal with `outcome` (if any) or by a
+    // request of `requester` (if any).
+    fn payload (&self, outcome: RenewOutcome, requester: Option<&Requester>) -> EventPayload {
+        EventPayload {
+            requested_by: requester.and_then (|requester| requester.token.clone()),
+            ..EventPayload::now (self.origin.clone(), outcome)
+        }
+    }
+
+    // Notifies `event` (along with its `payload`) and sends it to the subscribed clients right
+    // away, or through the coalescer if events are coalesced, after handing it to `on_event`.
+    fn notify (&self, event: Event, payload: EventPayload) -> Result<()> {
+        if let Some(ref on_event) = self.on_event {
+            (on_event.lock().unwrap()) (event.clone(), &payload);
+        }
+        match self.coalescer {
+            Some(ref coalescer) => coalescer.push (event, payload),
+            None => {
+                publish (&self.subscribers, &event, &payload);
+                notify_now (&self.notifier, event, &payload)
+                    .chain_err (|| "failed to notify the requested event")?
+            }
+        }
+        Ok(())
+    }
+
+    // See `Server::shut_down`.
+    fn shut_down (&self) {
+        if self.shutting_down.swap (true, Ordering::SeqCst) {
+            return;
+        }
+        info!(target: "server", "shutting down");
+        let payload = self.payload (RenewOutcome::default(), None);
+        if let Some(on_event) = self.on_event.as_deref() {
+            (on_event.lock().unwrap()) (Event::ServerShuttingDown, &payload);
+        }
+        publish (&self.subscribers, &Event::ServerShuttingDown, &payload);
+        let notified = notify_now (&self.notifier, Event::ServerShuttingDown, &payload);
+        if let Err(err) = notified {
+            log_error_with_chain!(
+                target: "server",
+                log::Level::Warn,
+                err, "failed to notify the shutdown: {}", err
+            );
+        }
+    }
+
+    // Notifies `event`, which doesn't carry the outcome of a renewal, caused by `requester` (if
+    // any). Failures are only logged, as they shouldn't fail what the event is about.
+    fn announce (&self, event: Event, requester: Option<&Requester>) {
+        if let Err(err) = self.notify (event, self.payload (RenewOutcome::default(), requester)) {
+            log_error_with_chain!(
+                target: "server",
+                log::Level::Warn,
+                err, "{}", err
+            );
+        }
+    }
+
+    fn availability (&self) -> RenewAvailability {
+        self.availability.lock().unwrap().current.clone()
+    }
+
+    // See `Server::set_availability`, as requested by `requester` (if any).
+    fn set_availability (
+        &self,
+        new_availability: RenewAvailability,
+        requester: Option<&Requester>
+    ) {
+        self.apply_availability (new_availability.clone());
+        self.announce (Event::AvailabilityChanged (new_availability), requester);
+    }
+
+    // Changes the availability of renewals without notifying it, see `set_availability`.
+    fn apply_availability (&self, new_availability: RenewAvailability) {
+        let mut availability = self.availability.lock().unwrap();
+        availability.generation += 1;
+        let generation = availability.generation;
+        store_availability (self.state.as_ref(), &new_availability);
+        if let RenewAvailability::UnavailableFor(_, duration) = new_availability {
+            let shared = self.clone();
+            thread::spawn (move || {
+                thread::sleep (duration);
+                let mut availability = shared.availability.lock().unwrap();
+                if availability.generation != generation {
+                    return;
+                }
+                availability.current = RenewAvailability::Available;
+                store_availability (shared.state.as_ref(), &availability.current);
+                drop (availability);
+                metrics::gauge ("server.available", 1.0);
+                info!(target: "server", "renewals are available again");
+                let payload = shared.payload (RenewOutcome::default(), None);
+                if let Err(err) = shared.notify (Event::UnavailabilityExpired, payload) {
+                    log_error_with_chain!(
+                        target: "server",
+                        log::Level::Warn,
+                        err, "failed to notify the expired unavailability: {}", err
+                    );
+                }
+            });
+        }
+        let available = if new_availability.reason().is_some() { 0.0 } else { 1.0 };
+        metrics::gauge ("server.available", available);
+        availability.current = new_availability;
+    }
+}
+
+// What the threads answering the clients need, see `Server::serve_encoded`. What involves the
+// renewer is run by the server, through `handle`.
+struct Clients {
+    shared: Shared,
+    handle: Handle,
+    admin_token: Option<Secret<String>>,
+    psk: Option<Secret<String>>,
+    tokens: Vec<Token>,
+    acl: Acl,
+    idle_timeout: Duration,
+    renewal_window: Duration,
+    #[cfg(feature = "tls")]
+    tls: Option<tls::ServerTls>,
+    // What the server supports, but the renewer in use, which can change.
+    capabilities: Capabilities
+}
+
+impl Clients {
+    // Answers the clients handed over through `accepted` one at a time, until there are no more.
+    fn work (&self, accepted: &Mutex<mpsc::Receiver<(TcpStream, Encoding)>>) {
+        loop {
+            let next = accepted.lock().unwrap().recv();
+            let (stream, encoding) = match next {
+                Ok(client) => client,
+                Err(_) => return
+            };
+            // Every message logged while handling this client is tagged with the same id.
+            let _correlation = logging::correlate();
+            if let Err(err) = self.handle (&stream, encoding) {
+                log_error_with_chain!(
+                    target: "server",
+                    log::Level::Warn,
+                    err, "{}", err
+                );
+            }
+        }
+    }
+
+    // Waits for the renewal requested by `requester` with `renewer` (if any), along with the
+    // other clients requesting it meanwhile, and returns the response to send.
+    fn renew (&self, renewer: Option<String>, requester: &Requester) -> Result<Packet> {
+        let (response_to, response) = mpsc::channel();
+        let first = {
+            let mut pending = self.shared.pending.lock().unwrap();
+            let waiting = pending.entry (renewer.clone()).or_default();
+            waiting.push ((requester.clone(), response_to));
+            waiting.len() == 1
+        };
+        // The first client waits for the others, then asks the server to renew for all of them.
+        if first {
+            thread::sleep (self.renewal_window);
+            let renewing = renewer.clone();
+            if let Err(err) = self.handle.spawn (move |server| server.renew_pending (renewing)) {
+                // Those waiting are answered with an error, rather than waiting forever.
+                self.shared.pending.lock().unwrap().remove (&renewer);
+                return Err(err);
+            }
+        } else {
+            debug!(target: "server", "client {} joined a pending renewal", requester);
+        }
+        response.recv().ok().chain_err (|| "the server stopped before renewing")
+    }
+
+    // Answers the requests of a client, over TLS if configured.
+    fn handle (&self, stream: &TcpStream, encoding: Encoding) -> Result<()> {
+        let peer_addr = stream.peer_addr().chain_err (|| "failed to retrieve peer address")?;
+        debug!(target: "server", "new client connected: {}", peer_addr);
+        metrics::counter ("server.connections", 1);
+        if !self.acl.allows_client (peer_addr.ip()) {
+            warn!(target: "server", "closing the connection of client {}, which isn't allowed",
+                peer_addr);
+            metrics::counter ("server.refused_clients", 1);
+            return Ok(());
+        }
+        stream.set_read_timeout (Some (REQUEST_TIMEOUT))
+            .chain_err (|| "failed to set stream read timeout to 5 seconds")?;
+        #[cfg(feature = "tls")]
+        if let Some(tls) = self.tls.clone() {
+            let tls_stream = tls.accept (
+                stream.try_clone().chain_err (|| "failed to clone the I/O stream")?
+            )?;
+            if self.answer (&tls_stream, stream, peer_addr, encoding) {
+                self.subscribe (tls_stream, stream, peer_addr, encoding)?;
+            }
+            return Ok(());
+        }
+        if self.answer (stream, stream, peer_addr, encoding) {
+            let owned = stream.try_clone().chain_err (|| "failed to clone the I/O stream")?;
+            self.subscribe (owned, stream, peer_addr, encoding)?;
+        }
+        Ok(())
+    }
+
+    // Answers the requests of the client at `peer_addr` through `stream` (a `TcpStream` or a
+    // `tls::Stream` over `socket`), until it closes the connection or leaves it idle for longer
+    // than the idle timeout. Returns whether the client subscribed to the events.
+    fn answer<S> (
+        &self,
+        stream: &S,
+        socket: &TcpStream,
+        peer_addr: SocketAddr,
+        encoding: Encoding
+    ) -> bool
+        where for<'s> &'s S: Read + Write
+    {
+        let mut writer = BufWriter::new (stream);
+        let mut reader = BufReader::new (stream);
+        let mut identity = Identity::Unknown;
+        loop {
+            let next = self.answer_request (
+                &mut reader, &mut writer, peer_addr, encoding, &mut identity
+            );
+            if let Err(err) = writer.flush() {
+                debug!(target: "server", "failed to answer client {}: {}", peer_addr, err);
+                return false;
+            }
+            match next {
+                Next::Close => return false,
+                Next::Subscribe => return true,
+                Next::KeepOpen if !self.await_request (&mut reader, socket, peer_addr) =>
+                    return false,
+                Next::KeepOpen => ()
+            }
+        }
+    }
+
+    // Sends the events to the client at `peer_addr` through `stream` (over `socket`) from a
+    // thread of its own, until it disconnects.
+    fn subscribe<S> (
+        &self,
+        stream: S,
+        socket: &TcpStream,
+        peer_addr: SocketAddr,
+        encoding: Encoding
+    ) -> Result<()>
+        where S: Send + 'static, for<'s> &'s S: Write
+    {
+        let socket = socket.try_clone().chain_err (|| "failed to clone the I/O stream")?;
+        // Clients which stop reading are dropped, rather than blocking their thread forever.
+        socket.set_write_timeout (Some (REQUEST_TIMEOUT))
+            .chain_err (|| "failed to set stream write timeout to 5 seconds")?;
+        let (sender, events) = mpsc::channel::<(Event, EventPayload)>();
+        let thread = thread::spawn (move || {
+            loop {
+                let (event, payload) = match events.recv_timeout (REQUEST_TIMEOUT) {
+                    Ok(event) => event,
+                    Err(RecvTimeoutError::Timeout) if connected (&socket) => continue,
+                    Err(_) => break
+                };
+                let mut writer = BufWriter::new (&stream);
+                let sent = Packet::Event (event, payload)
+                    .send_as (encoding, &mut writer, &peer_addr)
+                    .map_err (Error::from)
+                    .and_then (|_| writer.flush().chain_err (|| "failed to flush the I/O stream"));
+                if let Err(err) = sent {
+                    debug!(target: "server", "failed to send an event to client {}: {}",
+                        peer_addr, err);
+                    break;
+                }
+            }
+            debug!(target: "server", "client {} unsubscribed from the events", peer_addr);
+        });
+        self.shared.subscribers.lock().unwrap().push (Subscriber { events: sender, thread });
+        Ok(())
+    }
+
+    // Why a request reserved to administrators, along with `token`, is refused from a client
+    // which proved to be `identity`, if it is. Clients which authenticated with a token of scope
+    // `admin` don't need the admin token of the server.
+    fn admin_refusal (&self, identity: Identity, token: &Secret<String>) -> Option<&'static str> {
+        if let Identity::Token(authenticated) = identity {
+            if authenticated.scopes.contains (&Scope::Admin) {
+                return None;
+            }
+        }
+        match self.admin_token {
+            None => Some ("This server doesn't accept admin requests"),
+            Some(ref expected) if !tokens_match (expected, token) => Some ("Invalid admin token"),
+            Some(_) => None
+        }
+    }
+
+    // Authenticates the client at `peer_addr`, if the pre-shared key or tokens are required, by
+    // challenging it to answer a nonce with its HMAC. Returns who it proved to be, if anyone.
+    fn authenticate<S> (
+        &self,
+        reader: &mut BufReader<&S>,
+        writer: &mut BufWriter<&S>,
+        peer_addr: SocketAddr,
+        encoding: Encoding
+    ) -> Result<Option<Identity<'_>>>
+        where for<'s> &'s S: Read + Write
+    {
+        if self.psk.is_none() && self.tokens.is_empty() {
+            return Ok(Some (Identity::Trusted));
+        }
+        let nonce = nonce()?;
+        Packet::AuthChallenge (nonce).send_as (encoding, writer, &peer_addr)?;
+        writer.flush().chain_err (|| "failed to flush the I/O stream")?;
+        let response = match Packet::receive_as (encoding, reader, &peer_addr)
+            .chain_err (|| "invalid packet")?
+        {
+            Packet::AuthResponse(response) => response,
+            _ => return Ok(None)
+        };
+        let knows = |secret| bytes_match (&protocol::auth_response (secret, &nonce), &response);
+        if self.psk.as_ref().is_some_and (knows) {
+            return Ok(Some (Identity::Trusted));
+        }
+        Ok(self.tokens.iter().find (|token| knows (&token.secret)).map (Identity::Token))
+    }
+
+    // Waits for the next request of the client at `peer_addr` for up to the idle timeout,
+    // returning whether it arrived.
+    fn await_request<R: BufRead> (&self, reader: &mut R, socket: &TcpStream, peer_addr: SocketAddr)
+        -> bool
+    {
+        if self.idle_timeout.is_zero() {
+            return false;
+        }
+        if socket.set_read_timeout (Some (self.idle_timeout)).is_err() {
+            return false;
+        }
+        let waiting = reader.fill_buf().map (|buffer| !buffer.is_empty());
+        if socket.set_read_timeout (Some (REQUEST_TIMEOUT)).is_err() {
+            return false;
+        }
+        match waiting {
+            Ok(waiting) => {
+                if !waiting {
+                    debug!(target: "server", "client {} closed the connection", peer_addr);
+                }
+                waiting
+            },
+            Err(ref err)
+                if matches!(err.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) =>
+            {
+                debug!(target: "server", "closing the idle connection of client {}", peer_addr);
+                false
+            },
+            Err(err) => {
+                debug!(target: "server", "the connection of client {} failed: {}", peer_addr, err);
+                false
+            }
+        }
+    }
+
+    // Answers a request of the client at `peer_addr`, authenticating it first if its `identity`
+    // is still unknown. Errors are sent to the client rather than returned. Returns whether the
+    // connection can be kept open, i.e. the request was read in full, or turned into a
+    // subscription.
+    fn answer_request<'t, S> (
+        &'t self,
+        reader: &mut BufReader<&S>,
+        writer: &mut BufWriter<&S>,
+        peer_addr: SocketAddr,
+        encoding: Encoding,
+        identity: &mut Identity<'t>
+    ) -> Next
+        where for<'s> &'s S: Read + Write
+    {
+        // Local macro to make returning errors easy.
+        macro_rules! error_packet {
+            ($writer: ident, $peer: ident, $code: ident, $($message: tt),+) => {{
+                let msg = format!($($message),+);
+                warn!(target: "server", "client produced error: {}", msg);
+                metrics::counter ("server.errors", 1);
+                Packet::Error { code: ErrorCode::$code, detail: Some (msg) }
+                    .send_as (encoding, $writer, &$peer)
+                    .map_err (|e| e.into())
+            }}
+        }
+        metrics::counter ("server.requests", 1);
+        let mut next = Next::Close;
+
+        // poor man's try-catch block
+        let result = (|| -> Result<()> {
+            if let Identity::Unknown = *identity {
+                *identity = match self.authenticate (reader, writer, peer_addr, encoding)? {
+                    Some(identity) => identity,
+                    None => {
+                        metrics::counter ("server.auth_failures", 1);
+                        return error_packet!(writer, peer_addr, Unauthorized,
+                            "Authentication failed");
+                    }
+                };
+            }
+            let requester = Requester {
+                peer: peer_addr,
+                token: match *identity {
+                    Identity::Token(token) => Some (token.name.clone()),
+                    _ => None
+                }
+            };
+            let packet = Packet::receive_as (encoding, reader, &peer_addr)
+                .chain_err (|| "invalid packet")?;
+            // Whatever happens next, the following request can be read.
+            next = Next::KeepOpen;
+            let refused = Action::of (&packet)
+                .filter (|&action| !self.acl.allows (action, peer_addr.ip()));
+            if let Some(action) = refused {
+                return error_packet!(writer, peer_addr, Unauthorized,
+                    "This client isn't allowed to request '{}'", action);
+            }
+            if let (Identity::Token(token), Some(action)) = (*identity, Action::of (&packet)) {
+                if !token.allows (action) {
+                    let name = &token.name;
+                    return error_packet!(writer, peer_addr, Unauthorized,
+                        "The token '{}' isn't allowed to request '{}'", name, action);
+                }
+            }
+            match packet {
+                Packet::FreshIPRequest { renewer } => {
+                    match renewer {
+                        Some(ref renewer) => info!(target: "server",
+                            "client {} requested a new IP address from the renewer '{}'",
+                            requester, renewer),
+                        None => info!(target: "server", "client {} requested a new IP address",
+                            requester)
+                    }
+                    if let Some(reason) = self.shared.availability().reason() {
+                        return error_packet!(writer, peer_addr, Unavailable,
+                            "Renewal unavailable: {}", reason);
+                    }
+                    return self.renew (renewer, &requester)?
+                        .send_as (encoding, writer, &peer_addr)
+                        .map_err (|e| e.into());
+                },
+                Packet::SetRenewingAvailable (new_availability) => {
+                    info!(target: "server", "client {} set availability to {}",
+                        requester, new_availability);
+                    self.shared.set_availability (new_availability, Some (&requester));
+                },
+                Packet::ReloadConfig { token } => {
+                    info!(target: "server", "client {} requested a configuration reload",
+                        requester);
+                    if let Some(refusal) = self.admin_refusal (*identity, &token) {
+                        return error_packet!(writer, peer_addr, Unauthorized, "{}", refusal);
+                    }
+                    if !self.capabilities.reload {
+                        return error_packet!(writer, peer_addr, Unsupported,
+                            "This server can't reload its configuration");
+                    }
+                    self.handle.run (|server| server.reload())??;
+                },
+                Packet::SetRenewer { renewer, token } => {
+                    info!(target: "server", "client {} requested to switch to the renewer '{}'",
+                        requester, renewer);
+                    if let Some(refusal) = self.admin_refusal (*identity, &token) {
+                        return error_packet!(writer, peer_addr, Unauthorized, "{}", refusal);
+                    }
+                    // With an admin token, switching renewers is only unsupported without a way
+                    // to build them.
+                    if !self.capabilities.set_renewer {
+                        return error_packet!(writer, peer_addr, Unsupported,
+                            "This server can't switch renewers");
+                    }
+                    self.handle.run (move |server| server.switch_renewer (renewer))??;
+                },
+                Packet::StatusRequest => {
+                    debug!(target: "server", "client {} requested the status", peer_addr);
+                    return Packet::Status (self.handle.run (|server| server.status())?)
+                        .send_as (encoding, writer, &peer_addr)
+                        .map_err (|e| e.into());
+                },
+                Packet::HealthRequest => {
+                    debug!(target: "server", "client {} requested the health", peer_addr);
+                    // Without periodic checks, the renewer is checked on request.
+                    let checked = self.shared.health.lock().unwrap().clone();
+                    let (health, age) = match checked {
+                        Some((health, checked)) => (health, checked.elapsed()),
+                        None => (self.handle.run (|server| server.check_health())?, Duration::ZERO)
+                    };
+                    return Packet::Health { health, age }
+                        .send_as (encoding, writer, &peer_addr)
+                        .map_err (|e| e.into());
+                },
+                Packet::Subscribe => {
+                    info!(target: "server", "client {} subscribed to the events", requester);
+                    let mut subscribers = self.shared.subscribers.lock().unwrap();
+                    // Forget the clients which disconnected since the latest event.
+                    subscribers.retain (|subscriber| !subscriber.thread.is_finished());
+                    if subscribers.len() >= MAX_SUBSCRIBERS {
+                        return error_packet!(writer, peer_addr, RateLimited,
+                            "Too many clients are subscribed to the events");
+                    }
+                    next = Next::Subscribe;
+                },
+                Packet::CapabilitiesRequest => {
+                    debug!(target: "server", "client {} requested the capabilities", peer_addr);
+                    let renewer = self.shared.renewer_name();
+                    let capabilities = Capabilities { renewer, ..self.capabilities.clone() };
+                    return Packet::Capabilities (capabilities)
+                        .send_as (encoding, writer, &peer_addr)
+                        .map_err (|e| e.into());
+                },
+                Packet::HistoryRequest { limit } => {
+                    debug!(target: "server", "client {} requested the history", peer_addr);
+                    if !self.capabilities.history {
+                        return error_packet!(writer, peer_addr, Unsupported,
+                            "This server doesn't keep a history of renewals");
+                    }
+                    return Packet::History (self.shared.history (limit.into()))
+                        .send_as (encoding, writer, &peer_addr)
+                        .map_err (|e| e.into());
+                },
+                _ => return error_packet!(writer, peer_addr, Unsupported, "Unsupported packet")
+            };
+            Packet::Ok.send_as (encoding, writer, &peer_addr)?;
+            Ok(())
+        })();
+
+        if let Err(err) = result {
+            metrics::counter ("server.errors", 1);
+            log_error_with_chain!(
+                target: "server",
+                log::Level::Warn,
+                err, "client {} produced external error: {}", peer_addr, err
+            );
+
+            // ignore errors while writing errors
+            let error = Packet::Error {
+                code: err.client_code(),
+                detail: Some (err.client_message())
+            };
+            let _ = error.send_as (encoding, writer, &peer_addr);
+        }
+        next
+    }
+}
+
+// Hands the clients connecting through `streams` over to the threads answering them, until there
+// are no more or one of them fails to connect.
+fn accept<I> (streams: I, connections: mpsc::SyncSender<(TcpStream, Encoding)>) -> Result<()>
+    where I: Iterator<Item = (io::Result<TcpStream>, Encoding)>
+{
+    for (stream, encoding) in streams {
+        let stream = stream.chain_err (|| "failed to retrieve I/O stream")?;
+        if connections.send ((stream, encoding)).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+// Keeps `availability` in `state`, if any. Failures are only logged, as the server keeps working
+// without its state.
+fn store_availability (state: Option<&StateDir>, availability: &RenewAvailability) {
+    let state = match state {
+        Some(state) => state,
+        None => return
+    };
+    let stored = match *availability {
+        RenewAvailability::Available => state.remove ("availability"),
+        RenewAvailability::Unavailable(ref reason) => state.store (
+            "availability",
+            AVAILABILITY_VERSION,
+            &StoredAvailability { reason: Some (reason.clone()), until: None }
+        ),
+        RenewAvailability::UnavailableFor(ref reason, duration) => {
+            // Rounded up, so that renewals are never available again too early.
+            let until = SystemTime::now().duration_since (UNIX_EPOCH).unwrap_or_default()
+                + duration + Duration::from_millis (999);
+            state.store (
+                "availability",
+                AVAILABILITY_VERSION,
+                &StoredAvailability { reason: Some (reason.clone()), until: Some (until.as_secs()) }
+            )
+        }
+    };
+    if let Err(err) = stored {
+        log_error_with_chain!(
+            target: "server",
+            log::Level::Warn,
+            err, "failed to store the availability of renewals: {}", err
+        );
+    }
+}
+
+impl From<HistoryEntry> for StoredRenewal {
+    fn from (entry: HistoryEntry) -> StoredRenewal {
+        let mut stored = StoredRenewal {
+            timestamp: entry.timestamp.duration_since (UNIX_EPOCH).map_or (0, |d| d.as_secs()),
+            peer: entry.peer,
+            renewer: entry.renewer,
+            old_ip: None,
+            new_ip: None,
+            unchanged: false,
+            error: None
+        };
+        match entry.outcome {
+            HistoryOutcome::Renewed(outcome) => {
+                stored.old_ip = outcome.old_ip;
+                stored.new_ip = outcome.new_ip;
+            },
+            HistoryOutcome::Unchanged(ip) => {
+                stored.new_ip = Some (ip);
+                stored.unchanged = true;
+            },
+            HistoryOutcome::Failed(reason) => stored.error = Some (reason)
+        }
+        stored
+    }
+}
+
+impl From<StoredRenewal> for HistoryEntry {
+    fn from (stored: StoredRenewal) -> HistoryEntry {
+        let outcome = match (stored.error, stored.new_ip) {
+            (Some(reason), _) => HistoryOutcome::Failed (reason),
+            (None, Some(ip)) if stored.unchanged => HistoryOutcome::Unchanged (ip),
+            (None, new_ip) =>
+                HistoryOutcome::Renewed (RenewOutcome { old_ip: stored.old_ip, new_ip })
+        };
+        HistoryEntry {
+            timestamp: UNIX_EPOCH.checked_add (Duration::from_secs (stored.timestamp))
+                .unwrap_or (UNIX_EPOCH),
+            peer: stored.peer,
+            renewer: stored.renewer,
+            outcome
+        }
+    }
+}
+
+// Sends `event` to the subscribed clients, forgetting those which disconnected.
+fn publish (subscribers: &Subscribers, event: &Event, payload: &EventPayload) {
+    subscribers.lock().unwrap().retain (|subscriber| {
+        subscriber.events.send ((event.clone(), payload.clone())).is_ok()
+    });
+}
+
+// Whether the peer of `socket` is still connected, without reading what it sent.
+fn connected (socket: &TcpStream) -> bool {
+    if socket.set_nonblocking (true).is_err() {
+        return false;
+    }
+    let connected = match socket.peek (&mut [0]) {
+        Ok(read) => read > 0,
+        Err(err) => err.kind() == io::ErrorKind::WouldBlock
+    };
+    socket.set_nonblocking (false).is_ok() && connected
+}
+
+// Notifies `event` with `notifier`, counting the events notified and the failures.
+fn notify_now (notifier: &Mutex<Box<dyn Notifier>>, event: Event, payload: &EventPayload)
+    -> notifier::Result<()>
+{
+    let notified = notifier.lock().unwrap().notify_with_payload (event, payload);
+    metrics::counter (
+        if notified.is_ok() { "notifier.events" } else { "notifier.failures" }, 1
+    );
+    notified
+}
+
+// The host name of the machine, if it can be found: from the environment on Windows, and from the
+// kernel (or `/etc/hostname`) elsewhere.
+fn hostname() -> Option<String> {
+    #[cfg(windows)]
+    let hostname = std::env::var ("COMPUTERNAME").ok();
+    #[cfg(not(windows))]
+    let hostname = ["/proc/sys/kernel/hostname", "/etc/hostname"].iter()
+        .find_map (|path| std::fs::read_to_string (path).ok());
+    hostname.map (|name| name.trim().to_string()).filter (|name| !name.is_empty())
+}
+
+// Compares `token` with the `expected` one in constant time, so that how long it takes doesn't
+// tell how much of the token is right. Empty tokens never match.
+fn tokens_match (expected: &Secret<String>, token: &Secret<String>) -> bool {
+    let expected = expected.expose().as_bytes();
+    !expected.is_empty() && bytes_match (expected, token.expose().as_bytes())
+}
+
+// Compares `actual` with the `expected` bytes in constant time, see `tokens_match`.
+fn bytes_match (expected: &[u8], actual: &[u8]) -> bool {
+    expected.len() == actual.len() &&
+        expected.iter().zip (actual).fold (0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+// A nonce for `Packet::AuthChallenge`, which clients can't predict: it's read from the random
+// number generator of the operating system.
+fn nonce() -> Result<[u8; protocol::AUTH_LEN]> {
+    let mut nonce = [0; protocol::AUTH_LEN];
+    getrandom::getrandom (&mut nonce)
+        .chain_err (|| "failed to generate the nonce of the authentication challenge")?;
+    Ok(nonce)
+}
+
+// Reads the public IP address from `url`, which answers with it as plain text.
+#[cfg(feature = "http-client")]
+fn fetch_ip (url: &str) -> Result<IpAddr> {
+    let response = crate::http_client::get (url)
+        .chain_err (|| format!("HTTP request to '{}' failed", url))?;
+    ensure!(
+        response.status().is_success(),
+        "failed to retrieve the IP address from '{}', got status {}", url, response.status()
+    );
+    response.body().trim().parse()
+        .chain_err (|| format!("'{}' didn't answer with an IP address", url))
+}
+
+#[cfg(not(feature = "http-client"))]
+fn fetch_ip (_url: &str) -> Result<IpAddr> {
+    bail!("reading the IP address from a URL requires the feature 'http-client'")
+}
+
+// The magic cookie of STUN messages (RFC 5389), which also obfuscates XOR-MAPPED-ADDRESS.
+const STUN_COOKIE: u32 = 0x2112_a442;
+
+// Reads the public IP address from the STUN server `server` ("host:port"), with a Binding request.
+fn stun_ip (server: &str) -> Result<IpAddr> {
+    use byteorder::{ByteOrder, NetworkEndian};
+    use std::collections::hash_map::RandomState;
+    use std::hash::BuildHasher;
+    use std::net::{ToSocketAddrs, UdpSocket};
+
+    let addr = server.to_socket_addrs().ok()
+        .and_then (|mut addrs| addrs.next())
+        .chain_err (|| format!("failed to resolve the STUN server '{}'", server))?;
+    let socket = UdpSocket::bind (if addr.is_ipv4() { "0.0.0.0:0" } else { "[::]:0" })
+        .chain_err (|| "failed to open a UDP socket")?;
+    socket.set_read_timeout (Some (Duration::from_secs (5)))
+        .chain_err (|| "failed to set the timeout of the UDP socket")?;
+    // Type (Binding request), length of the attributes (none), cookie and transaction id.
+    let mut request = [0u8; 20];
+    NetworkEndian::write_u16 (&mut request[0..2], 0x0001);
+    NetworkEndian::write_u32 (&mut request[4..8], STUN_COOKIE);
+    let random = RandomState::new();
+    NetworkEndian::write_u64 (&mut request[8..16], random.hash_one (SystemTime::now()));
+    NetworkEndian::write_u32 (&mut request[16..20], random.hash_one (server) as u32);
+    socket.send_to (&request, addr)
+        .chain_err (|| format!("failed to send the request to the STUN server '{}'", server))?;
+    let mut response = [0u8; 576];
+    let length = socket.recv (&mut response)
+        .chain_err (|| format!("the STUN server '{}' didn't answer", server))?;
+    let response = &response[..length];
+    ensure!(
+        response.len() >= 20 && NetworkEndian::read_u16 (&response[0..2]) == 0x0101 &&
+            response[4..20] == request[4..20],
+        "the STUN server '{}' sent an invalid response", server
+    );
+    // Attributes: type, length and value, padded to 4 bytes.
+    let mut attributes = &response[20..];
+    while attributes.len() >= 4 {
+        let kind = NetworkEndian::read_u16 (&attributes[0..2]);
+        let length = NetworkEndian::read_u16 (&attributes[2..4]) as usize;
+        let value = attributes.get (4..4 + length)
+            .chain_err (|| format!("the STUN server '{}' sent a truncated response", server))?;
+        // MAPPED-ADDRESS and XOR-MAPPED-ADDRESS: reserved byte, family, port and address.
+        if (kind == 0x0001 || kind == 0x0020) && value.len() >= 8 {
+            let mut ip = value[4..].to_vec();
+            if kind == 0x0020 {
+                // The address is XORed with the cookie followed by the transaction id.
+                for (byte, mask) in ip.iter_mut().zip (&request[4..20]) {
+                    *byte ^= mask;
+                }
+            }
+            // The family is 1 for IPv4, 2 for IPv6.
+            match value[1] {
+                1 => if let Ok(octets) = <[u8; 4]>::try_from (&ip[..]) {
+                    return Ok(octets.into());
+                },
+                2 => if let Ok(octets) = <[u8; 16]>::try_from (&ip[..]) {
+                    return Ok(octets.into());
+                },
+                _ => ()
+            }
+        }
+        attributes = attributes.get ((4 + length).next_multiple_of (4)..).unwrap_or_default();
+    }
+    bail!("the STUN server '{}' didn't tell the IP address", server)
+}
@@ -0,0 +1,134 @@
+//! State which survives restarts (such as the availability of renewals), kept in the directory
+//! set with `state_dir`. Each piece of state lives in its own file named after it, along with the
+//! version of its schema, and is replaced atomically.
+
+use crate::errors::Cause;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("{0}")]
+    Msg(String),
+    #[error("{0}")]
+    Chained(Box<Error>, #[source] Cause),
+    /// The state has been stored with a schema other than the expected one.
+    #[error("the state '{0}' has schema version {1}, while version {2} is supported")]
+    UnsupportedVersion(String, u32, u32)
+}
+
+chained_error!(Error);
+
+// What is written to the file of each piece of state.
+#[derive(Serialize, Deserialize)]
+struct Stored<T> {
+    version: u32,
+    state: T
+}
+
+/// The directory where the state is kept.
+#[derive(Debug, Clone)]
+pub struct StateDir {
+    path: PathBuf
+}
+
+impl StateDir {
+    /// Uses the directory `path`, creating it if it doesn't exist.
+    pub fn open<P: Into<PathBuf>> (path: P) -> Result<StateDir> {
+        let path = path.into();
+        fs::create_dir_all (&path)
+            .chain_err (|| format!("can't create the state directory '{}'", path.display()))?;
+        Ok(StateDir { path })
+    }
+
+    pub fn path (&self) -> &Path {
+        &self.path
+    }
+
+    fn file (&self, name: &str) -> PathBuf {
+        self.path.join (format!("{}.toml", name))
+    }
+
+    /// Loads the state `name`, which must have been stored with the schema `version`. Returns
+    /// `None` if it has never been stored.
+    pub fn load<T: DeserializeOwned> (&self, name: &str, version: u32) -> Result<Option<T>> {
+        let file = self.file (name);
+        let contents = match fs::read_to_string (&file) {
+            Ok(contents) => contents,
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(error) => return Err(error)
+                .chain_err (|| format!("can't read the state file '{}'", file.display()))
+        };
+        let stored: Stored<toml::Value> = toml::from_str (&contents)
+            .chain_err (|| format!("can't parse the state file '{}'", file.display()))?;
+        if stored.version != version {
+            bail!(Error::UnsupportedVersion (name.into(), stored.version, version));
+        }
+        stored.state.try_into()
+            .map (Some)
+            .chain_err (|| format!("can't parse the state file '{}'", file.display()))
+    }
+
+    /// Stores the state `name` with the schema `version`, replacing the previous one. The state
+    /// is written to a temporary file first, so that the previous one is never lost halfway.
+    pub fn store<T: Serialize> (&self, name: &str, version: u32, state: &T) -> Result<()> {
+        let file = self.file (name);
+        let contents = toml::to_string (&Stored { version, state })
+            .chain_err (|| format!("can't serialize the state '{}'", name))?;
+        let temporary = self.path.join (format!(".{}.toml.tmp", name));
+        (|| -> io::Result<()> {
+            let mut writer = fs::File::create (&temporary)?;
+            writer.write_all (contents.as_bytes())?;
+            writer.sync_all()?;
+            fs::rename (&temporary, &file)
+        })().chain_err (|| format!("can't write the state file '{}'", file.display()))
+    }
+
+    /// Removes the state `name`, if it has been stored.
+    pub fn remove (&self, name: &str) -> Result<()> {
+        let file = self.file (name);
+        match fs::remove_file (&file) {
+            Err(error) if error.kind() != io::ErrorKind::NotFound => Err(error)
+                .chain_err (|| format!("can't remove the state file '{}'", file.display())),
+            _ => Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Serialize, Deserialize, Debug, PartialEq)]
+    struct Counter {
+        count: u32,
+        name: Option<String>
+    }
+
+    #[test]
+    fn state_is_stored_and_loaded() {
+        let path = std::env::temp_dir().join (format!("oxixenon-state-{}", std::process::id()));
+        let state = StateDir::open (path.join ("nested")).unwrap();
+        assert_eq!(state.load::<Counter> ("counter", 1).unwrap(), None);
+
+        let counter = Counter { count: 3, name: Some ("renewals".into()) };
+        state.store ("counter", 1, &counter).unwrap();
+        assert_eq!(state.load ("counter", 1).unwrap(), Some (counter));
+        match state.load::<Counter> ("counter", 2) {
+            Err(Error::UnsupportedVersion(name, 1, 2)) => assert_eq!(name, "counter"),
+            result => panic!("unexpected result {:?}", result)
+        }
+        // Only the state itself is left in the directory.
+        let files: Vec<_> = fs::read_dir (state.path()).unwrap()
+            .map (|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(files, vec!["counter.toml"]);
+
+        state.remove ("counter").unwrap();
+        state.remove ("counter").unwrap();
+        assert_eq!(state.load::<Counter> ("counter", 1).unwrap(), None);
+        fs::remove_dir_all (path).unwrap();
+    }
+}
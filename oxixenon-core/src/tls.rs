@@ -0,0 +1,185 @@
+//! TLS for the connections between clients and servers (feature `tls`), so that they can cross
+//! untrusted networks: servers present a certificate, and can require one from their clients
+//! too.
+//!
+//! Certificates and private keys are read from PEM files. The handshake happens along with the
+//! first packet of a connection, so that it fails like any other exchange.
+
+use crate::errors::*;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{ClientConnection, RootCertStore, ServerConnection, StreamOwned};
+use std::cell::{Cell, RefCell};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+
+/// The TLS configuration of a server, see `server::Server::tls`.
+#[derive(Clone, Debug)]
+pub struct ServerTls (Arc<rustls::ServerConfig>);
+
+impl ServerTls {
+    /// Presents the certificate chain in `cert`, whose private key is in `key`. If `client_ca` is
+    /// given, clients must present a certificate signed by one of the CAs it contains.
+    pub fn new (cert: &Path, key: &Path, client_ca: Option<&Path>) -> Result<ServerTls> {
+        let builder = rustls::ServerConfig::builder();
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let verifier = WebPkiClientVerifier::builder (Arc::new (roots (client_ca)?))
+                    .build()
+                    .chain_err (|| format!("invalid client CA '{}'", client_ca.display()))?;
+                builder.with_client_cert_verifier (verifier)
+            },
+            None => builder.with_no_client_auth()
+        };
+        let config = builder.with_single_cert (certificates (cert)?, private_key (key)?)
+            .chain_err (|| format!("invalid certificate '{}'", cert.display()))?;
+        Ok(ServerTls (Arc::new (config)))
+    }
+
+    /// Wraps the connection of a client.
+    pub fn accept (&self, stream: TcpStream) -> Result<Stream> {
+        let connection = ServerConnection::new (self.0.clone())
+            .chain_err (|| "failed to start a TLS connection")?;
+        Ok(Stream::new (StreamOwned::new (connection, stream)))
+    }
+}
+
+/// The TLS configuration of a client, see `client::Client::tls`.
+#[derive(Clone, Debug)]
+pub struct ClientTls {
+    config: Arc<rustls::ClientConfig>,
+    server_name: Option<String>
+}
+
+impl ClientTls {
+    /// Trusts the certificates of servers signed by one of the CAs in `ca` (which can be the
+    /// certificate of the server itself, if self-signed). `identity` is the certificate chain
+    /// presented to servers which require one, along with its private key.
+    pub fn new (ca: &Path, identity: Option<(&Path, &Path)>) -> Result<ClientTls> {
+        let builder = rustls::ClientConfig::builder().with_root_certificates (roots (ca)?);
+        let config = match identity {
+            Some((cert, key)) => builder
+                .with_client_auth_cert (certificates (cert)?, private_key (key)?)
+                .chain_err (|| format!("invalid certificate '{}'", cert.display()))?,
+            None => builder.with_no_client_auth()
+        };
+        Ok(ClientTls { config: Arc::new (config), server_name: None })
+    }
+
+    /// Checks the certificate of the server against `name`, rather than against the host the
+    /// client connects to.
+    pub fn server_name<S: Into<String>> (mut self, name: S) -> ClientTls {
+        self.server_name = Some (name.into());
+        self
+    }
+
+    /// Wraps the connection to the server at `addr` (e.g. "renewer.example.com:5454").
+    pub fn connect (&self, stream: TcpStream, addr: &str) -> Result<Stream> {
+        let name = self.server_name.clone().unwrap_or_else (|| host (addr).into());
+        let name = ServerName::try_from (name.clone())
+            .chain_err (|| format!("invalid server name '{}'", name))?;
+        let connection = ClientConnection::new (self.config.clone(), name)
+            .chain_err (|| "failed to start a TLS connection")?;
+        Ok(Stream::new (StreamOwned::new (connection, stream)))
+    }
+}
+
+// A connection over TLS, of either side.
+trait Connection: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Connection for T {}
+
+/// A connection over TLS, which (like `TcpStream`) can be read and written through shared
+/// references, as long as reads and writes don't overlap.
+pub struct Stream {
+    connection: RefCell<Box<dyn Connection>>,
+    // Once an operation fails, the following ones fail right away: rustls would otherwise try to
+    // complete the handshake again, waiting for a peer which already gave up.
+    failed: Cell<bool>
+}
+
+impl Stream {
+    fn new<C: Connection + 'static> (connection: C) -> Stream {
+        Stream { connection: RefCell::new (Box::new (connection)), failed: Cell::new (false) }
+    }
+
+    fn guard<T, F> (&self, operation: F) -> io::Result<T>
+        where F: FnOnce(&mut dyn Connection) -> io::Result<T>
+    {
+        if self.failed.get() {
+            return Err(io::Error::new (io::ErrorKind::NotConnected, "the TLS connection failed"));
+        }
+        let result = operation (&mut **self.connection.borrow_mut());
+        self.failed.set (result.is_err());
+        result
+    }
+}
+
+impl fmt::Debug for Stream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Stream")
+    }
+}
+
+impl Read for &Stream {
+    fn read (&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.guard (|connection| connection.read (buf))
+    }
+}
+
+impl Write for &Stream {
+    fn write (&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.guard (|connection| connection.write (buf))
+    }
+
+    fn flush (&mut self) -> io::Result<()> {
+        self.guard (|connection| connection.flush())
+    }
+}
+
+// The host of `addr`, without its port and the brackets of IPv6 addresses.
+fn host (addr: &str) -> &str {
+    let host = match addr.rsplit_once (':') {
+        Some((host, port)) if port.parse::<u16>().is_ok() => host,
+        _ => addr
+    };
+    host.trim_start_matches ('[').trim_end_matches (']')
+}
+
+fn certificates (path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certificates = CertificateDer::pem_file_iter (path)
+        .and_then (|certificates| certificates.collect::<std::result::Result<Vec<_>, _>>())
+        .chain_err (|| format!("failed to read the certificates in '{}'", path.display()))?;
+    ensure!(!certificates.is_empty(), "'{}' contains no certificates", path.display());
+    Ok(certificates)
+}
+
+fn private_key (path: &Path) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file (path)
+        .chain_err (|| format!("failed to read the private key in '{}'", path.display()))
+}
+
+fn roots (path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for certificate in certificates (path)? {
+        roots.add (certificate)
+            .chain_err (|| format!("invalid CA certificate in '{}'", path.display()))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hosts_are_extracted() {
+        assert_eq!(host ("renewer.example.com:5454"), "renewer.example.com");
+        assert_eq!(host ("[2001:db8::1]:5454"), "2001:db8::1");
+        assert_eq!(host ("192.0.2.1"), "192.0.2.1");
+    }
+}
@@ -0,0 +1,907 @@
+//! Runs a server on an ephemeral port within the test process, and drives it with the client.
+#![cfg(all(feature = "client", feature = "server"))]
+
+use oxixenon_core::acl::{Acl, Action, Scope, Token};
+use oxixenon_core::client::Client;
+use oxixenon_core::config::{self, NotifierConfig, RenewerConfig, Section};
+use oxixenon_core::errors::Error;
+use oxixenon_core::notifier::{self, Notifier, OnEvent, Shutdown};
+use oxixenon_core::protocol::{
+    ErrorCode, Event, EventPayload, HistoryOutcome, Packet, RenewAvailability, PROTOCOL_VERSION
+};
+use oxixenon_core::renewer::{self, Health, LinkInfo, RenewOutcome, Renewer, ResultExt};
+use oxixenon_core::server::{IpSource, Server};
+use oxixenon_core::state::StateDir;
+use std::net::{TcpListener, UdpSocket};
+use std::ops::ControlFlow;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Delivers the events notified by the server to the listening client, within the test process.
+struct Loopback {
+    sender: Option<Sender<Event>>,
+    receiver: Option<Receiver<Event>>
+}
+
+// The notifier of the server, and the one of the client.
+fn loopback() -> (Loopback, Loopback) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        Loopback { sender: Some (sender), receiver: None },
+        Loopback { sender: None, receiver: Some (receiver) }
+    )
+}
+
+impl Notifier for Loopback {
+    fn from_config (_notifier: &NotifierConfig) -> notifier::Result<Self> {
+        Ok(loopback().0)
+    }
+
+    fn notify (&mut self, event: Event) -> notifier::Result<()> {
+        let sender = self.sender.as_ref().ok_or ("this end of the loopback can't notify")?;
+        sender.send (event).map_err (|_| "nobody is listening".into())
+    }
+
+    fn listen (&mut self, on_event: &mut OnEvent, shutdown: &Shutdown) -> notifier::Result<()> {
+        let receiver = self.receiver.as_ref().ok_or ("this end of the loopback can't listen")?;
+        while !shutdown.is_requested() {
+            match receiver.recv_timeout (Duration::from_millis (100)) {
+                Ok(event) => if on_event (event, &EventPayload::default(), None).is_break() {
+                    break;
+                },
+                Err(RecvTimeoutError::Timeout) => continue,
+                Err(RecvTimeoutError::Disconnected) => break
+            }
+        }
+        Ok(())
+    }
+}
+
+// Listens with `listener` in the background, returning the events it receives.
+fn listen (mut listener: Loopback) -> Receiver<Event> {
+    let (events, received) = mpsc::channel();
+    thread::spawn (move || {
+        let mut on_event = |event, _: &_, _| {
+            events.send (event).unwrap();
+            ControlFlow::Continue(())
+        };
+        listener.listen (&mut on_event, &Shutdown::new()).unwrap()
+    });
+    received
+}
+
+// Fails every renewal.
+struct Failing;
+
+impl Renewer for Failing {
+    fn from_config (_renewer: &RenewerConfig) -> renewer::Result<Self> {
+        Ok(Failing)
+    }
+
+    fn renew_ip (&mut self) -> renewer::Result<RenewOutcome> {
+        Err("the router is on fire".into())
+    }
+}
+
+// Times out every renewal, as a renewer with `operation_timeout` (and retries) would.
+struct Hung;
+
+impl Renewer for Hung {
+    fn from_config (_renewer: &RenewerConfig) -> renewer::Result<Self> {
+        Ok(Hung)
+    }
+
+    fn renew_ip (&mut self) -> renewer::Result<RenewOutcome> {
+        Err(renewer::Error::TimedOut (Duration::from_secs (90))).chain_err (|| "failed 2 times")
+    }
+}
+
+// Reports a link which is still training, and that the password is wrong.
+struct Training;
+
+impl Renewer for Training {
+    fn from_config (_renewer: &RenewerConfig) -> renewer::Result<Self> {
+        Ok(Training)
+    }
+
+    fn renew_ip (&mut self) -> renewer::Result<RenewOutcome> {
+        Err("the router didn't answer".into())
+    }
+
+    fn link_info (&mut self) -> renewer::Result<Option<LinkInfo>> {
+        Ok(Some (LinkInfo { state: Some ("Initializing".into()), ..LinkInfo::default() }))
+    }
+
+    fn health_check (&mut self) -> renewer::Result<Health> {
+        Ok(Health::login_failed ("wrong password"))
+    }
+}
+
+// Reports the external IP address 192.0.2.x, where x is shared with the test and is incremented by
+// each renewal.
+struct Reassigned(Arc<AtomicUsize>);
+
+impl Renewer for Reassigned {
+    fn from_config (_renewer: &RenewerConfig) -> renewer::Result<Self> {
+        Ok(Reassigned (Arc::default()))
+    }
+
+    fn renew_ip (&mut self) -> renewer::Result<RenewOutcome> {
+        self.0.fetch_add (1, Ordering::SeqCst);
+        Ok(RenewOutcome::default())
+    }
+
+    fn link_info (&mut self) -> renewer::Result<Option<LinkInfo>> {
+        let ip = [192, 0, 2, self.0.load (Ordering::SeqCst) as u8];
+        Ok(Some (LinkInfo { external_ip: Some (ip.into()), ..LinkInfo::default() }))
+    }
+}
+
+// Tells the test when each renewal starts, and waits for it to let the renewal finish.
+struct Slow {
+    started: Sender<()>,
+    finish: Receiver<()>
+}
+
+impl Renewer for Slow {
+    fn from_config (_renewer: &RenewerConfig) -> renewer::Result<Self> {
+        Err("the slow renewer is built by the tests".into())
+    }
+
+    fn renew_ip (&mut self) -> renewer::Result<RenewOutcome> {
+        self.started.send (()).unwrap();
+        self.finish.recv().chain_err (|| "the test is gone")?;
+        Ok(RenewOutcome::default())
+    }
+}
+
+fn dummy_renewer() -> Box<dyn Renewer> {
+    renewer::get_renewer (&RenewerConfig {
+        name: "dummy".into(),
+        instance: "dummy".into(),
+        config: Section::empty ("server.renewer.dummy")
+    }, &[]).unwrap()
+}
+
+fn no_notifier() -> Box<dyn Notifier> {
+    notifier::get_notifier (&config::NotifierConfig {
+        name: "none".into(),
+        config: Section::empty ("notifier.none")
+    }).unwrap()
+}
+
+// Starts a server (built by `server` within its own thread, as renewers aren't `Send`) which
+// handles `clients` connections, and returns a client connected to it.
+fn start<F> (clients: usize, server: F) -> Client
+    where F: FnOnce() -> Server + Send + 'static
+{
+    let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+    let client = Client::new (listener.local_addr().unwrap().to_string());
+    thread::spawn (move || server().serve (listener.incoming().take (clients)).unwrap());
+    client
+}
+
+#[test]
+fn renewals_are_notified() {
+    let (notifier, listener) = loopback();
+    let client = start (2, move || Server::new (dummy_renewer(), Box::new (notifier)));
+    let received = listen (listener);
+
+    for _ in 0..2 {
+        client.renew_ip().unwrap();
+        assert_eq!(received.recv_timeout (Duration::from_secs (5)), Ok(Event::RenewalStarted));
+        assert_eq!(received.recv_timeout (Duration::from_secs (5)), Ok(Event::IPRenewed));
+    }
+}
+
+#[test]
+fn failures_and_availability_changes_are_notified() {
+    let (notifier, listener) = loopback();
+    let client = start (1, move || Server::new (Box::new (Failing), Box::new (notifier)));
+    let received = listen (listener);
+
+    let reason = RenewAvailability::Unavailable ("gaming".into());
+    client.set_renewing_availability (reason.clone()).unwrap();
+    let changed = received.recv_timeout (Duration::from_secs (5));
+    assert_eq!(changed, Ok(Event::AvailabilityChanged (reason)));
+    // Failed renewals tell why, without the causes.
+    let mut server = Server::new (Box::new (Failing), no_notifier());
+    let (events, received) = mpsc::channel();
+    server = server.on_event (move |event, _: &_| events.send (event).unwrap());
+    assert!(server.renew().is_err());
+    server.shut_down();
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![
+        Event::RenewalStarted,
+        Event::RenewalFailed ("failed to renew the IP address".into()),
+        Event::ServerShuttingDown
+    ]);
+}
+
+#[test]
+fn renewals_can_be_made_unavailable() {
+    let (notifier, _listener) = loopback();
+    let client = start (4, move || Server::new (dummy_renewer(), Box::new (notifier)));
+
+    let reason = RenewAvailability::Unavailable ("playing online".into());
+    client.set_renewing_availability (reason).unwrap();
+    let error = client.renew_ip().unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the server refused the request (unavailable): Renewal unavailable: playing online"
+    );
+    client.set_renewing_availability (RenewAvailability::Available).unwrap();
+    client.renew_ip().unwrap();
+}
+
+#[test]
+fn unavailability_expires() {
+    let (notifier, listener) = loopback();
+    let client = start (4, move || Server::new (dummy_renewer(), Box::new (notifier)));
+    let received = listen (listener);
+
+    let reason = RenewAvailability::UnavailableFor ("gaming".into(), Duration::from_secs (1));
+    client.set_renewing_availability (reason).unwrap();
+    assert!(client.renew_ip().is_err());
+    let changed = received.recv_timeout (Duration::from_secs (5));
+    assert!(matches!(changed, Ok(Event::AvailabilityChanged (_))));
+    let expired = received.recv_timeout (Duration::from_secs (5));
+    assert_eq!(expired, Ok(Event::UnavailabilityExpired));
+    client.renew_ip().unwrap();
+}
+
+#[test]
+fn renewals_are_requested_locally() {
+    let (notifier, listener) = loopback();
+    let mut server = Server::new (dummy_renewer(), Box::new (notifier));
+    server.renew().unwrap();
+    let received = listener.receiver.unwrap();
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), [Event::RenewalStarted, Event::IPRenewed]);
+    server.set_availability (RenewAvailability::Unavailable ("gaming".into()));
+    assert_eq!(server.availability().reason(), Some ("gaming"));
+    assert!(server.renew().is_err());
+}
+
+#[test]
+fn shutdowns_are_notified_once() {
+    let (notifier, listener) = loopback();
+    let server = Server::new (dummy_renewer(), Box::new (notifier));
+    // As when the server takes too long to stop, and is shut down from another thread.
+    server.handle().shut_down();
+    server.shut_down();
+    let received = listener.receiver.unwrap();
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), [Event::ServerShuttingDown]);
+}
+
+#[test]
+fn events_are_handled_within_the_process() {
+    let (events, received) = mpsc::channel();
+    let mut server = Server::new (dummy_renewer(), no_notifier())
+        .coalesce_events (Duration::from_secs (60))
+        .origin (Some ("router".into()))
+        .on_event (move |event, payload: &EventPayload| {
+            events.send ((event, payload.clone())).unwrap()
+        });
+    server.renew().unwrap();
+    server.renew().unwrap();
+    let received = received.try_iter().collect::<Vec<_>>();
+    // Events handled within the process are never coalesced.
+    let renewal = [Event::RenewalStarted, Event::IPRenewed];
+    let events = received.iter().map (|(event, _)| event.clone()).collect::<Vec<_>>();
+    assert_eq!(events, [renewal.clone(), renewal].concat());
+    // Every event tells where and when it happened.
+    for (_, payload) in received {
+        assert_eq!(payload.origin.as_deref(), Some ("router"));
+        assert!(payload.timestamp.unwrap().elapsed().unwrap() < Duration::from_secs (60));
+    }
+}
+
+#[test]
+fn listening_stops_when_the_callback_breaks() {
+    let (notifier, mut listener) = loopback();
+    let mut server = Server::new (dummy_renewer(), Box::new (notifier));
+    for _ in 0..3 {
+        server.renew().unwrap();
+    }
+    // Stops after the second event, the end of the first renewal.
+    let mut received = 0;
+    listener.listen (&mut |_, _, _| {
+        received += 1;
+        if received == 2 { ControlFlow::Break(()) } else { ControlFlow::Continue(()) }
+    }, &Shutdown::new()).unwrap();
+    assert_eq!(received, 2);
+    assert_eq!(listener.receiver.unwrap().try_recv(), Ok(Event::RenewalStarted));
+}
+
+#[test]
+fn availability_survives_restarts() {
+    let path = std::env::temp_dir().join (format!("oxixenon-e2e-state-{}", std::process::id()));
+    let server = |clients| {
+        let state = StateDir::open (&path).unwrap();
+        start (clients, move || Server::new (dummy_renewer(), no_notifier()).state_dir (state))
+    };
+    let reason = RenewAvailability::Unavailable ("gaming".into());
+    server (1).set_renewing_availability (reason).unwrap();
+    let client = server (2);
+    assert!(client.renew_ip().is_err());
+    client.set_renewing_availability (RenewAvailability::Available).unwrap();
+    server (1).renew_ip().unwrap();
+    std::fs::remove_dir_all (&path).unwrap();
+}
+
+#[test]
+fn renewals_are_recorded_in_the_history() {
+    let path = std::env::temp_dir().join (format!("oxixenon-e2e-history-{}", std::process::id()));
+    let mut server = Server::new (Box::new (Failing), no_notifier())
+        .renewer_name ("dsl")
+        .state_dir (StateDir::open (&path).unwrap());
+    assert!(server.renew().is_err());
+    // The history survives restarts, and refused renewals aren't recorded.
+    let state = StateDir::open (&path).unwrap();
+    let client = start (5, move || {
+        Server::new (dummy_renewer(), no_notifier()).history_size (3).state_dir (state)
+    });
+    client.renew_ip().unwrap();
+    client.set_renewing_availability (RenewAvailability::Unavailable ("gaming".into())).unwrap();
+    assert!(client.renew_ip().is_err());
+    let history = client.history (10).unwrap();
+    assert_eq!(history.len(), 2);
+    assert!(history[0].peer.as_deref().unwrap().starts_with ("127.0.0.1:"));
+    assert!(matches!(history[0].outcome, HistoryOutcome::Renewed (_)));
+    assert_eq!(history[1].peer, None);
+    assert_eq!(history[1].renewer.as_deref(), Some ("dsl"));
+    let failure = HistoryOutcome::Failed ("failed to renew the IP address".into());
+    assert_eq!(history[1].outcome, failure);
+    assert_eq!(client.history (1).unwrap(), history[..1]);
+    std::fs::remove_dir_all (&path).unwrap();
+}
+
+#[test]
+fn capabilities_are_advertised() {
+    let client = start (2, || Server::new (dummy_renewer(), no_notifier()).renewer_name ("dsl"));
+    let capabilities = client.capabilities().unwrap().unwrap();
+    assert_eq!(capabilities.version, PROTOCOL_VERSION);
+    assert_eq!(capabilities.renewer.as_deref(), Some ("dsl"));
+    assert!(capabilities.keep_alive && !capabilities.auth && !capabilities.reload);
+    assert!(capabilities.status && capabilities.history && !capabilities.schedule);
+    // Unsupported requests fail before being sent.
+    let error = client.reload_config ("secret".into()).unwrap_err();
+    assert_eq!(error.to_string(), format!(
+        "the server at {} doesn't support reloading its configuration", client.connect_to()
+    ));
+    client.renew_ip().unwrap();
+}
+
+#[test]
+fn events_are_streamed_to_subscribed_clients() {
+    let client = start (12, move || Server::new (dummy_renewer(), no_notifier()));
+    let (events, received) = mpsc::channel();
+    let subscriber = client.clone();
+    thread::spawn (move || {
+        let mut on_event = |event, _: &_, from: Option<_>| {
+            assert!(from.is_some());
+            events.send (event).unwrap();
+            ControlFlow::Continue(())
+        };
+        subscriber.subscribe (&mut on_event, &Shutdown::new()).unwrap()
+    });
+
+    // Renewals requested before the subscription go unheard, so renew until one is heard.
+    let heard = (0..10).any (|_| {
+        client.renew_ip().unwrap();
+        received.recv_timeout (Duration::from_millis (500)) == Ok(Event::RenewalStarted)
+    });
+    assert!(heard);
+    assert_eq!(received.recv_timeout (Duration::from_secs (5)), Ok(Event::IPRenewed));
+    client.set_renewing_availability (RenewAvailability::Unavailable ("gaming".into())).unwrap();
+    assert!(matches!(
+        received.recv_timeout (Duration::from_secs (5)),
+        Ok(Event::AvailabilityChanged (_))
+    ));
+}
+
+#[test]
+fn bursts_of_events_are_coalesced() {
+    let (notifier, listener) = loopback();
+    let client = start (4, move || {
+        Server::new (dummy_renewer(), Box::new (notifier))
+            .coalesce_events (Duration::from_millis (500))
+            .coalesce_renewals (Duration::ZERO)
+    });
+    let received = listen (listener);
+
+    for _ in 0..3 {
+        client.renew_ip().unwrap();
+    }
+    // The events of the first renewal right away, and those of the other two once the window
+    // elapses.
+    let mut events = (0..4)
+        .map (|_| received.recv_timeout (Duration::from_secs (5)).unwrap())
+        .collect::<Vec<_>>();
+    events.sort();
+    assert_eq!(events, [Event::IPRenewed, Event::IPRenewed, Event::RenewalStarted,
+        Event::RenewalStarted]);
+    assert!(received.recv_timeout (Duration::from_secs (1)).is_err());
+}
+
+#[test]
+fn subscribed_clients_receive_how_many_events_were_coalesced() {
+    let window = Duration::from_millis (500);
+    let client = start (30, move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .coalesce_events (window)
+            .coalesce_renewals (Duration::ZERO)
+    });
+    let (events, received) = mpsc::channel();
+    let subscriber = client.clone();
+    thread::spawn (move || {
+        let mut on_event = |event, payload: &EventPayload, _| {
+            events.send ((event, payload.coalesced)).unwrap();
+            ControlFlow::Continue(())
+        };
+        subscriber.subscribe (&mut on_event, &Shutdown::new()).unwrap()
+    });
+
+    // Changes made before the subscription go unheard, so change the availability until heard.
+    let gaming = RenewAvailability::Unavailable ("gaming".into());
+    let heard = (0..10).any (|_| {
+        client.set_renewing_availability (gaming.clone()).unwrap();
+        matches!(received.recv_timeout (window), Ok((Event::AvailabilityChanged (_), _)))
+    });
+    assert!(heard);
+    client.set_renewing_availability (RenewAvailability::Available).unwrap();
+    // Let the bursts of availability changes end.
+    thread::sleep (window * 3);
+    received.try_iter().for_each (drop);
+
+    for _ in 0..3 {
+        client.renew_ip().unwrap();
+    }
+    // The events of the first renewal right away, and those of the other two as one once the
+    // window elapses.
+    let mut events = (0..4)
+        .map (|_| received.recv_timeout (Duration::from_secs (5)).unwrap())
+        .collect::<Vec<_>>();
+    events.sort();
+    assert_eq!(events, [(Event::IPRenewed, None), (Event::IPRenewed, Some (2)),
+        (Event::RenewalStarted, None), (Event::RenewalStarted, Some (2))]);
+}
+
+#[test]
+fn errors_are_sent_without_their_causes() {
+    let client = start (2, || Server::new (Box::new (Failing), no_notifier()));
+    let error = client.renew_ip().unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (renewer_failure): failed to renew the IP address");
+    assert!(matches!(error, Error::Refused (ErrorCode::RenewerFailure, _)));
+    match client.request (&Packet::Ok).unwrap() {
+        Packet::Error { code: ErrorCode::Unsupported, detail: Some (detail) } =>
+            assert_eq!(detail, "Unsupported packet"),
+        packet => panic!("unexpected response {:?}", packet)
+    }
+}
+
+#[test]
+fn slow_renewals_dont_hold_up_other_clients() {
+    let (started, renewing) = mpsc::channel();
+    let (finish, finishing) = mpsc::channel();
+    let client = start (3, move || {
+        Server::new (Box::new (Slow { started, finish: finishing }), no_notifier())
+    });
+    let renewal = {
+        let client = client.clone();
+        thread::spawn (move || client.renew_ip())
+    };
+    renewing.recv_timeout (Duration::from_secs (5)).unwrap();
+    // The renewal is still running, but other requests are answered meanwhile.
+    assert_eq!(client.history (10).unwrap(), []);
+    client.set_renewing_availability (RenewAvailability::Unavailable ("gaming".into())).unwrap();
+    finish.send (()).unwrap();
+    renewal.join().unwrap().unwrap();
+}
+
+#[test]
+fn concurrent_renewals_are_coalesced() {
+    let renewals = Arc::new (AtomicUsize::new (0));
+    let client = {
+        let renewals = renewals.clone();
+        start (3, move || Server::new (Box::new (Reassigned (renewals)), no_notifier()))
+    };
+    let requests: Vec<_> = (0..3)
+        .map (|_| {
+            let client = client.clone();
+            thread::spawn (move || client.renew_ip())
+        })
+        .collect();
+    for request in requests {
+        request.join().unwrap().unwrap();
+    }
+    assert_eq!(renewals.load (Ordering::SeqCst), 1);
+}
+
+#[test]
+fn recent_renewals_are_reported() {
+    let mut server = Server::new (dummy_renewer(), no_notifier())
+        .coalesce_renewals (Duration::from_millis (500));
+    assert!(!server.renewed_recently());
+    server.renew().unwrap();
+    assert!(server.renewed_recently());
+    thread::sleep (Duration::from_secs (1));
+    assert!(!server.renewed_recently());
+}
+
+#[test]
+fn timeouts_are_reported_to_clients() {
+    let client = start (1, || Server::new (Box::new (Hung), no_notifier()));
+    let error = client.renew_ip().unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (renewer_failure): the renewal timed out after 1m30s");
+}
+
+#[test]
+fn configuration_is_reloaded_on_request() {
+    let client = start (1, || Server::new (dummy_renewer(), no_notifier()));
+    assert!(client.reload_config ("secret".into()).is_err());
+
+    let reloads = Arc::new (AtomicUsize::new (0));
+    let counter = reloads.clone();
+    let client = start (2, move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .admin_token ("secret".into())
+            .on_reload (move || { counter.fetch_add (1, Ordering::SeqCst); Ok(()) })
+    });
+    client.reload_config ("secret".into()).unwrap();
+    client.reload_config ("secret".into()).unwrap();
+    assert_eq!(reloads.load (Ordering::SeqCst), 2);
+}
+
+#[test]
+fn configuration_reloads_require_an_administrator() {
+    let reloads = Arc::new (AtomicUsize::new (0));
+    let counter = reloads.clone();
+    let client = start (4, move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .admin_token ("secret".into())
+            .on_reload (move || { counter.fetch_add (1, Ordering::SeqCst); Ok(()) })
+    });
+    // Sent as is, since the server advertises reloads.
+    for token in ["", "wrong"] {
+        let error = client.request (&Packet::ReloadConfig { token: token.into() }).unwrap();
+        assert!(matches!(error, Packet::Error { code: ErrorCode::Unauthorized, .. }));
+    }
+    assert_eq!(reloads.load (Ordering::SeqCst), 0);
+
+    // Without an admin token, reloads aren't even advertised.
+    let client = start (1, || Server::new (dummy_renewer(), no_notifier()).on_reload (|| Ok(())));
+    let error = client.reload_config ("".into()).unwrap_err();
+    assert_eq!(error.to_string(), format!(
+        "the server at {} doesn't support reloading its configuration", client.connect_to()
+    ));
+}
+
+#[test]
+fn renewers_are_switched_by_administrators() {
+    // Servers without an admin token don't advertise switching renewers.
+    let client = start (1, || Server::new (dummy_renewer(), no_notifier()));
+    let error = client.set_renewer ("failing", "secret".into()).unwrap_err();
+    assert_eq!(error.to_string(),
+        format!("the server at {} doesn't support switching renewers", client.connect_to()));
+
+    let client = start (5, || {
+        Server::new (dummy_renewer(), no_notifier())
+            .admin_token ("secret".into())
+            .on_set_renewer (|name| match name {
+                "failing" => Ok(Box::new (Failing)),
+                _ => Err(format!("there is no renewer named '{}'", name).into())
+            })
+    });
+    let error = client.set_renewer ("failing", "wrong".into()).unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (unauthorized): Invalid admin token");
+    let error = client.set_renewer ("lte", "secret".into()).unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (internal): failed to switch to the renewer 'lte'");
+    client.renew_ip().unwrap();
+    client.set_renewer ("failing", "secret".into()).unwrap();
+    assert!(client.renew_ip().is_err());
+}
+
+#[test]
+fn clients_are_authenticated_with_the_psk() {
+    let client = start (3, || Server::new (dummy_renewer(), no_notifier()).psk ("psk".into()));
+    let error = client.renew_ip().unwrap_err();
+    assert_eq!(error.to_string(), "the server requires a pre-shared key");
+    let error = client.clone().psk ("wrong".into()).renew_ip().unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (unauthorized): Authentication failed");
+    client.psk ("psk".into()).renew_ip().unwrap();
+}
+
+#[test]
+fn clients_are_restricted_by_address() {
+    let local = || vec!["127.0.0.0/8".parse().unwrap()];
+    let client = start (3, move || {
+        let acl = Acl::default()
+            .allow_clients (local())
+            .restrict (Action::SetAvailability, vec!["192.168.1.10".parse().unwrap()]);
+        Server::new (dummy_renewer(), no_notifier()).acl (acl)
+    });
+    let error = client.set_renewing_availability (RenewAvailability::Available).unwrap_err();
+    assert_eq!(error.to_string(), "the server refused the request (unauthorized): This client \
+        isn't allowed to request 'set_availability'");
+    client.renew_ip().unwrap();
+    let client = start (1, || {
+        let acl = Acl::default().allow_clients (vec!["192.168.1.0/24".parse().unwrap()]);
+        Server::new (dummy_renewer(), no_notifier()).acl (acl)
+    });
+    assert!(client.renew_ip().is_err());
+}
+
+#[test]
+fn admin_tokens_switch_renewers() {
+    let token = |name: &str, scope| Token {
+        name: name.into(),
+        secret: format!("{}'s secret", name).into(),
+        scopes: [scope].into()
+    };
+    let client = start (4, move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .token (token ("alice", Scope::Renew))
+            .token (token ("bob", Scope::Admin))
+            .on_set_renewer (|name| match name {
+                "failing" => Ok(Box::new (Failing)),
+                _ => Err(format!("there is no renewer named '{}'", name).into())
+            })
+    });
+    // Servers without an admin token still advertise switching renewers to admin tokens.
+    let alice = client.clone().psk ("alice's secret".into());
+    let error = alice.set_renewer ("failing", "".into()).unwrap_err();
+    assert_eq!(error.to_string(), "the server refused the request (unauthorized): The token \
+        'alice' isn't allowed to request 'set_renewer'");
+    let bob = client.psk ("bob's secret".into());
+    bob.renew_ip().unwrap();
+    bob.set_renewer ("failing", "".into()).unwrap();
+    assert!(bob.renew_ip().is_err());
+}
+
+#[test]
+fn tokens_tell_clients_apart() {
+    let token = |name: &str, scope| Token {
+        name: name.into(),
+        secret: format!("{}'s secret", name).into(),
+        scopes: [scope].into()
+    };
+    let (events, received) = mpsc::channel();
+    let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    thread::spawn (move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .token (token ("alice", Scope::Renew))
+            .token (token ("bob", Scope::Admin))
+            .coalesce_renewals (Duration::ZERO)
+            .on_event (move |event, payload: &EventPayload| {
+                events.send ((event, payload.requested_by.clone())).unwrap()
+            })
+            .serve (listener.incoming().take (5))
+            .unwrap()
+    });
+    let alice = Client::new (addr.clone()).psk ("alice's secret".into()).keep_alive();
+    alice.renew_ip().unwrap();
+    let error = alice.set_renewing_availability (RenewAvailability::Available).unwrap_err();
+    assert_eq!(error.to_string(), "the server refused the request (unauthorized): The token \
+        'alice' isn't allowed to request 'set_availability'");
+    let bob = Client::new (addr.clone()).psk ("bob's secret".into());
+    bob.set_renewing_availability (RenewAvailability::Available).unwrap();
+    let alice = Some ("alice".to_string());
+    assert_eq!(received.try_iter().collect::<Vec<_>>(), vec![
+        (Event::RenewalStarted, alice.clone()),
+        (Event::IPRenewed, alice),
+        (Event::AvailabilityChanged (RenewAvailability::Available), Some ("bob".into()))
+    ]);
+    let mallory = Client::new (addr).psk ("mallory's secret".into());
+    assert!(matches!(mallory.renew_ip(), Err(Error::Refused (ErrorCode::Unauthorized, _))));
+}
+
+#[test]
+fn connections_are_kept_open() {
+    // The second connection is only needed once the first one is closed for being idle.
+    let client = start (2, || {
+        Server::new (dummy_renewer(), no_notifier())
+            .psk ("psk".into())
+            .idle_timeout (Duration::from_millis (500))
+    }).psk ("psk".into()).keep_alive();
+    client.status().unwrap();
+    client.renew_ip().unwrap();
+    client.set_renewing_availability (RenewAvailability::Unavailable ("gaming".into())).unwrap();
+    assert!(matches!(client.renew_ip(), Err(Error::Refused (ErrorCode::Unavailable, _))));
+    thread::sleep (Duration::from_secs (1));
+    client.set_renewing_availability (RenewAvailability::Available).unwrap();
+    client.renew_ip().unwrap();
+}
+
+#[cfg(feature = "json-protocol")]
+#[test]
+fn clients_speak_json() {
+    use oxixenon_core::protocol::Encoding;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpStream;
+    let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    thread::spawn (move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .serve_with (listener.incoming().take (1), Encoding::Json)
+            .unwrap()
+    });
+    let stream = TcpStream::connect (addr).unwrap();
+    let mut reader = BufReader::new (&stream);
+    let mut exchange = |request: &str| -> serde_json::Value {
+        writeln!(&stream, "{}", request).unwrap();
+        let mut line = String::new();
+        reader.read_line (&mut line).unwrap();
+        serde_json::from_str (&line).unwrap()
+    };
+    let status = exchange (r#"{"type":"status_request"}"#);
+    assert_eq!(status["type"], "status");
+    assert_eq!(status["availability"]["available"], true);
+    let ok = exchange (
+        r#"{"type":"set_renewing_available","availability":{"available":false,"reason":"gaming"}}"#
+    );
+    assert_eq!(ok["type"], "ok");
+    let error = exchange (r#"{"type":"fresh_ip_request"}"#);
+    assert_eq!(error["type"], "error");
+    assert_eq!(error["code"], "unavailable");
+}
+
+#[cfg(feature = "tls")]
+#[test]
+fn clients_connect_over_tls() {
+    use oxixenon_core::tls::{ClientTls, ServerTls};
+    use std::path::{Path, PathBuf};
+    let fixture = |name: &str| -> PathBuf {
+        Path::new (env!("CARGO_MANIFEST_DIR")).join ("tests/fixtures/tls").join (name)
+    };
+    let server_tls = ServerTls::new (
+        &fixture ("server.pem"), &fixture ("server.key"), Some (&fixture ("ca.pem"))
+    ).unwrap();
+    let client = start (3, move || Server::new (dummy_renewer(), no_notifier()).tls (server_tls));
+    // The server requires a certificate from its clients.
+    let tls = ClientTls::new (&fixture ("ca.pem"), None).unwrap();
+    assert!(client.clone().tls (tls).renew_ip().is_err());
+    let (cert, key) = (fixture ("client.pem"), fixture ("client.key"));
+    let tls = ClientTls::new (&fixture ("ca.pem"), Some ((&cert, &key))).unwrap();
+    // The certificate of the server isn't valid for other names.
+    assert!(client.clone().tls (tls.clone().server_name ("xenon.example")).renew_ip().is_err());
+    client.tls (tls).renew_ip().unwrap();
+}
+
+#[test]
+fn renewals_select_renewers() {
+    let builds = Arc::new (AtomicUsize::new (0));
+    let counter = builds.clone();
+    let client = start (5, move || {
+        Server::new (dummy_renewer(), no_notifier())
+            .renewer_name ("wan1")
+            .on_set_renewer (move |name| match name {
+                "wan2" => {
+                    counter.fetch_add (1, Ordering::SeqCst);
+                    Ok(Box::new (Failing))
+                },
+                _ => Err(format!("there is no renewer named '{}'", name).into())
+            })
+    });
+    // Selecting a renewer doesn't switch to it, and it's only built once.
+    for _ in 0..2 {
+        assert!(client.renew_ip_with (Some ("wan2")).is_err());
+        client.renew_ip().unwrap();
+    }
+    assert_eq!(builds.load (Ordering::SeqCst), 1);
+    let error = client.renew_ip_with (Some ("wan3")).unwrap_err();
+    assert_eq!(error.to_string(),
+        "the server refused the request (internal): failed to select the renewer 'wan3'");
+}
+
+#[test]
+fn status_reports_the_link() {
+    let client = start (1, || Server::new (dummy_renewer(), no_notifier()));
+    let status = client.status().unwrap();
+    assert_eq!(status.renewer, None);
+    assert!(status.availability.reason().is_none());
+    assert_eq!(status.link, None);
+
+    let client = start (2, || {
+        Server::new (Box::new (Training), no_notifier()).renewer_name ("dsl")
+    });
+    client.set_renewing_availability (RenewAvailability::Unavailable ("gaming".into())).unwrap();
+    let status = client.status().unwrap();
+    assert_eq!(status.renewer.as_deref(), Some ("dsl"));
+    assert_eq!(status.availability.reason(), Some ("gaming"));
+    assert_eq!(status.link.unwrap().state.as_deref(), Some ("Initializing"));
+}
+
+#[test]
+fn health_is_reported() {
+    let client = start (1, || Server::new (dummy_renewer(), no_notifier()));
+    let (health, _) = client.health().unwrap();
+    assert!(health.is_healthy());
+
+    // Without periodic checks, the renewer is checked on request.
+    let client = start (2, || Server::new (Box::new (Training), no_notifier()));
+    for _ in 0..2 {
+        let (health, age) = client.health().unwrap();
+        assert_eq!(health, Health::login_failed ("wrong password"));
+        assert!(age < Duration::from_secs (5));
+    }
+}
+
+#[test]
+fn ip_changes_without_renewals_are_notified() {
+    let (notifier, listener) = loopback();
+    let events = listen (listener);
+    let ip = Arc::new (AtomicUsize::new (1));
+    let mut server = Server::new (Box::new (Reassigned (ip.clone())), Box::new (notifier));
+    // The first check only finds the address, which then changes because of a renewal.
+    server.check_ip (&IpSource::Renewer).unwrap();
+    server.renew().unwrap();
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::RenewalStarted);
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::IPRenewed);
+    server.check_ip (&IpSource::Renewer).unwrap();
+    server.check_ip (&IpSource::Renewer).unwrap();
+    assert!(events.recv_timeout (Duration::from_millis (300)).is_err());
+
+    // The ISP reassigns the address.
+    ip.store (42, Ordering::SeqCst);
+    server.check_ip (&IpSource::Renewer).unwrap();
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::IPChanged);
+}
+
+// Answers STUN Binding requests with the address 203.0.113.7 in the background, returning the
+// address of the server.
+fn stun_server() -> String {
+    let socket = UdpSocket::bind ("127.0.0.1:0").unwrap();
+    let addr = socket.local_addr().unwrap().to_string();
+    thread::spawn (move || {
+        let mut request = [0u8; 20];
+        while let Ok((_, client)) = socket.recv_from (&mut request) {
+            // Binding response, with the cookie and the transaction id of the request, and an
+            // XOR-MAPPED-ADDRESS attribute (port 1, address XORed with the cookie).
+            let mut response = vec![0x01, 0x01, 0x00, 0x0c];
+            response.extend_from_slice (&request[4..20]);
+            response.extend_from_slice (&[0x00, 0x20, 0x00, 0x08, 0x00, 0x01, 0x21, 0x13]);
+            let ip = [203, 0, 113, 7];
+            response.extend (ip.iter().zip (&request[4..8]).map (|(byte, mask)| byte ^ mask));
+            socket.send_to (&response, client).unwrap();
+        }
+    });
+    addr
+}
+
+#[test]
+fn renewals_are_verified() {
+    let interval = Duration::from_millis (50);
+    let (notifier, listener) = loopback();
+    let events = listen (listener);
+    let client = start (1, move || {
+        Server::new (Box::new (Reassigned (Arc::default())), Box::new (notifier))
+            .verify_renewals (IpSource::Renewer, Duration::from_millis (300), interval)
+    });
+    // The verification tells the addresses the renewer doesn't.
+    let outcome = client.renew_ip().unwrap();
+    assert_eq!(outcome.to_string(), "from 192.0.2.0 to 192.0.2.1");
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::RenewalStarted);
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::IPRenewed);
+
+    // The address seen by the STUN server never changes.
+    let (notifier, listener) = loopback();
+    let events = listen (listener);
+    let stun = IpSource::Stun (stun_server());
+    let client = start (1, move || {
+        Server::new (dummy_renewer(), Box::new (notifier))
+            .verify_renewals (stun, Duration::from_millis (300), interval)
+    });
+    let error = client.renew_ip().unwrap_err();
+    assert_eq!(error.to_string(), "the IP address was renewed, but it's still 203.0.113.7");
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::RenewalStarted);
+    assert_eq!(events.recv_timeout (Duration::from_secs (5)).unwrap(), Event::IPUnchanged);
+}
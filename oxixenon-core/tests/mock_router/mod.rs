@@ -0,0 +1,174 @@
+//! A local HTTP server which replays the login and renewal exchanges of a router, recorded in a
+//! fixture file, so that the real renewers can be run against it.
+//!
+//! Fixtures live in `tests/fixtures/<renewer>/<firmware>.http`, and list the exchanges in the
+//! order they are expected:
+//!
+//! ```text
+//! # Comments start with '#'.
+//! > GET /ui/login         starts an exchange: the method and the path of the request
+//! ? cookie: sid=abcdef    text which the request (headers or body) must contain
+//! < HTTP/1.1 200 OK       a line of the response ('<' alone is an empty line)
+//! ! fails                 the renewer is expected to fail (anywhere in the file)
+//! ```
+
+use std::fs;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// A request expected by the router, along with its response.
+#[derive(Clone, Debug, Default)]
+pub struct Exchange {
+    /// The method and the path of the request, e.g. "GET /ui/login".
+    pub request: String,
+    /// Text which the request must contain. Header names are lowercase.
+    pub expect: Vec<String>,
+    pub response: String
+}
+
+/// The exchanges recorded from a firmware.
+#[derive(Debug)]
+pub struct Fixture {
+    pub name: String,
+    pub exchanges: Vec<Exchange>,
+    /// Whether the renewer is expected to fail.
+    pub fails: bool
+}
+
+impl Fixture {
+    pub fn load (path: &Path) -> Fixture {
+        let source = fs::read_to_string (path)
+            .unwrap_or_else (|e| panic!("can't read fixture '{}': {}", path.display(), e));
+        let mut fixture = Fixture {
+            name: path.file_stem().unwrap().to_string_lossy().into_owned(),
+            exchanges: Vec::new(),
+            fails: false
+        };
+        for (number, line) in source.lines().enumerate() {
+            let (kind, rest) = line.split_at (line.len().min (1));
+            let rest = rest.strip_prefix (' ').unwrap_or (rest);
+            let exchange = fixture.exchanges.last_mut();
+            match (kind, exchange) {
+                ("" | "#", _) => {},
+                (">", _) => fixture.exchanges.push (Exchange {
+                    request: rest.to_string(),
+                    ..Exchange::default()
+                }),
+                ("?", Some(exchange)) => exchange.expect.push (rest.to_string()),
+                ("<", Some(exchange)) => {
+                    exchange.response.push_str (rest);
+                    exchange.response.push_str ("\r\n");
+                },
+                ("!", _) if rest == "fails" => fixture.fails = true,
+                _ => panic!("{}:{}: unexpected line '{}'", path.display(), number + 1, line)
+            }
+        }
+        fixture
+    }
+
+    /// All the fixtures of `renewer`, sorted by name.
+    pub fn all (renewer: &str) -> Vec<Fixture> {
+        let dir = Path::new (env!("CARGO_MANIFEST_DIR")).join ("tests/fixtures").join (renewer);
+        let mut paths = fs::read_dir (&dir)
+            .unwrap_or_else (|e| panic!("can't list fixtures in '{}': {}", dir.display(), e))
+            .map (|entry| entry.unwrap().path())
+            .filter (|path| path.extension().is_some_and (|ext| ext == "http"))
+            .collect::<Vec<PathBuf>>();
+        paths.sort();
+        assert!(!paths.is_empty(), "no fixtures in '{}'", dir.display());
+        paths.iter().map (|path| Fixture::load (path)).collect()
+    }
+}
+
+/// A router replaying the exchanges of a fixture, one connection per exchange.
+pub struct MockRouter {
+    addr: SocketAddr,
+    stop: Arc<AtomicBool>,
+    server: JoinHandle<Result<(), String>>
+}
+
+impl MockRouter {
+    pub fn start (fixture: &Fixture) -> MockRouter {
+        let listener = TcpListener::bind ("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let stop = Arc::new (AtomicBool::new (false));
+        let exchanges = fixture.exchanges.clone();
+        let stopped = stop.clone();
+        let server = thread::spawn (move || {
+            for (number, exchange) in exchanges.iter().enumerate() {
+                let (stream, _) = listener.accept().map_err (|e| e.to_string())?;
+                if stopped.load (Ordering::SeqCst) {
+                    return Err(format!("request #{} was not made: {}", number, exchange.request));
+                }
+                let request = read_request (&stream)?;
+                let request_line = request.lines().next().unwrap_or_default();
+                let mismatch = if !request_line.starts_with (&format!("{} ", exchange.request)) {
+                    Some (format!("expected '{}'", exchange.request))
+                } else {
+                    exchange.expect.iter()
+                        .find (|text| !request.contains (text.as_str()))
+                        .map (|text| format!("expected it to contain '{}'", text))
+                };
+                if let Some(mismatch) = mismatch {
+                    reply (stream, "HTTP/1.1 500 Internal Server Error\r\n\r\n");
+                    return Err(format!("request #{}: {}, got:\n{}", number, mismatch, request));
+                }
+                reply (stream, &exchange.response);
+            }
+            Ok(())
+        });
+        MockRouter { addr, stop, server }
+    }
+
+    /// The address of the router, e.g. "127.0.0.1:34567".
+    pub fn addr (&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Stops the router, and returns an error unless every exchange was performed as expected.
+    pub fn finish (self) -> Result<(), String> {
+        if !self.server.is_finished() {
+            self.stop.store (true, Ordering::SeqCst);
+            // Wake the server up if it's waiting for a request.
+            let _ = TcpStream::connect (self.addr);
+        }
+        self.server.join().map_err (|_| "the mock router panicked".to_string())?
+    }
+}
+
+// Reads the headers of a request, and its body as long as its `content-length`.
+fn read_request (stream: &TcpStream) -> Result<String, String> {
+    stream.set_read_timeout (Some (Duration::from_secs (5))).map_err (|e| e.to_string())?;
+    let mut reader = BufReader::new (stream);
+    let mut request = String::new();
+    let mut content_length = 0;
+    loop {
+        let mut line = String::new();
+        if reader.read_line (&mut line).map_err (|e| e.to_string())? == 0 || line == "\r\n" {
+            break;
+        }
+        if let Some(length) = line.strip_prefix ("content-length: ") {
+            content_length = length.trim().parse().map_err (|_| "invalid content-length")?;
+        }
+        request.push_str (&line);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact (&mut body).map_err (|e| e.to_string())?;
+    request.push_str ("\r\n");
+    request.push_str (&String::from_utf8_lossy (&body));
+    Ok(request)
+}
+
+fn reply (mut stream: TcpStream, response: &str) {
+    let _ = stream.write_all (response.as_bytes());
+    // Wait for the client to hang up, so that what's left of its request (e.g. the newline after
+    // the body) doesn't reset the connection before the response is read.
+    let _ = stream.shutdown (std::net::Shutdown::Write);
+    let _ = stream.read_to_end (&mut Vec::new());
+}
@@ -0,0 +1,72 @@
+//! Runs the renewers against mock routers replaying the exchanges recorded from real firmwares,
+//! see `mock_router`.
+#![cfg(any(
+    feature = "renewer-dlink", feature = "renewer-fritzbox", feature = "renewer-netgear",
+    feature = "renewer-tr064"
+))]
+
+mod mock_router;
+
+use mock_router::{Fixture, MockRouter};
+use oxixenon_core::config::{Config, Mode, NoArgs};
+use oxixenon_core::renewer;
+use std::fs;
+use std::process;
+
+// Renews the IP address with `renewer` against every fixture, with the options `options` (along
+// with the address of the router).
+fn renew_with_fixtures (renewer: &str, options: &str) {
+    for fixture in Fixture::all (renewer) {
+        let router = MockRouter::start (&fixture);
+        let path = std::env::temp_dir()
+            .join (format!("oxixenon-{}-{}-{}.toml", renewer, fixture.name, process::id()));
+        fs::write (&path, format!(
+            "mode = 'server'\nnotifier_name = 'none'\n\
+             [logging]\nverbosity = 'info'\nbackends = []\n\
+             [server]\nbind_to = '127.0.0.1:0'\nrenewer_name = '{}'\n\
+             [server.renewer.{}]\nip = '{}'\n{}\n",
+            renewer, renewer, router.addr(), options
+        )).unwrap();
+        let config = Config::parse_config (&path, &NoArgs);
+        fs::remove_file (&path).unwrap();
+        let server = match config.unwrap().mode {
+            Mode { server: Some(server), .. } => server,
+            mode => panic!("unexpected {}", mode)
+        };
+        let result = renewer::get_renewer (&server.renewer, &server.renewers)
+            .and_then (|mut renewer| renewer.init().and_then (|_| renewer.renew_ip()));
+        if let Err(error) = router.finish() {
+            panic!("{}/{}: {}", renewer, fixture.name, error);
+        }
+        assert_eq!(
+            result.is_err(), fixture.fails,
+            "{}/{}: unexpected result {:?}", renewer, fixture.name, result.err()
+        );
+    }
+}
+
+#[cfg(feature = "renewer-dlink")]
+#[test]
+fn dlink() {
+    renew_with_fixtures (
+        "dlink", "username = 'admin'\npassword = 'admin'\ninterface = 'pppif?if=1'"
+    );
+}
+
+#[cfg(feature = "renewer-fritzbox")]
+#[test]
+fn fritzbox() {
+    renew_with_fixtures ("fritzbox", "password = 'äbc'");
+}
+
+#[cfg(feature = "renewer-netgear")]
+#[test]
+fn netgear() {
+    renew_with_fixtures ("netgear", "password = 'äbc'");
+}
+
+#[cfg(feature = "renewer-tr064")]
+#[test]
+fn tr064() {
+    renew_with_fixtures ("tr064", "username = 'fritz1234'\npassword = 'äbc'");
+}
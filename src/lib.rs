@@ -1,28 +0,0 @@
-extern crate byteorder;
-extern crate toml;
-#[cfg(feature = "http-client")]
-extern crate http;
-extern crate clap;
-#[cfg(all(windows, feature = "client-toasts"))]
-extern crate winrt;
-extern crate fern;
-#[macro_use]
-extern crate log;
-extern crate chrono;
-#[cfg(all(not(windows), feature = "syslog-backend"))]
-extern crate syslog;
-#[macro_use]
-extern crate error_chain;
-
-pub mod errors;
-pub mod config;
-pub mod logging;
-pub mod protocol;
-#[cfg(feature = "server")]
-pub mod renewer;
-#[cfg(feature = "http-client")]
-pub mod http_client;
-pub mod notifier;
-
-#[cfg(feature = "client-toasts")]
-pub mod notification_toasts;
@@ -1,18 +1,59 @@
-extern crate oxixenon;
 #[macro_use]
 extern crate clap;
 #[macro_use]
 extern crate log;
-extern crate error_chain;
 
+use std::path::{Path, PathBuf};
 use std::process;
-use error_chain::ChainedError;
-use oxixenon::*;
-use oxixenon::errors::*;
-use oxixenon::notifier::Notifier;
+use oxixenon_core::*;
+use oxixenon_core::errors::*;
+use oxixenon_core::notifier::Notifier;
 
-#[cfg(all(feature = "client", feature = "client-toasts"))]
-use oxixenon::notification_toasts::*;
+#[cfg(feature = "notification-toasts")]
+use oxixenon_core::notification_toasts::*;
+
+// Xenon is a client, a server or both. Everything else (renewers, notifiers, logging backends) is
+// optional, see the features in Cargo.toml: `minimal` is the smallest useful server.
+#[cfg(not(any(feature = "client", feature = "server")))]
+compile_error!("at least one of the features 'client' and 'server' must be enabled");
+
+// The command line arguments, as used by the configuration.
+struct CliArgs<'a>(&'a clap::ArgMatches<'a>);
+
+impl<'a> config::Args for CliArgs<'a> {
+    fn value_of (&self, name: &str) -> Option<&str> {
+        self.0.value_of (name)
+    }
+
+    fn is_present (&self, name: &str) -> bool {
+        self.0.is_present (name)
+    }
+
+    fn subcommand (&self) -> Option<(&str, Box<dyn config::Args + '_>)> {
+        match self.0.subcommand() {
+            (name, Some(args)) => Some ((name, Box::new (CliArgs(args)))),
+            _ => None
+        }
+    }
+}
+
+// Like `CliArgs`, for subcommands which aren't a run mode (such as `test-notifier`): the run mode
+// is then taken from the environment or the configuration file.
+struct GlobalArgs<'a>(&'a clap::ArgMatches<'a>);
+
+impl<'a> config::Args for GlobalArgs<'a> {
+    fn value_of (&self, name: &str) -> Option<&str> {
+        self.0.value_of (name)
+    }
+
+    fn is_present (&self, name: &str) -> bool {
+        self.0.is_present (name)
+    }
+
+    fn subcommand (&self) -> Option<(&str, Box<dyn config::Args + '_>)> {
+        None
+    }
+}
 
 fn main() {
     let args = clap_app!(oxixenon =>
@@ -21,17 +62,27 @@ fn main() {
         (version: crate_version!())
         (about: "Fresh IPs for everyone.")
         (author: "Roberto Frenna [https://roberto.frenna.pro]")
-        (@arg config: -c --config +takes_value "Sets a custom config file (default: config.toml)")
+        (after_help: "Options can also be set with environment variables named after them, such as \
+            OXIXENON_CONFIG, OXIXENON_LEVEL, OXIXENON_NOTIFIER, OXIXENON_STRICT_CONFIG, \
+            OXIXENON_MODE, OXIXENON_RENEWER, OXIXENON_CONNECT_TO and OXIXENON_ACTION. Command \
+            line arguments take precedence over environment variables, which take precedence \
+            over the configuration file.")
+        (@arg config: -c --config +takes_value
+            "Sets a custom config file (default: the first one found in the standard locations)")
         (@arg level: -l +takes_value possible_value[off error warn info debug trace]
             "Sets logging level")
         (@arg verbose: -v --verbose "Sets logging level to 'debug'")
         (@arg notifier: -n --notifier +takes_value "Uses the specified notifier")
+        (@arg strict_config: --("strict-config")
+            "Rejects unknown configuration options instead of ignoring them")
         (@subcommand client =>
             (about: "Client mode")
             (@arg connect_to: -a --addr +takes_value
                 "Connects to the specified address + port (e.g. 1.2.3.4:1234)")
             (@subcommand renew =>
                 (about: "Sends an IP renewal request")
+                (@arg renewer: -r --renewer +takes_value
+                    "Renews with this renewer of the server, i.e. its key in server.renewer")
             )
             (@subcommand set_availability =>
                 (about: "Sets the availability of the renewal function")
@@ -39,33 +90,179 @@ fn main() {
                     "Availability")
                 (@arg reason: +takes_value
                     "Reason of unavailability - only required when availability is 'unavailable'")
+                (@arg for: --for +takes_value
+                    "Makes renewals available again after this long, e.g. 2h or 1h30m")
             )
             (@subcommand notifications =>
                 (about: "Subscribe to remote notifications")
+                (@arg output: -o --output +takes_value possible_value[log ndjson]
+                    "Logs the events (default) or writes them to stdout as JSON, one per line")
+                (@arg source: -s --source +takes_value possible_value[notifier server]
+                    "Receives the events from the notifier (default) or from the server, over \
+                    the connection to it")
+            )
+            (@subcommand reload =>
+                (about: "Asks the server to reload its logging, metrics and notifier configuration")
             )
+            (@subcommand status =>
+                (about: "Shows the status of the server, including the link of the router")
+            )
+            (@subcommand health =>
+                (about: "Shows the health of the renewer of the server, e.g. whether the router \
+                    accepts its credentials")
+            )
+            (@subcommand history =>
+                (about: "Shows the latest renewals of the server, who requested them and how \
+                    they went")
+                (@arg limit: -n --limit +takes_value
+                    "Number of renewals to show (default: 10)")
+            )
+            (@subcommand set_renewer =>
+                (about: "Switches the server to another of its configured renewers")
+                (visible_alias: "set-renewer")
+                (@arg renewer: * +takes_value "Name of the renewer, i.e. its key in server.renewer")
+            )
+            (@subcommand tray =>
+                (about: "Shows an icon in the notification area with a menu of actions (Windows)")
+            )
+        )
+        (@subcommand init =>
+            (about: "Writes a commented starter configuration file")
+            (@arg mode: -m --mode +takes_value possible_value[server client]
+                "Generates a configuration for the specified mode (default: server)")
+            (@arg renewer: -r --renewer +takes_value
+                possible_values(config_template::RENEWERS)
+                "Configures the specified renewer (default: dummy)")
+            (@arg notifier: -n --notifier +takes_value
+                possible_values(config_template::NOTIFIERS)
+                "Configures the specified notifier (default: multicast)")
+            (@arg output: -o --output +takes_value
+                "Where to write the configuration, or '-' for stdout (default: config.toml)")
+            (@arg force: -f --force "Overwrites the output file if it already exists")
         )
-        (@subcommand server =>
-            (about: "Server mode")
-            (@arg renewer:
-                -r --renewer +takes_value "Uses the specified renewer")
+        (@subcommand bench =>
+            (about: "Measures the requests per second and the latency of a local server, which \
+                uses the dummy renewer")
+            (@arg requests: -n --requests +takes_value
+                "Number of renewal requests to send (default: 10000)")
+            (@arg clients: --clients +takes_value
+                "Number of clients sending requests concurrently (default: 1)")
+            (@arg keep_alive: --("keep-alive")
+                "Sends all the requests of each client over the same connection")
         )
+    ).subcommand (
+        // Built here as `clap_app!` doesn't support subcommands whose name contains a dash, and
+        // shown right after `client`.
+        clap::SubCommand::with_name ("server")
+            .about ("Server mode")
+            .display_order (0)
+            .arg (clap::Arg::from_usage ("-r --renewer [renewer] 'Uses the specified renewer'"))
+            .subcommand (
+                clap::SubCommand::with_name ("test-renewer")
+                    .about ("Initializes the renewer (e.g. logs into the router) and reports \
+                        the results, without starting the server")
+                    .arg (clap::Arg::from_usage ("--renew 'Also renews the IP address once'"))
+            )
+    ).subcommand (
+        clap::SubCommand::with_name ("all-in-one")
+            .about ("Server mode, also handling the events of the server as 'client \
+                notifications' does")
+            .display_order (1)
+            .arg (clap::Arg::from_usage ("-r --renewer [renewer] 'Uses the specified renewer'"))
+            .arg (
+                clap::Arg::from_usage ("-o --output [output] 'Logs the events (default) or \
+                    writes them to stdout as JSON, one per line'")
+                    .possible_values (&["log", "ndjson"])
+            )
+    ).subcommand (
+        // `clap_app!` doesn't support subcommands whose name contains a dash.
+        clap::SubCommand::with_name ("config-schema")
+            .about ("Prints the JSON Schema of the configuration file")
+    ).subcommand (
+        clap::SubCommand::with_name ("test-notifier")
+            .about ("Sends an event through the configured notifier, to check that it's received")
+            .arg (
+                clap::Arg::from_usage ("--event [event] 'The event to send (default: ip_renewed)'")
+                    .possible_values (protocol::Event::NAMES)
+            )
+    ).subcommand (
+        clap::SubCommand::with_name ("setup-toasts")
+            .about ("Creates the Start Menu shortcut required by notification toasts on Windows")
     ).get_matches();
-    // Parse the specified (or default) configuration file.
-    let config_file = args.value_of ("config").unwrap_or ("config.toml");
-    let config = match config::Config::parse_config(config_file, &args) {
+    // `init` doesn't need (and usually doesn't have) a configuration file.
+    if let ("init", Some(init_args)) = args.subcommand() {
+        if let Err(error) = write_starter_config (init_args) {
+            eprintln!("Can't write the starter configuration");
+            eprintln!("{}", error.display_chain());
+            process::exit(1)
+        }
+        return;
+    }
+    if args.subcommand_name() == Some ("config-schema") {
+        print_config_schema();
+        return;
+    }
+    if args.subcommand_name() == Some ("setup-toasts") {
+        setup_toasts();
+        return;
+    }
+    // The benchmark runs its own server, without a configuration file.
+    if let ("bench", Some(bench_args)) = args.subcommand() {
+        if let Err(error) = run_benchmark (bench_args) {
+            eprintln!("Can't run the benchmark");
+            eprintln!("{}", error.display_chain());
+            process::exit(1)
+        }
+        return;
+    }
+    // Parse the specified configuration file, or the first one found in the standard locations.
+    let config_file = match args.value_of ("config").map (String::from)
+        .or_else (|| config::env_arg ("config"))
+    {
+        Some(config_file) => PathBuf::from (config_file),
+        None => config::find_config_file().unwrap_or_else (|| {
+            eprintln!("Can't find a configuration file. Searched in:");
+            for path in config::search_paths() {
+                eprintln!("- {}", path.display());
+            }
+            eprintln!("Specify one with --config, or create one with `oxixenon init`.");
+            process::exit(1)
+        })
+    };
+    let test_notifier_args = args.subcommand_matches ("test-notifier");
+    let parsed = match test_notifier_args {
+        Some(_) => config::Config::parse_config (&config_file, &GlobalArgs(&args)),
+        None => config::Config::parse_config (&config_file, &CliArgs(&args))
+    };
+    let config = match parsed {
         Err(error) => {
             eprintln!("Can't parse config file \"{}\" or command line arguments",
-                config_file);
+                config_file.display());
             eprintln!("{}", error.display_chain());
             process::exit(1)
         },
         Ok(result) => result
     };
-    // Setup logging.
+    // Setup logging. Events written to STDOUT must not be mixed with messages.
+    if let Some(ref client) = config.mode.client {
+        use config::{ClientAction, NotificationsOutput};
+        let action = &client.action;
+        if let ClientAction::SubscribeToNotifications(NotificationsOutput::Ndjson, _) = *action {
+            logging::reserve_stdout();
+        }
+    }
     if let Err(error) = logging::init (&config.logging) {
         eprintln!("Can't setup logging: {}", error.display_chain());
         process::exit(1)
     }
+    info!(target: "config", "loaded configuration from {}", config_file.display());
+    for warning in &config.warnings {
+        warn!(target: "config", "{}", warning);
+    }
+    if let Err(error) = metrics::init (&config.metrics) {
+        log_error_with_chain!(error, "can't setup metrics: {}", error);
+        process::exit(1)
+    }
     // Get and initialize the chosen notifier.
     let notifier = match notifier::get_notifier (&config.notifier) {
         Err(error) => {
@@ -75,10 +272,18 @@ fn main() {
         },
         Ok(result) => result
     };
-    info!("running in {}", config.mode);
-    let result = match config.mode {
-        config::Mode::Server(ref config) => start_server (config, notifier),
-        config::Mode::Client(ref config) => start_client (config, notifier)
+    let result = if let Some(test_args) = test_notifier_args {
+        test_notifier (&config.notifier, notifier, test_args)
+    } else {
+        info!("running in {}", config.mode);
+        match config.mode {
+            config::Mode { server: Some(ref server), client: Some(ref client) } =>
+                start_all_in_one (server, client, notifier, &config_file, &args),
+            config::Mode { server: Some(ref config), .. } =>
+                start_server (config, notifier, &config_file, &args, None),
+            config::Mode { client: Some(ref config), .. } => start_client (config, notifier),
+            config::Mode { .. } => unreachable!()
+        }
     };
     if let Err(error) = result {
         log_error_with_chain!(error, "{}", error);
@@ -86,145 +291,828 @@ fn main() {
     }
 }
 
+// Sends an event through the notifier, so that it can be checked end to end (e.g. by receiving it
+// with `client notifications` on another machine).
+fn test_notifier (
+    config: &config::NotifierConfig,
+    mut notifier: Box<dyn Notifier>,
+    args: &clap::ArgMatches
+) -> Result<()> {
+    let event = args.value_of ("event")
+        .and_then (protocol::Event::from_name)
+        .unwrap_or (protocol::Event::IPRenewed);
+    println!("sending event \"{}\" through notifier '{}'", event.name(), config.name);
+    notifier.notify (event)
+        .chain_err (|| format!("the notifier '{}' failed to send the event", config.name))?;
+    println!("event sent, check that it's received (e.g. with `oxixenon client notifications`)");
+    Ok(())
+}
+
+// Starter configuration
+fn write_starter_config (args: &clap::ArgMatches) -> Result<()> {
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    let defaults = config_template::Template::default();
+    let contents = config_template::Template {
+        mode: args.value_of ("mode").unwrap_or (defaults.mode),
+        renewer: args.value_of ("renewer").unwrap_or (defaults.renewer),
+        notifier: args.value_of ("notifier").unwrap_or (defaults.notifier)
+    }.render()?;
+    let output = args.value_of ("output").unwrap_or ("config.toml");
+    if output == "-" {
+        print!("{}", contents);
+        return Ok(());
+    }
+    let mut options = OpenOptions::new();
+    options.write (true);
+    if args.is_present ("force") {
+        options.create (true).truncate (true);
+    } else {
+        options.create_new (true);
+    }
+    options.open (output)
+        .and_then (|mut file| file.write_all (contents.as_bytes()))
+        .chain_err (|| format!(
+            "can't write to '{}' (use --force to overwrite an existing file)", output
+        ))?;
+    eprintln!("Starter configuration written to '{}'. Remember to review it!", output);
+    Ok(())
+}
+
+// Configuration schema
+#[cfg(feature = "config-schema")]
+fn print_config_schema() {
+    let schema = config::schema::config_schema();
+    println!("{}", serde_json::to_string_pretty (&schema).expect ("schemas can always be printed"));
+}
+
+#[cfg(not(feature = "config-schema"))]
+fn print_config_schema() {
+    eprintln!("Configuration schema export is disabled, rebuild with the feature 'config-schema'");
+    process::exit(255)
+}
+
+// Notification toasts setup
+#[cfg(feature = "client-toasts")]
+fn setup_toasts() {
+    match setup() {
+        Ok(setup) => {
+            eprintln!("Shortcut written to '{}'.", setup.shortcut.display());
+            if let Some(icon) = setup.icon {
+                eprintln!("Icon written to '{}'.", icon.display());
+            }
+            eprintln!("Notification toasts are ready, run `oxixenon client notifications`.");
+        },
+        Err(error) => {
+            eprintln!("Can't set up notification toasts: {}", error);
+            process::exit(1)
+        }
+    }
+}
+
+#[cfg(not(feature = "client-toasts"))]
+fn setup_toasts() {
+    eprintln!(
+        "Notification toasts on Windows are disabled, rebuild with the feature 'client-toasts'"
+    );
+    process::exit(255)
+}
+
 // Server
+// Re-applies the logging, metrics and notifier sections of the configuration file to a running
+// server.
+#[cfg(feature = "server")]
+#[derive(Clone)]
+struct ConfigReloader {
+    config_file: PathBuf,
+    args: clap::ArgMatches<'static>,
+    notifier: std::sync::Arc<std::sync::Mutex<Box<dyn Notifier>>>
+}
+
 #[cfg(feature = "server")]
-fn start_server (config: &config::ServerConfig, mut notifier: Box<dyn Notifier>) -> Result<()> {
-    use std::io::{BufWriter, BufReader};
-    use std::time;
+impl ConfigReloader {
+    fn reload (&self) -> Result<()> {
+        let config = config::Config::parse_config (&self.config_file, &CliArgs(&self.args))
+            .chain_err (|| format!("can't parse config file \"{}\"", self.config_file.display()))?;
+        // Everything is instantiated before being swapped, so that an invalid configuration
+        // leaves the server untouched.
+        let notifier = notifier::get_notifier (&config.notifier)
+            .chain_err (|| format!(
+                "can't instantiate the requested notifier '{}'", config.notifier.name
+            ))?;
+        logging::reload (&config.logging)?;
+        metrics::init (&config.metrics)?;
+        for warning in &config.warnings {
+            warn!(target: "config", "{}", warning);
+        }
+        *self.notifier.lock().unwrap() = notifier;
+        info!(target: "server", "configuration reloaded, now using notifier '{}'",
+            config.notifier.name);
+        Ok(())
+    }
+
+    // Reloads the configuration every time SIGHUP is received.
+    #[cfg(not(windows))]
+    fn reload_on_sighup (&self) -> Result<()> {
+        use signal_hook::{consts::SIGHUP, iterator::Signals};
+        use std::thread;
+        let mut signals = Signals::new ([SIGHUP])
+            .chain_err (|| "failed to register the SIGHUP handler")?;
+        let reloader = self.clone();
+        thread::spawn (move || {
+            for _ in signals.forever() {
+                let _correlation = logging::correlate();
+                info!(target: "server", "received SIGHUP, reloading the configuration");
+                if let Err(error) = reloader.reload() {
+                    log_error_with_chain!(
+                        target: "server",
+                        log::Level::Error,
+                        error, "can't reload the configuration: {}", error
+                    );
+                }
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(windows)]
+    fn reload_on_sighup (&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Handles the events of the server within the process, see `start_all_in_one`.
+type OnServerEvent = Box<dyn FnMut(protocol::Event, &protocol::EventPayload) + Send>;
+
+#[cfg(feature = "server")]
+fn start_server (
+    config: &config::ServerConfig,
+    notifier: Box<dyn Notifier>,
+    config_file: &Path,
+    args: &clap::ArgMatches<'static>,
+    on_event: Option<OnServerEvent>
+) -> Result<()> {
     use std::net::TcpListener;
-    use oxixenon::protocol::{Packet, Event, RenewAvailability};
-    // Local macro to make returning errors easy.
-    macro_rules! error_packet {
-        ($writer: ident, $($message: tt),+) => {{
-            let msg = format!($($message),+);
-            warn!(target: "server", "client produced error: {}", msg);
-            Packet::Error (msg)
-                .write (&mut $writer)
-                .map_err (|e| e.into())
-        }}
+    let test_renewer_args = args.subcommand_matches ("server")
+        .and_then (|server| server.subcommand_matches ("test-renewer"));
+    if let Some(test_args) = test_renewer_args {
+        return test_renewer (config, test_args.is_present ("renew"));
     }
     // Fetch an instance of the IP renewer
-    let mut renewer = renewer::get_renewer (&config.renewer)?;
+    info!(target: "server", "using renewer '{}' of type '{}'",
+        config.renewer.instance, config.renewer.name);
+    let mut renewer = renewer::get_renewer (&config.renewer, &config.renewers)?;
     renewer.init()?;
-    // Store the current availability status.
-    let mut availability = RenewAvailability::Available;
+    let mut server = server::Server::new (renewer, notifier)
+        .renewer_name (config.renewer.instance.as_str());
+    if let Some(window) = config.coalesce_events {
+        server = server.coalesce_events (window);
+    }
+    if let Some(ref hostname) = config.hostname {
+        server = server.origin (Some (hostname.clone()));
+    }
+    // Set first, so that a larger history is restored from the state directory.
+    if let Some(size) = config.history_size {
+        server = server.history_size (size);
+    }
+    server = server.scheduled (!config.schedule.is_empty());
+    if let Some(ref path) = config.state_dir {
+        server = server.state_dir (state::StateDir::open (path)?);
+    }
+    if let Some(on_event) = on_event {
+        server = server.on_event (on_event);
+    }
+    if let Some(ref token) = config.admin_token {
+        server = server.admin_token (token.clone());
+    }
+    if let Some(ref psk) = config.psk {
+        server = server.psk (psk.clone());
+    }
+    for token in &config.tokens {
+        server = server.token (token.clone());
+    }
+    server = server.acl (config.acl.clone());
+    if let Some(timeout) = config.idle_timeout {
+        server = server.idle_timeout (timeout);
+    }
+    if let Some(max_clients) = config.max_clients {
+        server = server.max_clients (max_clients);
+    }
+    if let Some(window) = config.coalesce_renewals {
+        server = server.coalesce_renewals (window);
+    }
+    if let Some(ref tls) = config.tls {
+        server = server_tls (server, tls)?;
+    }
+    if let Some(ref verify) = config.verify_renewal {
+        let source = ip_source (verify.url.as_deref(), verify.stun.as_deref());
+        server = server.verify_renewals (source, verify.timeout, verify.interval);
+    }
+    // Administrators can switch to any of the configured renewers.
+    let renewers: Vec<_> = Some (config.renewer.clone()).into_iter()
+        .chain (config.renewers.iter().cloned())
+        .collect();
+    server = server.on_set_renewer (move |instance| {
+        let renewer_config = renewers.iter()
+            .find (|renewer| renewer.instance == instance)
+            .chain_err (|| format!("there is no renewer named '{}'", instance))?;
+        info!(target: "server", "using renewer '{}' of type '{}'",
+            renewer_config.instance, renewer_config.name);
+        let mut renewer = renewer::get_renewer (renewer_config, &renewers)?;
+        renewer.init()?;
+        Ok(renewer)
+    });
+    // The notifier is replaced when the configuration is reloaded.
+    let reloader = ConfigReloader {
+        config_file: config_file.into(),
+        args: args.clone(),
+        notifier: server.notifier()
+    };
+    reloader.reload_on_sighup()?;
+    let server = server.on_reload (move || reloader.reload());
     info!(target: "server", "binding to {}", config.bind_to);
     let listener = TcpListener::bind (config.bind_to.as_str())
         .chain_err (|| format!("failed to bind to {}", config.bind_to))?;
-    for stream in listener.incoming() {
-        let stream = stream.chain_err (|| "failed to retrieve I/O stream")?;
-        let peer_addr = stream.peer_addr().chain_err (|| "failed to retrieve peer address")?;
-        let mut writer = BufWriter::new (&stream);
-        let mut reader = BufReader::new (&stream);
-        debug!(target: "server", "new client connected: {}", peer_addr);
-        
-        // poor man's try-catch block
-        let result = (|| -> Result<()> {
-            stream.set_read_timeout (Some (time::Duration::from_secs (5)))
-                .chain_err (|| "failed to set stream read timeout to 5 seconds")?;
-            let packet = Packet::read (&mut reader)
-                .chain_err (|| "invalid packet")?;
-            match packet {
-                Packet::FreshIPRequest => {
-                    info!(target: "server", "client {} requested a new IP address", peer_addr);
-                    if let RenewAvailability::Unavailable(reason) = &availability {
-                        return error_packet!(writer, "Renewal unavailable: {}", reason);
+    let mut listeners = vec![(listener, protocol::Encoding::Binary)];
+    if let Some(ref bind_to) = config.json_bind_to {
+        listeners.push (bind_json (bind_to)?);
+    }
+    serve (server, listeners, config)
+}
+
+// Binds to `bind_to` for the clients speaking the JSON encoding of the protocol.
+#[cfg(all(feature = "server", feature = "json-protocol"))]
+fn bind_json (bind_to: &str) -> Result<(std::net::TcpListener, protocol::Encoding)> {
+    info!(target: "server", "binding to {} for the JSON encoding", bind_to);
+    let listener = std::net::TcpListener::bind (bind_to)
+        .chain_err (|| format!("failed to bind to {}", bind_to))?;
+    Ok((listener, protocol::Encoding::Json))
+}
+
+#[cfg(all(feature = "server", not(feature = "json-protocol")))]
+fn bind_json (_bind_to: &str) -> Result<(std::net::TcpListener, protocol::Encoding)> {
+    bail!("'server.json_bind_to' requires the feature 'json-protocol'")
+}
+
+// How long a server stopped by SIGINT or SIGTERM waits for the clients being answered (e.g. for a
+// renewal in progress) before exiting anyway.
+#[cfg(all(feature = "server", not(windows)))]
+const SHUTDOWN_TIMEOUT: std::time::Duration = std::time::Duration::from_secs (30);
+
+// Handles the clients connecting to `listeners` (whose packets are in the given encoding), along
+// with SIGUSR1 (renew the IP address, as if a client asked for it), SIGUSR2 (toggle the
+// availability of renewals) and SIGINT or SIGTERM (stop accepting clients, and exit once those
+// connected are answered) where signals exist. The public IP address is also checked, the session
+// of the renewer refreshed and its health checked periodically, and the IP address renewed on
+// schedule, as configured by `server_config`.
+#[cfg(feature = "server")]
+fn serve (
+    mut server: server::Server,
+    listeners: Vec<(std::net::TcpListener, protocol::Encoding)>,
+    server_config: &config::ServerConfig
+) -> Result<()> {
+    use std::{sync::mpsc, thread};
+    // The renewer can't leave this thread, so signals, checks and keep-alives are sent to it as
+    // jobs, run in turn with the renewals requested by the clients.
+    let handle = server.handle();
+    // The accepted clients, until there's `None` when the server is stopped.
+    let (sender, streams) = mpsc::channel();
+    #[cfg(not(windows))]
+    let mut signals = {
+        use signal_hook::{consts::{SIGINT, SIGTERM, SIGUSR1, SIGUSR2}, iterator::Signals};
+        Signals::new ([SIGUSR1, SIGUSR2, SIGINT, SIGTERM])
+            .chain_err (|| "failed to register the SIGUSR1, SIGUSR2, SIGINT and SIGTERM handlers")?
+    };
+    #[cfg(not(windows))]
+    {
+        use signal_hook::{consts::{SIGINT, SIGTERM}, low_level::signal_name};
+        let (handle, stop) = (handle.clone(), sender.clone());
+        thread::spawn (move || {
+            let mut stopping = false;
+            for signal in signals.forever() {
+                let name = signal_name (signal).unwrap_or ("signal");
+                match signal {
+                    SIGINT | SIGTERM if stopping => {
+                        warn!(target: "server", "received {} again, exiting right away", name);
+                        handle.shut_down();
+                        process::exit(1)
+                    },
+                    SIGINT | SIGTERM => {
+                        info!(target: "server",
+                            "received {}, waiting up to {} for the connected clients", name,
+                            config::format_duration (SHUTDOWN_TIMEOUT));
+                        stopping = true;
+                        let _ = stop.send (None);
+                        let handle = handle.clone();
+                        thread::spawn (move || {
+                            thread::sleep (SHUTDOWN_TIMEOUT);
+                            warn!(target: "server", "the connected clients took too long, \
+                                exiting anyway");
+                            handle.shut_down();
+                            process::exit(1)
+                        });
+                    },
+                    _ => if handle.spawn (move |server| handle_signal (server, signal)).is_err() {
+                        break;
                     }
-                    // Make sure that the outermost error is something safe to send to the client.
-                    renewer.renew_ip()
-                        .chain_err (|| "failed to renew the IP address")?;
-                    notifier.notify (Event::IPRenewed)
-                        .chain_err (|| "failed to notify the requested event")?;
-                },
-                Packet::SetRenewingAvailable (new_availability) => {
-                    info!(target: "server", "client {} set availability to {}",
-                        peer_addr, new_availability);
-                    availability = new_availability;
-                },
-                _ => return error_packet!(writer, "Unsupported packet")
+                }
+            }
+        });
+    }
+    if let Some(ref ip_check) = server_config.ip_check {
+        info!(target: "server", "checking the IP address every {}",
+            config::format_duration (ip_check.interval));
+        let source = ip_source (ip_check.url.as_deref(), ip_check.stun.as_deref());
+        let (handle, interval) = (handle.clone(), ip_check.interval);
+        thread::spawn (move || {
+            let check_ip = move |server: &mut server::Server| {
+                let _correlation = logging::correlate();
+                if let Err(error) = server.check_ip (&source) {
+                    log_error_with_chain!(
+                        target: "server",
+                        log::Level::Warn,
+                        error, "can't check the IP address: {}", error
+                    );
+                }
+            };
+            while handle.run (check_ip.clone()).is_ok() {
+                thread::sleep (interval);
+            }
+        });
+    }
+    if let Some(interval) = server_config.keepalive_interval {
+        info!(target: "server", "refreshing the session of the renewer every {}",
+            config::format_duration (interval));
+        let handle = handle.clone();
+        thread::spawn (move || {
+            // The renewer has just logged in, so the first keep-alive waits for the interval.
+            thread::sleep (interval);
+            let keepalive = |server: &mut server::Server| {
+                let _correlation = logging::correlate();
+                if let Err(error) = server.keepalive() {
+                    log_error_with_chain!(
+                        target: "server",
+                        log::Level::Warn,
+                        error, "{}", error
+                    );
+                }
             };
-            Packet::Ok.write (&mut writer)?;
-            Ok(())
-        })();
+            while handle.run (keepalive).is_ok() {
+                thread::sleep (interval);
+            }
+        });
+    }
+    if let Some(interval) = server_config.health_check_interval {
+        info!(target: "server", "checking the health of the renewer every {}",
+            config::format_duration (interval));
+        let handle = handle.clone();
+        thread::spawn (move || {
+            let check_health = |server: &mut server::Server| {
+                let _correlation = logging::correlate();
+                server.check_health();
+            };
+            while handle.run (check_health).is_ok() {
+                thread::sleep (interval);
+            }
+        });
+    }
+    for schedule in &server_config.schedule {
+        info!(target: "server", "renewing the IP address on schedule ({})", schedule);
+        let (handle, schedule) = (handle.clone(), schedule.clone());
+        thread::spawn (move || {
+            while schedule.wait() {
+                let schedule = schedule.clone();
+                let renew = move |server: &mut server::Server| {
+                    let _correlation = logging::correlate();
+                    // Renewals made unavailable, even for a while, are skipped rather than failed.
+                    if let Some(reason) = server.availability().reason() {
+                        info!(target: "server", "skipping the scheduled renewal ({}): renewals \
+                            are unavailable ({})", schedule, reason);
+                        return;
+                    }
+                    if server.renewed_recently() {
+                        info!(target: "server", "skipping the scheduled renewal ({}): the IP \
+                            address was just renewed", schedule);
+                        return;
+                    }
+                    info!(target: "server", "renewing the IP address as scheduled ({})",
+                        schedule);
+                    if let Err(error) = server.renew() {
+                        log_error_with_chain!(
+                            target: "server",
+                            log::Level::Warn,
+                            error, "can't renew the IP address: {}", error
+                        );
+                    }
+                };
+                if handle.run (renew).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+    drop (handle);
+    for (listener, encoding) in listeners {
+        let sender = sender.clone();
+        thread::spawn (move || {
+            for stream in listener.incoming() {
+                let failed = stream.is_err();
+                if sender.send (Some ((stream, encoding))).is_err() || failed {
+                    break;
+                }
+            }
+        });
+    }
+    drop (sender);
+    // Whatever stops the server, the subscribers of its events are told.
+    let served = server.serve_encoded (streams.into_iter().map_while (|stream| stream));
+    server.shut_down();
+    served
+}
 
-        if let Err(err) = result {
+// Where the public IP address is read from, given the `url` and `stun` options of a section.
+#[cfg(feature = "server")]
+fn ip_source (url: Option<&str>, stun: Option<&str>) -> server::IpSource {
+    match (url, stun) {
+        (Some(url), _) => server::IpSource::Url (url.into()),
+        (None, Some(stun)) => server::IpSource::Stun (stun.into()),
+        (None, None) => server::IpSource::Renewer
+    }
+}
+
+#[cfg(all(feature = "server", not(windows)))]
+fn handle_signal (server: &mut server::Server, signal: i32) {
+    use oxixenon_core::protocol::RenewAvailability;
+    use signal_hook::consts::SIGUSR1;
+    let _correlation = logging::correlate();
+    if signal == SIGUSR1 {
+        info!(target: "server", "received SIGUSR1, renewing the IP address");
+        if let Err(error) = server.renew() {
             log_error_with_chain!(
                 target: "server",
                 log::Level::Warn,
-                err, "client {} produced external error: {}", peer_addr, err
+                error, "can't renew the IP address: {}", error
             );
+        }
+    } else {
+        let availability = match server.availability() {
+            RenewAvailability::Available =>
+                RenewAvailability::Unavailable ("toggled with SIGUSR2".into()),
+            _ => RenewAvailability::Available
+        };
+        info!(target: "server", "received SIGUSR2, setting availability to {}", availability);
+        server.set_availability (availability);
+    }
+}
 
-            // Retrieve a safe message to send to the client as an error message.
-            let message = match err {
-                // Protocol and chained errors can be safely sent (without the underlying cause)
-                Error(ErrorKind::Protocol(err), _) => err.to_string(),
-                Error(ErrorKind::Msg(err), _)      => err,
-                Error(ErrorKind::Notifier(_), _)   => "failed to send notifications".into(),
-                Error(ErrorKind::Renewer(_), _)    => "failed to renew the IP address".into(),
-                _                                  => "unexpected error".into()
-            };
-
-            // ignore errors while writing errors
-            let _ = Packet::Error(message).write (&mut writer);
+// Checks the configured renewer without starting the server: initializes it (which usually logs
+// into the router and thus checks the credentials) and, if `renew`, renews the IP address once.
+#[cfg(feature = "server")]
+fn test_renewer (config: &config::ServerConfig, renew: bool) -> Result<()> {
+    use std::time::Instant;
+    // Runs a step, printing whether it succeeded along with how long it took.
+    fn step<T> (name: &str, run: impl FnOnce() -> Result<T>) -> Result<T> {
+        let started = Instant::now();
+        let result = run();
+        let elapsed = started.elapsed().as_secs_f64() * 1000.0;
+        match result {
+            Ok(_) => println!("{}: ok ({:.1} ms)", name, elapsed),
+            Err(_) => println!("{}: failed ({:.1} ms)", name, elapsed)
+        }
+        result
+    }
+    println!("testing renewer '{}' of type '{}'", config.renewer.instance, config.renewer.name);
+    let mut renewer = step ("configuration", || {
+        Ok(renewer::get_renewer (&config.renewer, &config.renewers)?)
+    })?;
+    step ("initialization", || Ok(renewer.init()?))
+        .chain_err (|| "the renewer failed to initialize")?;
+    if renew {
+        let outcome = step ("renewal", || Ok(renewer.renew_ip()?))
+            .chain_err (|| "the renewer failed to renew the IP address")?;
+        if !outcome.is_empty() {
+            println!("renewed the IP address {}", outcome);
         }
+    } else {
+        println!("renewal: skipped, use --renew to renew the IP address");
     }
     Ok(())
 }
 
 #[cfg(not(feature = "server"))]
-fn start_server (_config: &config::ServerConfig, _notifier: Box<Notifier>) -> Result<()> {
+fn start_server (
+    _config: &config::ServerConfig,
+    _notifier: Box<dyn Notifier>,
+    _config_file: &Path,
+    _args: &clap::ArgMatches<'static>,
+    _on_event: Option<OnServerEvent>
+) -> Result<()> {
     error!("server functionality is disabled");
     process::exit(255)
 }
 
+// All-in-one
+// Runs a server, while handling its events within the process as `client notifications` handles
+// the events it receives (e.g. to feed a script updating the firewall of the router itself).
+#[cfg(all(feature = "client", feature = "server"))]
+fn start_all_in_one (
+    server: &config::ServerConfig,
+    client: &config::ClientConfig,
+    notifier: Box<dyn Notifier>,
+    config_file: &Path,
+    args: &clap::ArgMatches<'static>
+) -> Result<()> {
+    let output = match client.action {
+        config::ClientAction::SubscribeToNotifications (output, _) => output,
+        _ => config::NotificationsOutput::Log
+    };
+    let on_event = notification_handler (client, output)?;
+    let on_event = Box::new (move |event, payload: &_| on_event (event, payload, None));
+    start_server (server, notifier, config_file, args, Some (on_event))
+}
+
+#[cfg(not(all(feature = "client", feature = "server")))]
+fn start_all_in_one (
+    _server: &config::ServerConfig,
+    _client: &config::ClientConfig,
+    _notifier: Box<dyn Notifier>,
+    _config_file: &Path,
+    _args: &clap::ArgMatches<'static>
+) -> Result<()> {
+    error!("the all-in-one mode requires both the client and the server functionality");
+    process::exit(255)
+}
+
+// Benchmark
+// Sends renewal requests to a server running the dummy renewer (and no notifier) on the loopback
+// interface, and prints the requests per second along with the latency of the requests.
+#[cfg(all(feature = "server", feature = "client"))]
+fn run_benchmark (args: &clap::ArgMatches) -> Result<()> {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+    let count = |name: &str, default: usize| -> Result<usize> {
+        let value = args.value_of (name).map_or (Ok(default), str::parse)
+            .chain_err (|| format!("invalid value for '--{}'", name))?;
+        ensure!(value > 0, "'--{}' must be greater than zero", name);
+        Ok(value)
+    };
+    let requests = count ("requests", 10000)?;
+    let clients = count ("clients", 1)?.min (requests);
+    let keep_alive = args.is_present ("keep_alive");
+    let renewer = renewer::get_renewer (&config::RenewerConfig {
+        name: "dummy".into(),
+        instance: "dummy".into(),
+        config: config::Section::empty ("server.renewer.dummy")
+    }, &[])?;
+    let notifier = notifier::get_notifier (&config::NotifierConfig {
+        name: "none".into(),
+        config: config::Section::empty ("notifier.none")
+    })?;
+    // Requests aren't held back waiting for others, though those arriving during a renewal are
+    // still coalesced into the next one.
+    let mut server = server::Server::new (renewer, notifier).coalesce_renewals (Duration::ZERO);
+    let listener = TcpListener::bind ("127.0.0.1:0")
+        .chain_err (|| "failed to bind to 127.0.0.1")?;
+    let addr = listener.local_addr().chain_err (|| "failed to retrieve the local address")?;
+    println!("sending {} requests from {} clients to {}...", requests, clients, addr);
+    let started = Instant::now();
+    let client_threads = (0..clients)
+        .map (|n| {
+            // The first clients send the requests left over by the division.
+            let requests = requests / clients + usize::from (n < requests % clients);
+            let client = match keep_alive {
+                true => client::Client::new (addr.to_string()).keep_alive(),
+                false => client::Client::new (addr.to_string())
+            };
+            thread::spawn (move || -> Result<Vec<Duration>> {
+                (0..requests)
+                    .map (|_| {
+                        let sent = Instant::now();
+                        client.renew_ip()?;
+                        Ok(sent.elapsed())
+                    })
+                    .collect()
+            })
+        })
+        .collect::<Vec<_>>();
+    // Kept connections are closed once their client sent all of its requests.
+    server.serve (listener.incoming().take (if keep_alive { clients } else { requests }))?;
+    let mut latencies = Vec::with_capacity (requests);
+    for client_thread in client_threads {
+        latencies.extend (client_thread.join().expect ("a client thread panicked")?);
+    }
+    let elapsed = started.elapsed();
+    latencies.sort();
+    let percentile = |p: usize| latencies[(latencies.len() - 1) * p / 100];
+    println!("{} requests in {:.3?}: {:.0} requests/second", requests, elapsed,
+        requests as f64 / elapsed.as_secs_f64());
+    println!("latency: min {:.1?}, p50 {:.1?}, p90 {:.1?}, p99 {:.1?}, max {:.1?}",
+        latencies[0], percentile (50), percentile (90), percentile (99), percentile (100));
+    Ok(())
+}
+
+#[cfg(not(all(feature = "server", feature = "client")))]
+fn run_benchmark (_args: &clap::ArgMatches) -> Result<()> {
+    bail!("the benchmark requires both the client and the server, rebuild with the features \
+        'client' and 'server'")
+}
+
 // Client
-#[cfg(feature = "client-toasts")]
-fn try_send_toast (toasts: &NotificationToasts, message: &str) {
-    if let Err(e) = toasts.send_toast (message) {
+#[cfg(feature = "notification-toasts")]
+fn try_send_toast (toasts: &NotificationToasts, toast: &Toast) {
+    if let Err(e) = toasts.send_toast (toast) {
         warn!(target: "client", "can't send notification toast: {}", e)
     }
 }
 
+// Shows the toasts of the events received, coalescing bursts of identical events if configured.
+// Toasts are shown from their own thread, as coalesced events are delivered later on.
+#[cfg(feature = "notification-toasts")]
+fn toast_events (config: &config::ClientConfig) -> Result<impl Fn(
+    protocol::Event, &protocol::EventPayload, Option<std::net::SocketAddr>
+) + Send> {
+    use std::sync::mpsc;
+    use std::thread;
+    let templates = toast_templates (config)?;
+    let (sender, received) = mpsc::channel();
+    thread::spawn (move || {
+        let toasts = NotificationToasts::new();
+        for (event, (payload, source), burst) in received {
+            try_send_toast (&toasts, &templates.render_burst (event, &payload, source, burst));
+        }
+    });
+    let coalescer = config.coalesce_events.map (|window| {
+        let sender = sender.clone();
+        notifier::Coalescer::new (window, move |event, data, burst| {
+            let _ = sender.send ((event, data, burst));
+        })
+    });
+    // Events coalesced by the server already stand for several ones.
+    Ok(move |event, payload: &protocol::EventPayload, source| {
+        let count = payload.coalesced.unwrap_or (1);
+        match coalescer {
+            Some(ref coalescer) => coalescer.push_counted (event, (payload.clone(), source), count),
+            None => { let _ = sender.send ((event, (payload.clone(), source), count)); }
+        }
+    })
+}
+
+// An event as a JSON object, e.g. {"event":"ip_renewed","description":"An IP renewal has been
+// requested","source":"192.168.1.2:5454","origin":"router","old_ip":null,"new_ip":"192.0.2.1",
+// "timestamp":1500000000}. The source, the origin and the addresses may be null. Events without a
+// timestamp are timestamped when received.
+#[cfg(feature = "client")]
+fn event_json (
+    event: &protocol::Event,
+    payload: &protocol::EventPayload,
+    source: Option<std::net::SocketAddr>
+) -> String {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    // The values never contain control characters, so escaping quotes and backslashes suffices.
+    let string = |value: &str|
+        format!("\"{}\"", value.replace ('\\', "\\\\").replace ('"', "\\\""));
+    let optional = |value: Option<String>|
+        value.map_or_else (|| "null".to_string(), |value| string (&value));
+    let timestamp = payload.timestamp.unwrap_or_else (SystemTime::now);
+    format!(
+        "{{\"event\":{},\"description\":{},\"source\":{},\"origin\":{},\"old_ip\":{},\
+         \"new_ip\":{},\"timestamp\":{}}}",
+        string (event.name()),
+        string (event.extended_descr()),
+        optional (source.map (|source| source.to_string())),
+        optional (payload.origin.clone()),
+        optional (payload.outcome.old_ip.map (|ip| ip.to_string())),
+        optional (payload.outcome.new_ip.map (|ip| ip.to_string())),
+        timestamp.duration_since (UNIX_EPOCH).map_or (0, |d| d.as_secs())
+    )
+}
+
+// Reads the configuration of the toasts, in `client.toasts`.
+#[cfg(feature = "notification-toasts")]
+fn toast_templates (config: &config::ClientConfig) -> Result<ToastTemplates> {
+    let (templates, warnings) = config.toasts.parse_with_warnings::<ToastTemplates>()
+        .chain_err (|| "the notification toasts are not configured correctly")?;
+    for warning in warnings {
+        warn!(target: "config", "{}", warning);
+    }
+    Ok(templates)
+}
+
+// Handles the events received by `client notifications`: logs them, writes them to STDOUT when
+// the output is NDJSON and shows them as toasts (if enabled).
+#[cfg(feature = "client")]
+#[cfg_attr(not(feature = "notification-toasts"), allow(unused_variables))]
+fn notification_handler (config: &config::ClientConfig, output: config::NotificationsOutput)
+    -> Result<impl Fn(protocol::Event, &protocol::EventPayload, Option<std::net::SocketAddr>)
+        + Send>
+{
+    #[cfg(feature = "notification-toasts")]
+    let show_toast = toast_events (config)?;
+    Ok(move |event, payload: &protocol::EventPayload, from: Option<std::net::SocketAddr>| {
+        let from_str = from.map (|x| x.to_string()).unwrap_or ("unknown".into());
+        match payload.is_empty() {
+            true => info!(target: "client", "received event \"{}\" from {}", event, from_str),
+            false => info!(target: "client", "received event \"{}\" from {} ({})",
+                event, from_str, payload)
+        }
+        if output == config::NotificationsOutput::Ndjson {
+            println!("{}", event_json (&event, payload, from));
+        }
+        #[cfg(feature = "notification-toasts")]
+        show_toast (event, payload, from);
+    })
+}
+
 #[cfg(feature = "client")]
 fn start_client (config: &config::ClientConfig, mut notifier: Box<dyn Notifier>) -> Result<()> {
-    use std::io::prelude::*;
-    use std::io::{BufReader, BufWriter};
-    use std::net::TcpStream;
-    use oxixenon::protocol::Packet;
+    use oxixenon_core::protocol::Packet;
+    use std::ops::ControlFlow;
     info!(target: "client", "running action '{}'", config.action);
+    // The admin token required by the requests reserved to administrators, which tokens of scope
+    // `admin` (sent as the pre-shared key) don't need.
+    let admin_token = |request: &str| -> Result<config::Secret<String>> {
+        match (config.admin_token.clone(), &config.psk) {
+            (Some(token), _) => Ok(token),
+            (None, Some(_)) => Ok("".into()),
+            (None, None) => bail!(
+                "{} requires the admin token of the server, see 'admin_token'", request
+            )
+        }
+    };
     let packet = match config.action {
-        config::ClientAction::RenewIP => Some (Packet::FreshIPRequest),
+        config::ClientAction::RenewIP (ref renewer) =>
+            Some (Packet::FreshIPRequest { renewer: renewer.clone() }),
         config::ClientAction::SetRenewingAvailability (ref availability) =>
             Some (Packet::SetRenewingAvailable (availability.clone())),
-        config::ClientAction::SubscribeToNotifications => {
-            #[cfg(feature = "client-toasts")]
-            let toasts = NotificationToasts::new();
-            notifier.listen (&|event, from| {
-                let from_str = from.map (|x| x.to_string()).unwrap_or ("unknown".into());
-                info!(target: "client", "received event \"{}\" from {}", event, from_str);
-                #[cfg(feature = "client-toasts")]
-                try_send_toast (&toasts,
-                    format!("{}\nRequest sent by {}", event.extended_descr(), from_str).as_str());
-            })?;
+        config::ClientAction::ReloadConfig =>
+            Some (Packet::ReloadConfig { token: admin_token ("reloading the configuration")? }),
+        config::ClientAction::Status => Some (Packet::StatusRequest),
+        config::ClientAction::Health => Some (Packet::HealthRequest),
+        config::ClientAction::History (limit) => Some (Packet::HistoryRequest { limit }),
+        config::ClientAction::SetRenewer (ref renewer) => Some (Packet::SetRenewer {
+            renewer: renewer.clone(),
+            token: admin_token ("switching renewers")?
+        }),
+        config::ClientAction::SubscribeToNotifications (output, source) => {
+            let on_event = notification_handler (config, output)?;
+            let mut on_event = |event, payload: &_, from| {
+                on_event (event, payload, from);
+                ControlFlow::Continue(())
+            };
+            // Listens until the process is terminated.
+            let shutdown = notifier::Shutdown::new();
+            match source {
+                config::NotificationsSource::Notifier =>
+                    notifier.listen (&mut on_event, &shutdown)?,
+                config::NotificationsSource::Server =>
+                    new_client (config)?.subscribe (&mut on_event, &shutdown)?
+            }
+            None
+        },
+        config::ClientAction::Tray => {
+            run_tray (config, notifier)?;
             None
         }
     };
 
     if let Some(packet) = packet {
-        info!(target: "client", "connecting to {}...", config.connect_to);
-        let stream = TcpStream::connect (config.connect_to.as_str())
-            .chain_err (|| format!("failed to connect to {}", config.connect_to))?;
-        let mut reader = BufReader::new (&stream);
-        let mut writer = BufWriter::new (&stream);
-        packet.write (&mut writer)?;
-        writer.flush()
-            .chain_err (|| "failed to flush the I/O stream")?;
-
-        let response = Packet::read (&mut reader)?;
+        let _correlation = logging::correlate();
+        let response = new_client (config)?.request (&packet)?;
 
         match response {
             Packet::Ok => info!(target: "client", "action completed successfully"),
-            Packet::Error (ref msg) => error!(target: "client", "{}", msg),
+            Packet::Renewed (outcome) =>
+                info!(target: "client", "renewed the IP address {}", outcome),
+            Packet::IPUnchanged (ip) =>
+                warn!(target: "client", "the IP address was renewed, but it's still {}", ip),
+            Packet::Status (status) => {
+                println!("renewer: {}", status.renewer.as_deref().unwrap_or ("unknown"));
+                println!("availability: {}", status.availability);
+                match status.link {
+                    Some(link) => println!("link: {}", link),
+                    None => println!("link: unknown, the renewer doesn't report it")
+                }
+            },
+            Packet::Health { health, age } =>
+                println!("{}, checked {} ago", health, config::format_duration (age)),
+            Packet::History (entries) => {
+                if entries.is_empty() {
+                    println!("no renewals yet");
+                }
+                // Most recent last, as in a log.
+                for entry in entries.iter().rev() {
+                    println!(
+                        "{}  {}  {}  {}",
+                        logging::format_time (entry.timestamp, true),
+                        entry.renewer.as_deref().unwrap_or ("-"),
+                        entry.peer.as_deref().unwrap_or ("server"),
+                        entry.outcome
+                    );
+                }
+            },
+            Packet::Error { code, detail } =>
+                error!(target: "client", "{}", Error::Refused (code, detail)),
             _ => error!(target: "client", "received unknown packet: {:?}", response)
         }
     }
@@ -232,8 +1120,186 @@ fn start_client (config: &config::ClientConfig, mut notifier: Box<dyn Notifier>)
     Ok(())
 }
 
+// A client of the server of `config`.
+#[cfg(feature = "client")]
+fn new_client (config: &config::ClientConfig) -> Result<client::Client> {
+    let mut client = client::Client::new (config.connect_to.as_str());
+    if let Some(interval) = config.resolve_interval {
+        client = client.resolve_interval (interval);
+    }
+    if let Some(ref proxy) = config.proxy {
+        client = client.proxy (client::Proxy {
+            addr: proxy.addr.clone(),
+            credentials: proxy.credentials.clone()
+        });
+    }
+    if let Some(ref psk) = config.psk {
+        client = client.psk (psk.clone());
+    }
+    match config.tls {
+        Some(ref tls) => client_tls (client, tls),
+        None => Ok(client)
+    }
+}
+
+// Makes `server` talk to its clients over TLS.
+#[cfg(all(feature = "server", feature = "tls"))]
+fn server_tls (server: server::Server, config: &config::ServerTlsConfig)
+    -> Result<server::Server>
+{
+    let tls = tls::ServerTls::new (&config.cert, &config.key, config.client_ca.as_deref())
+        .chain_err (|| "invalid option 'server.tls'")?;
+    Ok(server.tls (tls))
+}
+
+#[cfg(all(feature = "server", not(feature = "tls")))]
+fn server_tls (_server: server::Server, _config: &config::ServerTlsConfig)
+    -> Result<server::Server>
+{
+    bail!("'server.tls' requires the feature 'tls'")
+}
+
+// Makes `client` talk to its server over TLS.
+#[cfg(all(feature = "client", feature = "tls"))]
+fn client_tls (client: client::Client, config: &config::ClientTlsConfig)
+    -> Result<client::Client>
+{
+    let identity = config.identity.as_ref()
+        .map (|(cert, key)| (cert.as_path(), key.as_path()));
+    let mut tls = tls::ClientTls::new (&config.ca, identity)
+        .chain_err (|| "invalid option 'client.tls'")?;
+    if let Some(ref name) = config.server_name {
+        tls = tls.server_name (name.as_str());
+    }
+    Ok(client.tls (tls))
+}
+
+#[cfg(all(feature = "client", not(feature = "tls")))]
+fn client_tls (_client: client::Client, _config: &config::ClientTlsConfig)
+    -> Result<client::Client>
+{
+    bail!("'client.tls' requires the feature 'tls'")
+}
+
+// Shows an icon in the notification area: events are shown as toasts, while the actions chosen
+// from its menu are sent to the server.
+#[cfg(feature = "client-tray")]
+fn run_tray (config: &config::ClientConfig, mut notifier: Box<dyn Notifier>) -> Result<()> {
+    use std::ops::ControlFlow;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+    use oxixenon_core::notification_toasts::tray::{self, TrayCommand, UNAVAILABILITY_MS};
+    use oxixenon_core::protocol::{Packet, RenewAvailability};
+    let show_toast = toast_events (config)?;
+    // The last event received, along with when it was received.
+    let last_event = Arc::new (Mutex::new (None));
+    // Events are received in the background, as the icon needs the main thread, until the icon is
+    // removed.
+    let shutdown = notifier::Shutdown::new();
+    {
+        let last_event = last_event.clone();
+        let shutdown = shutdown.clone();
+        thread::spawn (move || {
+            let result = notifier.listen (&mut |event, payload, from| {
+                let from_str = from.map (|x| x.to_string()).unwrap_or ("unknown".into());
+                info!(target: "client", "received event \"{}\" from {}", event, from_str);
+                *last_event.lock().unwrap_or_else (|e| e.into_inner()) =
+                    Some ((event.clone(), chrono::Local::now()));
+                show_toast (event, payload, from);
+                ControlFlow::Continue(())
+            }, &shutdown);
+            if let Err(error) = result {
+                log_error_with_chain!(error, "can't receive events: {}", error);
+            }
+        });
+    }
+    let toasts = NotificationToasts::new();
+    let client = new_client (config)?;
+    // Until when renewals have been made unavailable from the menu, if they have.
+    let mut unavailable_until: Option<chrono::DateTime<chrono::Local>> = None;
+    let tooltip = format!("Xenon ({})", client.connect_to());
+    let title = tooltip.clone();
+    let result = tray::run (&tooltip, move |command| {
+        let _correlation = logging::correlate();
+        info!(target: "client", "running tray command {:?}", command);
+        let (packet, success) = match command {
+            TrayCommand::Renew =>
+                (Packet::FreshIPRequest { renewer: None }, "IP renewal requested"),
+            TrayCommand::SetUnavailable => (
+                Packet::SetRenewingAvailable (RenewAvailability::UnavailableFor (
+                    "set from the notification area".into(),
+                    Duration::from_millis (UNAVAILABILITY_MS.into())
+                )),
+                "Renewals are unavailable for 1 hour"
+            ),
+            TrayCommand::SetAvailable => (
+                Packet::SetRenewingAvailable (RenewAvailability::Available),
+                "Renewals are available again"
+            ),
+            TrayCommand::ShowStatus => {
+                let availability = match unavailable_until {
+                    Some(until) =>
+                        format!("Renewals unavailable until {}.", until.format ("%H:%M")),
+                    None => "Renewals available.".to_string()
+                };
+                let last_event = match *last_event.lock().unwrap_or_else (|e| e.into_inner()) {
+                    Some((ref event, time)) =>
+                        format!("Last event: {} at {}.", event, time.format ("%H:%M")),
+                    None => "No events received yet.".to_string()
+                };
+                let body = format!("{} {}", availability, last_event);
+                try_send_toast (&toasts, &Toast::new (title.as_str(), body));
+                return;
+            }
+        };
+        let toast = match client.request (&packet) {
+            Ok(Packet::Ok) => {
+                match command {
+                    TrayCommand::SetUnavailable => unavailable_until = Some (
+                        chrono::Local::now() +
+                            chrono::Duration::milliseconds (UNAVAILABILITY_MS.into())
+                    ),
+                    TrayCommand::SetAvailable => unavailable_until = None,
+                    _ => ()
+                }
+                Toast::new (success, "")
+            },
+            Ok(Packet::Renewed(outcome)) => {
+                info!(target: "client", "renewed the IP address {}", outcome);
+                Toast::new (success, format!("Renewed {}.", outcome))
+            },
+            Ok(Packet::IPUnchanged(ip)) => {
+                warn!(target: "client", "the IP address was renewed, but it's still {}", ip);
+                Toast::new ("The IP address didn't change", format!("It's still {}.", ip))
+            },
+            Ok(Packet::Error { code, detail }) => {
+                error!(target: "client", "{}", Error::Refused (code, detail.clone()));
+                Toast::new ("The server refused the request", detail.unwrap_or_default())
+            },
+            Ok(response) => {
+                error!(target: "client", "received unknown packet: {:?}", response);
+                Toast::new ("The server sent an unknown response", "")
+            },
+            Err(error) => {
+                log_error_with_chain!(error, "{}", error);
+                Toast::new ("Can't reach the server", error.to_string())
+            }
+        };
+        try_send_toast (&toasts, &toast);
+    }).chain_err (|| "can't show the notification area icon");
+    shutdown.request();
+    result
+}
+
+#[cfg(all(feature = "client", not(feature = "client-tray")))]
+fn run_tray (_config: &config::ClientConfig, _notifier: Box<dyn Notifier>) -> Result<()> {
+    error!("the notification area icon is disabled, rebuild with the feature 'client-tray'");
+    process::exit(255)
+}
+
 #[cfg(not(feature = "client"))]
-fn start_client (_config: &config::ClientConfig, _notifier: Box<Notifier>) -> Result<()> {
+fn start_client (_config: &config::ClientConfig, _notifier: Box<dyn Notifier>) -> Result<()> {
     error!("client functionality is disabled");
     process::exit(255)
 }
@@ -0,0 +1,42 @@
+//! Checks that every supported combination of features compiles without warnings. It's slow, so
+//! it only runs when asked: `cargo test --test feature_matrix -- --ignored`.
+
+use std::process::Command;
+
+// Every combination is built with `--no-default-features`. Features which only work on some
+// platforms (such as `client-toasts`) are left out.
+const COMBINATIONS: &[&str] = &[
+    "minimal",
+    "server",
+    "server,local-time,notifier-multicast",
+    "server,renewer-dlink",
+    "server,renewer-fritzbox",
+    "server,renewer-fritzbox-local",
+    "server,syslog-backend",
+    "server,config-schema",
+    "server,tls",
+    "client",
+    "client,notifier-multicast",
+    "client,tls",
+    "client,server,local-time,notifier-multicast"
+];
+
+#[test]
+#[ignore]
+fn every_combination_compiles() {
+    let target_dir = concat!(env!("CARGO_TARGET_TMPDIR"), "/feature-matrix");
+    let failed = COMBINATIONS.iter()
+        .filter (|features| {
+            let status = Command::new (env!("CARGO"))
+                .args (["check", "--all-targets", "--no-default-features", "--features"])
+                .arg (features)
+                .args (["--target-dir", target_dir])
+                .current_dir (env!("CARGO_MANIFEST_DIR"))
+                .env ("RUSTFLAGS", "-D warnings")
+                .status()
+                .expect ("can't run cargo");
+            !status.success()
+        })
+        .collect::<Vec<_>>();
+    assert!(failed.is_empty(), "these combinations don't compile: {:?}", failed);
+}